@@ -0,0 +1,24 @@
+//! Fuzz target for `fw_common::FileEvent::from_bytes`
+//!
+//! Feeds arbitrary byte buffers to the decoder. Any input must either
+//! decode to an event whose accessors do not panic, or return an error.
+//!
+//! Run with: `cargo +nightly fuzz run decode_event` from `fw-common/`.
+
+#![no_main]
+
+use fw_common::decoded::DecodedFileEvent;
+use fw_common::FileEvent;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(event) = FileEvent::from_bytes(data) {
+        // Exercise the accessors userspace relies on
+        let _ = event.path_str();
+        let _ = event.filename_str();
+        let _ = event.is_open();
+        let _ = event.has_more_chunks();
+        let _ = event.is_path_truncated();
+        let _ = DecodedFileEvent::try_from(event);
+    }
+});
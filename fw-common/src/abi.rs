@@ -0,0 +1,703 @@
+//! Kernel ABI module
+//!
+//! Layout of the events the probes send and of the values fw writes into
+//! their maps. Only the probes and the Linux collector need it; the event
+//! model in `event` doesn't.
+
+use core::fmt;
+
+use zerocopy::{ConvertError, FromBytes, Immutable, IntoBytes, KnownLayout};
+
+/// Layout version of `FileEvent`; bump whenever its fields change
+pub const EVENT_ABI_VERSION: u32 = 8;
+
+/// Marker before the event layout version stamped into the eBPF object
+pub const OBJECT_STAMP_MAGIC: [u8; 8] = *b"fw-abi:\0";
+
+/// Length of the stamp: the marker and a little-endian layout version
+pub const OBJECT_STAMP_LEN: usize = 12;
+
+/// Stamp the eBPF object carries, so fw can tell which event layout an
+/// object was built for
+pub const fn object_stamp() -> [u8; OBJECT_STAMP_LEN] {
+    let version = EVENT_ABI_VERSION.to_le_bytes();
+    let mut stamp = [0; OBJECT_STAMP_LEN];
+    let mut i = 0;
+    while i < OBJECT_STAMP_LEN {
+        stamp[i] = if i < OBJECT_STAMP_MAGIC.len() {
+            OBJECT_STAMP_MAGIC[i]
+        } else {
+            version[i - OBJECT_STAMP_MAGIC.len()]
+        };
+        i += 1;
+    }
+    stamp
+}
+
+/// Event layout version stamped into an eBPF object
+///
+/// # Arguments
+/// * `object` - Contents of the object file
+///
+/// # Returns
+/// * `Option<u32>` - Version, or None if the object carries no stamp
+pub fn stamped_version(object: &[u8]) -> Option<u32> {
+    let magic = OBJECT_STAMP_MAGIC.len();
+    let start = object
+        .windows(magic)
+        .position(|w| w == OBJECT_STAMP_MAGIC)?;
+    let version = object.get(start + magic..start + OBJECT_STAMP_LEN)?;
+    Some(u32::from_le_bytes(version.try_into().ok()?))
+}
+
+/// FNV-1a checksum of an eBPF object, recorded by the build so a damaged
+/// embedded object is caught before it is loaded
+///
+/// # Arguments
+/// * `object` - Contents of the object file
+///
+/// # Returns
+/// * `u64` - Checksum
+pub fn object_checksum(object: &[u8]) -> u64 {
+    object.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Maximum path length we can capture
+pub const MAX_PATH_LEN: usize = 256;
+
+/// Maximum filename length
+pub const MAX_FILENAME_LEN: usize = 64;
+
+/// Number of path bytes carried by each chunk (one byte is kept for the
+/// null terminator written by `bpf_probe_read_user_str`)
+pub const PATH_CHUNK_LEN: usize = MAX_PATH_LEN - 1;
+
+/// Maximum number of chunks sent for a single path (covers PATH_MAX, 4096)
+pub const MAX_PATH_CHUNKS: usize = 17;
+
+/// Event type: a file was opened (`fd` holds the new descriptor, `arg` the
+/// open flags, `arg2` the inode mode, or 0 if it couldn't be read)
+pub const EVENT_TYPE_OPEN: u32 = 0;
+
+/// Event type: a descriptor was closed (`fd` holds the descriptor)
+pub const EVENT_TYPE_CLOSE: u32 = 1;
+
+/// Event type: `old_fd` was duplicated into `fd` (dup, dup2, dup3,
+/// fcntl F_DUPFD)
+pub const EVENT_TYPE_DUP: u32 = 2;
+
+/// Event type: the process forked `child_pid`, which inherits its
+/// descriptors
+pub const EVENT_TYPE_FORK: u32 = 3;
+
+/// Event type: the process exited and its descriptors are gone
+pub const EVENT_TYPE_EXIT: u32 = 4;
+
+/// Event type: the mode of the file open on `fd` changed (`arg` holds the
+/// new mode)
+pub const EVENT_TYPE_CHMOD: u32 = 5;
+
+/// Event type: the owner of the file open on `fd` changed (`arg` holds the
+/// uid, `arg2` the gid)
+pub const EVENT_TYPE_CHOWN: u32 = 6;
+
+/// Event type: the file open on `fd` was truncated (`arg` holds the new
+/// length)
+pub const EVENT_TYPE_TRUNCATE: u32 = 7;
+
+/// Event type: `path` holds the source of the link or rename described by
+/// the next `EVENT_TYPE_LINK`, `EVENT_TYPE_SYMLINK` or `EVENT_TYPE_RENAME`
+/// event from the same task
+pub const EVENT_TYPE_LINK_SOURCE: u32 = 8;
+
+/// Event type: a hardlink was created at `path`
+pub const EVENT_TYPE_LINK: u32 = 9;
+
+/// Event type: a symlink was created at `path`
+pub const EVENT_TYPE_SYMLINK: u32 = 10;
+
+/// Event type: an extended attribute was set, either on `path` or on the
+/// file open on `fd` (`filename` holds the attribute name)
+pub const EVENT_TYPE_SETXATTR: u32 = 11;
+
+/// Event type: an extended attribute was removed, either from `path` or
+/// from the file open on `fd` (`filename` holds the attribute name)
+pub const EVENT_TYPE_REMOVEXATTR: u32 = 12;
+
+/// Event type: the file open on `fd` was flushed to storage (`arg` holds
+/// the `SYNC_KIND_*` of the call, `open_latency_ns` how long it took)
+pub const EVENT_TYPE_SYNC: u32 = 13;
+
+/// Event type: a file was renamed to `path`
+pub const EVENT_TYPE_RENAME: u32 = 14;
+
+/// Event type: data was read from the file open on `fd` (`arg` holds the
+/// offset, or `IO_OFFSET_CURRENT`; `arg2` the bytes read, capped at
+/// `u32::MAX`)
+pub const EVENT_TYPE_READ: u32 = 15;
+
+/// Event type: data was written to the file open on `fd` (`arg` holds the
+/// offset, or `IO_OFFSET_CURRENT`; `arg2` the bytes written, capped at
+/// `u32::MAX`)
+pub const EVENT_TYPE_WRITE: u32 = 16;
+
+/// Event type: a lock was requested on the file open on `fd` with flock
+/// or fcntl (`arg` holds the `LOCK_KIND_*`, `arg2` the `LOCK_FLAG_*`,
+/// `open_latency_ns` how long the call took)
+pub const EVENT_TYPE_LOCK: u32 = 17;
+
+/// Event type: a lock on the file open on `fd` was released (`arg2` holds
+/// the `LOCK_FLAG_*`)
+pub const EVENT_TYPE_UNLOCK: u32 = 18;
+
+/// Event type: the working directory changed, to `path` for chdir or to
+/// the directory open on `fd` for fchdir (`fd` is -1 for chdir)
+pub const EVENT_TYPE_CHDIR: u32 = 19;
+
+/// Offset of a read or write at the descriptor's current file position
+/// (read and write rather than pread64 and pwrite64)
+pub const IO_OFFSET_CURRENT: u64 = u64::MAX;
+
+/// Sync kind: fsync, flushing data and metadata
+pub const SYNC_KIND_FSYNC: u64 = 0;
+
+/// Sync kind: fdatasync, flushing data and only essential metadata
+pub const SYNC_KIND_FDATASYNC: u64 = 1;
+
+/// Sync kind: sync_file_range, flushing part of the file's data
+pub const SYNC_KIND_SYNC_FILE_RANGE: u64 = 2;
+
+/// Lock kind: shared (flock LOCK_SH, fcntl F_RDLCK)
+pub const LOCK_KIND_SHARED: u64 = 0;
+
+/// Lock kind: exclusive (flock LOCK_EX, fcntl F_WRLCK)
+pub const LOCK_KIND_EXCLUSIVE: u64 = 1;
+
+/// Lock flag: a byte-range lock taken with fcntl rather than flock
+pub const LOCK_FLAG_RECORD: u32 = 1 << 0;
+
+/// Lock flag: the caller asked not to wait (LOCK_NB, F_SETLK)
+pub const LOCK_FLAG_NONBLOCKING: u32 = 1 << 1;
+
+/// Lock flag: the non-blocking request was refused because another
+/// holder had a conflicting lock
+pub const LOCK_FLAG_REFUSED: u32 = 1 << 2;
+
+/// Maximum number of users in the kernel uid filter
+pub const MAX_UID_FILTER_ENTRIES: u32 = 64;
+
+/// Uid filter entry: report this user's activity
+pub const UID_FILTER_INCLUDE: u8 = 1;
+
+/// Uid filter entry: never report this user's activity
+pub const UID_FILTER_EXCLUDE: u8 = 2;
+
+/// Maximum number of exact process names in the kernel process filter
+pub const MAX_COMM_FILTER_ENTRIES: u32 = 1024;
+
+/// Process filter mode: every process is reported
+pub const COMM_FILTER_OFF: u32 = 0;
+
+/// Process filter mode: activity of listed processes is dropped
+pub const COMM_FILTER_DENY: u32 = 1;
+
+/// Process filter mode: only activity of listed processes is reported
+pub const COMM_FILTER_ALLOW: u32 = 2;
+
+/// Maximum number of processes whose name the process filter remembers
+pub const MAX_LEADER_COMMS: u32 = 32768;
+
+/// Maximum number of processes whose fork time the probes remember
+pub const MAX_PROCESS_STARTS: u32 = 32768;
+
+/// Length of a task's command name, including the null terminator
+pub const MAX_COMM_LEN: usize = 16;
+
+/// Maximum file extension length counted separately in kernel
+/// aggregation, including the null terminator; longer ones are cut off
+pub const MAX_EXT_LEN: usize = 16;
+
+/// Maximum number of (program, extension, action) counters kept in the
+/// kernel between two flushes
+pub const MAX_AGG_ENTRIES: u32 = 8192;
+
+/// Number of slots in the eBPF tail-call program array
+pub const MAX_TAIL_CALLS: u32 = 8;
+
+/// Tail-call slot of the program sending the remaining chunks of a long
+/// open path
+pub const TAIL_CALL_OPEN_PATH_CHUNKS: u32 = 0;
+
+/// Tail-call slot of the program sending the link path after its source
+pub const TAIL_CALL_LINK_TARGET: u32 = 1;
+
+/// Frames kept per stack trace (the kernel's `PERF_MAX_STACK_DEPTH`)
+pub const MAX_STACK_DEPTH: usize = 127;
+
+/// Number of distinct stacks kept in the stack trace map
+pub const MAX_STACK_ENTRIES: u32 = 16384;
+
+/// Stack mode flag: record the user stack of each event
+pub const STACK_USER: u32 = 1 << 0;
+
+/// Stack mode flag: record the kernel stack of each event
+pub const STACK_KERNEL: u32 = 1 << 1;
+
+/// Maximum number of stack capture criteria (severity rules with
+/// `capture_stack=true`) pushed to the probes
+pub const MAX_STACK_CRITERIA: u32 = 16;
+
+/// Bytes of path prefix a stack capture criterion holds; longer prefixes
+/// are cut, so a few extra stacks are recorded and dropped in userspace
+pub const STACK_PREFIX_LEN: usize = 64;
+
+/// Maximum number of criteria (built like stack capture criteria, from
+/// the severity rules rating events warning or critical) for events
+/// overload sampling never drops
+pub const MAX_SAMPLE_EXEMPTIONS: u32 = 16;
+
+/// Maximum number of tripwire decoy paths pushed to the probes
+pub const MAX_TRIPWIRES: u32 = 256;
+
+/// Flag: more path chunks follow this event for the same pid/tgid
+pub const EVENT_FLAG_MORE_CHUNKS: u32 = 1 << 0;
+
+/// Flag: the path was longer than `MAX_PATH_CHUNKS` chunks and was cut off
+pub const EVENT_FLAG_PATH_TRUNCATED: u32 = 1 << 1;
+
+/// Flag: the event was sent while only 1 in `SAMPLE_RATE` events was,
+/// so it stands for that many
+pub const EVENT_FLAG_SAMPLED: u32 = 1 << 2;
+
+/// Flag: the event opened a tripwire decoy, so the kernel filters,
+/// sampling and aggregation let it through
+pub const EVENT_FLAG_TRIPWIRE: u32 = 1 << 3;
+
+/// Mount points of the pseudo-filesystems left out unless asked for
+/// (`fw collect --include-pseudo-fs`)
+pub const PSEUDO_FS_PREFIXES: [&[u8]; 3] = [b"/proc", b"/sys", b"/dev"];
+
+/// Check whether a path is at or below one of `PSEUDO_FS_PREFIXES`
+///
+/// The path may be null-terminated, as in an event's path buffer.
+pub fn is_pseudo_fs_path(path: &[u8]) -> bool {
+    PSEUDO_FS_PREFIXES.iter().any(|prefix| {
+        path.starts_with(prefix)
+            && matches!(path.get(prefix.len()), None | Some(b'/') | Some(0))
+    })
+}
+
+/// Event data structure sent from eBPF program to userspace
+///
+/// The layout has no padding, so it can be reinterpreted directly from the
+/// perf buffer bytes (see `FileEvent::from_bytes`). The eBPF programs
+/// build events in a per-CPU map slot rather than on the 512-byte BPF
+/// stack, so new fields don't need to fit in the stack.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct FileEvent {
+    /// Layout version (`EVENT_ABI_VERSION`) of the producing eBPF program
+    pub version: u32,
+    /// Process ID that triggered the event
+    pub pid: u32,
+    /// Thread group ID
+    pub tgid: u32,
+    /// File path (null-terminated)
+    pub path: [u8; MAX_PATH_LEN],
+    /// Filename only (null-terminated); the attribute name for xattr
+    /// events
+    pub filename: [u8; MAX_FILENAME_LEN],
+    /// Event type (`EVENT_TYPE_*`)
+    pub event_type: u32,
+    /// Index of the path chunk carried by this event (0 for the first)
+    pub chunk_index: u32,
+    /// Bit flags (`EVENT_FLAG_*`) describing this event
+    pub flags: u32,
+    /// File descriptor the event applies to (-1 if none)
+    pub fd: i32,
+    /// Source descriptor of a dup event; for opens, links and renames,
+    /// the directory descriptor a relative path starts from (negative,
+    /// e.g. `AT_FDCWD`, for the working directory); -1 otherwise
+    pub old_fd: i32,
+    /// Child process ID of a fork event (0 otherwise)
+    pub child_pid: u32,
+    /// Second event-specific argument (see `EVENT_TYPE_*`); placed first
+    /// so `arg` stays 8-byte aligned without padding
+    pub arg2: u32,
+    /// First event-specific argument (see `EVENT_TYPE_*`)
+    pub arg: u64,
+    /// Time the call spent in the kernel, in nanoseconds (open and sync
+    /// events only)
+    pub open_latency_ns: u64,
+    /// Device of the opened file in kernel `dev_t` encoding (MAJOR << 20 |
+    /// MINOR), or 0 if unknown (open events only)
+    pub dev: u64,
+    /// Inode number of the opened file, or 0 if unknown (open events only)
+    pub ino: u64,
+    /// Boot time in nanoseconds at which the process was forked, telling
+    /// reused process IDs apart; 0 if it was forked before the probes
+    /// were loaded
+    pub process_start_ns: u64,
+    /// Real user ID of the task, as seen from the initial user namespace
+    pub uid: u32,
+    /// Real group ID of the task, as seen from the initial user namespace
+    pub gid: u32,
+    /// Id of the user stack in the stack trace map, or -1 if not recorded
+    pub user_stack_id: i32,
+    /// Id of the kernel stack in the stack trace map, or -1 if not
+    /// recorded
+    pub kernel_stack_id: i32,
+}
+
+/// Key of a kernel-side aggregation counter
+///
+/// With aggregation on, probes count events per key in a map instead of
+/// sending them, and userspace reads and resets the counts periodically.
+#[repr(C)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    FromBytes,
+    IntoBytes,
+    Immutable,
+    KnownLayout,
+)]
+pub struct AggKey {
+    /// Command name of the task (null-terminated unless 16 bytes long)
+    pub comm: [u8; MAX_COMM_LEN],
+    /// Extension of the file name without the dot (null-terminated), or
+    /// empty for events that carry no path
+    pub ext: [u8; MAX_EXT_LEN],
+    /// Event type (`EVENT_TYPE_*`)
+    pub event_type: u32,
+    /// Event-specific detail: the `SYNC_KIND_*` of sync events, else 0
+    pub detail: u32,
+}
+
+impl AggKey {
+    /// Get the command name as a string
+    pub fn comm_str(&self) -> Result<&str, core::str::Utf8Error> {
+        let null_pos = self
+            .comm
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.comm.len());
+        core::str::from_utf8(&self.comm[..null_pos])
+    }
+
+    /// Get the extension as a string
+    pub fn ext_str(&self) -> Result<&str, core::str::Utf8Error> {
+        let null_pos = self
+            .ext
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.ext.len());
+        core::str::from_utf8(&self.ext[..null_pos])
+    }
+}
+
+// The derives above prove the layout has no padding and that any bytes
+// are a valid value
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for AggKey {}
+
+/// Events whose stacks are recorded when stack capture criteria are set
+///
+/// An event matches when both its process and the first chunk of its path
+/// do. Events known only by descriptor, or by a path relative to one,
+/// carry no absolute path in the kernel, so they match on the process
+/// alone; userspace resolves the descriptor and drops the stacks of the
+/// events no rule captures.
+#[repr(C)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    FromBytes,
+    IntoBytes,
+    Immutable,
+    KnownLayout,
+)]
+pub struct StackCriterion {
+    /// Process ID the event must come from, or 0 for any
+    pub pid: u32,
+    /// Number of bytes used in `prefix`; 0 matches any path
+    pub prefix_len: u32,
+    /// Path prefix the event's path must start with
+    pub prefix: [u8; STACK_PREFIX_LEN],
+}
+
+// The derives above prove the layout has no padding and that any bytes
+// are a valid value
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for StackCriterion {}
+
+impl StackCriterion {
+    /// Build a criterion, cutting the prefix to `STACK_PREFIX_LEN` bytes
+    pub fn new(pid: Option<u32>, prefix: &[u8]) -> Self {
+        let len = prefix.len().min(STACK_PREFIX_LEN);
+        let mut criterion = Self {
+            pid: pid.unwrap_or(0),
+            prefix_len: len as u32,
+            prefix: [0; STACK_PREFIX_LEN],
+        };
+        criterion.prefix[..len].copy_from_slice(&prefix[..len]);
+        criterion
+    }
+
+    /// Check whether an event from `pid` on `path` matches
+    ///
+    /// The path may be null-terminated, as in an event's path buffer. A
+    /// path that isn't absolute (or is empty) matches any prefix, since
+    /// only the resolved path can tell. The loop is bounded by
+    /// `STACK_PREFIX_LEN` so the verifier accepts it.
+    pub fn matches(&self, pid: u32, path: &[u8]) -> bool {
+        if self.pid != 0 && self.pid != pid {
+            return false;
+        }
+        if path.first() != Some(&b'/') {
+            return true;
+        }
+        for index in 0..STACK_PREFIX_LEN {
+            if index >= self.prefix_len as usize {
+                break;
+            }
+            if path.get(index) != Some(&self.prefix[index]) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Errors returned when decoding a raw event buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Buffer is shorter than a `FileEvent`
+    TooShort {
+        /// Length of the buffer that was received
+        len: usize,
+    },
+    /// Buffer is not aligned for a `FileEvent`
+    Misaligned,
+    /// Event was produced by an eBPF program with a different layout
+    UnsupportedVersion {
+        /// Version found in the event
+        version: u32,
+    },
+    /// Event type isn't one of `EVENT_TYPE_*`
+    UnknownEventType {
+        /// Type found in the event
+        event_type: u32,
+    },
+    /// A name that must be text isn't valid UTF-8
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooShort { len } => write!(
+                f,
+                "event buffer too short: {} bytes, expected {}",
+                len,
+                core::mem::size_of::<FileEvent>()
+            ),
+            DecodeError::Misaligned => {
+                write!(f, "event buffer is not aligned for FileEvent")
+            }
+            DecodeError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported event version {}, expected {}",
+                version, EVENT_ABI_VERSION
+            ),
+            DecodeError::UnknownEventType { event_type } => {
+                write!(f, "unknown event type {}", event_type)
+            }
+            DecodeError::InvalidUtf8 => write!(f, "name is not valid UTF-8"),
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+impl FileEvent {
+    /// Decode an event from raw perf buffer bytes without copying
+    ///
+    /// Trailing bytes after the event (perf record padding) are ignored.
+    ///
+    /// # Arguments
+    /// * `bytes` - Raw bytes received from the perf buffer
+    ///
+    /// # Returns
+    /// * `Result<&FileEvent, DecodeError>` - Borrowed event or decode error
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self, DecodeError> {
+        let (event, _padding) =
+            Self::ref_from_prefix(bytes).map_err(|e| match e {
+                ConvertError::Alignment(_) => DecodeError::Misaligned,
+                ConvertError::Size(_) => {
+                    DecodeError::TooShort { len: bytes.len() }
+                }
+                ConvertError::Validity(never) => match never {},
+            })?;
+
+        if event.version != EVENT_ABI_VERSION {
+            return Err(DecodeError::UnsupportedVersion {
+                version: event.version,
+            });
+        }
+        Ok(event)
+    }
+
+    /// Get the path as a string
+    pub fn path_str(&self) -> Result<&str, core::str::Utf8Error> {
+        let null_pos = self
+            .path
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.path.len());
+        core::str::from_utf8(&self.path[..null_pos])
+    }
+
+    /// Get the filename as a string
+    pub fn filename_str(&self) -> Result<&str, core::str::Utf8Error> {
+        let null_pos = self
+            .filename
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.filename.len());
+        core::str::from_utf8(&self.filename[..null_pos])
+    }
+
+    /// Check if this is an open event
+    pub fn is_open(&self) -> bool {
+        self.event_type == EVENT_TYPE_OPEN
+    }
+
+    /// Check if this is a close event
+    pub fn is_close(&self) -> bool {
+        self.event_type == EVENT_TYPE_CLOSE
+    }
+
+    /// Check if more path chunks follow this event
+    pub fn has_more_chunks(&self) -> bool {
+        self.flags & EVENT_FLAG_MORE_CHUNKS != 0
+    }
+
+    /// Check if the kernel had to truncate the path
+    pub fn is_path_truncated(&self) -> bool {
+        self.flags & EVENT_FLAG_PATH_TRUNCATED != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    /// Build an open event for the given path
+    fn sample_event(path: &[u8]) -> FileEvent {
+        let mut event = FileEvent::new_zeroed();
+        event.version = EVENT_ABI_VERSION;
+        event.pid = 7;
+        event.tgid = 7;
+        event.fd = 3;
+        event.old_fd = -1;
+        event.path[..path.len()].copy_from_slice(path);
+        event
+    }
+
+    /// Copy bytes into a u32-backed buffer so the result is 4-byte aligned
+    fn aligned_copy(bytes: &[u8], extra: usize) -> Vec<u32> {
+        let mut words = vec![0u32; (bytes.len() + extra).div_ceil(4)];
+        words.as_mut_bytes()[..bytes.len()].copy_from_slice(bytes);
+        words
+    }
+
+    #[test]
+    fn test_object_stamp() {
+        let mut object = b"\x7fELF padding".to_vec();
+        object.extend_from_slice(&object_stamp());
+        object.extend_from_slice(b" more sections");
+        assert_eq!(stamped_version(&object), Some(EVENT_ABI_VERSION));
+        assert_eq!(stamped_version(b"\x7fELF"), None);
+        assert_ne!(object_checksum(&object), object_checksum(b"\x7fELF"));
+    }
+
+    #[test]
+    fn test_from_bytes_round_trip() {
+        let event = sample_event(b"/tmp/a.rs");
+        let words = aligned_copy(event.as_bytes(), 8);
+
+        let decoded = FileEvent::from_bytes(words.as_bytes()).unwrap();
+        assert_eq!(decoded.pid, 7);
+        assert_eq!(decoded.fd, 3);
+        assert_eq!(decoded.path_str(), Ok("/tmp/a.rs"));
+    }
+
+    #[test]
+    fn test_from_bytes_too_short() {
+        let event = sample_event(b"/tmp/a.rs");
+        let bytes = &event.as_bytes()[..16];
+        assert_eq!(
+            FileEvent::from_bytes(bytes).unwrap_err(),
+            DecodeError::TooShort { len: 16 }
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_misaligned() {
+        let event = sample_event(b"/tmp/a.rs");
+        let words = aligned_copy(event.as_bytes(), 4);
+        let bytes = &words.as_bytes()[1..];
+        assert_eq!(
+            FileEvent::from_bytes(bytes).unwrap_err(),
+            DecodeError::Misaligned
+        );
+    }
+
+    #[test]
+    fn test_pseudo_fs_paths() {
+        assert!(is_pseudo_fs_path(b"/proc/self/stat"));
+        assert!(is_pseudo_fs_path(b"/dev\0garbage"));
+        assert!(is_pseudo_fs_path(b"/sys"));
+        assert!(!is_pseudo_fs_path(b"/devices/a"));
+        assert!(!is_pseudo_fs_path(b"/home/proc"));
+    }
+
+    #[test]
+    fn test_stack_criteria() {
+        let etc = StackCriterion::new(None, b"/etc/ssh");
+        assert!(etc.matches(3, b"/etc/ssh/sshd_config\0"));
+        assert!(!etc.matches(3, b"/etc\0"));
+        // Descriptor-only and relative paths are checked once resolved
+        assert!(etc.matches(3, &[0; MAX_PATH_LEN]));
+        assert!(etc.matches(3, b"sshd_config\0"));
+        let pid = StackCriterion::new(Some(7), b"");
+        assert!(pid.matches(7, &[0; MAX_PATH_LEN]));
+        assert!(!pid.matches(8, b"/etc/ssh"));
+        let long = StackCriterion::new(None, &[b'a'; STACK_PREFIX_LEN + 8]);
+        assert_eq!(long.prefix_len as usize, STACK_PREFIX_LEN);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_unknown_version() {
+        let mut event = sample_event(b"/tmp/a.rs");
+        event.version = EVENT_ABI_VERSION + 1;
+        let words = aligned_copy(event.as_bytes(), 0);
+        assert_eq!(
+            FileEvent::from_bytes(words.as_bytes()).unwrap_err(),
+            DecodeError::UnsupportedVersion {
+                version: EVENT_ABI_VERSION + 1
+            }
+        );
+    }
+}
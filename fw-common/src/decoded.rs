@@ -0,0 +1,362 @@
+//! Decoded event module
+//!
+//! Owned, typed form of one raw `FileEvent`: strings and paths instead of
+//! null-terminated arrays, and the event type and its arguments as an
+//! enum. Consumers of the raw events convert with `TryFrom` instead of
+//! picking the buffers apart themselves. Long paths still arrive in
+//! chunks, one event each; joining them is up to the consumer.
+
+use std::path::PathBuf;
+use std::string::{String, ToString};
+
+use crate::abi::*;
+use crate::event::{FileId, FileType, LockKind, SyncKind};
+
+/// Event type with its event-specific arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    /// A file was opened on `fd`
+    Open {
+        /// Flags passed to open
+        flags: u32,
+        /// Type of the opened file, if the kernel could read its inode
+        file_type: Option<FileType>,
+        /// Device and inode of the opened file, if known
+        file_id: Option<FileId>,
+        /// Time spent in the call, in nanoseconds
+        latency_ns: u64,
+    },
+    /// `fd` was closed
+    Close,
+    /// `old_fd` was duplicated into `fd`
+    Dup {
+        /// Descriptor that was duplicated
+        old_fd: i32,
+    },
+    /// The process forked
+    Fork {
+        /// Process ID of the child
+        child_pid: u32,
+    },
+    /// The process exited
+    Exit,
+    /// The mode of the file open on `fd` changed
+    Chmod {
+        /// New mode bits
+        mode: u32,
+    },
+    /// The owner of the file open on `fd` changed
+    Chown {
+        /// New owning user ID
+        uid: u32,
+        /// New owning group ID
+        gid: u32,
+    },
+    /// The file open on `fd` was truncated
+    Truncate {
+        /// New length in bytes
+        length: u64,
+    },
+    /// `path` is the source of the next link, symlink or rename from the
+    /// same task
+    LinkSource,
+    /// A hardlink was created at `path`
+    Link,
+    /// A symlink was created at `path`
+    Symlink,
+    /// An extended attribute was set on `path` or the file open on `fd`
+    SetXattr {
+        /// Attribute name, e.g. "security.selinux"
+        name: String,
+    },
+    /// An extended attribute was removed from `path` or the file open on
+    /// `fd`
+    RemoveXattr {
+        /// Attribute name
+        name: String,
+    },
+    /// The file open on `fd` was flushed to storage
+    Sync {
+        /// System call that flushed it
+        kind: SyncKind,
+        /// Time spent in the call, in nanoseconds
+        latency_ns: u64,
+    },
+    /// A file was renamed to `path`
+    Rename,
+    /// Data was read from the file open on `fd`
+    Read {
+        /// Offset read from, or None at the descriptor's file position
+        offset: Option<u64>,
+        /// Bytes read, capped at `u32::MAX`
+        bytes: u64,
+    },
+    /// Data was written to the file open on `fd`
+    Write {
+        /// Offset written at, or None at the descriptor's file position
+        offset: Option<u64>,
+        /// Bytes written, capped at `u32::MAX`
+        bytes: u64,
+    },
+    /// A lock was requested on the file open on `fd`
+    Lock {
+        /// Shared or exclusive
+        kind: LockKind,
+        /// Byte-range lock taken with fcntl rather than flock
+        record: bool,
+        /// The caller asked not to wait
+        nonblocking: bool,
+        /// The non-blocking request was refused
+        refused: bool,
+        /// Time spent in the call, in nanoseconds
+        latency_ns: u64,
+    },
+    /// A lock on the file open on `fd` was released
+    Unlock {
+        /// Byte-range lock taken with fcntl rather than flock
+        record: bool,
+    },
+    /// The working directory changed to `path`, or to the directory open
+    /// on `fd`
+    Chdir,
+}
+
+/// Raw event converted to owned, typed fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodedFileEvent {
+    /// Process ID that triggered the event
+    pub pid: u32,
+    /// Thread group ID
+    pub tgid: u32,
+    /// Event type and arguments
+    pub kind: EventKind,
+    /// Path chunk carried by the event, or None if it carries none
+    pub path: Option<PathBuf>,
+    /// Index of the path chunk (0 for the first)
+    pub chunk_index: u32,
+    /// More path chunks follow for the same pid/tgid
+    pub more_chunks: bool,
+    /// The kernel cut the path off after `MAX_PATH_CHUNKS` chunks
+    pub path_truncated: bool,
+    /// Descriptor the event applies to, if any
+    pub fd: Option<i32>,
+    /// Directory descriptor a relative path starts from, or None for the
+    /// working directory
+    pub dir_fd: Option<i32>,
+    /// Boot time in nanoseconds at which the process was forked, if it
+    /// was forked after the probes were loaded
+    pub process_start_ns: Option<u64>,
+    /// Real user ID of the task
+    pub uid: u32,
+    /// Real group ID of the task
+    pub gid: u32,
+    /// Id of the user stack in the stack trace map, if recorded
+    pub user_stack_id: Option<i32>,
+    /// Id of the kernel stack in the stack trace map, if recorded
+    pub kernel_stack_id: Option<i32>,
+}
+
+/// Bytes of a null-terminated buffer before the terminator
+fn until_null(buffer: &[u8]) -> &[u8] {
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    &buffer[..len]
+}
+
+/// Path from raw bytes; any bytes on Unix, UTF-8 elsewhere
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, DecodeError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(std::ffi::OsStr::from_bytes(bytes).into())
+    }
+    #[cfg(not(unix))]
+    {
+        core::str::from_utf8(bytes)
+            .map(PathBuf::from)
+            .map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl TryFrom<&FileEvent> for DecodedFileEvent {
+    type Error = DecodeError;
+
+    fn try_from(raw: &FileEvent) -> Result<Self, DecodeError> {
+        if raw.version != EVENT_ABI_VERSION {
+            return Err(DecodeError::UnsupportedVersion {
+                version: raw.version,
+            });
+        }
+        let xattr_name = || {
+            raw.filename_str()
+                .map(ToString::to_string)
+                .map_err(|_| DecodeError::InvalidUtf8)
+        };
+        let offset = (raw.arg != IO_OFFSET_CURRENT).then_some(raw.arg);
+        let kind = match raw.event_type {
+            EVENT_TYPE_OPEN => EventKind::Open {
+                flags: raw.arg as u32,
+                file_type: FileType::from_mode(raw.arg2),
+                file_id: FileId::from_raw(raw.dev, raw.ino),
+                latency_ns: raw.open_latency_ns,
+            },
+            EVENT_TYPE_CLOSE => EventKind::Close,
+            EVENT_TYPE_DUP => EventKind::Dup { old_fd: raw.old_fd },
+            EVENT_TYPE_FORK => EventKind::Fork {
+                child_pid: raw.child_pid,
+            },
+            EVENT_TYPE_EXIT => EventKind::Exit,
+            EVENT_TYPE_CHMOD => EventKind::Chmod {
+                mode: raw.arg as u32,
+            },
+            EVENT_TYPE_CHOWN => EventKind::Chown {
+                uid: raw.arg as u32,
+                gid: raw.arg2,
+            },
+            EVENT_TYPE_TRUNCATE => EventKind::Truncate { length: raw.arg },
+            EVENT_TYPE_LINK_SOURCE => EventKind::LinkSource,
+            EVENT_TYPE_LINK => EventKind::Link,
+            EVENT_TYPE_SYMLINK => EventKind::Symlink,
+            EVENT_TYPE_SETXATTR => EventKind::SetXattr {
+                name: xattr_name()?,
+            },
+            EVENT_TYPE_REMOVEXATTR => EventKind::RemoveXattr {
+                name: xattr_name()?,
+            },
+            EVENT_TYPE_SYNC => EventKind::Sync {
+                kind: SyncKind::from_raw(raw.arg),
+                latency_ns: raw.open_latency_ns,
+            },
+            EVENT_TYPE_RENAME => EventKind::Rename,
+            EVENT_TYPE_READ => EventKind::Read {
+                offset,
+                bytes: u64::from(raw.arg2),
+            },
+            EVENT_TYPE_WRITE => EventKind::Write {
+                offset,
+                bytes: u64::from(raw.arg2),
+            },
+            EVENT_TYPE_LOCK => EventKind::Lock {
+                kind: LockKind::from_raw(raw.arg),
+                record: raw.arg2 & LOCK_FLAG_RECORD != 0,
+                nonblocking: raw.arg2 & LOCK_FLAG_NONBLOCKING != 0,
+                refused: raw.arg2 & LOCK_FLAG_REFUSED != 0,
+                latency_ns: raw.open_latency_ns,
+            },
+            EVENT_TYPE_UNLOCK => EventKind::Unlock {
+                record: raw.arg2 & LOCK_FLAG_RECORD != 0,
+            },
+            EVENT_TYPE_CHDIR => EventKind::Chdir,
+            event_type => {
+                return Err(DecodeError::UnknownEventType { event_type })
+            }
+        };
+        let dir_fd = match raw.event_type {
+            EVENT_TYPE_OPEN
+            | EVENT_TYPE_LINK_SOURCE
+            | EVENT_TYPE_LINK
+            | EVENT_TYPE_SYMLINK
+            | EVENT_TYPE_RENAME => (raw.old_fd >= 0).then_some(raw.old_fd),
+            _ => None,
+        };
+        let path = until_null(&raw.path);
+        Ok(Self {
+            pid: raw.pid,
+            tgid: raw.tgid,
+            kind,
+            path: (!path.is_empty())
+                .then(|| path_from_bytes(path))
+                .transpose()?,
+            chunk_index: raw.chunk_index,
+            more_chunks: raw.has_more_chunks(),
+            path_truncated: raw.is_path_truncated(),
+            fd: (raw.fd >= 0).then_some(raw.fd),
+            dir_fd,
+            process_start_ns: (raw.process_start_ns != 0)
+                .then_some(raw.process_start_ns),
+            uid: raw.uid,
+            gid: raw.gid,
+            user_stack_id: (raw.user_stack_id >= 0)
+                .then_some(raw.user_stack_id),
+            kernel_stack_id: (raw.kernel_stack_id >= 0)
+                .then_some(raw.kernel_stack_id),
+        })
+    }
+}
+
+impl TryFrom<FileEvent> for DecodedFileEvent {
+    type Error = DecodeError;
+
+    fn try_from(raw: FileEvent) -> Result<Self, DecodeError> {
+        Self::try_from(&raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::FromZeros;
+
+    fn raw_event(event_type: u32) -> FileEvent {
+        let mut event = FileEvent::new_zeroed();
+        event.version = EVENT_ABI_VERSION;
+        event.event_type = event_type;
+        event.pid = 7;
+        event.tgid = 7;
+        event.fd = -1;
+        event.old_fd = -100;
+        event.user_stack_id = -1;
+        event.kernel_stack_id = -1;
+        event
+    }
+
+    #[test]
+    fn test_decode_open() {
+        let mut raw = raw_event(EVENT_TYPE_OPEN);
+        raw.path[..10].copy_from_slice(b"/etc/\xffhost");
+        raw.fd = 3;
+        raw.arg = 0o2;
+        raw.arg2 = 0o100644;
+        raw.ino = 42;
+        raw.flags = EVENT_FLAG_MORE_CHUNKS;
+        let event = DecodedFileEvent::try_from(raw).unwrap();
+        assert_eq!(
+            event.kind,
+            EventKind::Open {
+                flags: 0o2,
+                file_type: Some(FileType::File),
+                file_id: Some(FileId { dev: 0, ino: 42 }),
+                latency_ns: 0,
+            }
+        );
+        assert_eq!(event.path.unwrap().as_os_str().len(), 10);
+        assert!(event.more_chunks);
+        assert_eq!((event.fd, event.dir_fd), (Some(3), None));
+        assert_eq!(event.user_stack_id, None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut raw = raw_event(EVENT_TYPE_SETXATTR);
+        raw.filename[..4].copy_from_slice(b"user");
+        let event = DecodedFileEvent::try_from(&raw).unwrap();
+        assert_eq!(
+            event.kind,
+            EventKind::SetXattr {
+                name: "user".to_string()
+            }
+        );
+        assert_eq!(event.path, None);
+
+        raw.filename[0] = 0xff;
+        assert_eq!(
+            DecodedFileEvent::try_from(&raw),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            DecodedFileEvent::try_from(raw_event(99)),
+            Err(DecodeError::UnknownEventType { event_type: 99 })
+        );
+    }
+}
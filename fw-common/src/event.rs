@@ -0,0 +1,574 @@
+//! Event model module
+//!
+//! What fw reports about a file operation: the action, the kind of file,
+//! its identity, how serious it is and the overlay layer it came through,
+//! with the text forms fw prints. The `record` module puts these together
+//! into the event itself. Nothing here needs std or a Linux target, so
+//! the types compile for wasm32 and can be shared by anything that reads
+//! fw's output; with the `serde` feature they serialize as in `fw
+//! forward`'s JSON.
+
+use core::fmt;
+use core::str::FromStr;
+
+#[cfg(feature = "abi")]
+use crate::abi::{
+    LOCK_KIND_EXCLUSIVE, SYNC_KIND_FDATASYNC, SYNC_KIND_SYNC_FILE_RANGE,
+};
+
+/// File type bits of an inode mode, as on Linux
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// Error for a name that isn't one of a type's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownName;
+
+impl fmt::Display for UnknownName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown name")
+    }
+}
+
+impl core::error::Error for UnknownName {}
+
+/// Represents the type of file operation that occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileAction {
+    /// File was opened for reading or writing
+    Opened,
+    /// File was already open when monitoring started
+    AlreadyOpen,
+    /// File was closed after being opened
+    #[allow(dead_code)]
+    Closed,
+    /// Permission bits of an open file were changed
+    ModeChanged {
+        /// New mode bits
+        mode: u32,
+    },
+    /// Owner of an open file was changed
+    OwnerChanged {
+        /// New owning user ID
+        uid: u32,
+        /// New owning group ID
+        gid: u32,
+    },
+    /// Open file was truncated
+    Truncated {
+        /// New file length in bytes
+        length: u64,
+    },
+    /// Hardlink to an existing file was created
+    Linked,
+    /// Symbolic link was created
+    Symlinked,
+    /// Extended attribute was set
+    XattrSet,
+    /// Extended attribute was removed
+    XattrRemoved,
+    /// Open file was flushed to storage
+    Synced {
+        /// System call that flushed it
+        kind: SyncKind,
+    },
+    /// Data was read from an open file
+    Read {
+        /// Offset read from, or None at the descriptor's file position
+        offset: Option<u64>,
+        /// Bytes read
+        bytes: u64,
+    },
+    /// Data was written to an open file
+    Written {
+        /// Offset written at, or None at the descriptor's file position
+        offset: Option<u64>,
+        /// Bytes written
+        bytes: u64,
+    },
+    /// Lock on an open file was requested with flock or fcntl
+    Locked {
+        /// Shared or exclusive
+        kind: LockKind,
+        /// Whether the call waited for, or was refused by, another holder
+        blocked: bool,
+        /// Whether the lock was taken; false for a refused non-blocking
+        /// request
+        acquired: bool,
+    },
+    /// Lock on an open file was released
+    Unlocked,
+    /// File was renamed
+    Renamed,
+    /// File was replaced by renaming a freshly written file over it
+    AtomicSave,
+}
+
+/// System call used to flush a file to storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncKind {
+    /// fsync: data and metadata
+    Fsync,
+    /// fdatasync: data and only the metadata needed to read it back
+    Fdatasync,
+    /// sync_file_range: part of the data
+    SyncFileRange,
+}
+
+#[cfg(feature = "abi")]
+impl SyncKind {
+    /// Decode the `SYNC_KIND_*` value sent by the kernel
+    ///
+    /// # Arguments
+    /// * `kind` - Raw sync kind; unknown values are treated as fsync
+    ///
+    /// # Returns
+    /// * `SyncKind` - Decoded kind
+    pub fn from_raw(kind: u64) -> Self {
+        match kind {
+            SYNC_KIND_FDATASYNC => SyncKind::Fdatasync,
+            SYNC_KIND_SYNC_FILE_RANGE => SyncKind::SyncFileRange,
+            _ => SyncKind::Fsync,
+        }
+    }
+}
+
+impl fmt::Display for SyncKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncKind::Fsync => write!(f, "fsync"),
+            SyncKind::Fdatasync => write!(f, "fdatasync"),
+            SyncKind::SyncFileRange => write!(f, "sync_file_range"),
+        }
+    }
+}
+
+/// Kind of lock requested on a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LockKind {
+    /// Shared (read) lock; many holders at once
+    Shared,
+    /// Exclusive (write) lock; one holder
+    Exclusive,
+}
+
+#[cfg(feature = "abi")]
+impl LockKind {
+    /// Decode the `LOCK_KIND_*` value sent by the kernel
+    ///
+    /// # Arguments
+    /// * `kind` - Raw lock kind; unknown values are treated as shared
+    ///
+    /// # Returns
+    /// * `LockKind` - Decoded kind
+    pub fn from_raw(kind: u64) -> Self {
+        match kind {
+            LOCK_KIND_EXCLUSIVE => LockKind::Exclusive,
+            _ => LockKind::Shared,
+        }
+    }
+}
+
+impl fmt::Display for LockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockKind::Shared => write!(f, "shared"),
+            LockKind::Exclusive => write!(f, "exclusive"),
+        }
+    }
+}
+
+/// Kind of filesystem object an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileType {
+    /// Regular file
+    File,
+    /// Directory
+    Dir,
+    /// Character or block device
+    Device,
+    /// Unix domain socket
+    Socket,
+    /// Named pipe
+    Fifo,
+    /// Symbolic link itself (e.g. opened with O_PATH | O_NOFOLLOW)
+    Symlink,
+}
+
+impl FileType {
+    /// Every type, in the order `--types` lists them
+    pub const ALL: [FileType; 6] = [
+        FileType::File,
+        FileType::Dir,
+        FileType::Device,
+        FileType::Socket,
+        FileType::Fifo,
+        FileType::Symlink,
+    ];
+
+    /// Name of the type, as printed and parsed
+    ///
+    /// # Returns
+    /// * `&'static str` - Name such as "dir"
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::File => "file",
+            FileType::Dir => "dir",
+            FileType::Device => "device",
+            FileType::Socket => "socket",
+            FileType::Fifo => "fifo",
+            FileType::Symlink => "symlink",
+        }
+    }
+
+    /// Decode the type bits of an inode mode
+    ///
+    /// # Arguments
+    /// * `mode` - `st_mode` or kernel `i_mode`; permission bits are ignored
+    ///
+    /// # Returns
+    /// * `Option<FileType>` - Type, or None if the mode is unknown (0)
+    pub fn from_mode(mode: u32) -> Option<Self> {
+        match mode & S_IFMT {
+            S_IFREG => Some(FileType::File),
+            S_IFDIR => Some(FileType::Dir),
+            S_IFCHR | S_IFBLK => Some(FileType::Device),
+            S_IFSOCK => Some(FileType::Socket),
+            S_IFIFO => Some(FileType::Fifo),
+            S_IFLNK => Some(FileType::Symlink),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for FileType {
+    type Err = UnknownName;
+
+    /// Parse the name `Display` prints
+    fn from_str(name: &str) -> Result<Self, UnknownName> {
+        FileType::ALL
+            .into_iter()
+            .find(|file_type| file_type.name() == name)
+            .ok_or(UnknownName)
+    }
+}
+
+impl FileAction {
+    /// Check whether the action changed the file rather than accessed it
+    ///
+    /// # Returns
+    /// * `bool` - True for truncation, metadata, xattr, link, rename,
+    ///   write and sync events
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            FileAction::Opened
+                | FileAction::AlreadyOpen
+                | FileAction::Closed
+                | FileAction::Read { .. }
+                | FileAction::Locked { .. }
+                | FileAction::Unlocked
+        )
+    }
+}
+
+impl fmt::Display for FileAction {
+    /// Format the file action for human-readable output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileAction::Opened => write!(f, "opened"),
+            FileAction::AlreadyOpen => write!(f, "already open"),
+            FileAction::Closed => write!(f, "closed"),
+            FileAction::ModeChanged { mode } => write!(f, "chmod {:04o}", mode),
+            FileAction::OwnerChanged { uid, gid } => {
+                write!(f, "chown {}:{}", uid, gid)
+            }
+            FileAction::Truncated { length } => {
+                write!(f, "truncate {}", length)
+            }
+            FileAction::Linked => write!(f, "linked"),
+            FileAction::Symlinked => write!(f, "symlinked"),
+            FileAction::XattrSet => write!(f, "setxattr"),
+            FileAction::XattrRemoved => write!(f, "removexattr"),
+            FileAction::Synced { kind } => write!(f, "{}", kind),
+            FileAction::Read { offset, bytes } => {
+                write!(f, "read {}", bytes)?;
+                offset.map_or(Ok(()), |offset| write!(f, " at {}", offset))
+            }
+            FileAction::Written { offset, bytes } => {
+                write!(f, "write {}", bytes)?;
+                offset.map_or(Ok(()), |offset| write!(f, " at {}", offset))
+            }
+            FileAction::Locked {
+                kind,
+                blocked,
+                acquired,
+            } => {
+                write!(f, "lock {}", kind)?;
+                match (acquired, blocked) {
+                    (false, _) => write!(f, " refused"),
+                    (true, true) => write!(f, " blocked"),
+                    (true, false) => Ok(()),
+                }
+            }
+            FileAction::Unlocked => write!(f, "unlock"),
+            FileAction::Renamed => write!(f, "renamed"),
+            FileAction::AtomicSave => write!(f, "modified via atomic rename"),
+        }
+    }
+}
+
+/// Identity of a file that survives renames and is shared by hardlinks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileId {
+    /// Device in kernel `dev_t` encoding (MAJOR << 20 | MINOR)
+    pub dev: u64,
+    /// Inode number on that device
+    pub ino: u64,
+}
+
+impl FileId {
+    /// Build an identity from raw kernel values
+    ///
+    /// # Arguments
+    /// * `dev` - Device in kernel `dev_t` encoding
+    /// * `ino` - Inode number
+    ///
+    /// # Returns
+    /// * `Option<FileId>` - Identity, or None if the kernel couldn't read
+    ///   the inode (reported as 0)
+    pub fn from_raw(dev: u64, ino: u64) -> Option<Self> {
+        (ino != 0).then_some(Self { dev, ino })
+    }
+
+    /// Major number of the device
+    ///
+    /// # Returns
+    /// * `u64` - Device major number
+    pub fn major(&self) -> u64 {
+        self.dev >> 20
+    }
+
+    /// Minor number of the device
+    ///
+    /// # Returns
+    /// * `u64` - Device minor number
+    pub fn minor(&self) -> u64 {
+        self.dev & 0xfffff
+    }
+}
+
+impl fmt::Display for FileId {
+    /// Format as "major:minor/inode", matching `stat -c '%Hd:%Ld/%i'`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}/{}", self.major(), self.minor(), self.ino)
+    }
+}
+
+/// How serious an event is, least serious first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// Routine activity
+    Info,
+    /// Worth recording, e.g. changes to user data
+    Notice,
+    /// Worth reviewing, e.g. reads of credentials
+    Warning,
+    /// Needs attention, e.g. changes to credentials
+    Critical,
+}
+
+impl Severity {
+    /// Every severity, least serious first
+    pub const ALL: [Severity; 4] = [
+        Severity::Info,
+        Severity::Notice,
+        Severity::Warning,
+        Severity::Critical,
+    ];
+
+    /// Name of the severity, as printed and parsed
+    ///
+    /// # Returns
+    /// * `&'static str` - Name such as "warning"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Notice => "notice",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = UnknownName;
+
+    /// Parse the name `Display` prints, ignoring ASCII case
+    fn from_str(name: &str) -> Result<Self, UnknownName> {
+        Severity::ALL
+            .into_iter()
+            .find(|severity| severity.name().eq_ignore_ascii_case(name))
+            .ok_or(UnknownName)
+    }
+}
+
+/// Layer of an overlay filesystem a file was reached through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverlayLayer {
+    /// The writable upper directory
+    Upper,
+    /// A lower directory, counted from the topmost (0)
+    Lower(usize),
+}
+
+impl fmt::Display for OverlayLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverlayLayer::Upper => write!(f, "upper"),
+            OverlayLayer::Lower(index) => write!(f, "lower {}", index),
+        }
+    }
+}
+
+/// Compare two strings ignoring case, including outside ASCII
+///
+/// # Arguments
+/// * `a` - First string
+/// * `b` - Second string
+///
+/// # Returns
+/// * `bool` - True if the lowercase forms are equal
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type_names() {
+        for file_type in FileType::ALL {
+            assert_eq!(file_type.to_string().parse(), Ok(file_type));
+        }
+        assert_eq!("pipe".parse::<FileType>(), Err(UnknownName));
+        assert_eq!(
+            FileType::from_mode(S_IFLNK | 0o777),
+            Some(FileType::Symlink)
+        );
+        assert_eq!(
+            FileType::from_mode(S_IFCHR | 0o620),
+            Some(FileType::Device)
+        );
+        assert_eq!(FileType::from_mode(S_IFREG), Some(FileType::File));
+        assert_eq!(FileType::from_mode(0), None);
+    }
+
+    #[test]
+    fn test_action_text() {
+        let action = FileAction::Written {
+            offset: Some(4096),
+            bytes: 512,
+        };
+        assert_eq!(action.to_string(), "write 512 at 4096");
+        assert!(action.is_write());
+        assert_eq!(
+            FileId {
+                dev: 8 << 20 | 1,
+                ino: 12
+            }
+            .to_string(),
+            "8:1/12"
+        );
+    }
+
+    #[test]
+    fn test_file_action_display() {
+        assert_eq!(format!("{}", FileAction::Opened), "opened");
+        assert_eq!(format!("{}", FileAction::AlreadyOpen), "already open");
+        assert_eq!(format!("{}", FileAction::Closed), "closed");
+        assert_eq!(
+            format!("{}", FileAction::ModeChanged { mode: 0o644 }),
+            "chmod 0644"
+        );
+        assert_eq!(
+            format!("{}", FileAction::OwnerChanged { uid: 0, gid: 100 }),
+            "chown 0:100"
+        );
+        assert_eq!(
+            format!("{}", FileAction::Truncated { length: 0 }),
+            "truncate 0"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                FileAction::Synced {
+                    kind: SyncKind::SyncFileRange
+                }
+            ),
+            "sync_file_range"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                FileAction::Read {
+                    offset: Some(8192),
+                    bytes: 4096
+                }
+            ),
+            "read 4096 at 8192"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                FileAction::Written {
+                    offset: None,
+                    bytes: 12
+                }
+            ),
+            "write 12"
+        );
+        assert_eq!(
+            format!("{}", FileAction::AtomicSave),
+            "modified via atomic rename"
+        );
+    }
+
+    #[test]
+    fn test_severity_names() {
+        for severity in Severity::ALL {
+            assert_eq!(severity.to_string().parse(), Ok(severity));
+        }
+        assert_eq!("Warning".parse(), Ok(Severity::Warning));
+        assert_eq!("urgent".parse::<Severity>(), Err(UnknownName));
+        assert!(Severity::Notice < Severity::Critical);
+    }
+}
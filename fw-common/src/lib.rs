@@ -1,47 +1,26 @@
 //! Shared definitions between eBPF program and userspace application
+//!
+//! `event` is the event model fw reports and consumers read back: actions,
+//! file types and identities, severities, with their text forms and
+//! optional serde support, and `record`, behind the `alloc` feature, is
+//! the `FileEvent` built from them. Neither needs std or Linux, so
+//! dashboards can decode fw's output on wasm32. `abi`, behind the default
+//! `abi` feature, is the kernel ABI shared with the probes, and
+//! `decoded`, which also needs the default `std` feature, converts its
+//! raw events to owned, typed ones.
 
-/// Maximum path length we can capture
-pub const MAX_PATH_LEN: usize = 256;
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
-/// Maximum filename length
-pub const MAX_FILENAME_LEN: usize = 64;
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
-/// Event data structure sent from eBPF program to userspace
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct FileEvent {
-    /// Process ID that triggered the event
-    pub pid: u32,
-    /// Thread group ID
-    pub tgid: u32,
-    /// File path (null-terminated)
-    pub path: [u8; MAX_PATH_LEN],
-    /// Filename only (null-terminated)
-    pub filename: [u8; MAX_FILENAME_LEN],
-    /// Event type: 0=open, 1=close
-    pub event_type: u32,
-}
+#[cfg(feature = "abi")]
+pub mod abi;
+#[cfg(all(feature = "abi", feature = "std"))]
+pub mod decoded;
+pub mod event;
+#[cfg(feature = "alloc")]
+pub mod record;
 
-impl FileEvent {
-    /// Get the path as a string
-    pub fn path_str(&self) -> Result<&str, std::str::Utf8Error> {
-        let null_pos = self.path.iter().position(|&b| b == 0).unwrap_or(self.path.len());
-        std::str::from_utf8(&self.path[..null_pos])
-    }
-
-    /// Get the filename as a string
-    pub fn filename_str(&self) -> Result<&str, std::str::Utf8Error> {
-        let null_pos = self.filename.iter().position(|&b| b == 0).unwrap_or(self.filename.len());
-        std::str::from_utf8(&self.filename[..null_pos])
-    }
-
-    /// Check if this is an open event
-    pub fn is_open(&self) -> bool {
-        self.event_type == 0
-    }
-
-    /// Check if this is a close event
-    pub fn is_close(&self) -> bool {
-        self.event_type == 1
-    }
-}
+#[cfg(feature = "abi")]
+pub use abi::*;
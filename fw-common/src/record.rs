@@ -0,0 +1,669 @@
+//! Event record module
+//!
+//! The record fw keeps for each file operation, as `fw forward` sends it
+//! between hosts and as fw prints it, with the stack traces recorded
+//! alongside. It owns its strings, so it needs an allocator but not std,
+//! and decodes on wasm32 with the `serde` feature.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use chrono::{DateTime, Utc};
+use core::fmt;
+use core::time::Duration;
+
+use crate::event::{
+    eq_ignore_case, FileAction, FileId, FileType, OverlayLayer, Severity,
+};
+
+/// One resolved stack frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Return address (user) or instruction pointer (kernel)
+    pub addr: u64,
+    /// Whether the frame is in the kernel
+    pub kernel: bool,
+    /// Function name, with the offset into it for kernel frames
+    pub symbol: Option<String>,
+    /// Source file and line, if the mapped file has debug info
+    pub location: Option<String>,
+    /// Mapped file (user) or kernel module the address is in
+    pub module: Option<String>,
+}
+
+impl fmt::Display for Frame {
+    /// Format as "0x7f3a1c2b4d10 read_config (src/config.c:42)
+    /// [/usr/bin/app]", with "??" for an unknown symbol
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} {}",
+            self.addr,
+            self.symbol.as_deref().unwrap_or("??")
+        )?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        if let Some(module) = &self.module {
+            write!(f, " [{}]", module)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stack trace recorded with an event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stack {
+    /// Kernel instruction pointers, innermost first
+    pub kernel: Vec<u64>,
+    /// User return addresses, innermost first
+    pub user: Vec<u64>,
+    /// Resolved frames, kernel first; empty until symbolized
+    pub frames: Vec<Frame>,
+}
+
+impl Stack {
+    /// Check whether no addresses were recorded
+    ///
+    /// # Returns
+    /// * `bool` - True if both stacks are empty
+    pub fn is_empty(&self) -> bool {
+        self.kernel.is_empty() && self.user.is_empty()
+    }
+}
+
+/// Represents a file operation event captured from the system
+///
+/// Contains all relevant information about a file operation including
+/// the file path, the program that performed the operation, the type
+/// of operation, and when it occurred.
+///
+/// Serializes to the JSON `fw forward` sends between hosts, without the
+/// stack.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileEvent {
+    /// Full path to the file that was accessed; escaped with `\xNN` for
+    /// bytes that are not valid UTF-8
+    pub file_path: String,
+    /// Exact path bytes when they are not valid UTF-8
+    pub raw_path: Option<Vec<u8>>,
+    /// Name of the program/process that accessed the file
+    pub program_name: String,
+    /// Type of file operation (opened or closed)
+    pub action: FileAction,
+    /// Timestamp when the operation occurred
+    pub timestamp: DateTime<Utc>,
+    /// Process ID of the program that accessed the file
+    pub pid: u32,
+    /// Real user ID of the program, if the kernel captured it
+    pub uid: Option<u32>,
+    /// Real group ID of the program, if the kernel captured it
+    pub gid: Option<u32>,
+    /// Name of the user, or the uid where no name applies (set by
+    /// enrichment)
+    pub user: Option<String>,
+    /// Name of the group, or the gid where no name applies (set by
+    /// enrichment)
+    pub group: Option<String>,
+    /// True if the kernel could not capture the full path
+    pub path_truncated: bool,
+    /// Mount point of the filesystem holding the file, if known
+    pub mount_point: Option<String>,
+    /// Type of the filesystem holding the file (e.g. "ext4"), if known
+    pub fs_type: Option<String>,
+    /// Remote source (e.g. "server:/export") for network filesystems
+    pub remote_source: Option<String>,
+    /// Overlay layer the file was reached through, when the kernel
+    /// reported a path inside the layer rather than the merged mount
+    pub overlay_layer: Option<OverlayLayer>,
+    /// Time the kernel spent in the open, sync or lock call, in
+    /// nanoseconds (opens, syncs and locks only)
+    pub open_latency_ns: Option<u64>,
+    /// Device and inode of the file, if the kernel captured them
+    pub file_id: Option<FileId>,
+    /// Descriptor the file was opened on or closed from (opens and
+    /// closes only)
+    pub fd: Option<i32>,
+    /// For link events, the existing file a hardlink points at or the
+    /// contents of a symlink; `file_path` is the newly created link
+    pub link_source: Option<String>,
+    /// For rename events, the previous name; for atomic saves, the
+    /// temporary file that was written and renamed into place
+    pub renamed_from: Option<String>,
+    /// Flags the file was opened with (opens only)
+    pub open_flags: Option<u32>,
+    /// For extended attribute events, the attribute name (e.g.
+    /// "security.selinux")
+    pub xattr_name: Option<String>,
+    /// Custom metadata attached by enrichers (e.g. "team" => "payments")
+    pub tags: BTreeMap<String, String>,
+    /// How serious the event is, once classified
+    pub severity: Option<Severity>,
+    /// Name of the policy rule that labelled the event, once classified
+    /// (none if no rule matched)
+    pub rule: Option<String>,
+    /// Kind of object the path names (regular file, directory, device,
+    /// ...), if known
+    pub file_type: Option<FileType>,
+    /// Size of the file in bytes when it was opened (set by enrichment)
+    pub file_size: Option<u64>,
+    /// Last modification of the file when it was opened (set by
+    /// enrichment)
+    pub file_modified: Option<DateTime<Utc>>,
+    /// One in this many events like this one was sent, if the kernel was
+    /// sampling when it was
+    pub sample_rate: Option<u32>,
+    /// Stack trace of the call, with `--stacks`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stack: Option<Arc<Stack>>,
+}
+
+impl FileEvent {
+    /// Create a new file event
+    ///
+    /// # Arguments
+    /// * `file_path` - Path to the file that was accessed
+    /// * `program_name` - Name of the program that accessed the file
+    /// * `action` - Type of file operation
+    /// * `pid` - Process ID of the accessing program
+    /// * `timestamp` - When the operation occurred
+    ///
+    /// # Returns
+    /// * `FileEvent` - New file event
+    pub fn new(
+        file_path: String,
+        program_name: String,
+        action: FileAction,
+        pid: u32,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            file_path,
+            raw_path: None,
+            program_name,
+            action,
+            timestamp,
+            pid,
+            uid: None,
+            gid: None,
+            user: None,
+            group: None,
+            path_truncated: false,
+            mount_point: None,
+            fs_type: None,
+            remote_source: None,
+            overlay_layer: None,
+            open_latency_ns: None,
+            file_id: None,
+            fd: None,
+            link_source: None,
+            renamed_from: None,
+            open_flags: None,
+            xattr_name: None,
+            tags: BTreeMap::new(),
+            severity: None,
+            rule: None,
+            file_type: None,
+            file_size: None,
+            file_modified: None,
+            sample_rate: None,
+            stack: None,
+        }
+    }
+
+    /// Number of events this one stands for in counts
+    ///
+    /// # Returns
+    /// * `u64` - The sampling rate it was sent at, or 1 if it wasn't
+    ///   sampled
+    pub fn weight(&self) -> u64 {
+        u64::from(self.sample_rate.unwrap_or(1).max(1))
+    }
+
+    /// Mark whether the file path was truncated by the kernel
+    ///
+    /// # Arguments
+    /// * `truncated` - True if the path is incomplete
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the truncation flag set
+    pub fn with_path_truncated(mut self, truncated: bool) -> Self {
+        self.path_truncated = truncated;
+        self
+    }
+
+    /// Keep the exact bytes of a path that is not valid UTF-8
+    ///
+    /// # Arguments
+    /// * `raw` - Path bytes, or None if the path is valid UTF-8
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the raw path set
+    pub fn with_raw_path(mut self, raw: Option<Vec<u8>>) -> Self {
+        self.raw_path = raw;
+        self
+    }
+
+    /// Replace the construction-time timestamp
+    ///
+    /// # Arguments
+    /// * `timestamp` - When the operation occurred
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the timestamp set
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Attach the time the kernel spent completing the open
+    ///
+    /// # Arguments
+    /// * `latency_ns` - Open latency in nanoseconds
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the latency set
+    pub fn with_open_latency_ns(mut self, latency_ns: u64) -> Self {
+        self.open_latency_ns = Some(latency_ns);
+        self
+    }
+
+    /// Attach the real user and group IDs of the program
+    ///
+    /// # Arguments
+    /// * `uid` - User ID
+    /// * `gid` - Group ID
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the ids set
+    pub fn with_ids(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = Some(uid);
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Attach the descriptor the file was opened on or closed from
+    ///
+    /// # Arguments
+    /// * `fd` - Descriptor number
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the descriptor set
+    pub fn with_fd(mut self, fd: i32) -> Self {
+        self.fd = Some(fd);
+        self
+    }
+
+    /// Attach the device and inode identity of the file
+    ///
+    /// # Arguments
+    /// * `file_id` - Identity of the file, if known
+    ///
+    /// # Returns
+    /// * `FileEvent` - The event with the identity set
+    pub fn with_file_id(mut self, file_id: Option<FileId>) -> Self {
+        self.file_id = file_id;
+        self
+    }
+
+    /// Time between the file's last modification and the event
+    ///
+    /// # Returns
+    /// * `Option<Duration>` - Age of the file, or None if it wasn't
+    ///   statted; modifications after the event count as zero
+    pub fn file_age(&self) -> Option<Duration> {
+        self.file_modified.map(|modified| {
+            (self.timestamp - modified).to_std().unwrap_or_default()
+        })
+    }
+
+    /// Check whether the file lives on a network filesystem
+    ///
+    /// # Returns
+    /// * `Option<bool>` - Whether the filesystem is remote, or None if the
+    ///   filesystem is unknown
+    pub fn is_remote(&self) -> Option<bool> {
+        self.fs_type.as_ref().map(|_| self.remote_source.is_some())
+    }
+
+    /// Check if this event matches the specified file extensions filter
+    ///
+    /// # Arguments
+    /// * `extensions` - Optional list of file extensions to match against
+    /// * `case_sensitive` - Compare exactly instead of ignoring case
+    ///
+    /// # Returns
+    /// * `bool` - True if the file matches the filter or no filter is set
+    pub fn matches_extensions(
+        &self,
+        extensions: &Option<Vec<String>>,
+        case_sensitive: bool,
+    ) -> bool {
+        match extensions {
+            None => true, // No filter means all files match
+            Some(exts) => {
+                // Extract file extension from path
+                if let Some(file_name) = self.file_path.split('/').next_back() {
+                    if let Some(ext) = file_name.split('.').next_back() {
+                        return exts.iter().any(|e| match case_sensitive {
+                            true => e == ext,
+                            false => eq_ignore_case(e, ext),
+                        });
+                    }
+                }
+                false // No extension found or doesn't match
+            }
+        }
+    }
+}
+
+impl fmt::Display for FileEvent {
+    /// Format the file event for output to stderr
+    ///
+    /// Output format: timestamp | program_name (pid) | action | file_path
+    ///
+    /// Once user and group names are resolved they follow the pid (e.g.
+    /// "postgres (812, postgres:postgres)").
+    ///
+    /// Link events show what the link points at after the new link path
+    /// (e.g. "/usr/bin/python -> python3"), and renames and atomic saves
+    /// the name the file had before (e.g. "main.rs (from .main.rs.tmp)").
+    ///
+    /// Opens with a measured latency show it after the action (e.g.
+    /// "opened (1.2ms)"), and extended attribute events show the attribute
+    /// name there (e.g. "setxattr security.selinux"). Objects other than
+    /// regular files show their type right after the action (e.g.
+    /// "opened device").
+    ///
+    /// Truncated paths are suffixed with " (truncated)" and paths that are
+    /// not valid UTF-8, shown escaped, with " (non-utf8)". The filesystem
+    /// type is appended in brackets when known (e.g. " [ext4]"), followed
+    /// by the remote source for network filesystems (e.g.
+    /// " [nfs4 server:/export]"). The file identity follows when known
+    /// (e.g. " <8:1/1234>"). Severities above info are marked next (e.g.
+    /// " !warning"), and tags come last in braces (e.g. " {team=payments}").
+    ///
+    /// The alternate form (`{:#}`) gives the timestamp to the nanosecond
+    /// (e.g. "2024-01-01 12:00:05.250000000 UTC"), as recordings keep it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timestamp = if f.alternate() {
+            "%Y-%m-%d %H:%M:%S%.9f UTC"
+        } else {
+            "%Y-%m-%d %H:%M:%S UTC"
+        };
+        write!(
+            f,
+            "{} | {} ({}",
+            self.timestamp.format(timestamp),
+            self.program_name,
+            self.pid,
+        )?;
+        if let Some(user) = &self.user {
+            write!(f, ", {}", user)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, ":{}", group)?;
+        }
+        write!(f, ") | {}", self.action)?;
+        if let Some(file_type) = self.file_type.filter(|&t| t != FileType::File)
+        {
+            write!(f, " {}", file_type)?;
+        }
+        if let Some(name) = &self.xattr_name {
+            write!(f, " {}", name)?;
+        }
+        if let Some(latency_ns) = self.open_latency_ns {
+            write!(f, " ({})", format_latency(latency_ns))?;
+        }
+        write!(f, " | {}", self.file_path)?;
+        if let Some(source) = &self.link_source {
+            write!(f, " -> {}", source)?;
+        }
+        if let Some(from) = &self.renamed_from {
+            write!(f, " (from {})", from)?;
+        }
+        if self.path_truncated {
+            write!(f, " (truncated)")?;
+        }
+        if self.raw_path.is_some() {
+            write!(f, " (non-utf8)")?;
+        }
+        match (&self.fs_type, &self.remote_source) {
+            (Some(fs_type), Some(source)) => {
+                write!(f, " [{} {}]", fs_type, source)?
+            }
+            (Some(fs_type), None) => match self.overlay_layer {
+                Some(layer) => write!(f, " [{} {}]", fs_type, layer)?,
+                None => write!(f, " [{}]", fs_type)?,
+            },
+            _ => {}
+        }
+        if let Some(file_id) = &self.file_id {
+            write!(f, " <{}>", file_id)?;
+        }
+        if let Some(severity) = self.severity.filter(|&s| s > Severity::Info) {
+            write!(f, " !{}", severity)?;
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write!(f, " {{{}}}", tags.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a latency or duration with a unit suited to its magnitude
+///
+/// # Arguments
+/// * `latency_ns` - Latency in nanoseconds
+///
+/// # Returns
+/// * `String` - Human-readable latency (e.g. "850ns", "1.2ms")
+pub fn format_latency(latency_ns: u64) -> String {
+    match latency_ns {
+        0..=999 => format!("{}ns", latency_ns),
+        1_000..=999_999 => format!("{:.1}us", latency_ns as f64 / 1e3),
+        1_000_000..=999_999_999 => {
+            format!("{:.1}ms", latency_ns as f64 / 1e6)
+        }
+        _ => format!("{:.2}s", latency_ns as f64 / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed time for events whose time isn't checked
+    fn timestamp() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    #[test]
+    fn test_file_event_matches_extensions_no_filter() {
+        let event = FileEvent::new(
+            "/path/to/file.rs".to_string(),
+            "rustc".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        );
+        assert!(event.matches_extensions(&None, false));
+    }
+
+    #[test]
+    fn test_file_event_matches_extensions_with_filter() {
+        let event = FileEvent::new(
+            "/path/to/file.rs".to_string(),
+            "rustc".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        );
+        let extensions = Some(vec!["rs".to_string(), "md".to_string()]);
+        assert!(event.matches_extensions(&extensions, false));
+
+        let non_matching_extensions =
+            Some(vec!["py".to_string(), "js".to_string()]);
+        assert!(!event.matches_extensions(&non_matching_extensions, false));
+    }
+
+    #[test]
+    fn test_file_event_format() {
+        let event = FileEvent::new(
+            "/path/to/file.rs".to_string(),
+            "rustc".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        );
+        let formatted = format!("{}", event);
+        assert!(formatted.contains("rustc (1234)"));
+        assert!(formatted.contains("opened"));
+        assert!(formatted.contains("/path/to/file.rs"));
+        assert!(!formatted.contains("(truncated)"));
+
+        let mut event = event.with_ids(999, 999);
+        event.user = Some("alice".to_string());
+        event.group = Some("999".to_string());
+        assert!(format!("{}", event).contains("rustc (1234, alice:999) |"));
+    }
+
+    #[test]
+    fn test_file_event_format_truncated() {
+        let event = FileEvent::new(
+            "/very/long/path".to_string(),
+            "node".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        )
+        .with_path_truncated(true);
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("/very/long/path (truncated)"));
+    }
+
+    #[test]
+    fn test_file_event_format_latency() {
+        let event = FileEvent::new(
+            "/nfs/slow.db".to_string(),
+            "sqlite".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        )
+        .with_open_latency_ns(12_345_678);
+        let formatted = format!("{}", event);
+        assert!(formatted.contains("| opened (12.3ms) | /nfs/slow.db"));
+
+        assert_eq!(format_latency(850), "850ns");
+        assert_eq!(format_latency(1_500), "1.5us");
+        assert_eq!(format_latency(2_000_000_000), "2.00s");
+    }
+
+    #[test]
+    fn test_file_event_format_link() {
+        let mut event = FileEvent::new(
+            "/usr/bin/python".to_string(),
+            "ln".to_string(),
+            FileAction::Symlinked,
+            42,
+            timestamp(),
+        );
+        event.link_source = Some("python3".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| symlinked | /usr/bin/python -> python3"));
+    }
+
+    #[test]
+    fn test_file_event_format_xattr() {
+        let mut event = FileEvent::new(
+            "/usr/bin/ping".to_string(),
+            "setcap".to_string(),
+            FileAction::XattrSet,
+            42,
+            timestamp(),
+        );
+        event.xattr_name = Some("security.capability".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted
+            .ends_with("| setxattr security.capability | /usr/bin/ping"));
+    }
+
+    #[test]
+    fn test_file_event_format_file_id() {
+        // /dev/sda1 is 8:1
+        let file_id = FileId::from_raw((8 << 20) | 1, 1234);
+        let mut event = FileEvent::new(
+            "/data/file.rs".to_string(),
+            "rustc".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        )
+        .with_file_id(file_id);
+        event.fs_type = Some("ext4".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| /data/file.rs [ext4] <8:1/1234>"));
+
+        event.tags.insert("team".to_string(), "db".to_string());
+        event.tags.insert("class".to_string(), "pii".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("<8:1/1234> {class=pii, team=db}"));
+
+        event.severity = Some(Severity::Info);
+        assert!(
+            format!("{}", event).ends_with("<8:1/1234> {class=pii, team=db}")
+        );
+        event.severity = Some(Severity::Critical);
+        assert!(format!("{}", event)
+            .ends_with("<8:1/1234> !critical {class=pii, team=db}"));
+
+        assert_eq!(FileId::from_raw(8 << 20, 0), None);
+    }
+
+    #[test]
+    fn test_file_event_format_fs_type() {
+        let mut event = FileEvent::new(
+            "/data/file.rs".to_string(),
+            "rustc".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        );
+        event.fs_type = Some("xfs".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| /data/file.rs [xfs]"));
+
+        event.fs_type = Some("nfs".to_string());
+        event.remote_source = Some("nas:/vol1".to_string());
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| /data/file.rs [nfs nas:/vol1]"));
+    }
+
+    #[test]
+    fn test_file_event_format_file_type() {
+        let mut event = FileEvent::new(
+            "/run/docker.sock".to_string(),
+            "curl".to_string(),
+            FileAction::Opened,
+            1234,
+            timestamp(),
+        );
+        event.file_type = Some(FileType::Socket);
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| opened socket | /run/docker.sock"));
+
+        // Regular files are the common case and aren't labelled
+        event.file_type = Some(FileType::File);
+        let formatted = format!("{}", event);
+        assert!(formatted.ends_with("| opened | /run/docker.sock"));
+    }
+}
@@ -0,0 +1,87 @@
+//! Cwd module
+//!
+//! Probes reporting working directory changes made with chdir and
+//! fchdir, so userspace can resolve relative paths against where a
+//! process is now rather than where it was when first seen.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::bpf_get_current_pid_tgid,
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{FileEvent, EVENT_TYPE_CHDIR};
+
+use crate::helpers::{
+    current_event, emit, read_user_path, syscall_arg, task_allowed,
+};
+use crate::maps::CHDIR_CALLS;
+
+/// Kernel probe for chdir system call
+#[kprobe]
+pub fn chdir(ctx: ProbeContext) -> u32 {
+    match try_chdir(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_chdir(ctx: ProbeContext) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let path: u64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let event = current_event(EVENT_TYPE_CHDIR, -1).ok_or(1u32)?;
+    read_user_path(event, path);
+    remember_chdir_call(event)
+}
+
+/// Kernel probe for fchdir system call
+#[kprobe]
+pub fn fchdir(ctx: ProbeContext) -> u32 {
+    match try_fchdir(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fchdir(ctx: ProbeContext) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let event = current_event(EVENT_TYPE_CHDIR, fd).ok_or(1u32)?;
+    remember_chdir_call(event)
+}
+
+/// Store the chdir event until the syscall returns
+fn remember_chdir_call(event: &FileEvent) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    CHDIR_CALLS
+        .insert(&pid_tgid, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by chdir and fchdir, reporting successful calls
+#[kretprobe]
+pub fn chdir_ret(ctx: RetProbeContext) -> u32 {
+    match try_chdir_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_chdir_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = match CHDIR_CALLS.get_ptr_mut(&pid_tgid) {
+        Some(event) => unsafe { &mut *event },
+        None => return Ok(0),
+    };
+    let ret: i64 = ctx.ret().unwrap_or(-1);
+    if ret == 0 {
+        emit(&ctx, event);
+    }
+    CHDIR_CALLS.remove(&pid_tgid).ok();
+    Ok(0)
+}
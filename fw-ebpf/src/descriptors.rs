@@ -0,0 +1,153 @@
+//! Descriptors module
+//!
+//! Probes keeping the userspace descriptor table in sync: close and the
+//! dup family. Closes carry only the descriptor; userspace correlates it
+//! with the path it saw opening.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::bpf_get_current_pid_tgid,
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use aya_log_ebpf::info;
+use fw_common::{EVENT_TYPE_CLOSE, EVENT_TYPE_DUP};
+
+use crate::helpers::{descriptor_event, emit, syscall_arg, task_allowed};
+use crate::maps::DUP_SOURCES;
+
+/// fcntl command that duplicates a descriptor
+const F_DUPFD: u32 = 0;
+
+/// fcntl command that duplicates a descriptor with close-on-exec set
+const F_DUPFD_CLOEXEC: u32 = 1030;
+
+/// Kernel probe for close system call
+#[kprobe]
+pub fn close(ctx: ProbeContext) -> u32 {
+    match try_close(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_close(ctx: ProbeContext) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+
+    // Get the file descriptor parameter
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+
+    // We can't easily get the filename from just the fd in eBPF,
+    // so we'll send a close event with the fd and let userspace
+    // correlate it with previously opened files
+    let event =
+        descriptor_event(EVENT_TYPE_CLOSE, pid, tgid, fd).ok_or(1u32)?;
+
+    emit(&ctx, event);
+    info!(&ctx, "File close: pid={} fd={}", pid, fd);
+    Ok(0)
+}
+
+/// Kernel probe for dup system call
+#[kprobe]
+pub fn dup(ctx: ProbeContext) -> u32 {
+    match try_dup_entry(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for dup2 system call
+#[kprobe]
+pub fn dup2(ctx: ProbeContext) -> u32 {
+    match try_dup_entry(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for dup3 system call
+#[kprobe]
+pub fn dup3(ctx: ProbeContext) -> u32 {
+    match try_dup_entry(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Remember the source descriptor of a dup/dup2/dup3 call
+///
+/// All three take the source descriptor as their first argument.
+fn try_dup_entry(ctx: ProbeContext) -> Result<u32, u32> {
+    let old_fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    remember_dup_source(old_fd)
+}
+
+/// Kernel probe for fcntl system call, tracking F_DUPFD duplication
+#[kprobe]
+pub fn fcntl(ctx: ProbeContext) -> u32 {
+    match try_fcntl(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fcntl(ctx: ProbeContext) -> Result<u32, u32> {
+    let cmd: u32 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    if cmd != F_DUPFD && cmd != F_DUPFD_CLOEXEC {
+        return Ok(0);
+    }
+    let old_fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    remember_dup_source(old_fd)
+}
+
+/// Store the source descriptor until the syscall returns the new one
+fn remember_dup_source(old_fd: i32) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    DUP_SOURCES
+        .insert(&pid_tgid, &old_fd, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by dup, dup2, dup3 and fcntl
+///
+/// Every one of these returns the new descriptor on success. For fcntl,
+/// only F_DUPFD commands stored a source descriptor, so other commands
+/// are ignored here.
+#[kretprobe]
+pub fn dup_ret(ctx: RetProbeContext) -> u32 {
+    match try_dup_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_dup_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let old_fd = match unsafe { DUP_SOURCES.get(&pid_tgid) } {
+        Some(fd) => *fd,
+        None => return Ok(0),
+    };
+    DUP_SOURCES.remove(&pid_tgid).ok();
+
+    let new_fd: i64 = ctx.ret().ok_or(1u32)?;
+    if new_fd < 0 {
+        return Ok(0);
+    }
+
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    let event = descriptor_event(EVENT_TYPE_DUP, pid, tgid, new_fd as i32)
+        .ok_or(1u32)?;
+    event.old_fd = old_fd;
+
+    emit(&ctx, event);
+    info!(&ctx, "Descriptor dup: pid={} {} -> {}", pid, old_fd, new_fd);
+    Ok(0)
+}
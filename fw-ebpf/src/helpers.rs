@@ -0,0 +1,457 @@
+//! Helpers module
+//!
+//! Event construction and userspace reads shared by the probe programs.
+
+#[cfg(bpf_target_arch = "x86_64")]
+use aya_ebpf::bindings::pt_regs;
+#[cfg(bpf_target_arch = "aarch64")]
+use aya_ebpf::bindings::user_pt_regs as pt_regs;
+#[cfg(bpf_target_arch = "riscv64")]
+use aya_ebpf::bindings::user_regs_struct as pt_regs;
+use aya_ebpf::{
+    bindings::{BPF_ANY, BPF_F_USER_STACK, BPF_NOEXIST},
+    helpers::{
+        bpf_get_current_comm, bpf_get_current_pid_tgid,
+        bpf_get_current_uid_gid, bpf_get_prandom_u32, bpf_probe_read_kernel,
+    },
+    programs::ProbeContext,
+    EbpfContext,
+};
+use fw_common::{
+    is_pseudo_fs_path, AggKey, FileEvent, COMM_FILTER_ALLOW, COMM_FILTER_OFF,
+    EVENT_ABI_VERSION, EVENT_FLAG_PATH_TRUNCATED, EVENT_FLAG_SAMPLED,
+    EVENT_FLAG_TRIPWIRE, EVENT_TYPE_CHDIR, EVENT_TYPE_DUP, EVENT_TYPE_EXIT,
+    EVENT_TYPE_FORK, EVENT_TYPE_LINK_SOURCE, EVENT_TYPE_SYNC, MAX_COMM_LEN,
+    MAX_EXT_LEN, MAX_FILENAME_LEN, MAX_PATH_LEN, MAX_SAMPLE_EXEMPTIONS,
+    MAX_STACK_CRITERIA, STACK_KERNEL, STACK_USER, UID_FILTER_INCLUDE,
+};
+
+use crate::maps::{
+    AGG_ACTIVE, AGG_COUNTS, AGG_OVERFLOW, COMM_FILTER, COMM_FILTER_MODE,
+    EVENTS, LEADER_COMMS, PROCESS_STARTS, PSEUDO_FS_EXCLUDED, SAMPLE_EXEMPT,
+    SAMPLE_EXEMPT_COUNT, SAMPLE_RATE, SCRATCH, STACKS, STACK_CRITERIA,
+    STACK_CRITERIA_COUNT, STACK_MODE, TAIL_CALLS, TRIPWIRES, UID_FILTER,
+    UID_FILTER_ACTIVE,
+};
+
+/// Check the calling task against the uid and process filters
+pub(crate) fn task_allowed() -> bool {
+    uid_allowed() && comm_allowed()
+}
+
+/// Check the calling task's uid against the uid filter
+fn uid_allowed() -> bool {
+    let uid = bpf_get_current_uid_gid() as u32;
+    if let Some(entry) = unsafe { UID_FILTER.get(&uid) } {
+        return *entry == UID_FILTER_INCLUDE;
+    }
+    !matches!(UID_FILTER_ACTIVE.get(0), Some(&1))
+}
+
+/// Check the calling task's process name against the process filter
+///
+/// The name is the thread group leader's, as userspace reports it, so
+/// threads that renamed themselves are judged with their process.
+fn comm_allowed() -> bool {
+    let mode = match COMM_FILTER_MODE.get(0) {
+        Some(&mode) if mode != COMM_FILTER_OFF => mode,
+        _ => return true,
+    };
+    let Some(comm) = leader_comm() else {
+        return true;
+    };
+    let listed = unsafe { COMM_FILTER.get(&comm) }.is_some();
+    listed == (mode == COMM_FILTER_ALLOW)
+}
+
+/// Name of the calling task's thread group leader
+///
+/// The leader records its name whenever it passes through here; other
+/// threads fall back to their own name until it has.
+fn leader_comm() -> Option<[u8; MAX_COMM_LEN]> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let process = (pid_tgid >> 32) as u32;
+    let thread = pid_tgid as u32;
+    if thread != process {
+        if let Some(comm) = unsafe { LEADER_COMMS.get(&process) } {
+            return Some(*comm);
+        }
+        return bpf_get_current_comm().ok();
+    }
+    let comm = bpf_get_current_comm().ok()?;
+    if unsafe { LEADER_COMMS.get(&process) } != Some(&comm) {
+        LEADER_COMMS.insert(&process, &comm, BPF_ANY as u64).ok();
+    }
+    Some(comm)
+}
+
+/// Check whether a path is a tripwire decoy
+///
+/// # Arguments
+/// * `path` - First chunk of the path being opened, null-padded
+pub(crate) fn is_tripwire(path: &[u8; MAX_PATH_LEN]) -> bool {
+    unsafe { TRIPWIRES.get(path) }.is_some()
+}
+
+/// Check whether opens of a path are left out as pseudo-filesystem noise
+///
+/// # Arguments
+/// * `path` - First chunk of the path being opened
+pub(crate) fn pseudo_fs_excluded(path: &[u8]) -> bool {
+    matches!(PSEUDO_FS_EXCLUDED.get(0), Some(&1)) && is_pseudo_fs_path(path)
+}
+
+/// Check whether events are counted in the kernel instead of sent
+pub(crate) fn aggregating() -> bool {
+    matches!(AGG_ACTIVE.get(0), Some(&1))
+}
+
+/// Check whether an event passes the overload sampling rate
+///
+/// Events matching SAMPLE_EXEMPT and tripwire opens always pass. Others
+/// passing while sampling are flagged, so userspace counts each as the
+/// events it stands for.
+fn sampled(event: &mut FileEvent) -> bool {
+    if event.flags & EVENT_FLAG_TRIPWIRE != 0 {
+        return true;
+    }
+    match SAMPLE_RATE.get(0) {
+        Some(&rate) if rate > 1 && !sample_exempt(event) => {
+            event.flags |= EVENT_FLAG_SAMPLED;
+            bpf_get_prandom_u32() % rate == 0
+        }
+        _ => true,
+    }
+}
+
+/// Check whether an event matches one of the sampling exemptions
+fn sample_exempt(event: &FileEvent) -> bool {
+    let count = SAMPLE_EXEMPT_COUNT.get(0).copied().unwrap_or(0);
+    for index in 0..MAX_SAMPLE_EXEMPTIONS {
+        if index >= count {
+            break;
+        }
+        match SAMPLE_EXEMPT.get(index) {
+            Some(criterion) if criterion.matches(event.pid, &event.path) => {
+                return true
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Send an event to userspace, or count it when aggregating
+///
+/// Only the first chunk of a reported event is counted; bookkeeping
+/// events (dup, fork, exit, link sources, chdir) only matter to userspace's
+/// descriptor table, which isn't kept while aggregating. When sampling,
+/// bookkeeping events, the chunks of long paths and events matching
+/// SAMPLE_EXEMPT are always sent so the table, path assembly and
+/// serious alerts stay intact. Tripwire opens are sent even while
+/// aggregating. Sent events get the stacks
+/// selected in STACK_MODE, taken where the event is sent (for most
+/// calls, their return probe).
+pub(crate) fn emit<C: EbpfContext>(ctx: &C, event: &mut FileEvent) {
+    let bookkeeping = matches!(
+        event.event_type,
+        EVENT_TYPE_DUP
+            | EVENT_TYPE_FORK
+            | EVENT_TYPE_EXIT
+            | EVENT_TYPE_LINK_SOURCE
+            | EVENT_TYPE_CHDIR
+    );
+    if aggregating() && event.flags & EVENT_FLAG_TRIPWIRE == 0 {
+        if event.chunk_index == 0 && !bookkeeping {
+            count_event(event);
+        }
+        return;
+    }
+    let chunked = event.chunk_index > 0 || event.has_more_chunks();
+    if bookkeeping || chunked || sampled(event) {
+        if !bookkeeping && event.chunk_index == 0 {
+            record_stacks(ctx, event);
+        }
+        EVENTS.output(ctx, event, 0);
+    }
+}
+
+/// Store the ids of the stacks selected in STACK_MODE in an event that
+/// meets the stack capture criteria
+fn record_stacks<C: EbpfContext>(ctx: &C, event: &mut FileEvent) {
+    let mode = STACK_MODE.get(0).copied().unwrap_or(0);
+    if mode == 0 || !stack_wanted(event) {
+        return;
+    }
+    if mode & STACK_USER != 0 {
+        event.user_stack_id =
+            unsafe { STACKS.get_stackid(ctx, BPF_F_USER_STACK as u64) }
+                .map_or(-1, |id| id as i32);
+    }
+    if mode & STACK_KERNEL != 0 {
+        event.kernel_stack_id =
+            unsafe { STACKS.get_stackid(ctx, 0) }.map_or(-1, |id| id as i32);
+    }
+}
+
+/// Check an event against the stack capture criteria
+///
+/// Without criteria every event's stacks are recorded; with them, only
+/// those of events matching one of the criteria.
+fn stack_wanted(event: &FileEvent) -> bool {
+    let count = STACK_CRITERIA_COUNT.get(0).copied().unwrap_or(0);
+    if count == 0 {
+        return true;
+    }
+    for index in 0..MAX_STACK_CRITERIA {
+        if index >= count {
+            break;
+        }
+        match STACK_CRITERIA.get(index) {
+            Some(criterion) if criterion.matches(event.pid, &event.path) => {
+                return true
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Add one to the counter of the event's program, extension and action
+fn count_event(event: &FileEvent) {
+    let mut key = AggKey {
+        comm: bpf_get_current_comm().unwrap_or([0; MAX_COMM_LEN]),
+        ext: [0; MAX_EXT_LEN],
+        event_type: event.event_type,
+        detail: 0,
+    };
+    if event.event_type == EVENT_TYPE_SYNC {
+        key.detail = event.arg as u32;
+    }
+    // The end of a long path, and with it the extension, isn't read
+    if !event.has_more_chunks() {
+        copy_extension(&event.path, &mut key.ext);
+    }
+
+    // Per-CPU values, so no other CPU updates this one concurrently
+    if let Some(count) = AGG_COUNTS.get_ptr_mut(&key) {
+        unsafe { *count += 1 };
+    } else if AGG_COUNTS.insert(&key, &1, BPF_NOEXIST as u64).is_err() {
+        if let Some(overflow) = AGG_OVERFLOW.get_ptr_mut(0) {
+            unsafe { *overflow += 1 };
+        }
+    }
+}
+
+/// Copy the extension of the last path component, without the dot
+///
+/// A leading dot (e.g. ".bashrc") doesn't start an extension.
+fn copy_extension(path: &[u8; MAX_PATH_LEN], ext: &mut [u8; MAX_EXT_LEN]) {
+    let mut component = 0;
+    let mut start = None;
+    for (i, &byte) in path.iter().enumerate() {
+        match byte {
+            0 => break,
+            b'/' => {
+                component = i + 1;
+                start = None;
+            }
+            b'.' if i > component => start = Some(i + 1),
+            _ => {}
+        }
+    }
+    let Some(start) = start else {
+        return;
+    };
+    for i in 0..MAX_EXT_LEN - 1 {
+        match path.get(start + i) {
+            Some(&byte) if byte != 0 => ext[i] = byte,
+            _ => break,
+        }
+    }
+}
+
+/// Read argument `n` of the system call a probe is attached to
+///
+/// Syscall probes attach to the architecture's wrapper symbol (e.g.
+/// `__arm64_sys_openat`), whose only argument is the caller's saved user
+/// registers, so the syscall arguments are read from there.
+///
+/// # Arguments
+/// * `ctx` - Context of a probe on a syscall wrapper
+/// * `n` - Zero-based syscall argument index
+///
+/// # Returns
+/// * `Option<T>` - The argument, or None if it can't be read
+pub(crate) fn syscall_arg<T: Copy>(ctx: &ProbeContext, n: usize) -> Option<T> {
+    let regs: *const pt_regs = ctx.arg(0)?;
+    let reg = unsafe { syscall_arg_reg(regs, n)? };
+    // Registers are 64 bits and every supported arch is little-endian, so
+    // a narrower argument is at the start of its register
+    unsafe { bpf_probe_read_kernel(reg as *const T) }.ok()
+}
+
+/// Address of the register holding syscall argument `n` on x86_64
+///
+/// The fourth argument is in r10, not rcx as for ordinary calls.
+#[cfg(bpf_target_arch = "x86_64")]
+unsafe fn syscall_arg_reg(
+    regs: *const pt_regs,
+    n: usize,
+) -> Option<*const u64> {
+    use core::ptr::addr_of;
+    let reg = match n {
+        0 => addr_of!((*regs).rdi),
+        1 => addr_of!((*regs).rsi),
+        2 => addr_of!((*regs).rdx),
+        3 => addr_of!((*regs).r10),
+        4 => addr_of!((*regs).r8),
+        5 => addr_of!((*regs).r9),
+        _ => return None,
+    };
+    Some(reg as *const u64)
+}
+
+/// Address of the register holding syscall argument `n` on aarch64
+#[cfg(bpf_target_arch = "aarch64")]
+unsafe fn syscall_arg_reg(
+    regs: *const pt_regs,
+    n: usize,
+) -> Option<*const u64> {
+    if n > 5 {
+        return None;
+    }
+    Some(core::ptr::addr_of!((*regs).regs[n]) as *const u64)
+}
+
+/// Address of the register holding syscall argument `n` on riscv64
+#[cfg(bpf_target_arch = "riscv64")]
+unsafe fn syscall_arg_reg(
+    regs: *const pt_regs,
+    n: usize,
+) -> Option<*const u64> {
+    use core::ptr::addr_of;
+    let reg = match n {
+        0 => addr_of!((*regs).a0),
+        1 => addr_of!((*regs).a1),
+        2 => addr_of!((*regs).a2),
+        3 => addr_of!((*regs).a3),
+        4 => addr_of!((*regs).a4),
+        5 => addr_of!((*regs).a5),
+        _ => return None,
+    };
+    Some(reg as *const u64)
+}
+
+/// Jump to the program in a `TAIL_CALL_*` slot
+///
+/// Only returns if the slot is empty or the tail call limit was reached,
+/// so callers follow it with their fallback.
+pub(crate) fn tail_call<C: EbpfContext>(ctx: &C, slot: u32) {
+    let _ = unsafe { TAIL_CALLS.tail_call(ctx, slot) };
+}
+
+/// Build a descriptor event for the calling task
+pub(crate) fn current_event(
+    event_type: u32,
+    fd: i32,
+) -> Option<&'static mut FileEvent> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    descriptor_event(event_type, pid, tgid, fd)
+}
+
+/// Build an event that carries a descriptor but no path
+///
+/// The event lives in this CPU's SCRATCH slot, not on the stack. The next
+/// event built on this CPU overwrites it, so anything that must outlive
+/// the probe has to be copied into a map.
+///
+/// # Returns
+/// * `Option<&'static mut FileEvent>` - Zeroed event with the given
+///   fields set, or None if the scratch slot can't be looked up
+pub(crate) fn descriptor_event(
+    event_type: u32,
+    pid: u32,
+    tgid: u32,
+    fd: i32,
+) -> Option<&'static mut FileEvent> {
+    let event = SCRATCH.get_ptr_mut(0)?;
+    // Zero in place; a struct literal would be built on the stack first
+    let event = unsafe {
+        core::ptr::write_bytes(event, 0, 1);
+        &mut *event
+    };
+    let uid_gid = bpf_get_current_uid_gid();
+    event.version = EVENT_ABI_VERSION;
+    event.pid = pid;
+    event.tgid = tgid;
+    event.uid = uid_gid as u32;
+    event.gid = (uid_gid >> 32) as u32;
+    event.process_start_ns =
+        unsafe { PROCESS_STARTS.get(&pid) }.copied().unwrap_or(0);
+    event.event_type = event_type;
+    event.fd = fd;
+    event.old_fd = -1;
+    event.user_stack_id = -1;
+    event.kernel_stack_id = -1;
+    Some(event)
+}
+
+/// Read a userspace path into an event, flagging it if it was cut short
+pub(crate) fn read_user_path(event: &mut FileEvent, ptr: u64) {
+    let ret = unsafe {
+        bpf_probe_read_user_str(
+            event.path.as_mut_ptr(),
+            MAX_PATH_LEN as u32,
+            ptr as *const core::ffi::c_void,
+        )
+    };
+    if ret < 0 {
+        event.path[0] = 0;
+    } else if ret as usize == MAX_PATH_LEN {
+        event.flags |= EVENT_FLAG_PATH_TRUNCATED;
+    }
+}
+
+/// Extract filename from a full path
+pub(crate) fn extract_filename(
+    path: &[u8; MAX_PATH_LEN],
+    filename: &mut [u8; MAX_FILENAME_LEN],
+) {
+    let mut last_slash = 0;
+
+    // Find the last slash in the path
+    for (i, &byte) in path.iter().enumerate() {
+        if byte == 0 {
+            break;
+        }
+        if byte == b'/' {
+            last_slash = i + 1;
+        }
+    }
+
+    // Copy from last slash to end (or from beginning if no slash)
+    let mut copied = 0;
+    for i in last_slash..MAX_PATH_LEN {
+        if copied >= MAX_FILENAME_LEN - 1 || path[i] == 0 {
+            break;
+        }
+        filename[copied] = path[i];
+        copied += 1;
+    }
+    // Ensure null termination
+    if copied < MAX_FILENAME_LEN {
+        filename[copied] = 0;
+    }
+}
+
+/// Helper function to read user string (declaration)
+extern "C" {
+    pub(crate) fn bpf_probe_read_user_str(
+        dst: *mut u8,
+        size: u32,
+        unsafe_ptr: *const core::ffi::c_void,
+    ) -> i32;
+}
@@ -0,0 +1,103 @@
+//! I/O module
+//!
+//! Probes reporting reads and writes on descriptors with their offset and
+//! size, so userspace can tell how each open file is accessed. They fire
+//! for every descriptor, pipes and sockets included, and are only
+//! attached when access patterns are requested.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::bpf_get_current_pid_tgid,
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{EVENT_TYPE_READ, EVENT_TYPE_WRITE, IO_OFFSET_CURRENT};
+
+use crate::helpers::{current_event, emit, syscall_arg, task_allowed};
+use crate::maps::IO_CALLS;
+
+/// Kernel probe for read system call
+#[kprobe]
+pub fn read(ctx: ProbeContext) -> u32 {
+    match try_io_entry(ctx, EVENT_TYPE_READ, false) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for write system call
+#[kprobe]
+pub fn write(ctx: ProbeContext) -> u32 {
+    match try_io_entry(ctx, EVENT_TYPE_WRITE, false) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for pread64 system call
+#[kprobe]
+pub fn pread64(ctx: ProbeContext) -> u32 {
+    match try_io_entry(ctx, EVENT_TYPE_READ, true) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for pwrite64 system call
+#[kprobe]
+pub fn pwrite64(ctx: ProbeContext) -> u32 {
+    match try_io_entry(ctx, EVENT_TYPE_WRITE, true) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Store the event and offset until the syscall returns its size
+fn try_io_entry(
+    ctx: ProbeContext,
+    event_type: u32,
+    positional: bool,
+) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let event = current_event(event_type, fd).ok_or(1u32)?;
+    event.arg = if positional {
+        syscall_arg(&ctx, 3).ok_or(1u32)?
+    } else {
+        IO_OFFSET_CURRENT
+    };
+    let pid_tgid = bpf_get_current_pid_tgid();
+    IO_CALLS
+        .insert(&pid_tgid, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by read, write, pread64 and pwrite64
+///
+/// Reports calls that transferred data with the number of bytes moved.
+#[kretprobe]
+pub fn io_ret(ctx: RetProbeContext) -> u32 {
+    match try_io_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_io_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = match IO_CALLS.get_ptr_mut(&pid_tgid) {
+        Some(event) => unsafe { &mut *event },
+        None => return Ok(0),
+    };
+
+    // Complete the stored event in place rather than copying it out
+    if let Some(bytes) = ctx.ret::<i64>().filter(|&ret| ret > 0) {
+        event.arg2 = bytes.min(u32::MAX as i64) as u32;
+        emit(&ctx, event);
+    }
+    IO_CALLS.remove(&pid_tgid).ok();
+    Ok(0)
+}
@@ -0,0 +1,209 @@
+//! Link module
+//!
+//! Probes reporting hardlink and symlink creation and renames. The return
+//! probe sends the source (or old) path and tail-calls a second program
+//! for the link (or new) path, so neither has to read two user strings.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::bpf_get_current_pid_tgid,
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{
+    EVENT_TYPE_LINK, EVENT_TYPE_LINK_SOURCE, EVENT_TYPE_RENAME,
+    EVENT_TYPE_SYMLINK, TAIL_CALL_LINK_TARGET,
+};
+
+use crate::helpers::{
+    descriptor_event, emit, read_user_path, syscall_arg, tail_call,
+    task_allowed,
+};
+use crate::maps::{LinkArgs, LINK_ARGS};
+
+/// Directory descriptor standing for the working directory
+const AT_FDCWD: i32 = -100;
+
+/// Kernel probe for linkat system call
+#[kprobe]
+pub fn linkat(ctx: ProbeContext) -> u32 {
+    match try_linkat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_linkat(ctx: ProbeContext) -> Result<u32, u32> {
+    let source_dirfd: i64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let source: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let target_dirfd: i64 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    let target: u64 = syscall_arg(&ctx, 3).ok_or(1u32)?;
+    remember_link_args(
+        EVENT_TYPE_LINK,
+        (source_dirfd as i32, source),
+        (target_dirfd as i32, target),
+    )
+}
+
+/// Kernel probe for symlinkat system call
+#[kprobe]
+pub fn symlinkat(ctx: ProbeContext) -> u32 {
+    match try_symlinkat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_symlinkat(ctx: ProbeContext) -> Result<u32, u32> {
+    let source: u64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let target_dirfd: i64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let target: u64 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    remember_link_args(
+        EVENT_TYPE_SYMLINK,
+        (-1, source),
+        (target_dirfd as i32, target),
+    )
+}
+
+/// Kernel probe for renameat2 system call
+#[kprobe]
+pub fn renameat2(ctx: ProbeContext) -> u32 {
+    match try_renameat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for renameat system call
+#[kprobe]
+pub fn renameat(ctx: ProbeContext) -> u32 {
+    match try_renameat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_renameat(ctx: ProbeContext) -> Result<u32, u32> {
+    let old_dirfd: i64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let old: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let new_dirfd: i64 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    let new: u64 = syscall_arg(&ctx, 3).ok_or(1u32)?;
+    remember_link_args(
+        EVENT_TYPE_RENAME,
+        (old_dirfd as i32, old),
+        (new_dirfd as i32, new),
+    )
+}
+
+/// Kernel probe for rename system call
+#[kprobe]
+pub fn rename(ctx: ProbeContext) -> u32 {
+    match try_rename(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_rename(ctx: ProbeContext) -> Result<u32, u32> {
+    let old: u64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let new: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    remember_link_args(EVENT_TYPE_RENAME, (AT_FDCWD, old), (AT_FDCWD, new))
+}
+
+/// Save link or rename call arguments until the call returns
+///
+/// The source and target are each a directory descriptor and a pointer to
+/// the path.
+fn remember_link_args(
+    event_type: u32,
+    (source_dirfd, source): (i32, u64),
+    (target_dirfd, target): (i32, u64),
+) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let args = LinkArgs {
+        event_type,
+        source_dirfd,
+        target_dirfd,
+        _pad: 0,
+        source,
+        target,
+    };
+    LINK_ARGS
+        .insert(&pid_tgid, &args, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by linkat, symlinkat and the rename calls
+///
+/// On success, sends the source path as an EVENT_TYPE_LINK_SOURCE event,
+/// then tail-calls `link_target` to send the link or new path.
+#[kretprobe]
+pub fn link_ret(ctx: RetProbeContext) -> u32 {
+    match try_link_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_link_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let args = match unsafe { LINK_ARGS.get(&pid_tgid) } {
+        Some(args) => *args,
+        None => return Ok(0),
+    };
+
+    let ret: i64 = ctx.ret().ok_or(1u32)?;
+    if ret != 0 {
+        LINK_ARGS.remove(&pid_tgid).ok();
+        return Ok(0);
+    }
+
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    let event = match descriptor_event(EVENT_TYPE_LINK_SOURCE, pid, tgid, -1) {
+        Some(event) => event,
+        None => {
+            LINK_ARGS.remove(&pid_tgid).ok();
+            return Err(1);
+        }
+    };
+    event.old_fd = args.source_dirfd;
+    read_user_path(event, args.source);
+    emit(&ctx, event);
+
+    // The arguments stay in LINK_ARGS for the target program
+    tail_call(&ctx, TAIL_CALL_LINK_TARGET);
+    // Only reached if the target program isn't loaded
+    LINK_ARGS.remove(&pid_tgid).ok();
+    Ok(0)
+}
+
+/// Tail-called by link_ret to send the path of the new link or name
+#[kretprobe]
+pub fn link_target(ctx: RetProbeContext) -> u32 {
+    match try_link_target(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_link_target(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let args = match unsafe { LINK_ARGS.get(&pid_tgid) } {
+        Some(args) => *args,
+        None => return Ok(0),
+    };
+    LINK_ARGS.remove(&pid_tgid).ok();
+
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    let event = descriptor_event(args.event_type, pid, tgid, -1).ok_or(1u32)?;
+    event.old_fd = args.target_dirfd;
+    read_user_path(event, args.target);
+    emit(&ctx, event);
+    Ok(0)
+}
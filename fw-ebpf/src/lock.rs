@@ -0,0 +1,173 @@
+//! Lock module
+//!
+//! Probes reporting file locks taken and released with flock and with
+//! fcntl's record locking commands, together with the time each call
+//! spent in the kernel, so userspace can tell which calls waited for
+//! another holder.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::{
+        bpf_get_current_pid_tgid, bpf_ktime_get_ns, bpf_probe_read_user,
+    },
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{
+    EVENT_TYPE_LOCK, EVENT_TYPE_UNLOCK, LOCK_FLAG_NONBLOCKING,
+    LOCK_FLAG_RECORD, LOCK_FLAG_REFUSED, LOCK_KIND_EXCLUSIVE, LOCK_KIND_SHARED,
+};
+
+use crate::helpers::{current_event, emit, syscall_arg, task_allowed};
+use crate::maps::LOCK_CALLS;
+
+/// flock operation: shared lock
+const LOCK_SH: u32 = 1;
+
+/// flock operation: exclusive lock
+const LOCK_EX: u32 = 2;
+
+/// flock flag: fail instead of waiting
+const LOCK_NB: u32 = 4;
+
+/// flock operation: release the lock
+const LOCK_UN: u32 = 8;
+
+/// fcntl command: set a record lock, failing if it is held
+const F_SETLK: u32 = 6;
+
+/// fcntl command: set a record lock, waiting if it is held
+const F_SETLKW: u32 = 7;
+
+/// fcntl command: set an open file description lock, failing if held
+const F_OFD_SETLK: u32 = 37;
+
+/// fcntl command: set an open file description lock, waiting if held
+const F_OFD_SETLKW: u32 = 38;
+
+/// `struct flock` lock type: shared
+const F_RDLCK: i16 = 0;
+
+/// `struct flock` lock type: exclusive
+const F_WRLCK: i16 = 1;
+
+/// Error of a non-blocking request refused because of another holder
+const EAGAIN: i64 = 11;
+
+/// Error fcntl may return instead of EAGAIN for a refused request
+const EACCES: i64 = 13;
+
+/// Kernel probe for flock system call
+#[kprobe]
+pub fn flock(ctx: ProbeContext) -> u32 {
+    match try_flock(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_flock(ctx: ProbeContext) -> Result<u32, u32> {
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let operation: u32 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let mut flags = 0;
+    if operation & LOCK_NB != 0 {
+        flags |= LOCK_FLAG_NONBLOCKING;
+    }
+    let (event_type, kind) = match operation & !LOCK_NB {
+        LOCK_SH => (EVENT_TYPE_LOCK, LOCK_KIND_SHARED),
+        LOCK_EX => (EVENT_TYPE_LOCK, LOCK_KIND_EXCLUSIVE),
+        LOCK_UN => (EVENT_TYPE_UNLOCK, 0),
+        _ => return Ok(0),
+    };
+    remember_lock_call(event_type, fd, kind, flags)
+}
+
+/// Kernel probe for fcntl system call, tracking record locks
+///
+/// Shares the fcntl syscall with the descriptor probe for F_DUPFD, which
+/// is a different program so locks can be switched off on their own.
+#[kprobe]
+pub fn fcntl_lock(ctx: ProbeContext) -> u32 {
+    match try_fcntl_lock(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fcntl_lock(ctx: ProbeContext) -> Result<u32, u32> {
+    let cmd: u32 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let mut flags = LOCK_FLAG_RECORD;
+    match cmd {
+        F_SETLK | F_OFD_SETLK => flags |= LOCK_FLAG_NONBLOCKING,
+        F_SETLKW | F_OFD_SETLKW => {}
+        _ => return Ok(0),
+    }
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    // l_type is the first field of struct flock
+    let lock: *const i16 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    let l_type = unsafe { bpf_probe_read_user(lock) }.map_err(|_| 1u32)?;
+    let (event_type, kind) = match l_type {
+        F_RDLCK => (EVENT_TYPE_LOCK, LOCK_KIND_SHARED),
+        F_WRLCK => (EVENT_TYPE_LOCK, LOCK_KIND_EXCLUSIVE),
+        _ => (EVENT_TYPE_UNLOCK, 0),
+    };
+    remember_lock_call(event_type, fd, kind, flags)
+}
+
+/// Store the lock event and entry time until the syscall returns
+fn remember_lock_call(
+    event_type: u32,
+    fd: i32,
+    kind: u64,
+    flags: u32,
+) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let event = current_event(event_type, fd).ok_or(1u32)?;
+    event.arg = kind;
+    event.arg2 = flags;
+    event.open_latency_ns = bpf_ktime_get_ns();
+    let pid_tgid = bpf_get_current_pid_tgid();
+    LOCK_CALLS
+        .insert(&pid_tgid, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by flock and fcntl
+///
+/// Reports successful calls with the time they spent in the kernel, and
+/// non-blocking lock requests refused because another holder had the
+/// lock, which are what contention looks like to programs that retry.
+#[kretprobe]
+pub fn lock_ret(ctx: RetProbeContext) -> u32 {
+    match try_lock_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_lock_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = match LOCK_CALLS.get_ptr_mut(&pid_tgid) {
+        Some(event) => unsafe { &mut *event },
+        None => return Ok(0),
+    };
+
+    // Complete the stored event in place rather than copying it out
+    let ret: i64 = ctx.ret().unwrap_or(-1);
+    let refused = event.event_type == EVENT_TYPE_LOCK
+        && event.arg2 & LOCK_FLAG_NONBLOCKING != 0
+        && (ret == -EAGAIN || ret == -EACCES);
+    if ret >= 0 || refused {
+        if refused {
+            event.arg2 |= LOCK_FLAG_REFUSED;
+        }
+        event.open_latency_ns =
+            bpf_ktime_get_ns().saturating_sub(event.open_latency_ns);
+        emit(&ctx, event);
+    }
+    LOCK_CALLS.remove(&pid_tgid).ok();
+    Ok(0)
+}
@@ -0,0 +1,217 @@
+//! Maps module
+//!
+//! BPF maps shared by the probe programs. State and filter maps are pinned
+//! by name so a restarted fw can reuse them (see `fw collect
+//! --reuse-pinned`).
+
+use aya_ebpf::{
+    macros::map,
+    maps::{
+        Array, HashMap, LruHashMap, PerCpuArray, PerCpuHashMap, PerfEventArray,
+        ProgramArray, StackTrace,
+    },
+};
+use fw_common::{
+    AggKey, FileEvent, StackCriterion, MAX_AGG_ENTRIES,
+    MAX_COMM_FILTER_ENTRIES, MAX_COMM_LEN, MAX_LEADER_COMMS, MAX_PATH_LEN,
+    MAX_PROCESS_STARTS, MAX_SAMPLE_EXEMPTIONS, MAX_STACK_CRITERIA,
+    MAX_STACK_ENTRIES, MAX_TAIL_CALLS, MAX_TRIPWIRES, MAX_UID_FILTER_ENTRIES,
+};
+
+/// PerfEvent array for sending events to userspace
+#[map]
+pub(crate) static EVENTS: PerfEventArray<FileEvent> = PerfEventArray::new(0);
+
+/// Per-CPU slot events are built in before being sent or stored
+///
+/// A `FileEvent` takes most of the 512-byte BPF stack, so it is never
+/// built on the stack; see `helpers::descriptor_event`.
+#[map]
+pub(crate) static SCRATCH: PerCpuArray<FileEvent> =
+    PerCpuArray::with_max_entries(1, 0);
+
+/// Programs reached by tail call, indexed by `TAIL_CALL_*` slot
+///
+/// Work that would make a probe too complex for the verifier (e.g. the
+/// chunk loop for long paths) lives in its own program here, and probes
+/// pass it their state through the per-task maps below.
+#[map]
+pub(crate) static TAIL_CALLS: ProgramArray =
+    ProgramArray::with_max_entries(MAX_TAIL_CALLS, 0);
+
+/// Map to track opened files by file descriptor
+///
+/// While the open is in flight, `open_latency_ns` holds the entry
+/// timestamp; the return probe replaces it with the elapsed time.
+#[map]
+pub(crate) static OPEN_FILES: HashMap<u64, FileEvent> =
+    HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the userspace path pointer of an in-flight openat,
+/// used to read the remaining chunks of paths longer than one chunk
+#[map]
+pub(crate) static OPEN_PATH_PTRS: HashMap<u64, u64> = HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the source descriptor of an in-flight dup call
+#[map]
+pub(crate) static DUP_SOURCES: HashMap<u64, i32> = HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the flags of an in-flight clone, read by the fork
+/// tracepoint to tell new threads from new processes
+#[map]
+pub(crate) static CLONE_FLAGS: HashMap<u64, u64> = HashMap::pinned(1024, 0);
+
+/// Userspace path arguments of an in-flight link, symlink or rename call
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct LinkArgs {
+    /// EVENT_TYPE_LINK, EVENT_TYPE_SYMLINK or EVENT_TYPE_RENAME
+    pub(crate) event_type: u32,
+    /// Directory a relative source starts from, or -1 for symlink
+    /// contents, which are stored as given
+    pub(crate) source_dirfd: i32,
+    /// Directory a relative target starts from
+    pub(crate) target_dirfd: i32,
+    pub(crate) _pad: u32,
+    /// Existing file (linkat), symlink contents (symlinkat) or old name
+    /// (renames)
+    pub(crate) source: u64,
+    /// Path of the link being created, or the new name
+    pub(crate) target: u64,
+}
+
+/// Map from pid_tgid to the arguments of an in-flight link or rename call
+#[map]
+pub(crate) static LINK_ARGS: HashMap<u64, LinkArgs> = HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the sync event of an in-flight fsync, fdatasync or
+/// sync_file_range call; `open_latency_ns` holds the entry timestamp
+#[map]
+pub(crate) static SYNC_CALLS: HashMap<u64, FileEvent> =
+    HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the lock event of an in-flight flock or fcntl
+/// lock call; `open_latency_ns` holds the entry timestamp
+#[map]
+pub(crate) static LOCK_CALLS: HashMap<u64, FileEvent> =
+    HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the event of an in-flight fchmod, fchown,
+/// fchownat or ftruncate call
+#[map]
+pub(crate) static METADATA_CALLS: HashMap<u64, FileEvent> =
+    HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the chdir event of an in-flight chdir or fchdir
+/// call
+#[map]
+pub(crate) static CHDIR_CALLS: HashMap<u64, FileEvent> =
+    HashMap::pinned(1024, 0);
+
+/// Map from pid_tgid to the read or write event of an in-flight read,
+/// write, pread64 or pwrite64 call
+#[map]
+pub(crate) static IO_CALLS: HashMap<u64, FileEvent> = HashMap::pinned(1024, 0);
+
+/// Users whose activity is included or excluded (`UID_FILTER_*`), set
+/// from `fw collect --user` and `--exclude-user`
+#[map]
+pub(crate) static UID_FILTER: HashMap<u32, u8> =
+    HashMap::pinned(MAX_UID_FILTER_ENTRIES, 0);
+
+/// Index 0 is 1 when UID_FILTER holds include entries, so users without
+/// an entry are left out
+#[map]
+pub(crate) static UID_FILTER_ACTIVE: Array<u32> = Array::pinned(1, 0);
+
+/// Tripwire decoy paths (null-padded) from `fw collect --tripwires`,
+/// whose opens bypass the filters, sampling and aggregation
+#[map]
+pub(crate) static TRIPWIRES: HashMap<[u8; MAX_PATH_LEN], u8> =
+    HashMap::with_max_entries(MAX_TRIPWIRES, 0);
+
+/// Task names (null-padded comm) from `fw collect --process-list-file`;
+/// rewritten while running whenever the list is reloaded
+#[map]
+pub(crate) static COMM_FILTER: HashMap<[u8; MAX_COMM_LEN], u8> =
+    HashMap::pinned(MAX_COMM_FILTER_ENTRIES, 0);
+
+/// Index 0 is the `COMM_FILTER_*` mode COMM_FILTER is checked in
+#[map]
+pub(crate) static COMM_FILTER_MODE: Array<u32> = Array::pinned(1, 0);
+
+/// Map from process ID to the name of its thread group leader, which
+/// the process filter matches so renamed threads stay with their
+/// process; kept up to date by the leader's own calls and seeded by
+/// userspace for processes started earlier
+#[map]
+pub(crate) static LEADER_COMMS: LruHashMap<u32, [u8; MAX_COMM_LEN]> =
+    LruHashMap::with_max_entries(MAX_LEADER_COMMS, 0);
+
+/// Map from process ID to the boot time in nanoseconds at which it was
+/// forked, stamped on its events so userspace can tell a reused process
+/// ID from the process that had it before
+#[map]
+pub(crate) static PROCESS_STARTS: LruHashMap<u32, u64> =
+    LruHashMap::with_max_entries(MAX_PROCESS_STARTS, 0);
+
+/// Index 0 is 1 when opens under /proc, /sys and /dev are not sent
+/// (the default; `fw collect --include-pseudo-fs` leaves it 0)
+#[map]
+pub(crate) static PSEUDO_FS_EXCLUDED: Array<u32> =
+    Array::with_max_entries(1, 0);
+
+/// Event counts per (program, extension, action) while aggregating in the
+/// kernel; userspace sums the per-CPU values and deletes what it read
+#[map]
+pub(crate) static AGG_COUNTS: PerCpuHashMap<AggKey, u64> =
+    PerCpuHashMap::with_max_entries(MAX_AGG_ENTRIES, 0);
+
+/// Index 0 counts events not counted because AGG_COUNTS was full
+#[map]
+pub(crate) static AGG_OVERFLOW: PerCpuArray<u64> =
+    PerCpuArray::with_max_entries(1, 0);
+
+/// Index 0 is 1 when probes count events in AGG_COUNTS instead of sending
+/// them (`fw collect --kernel-agg`)
+#[map]
+pub(crate) static AGG_ACTIVE: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Stack traces of sent events, keyed by the ids stored in each event
+#[map]
+pub(crate) static STACKS: StackTrace =
+    StackTrace::with_max_entries(MAX_STACK_ENTRIES, 0);
+
+/// Index 0 holds the `STACK_*` flags of the stacks recorded with each
+/// event (`fw collect --stacks`); 0 records none
+#[map]
+pub(crate) static STACK_MODE: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Events whose stacks are recorded, from severity rules with
+/// `capture_stack=true`; only the first STACK_CRITERIA_COUNT[0] are used
+#[map]
+pub(crate) static STACK_CRITERIA: Array<StackCriterion> =
+    Array::with_max_entries(MAX_STACK_CRITERIA, 0);
+
+/// Index 0 is the number of entries in STACK_CRITERIA; 0 records the
+/// stacks of every event
+#[map]
+pub(crate) static STACK_CRITERIA_COUNT: Array<u32> =
+    Array::with_max_entries(1, 0);
+
+/// Index 0 is N when only 1 in N events is sent, set by userspace's
+/// overload controller; 0 or 1 sends every event
+#[map]
+pub(crate) static SAMPLE_RATE: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Events sent whatever SAMPLE_RATE says, from the severity rules rating
+/// events warning or critical; only the first SAMPLE_EXEMPT_COUNT[0] are
+/// used
+#[map]
+pub(crate) static SAMPLE_EXEMPT: Array<StackCriterion> =
+    Array::with_max_entries(MAX_SAMPLE_EXEMPTIONS, 0);
+
+/// Index 0 is the number of entries in SAMPLE_EXEMPT
+#[map]
+pub(crate) static SAMPLE_EXEMPT_COUNT: Array<u32> =
+    Array::with_max_entries(1, 0);
@@ -0,0 +1,134 @@
+//! Metadata module
+//!
+//! Probes reporting mode, owner and size changes made through an open
+//! descriptor. The event is built on entry and sent when the call
+//! returns, and only if it succeeded. Userspace resolves the descriptor
+//! to a path using its descriptor table.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::bpf_get_current_pid_tgid,
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{EVENT_TYPE_CHMOD, EVENT_TYPE_CHOWN, EVENT_TYPE_TRUNCATE};
+
+use crate::helpers::{current_event, emit, syscall_arg, task_allowed};
+use crate::maps::METADATA_CALLS;
+
+/// fchownat flag meaning "operate on dirfd itself"
+const AT_EMPTY_PATH: u32 = 0x1000;
+
+/// Kernel probe for fchmod system call
+#[kprobe]
+pub fn fchmod(ctx: ProbeContext) -> u32 {
+    match try_fchmod(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fchmod(ctx: ProbeContext) -> Result<u32, u32> {
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let mode: u32 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    remember_metadata_call(EVENT_TYPE_CHMOD, fd, mode as u64, 0)
+}
+
+/// Kernel probe for fchown system call
+#[kprobe]
+pub fn fchown(ctx: ProbeContext) -> u32 {
+    match try_fchown(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fchown(ctx: ProbeContext) -> Result<u32, u32> {
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let uid: u32 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    let gid: u32 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    remember_metadata_call(EVENT_TYPE_CHOWN, fd, uid as u64, gid)
+}
+
+/// Kernel probe for fchownat system call
+///
+/// Only calls with AT_EMPTY_PATH act on an already-open descriptor; path
+/// based calls are not descriptor metadata changes.
+#[kprobe]
+pub fn fchownat(ctx: ProbeContext) -> u32 {
+    match try_fchownat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_fchownat(ctx: ProbeContext) -> Result<u32, u32> {
+    let flags: u32 = syscall_arg(&ctx, 4).ok_or(1u32)?;
+    if flags & AT_EMPTY_PATH == 0 {
+        return Ok(0);
+    }
+    let dirfd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let uid: u32 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+    let gid: u32 = syscall_arg(&ctx, 3).ok_or(1u32)?;
+    remember_metadata_call(EVENT_TYPE_CHOWN, dirfd, uid as u64, gid)
+}
+
+/// Kernel probe for ftruncate system call
+#[kprobe]
+pub fn ftruncate(ctx: ProbeContext) -> u32 {
+    match try_ftruncate(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_ftruncate(ctx: ProbeContext) -> Result<u32, u32> {
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let length: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+    remember_metadata_call(EVENT_TYPE_TRUNCATE, fd, length, 0)
+}
+
+/// Store a metadata-change event until the syscall returns
+fn remember_metadata_call(
+    event_type: u32,
+    fd: i32,
+    arg: u64,
+    arg2: u32,
+) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let event = current_event(event_type, fd).ok_or(1u32)?;
+    event.arg = arg;
+    event.arg2 = arg2;
+    let pid_tgid = bpf_get_current_pid_tgid();
+    METADATA_CALLS
+        .insert(&pid_tgid, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by fchmod, fchown, fchownat and ftruncate
+///
+/// Reports the change only if the call succeeded, so refused changes
+/// and bad descriptors never look like they happened.
+#[kretprobe]
+pub fn metadata_ret(ctx: RetProbeContext) -> u32 {
+    match try_metadata_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_metadata_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = match METADATA_CALLS.get_ptr_mut(&pid_tgid) {
+        Some(event) => unsafe { &mut *event },
+        None => return Ok(0),
+    };
+    if ctx.ret::<i64>() == Some(0) {
+        emit(&ctx, event);
+    }
+    METADATA_CALLS.remove(&pid_tgid).ok();
+    Ok(0)
+}
@@ -0,0 +1,297 @@
+//! Open module
+//!
+//! Probes reporting file opens with their path, latency and inode. Paths
+//! longer than one chunk are sent by a separate tail-called program, so
+//! the chunk loop doesn't count against the return probe's complexity.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::{
+        bpf_get_current_pid_tgid, bpf_ktime_get_ns, bpf_probe_read_kernel,
+    },
+    macros::kprobe,
+    programs::ProbeContext,
+};
+use aya_log_ebpf::info;
+use fw_common::{
+    FileEvent, EVENT_FLAG_MORE_CHUNKS, EVENT_FLAG_PATH_TRUNCATED,
+    EVENT_FLAG_TRIPWIRE, EVENT_TYPE_OPEN, MAX_PATH_CHUNKS, MAX_PATH_LEN,
+    PATH_CHUNK_LEN, TAIL_CALL_OPEN_PATH_CHUNKS,
+};
+
+use crate::helpers::{
+    aggregating, bpf_probe_read_user_str, descriptor_event, emit,
+    extract_filename, is_tripwire, pseudo_fs_excluded, syscall_arg, tail_call,
+    task_allowed,
+};
+use crate::maps::{OPEN_FILES, OPEN_PATH_PTRS};
+
+/// Offset of `dentry` in `struct path`
+const PATH_DENTRY_OFFSET: usize = 8;
+
+/// Offset of `d_inode` in `struct dentry` (x86_64/arm64)
+const DENTRY_INODE_OFFSET: usize = 48;
+
+/// Offset of `i_mode` in `struct inode`
+const INODE_MODE_OFFSET: usize = 0;
+
+/// Offset of `i_sb` in `struct inode` (x86_64/arm64)
+const INODE_SB_OFFSET: usize = 40;
+
+/// Offset of `i_ino` in `struct inode` (x86_64/arm64, CONFIG_SECURITY=y)
+const INODE_INO_OFFSET: usize = 64;
+
+/// Offset of `s_dev` in `struct super_block`
+const SUPER_BLOCK_DEV_OFFSET: usize = 16;
+
+/// Kernel probe for openat system call
+#[kprobe]
+pub fn openat(ctx: ProbeContext) -> u32 {
+    match try_openat(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_openat(ctx: ProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+
+    // Get the filename parameter (second argument to openat)
+    let filename_ptr: *const u8 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+
+    let flags: u64 = syscall_arg(&ctx, 2).ok_or(1u32)?;
+
+    // Relative paths start from this directory (AT_FDCWD is negative)
+    let dirfd: i64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+
+    // fd is filled in by the return probe, dev/ino by the vfs_open probe
+    let event = descriptor_event(EVENT_TYPE_OPEN, pid, tgid, -1).ok_or(1u32)?;
+    event.open_latency_ns = bpf_ktime_get_ns();
+    event.arg = flags & 0xffff_ffff;
+    event.old_fd = dirfd as i32;
+
+    // Safely read the filename from userspace
+    let ret = unsafe {
+        bpf_probe_read_user_str(
+            event.path.as_mut_ptr(),
+            MAX_PATH_LEN as u32,
+            filename_ptr as *const core::ffi::c_void,
+        )
+    };
+
+    if ret < 0 {
+        return Err(1);
+    }
+
+    // Decoys are reported whoever opens them, so the filters are checked
+    // once the path is known
+    let tripwire = is_tripwire(&event.path);
+    if tripwire {
+        event.flags |= EVENT_FLAG_TRIPWIRE;
+    } else if !task_allowed() {
+        return Ok(0);
+    }
+
+    // Relative paths can't be told apart here; userspace filters those
+    if !tripwire && pseudo_fs_excluded(&event.path) {
+        return Ok(0);
+    }
+
+    // A completely filled buffer means the path may continue past this
+    // chunk; remember the pointer so the return probe can read the rest
+    if ret as usize == MAX_PATH_LEN {
+        event.flags |= EVENT_FLAG_MORE_CHUNKS;
+        OPEN_PATH_PTRS
+            .insert(&pid_tgid, &(filename_ptr as u64), BPF_ANY as u64)
+            .map_err(|_| 1u32)?;
+    }
+
+    // Extract just the filename from the full path
+    extract_filename(&event.path, &mut event.filename);
+
+    // Store the event temporarily with a key based on current context
+    // We'll use this in the return probe to get the file descriptor
+    let key = pid_tgid;
+    OPEN_FILES
+        .insert(&key, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+
+    info!(&ctx, "File open: pid={} path={:?}", pid, event.path);
+    Ok(0)
+}
+
+/// Kernel probe for vfs_open, called while an openat is in flight
+///
+/// Records the device, inode and mode of the file being opened on the
+/// event staged by the openat probe, so renamed or hardlinked files keep
+/// one identity in userspace and device nodes, sockets and fifos can be
+/// told apart from regular files.
+#[kprobe]
+pub fn vfs_open(ctx: ProbeContext) -> u32 {
+    match try_vfs_open(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_vfs_open(ctx: ProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    // Only opens staged by the openat probe are of interest
+    let event = OPEN_FILES.get_ptr_mut(&pid_tgid).ok_or(0u32)?;
+    let path: *const u8 = ctx.arg(0).ok_or(1u32)?;
+
+    let (dev, ino, mode) = unsafe { read_path_identity(path) }.ok_or(1u32)?;
+    unsafe {
+        (*event).dev = dev;
+        (*event).ino = ino;
+        (*event).arg2 = mode as u32;
+    }
+    Ok(0)
+}
+
+/// Read the device, inode number and mode behind a kernel `struct path`
+///
+/// # Safety
+/// `path` must point to a kernel `struct path`.
+unsafe fn read_path_identity(path: *const u8) -> Option<(u64, u64, u16)> {
+    let read_ptr = |base: *const u8, offset: usize| {
+        bpf_probe_read_kernel(base.add(offset) as *const *const u8).ok()
+    };
+    let dentry = read_ptr(path, PATH_DENTRY_OFFSET)?;
+    let inode = read_ptr(dentry, DENTRY_INODE_OFFSET)?;
+    let sb = read_ptr(inode, INODE_SB_OFFSET)?;
+    let mode =
+        bpf_probe_read_kernel(inode.add(INODE_MODE_OFFSET) as *const u16)
+            .ok()?;
+    let ino = bpf_probe_read_kernel(inode.add(INODE_INO_OFFSET) as *const u64)
+        .ok()?;
+    let dev =
+        bpf_probe_read_kernel(sb.add(SUPER_BLOCK_DEV_OFFSET) as *const u32)
+            .ok()?;
+    Some((dev as u64, ino, mode))
+}
+
+/// Kernel return probe for openat system call
+#[kprobe(name = "openat_ret")]
+pub fn openat_ret(ctx: ProbeContext) -> u32 {
+    match try_openat_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_openat_ret(ctx: ProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let ret_value: i64 = ctx.ret().ok_or(1u32)?;
+
+    // Only process successful opens (positive file descriptor)
+    if ret_value < 0 {
+        OPEN_FILES.remove(&pid_tgid).ok();
+        OPEN_PATH_PTRS.remove(&pid_tgid).ok();
+        return Ok(0);
+    }
+
+    // Complete the stored event from the open call in place, so the chunk
+    // program can pick it up
+    let event = OPEN_FILES.get_ptr_mut(&pid_tgid).ok_or(1u32)?;
+    let event = unsafe { &mut *event };
+    event.fd = ret_value as i32;
+    event.open_latency_ns =
+        bpf_ktime_get_ns().saturating_sub(event.open_latency_ns);
+
+    // Counting in the kernel needs only the first chunk
+    if event.has_more_chunks() && !aggregating() {
+        tail_call(&ctx, TAIL_CALL_OPEN_PATH_CHUNKS);
+        // Only reached if the chunk program isn't loaded
+        event.flags = EVENT_FLAG_PATH_TRUNCATED;
+    }
+    emit(&ctx, event);
+    info!(
+        &ctx,
+        "File opened successfully: fd={} pid={}", ret_value, event.pid
+    );
+
+    // Clean up the temporary storage
+    OPEN_FILES.remove(&pid_tgid).ok();
+    OPEN_PATH_PTRS.remove(&pid_tgid).ok();
+    Ok(0)
+}
+
+/// Tail-called by openat_ret to send a path longer than one chunk
+#[kprobe]
+pub fn open_path_chunks(ctx: ProbeContext) -> u32 {
+    match try_open_path_chunks(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_open_path_chunks(ctx: ProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = OPEN_FILES.get_ptr_mut(&pid_tgid).ok_or(1u32)?;
+    let path_ptr = unsafe { OPEN_PATH_PTRS.get(&pid_tgid) }.copied();
+
+    match path_ptr {
+        Some(ptr) => output_path_chunks(&ctx, unsafe { &mut *event }, ptr),
+        None => emit(&ctx, unsafe { &mut *event }),
+    }
+
+    OPEN_FILES.remove(&pid_tgid).ok();
+    OPEN_PATH_PTRS.remove(&pid_tgid).ok();
+    Ok(0)
+}
+
+/// Send a long path to userspace as a sequence of chunk events
+///
+/// The first chunk is already in `event`. Each following chunk is read from
+/// the userspace buffer at the next `PATH_CHUNK_LEN` offset and sent with
+/// the same pid/tgid so `EbpfMonitor` can reassemble the full path. If the
+/// path has not ended after `MAX_PATH_CHUNKS` chunks, the last event is
+/// flagged as truncated.
+///
+/// # Arguments
+/// * `ctx` - Probe context used to output events
+/// * `event` - Event holding the first chunk; reused for later chunks
+/// * `path_ptr` - Userspace address of the path string
+fn output_path_chunks(
+    ctx: &ProbeContext,
+    event: &mut FileEvent,
+    path_ptr: u64,
+) {
+    emit(ctx, event);
+
+    for index in 1..MAX_PATH_CHUNKS {
+        let offset = (index * PATH_CHUNK_LEN) as u64;
+        event.path = [0u8; MAX_PATH_LEN];
+        event.chunk_index = index as u32;
+
+        let ret = unsafe {
+            bpf_probe_read_user_str(
+                event.path.as_mut_ptr(),
+                MAX_PATH_LEN as u32,
+                (path_ptr + offset) as *const core::ffi::c_void,
+            )
+        };
+
+        // A failed read leaves us with a partial path
+        if ret < 0 {
+            event.flags = EVENT_FLAG_PATH_TRUNCATED;
+            emit(ctx, event);
+            return;
+        }
+
+        let more = ret as usize == MAX_PATH_LEN;
+        event.flags = match (more, index == MAX_PATH_CHUNKS - 1) {
+            (false, _) => 0,
+            (true, false) => EVENT_FLAG_MORE_CHUNKS,
+            (true, true) => EVENT_FLAG_PATH_TRUNCATED,
+        };
+        emit(ctx, event);
+
+        if !more {
+            return;
+        }
+    }
+}
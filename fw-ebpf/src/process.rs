@@ -0,0 +1,116 @@
+//! Process module
+//!
+//! Scheduler tracepoints telling userspace when a descriptor table is
+//! inherited by a child or goes away with its process. Threads share
+//! their process's table, which userspace keeps per thread group, so new
+//! threads and the exits of threads other than the leader aren't
+//! reported.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::{
+        bpf_get_current_pid_tgid, bpf_ktime_get_boot_ns, bpf_probe_read_kernel,
+    },
+    macros::{kprobe, tracepoint},
+    programs::{ProbeContext, TracePointContext},
+};
+use fw_common::{EVENT_TYPE_EXIT, EVENT_TYPE_FORK};
+
+use crate::helpers::{descriptor_event, emit};
+use crate::maps::{CLONE_FLAGS, LEADER_COMMS, PROCESS_STARTS};
+
+/// Offset of `child_pid` in the sched_process_fork tracepoint record
+const FORK_CHILD_PID_OFFSET: usize = 44;
+
+/// Offset of `flags` in `struct kernel_clone_args`
+const CLONE_ARGS_FLAGS_OFFSET: usize = 0;
+
+/// Clone flag making the new task a thread of the caller's process
+const CLONE_THREAD: u64 = 0x0001_0000;
+
+/// Kernel function probe storing the flags of a clone until the fork
+/// tracepoint fires for it
+#[kprobe]
+pub fn kernel_clone(ctx: ProbeContext) -> u32 {
+    match try_kernel_clone(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_kernel_clone(ctx: ProbeContext) -> Result<u32, u32> {
+    let args: *const u8 = ctx.arg(0).ok_or(1u32)?;
+    let flags = unsafe {
+        bpf_probe_read_kernel(args.add(CLONE_ARGS_FLAGS_OFFSET) as *const u64)
+    }
+    .map_err(|_| 1u32)?;
+    CLONE_FLAGS
+        .insert(&bpf_get_current_pid_tgid(), &flags, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Tracepoint for process fork, so the child inherits the descriptor table
+#[tracepoint]
+pub fn sched_process_fork(ctx: TracePointContext) -> u32 {
+    match try_sched_process_fork(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_sched_process_fork(ctx: TracePointContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let flags = unsafe { CLONE_FLAGS.get(&pid_tgid) }.copied().unwrap_or(0);
+    CLONE_FLAGS.remove(&pid_tgid).ok();
+    // A new thread shares the table of the process it joins
+    if flags & CLONE_THREAD != 0 {
+        return Ok(0);
+    }
+    let child_pid: u32 =
+        unsafe { ctx.read_at(FORK_CHILD_PID_OFFSET) }.map_err(|_| 1u32)?;
+    // Recorded before the event is filtered or sampled out, so every
+    // process forked from now on is told apart from its pid's last owner
+    PROCESS_STARTS
+        .insert(&child_pid, &bpf_ktime_get_boot_ns(), BPF_ANY as u64)
+        .ok();
+
+    // The table is the calling thread's process's, whichever thread
+    // forked
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    let event = descriptor_event(EVENT_TYPE_FORK, pid, tgid, -1).ok_or(1u32)?;
+    event.child_pid = child_pid;
+
+    emit(&ctx, event);
+    Ok(0)
+}
+
+/// Tracepoint for process exit, so userspace can drop the descriptor table
+#[tracepoint]
+pub fn sched_process_exit(ctx: TracePointContext) -> u32 {
+    match try_sched_process_exit(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_sched_process_exit(ctx: TracePointContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let pid = (pid_tgid >> 32) as u32;
+    let tgid = pid_tgid as u32;
+    // Left behind by a clone that failed
+    CLONE_FLAGS.remove(&pid_tgid).ok();
+
+    // Only the thread group leader's exit ends the process; other
+    // threads share its descriptor table
+    if pid != tgid {
+        return Ok(0);
+    }
+    LEADER_COMMS.remove(&pid).ok();
+
+    let event = descriptor_event(EVENT_TYPE_EXIT, pid, tgid, -1);
+    PROCESS_STARTS.remove(&pid).ok();
+    emit(&ctx, event.ok_or(1u32)?);
+    Ok(0)
+}
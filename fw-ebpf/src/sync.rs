@@ -0,0 +1,89 @@
+//! Sync module
+//!
+//! Probes reporting fsync, fdatasync and sync_file_range together with
+//! the time each call spent in the kernel.
+
+use aya_ebpf::{
+    bindings::BPF_ANY,
+    helpers::{bpf_get_current_pid_tgid, bpf_ktime_get_ns},
+    macros::{kprobe, kretprobe},
+    programs::{ProbeContext, RetProbeContext},
+};
+use fw_common::{
+    EVENT_TYPE_SYNC, SYNC_KIND_FDATASYNC, SYNC_KIND_FSYNC,
+    SYNC_KIND_SYNC_FILE_RANGE,
+};
+
+use crate::helpers::{current_event, emit, syscall_arg, task_allowed};
+use crate::maps::SYNC_CALLS;
+
+/// Kernel probe for fsync system call
+#[kprobe]
+pub fn fsync(ctx: ProbeContext) -> u32 {
+    match try_sync_entry(ctx, SYNC_KIND_FSYNC) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for fdatasync system call
+#[kprobe]
+pub fn fdatasync(ctx: ProbeContext) -> u32 {
+    match try_sync_entry(ctx, SYNC_KIND_FDATASYNC) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for sync_file_range system call
+#[kprobe]
+pub fn sync_file_range(ctx: ProbeContext) -> u32 {
+    match try_sync_entry(ctx, SYNC_KIND_SYNC_FILE_RANGE) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Store the sync event and entry time until the syscall returns
+fn try_sync_entry(ctx: ProbeContext, kind: u64) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let event = current_event(EVENT_TYPE_SYNC, fd).ok_or(1u32)?;
+    event.arg = kind;
+    event.open_latency_ns = bpf_ktime_get_ns();
+    let pid_tgid = bpf_get_current_pid_tgid();
+    SYNC_CALLS
+        .insert(&pid_tgid, event, BPF_ANY as u64)
+        .map_err(|_| 1u32)?;
+    Ok(0)
+}
+
+/// Return probe shared by fsync, fdatasync and sync_file_range
+///
+/// Reports successful calls with the time they spent in the kernel.
+#[kretprobe]
+pub fn sync_ret(ctx: RetProbeContext) -> u32 {
+    match try_sync_ret(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_sync_ret(ctx: RetProbeContext) -> Result<u32, u32> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let event = match SYNC_CALLS.get_ptr_mut(&pid_tgid) {
+        Some(event) => unsafe { &mut *event },
+        None => return Ok(0),
+    };
+
+    // Complete the stored event in place rather than copying it out
+    if matches!(ctx.ret::<i64>(), Some(ret) if ret >= 0) {
+        event.open_latency_ns =
+            bpf_ktime_get_ns().saturating_sub(event.open_latency_ns);
+        emit(&ctx, event);
+    }
+    SYNC_CALLS.remove(&pid_tgid).ok();
+    Ok(0)
+}
@@ -0,0 +1,113 @@
+//! Xattr module
+//!
+//! Probes reporting extended attribute changes, on a path or on an open
+//! descriptor.
+
+use aya_ebpf::{macros::kprobe, programs::ProbeContext};
+use fw_common::{
+    FileEvent, EVENT_TYPE_REMOVEXATTR, EVENT_TYPE_SETXATTR, MAX_FILENAME_LEN,
+};
+
+use crate::helpers::{
+    bpf_probe_read_user_str, current_event, emit, read_user_path, syscall_arg,
+    task_allowed,
+};
+
+/// Kernel probe for setxattr system call
+#[kprobe]
+pub fn setxattr(ctx: ProbeContext) -> u32 {
+    match try_path_xattr(ctx, EVENT_TYPE_SETXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for lsetxattr system call
+#[kprobe]
+pub fn lsetxattr(ctx: ProbeContext) -> u32 {
+    match try_path_xattr(ctx, EVENT_TYPE_SETXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for fsetxattr system call
+#[kprobe]
+pub fn fsetxattr(ctx: ProbeContext) -> u32 {
+    match try_fd_xattr(ctx, EVENT_TYPE_SETXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for removexattr system call
+#[kprobe]
+pub fn removexattr(ctx: ProbeContext) -> u32 {
+    match try_path_xattr(ctx, EVENT_TYPE_REMOVEXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for lremovexattr system call
+#[kprobe]
+pub fn lremovexattr(ctx: ProbeContext) -> u32 {
+    match try_path_xattr(ctx, EVENT_TYPE_REMOVEXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Kernel probe for fremovexattr system call
+#[kprobe]
+pub fn fremovexattr(ctx: ProbeContext) -> u32 {
+    match try_fd_xattr(ctx, EVENT_TYPE_REMOVEXATTR) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+/// Handle the path variants, whose first two arguments are path and name
+fn try_path_xattr(ctx: ProbeContext, event_type: u32) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let path: u64 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let name: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+
+    let event = current_event(event_type, -1).ok_or(1u32)?;
+    read_user_path(event, path);
+    read_xattr_name(event, name);
+    emit(&ctx, event);
+    Ok(0)
+}
+
+/// Handle the fd variants, whose first two arguments are fd and name
+///
+/// Userspace resolves the descriptor to a path.
+fn try_fd_xattr(ctx: ProbeContext, event_type: u32) -> Result<u32, u32> {
+    if !task_allowed() {
+        return Ok(0);
+    }
+    let fd: i32 = syscall_arg(&ctx, 0).ok_or(1u32)?;
+    let name: u64 = syscall_arg(&ctx, 1).ok_or(1u32)?;
+
+    let event = current_event(event_type, fd).ok_or(1u32)?;
+    read_xattr_name(event, name);
+    emit(&ctx, event);
+    Ok(0)
+}
+
+/// Read an extended attribute name into the event's filename field
+fn read_xattr_name(event: &mut FileEvent, ptr: u64) {
+    let ret = unsafe {
+        bpf_probe_read_user_str(
+            event.filename.as_mut_ptr(),
+            MAX_FILENAME_LEN as u32,
+            ptr as *const core::ffi::c_void,
+        )
+    };
+    if ret < 0 {
+        event.filename[0] = 0;
+    }
+}
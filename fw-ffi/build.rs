@@ -0,0 +1,14 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    // The header is checked in so C and Go consumers can build against it
+    // without a Rust toolchain; regenerate it whenever the API changes
+    cbindgen::generate(&crate_dir)
+        .expect("Failed to generate the C header")
+        .write_to_file(crate_dir.join("include/fw.h"));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,173 @@
+/* C API of the fw file watcher; link with -lfw_ffi */
+
+#ifndef FW_H
+#define FW_H
+
+/* Generated by cbindgen from fw-ffi/src/lib.rs; do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Version of the `fw_event` layout; fields are only ever appended
+#define FW_EVENT_VERSION 1
+
+// Bytes in `fw_event.path`, including the terminating NUL
+#define FW_PATH_LEN 4096
+
+// Bytes in `fw_event.comm`, including the terminating NUL
+#define FW_COMM_LEN 16
+
+// Value of an id the kernel didn't capture
+#define FW_ID_UNKNOWN UINT32_MAX
+
+// Values of `fw_event.action`
+#define FW_ACTION_OPENED 0
+
+#define FW_ACTION_ALREADY_OPEN 1
+
+#define FW_ACTION_CLOSED 2
+
+#define FW_ACTION_MODE_CHANGED 3
+
+#define FW_ACTION_OWNER_CHANGED 4
+
+#define FW_ACTION_TRUNCATED 5
+
+#define FW_ACTION_LINKED 6
+
+#define FW_ACTION_SYMLINKED 7
+
+#define FW_ACTION_XATTR_SET 8
+
+#define FW_ACTION_XATTR_REMOVED 9
+
+#define FW_ACTION_SYNCED 10
+
+#define FW_ACTION_READ 11
+
+#define FW_ACTION_WRITTEN 12
+
+#define FW_ACTION_LOCKED 13
+
+#define FW_ACTION_UNLOCKED 14
+
+#define FW_ACTION_RENAMED 15
+
+#define FW_ACTION_ATOMIC_SAVE 16
+
+// Watcher handle; created by `fw_watcher_new`, released by
+// `fw_watcher_free`
+struct fw_watcher;
+
+// Criteria for `fw_watcher_set_filter`; arrays may be NULL when their
+// count is 0, and unset criteria match every event
+struct fw_filter {
+  // Globs the whole path must match, such as "/srv/**"
+  const char *const *path_globs;
+  size_t path_glob_count;
+  // Globs the file name must match, such as "*.conf"
+  const char *const *name_globs;
+  size_t name_glob_count;
+  // Processes to report
+  const uint32_t *pids;
+  size_t pid_count;
+  // Users to report, filtered in the kernel
+  const uint32_t *uids;
+  size_t uid_count;
+  // Process names to report, filtered in the kernel
+  const char *const *comms;
+  size_t comm_count;
+  // Report files under /proc, /sys and /dev too
+  bool include_pseudo_fs;
+};
+
+// File event, filled in by `fw_watcher_poll_event`
+struct fw_event {
+  // `FW_EVENT_VERSION` of the library that filled it in
+  uint32_t version;
+  // One of the `FW_ACTION_*` values
+  uint32_t action;
+  // Process that acted on the file
+  uint32_t pid;
+  // Real user of the process, or `FW_ID_UNKNOWN`
+  uint32_t uid;
+  // Real group of the process, or `FW_ID_UNKNOWN`
+  uint32_t gid;
+  // Descriptor opened or closed, or -1
+  int32_t fd;
+  // Time of the event, in nanoseconds since the Unix epoch
+  int64_t timestamp_ns;
+  // New mode bits for `FW_ACTION_MODE_CHANGED`, otherwise 0
+  uint32_t mode;
+  // New owner for `FW_ACTION_OWNER_CHANGED`, otherwise
+  // `FW_ID_UNKNOWN`
+  uint32_t owner_uid;
+  // New group for `FW_ACTION_OWNER_CHANGED`, otherwise
+  // `FW_ID_UNKNOWN`
+  uint32_t owner_gid;
+  // Whether the path is incomplete, as captured or as copied here
+  bool path_truncated;
+  // New length for `FW_ACTION_TRUNCATED`, bytes transferred for
+  // `FW_ACTION_READ` and `FW_ACTION_WRITTEN`, otherwise 0
+  uint64_t bytes;
+  // Process name, NUL-terminated
+  char comm[FW_COMM_LEN];
+  // Path of the file, NUL-terminated; the exact bytes when they aren't
+  // valid UTF-8
+  char path[FW_PATH_LEN];
+};
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a watcher over the eBPF probes. Nothing is attached until the
+// first `fw_watcher_poll_event`.
+//
+// Returns NULL on failure, e.g. without eBPF support or privileges; see
+// `fw_last_error`.
+struct fw_watcher *fw_watcher_new(void);
+
+// Only report the events a filter matches. Must be called before the
+// first `fw_watcher_poll_event`; the filter is copied.
+//
+// Returns 0, or -1 on failure (an invalid glob or process name, or an
+// already started watcher); see `fw_last_error`.
+//
+// # Safety
+// `watcher` must come from `fw_watcher_new`, and the arrays in `filter`
+// must be valid for their counts.
+int fw_watcher_set_filter(struct fw_watcher *watcher,
+                          const struct fw_filter *filter);
+
+// Wait for the next event, starting the watcher on the first call.
+//
+// `timeout_ms` is how long to wait: 0 doesn't wait, a negative value
+// waits until an event arrives.
+//
+// Returns 1 with `event` filled in, 0 if no event arrived in time, or -1
+// on failure or once the watcher has stopped; see `fw_last_error`.
+//
+// # Safety
+// `watcher` must come from `fw_watcher_new` and `event` must point to an
+// `fw_event`. Calls on one watcher must not overlap.
+int fw_watcher_poll_event(struct fw_watcher *watcher,
+                          struct fw_event *event,
+                          int timeout_ms);
+
+// Stop a watcher, detaching its probes, and release it. NULL is ignored.
+//
+// # Safety
+// `watcher` must come from `fw_watcher_new` and not be used afterwards.
+void fw_watcher_free(struct fw_watcher *watcher);
+
+// Message describing the last failure on the calling thread, or NULL.
+// Valid until the next failing call on the thread.
+const char *fw_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FW_H */
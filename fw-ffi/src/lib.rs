@@ -0,0 +1,518 @@
+//! C bindings for fw
+//!
+//! Exposes a small C API over `fw::watcher::Watcher` for programs that
+//! aren't written in Rust. A watcher owns a tokio runtime running the
+//! event delivery task; the caller polls it for events, which are copied
+//! into a fixed-size `fw_event` struct whose layout only grows at the end.
+//! Functions that fail record a message readable with `fw_last_error` on
+//! the calling thread. include/fw.h is generated from this file by
+//! build.rs.
+
+#![allow(non_camel_case_types)]
+
+use anyhow::{anyhow, Result};
+use futures_core::Stream;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::ptr;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use fw::ebpf_monitor::EbpfMonitor;
+use fw::file_event::{FileAction, FileEvent};
+use fw::filter_builder::FilterBuilder;
+use fw::monitor_backend::MonitorBackend;
+use fw::process_list::ProcessListMode;
+use fw::watcher::{EventStream, Watcher};
+
+#[cfg(test)]
+use fw::mock_monitor::MockMonitor;
+
+/// Version of the `fw_event` layout; fields are only ever appended
+pub const FW_EVENT_VERSION: u32 = 1;
+
+/// Bytes in `fw_event.path`, including the terminating NUL
+pub const FW_PATH_LEN: usize = 4096;
+
+/// Bytes in `fw_event.comm`, including the terminating NUL
+pub const FW_COMM_LEN: usize = 16;
+
+/// Value of an id the kernel didn't capture
+pub const FW_ID_UNKNOWN: u32 = u32::MAX;
+
+/// Values of `fw_event.action`
+pub const FW_ACTION_OPENED: u32 = 0;
+pub const FW_ACTION_ALREADY_OPEN: u32 = 1;
+pub const FW_ACTION_CLOSED: u32 = 2;
+pub const FW_ACTION_MODE_CHANGED: u32 = 3;
+pub const FW_ACTION_OWNER_CHANGED: u32 = 4;
+pub const FW_ACTION_TRUNCATED: u32 = 5;
+pub const FW_ACTION_LINKED: u32 = 6;
+pub const FW_ACTION_SYMLINKED: u32 = 7;
+pub const FW_ACTION_XATTR_SET: u32 = 8;
+pub const FW_ACTION_XATTR_REMOVED: u32 = 9;
+pub const FW_ACTION_SYNCED: u32 = 10;
+pub const FW_ACTION_READ: u32 = 11;
+pub const FW_ACTION_WRITTEN: u32 = 12;
+pub const FW_ACTION_LOCKED: u32 = 13;
+pub const FW_ACTION_UNLOCKED: u32 = 14;
+pub const FW_ACTION_RENAMED: u32 = 15;
+pub const FW_ACTION_ATOMIC_SAVE: u32 = 16;
+
+/// File event, filled in by `fw_watcher_poll_event`
+#[repr(C)]
+pub struct fw_event {
+    /// `FW_EVENT_VERSION` of the library that filled it in
+    pub version: u32,
+    /// One of the `FW_ACTION_*` values
+    pub action: u32,
+    /// Process that acted on the file
+    pub pid: u32,
+    /// Real user of the process, or `FW_ID_UNKNOWN`
+    pub uid: u32,
+    /// Real group of the process, or `FW_ID_UNKNOWN`
+    pub gid: u32,
+    /// Descriptor opened or closed, or -1
+    pub fd: i32,
+    /// Time of the event, in nanoseconds since the Unix epoch
+    pub timestamp_ns: i64,
+    /// New mode bits for `FW_ACTION_MODE_CHANGED`, otherwise 0
+    pub mode: u32,
+    /// New owner for `FW_ACTION_OWNER_CHANGED`, otherwise
+    /// `FW_ID_UNKNOWN`
+    pub owner_uid: u32,
+    /// New group for `FW_ACTION_OWNER_CHANGED`, otherwise
+    /// `FW_ID_UNKNOWN`
+    pub owner_gid: u32,
+    /// Whether the path is incomplete, as captured or as copied here
+    pub path_truncated: bool,
+    /// New length for `FW_ACTION_TRUNCATED`, bytes transferred for
+    /// `FW_ACTION_READ` and `FW_ACTION_WRITTEN`, otherwise 0
+    pub bytes: u64,
+    /// Process name, NUL-terminated
+    pub comm: [c_char; FW_COMM_LEN],
+    /// Path of the file, NUL-terminated; the exact bytes when they aren't
+    /// valid UTF-8
+    pub path: [c_char; FW_PATH_LEN],
+}
+
+/// Criteria for `fw_watcher_set_filter`; arrays may be NULL when their
+/// count is 0, and unset criteria match every event
+#[repr(C)]
+pub struct fw_filter {
+    /// Globs the whole path must match, such as "/srv/**"
+    pub path_globs: *const *const c_char,
+    pub path_glob_count: usize,
+    /// Globs the file name must match, such as "*.conf"
+    pub name_globs: *const *const c_char,
+    pub name_glob_count: usize,
+    /// Processes to report
+    pub pids: *const u32,
+    pub pid_count: usize,
+    /// Users to report, filtered in the kernel
+    pub uids: *const u32,
+    pub uid_count: usize,
+    /// Process names to report, filtered in the kernel
+    pub comms: *const *const c_char,
+    pub comm_count: usize,
+    /// Report files under /proc, /sys and /dev too
+    pub include_pseudo_fs: bool,
+}
+
+/// Backends a C watcher can run
+enum Backend {
+    Ebpf(Box<EbpfMonitor>),
+    #[cfg(test)]
+    Mock(MockMonitor),
+}
+
+impl MonitorBackend for Backend {
+    async fn start_monitoring(
+        &mut self,
+    ) -> Result<tokio::sync::mpsc::Receiver<FileEvent>> {
+        match self {
+            Backend::Ebpf(monitor) => monitor.start_monitoring().await,
+            #[cfg(test)]
+            Backend::Mock(monitor) => monitor.start_monitoring().await,
+        }
+    }
+
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        match self {
+            Backend::Ebpf(monitor) => monitor.stop_monitoring().await,
+            #[cfg(test)]
+            Backend::Mock(monitor) => monitor.stop_monitoring().await,
+        }
+    }
+}
+
+/// Watcher handle; created by `fw_watcher_new`, released by
+/// `fw_watcher_free`
+pub struct fw_watcher {
+    runtime: Runtime,
+    watcher: Watcher<Backend>,
+    /// Events, once the first poll started the watcher
+    events: Option<EventStream>,
+}
+
+thread_local! {
+    /// Message of the last failure on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Record a failure for `fw_last_error`
+fn set_last_error(error: &anyhow::Error) {
+    let message = format!("{:#}", error).replace('\0', " ");
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = CString::new(message).ok();
+    });
+}
+
+/// Record a failure and return the error code
+fn fail(error: anyhow::Error) -> c_int {
+    set_last_error(&error);
+    -1
+}
+
+impl fw_watcher {
+    fn with_backend(backend: Backend) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("fw-watcher")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            runtime,
+            watcher: Watcher::new(backend),
+            events: None,
+        })
+    }
+
+    /// Wait for the next event, starting the watcher on the first call
+    fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<FileEvent>> {
+        let events = match &mut self.events {
+            Some(events) => events,
+            events @ None => {
+                events.insert(self.runtime.block_on(self.watcher.events())?)
+            }
+        };
+        let next = poll_fn(|cx| Pin::new(&mut *events).poll_next(cx));
+        let event = match timeout {
+            Some(timeout) => {
+                let next = async { tokio::time::timeout(timeout, next).await };
+                match self.runtime.block_on(next) {
+                    Ok(event) => event,
+                    Err(_) => return Ok(None),
+                }
+            }
+            None => self.runtime.block_on(next),
+        };
+        event
+            .map(Some)
+            .ok_or_else(|| anyhow!("The watcher has stopped producing events"))
+    }
+}
+
+/// Copy a string into a fixed-size C buffer, NUL-terminated
+///
+/// # Returns
+/// * `bool` - True if it had to be cut short
+fn copy_c_string(bytes: &[u8], buffer: &mut [c_char]) -> bool {
+    let len = bytes.len().min(buffer.len() - 1);
+    for (dst, &src) in buffer.iter_mut().zip(&bytes[..len]) {
+        *dst = src as c_char;
+    }
+    buffer[len] = 0;
+    len < bytes.len()
+}
+
+/// Action code and its details
+fn action_fields(action: &FileAction) -> (u32, u32, (u32, u32), u64) {
+    let unknown = (FW_ID_UNKNOWN, FW_ID_UNKNOWN);
+    match *action {
+        FileAction::Opened => (FW_ACTION_OPENED, 0, unknown, 0),
+        FileAction::AlreadyOpen => (FW_ACTION_ALREADY_OPEN, 0, unknown, 0),
+        FileAction::Closed => (FW_ACTION_CLOSED, 0, unknown, 0),
+        FileAction::ModeChanged { mode } => {
+            (FW_ACTION_MODE_CHANGED, mode, unknown, 0)
+        }
+        FileAction::OwnerChanged { uid, gid } => {
+            (FW_ACTION_OWNER_CHANGED, 0, (uid, gid), 0)
+        }
+        FileAction::Truncated { length } => {
+            (FW_ACTION_TRUNCATED, 0, unknown, length)
+        }
+        FileAction::Linked => (FW_ACTION_LINKED, 0, unknown, 0),
+        FileAction::Symlinked => (FW_ACTION_SYMLINKED, 0, unknown, 0),
+        FileAction::XattrSet => (FW_ACTION_XATTR_SET, 0, unknown, 0),
+        FileAction::XattrRemoved => (FW_ACTION_XATTR_REMOVED, 0, unknown, 0),
+        FileAction::Synced { .. } => (FW_ACTION_SYNCED, 0, unknown, 0),
+        FileAction::Read { bytes, .. } => (FW_ACTION_READ, 0, unknown, bytes),
+        FileAction::Written { bytes, .. } => {
+            (FW_ACTION_WRITTEN, 0, unknown, bytes)
+        }
+        FileAction::Locked { .. } => (FW_ACTION_LOCKED, 0, unknown, 0),
+        FileAction::Unlocked => (FW_ACTION_UNLOCKED, 0, unknown, 0),
+        FileAction::Renamed => (FW_ACTION_RENAMED, 0, unknown, 0),
+        FileAction::AtomicSave => (FW_ACTION_ATOMIC_SAVE, 0, unknown, 0),
+    }
+}
+
+/// Fill in a C event from a file event
+fn fill_event(event: &FileEvent, out: &mut fw_event) {
+    let (action, mode, (owner_uid, owner_gid), bytes) =
+        action_fields(&event.action);
+    out.version = FW_EVENT_VERSION;
+    out.action = action;
+    out.pid = event.pid;
+    out.uid = event.uid.unwrap_or(FW_ID_UNKNOWN);
+    out.gid = event.gid.unwrap_or(FW_ID_UNKNOWN);
+    out.fd = event.fd.unwrap_or(-1);
+    out.timestamp_ns = event.timestamp.timestamp_nanos_opt().unwrap_or(0);
+    out.mode = mode;
+    out.owner_uid = owner_uid;
+    out.owner_gid = owner_gid;
+    out.bytes = bytes;
+    copy_c_string(event.program_name.as_bytes(), &mut out.comm);
+    let path = event
+        .raw_path
+        .as_deref()
+        .unwrap_or(event.file_path.as_bytes());
+    out.path_truncated =
+        copy_c_string(path, &mut out.path) || event.path_truncated;
+}
+
+/// Read a C string array
+///
+/// # Safety
+/// `strings` must point to `count` NUL-terminated strings, or `count`
+/// must be 0
+unsafe fn strings(
+    strings: *const *const c_char,
+    count: usize,
+) -> Result<Vec<String>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    std::slice::from_raw_parts(strings, count)
+        .iter()
+        .map(|&s| {
+            if s.is_null() {
+                return Err(anyhow!("NULL string in filter"));
+            }
+            Ok(CStr::from_ptr(s).to_str()?.to_string())
+        })
+        .collect()
+}
+
+/// Read an id array
+///
+/// # Safety
+/// `ids` must point to `count` ids, or `count` must be 0
+unsafe fn ids(ids: *const u32, count: usize) -> Vec<u32> {
+    if count == 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(ids, count).to_vec()
+}
+
+/// Compile a C filter
+///
+/// # Safety
+/// The arrays in `filter` must be valid for their counts
+unsafe fn builder(filter: &fw_filter) -> Result<FilterBuilder> {
+    let mut builder =
+        FilterBuilder::new().with_pseudo_fs(filter.include_pseudo_fs);
+    for glob in strings(filter.path_globs, filter.path_glob_count)? {
+        builder = builder.with_path_glob(glob);
+    }
+    for glob in strings(filter.name_globs, filter.name_glob_count)? {
+        builder = builder.with_name_glob(glob);
+    }
+    for pid in ids(filter.pids, filter.pid_count) {
+        builder = builder.with_pid(pid);
+    }
+    for uid in ids(filter.uids, filter.uid_count) {
+        builder = builder.with_uid(uid);
+    }
+    let comms = strings(filter.comms, filter.comm_count)?;
+    if !comms.is_empty() {
+        builder = builder.with_comm_mode(ProcessListMode::Allow);
+    }
+    for comm in comms {
+        builder = builder.with_comm(comm);
+    }
+    Ok(builder)
+}
+
+/// Create a watcher over the eBPF probes. Nothing is attached until the
+/// first `fw_watcher_poll_event`.
+///
+/// Returns NULL on failure, e.g. without eBPF support or privileges; see
+/// `fw_last_error`.
+#[no_mangle]
+pub extern "C" fn fw_watcher_new() -> *mut fw_watcher {
+    let watcher = EbpfMonitor::new().and_then(|monitor| {
+        fw_watcher::with_backend(Backend::Ebpf(monitor.into()))
+    });
+    match watcher {
+        Ok(watcher) => Box::into_raw(Box::new(watcher)),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Only report the events a filter matches. Must be called before the
+/// first `fw_watcher_poll_event`; the filter is copied.
+///
+/// Returns 0, or -1 on failure (an invalid glob or process name, or an
+/// already started watcher); see `fw_last_error`.
+///
+/// # Safety
+/// `watcher` must come from `fw_watcher_new`, and the arrays in `filter`
+/// must be valid for their counts.
+#[no_mangle]
+pub unsafe extern "C" fn fw_watcher_set_filter(
+    watcher: *mut fw_watcher,
+    filter: *const fw_filter,
+) -> c_int {
+    let (Some(watcher), Some(filter)) = (watcher.as_mut(), filter.as_ref())
+    else {
+        return fail(anyhow!("NULL watcher or filter"));
+    };
+    if watcher.events.is_some() {
+        return fail(anyhow!("The filter must be set before the first poll"));
+    }
+    match builder(filter).and_then(FilterBuilder::build) {
+        Ok(filter) => {
+            watcher.watcher.set_filter(filter);
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Wait for the next event, starting the watcher on the first call.
+///
+/// `timeout_ms` is how long to wait: 0 doesn't wait, a negative value
+/// waits until an event arrives.
+///
+/// Returns 1 with `event` filled in, 0 if no event arrived in time, or -1
+/// on failure or once the watcher has stopped; see `fw_last_error`.
+///
+/// # Safety
+/// `watcher` must come from `fw_watcher_new` and `event` must point to an
+/// `fw_event`. Calls on one watcher must not overlap.
+#[no_mangle]
+pub unsafe extern "C" fn fw_watcher_poll_event(
+    watcher: *mut fw_watcher,
+    event: *mut fw_event,
+    timeout_ms: c_int,
+) -> c_int {
+    let (Some(watcher), Some(event)) = (watcher.as_mut(), event.as_mut())
+    else {
+        return fail(anyhow!("NULL watcher or event"));
+    };
+    let timeout = u64::try_from(timeout_ms).ok().map(Duration::from_millis);
+    match watcher.poll(timeout) {
+        Ok(Some(file_event)) => {
+            fill_event(&file_event, event);
+            1
+        }
+        Ok(None) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+/// Stop a watcher, detaching its probes, and release it. NULL is ignored.
+///
+/// # Safety
+/// `watcher` must come from `fw_watcher_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fw_watcher_free(watcher: *mut fw_watcher) {
+    if watcher.is_null() {
+        return;
+    }
+    let mut watcher = Box::from_raw(watcher);
+    if let Err(e) = watcher.runtime.block_on(watcher.watcher.stop()) {
+        set_last_error(&e);
+    }
+}
+
+/// Message describing the last failure on the calling thread, or NULL.
+/// Valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn fw_last_error() -> *const c_char {
+    LAST_ERROR
+        .with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fw::clock::{Clock, SystemClock};
+
+    fn event(path: &str, program: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            program.to_string(),
+            FileAction::Truncated { length: 42 },
+            7,
+            SystemClock.now(),
+        )
+        .with_ids(1000, 100)
+    }
+
+    #[test]
+    fn test_poll_with_filter() {
+        let backend = Backend::Mock(MockMonitor::new(vec![
+            event("/srv/a.conf", "nginx"),
+            event("/srv/b.conf", "bash"),
+        ]));
+        let watcher =
+            Box::into_raw(Box::new(fw_watcher::with_backend(backend).unwrap()));
+        let comms = [c"nginx".as_ptr()];
+        let filter = fw_filter {
+            path_globs: ptr::null(),
+            path_glob_count: 0,
+            name_globs: ptr::null(),
+            name_glob_count: 0,
+            pids: ptr::null(),
+            pid_count: 0,
+            uids: ptr::null(),
+            uid_count: 0,
+            comms: comms.as_ptr(),
+            comm_count: comms.len(),
+            include_pseudo_fs: false,
+        };
+        let mut out: Box<fw_event> = Box::new(unsafe { std::mem::zeroed() });
+        unsafe {
+            assert_eq!(fw_watcher_set_filter(watcher, &filter), 0);
+            assert_eq!(fw_watcher_poll_event(watcher, &mut *out, -1), 1);
+            assert_eq!(out.action, FW_ACTION_TRUNCATED);
+            assert_eq!((out.pid, out.uid, out.gid), (7, 1000, 100));
+            assert_eq!(out.bytes, 42);
+            assert_eq!(out.fd, -1);
+            assert_eq!(CStr::from_ptr(out.path.as_ptr()), c"/srv/a.conf");
+            assert_eq!(CStr::from_ptr(out.comm.as_ptr()), c"nginx");
+
+            // bash is filtered out, then the mock backend runs dry
+            assert_eq!(fw_watcher_poll_event(watcher, &mut *out, -1), -1);
+            assert!(!fw_last_error().is_null());
+            assert_eq!(fw_watcher_set_filter(watcher, &filter), -1);
+            fw_watcher_free(watcher);
+        }
+    }
+
+    #[test]
+    fn test_long_path_is_truncated() {
+        let mut out: Box<fw_event> = Box::new(unsafe { std::mem::zeroed() });
+        fill_event(&event(&"/x".repeat(FW_PATH_LEN), "sh"), &mut out);
+        assert!(out.path_truncated);
+        assert_eq!(out.path[FW_PATH_LEN - 1], 0);
+    }
+}
@@ -0,0 +1,72 @@
+//! Criterion benchmarks for the event pipeline
+//!
+//! Measures the userspace cost of each stage an event goes through:
+//! decoding raw kernel bytes, filtering by extension, and formatting the
+//! output line. Run with `cargo bench -p fw`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fw::clock::{Clock, SystemClock};
+use fw::file_event::{FileAction, FileEvent};
+use fw::path_assembler::PathAssembler;
+use fw_common::{FileEvent as RawFileEvent, EVENT_ABI_VERSION};
+use zerocopy::{FromZeros, IntoBytes};
+
+/// Build a raw kernel event for the given path
+fn raw_event(path: &[u8]) -> RawFileEvent {
+    let mut raw = RawFileEvent::new_zeroed();
+    raw.version = EVENT_ABI_VERSION;
+    raw.pid = 1234;
+    raw.tgid = 1234;
+    raw.fd = 3;
+    raw.path[..path.len()].copy_from_slice(path);
+    raw
+}
+
+/// Build a decoded event for the given path
+fn sample_event(path: &str) -> FileEvent {
+    FileEvent::new(
+        path.to_string(),
+        "rustc".to_string(),
+        FileAction::Opened,
+        1234,
+        SystemClock.now(),
+    )
+}
+
+/// Benchmark decoding raw perf buffer bytes into a complete path
+fn bench_decode(c: &mut Criterion) {
+    let raw = raw_event(b"/home/user/project/src/main.rs");
+    let bytes = raw.as_bytes();
+    let mut assembler = PathAssembler::new();
+
+    c.bench_function("decode", |b| {
+        b.iter(|| {
+            let event = RawFileEvent::from_bytes(black_box(bytes)).unwrap();
+            black_box(assembler.push(event))
+        })
+    });
+}
+
+/// Benchmark the extension filter for matching and non-matching events
+fn bench_filter(c: &mut Criterion) {
+    let matching = sample_event("/home/user/project/src/main.rs");
+    let other = sample_event("/usr/lib/libc.so.6");
+    let extensions = Some(vec!["rs".to_string(), "toml".to_string()]);
+
+    c.bench_function("filter_match", |b| {
+        b.iter(|| black_box(&matching).matches_extensions(&extensions, false))
+    });
+    c.bench_function("filter_miss", |b| {
+        b.iter(|| black_box(&other).matches_extensions(&extensions, false))
+    });
+}
+
+/// Benchmark formatting an event into its output line
+fn bench_format(c: &mut Criterion) {
+    let event = sample_event("/home/user/project/src/main.rs");
+
+    c.bench_function("format", |b| b.iter(|| black_box(&event).to_string()));
+}
+
+criterion_group!(benches, bench_decode, bench_filter, bench_format);
+criterion_main!(benches);
@@ -1,38 +1,59 @@
+use anyhow::{anyhow, Context as _};
+use aya_build::cargo_metadata;
 use std::env;
 use std::path::PathBuf;
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
-    // Tell cargo to rerun if the eBPF program changes
-    println!("cargo:rerun-if-changed=../fw-ebpf/src");
-
-    // Compile the eBPF program
-    let mut cmd = std::process::Command::new("cargo");
-    cmd.current_dir("../fw-ebpf")
-        .env("CARGO_CFG_TARGET_ARCH", "bpf")
-        .args(&[
-            "build",
-            "--target=bpfel-unknown-none",
-            "--release",
-        ]);
-
-    let output = cmd.output().expect("Failed to build eBPF program");
-
-    if !output.status.success() {
-        panic!(
-            "Failed to build eBPF program:\nstdout: {}\nstderr: {}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    // Copy the compiled eBPF object to our output directory
-    let ebpf_obj = PathBuf::from("../fw-ebpf/target/bpfel-unknown-none/release/fw-ebpf");
-    let dest = out_dir.join("fw-ebpf.o");
-
-    std::fs::copy(&ebpf_obj, &dest)
-        .unwrap_or_else(|e| panic!("Failed to copy eBPF object from {:?} to {:?}: {}", ebpf_obj, dest, e));
-
-    println!("cargo:rustc-env=EBPF_OBJECT_PATH={}", dest.display());
+    // Find fw-ebpf through cargo rather than a path relative to the
+    // directory the build happens to run in
+    let cargo_metadata::Metadata { packages, .. } =
+        cargo_metadata::MetadataCommand::new()
+            .no_deps()
+            .exec()
+            .context("Failed to read the workspace metadata")?;
+    let cargo_metadata::Package {
+        name,
+        manifest_path,
+        ..
+    } = packages
+        .into_iter()
+        .find(|package| package.name.as_str() == "fw-ebpf")
+        .ok_or_else(|| anyhow!("fw-ebpf package not found"))?;
+    let root_dir = manifest_path
+        .parent()
+        .ok_or_else(|| anyhow!("No parent for {}", manifest_path))?;
+
+    // Compile the eBPF program with the nightly toolchain and bpf-linker of
+    // the host, whatever target fw itself is built for. aya-build picks the
+    // BPF endianness and the architecture whose saved user registers the
+    // probes read syscall arguments from (CARGO_CFG_BPF_TARGET_ARCH) from
+    // fw's target, and builds into OUT_DIR so the outer build's target
+    // directory and flags don't leak in
+    aya_build::build_ebpf(
+        [aya_build::Package {
+            name: name.as_str(),
+            root_dir: root_dir.as_str(),
+            ..Default::default()
+        }],
+        aya_build::Toolchain::default(),
+    )?;
+
+    // Probes share their event layout with fw-common
+    println!("cargo:rerun-if-changed=../fw-common/src");
+
+    // Record a checksum so fw can tell the embedded object is intact;
+    // src/bpf_object.rs embeds the object from OUT_DIR
+    let object_path = out_dir.join("fw-ebpf");
+    let object = std::fs::read(&object_path)
+        .with_context(|| format!("Failed to read {}", object_path.display()))?;
+    println!(
+        "cargo:rustc-env=EBPF_OBJECT_CHECKSUM={:016x}",
+        fw_common::object_checksum(&object)
+    );
+
+    // Reported by `fw --version --verbose`
+    println!("cargo:rustc-env=FW_TARGET={}", env::var("TARGET")?);
+    Ok(())
 }
@@ -0,0 +1,280 @@
+//! Access Pattern module
+//!
+//! Classifies how a file was used while it was open from the offsets and
+//! sizes of its reads and writes (`fw collect --access-patterns`). A
+//! transfer is sequential when it starts where the previous one ended;
+//! the share of sequential transfers gives the order, and the bytes read
+//! against the bytes written give the balance. Plain read and write calls
+//! continue at the file position, so they count as sequential unless
+//! pread and pwrite moved elsewhere in between (lseek is not traced).
+
+use std::fmt;
+
+use crate::file_event::FileAction;
+use crate::report::json_string;
+
+/// Share of transfers that must continue the previous one for the order
+/// to be sequential; random when at most `1 - SEQUENTIAL_SHARE` do
+pub const SEQUENTIAL_SHARE: f64 = 0.8;
+
+/// How many times more bytes must move one way than the other for a
+/// session to be read-heavy or write-heavy
+pub const HEAVY_RATIO: u64 = 2;
+
+/// Whether transfers followed each other through the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessOrder {
+    /// Almost every transfer continued where the previous one ended
+    Sequential,
+    /// Almost every transfer jumped to another offset
+    Random,
+    /// Neither dominated
+    Mixed,
+}
+
+impl fmt::Display for AccessOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessOrder::Sequential => write!(f, "sequential"),
+            AccessOrder::Random => write!(f, "random"),
+            AccessOrder::Mixed => write!(f, "mixed"),
+        }
+    }
+}
+
+/// Whether reads or writes moved more data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessBalance {
+    /// Nothing was written
+    ReadOnly,
+    /// Reads moved at least `HEAVY_RATIO` times the bytes of writes
+    ReadHeavy,
+    /// Neither direction dominated
+    Balanced,
+    /// Writes moved at least `HEAVY_RATIO` times the bytes of reads
+    WriteHeavy,
+    /// Nothing was read
+    WriteOnly,
+}
+
+impl fmt::Display for AccessBalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessBalance::ReadOnly => write!(f, "read-only"),
+            AccessBalance::ReadHeavy => write!(f, "read-heavy"),
+            AccessBalance::Balanced => write!(f, "balanced"),
+            AccessBalance::WriteHeavy => write!(f, "write-heavy"),
+            AccessBalance::WriteOnly => write!(f, "write-only"),
+        }
+    }
+}
+
+/// Label of a session's access pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessPattern {
+    /// Whether transfers were sequential or random
+    pub order: AccessOrder,
+    /// Whether reads or writes dominated
+    pub balance: AccessBalance,
+}
+
+impl fmt::Display for AccessPattern {
+    /// Format as "order balance", e.g. "sequential read-heavy"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.order, self.balance)
+    }
+}
+
+/// Reads and writes seen during one session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessTracker {
+    /// Number of reads
+    pub reads: u64,
+    /// Number of writes
+    pub writes: u64,
+    /// Bytes read
+    pub read_bytes: u64,
+    /// Bytes written
+    pub written_bytes: u64,
+    /// Transfers after the first that started where the previous ended
+    pub sequential: u64,
+    /// Offset just past the previous transfer
+    next_offset: Option<u64>,
+}
+
+impl AccessTracker {
+    /// Count a read or write
+    ///
+    /// # Arguments
+    /// * `action` - Action of an event on the session's file
+    ///
+    /// # Returns
+    /// * `bool` - True if the action was a read or write
+    pub fn record(&mut self, action: &FileAction) -> bool {
+        let (offset, bytes) = match *action {
+            FileAction::Read { offset, bytes } => {
+                self.reads += 1;
+                self.read_bytes += bytes;
+                (offset, bytes)
+            }
+            FileAction::Written { offset, bytes } => {
+                self.writes += 1;
+                self.written_bytes += bytes;
+                (offset, bytes)
+            }
+            _ => return false,
+        };
+        let start = match (offset, self.next_offset) {
+            (Some(offset), _) => offset,
+            // At the file position, which the previous transfer advanced
+            (None, Some(next)) => next,
+            (None, None) => 0,
+        };
+        if self.next_offset == Some(start) {
+            self.sequential += 1;
+        }
+        self.next_offset = Some(start.saturating_add(bytes));
+        true
+    }
+
+    /// Number of reads and writes
+    ///
+    /// # Returns
+    /// * `u64` - Transfer count
+    pub fn transfers(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    /// Classify the transfers seen so far
+    ///
+    /// # Returns
+    /// * `Option<AccessPattern>` - Pattern, or None if nothing was read or
+    ///   written
+    pub fn pattern(&self) -> Option<AccessPattern> {
+        let transfers = self.transfers();
+        if transfers == 0 {
+            return None;
+        }
+        // The first transfer has nothing to follow
+        let share = match transfers - 1 {
+            0 => 1.0,
+            followers => self.sequential as f64 / followers as f64,
+        };
+        let order = if share >= SEQUENTIAL_SHARE {
+            AccessOrder::Sequential
+        } else if share <= 1.0 - SEQUENTIAL_SHARE {
+            AccessOrder::Random
+        } else {
+            AccessOrder::Mixed
+        };
+        let (read, written) = (self.read_bytes, self.written_bytes);
+        let balance = if self.writes == 0 {
+            AccessBalance::ReadOnly
+        } else if self.reads == 0 {
+            AccessBalance::WriteOnly
+        } else if read >= written.saturating_mul(HEAVY_RATIO) {
+            AccessBalance::ReadHeavy
+        } else if written >= read.saturating_mul(HEAVY_RATIO) {
+            AccessBalance::WriteHeavy
+        } else {
+            AccessBalance::Balanced
+        };
+        Some(AccessPattern { order, balance })
+    }
+
+    /// Render the counts and pattern as JSON object fields
+    ///
+    /// # Returns
+    /// * `String` - Comma-separated fields without braces, or an empty
+    ///   string if nothing was read or written
+    pub fn json_fields(&self) -> String {
+        let Some(pattern) = self.pattern() else {
+            return String::new();
+        };
+        format!(
+            "\"pattern\":{},\"order\":{},\"balance\":{},\"reads\":{},\
+             \"writes\":{},\"read_bytes\":{},\"written_bytes\":{}",
+            json_string(&pattern.to_string()),
+            json_string(&pattern.order.to_string()),
+            json_string(&pattern.balance.to_string()),
+            self.reads,
+            self.writes,
+            self.read_bytes,
+            self.written_bytes
+        )
+    }
+}
+
+impl fmt::Display for AccessTracker {
+    /// Format as "pattern, read NB in N, wrote NB in N", e.g.
+    /// "random read-heavy, read 8192B in 2, wrote 100B in 1"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(pattern) = self.pattern() else {
+            return write!(f, "no i/o");
+        };
+        write!(
+            f,
+            "{}, read {}B in {}, wrote {}B in {}",
+            pattern,
+            self.read_bytes,
+            self.reads,
+            self.written_bytes,
+            self.writes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(offset: Option<u64>, bytes: u64) -> FileAction {
+        FileAction::Read { offset, bytes }
+    }
+
+    fn write(offset: Option<u64>, bytes: u64) -> FileAction {
+        FileAction::Written { offset, bytes }
+    }
+
+    #[test]
+    fn test_classifies_order_and_balance() {
+        // A file streamed through with read(2), one small write at the end
+        let mut stream = AccessTracker::default();
+        for _ in 0..10 {
+            assert!(stream.record(&read(None, 4096)));
+        }
+        stream.record(&write(None, 100));
+        assert!(!stream.record(&FileAction::Closed));
+        assert_eq!(stream.sequential, 10);
+        assert_eq!(
+            stream.to_string(),
+            "sequential read-heavy, read 40960B in 10, wrote 100B in 1"
+        );
+
+        // A database page cache: preads and pwrites all over the file
+        let mut pages = AccessTracker::default();
+        for (i, page) in [7u64, 2, 9, 4, 3, 11].into_iter().enumerate() {
+            let action = match i % 2 {
+                0 => read(Some(page * 4096), 4096),
+                _ => write(Some(page * 4096), 4096),
+            };
+            pages.record(&action);
+        }
+        let pattern = pages.pattern().unwrap();
+        assert_eq!(pattern.order, AccessOrder::Random);
+        assert_eq!(pattern.balance, AccessBalance::Balanced);
+
+        let mut log = AccessTracker::default();
+        log.record(&write(Some(0), 10));
+        log.record(&write(Some(10), 10));
+        log.record(&write(Some(100), 10));
+        assert_eq!(log.pattern().unwrap().to_string(), "mixed write-only");
+        assert_eq!(
+            log.json_fields(),
+            "\"pattern\":\"mixed write-only\",\"order\":\"mixed\",\
+             \"balance\":\"write-only\",\"reads\":0,\"writes\":3,\
+             \"read_bytes\":0,\"written_bytes\":30"
+        );
+        assert_eq!(AccessTracker::default().pattern(), None);
+    }
+}
@@ -0,0 +1,110 @@
+//! Arch module
+//!
+//! Kernel architecture detection for probe attachment. Syscalls are entered
+//! through per-architecture wrapper symbols (`__x64_sys_openat`,
+//! `__arm64_sys_openat`, ...), so the symbol a syscall probe attaches to
+//! depends on the machine fw is loaded on. The wrappers receive the user
+//! registers as their only argument; the eBPF programs read the syscall
+//! arguments from there using the register layout of the architecture
+//! they were built for.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// A kernel architecture fw can attach probes on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    /// 64-bit x86
+    X86_64,
+    /// 64-bit ARM
+    Aarch64,
+    /// 64-bit RISC-V
+    Riscv64,
+}
+
+impl Arch {
+    /// Parse the machine name reported by `uname -m`
+    ///
+    /// # Arguments
+    /// * `machine` - Machine name, e.g. "x86_64" or "aarch64"
+    ///
+    /// # Returns
+    /// * `Option<Arch>` - The architecture, or None if it isn't supported
+    pub fn from_machine(machine: &str) -> Option<Self> {
+        match machine {
+            "x86_64" | "amd64" => Some(Arch::X86_64),
+            "aarch64" | "arm64" => Some(Arch::Aarch64),
+            "riscv64" => Some(Arch::Riscv64),
+            _ => None,
+        }
+    }
+
+    /// Detect the architecture of the running kernel
+    ///
+    /// # Returns
+    /// * `Result<Arch>` - The kernel's architecture, or error if fw can't
+    ///   attach probes on it
+    pub fn detect() -> Result<Self> {
+        let uts = nix::sys::utsname::uname()?;
+        let machine = uts.machine().to_string_lossy();
+        Self::from_machine(&machine).ok_or_else(|| {
+            anyhow!("Unsupported architecture for eBPF probes: {}", machine)
+        })
+    }
+
+    /// Prefix of the kernel's syscall wrapper symbols
+    ///
+    /// # Returns
+    /// * `&'static str` - Prefix to put before a syscall name
+    pub fn syscall_prefix(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "__x64_sys_",
+            Arch::Aarch64 => "__arm64_sys_",
+            Arch::Riscv64 => "__riscv_sys_",
+        }
+    }
+
+    /// Kernel symbol of a syscall's entry point
+    ///
+    /// # Arguments
+    /// * `syscall` - Syscall name, e.g. "openat"
+    ///
+    /// # Returns
+    /// * `String` - Symbol to attach to, e.g. "__arm64_sys_openat"
+    pub fn syscall_symbol(self, syscall: &str) -> String {
+        format!("{}{}", self.syscall_prefix(), syscall)
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+            Arch::Riscv64 => "riscv64",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_machine() {
+        assert_eq!(Arch::from_machine("x86_64"), Some(Arch::X86_64));
+        assert_eq!(Arch::from_machine("arm64"), Some(Arch::Aarch64));
+        assert_eq!(Arch::from_machine("aarch64"), Some(Arch::Aarch64));
+        assert_eq!(Arch::from_machine("riscv64"), Some(Arch::Riscv64));
+        assert_eq!(Arch::from_machine("i686"), None);
+        assert_eq!(Arch::from_machine("armv7l"), None);
+    }
+
+    #[test]
+    fn test_syscall_symbol() {
+        assert_eq!(Arch::X86_64.syscall_symbol("openat"), "__x64_sys_openat");
+        assert_eq!(Arch::Aarch64.syscall_symbol("dup3"), "__arm64_sys_dup3");
+        assert_eq!(Arch::Riscv64.syscall_symbol("fsync"), "__riscv_sys_fsync");
+    }
+}
@@ -0,0 +1,520 @@
+//! Audit format module
+//!
+//! Formats events for SIEM ingestion: Elastic Common Schema (ECS) JSON
+//! lines and ArcSight Common Event Format (CEF) lines. fw's fields are
+//! mapped onto the standard names (`file.path`, `process.pid`,
+//! `event.action`, ...) so collectors can take the output as is; fields
+//! with no standard counterpart go under `fw.*` in ECS and into labelled
+//! custom strings in CEF.
+
+use anyhow::Result;
+use std::fmt;
+use std::io::Write;
+
+use chrono::SecondsFormat;
+
+use crate::binary_format::BinaryFormat;
+use crate::fanout::EventEncoder;
+use crate::file_event::{FileAction, FileEvent, FileType};
+use crate::report::json_string;
+use crate::severity::Severity;
+use crate::stacks::frames_json;
+use crate::template::EventTemplate;
+
+/// ECS version the JSON lines follow
+const ECS_VERSION: &str = "8.11.0";
+
+/// How `fw collect --mode events` writes each event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventFormat {
+    /// Human-readable line
+    #[default]
+    Text,
+    /// Elastic Common Schema JSON, one object per line
+    EcsJson,
+    /// ArcSight Common Event Format
+    Cef,
+    /// Layout given with `--template`
+    Template,
+    /// Length-prefixed MessagePack frames (see [`crate::binary_format`])
+    Msgpack,
+    /// Length-prefixed CBOR frames (see [`crate::binary_format`])
+    Cbor,
+    /// Length-prefixed protocol buffer frames (see
+    /// [`crate::proto_format`])
+    Proto,
+}
+
+impl EventFormat {
+    /// Binary encoding written instead of lines, if any
+    ///
+    /// # Returns
+    /// * `Option<BinaryFormat>` - Encoding of each frame, or None for
+    ///   the line formats
+    pub fn binary(self) -> Option<BinaryFormat> {
+        match self {
+            EventFormat::Msgpack => Some(BinaryFormat::Msgpack),
+            EventFormat::Cbor => Some(BinaryFormat::Cbor),
+            EventFormat::Proto => Some(BinaryFormat::Proto),
+            _ => None,
+        }
+    }
+
+    /// How a [`TextSink`](crate::fanout::TextSink) writes events in this
+    /// format
+    ///
+    /// # Arguments
+    /// * `template` - Layout for `EventFormat::Template`; the
+    ///   human-readable line is written if None
+    ///
+    /// # Returns
+    /// * `Box<dyn EventEncoder>` - Encoder for each event
+    pub fn encoder(
+        self,
+        template: Option<EventTemplate>,
+    ) -> Box<dyn EventEncoder> {
+        match (self, template) {
+            (EventFormat::Text, _) | (EventFormat::Template, None) => {
+                Box::new(PlainText)
+            }
+            (EventFormat::Template, Some(template)) => Box::new(template),
+            (EventFormat::EcsJson, _) => Box::new(Line(to_ecs_json)),
+            (EventFormat::Cef, _) => Box::new(Line(to_cef)),
+            (EventFormat::Msgpack, _) => Box::new(BinaryFormat::Msgpack),
+            (EventFormat::Cbor, _) => Box::new(BinaryFormat::Cbor),
+            (EventFormat::Proto, _) => Box::new(BinaryFormat::Proto),
+        }
+    }
+}
+
+/// Human-readable line, followed by the stack frames if captured
+pub struct PlainText;
+
+impl EventEncoder for PlainText {
+    fn encode(&self, writer: &mut dyn Write, event: &FileEvent) -> Result<()> {
+        writeln!(writer, "{}", event)?;
+        if let Some(stack) = &event.stack {
+            for frame in &stack.frames {
+                writeln!(writer, "    at {}", frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One line per event, laid out by a function; the structured formats
+/// carry the stack in the line itself
+struct Line(fn(&FileEvent) -> String);
+
+impl EventEncoder for Line {
+    fn encode(&self, writer: &mut dyn Write, event: &FileEvent) -> Result<()> {
+        Ok(writeln!(writer, "{}", (self.0)(event))?)
+    }
+}
+
+impl fmt::Display for EventFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventFormat::Text => write!(f, "text"),
+            EventFormat::EcsJson => write!(f, "ecs-json"),
+            EventFormat::Cef => write!(f, "cef"),
+            EventFormat::Template => write!(f, "template"),
+            EventFormat::Msgpack => write!(f, "msgpack"),
+            EventFormat::Cbor => write!(f, "cbor"),
+            EventFormat::Proto => write!(f, "proto"),
+        }
+    }
+}
+
+/// Stable name of an action, used as `event.action` and the CEF
+/// signature ID
+///
+/// # Arguments
+/// * `action` - Action to name
+///
+/// # Returns
+/// * `String` - Name without the action's details (e.g. "chmod" rather
+///   than "chmod 0644")
+pub fn action_name(action: &FileAction) -> String {
+    let name = match action {
+        FileAction::Opened => "opened",
+        FileAction::AlreadyOpen => "already-open",
+        FileAction::Closed => "closed",
+        FileAction::ModeChanged { .. } => "chmod",
+        FileAction::OwnerChanged { .. } => "chown",
+        FileAction::Truncated { .. } => "truncate",
+        FileAction::Linked => "linked",
+        FileAction::Symlinked => "symlinked",
+        FileAction::XattrSet => "setxattr",
+        FileAction::XattrRemoved => "removexattr",
+        FileAction::Synced { kind } => return kind.to_string(),
+        FileAction::Read { .. } => "read",
+        FileAction::Written { .. } => "write",
+        FileAction::Locked { .. } => "lock",
+        FileAction::Unlocked => "unlock",
+        FileAction::Renamed => "renamed",
+        FileAction::AtomicSave => "atomic-save",
+    };
+    name.to_string()
+}
+
+/// Numeric severity shared by both formats, on CEF's 0-10 scale
+///
+/// Events that weren't classified (enrichment off) are 0.
+fn severity_level(severity: Option<Severity>) -> u8 {
+    match severity {
+        None => 0,
+        Some(Severity::Info) => 1,
+        Some(Severity::Notice) => 4,
+        Some(Severity::Warning) => 7,
+        Some(Severity::Critical) => 10,
+    }
+}
+
+/// ECS `event.type` of an action in the file category
+fn ecs_event_type(action: &FileAction) -> &'static str {
+    match action {
+        FileAction::Linked | FileAction::Symlinked => "creation",
+        action if action.is_write() => "change",
+        _ => "access",
+    }
+}
+
+/// Split a path into its directory and final component
+fn split_path(path: &str) -> (Option<&str>, &str) {
+    match path.rsplit_once('/') {
+        Some(("", name)) => (Some("/"), name),
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    }
+}
+
+/// Join JSON members into an object, or None if there are none
+fn json_object(members: Vec<String>) -> Option<String> {
+    (!members.is_empty()).then(|| format!("{{{}}}", members.join(",")))
+}
+
+/// Format an event as one ECS JSON object
+///
+/// # Arguments
+/// * `event` - Event to format
+///
+/// # Returns
+/// * `String` - JSON object without a trailing newline
+pub fn to_ecs_json(event: &FileEvent) -> String {
+    let mut members = vec![
+        format!(
+            "\"@timestamp\":{}",
+            json_string(
+                &event.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+            )
+        ),
+        format!("\"ecs\":{{\"version\":{}}}", json_string(ECS_VERSION)),
+    ];
+
+    let mut ev = vec![
+        "\"kind\":\"event\"".to_string(),
+        "\"category\":[\"file\"]".to_string(),
+        format!("\"type\":[\"{}\"]", ecs_event_type(&event.action)),
+        format!("\"action\":{}", json_string(&action_name(&event.action))),
+        "\"module\":\"fw\"".to_string(),
+    ];
+    if let Some(latency_ns) = event.open_latency_ns {
+        ev.push(format!("\"duration\":{}", latency_ns));
+    }
+    if event.severity.is_some() {
+        ev.push(format!("\"severity\":{}", severity_level(event.severity)));
+    }
+    members.push(format!("\"event\":{{{}}}", ev.join(",")));
+
+    let (dir, name) = split_path(&event.file_path);
+    let mut file = vec![
+        format!("\"path\":{}", json_string(&event.file_path)),
+        format!("\"name\":{}", json_string(name)),
+    ];
+    if let Some(dir) = dir {
+        file.push(format!("\"directory\":{}", json_string(dir)));
+    }
+    if let Some(ext) = name.rsplit_once('.').map(|(_, ext)| ext) {
+        file.push(format!("\"extension\":{}", json_string(ext)));
+    }
+    if let Some(file_type) = event.file_type {
+        // ECS names only these three; other types keep fw's name
+        let ecs_type = match file_type {
+            FileType::File => "file".to_string(),
+            FileType::Dir => "dir".to_string(),
+            FileType::Symlink => "symlink".to_string(),
+            other => other.to_string(),
+        };
+        file.push(format!("\"type\":{}", json_string(&ecs_type)));
+    }
+    if let Some(size) = event.file_size {
+        file.push(format!("\"size\":{}", size));
+    }
+    if let Some(modified) = event.file_modified {
+        file.push(format!(
+            "\"mtime\":{}",
+            json_string(&modified.to_rfc3339_opts(SecondsFormat::Millis, true))
+        ));
+    }
+    if let Some(file_id) = &event.file_id {
+        file.push(format!("\"inode\":\"{}\"", file_id.ino));
+        file.push(format!(
+            "\"device\":\"{}:{}\"",
+            file_id.major(),
+            file_id.minor()
+        ));
+    }
+    if let Some(source) = &event.link_source {
+        file.push(format!("\"target_path\":{}", json_string(source)));
+    }
+    if let Some(mount_point) = &event.mount_point {
+        file.push(format!("\"mount_point\":{}", json_string(mount_point)));
+    }
+    members.push(format!("\"file\":{{{}}}", file.join(",")));
+
+    members.push(format!(
+        "\"process\":{{\"pid\":{},\"name\":{}}}",
+        event.pid,
+        json_string(&event.program_name)
+    ));
+    let identity = |id: Option<u32>, name: &Option<String>| {
+        let mut members = Vec::new();
+        if let Some(id) = id {
+            members.push(format!("\"id\":\"{}\"", id));
+        }
+        if let Some(name) = name {
+            members.push(format!("\"name\":{}", json_string(name)));
+        }
+        json_object(members)
+    };
+    if let Some(user) = identity(event.uid, &event.user) {
+        members.push(format!("\"user\":{}", user));
+    }
+    if let Some(group) = identity(event.gid, &event.group) {
+        members.push(format!("\"group\":{}", group));
+    }
+    if !event.tags.is_empty() {
+        let labels: Vec<String> = event
+            .tags
+            .iter()
+            .map(|(key, value)| {
+                format!("{}:{}", json_string(key), json_string(value))
+            })
+            .collect();
+        members.push(format!("\"labels\":{{{}}}", labels.join(",")));
+    }
+
+    let mut fw = Vec::new();
+    if let Some(severity) = event.severity {
+        fw.push(format!("\"severity\":\"{}\"", severity));
+    }
+    if let Some(fd) = event.fd {
+        fw.push(format!("\"fd\":{}", fd));
+    }
+    if let Some(fs_type) = &event.fs_type {
+        fw.push(format!("\"fs_type\":{}", json_string(fs_type)));
+    }
+    if let Some(source) = &event.remote_source {
+        fw.push(format!("\"remote_source\":{}", json_string(source)));
+    }
+    if let Some(from) = &event.renamed_from {
+        fw.push(format!("\"renamed_from\":{}", json_string(from)));
+    }
+    if let Some(name) = &event.xattr_name {
+        fw.push(format!("\"xattr_name\":{}", json_string(name)));
+    }
+    if event.path_truncated {
+        fw.push("\"path_truncated\":true".to_string());
+    }
+    if let Some(stack) = event.stack.as_ref().filter(|s| !s.frames.is_empty()) {
+        fw.push(format!("\"stack\":{}", frames_json(stack)));
+    }
+    if let Some(fw) = json_object(fw) {
+        members.push(format!("\"fw\":{}", fw));
+    }
+    format!("{{{}}}", members.join(","))
+}
+
+/// Escape a CEF header field, where pipes and backslashes are special
+fn cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escape a CEF extension value, where equals signs, backslashes and line
+/// breaks are special
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Format an event as one CEF line
+///
+/// # Arguments
+/// * `event` - Event to format
+///
+/// # Returns
+/// * `String` - CEF record without a trailing newline
+pub fn to_cef(event: &FileEvent) -> String {
+    let action = action_name(&event.action);
+    let mut extension = vec![
+        format!("rt={}", event.timestamp.timestamp_millis()),
+        format!("act={}", cef_value(&action)),
+        format!("sproc={}", cef_value(&event.program_name)),
+        format!("spid={}", event.pid),
+    ];
+    if let Some(uid) = event.uid {
+        extension.push(format!("suid={}", uid));
+    }
+    if let Some(user) = &event.user {
+        extension.push(format!("suser={}", cef_value(user)));
+    }
+    let (_, name) = split_path(&event.file_path);
+    extension.push(format!("fname={}", cef_value(name)));
+    extension.push(format!("filePath={}", cef_value(&event.file_path)));
+    if let Some(file_type) = event.file_type {
+        extension.push(format!("fileType={}", file_type));
+    }
+    if let Some(size) = event.file_size {
+        extension.push(format!("fsize={}", size));
+    }
+    if let Some(modified) = event.file_modified {
+        extension.push(format!(
+            "fileModificationTime={}",
+            modified.timestamp_millis()
+        ));
+    }
+    if let Some(file_id) = &event.file_id {
+        extension.push(format!("fileId={}", file_id.ino));
+    }
+    if let Some(old) =
+        event.renamed_from.as_ref().or(event.link_source.as_ref())
+    {
+        extension.push(format!("oldFilePath={}", cef_value(old)));
+    }
+    let mut custom = Vec::new();
+    if let Some(fs_type) = &event.fs_type {
+        custom.push(("fsType", fs_type.clone()));
+    }
+    if let Some(severity) = event.severity {
+        custom.push(("severity", severity.to_string()));
+    }
+    if !event.tags.is_empty() {
+        let tags: Vec<String> = event
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        custom.push(("tags", tags.join(",")));
+    }
+    if let Some(name) = &event.xattr_name {
+        custom.push(("xattrName", name.clone()));
+    }
+    for (i, (label, value)) in custom.into_iter().enumerate() {
+        extension.push(format!("cs{}={}", i + 1, cef_value(&value)));
+        extension.push(format!("cs{}Label={}", i + 1, label));
+    }
+    if let Some(latency_ns) = event.open_latency_ns {
+        extension.push(format!("cn1={}", latency_ns));
+        extension.push("cn1Label=latencyNs".to_string());
+    }
+    format!(
+        "CEF:0|fw|file-watcher|{}|{}|{}|{}|{}",
+        cef_header(env!("CARGO_PKG_VERSION")),
+        cef_header(&action),
+        cef_header(&event.action.to_string()),
+        severity_level(event.severity),
+        extension.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileId;
+
+    fn event() -> FileEvent {
+        let mut event = FileEvent::new(
+            "/etc/app=1/db.conf".to_string(),
+            "vi".to_string(),
+            FileAction::ModeChanged { mode: 0o600 },
+            42,
+            SystemClock.now(),
+        )
+        .with_ids(1000, 100)
+        .with_file_id(FileId::from_raw(8 << 20 | 1, 77));
+        event.user = Some("alice".to_string());
+        event.severity = Some(Severity::Warning);
+        event.fs_type = Some("ext4".to_string());
+        event.tags.insert("team".to_string(), "ops".to_string());
+        event
+    }
+
+    #[test]
+    fn test_ecs_json_fields() {
+        let json = to_ecs_json(&event());
+        assert!(json.starts_with("{\"@timestamp\":\""));
+        for member in [
+            "\"type\":[\"change\"],\"action\":\"chmod\"",
+            "\"severity\":7}",
+            "\"path\":\"/etc/app=1/db.conf\",\"name\":\"db.conf\"",
+            "\"directory\":\"/etc/app=1\",\"extension\":\"conf\"",
+            "\"inode\":\"77\",\"device\":\"8:1\"",
+            "\"process\":{\"pid\":42,\"name\":\"vi\"}",
+            "\"user\":{\"id\":\"1000\",\"name\":\"alice\"}",
+            "\"group\":{\"id\":\"100\"}",
+            "\"labels\":{\"team\":\"ops\"}",
+            "\"fw\":{\"severity\":\"warning\",\"fs_type\":\"ext4\"}}",
+        ] {
+            assert!(json.contains(member), "{} in {}", member, json);
+        }
+    }
+
+    #[test]
+    fn test_cef_line() {
+        let cef = to_cef(&event());
+        let header = format!(
+            "CEF:0|fw|file-watcher|{}|chmod|chmod 0600|7|rt=",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(cef.starts_with(&header), "{}", cef);
+        assert!(cef.contains(
+            " act=chmod sproc=vi spid=42 suid=1000 suser=alice \
+             fname=db.conf filePath=/etc/app\\=1/db.conf fileId=77 \
+             cs1=ext4 cs1Label=fsType cs2=warning cs2Label=severity \
+             cs3=team\\=ops cs3Label=tags"
+        ));
+        assert!(!cef.contains('\n'));
+
+        let mut unclassified = event();
+        unclassified.severity = None;
+        unclassified.action = FileAction::Opened;
+        assert!(to_cef(&unclassified).contains("|opened|opened|0|"));
+    }
+
+    #[test]
+    fn test_encoders() {
+        let event = event();
+        let write = |format: EventFormat, template| {
+            let mut out = Vec::new();
+            format.encoder(template).encode(&mut out, &event).unwrap();
+            out
+        };
+        let text = String::from_utf8(write(EventFormat::Text, None)).unwrap();
+        assert_eq!(text, format!("{}\n", event));
+        assert_eq!(write(EventFormat::Template, None), text.as_bytes());
+        let template = EventTemplate::parse("{comm} {pid}").unwrap();
+        assert_eq!(write(EventFormat::Template, Some(template)), b"vi 42\n");
+        let ecs = write(EventFormat::EcsJson, None);
+        assert_eq!(ecs, format!("{}\n", to_ecs_json(&event)).as_bytes());
+
+        let frame = write(EventFormat::Msgpack, None);
+        let payload = BinaryFormat::Msgpack.encode(&event).unwrap();
+        assert_eq!(frame[..4], (payload.len() as u32).to_be_bytes());
+        assert_eq!(frame[4..], payload);
+    }
+}
@@ -0,0 +1,335 @@
+//! Baseline module
+//!
+//! Learns which directories each program touches and flags accesses
+//! outside that profile. `fw baseline learn` reads captures or recordings
+//! of normal activity (e.g. a week of `fw record`) and writes a profile
+//! mapping every program to the set of directories, cut off after a few
+//! levels, it accessed. `fw collect --baseline` loads the profile and
+//! reports an anomaly the first time a program accesses a directory it
+//! never did while learning, or a program that wasn't seen at all shows
+//! up.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::compression::read_capture;
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::report::CapturedEvent;
+use crate::stats::dir_prefix;
+
+/// Directory levels kept in a profile unless `--depth` says otherwise
+pub const DEFAULT_BASELINE_DEPTH: usize = 2;
+
+/// Version of the profile file format
+const PROFILE_VERSION: u32 = 1;
+
+/// Directories each program accessed while learning
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// File format version
+    version: u32,
+    /// Directory levels kept, e.g. 2 for "/var/log"
+    depth: usize,
+    /// Directory prefixes accessed, per program name
+    processes: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Access outside a profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The program never accessed anything while learning
+    NewProcess,
+    /// The program never accessed this directory while learning
+    NewPrefix(String),
+}
+
+impl Profile {
+    /// Create an empty profile
+    ///
+    /// # Arguments
+    /// * `depth` - Directory levels kept for each access
+    ///
+    /// # Returns
+    /// * `Profile` - Profile with no programs
+    pub fn new(depth: usize) -> Self {
+        Self {
+            version: PROFILE_VERSION,
+            depth,
+            processes: BTreeMap::new(),
+        }
+    }
+
+    /// Learn one access
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program
+    /// * `path` - File it accessed
+    pub fn learn(&mut self, program: &str, path: &str) {
+        let prefix = dir_prefix(path, self.depth);
+        self.processes
+            .entry(program.to_string())
+            .or_default()
+            .insert(prefix);
+    }
+
+    /// Check an access against the profile
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program
+    /// * `path` - File it accessed
+    ///
+    /// # Returns
+    /// * `Option<Anomaly>` - Why the access is out of profile, or None
+    pub fn check(&self, program: &str, path: &str) -> Option<Anomaly> {
+        let Some(prefixes) = self.processes.get(program) else {
+            return Some(Anomaly::NewProcess);
+        };
+        let prefix = dir_prefix(path, self.depth);
+        (!prefixes.contains(&prefix)).then_some(Anomaly::NewPrefix(prefix))
+    }
+
+    /// Number of programs and of directory prefixes in the profile
+    ///
+    /// # Returns
+    /// * `(usize, usize)` - Programs, and prefixes over all programs
+    pub fn size(&self) -> (usize, usize) {
+        let prefixes = self.processes.values().map(BTreeSet::len).sum();
+        (self.processes.len(), prefixes)
+    }
+
+    /// Load a profile written by `fw baseline learn`
+    ///
+    /// # Arguments
+    /// * `path` - Profile file
+    ///
+    /// # Returns
+    /// * `Result<Profile>` - Profile, or error if it can't be read
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let profile: Profile =
+            serde_json::from_str(&text).with_context(|| {
+                format!("Invalid baseline profile {}", path.display())
+            })?;
+        if profile.version != PROFILE_VERSION {
+            return Err(anyhow!(
+                "Baseline profile {} has version {}; this fw reads {}",
+                path.display(),
+                profile.version,
+                PROFILE_VERSION
+            ));
+        }
+        Ok(profile)
+    }
+
+    /// Write the profile as JSON
+    ///
+    /// # Arguments
+    /// * `path` - Destination file
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the file can't be written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = serde_json::to_string_pretty(self)
+            .context("Failed to encode baseline profile")?;
+        text.push('\n');
+        fs::write(path, text)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// How `fw collect --baseline` checks events
+#[derive(Debug, Clone)]
+pub struct BaselineConfig {
+    /// Profile events are checked against
+    pub profile: Arc<Profile>,
+}
+
+/// Sink that writes an alert for accesses outside a profile
+///
+/// Each program is reported once per new directory, so a program that
+/// starts using a new directory alerts once rather than on every event.
+pub struct AnomalySink<W> {
+    /// Profile events are checked against
+    profile: Arc<Profile>,
+    /// Destination for alert lines
+    writer: W,
+    /// Programs and directories already alerted on; an empty directory
+    /// stands for a program missing from the profile
+    alerted: HashSet<(String, String)>,
+}
+
+impl<W: Write + Send + 'static> AnomalySink<W> {
+    /// Create a sink checking events against a profile
+    ///
+    /// # Arguments
+    /// * `config` - Profile to check against
+    /// * `writer` - Destination for alert lines
+    ///
+    /// # Returns
+    /// * `AnomalySink<W>` - New sink
+    pub fn new(config: BaselineConfig, writer: W) -> Self {
+        Self {
+            profile: config.profile,
+            writer,
+            alerted: HashSet::new(),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for AnomalySink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let program = &event.program_name;
+        let (prefix, reason) =
+            match self.profile.check(program, &event.file_path) {
+                None => return Ok(()),
+                Some(Anomaly::NewProcess) => (
+                    String::new(),
+                    format!("{} is not in the baseline", program),
+                ),
+                Some(Anomaly::NewPrefix(prefix)) => {
+                    let reason = format!(
+                        "{} never accessed {} in the baseline",
+                        program, prefix
+                    );
+                    (prefix, reason)
+                }
+            };
+        if !self.alerted.insert((program.clone(), prefix)) {
+            return Ok(());
+        }
+        writeln!(
+            self.writer,
+            "{} | anomaly | {} ({}) | {} | {} | {}",
+            event.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+            program,
+            event.pid,
+            event.action,
+            event.file_path,
+            reason
+        )
+        .context("Failed to write anomaly")?;
+        self.writer.flush().context("Failed to flush anomalies")
+    }
+}
+
+/// Run `fw baseline learn`
+///
+/// # Arguments
+/// * `inputs` - Captures or recordings of normal activity
+/// * `output` - File to write the profile to
+/// * `depth` - Directory levels kept for each access
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_learn(
+    inputs: &[PathBuf],
+    output: &Path,
+    depth: usize,
+) -> Result<()> {
+    let mut profile = Profile::new(depth);
+    let mut events = 0u64;
+    for input in inputs {
+        let text = read_capture(input)?;
+        for event in text.lines().filter_map(CapturedEvent::parse) {
+            profile.learn(&event.program, &event.path);
+            events += 1;
+        }
+    }
+    if events == 0 {
+        return Err(anyhow!("No events to learn from"));
+    }
+    profile.save(output)?;
+    let (programs, prefixes) = profile.size();
+    println!(
+        "Learned {} programs and {} directories from {} events",
+        programs, prefixes, events
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn profile() -> Profile {
+        let mut profile = Profile::new(2);
+        profile.learn("nginx", "/etc/nginx/nginx.conf");
+        profile.learn("nginx", "/var/log/nginx/access.log");
+        profile.learn("nginx", "/etc/hosts");
+        profile
+    }
+
+    #[test]
+    fn test_profile_check() {
+        let profile = profile();
+        assert_eq!(profile.size(), (1, 3));
+        assert_eq!(profile.check("nginx", "/etc/nginx/mime.types"), None);
+        assert_eq!(profile.check("nginx", "/etc/resolv.conf"), None);
+        assert_eq!(
+            profile.check("nginx", "/etc/ssh/sshd_config"),
+            Some(Anomaly::NewPrefix("/etc/ssh".to_string()))
+        );
+        assert_eq!(
+            profile.check("curl", "/etc/hosts"),
+            Some(Anomaly::NewProcess)
+        );
+    }
+
+    #[test]
+    fn test_profile_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.json");
+        profile().save(&path).unwrap();
+        assert_eq!(Profile::load(&path).unwrap(), profile());
+
+        fs::write(&path, "{\"version\":9,\"depth\":2,\"processes\":{}}")
+            .unwrap();
+        assert!(Profile::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_anomaly_sink_alerts_once() {
+        let config = BaselineConfig {
+            profile: Arc::new(profile()),
+        };
+        let mut sink = AnomalySink::new(config, Vec::new());
+        for (program, path) in [
+            ("nginx", "/etc/nginx/nginx.conf"),
+            ("nginx", "/etc/ssh/sshd_config"),
+            ("nginx", "/etc/ssh/ssh_host_rsa_key"),
+            ("curl", "/etc/hosts"),
+            ("curl", "/tmp/out"),
+        ] {
+            let event = FileEvent::new(
+                path.to_string(),
+                program.to_string(),
+                FileAction::Opened,
+                7,
+                SystemClock.now(),
+            );
+            sink.write_event(&event).unwrap();
+        }
+        let alerts = String::from_utf8(sink.writer).unwrap();
+        let reasons: Vec<&str> = alerts
+            .lines()
+            .map(|line| line.rsplit(" | ").next().unwrap())
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "nginx never accessed /etc/ssh in the baseline",
+                "curl is not in the baseline",
+            ]
+        );
+        assert!(alerts.contains(" | anomaly | nginx (7) | opened | "));
+    }
+}
@@ -0,0 +1,402 @@
+//! Benchmark module
+//!
+//! Implements `fw bench`, which drives a synthetic open/close workload
+//! against temporary files while the monitor runs. Each measurement pass
+//! reports how many events the pipeline handled per second, how many of
+//! the generated opens were never seen, and how much CPU the monitoring
+//! side consumed.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use nix::sys::resource::{getrusage, UsageWho};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::ebpf_monitor::EbpfMonitor;
+use crate::file_event::FileEvent;
+use crate::filter::FilterSpec;
+use crate::monitor_backend::MonitorBackend;
+use crate::mount_table::MountTable;
+
+/// Extension given to every file created by the synthetic workload
+const BENCH_EXTENSION: &str = "fwbench";
+
+/// Number of distinct files the workload cycles through
+const BENCH_FILE_COUNT: u64 = 16;
+
+/// Extra time allowed after the workload ends for in-flight events
+const DRAIN_GRACE: Duration = Duration::from_millis(500);
+
+/// Results of a single benchmark pass
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// Name of the pass (e.g. "unfiltered")
+    pub label: &'static str,
+    /// Number of file opens performed by the workload
+    pub generated: u64,
+    /// Number of workload opens seen by the pipeline
+    pub received: u64,
+    /// Wall-clock duration of the pass
+    pub elapsed: Duration,
+    /// CPU time used by the monitor, excluding the workload thread
+    pub monitor_cpu: Duration,
+}
+
+impl BenchReport {
+    /// Events handled per second of wall-clock time
+    ///
+    /// # Returns
+    /// * `f64` - Received events divided by elapsed seconds
+    pub fn events_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.received as f64 / secs
+    }
+
+    /// Fraction of generated opens that were never received
+    ///
+    /// # Returns
+    /// * `f64` - Drop rate between 0.0 and 1.0
+    pub fn drop_rate(&self) -> f64 {
+        if self.generated == 0 {
+            return 0.0;
+        }
+        let dropped = self.generated.saturating_sub(self.received);
+        dropped as f64 / self.generated as f64
+    }
+
+    /// Monitor CPU time as a percentage of one core over the pass
+    ///
+    /// # Returns
+    /// * `f64` - CPU overhead percentage
+    pub fn cpu_overhead_percent(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.monitor_cpu.as_secs_f64() / secs * 100.0
+    }
+}
+
+impl fmt::Display for BenchReport {
+    /// Format the report as a single summary line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} | generated {:>8} | received {:>8} | {:>10.1} events/s \
+             | drop {:>5.1}% | cpu {:>5.1}%",
+            self.label,
+            self.generated,
+            self.received,
+            self.events_per_sec(),
+            self.drop_rate() * 100.0,
+            self.cpu_overhead_percent()
+        )
+    }
+}
+
+/// Counters reported by the workload thread
+struct WorkloadStats {
+    /// Number of file opens performed
+    opens: u64,
+    /// CPU time consumed by the workload thread itself
+    cpu_time: Duration,
+}
+
+/// Run the benchmark with and without extension filtering
+///
+/// # Arguments
+/// * `opens_per_sec` - Target workload rate
+/// * `duration_secs` - Length of each measurement pass in seconds
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_bench(opens_per_sec: u32, duration_secs: u64) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+    let dir = create_bench_dir()?;
+    let duration = Duration::from_secs(duration_secs);
+
+    eprintln!(
+        "Benchmarking {} opens/sec for {}s per pass in {}",
+        opens_per_sec,
+        duration_secs,
+        dir.display()
+    );
+    eprintln!("{}", "-".repeat(60));
+
+    let mounts = MountTable::load().context("Failed to load mount table")?;
+
+    let result = rt.block_on(async {
+        let passes = [
+            ("unfiltered", FilterSpec::default()),
+            (
+                "filtered",
+                FilterSpec {
+                    extensions: Some(vec![BENCH_EXTENSION.to_string()]),
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (label, filter) in passes {
+            let pass = BenchPass {
+                label,
+                dir: &dir,
+                filter: &filter,
+                mounts: &mounts,
+            };
+            let report = run_pass(pass, opens_per_sec, duration).await?;
+            eprintln!("{}", report);
+        }
+        Ok(())
+    });
+
+    // Always remove the scratch directory, even if a pass failed
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("Failed to remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+/// Settings for one measurement pass
+struct BenchPass<'a> {
+    /// Name of the pass for the report
+    label: &'static str,
+    /// Directory the workload writes into
+    dir: &'a Path,
+    /// Filter applied to events
+    filter: &'a FilterSpec,
+    /// Mount table used to annotate events
+    mounts: &'a MountTable,
+}
+
+/// Run one measurement pass
+///
+/// # Arguments
+/// * `pass` - Pass settings
+/// * `opens_per_sec` - Target workload rate
+/// * `duration` - How long the workload runs
+///
+/// # Returns
+/// * `Result<BenchReport>` - Measurements for this pass
+async fn run_pass(
+    pass: BenchPass<'_>,
+    opens_per_sec: u32,
+    duration: Duration,
+) -> Result<BenchReport> {
+    info!("Starting benchmark pass: {}", pass.label);
+
+    let mut monitor =
+        EbpfMonitor::new().context("Failed to initialize eBPF monitor")?;
+    let mut event_receiver = monitor
+        .start_monitoring()
+        .await
+        .context("Failed to start eBPF monitoring")?;
+
+    let cpu_before = cpu_time(UsageWho::RUSAGE_SELF)?;
+    let start = Instant::now();
+
+    // Drive the workload on a blocking thread so it can't starve the runtime
+    let workload_dir = pass.dir.to_path_buf();
+    let workload = tokio::task::spawn_blocking(move || {
+        run_workload(&workload_dir, opens_per_sec, duration)
+    });
+
+    let deadline = tokio::time::sleep(duration + DRAIN_GRACE);
+    tokio::pin!(deadline);
+
+    let mut received = 0;
+    loop {
+        tokio::select! {
+            event_result = event_receiver.recv() => match event_result {
+                Some(event) => {
+                    if handle_bench_event(event, &pass) {
+                        received += 1;
+                    }
+                }
+                None => break,
+            },
+            _ = &mut deadline => break,
+        }
+    }
+
+    let elapsed = start.elapsed();
+    let cpu_used = cpu_time(UsageWho::RUSAGE_SELF)? - cpu_before;
+    let stats = workload
+        .await
+        .map_err(|e| anyhow!("Workload thread failed: {}", e))??;
+
+    monitor
+        .stop_monitoring()
+        .await
+        .context("Failed to stop eBPF monitoring")?;
+
+    Ok(BenchReport {
+        label: pass.label,
+        generated: stats.opens,
+        received,
+        elapsed,
+        monitor_cpu: cpu_used.saturating_sub(stats.cpu_time),
+    })
+}
+
+/// Push one event through the annotate/filter/format pipeline
+///
+/// The event is formatted exactly as `collect` would format it, but the
+/// output is discarded so the terminal does not dominate the measurement.
+///
+/// # Arguments
+/// * `event` - Event received from the monitor
+/// * `pass` - Settings of the current pass
+///
+/// # Returns
+/// * `bool` - True if the event passed the filter and came from the
+///   workload
+fn handle_bench_event(mut event: FileEvent, pass: &BenchPass<'_>) -> bool {
+    pass.mounts.annotate(&mut event);
+    if !pass.filter.matches(&event) {
+        return false;
+    }
+    std::hint::black_box(event.to_string());
+    Path::new(&event.file_path).starts_with(pass.dir)
+}
+
+/// Open and close workload files at a steady rate
+///
+/// # Arguments
+/// * `dir` - Directory to create files in
+/// * `opens_per_sec` - Target open rate
+/// * `duration` - How long to keep generating opens
+///
+/// # Returns
+/// * `Result<WorkloadStats>` - Number of opens and CPU time used
+fn run_workload(
+    dir: &Path,
+    opens_per_sec: u32,
+    duration: Duration,
+) -> Result<WorkloadStats> {
+    let interval = Duration::from_secs(1) / opens_per_sec;
+    let cpu_before = cpu_time(UsageWho::RUSAGE_THREAD)?;
+    let start = Instant::now();
+    let mut opens: u64 = 0;
+
+    while start.elapsed() < duration {
+        let name =
+            format!("file_{}.{}", opens % BENCH_FILE_COUNT, BENCH_EXTENSION);
+        // Dropping the handle immediately closes the file
+        File::create(dir.join(name))
+            .context("Failed to create workload file")?;
+        opens += 1;
+
+        // Sleep until the next open is due to hold the target rate
+        let due = interval.saturating_mul(opens as u32);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    Ok(WorkloadStats {
+        opens,
+        cpu_time: cpu_time(UsageWho::RUSAGE_THREAD)? - cpu_before,
+    })
+}
+
+/// Create a fresh scratch directory for the workload
+///
+/// # Returns
+/// * `Result<PathBuf>` - Path of the created directory
+fn create_bench_dir() -> Result<PathBuf> {
+    let dir =
+        std::env::temp_dir().join(format!("fw-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Get the user plus system CPU time consumed so far
+///
+/// # Arguments
+/// * `who` - Whether to measure the whole process or the calling thread
+///
+/// # Returns
+/// * `Result<Duration>` - Total CPU time
+fn cpu_time(who: UsageWho) -> Result<Duration> {
+    let usage = getrusage(who).context("Failed to read resource usage")?;
+    let to_duration = |tv: nix::sys::time::TimeVal| {
+        Duration::new(tv.tv_sec() as u64, tv.tv_usec() as u32 * 1000)
+    };
+    Ok(to_duration(usage.user_time()) + to_duration(usage.system_time()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    /// Build a report with the given counters over one second
+    fn report(generated: u64, received: u64, cpu_ms: u64) -> BenchReport {
+        BenchReport {
+            label: "test",
+            generated,
+            received,
+            elapsed: Duration::from_secs(1),
+            monitor_cpu: Duration::from_millis(cpu_ms),
+        }
+    }
+
+    #[test]
+    fn test_report_metrics() {
+        let report = report(1000, 900, 50);
+        assert_eq!(report.events_per_sec(), 900.0);
+        assert!((report.drop_rate() - 0.1).abs() < f64::EPSILON);
+        assert!((report.cpu_overhead_percent() - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_report_handles_empty_pass() {
+        let report = report(0, 0, 0);
+        assert_eq!(report.drop_rate(), 0.0);
+        assert!(format!("{}", report).contains("test"));
+    }
+
+    #[test]
+    fn test_handle_bench_event_counts_workload_files() {
+        let dir = Path::new("/tmp/fw-bench-1");
+        let event = FileEvent::new(
+            "/tmp/fw-bench-1/file_0.fwbench".to_string(),
+            "fw".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        let other = FileEvent::new(
+            "/etc/passwd".to_string(),
+            "cat".to_string(),
+            FileAction::Opened,
+            2,
+            SystemClock.now(),
+        );
+        let unfiltered = FilterSpec::default();
+        let filtered = FilterSpec {
+            extensions: Some(vec![BENCH_EXTENSION.to_string()]),
+            ..Default::default()
+        };
+        let mounts = MountTable::empty();
+        let pass = |filter| BenchPass {
+            label: "test",
+            dir,
+            filter,
+            mounts: &mounts,
+        };
+
+        assert!(handle_bench_event(event.clone(), &pass(&unfiltered)));
+        assert!(handle_bench_event(event, &pass(&filtered)));
+        assert!(!handle_bench_event(other.clone(), &pass(&unfiltered)));
+        assert!(!handle_bench_event(other, &pass(&filtered)));
+    }
+}
@@ -0,0 +1,250 @@
+//! Binary format module
+//!
+//! Writes events as MessagePack, CBOR or protocol buffers (`fw collect
+//! --format msgpack`, `cbor` or `proto`) for pipelines where encoding
+//! JSON lines costs too much, and implements `fw decode`, which turns
+//! such a stream back into JSON lines.
+//!
+//! A stream is a sequence of frames, one per event, each a 4-byte
+//! big-endian payload length followed by the payload. Protocol buffer
+//! payloads are `FileEvent` messages of `fw/proto/event.proto` (see
+//! [`crate::proto_format`]). MessagePack and CBOR payloads are a map with
+//! the same keys and values as the JSON `fw forward` sends:
+//!
+//! ```text
+//! file_path        string    path, escaped if not valid UTF-8
+//! raw_path         bytes[]   exact path bytes when not UTF-8, else nil
+//! program_name     string    process name
+//! action           string    unit actions, e.g. "Opened"; others a
+//!                  or map    one-entry map, e.g. {"ModeChanged":
+//!                            {"mode": 420}}
+//! timestamp        string    RFC 3339, UTC
+//! pid              uint
+//! uid, gid         uint/nil
+//! user, group      string/nil
+//! path_truncated   bool
+//! mount_point      string/nil
+//! fs_type          string/nil
+//! remote_source    string/nil
+//! overlay_layer    string/nil  "Upper", or map/nil {"Lower": uint}
+//! open_latency_ns  uint/nil
+//! file_id          map/nil   {"dev": uint, "ino": uint}
+//! fd               int/nil
+//! link_source      string/nil
+//! renamed_from     string/nil
+//! open_flags       uint/nil
+//! xattr_name       string/nil
+//! tags             map       string keys and values
+//! severity         string/nil  "Info", "Notice", "Warning", "Critical"
+//! rule             string/nil
+//! file_type        string/nil  "File", "Dir", "Device", "Socket",
+//!                              "Fifo" or "Symlink"
+//! file_size        uint/nil
+//! file_modified    string/nil  RFC 3339, UTC
+//! ```
+//!
+//! Readers should ignore keys they don't know, since later versions may
+//! add some. Stacks are not encoded.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, BufRead, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use crate::compression::open_capture;
+use crate::fanout::EventEncoder;
+use crate::file_event::FileEvent;
+use crate::proto_format;
+
+/// Largest payload a frame may announce; longer ones mean the stream is
+/// not what `--from` says it is
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Binary encodings of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BinaryFormat {
+    /// MessagePack, with field names as map keys
+    Msgpack,
+    /// CBOR (RFC 8949)
+    Cbor,
+    /// Protocol buffers, `FileEvent` messages of `fw/proto/event.proto`
+    Proto,
+}
+
+impl BinaryFormat {
+    /// Encode an event as one frame payload
+    ///
+    /// # Arguments
+    /// * `event` - Event to encode
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>>` - Payload, without the length prefix
+    pub fn encode(self, event: &FileEvent) -> Result<Vec<u8>> {
+        match self {
+            BinaryFormat::Msgpack => rmp_serde::to_vec_named(event)
+                .context("Failed to encode event as MessagePack"),
+            BinaryFormat::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(event, &mut payload)
+                    .context("Failed to encode event as CBOR")?;
+                Ok(payload)
+            }
+            BinaryFormat::Proto => proto_format::encode(event),
+        }
+    }
+
+    /// Convert a frame payload to a JSON object
+    ///
+    /// # Arguments
+    /// * `payload` - Payload of one frame
+    ///
+    /// # Returns
+    /// * `Result<serde_json::Value>` - Decoded value, or error if the
+    ///   payload isn't valid in this format
+    pub fn to_json(self, payload: &[u8]) -> Result<serde_json::Value> {
+        match self {
+            BinaryFormat::Msgpack => rmp_serde::from_slice(payload)
+                .context("Invalid MessagePack frame"),
+            BinaryFormat::Cbor => {
+                ciborium::from_reader(payload).context("Invalid CBOR frame")
+            }
+            BinaryFormat::Proto => proto_format::to_json(payload),
+        }
+    }
+}
+
+impl EventEncoder for BinaryFormat {
+    fn encode(&self, writer: &mut dyn Write, event: &FileEvent) -> Result<()> {
+        Ok(write_frame(writer, &BinaryFormat::encode(*self, event)?)?)
+    }
+}
+
+/// Write one length-prefixed frame
+///
+/// # Arguments
+/// * `writer` - Destination of the stream
+/// * `payload` - Encoded event
+///
+/// # Returns
+/// * `io::Result<()>` - Error if the frame couldn't be written
+pub fn write_frame(writer: &mut dyn Write, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(ErrorKind::InvalidInput, "frame too big")
+    })?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Read the next length-prefixed frame
+///
+/// # Arguments
+/// * `reader` - Source of the stream
+///
+/// # Returns
+/// * `Result<Option<Vec<u8>>>` - Payload, None at the end of the
+///   stream, or error if it ends inside a frame
+pub fn read_frame(reader: &mut dyn Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(anyhow!("Stream ends inside a frame")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("Failed to read frame"),
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(anyhow!(
+            "Frame of {} bytes is over the {} byte limit; wrong --from?",
+            len,
+            MAX_FRAME
+        ));
+    }
+    let mut payload = vec![0; len];
+    reader
+        .read_exact(&mut payload)
+        .context("Stream ends inside a frame")?;
+    Ok(Some(payload))
+}
+
+/// Run `fw decode`
+///
+/// # Arguments
+/// * `inputs` - Streams to decode, plain or compressed; stdin if empty
+/// * `format` - Encoding of the frames
+///
+/// # Returns
+/// * `Result<()>` - Success, or error at the first bad frame
+pub fn run_decode(inputs: &[PathBuf], format: BinaryFormat) -> Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut decode = |reader: &mut dyn BufRead, name: &str| -> Result<()> {
+        let mut frames = 0;
+        // Name the frame in the message itself, which is all that's shown
+        let at = |e: anyhow::Error, frame| {
+            anyhow!("{}, frame {}: {:#}", name, frame, e)
+        };
+        while let Some(payload) =
+            read_frame(reader).map_err(|e| at(e, frames + 1))?
+        {
+            frames += 1;
+            let value = format.to_json(&payload).map_err(|e| at(e, frames))?;
+            writeln!(out, "{}", value).context("Failed to write JSON")?;
+        }
+        Ok(())
+    };
+    if inputs.is_empty() {
+        decode(&mut io::stdin().lock(), "stdin")?;
+    }
+    for input in inputs {
+        decode(&mut open_capture(input)?, &input.display().to_string())?;
+    }
+    out.flush().context("Failed to write JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use crate::severity::Severity;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "vipw".to_string(),
+            FileAction::ModeChanged { mode: 0o600 },
+            42,
+            SystemClock.now(),
+        );
+        event.severity = Some(Severity::Critical);
+        event.tags.insert("team".to_string(), "infra".to_string());
+        let json = serde_json::to_value(&event).unwrap();
+
+        for format in [BinaryFormat::Msgpack, BinaryFormat::Cbor] {
+            let mut stream = Vec::new();
+            for _ in 0..2 {
+                write_frame(&mut stream, &format.encode(&event).unwrap())
+                    .unwrap();
+            }
+            let mut reader = stream.as_slice();
+            for _ in 0..2 {
+                let payload = read_frame(&mut reader).unwrap().unwrap();
+                assert_eq!(format.to_json(&payload).unwrap(), json);
+            }
+            assert!(read_frame(&mut reader).unwrap().is_none());
+
+            // Cut short inside the second frame, and inside its length
+            let half = stream.len() / 2;
+            for cut in [stream.len() - 1, half + 2] {
+                let mut reader = &stream[..cut];
+                read_frame(&mut reader).unwrap();
+                assert!(read_frame(&mut reader).is_err());
+            }
+        }
+        let garbage = [0xff, 0xff, 0xff, 0xff];
+        assert!(read_frame(&mut garbage.as_slice()).is_err());
+    }
+}
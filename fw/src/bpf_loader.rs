@@ -0,0 +1,689 @@
+//! BPF loader module
+//!
+//! Loads the programs and maps of a [`BpfObject`] into the kernel and
+//! attaches and detaches individual probes. The monitor only drives the
+//! loaded object through [`LoadedProbes`], so the probe changes it makes
+//! can be checked without a kernel.
+
+use anyhow::Result;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+use crate::bpf_object::BpfObject;
+use crate::health::Health;
+use crate::kernel_agg::KernelCounts;
+use crate::pinning::PinDir;
+use crate::probes::ProbeSpec;
+use fw_common::{StackCriterion, MAX_COMM_LEN, MAX_PATH_LEN};
+
+/// Why a program couldn't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The kernel's verifier rejected the program
+    Rejected {
+        /// Error the load returned
+        error: io::Error,
+        /// Verifier log; empty if the kernel didn't write one
+        log: String,
+    },
+    /// The program is missing or couldn't be loaded for another reason
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for LoadError {
+    fn from(error: anyhow::Error) -> Self {
+        LoadError::Failed(error)
+    }
+}
+
+/// Programs and maps loaded into the kernel
+pub trait LoadedProbes: Send {
+    /// Load a program, if it isn't loaded yet
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program in the eBPF object
+    ///
+    /// # Returns
+    /// * `Result<(), LoadError>` - Error if the program is missing or
+    ///   the kernel rejected it
+    fn load(&mut self, program: &str) -> Result<(), LoadError>;
+
+    /// Attach a loaded program as one probe
+    ///
+    /// Attaching a probe that is already attached does nothing.
+    ///
+    /// # Arguments
+    /// * `probe` - Probe to attach
+    /// * `symbol` - Kernel symbol or tracepoint name to attach to
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the kernel refused the attachment
+    fn attach(&mut self, probe: &ProbeSpec, symbol: &str) -> Result<()>;
+
+    /// Detach a probe, removing its link
+    ///
+    /// Detaching a probe that isn't attached does nothing.
+    ///
+    /// # Arguments
+    /// * `probe` - Probe to detach
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the link couldn't be removed
+    fn detach(&mut self, probe: &ProbeSpec) -> Result<()>;
+
+    /// Point a tail call slot at a loaded program
+    ///
+    /// # Arguments
+    /// * `slot` - `TAIL_CALL_*` index in TAIL_CALLS
+    /// * `program` - Name of the program to jump to
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the program isn't loaded or the slot
+    ///   couldn't be set
+    fn set_tail_call(&mut self, slot: u32, program: &str) -> Result<()>;
+
+    /// Forward every record the probes send
+    ///
+    /// Reading goes on in the background until `records` is closed;
+    /// records the kernel had to drop are counted in `health`.
+    ///
+    /// # Arguments
+    /// * `records` - Receives the raw bytes of each record
+    /// * `health` - Counts the records lost
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the event buffers couldn't be opened
+    fn read_events(
+        &mut self,
+        records: mpsc::Sender<Vec<u8>>,
+        health: &Health,
+    ) -> Result<()>;
+
+    /// Replace the users the probes report
+    ///
+    /// # Arguments
+    /// * `entries` - Uid and `UID_FILTER_*` entry for UID_FILTER
+    /// * `includes` - Value of UID_FILTER_ACTIVE: only included uids are
+    ///   reported
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a map couldn't be written
+    fn set_uid_filter(
+        &mut self,
+        entries: &[(u32, u8)],
+        includes: bool,
+    ) -> Result<()>;
+
+    /// Replace the process names the probes filter on
+    ///
+    /// # Arguments
+    /// * `names` - Null-padded names for COMM_FILTER
+    /// * `mode` - `COMM_FILTER_*` mode for COMM_FILTER_MODE
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a map couldn't be written
+    fn set_comm_filter(
+        &mut self,
+        names: &[[u8; MAX_COMM_LEN]],
+        mode: u32,
+    ) -> Result<()>;
+
+    /// Record the names of processes started before the probes
+    ///
+    /// # Arguments
+    /// * `comms` - Process ID and null-padded name for LEADER_COMMS
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_leader_comms(
+        &mut self,
+        comms: &[(u32, [u8; MAX_COMM_LEN])],
+    ) -> Result<()>;
+
+    /// Replace the decoys whose opens bypass the filters
+    ///
+    /// # Arguments
+    /// * `paths` - Null-padded paths for TRIPWIRES
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_tripwires(&mut self, paths: &[[u8; MAX_PATH_LEN]]) -> Result<()>;
+
+    /// Drop opens of absolute paths under /proc, /sys and /dev
+    ///
+    /// # Arguments
+    /// * `excluded` - Whether PSEUDO_FS_EXCLUDED is set
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_pseudo_fs_excluded(&mut self, excluded: bool) -> Result<()>;
+
+    /// Count events in AGG_COUNTS instead of sending them
+    ///
+    /// # Arguments
+    /// * `active` - Whether AGG_ACTIVE is set
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_kernel_aggregation(&mut self, active: bool) -> Result<()>;
+
+    /// Read and delete the AGG_COUNTS entries, then read and zero
+    /// AGG_OVERFLOW
+    ///
+    /// # Returns
+    /// * `Result<KernelCounts>` - Counts summed over CPUs, or error if a
+    ///   map couldn't be read or reset
+    fn drain_counts(&mut self) -> Result<KernelCounts>;
+
+    /// Send only 1 in `rate` events
+    ///
+    /// # Arguments
+    /// * `rate` - Value of SAMPLE_RATE; 1 sends every event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_sample_rate(&mut self, rate: u32) -> Result<()>;
+
+    /// Replace the events sent whatever the sampling rate
+    ///
+    /// # Arguments
+    /// * `criteria` - Entries for SAMPLE_EXEMPT, at most
+    ///   `MAX_SAMPLE_EXEMPTIONS`
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a map couldn't be written
+    fn set_sample_exemptions(
+        &mut self,
+        criteria: &[StackCriterion],
+    ) -> Result<()>;
+
+    /// Choose the stacks recorded with each event
+    ///
+    /// # Arguments
+    /// * `flags` - `STACK_*` flags for STACK_MODE; 0 records none
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be written
+    fn set_stack_mode(&mut self, flags: u32) -> Result<()>;
+
+    /// Replace the events whose stacks are recorded
+    ///
+    /// # Arguments
+    /// * `criteria` - Entries for STACK_CRITERIA, at most
+    ///   `MAX_STACK_CRITERIA`; empty for every event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a map couldn't be written
+    fn set_stack_criteria(&mut self, criteria: &[StackCriterion])
+        -> Result<()>;
+
+    /// Read a recorded stack and remove it from STACKS
+    ///
+    /// # Arguments
+    /// * `id` - Stack id from an event
+    ///
+    /// # Returns
+    /// * `Result<Vec<u64>>` - Addresses, innermost first, or error if
+    ///   there is no stack by that id
+    fn take_stack(&mut self, id: u32) -> Result<Vec<u64>>;
+}
+
+/// Loaded probes shared by the monitor and its background tasks
+pub type SharedProbes = Arc<Mutex<Box<dyn LoadedProbes>>>;
+
+/// Lock the loaded probes, even if a holder panicked
+pub fn lock(probes: &SharedProbes) -> MutexGuard<'_, Box<dyn LoadedProbes>> {
+    probes.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Load an object's programs and maps into the kernel
+///
+/// Programs are only loaded once a probe or tail call needs them. The
+/// state maps (`PINNED_MAPS`) are pinned by name in the object: those
+/// already pinned in `pins` are reopened, so a restarted fw carries on
+/// with the descriptors a crashed one tracked, and the rest are created
+/// and pinned there. Without `pins` they are pinned in a private
+/// directory that is removed once loaded, so they start empty and leave
+/// nothing behind.
+///
+/// # Arguments
+/// * `object` - Object to load
+/// * `pins` - Claimed pin directory, or None to keep nothing pinned
+///
+/// # Returns
+/// * `Result<SharedProbes>` - Loaded object, or error if the kernel
+///   refused its maps
+#[cfg(feature = "ebpf")]
+pub fn load(object: &BpfObject, pins: Option<&PinDir>) -> Result<SharedProbes> {
+    let probes = match pins {
+        Some(pins) => aya_probes::AyaProbes::load(object, pins)?,
+        None => {
+            let private = PinDir::for_current_process();
+            private.claim(false)?;
+            let loaded = aya_probes::AyaProbes::load(object, &private);
+            private.cleanup(true)?;
+            loaded?
+        }
+    };
+    Ok(Arc::new(Mutex::new(Box::new(probes))))
+}
+
+/// Load an object's programs and maps into the kernel
+///
+/// # Arguments
+/// * `object` - Object to load
+/// * `pins` - Claimed pin directory, or None to keep nothing pinned
+///
+/// # Returns
+/// * `Result<SharedProbes>` - Always an error without the `ebpf` feature
+#[cfg(not(feature = "ebpf"))]
+pub fn load(
+    object: &BpfObject,
+    _pins: Option<&PinDir>,
+) -> Result<SharedProbes> {
+    Err(anyhow::anyhow!(
+        "Can't load {}: fw was built without the ebpf feature",
+        object
+    ))
+}
+
+#[cfg(feature = "ebpf")]
+mod aya_probes {
+    use anyhow::{anyhow, Context, Result};
+    use aya::maps::{
+        Array, AsyncPerfEventArray, HashMap as BpfHashMap, Map, MapData,
+        PerCpuArray, PerCpuHashMap, PerCpuValues, ProgramArray, StackTraceMap,
+    };
+    use aya::programs::kprobe::KProbeLinkId;
+    use aya::programs::trace_point::TracePointLinkId;
+    use aya::programs::{Program, ProgramError};
+    use aya::util::online_cpus;
+    use aya::{Ebpf, EbpfLoader, Pod};
+    use bytes::BytesMut;
+    use log::warn;
+    use std::collections::{HashMap, HashSet};
+    use std::hash::Hash;
+    use tokio::sync::mpsc;
+
+    use super::{LoadError, LoadedProbes};
+    use crate::bpf_object::BpfObject;
+    use crate::health::Health;
+    use crate::kernel_agg::KernelCounts;
+    use crate::pinning::PinDir;
+    use crate::probes::{ProbeKind, ProbeSpec};
+    use fw_common::{
+        AggKey, FileEvent as RawFileEvent, StackCriterion, MAX_COMM_LEN,
+        MAX_PATH_LEN,
+    };
+
+    /// Records read from a perf buffer at once
+    const RECORDS_PER_READ: usize = 16;
+
+    /// Link of one attached probe
+    enum Link {
+        /// Kprobe or kretprobe attachment
+        KProbe(KProbeLinkId),
+        /// Tracepoint attachment
+        TracePoint(TracePointLinkId),
+    }
+
+    /// Object loaded with aya
+    pub(super) struct AyaProbes {
+        /// Programs and maps
+        ebpf: Ebpf,
+        /// Programs loaded so far
+        loaded: HashSet<String>,
+        /// Link of every attached probe
+        links: HashMap<ProbeSpec, Link>,
+    }
+
+    impl AyaProbes {
+        /// Load an object's maps, leaving its programs to be loaded
+        ///
+        /// # Arguments
+        /// * `object` - Object to load
+        /// * `pins` - Directory the maps pinned by name are reopened
+        ///   from (with `MapData::from_pin`) or pinned into
+        ///
+        /// # Returns
+        /// * `Result<AyaProbes>` - Loaded object, or error if the kernel
+        ///   refused its maps
+        pub(super) fn load(object: &BpfObject, pins: &PinDir) -> Result<Self> {
+            let ebpf = EbpfLoader::new()
+                .map_pin_path(pins.root().join("maps"))
+                .load(object.bytes())
+                .with_context(|| format!("Failed to load {}", object))?;
+            Ok(Self {
+                ebpf,
+                loaded: HashSet::new(),
+                links: HashMap::new(),
+            })
+        }
+
+        /// Look up a program of the object
+        ///
+        /// # Arguments
+        /// * `name` - Name of the program
+        ///
+        /// # Returns
+        /// * `Result<&mut Program>` - Program, or error if the object
+        ///   has none by that name
+        fn program(&mut self, name: &str) -> Result<&mut Program> {
+            self.ebpf.program_mut(name).ok_or_else(|| {
+                anyhow!("No program {} in the eBPF object", name)
+            })
+        }
+
+        /// Look up a map of the object
+        ///
+        /// # Arguments
+        /// * `name` - Name of the map
+        ///
+        /// # Returns
+        /// * `Result<&mut Map>` - Map, or error if the object has none by
+        ///   that name
+        fn map(&mut self, name: &str) -> Result<&mut Map> {
+            self.ebpf
+                .map_mut(name)
+                .ok_or_else(|| anyhow!("No map {} in the eBPF object", name))
+        }
+    }
+
+    /// Replace the contents of a hash map
+    ///
+    /// Keys not among the entries are removed first, so entries left by
+    /// an earlier run in a pinned map don't linger.
+    ///
+    /// # Arguments
+    /// * `map` - Map to write
+    /// * `entries` - Keys and values the map should hold
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the map couldn't be read or written
+    fn replace<K: Pod + Eq + Hash, V: Pod>(
+        map: &mut BpfHashMap<&mut MapData, K, V>,
+        entries: &[(K, V)],
+    ) -> Result<()> {
+        let wanted: HashSet<K> = entries.iter().map(|(key, _)| *key).collect();
+        let stale = map
+            .keys()
+            .filter(|key| !key.as_ref().is_ok_and(|key| wanted.contains(key)))
+            .collect::<Result<Vec<K>, _>>()?;
+        for key in &stale {
+            map.remove(key)?;
+        }
+        for (key, value) in entries {
+            map.insert(key, value, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Write criteria to the start of an array map and their number to
+    /// the count map the probes bound it with
+    ///
+    /// # Arguments
+    /// * `probes` - Loaded object holding the maps
+    /// * `array` - Name of the array map
+    /// * `count` - Name of the map whose index 0 holds the number used
+    /// * `criteria` - Entries to write
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a map couldn't be written
+    fn fill(
+        probes: &mut AyaProbes,
+        array: &str,
+        count: &str,
+        criteria: &[StackCriterion],
+    ) -> Result<()> {
+        let mut entries: Array<_, StackCriterion> =
+            Array::try_from(probes.map(array)?)?;
+        for (index, criterion) in (0..).zip(criteria) {
+            entries.set(index, criterion, 0)?;
+        }
+        let mut used = Array::try_from(probes.map(count)?)?;
+        used.set(0, criteria.len() as u32, 0)?;
+        Ok(())
+    }
+
+    impl LoadedProbes for AyaProbes {
+        fn load(&mut self, program: &str) -> Result<(), LoadError> {
+            if self.loaded.contains(program) {
+                return Ok(());
+            }
+            let loaded = match self.program(program)? {
+                Program::KProbe(kprobe) => kprobe.load(),
+                Program::TracePoint(tracepoint) => tracepoint.load(),
+                _ => return Err(anyhow!("{} is not a probe", program).into()),
+            };
+            match loaded {
+                Ok(()) => {}
+                Err(ProgramError::LoadError {
+                    io_error,
+                    verifier_log,
+                }) => {
+                    return Err(LoadError::Rejected {
+                        error: io_error,
+                        log: verifier_log.to_string(),
+                    })
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("Failed to load {}", program))
+                        .into())
+                }
+            }
+            self.loaded.insert(program.to_string());
+            Ok(())
+        }
+
+        fn attach(&mut self, probe: &ProbeSpec, symbol: &str) -> Result<()> {
+            if self.links.contains_key(probe) {
+                return Ok(());
+            }
+            let link = match (probe.kind, self.program(probe.program)?) {
+                (
+                    ProbeKind::Kprobe | ProbeKind::Kretprobe,
+                    Program::KProbe(kprobe),
+                ) => kprobe.attach(symbol, 0).map(Link::KProbe),
+                (
+                    ProbeKind::Tracepoint { category },
+                    Program::TracePoint(tracepoint),
+                ) => tracepoint.attach(category, symbol).map(Link::TracePoint),
+                _ => {
+                    return Err(anyhow!("{} has the wrong program type", probe))
+                }
+            }
+            .with_context(|| format!("Failed to attach {}", probe))?;
+            self.links.insert(*probe, link);
+            Ok(())
+        }
+
+        fn detach(&mut self, probe: &ProbeSpec) -> Result<()> {
+            let Some(link) = self.links.remove(probe) else {
+                return Ok(());
+            };
+            match (link, self.program(probe.program)?) {
+                (Link::KProbe(id), Program::KProbe(kprobe)) => {
+                    kprobe.detach(id)
+                }
+                (Link::TracePoint(id), Program::TracePoint(tracepoint)) => {
+                    tracepoint.detach(id)
+                }
+                _ => {
+                    return Err(anyhow!("{} has the wrong program type", probe))
+                }
+            }
+            .with_context(|| format!("Failed to detach {}", probe))
+        }
+
+        fn set_tail_call(&mut self, slot: u32, program: &str) -> Result<()> {
+            let fd = self.program(program)?.fd()?.try_clone()?;
+            let mut calls = ProgramArray::try_from(self.map("TAIL_CALLS")?)?;
+            calls.set(slot, &fd, 0).with_context(|| {
+                format!("Failed to set tail call {} to {}", slot, program)
+            })
+        }
+
+        fn read_events(
+            &mut self,
+            records: mpsc::Sender<Vec<u8>>,
+            health: &Health,
+        ) -> Result<()> {
+            let map = self
+                .ebpf
+                .take_map("EVENTS")
+                .ok_or_else(|| anyhow!("No map EVENTS in the eBPF object"))?;
+            let mut events = AsyncPerfEventArray::try_from(map)?;
+            let cpus = online_cpus().map_err(|(path, e)| {
+                anyhow::Error::new(e)
+                    .context(format!("Failed to read {}", path))
+            })?;
+            for cpu in cpus {
+                let mut buffer = events.open(cpu, None).with_context(|| {
+                    format!("Failed to open the event buffer of CPU {}", cpu)
+                })?;
+                let records = records.clone();
+                let health = health.clone();
+                tokio::spawn(async move {
+                    let size = std::mem::size_of::<RawFileEvent>();
+                    let mut buffers =
+                        vec![BytesMut::with_capacity(size); RECORDS_PER_READ];
+                    loop {
+                        let read = tokio::select! {
+                            _ = records.closed() => return,
+                            read = buffer.read_events(&mut buffers) => read,
+                        };
+                        let read = match read {
+                            Ok(read) => read,
+                            Err(e) => {
+                                warn!("Stopped reading CPU {}: {}", cpu, e);
+                                return;
+                            }
+                        };
+                        health.record_dropped(read.lost as u64);
+                        for record in &buffers[..read.read] {
+                            if records.send(record.to_vec()).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+            Ok(())
+        }
+
+        fn set_uid_filter(
+            &mut self,
+            entries: &[(u32, u8)],
+            includes: bool,
+        ) -> Result<()> {
+            let mut filter = BpfHashMap::try_from(self.map("UID_FILTER")?)?;
+            replace(&mut filter, entries)?;
+            let mut active = Array::try_from(self.map("UID_FILTER_ACTIVE")?)?;
+            active.set(0, u32::from(includes), 0)?;
+            Ok(())
+        }
+
+        fn set_comm_filter(
+            &mut self,
+            names: &[[u8; MAX_COMM_LEN]],
+            mode: u32,
+        ) -> Result<()> {
+            let entries: Vec<_> = names.iter().map(|name| (*name, 1)).collect();
+            let mut filter = BpfHashMap::try_from(self.map("COMM_FILTER")?)?;
+            replace(&mut filter, &entries)?;
+            let mut filter_mode =
+                Array::try_from(self.map("COMM_FILTER_MODE")?)?;
+            filter_mode.set(0, mode, 0)?;
+            Ok(())
+        }
+
+        fn set_leader_comms(
+            &mut self,
+            comms: &[(u32, [u8; MAX_COMM_LEN])],
+        ) -> Result<()> {
+            let mut leaders: BpfHashMap<_, u32, [u8; MAX_COMM_LEN]> =
+                BpfHashMap::try_from(self.map("LEADER_COMMS")?)?;
+            for (pid, comm) in comms {
+                leaders.insert(pid, comm, 0)?;
+            }
+            Ok(())
+        }
+
+        fn set_tripwires(
+            &mut self,
+            paths: &[[u8; MAX_PATH_LEN]],
+        ) -> Result<()> {
+            let entries: Vec<_> = paths.iter().map(|path| (*path, 1)).collect();
+            let mut tripwires = BpfHashMap::try_from(self.map("TRIPWIRES")?)?;
+            replace(&mut tripwires, &entries)
+        }
+
+        fn set_pseudo_fs_excluded(&mut self, excluded: bool) -> Result<()> {
+            let mut flag = Array::try_from(self.map("PSEUDO_FS_EXCLUDED")?)?;
+            flag.set(0, u32::from(excluded), 0)?;
+            Ok(())
+        }
+
+        fn set_kernel_aggregation(&mut self, active: bool) -> Result<()> {
+            let mut flag = Array::try_from(self.map("AGG_ACTIVE")?)?;
+            flag.set(0, u32::from(active), 0)?;
+            Ok(())
+        }
+
+        fn drain_counts(&mut self) -> Result<KernelCounts> {
+            let mut drained = KernelCounts::default();
+            let mut counts: PerCpuHashMap<_, AggKey, u64> =
+                PerCpuHashMap::try_from(self.map("AGG_COUNTS")?)?;
+            // Deleting while walking the keys would restart the walk
+            let keys: Vec<_> = counts.keys().collect::<Result<_, _>>()?;
+            for key in keys {
+                let values = counts.get(&key, 0)?;
+                counts.remove(&key)?;
+                drained.counts.push((key, values.iter().sum()));
+            }
+
+            let mut overflow: PerCpuArray<_, u64> =
+                PerCpuArray::try_from(self.map("AGG_OVERFLOW")?)?;
+            let values = overflow.get(&0, 0)?;
+            drained.overflow = values.iter().sum();
+            let zeros = PerCpuValues::try_from(vec![0; values.len()])?;
+            overflow.set(0, zeros, 0)?;
+            Ok(drained)
+        }
+
+        fn set_sample_rate(&mut self, rate: u32) -> Result<()> {
+            let mut sample_rate = Array::try_from(self.map("SAMPLE_RATE")?)?;
+            sample_rate.set(0, rate, 0)?;
+            Ok(())
+        }
+
+        fn set_sample_exemptions(
+            &mut self,
+            criteria: &[StackCriterion],
+        ) -> Result<()> {
+            fill(self, "SAMPLE_EXEMPT", "SAMPLE_EXEMPT_COUNT", criteria)
+        }
+
+        fn set_stack_mode(&mut self, flags: u32) -> Result<()> {
+            let mut mode = Array::try_from(self.map("STACK_MODE")?)?;
+            mode.set(0, flags, 0)?;
+            Ok(())
+        }
+
+        fn set_stack_criteria(
+            &mut self,
+            criteria: &[StackCriterion],
+        ) -> Result<()> {
+            fill(self, "STACK_CRITERIA", "STACK_CRITERIA_COUNT", criteria)
+        }
+
+        fn take_stack(&mut self, id: u32) -> Result<Vec<u64>> {
+            let mut stacks = StackTraceMap::try_from(self.map("STACKS")?)?;
+            let stack = stacks.get(&id, 0)?;
+            stacks.remove(&id)?;
+            Ok(stack.frames().iter().map(|frame| frame.ip).collect())
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! BPF object module
+//!
+//! The compiled probes are embedded in the fw binary, so it keeps working
+//! when copied to another machine without the build tree. Before the
+//! object is loaded fw checks that it is intact, against the checksum the
+//! build recorded, and that it was built for the event layout this fw
+//! decodes. `fw collect --bpf-object` loads an object from a file instead,
+//! for trying out probe changes without rebuilding fw.
+
+use anyhow::{anyhow, Context, Result};
+use fw_common::{object_checksum, stamped_version, EVENT_ABI_VERSION};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Probes compiled by build.rs
+static EMBEDDED: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fw-ebpf"));
+
+/// Checksum build.rs recorded for the embedded probes, as hex
+const EMBEDDED_CHECKSUM: &str = env!("EBPF_OBJECT_CHECKSUM");
+
+/// An eBPF object ready to be loaded
+#[derive(Debug, Clone)]
+pub struct BpfObject {
+    /// File the object was read from; None for the embedded one
+    source: Option<PathBuf>,
+    /// Contents of the object
+    bytes: Cow<'static, [u8]>,
+}
+
+impl BpfObject {
+    /// The probes embedded in this binary
+    ///
+    /// # Returns
+    /// * `Result<BpfObject>` - Object, or error if it doesn't match the
+    ///   checksum recorded at build time or the event layout
+    pub fn embedded() -> Result<Self> {
+        let expected = u64::from_str_radix(EMBEDDED_CHECKSUM, 16)?;
+        let checksum = object_checksum(EMBEDDED);
+        if checksum != expected {
+            return Err(anyhow!(
+                "The embedded eBPF object is damaged (checksum {:016x}, \
+                 built as {:016x}); reinstall fw",
+                checksum,
+                expected
+            ));
+        }
+        let object = Self {
+            source: None,
+            bytes: Cow::Borrowed(EMBEDDED),
+        };
+        object.check_version()?;
+        Ok(object)
+    }
+
+    /// Read an object built separately, e.g. by `cargo build` in fw-ebpf
+    ///
+    /// # Arguments
+    /// * `path` - Object file
+    ///
+    /// # Returns
+    /// * `Result<BpfObject>` - Object, or error if it can't be read or was
+    ///   built for another event layout
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let object = Self {
+            source: Some(path.to_path_buf()),
+            bytes: Cow::Owned(bytes),
+        };
+        object.check_version()?;
+        Ok(object)
+    }
+
+    /// Check that the object was built for the event layout fw decodes
+    fn check_version(&self) -> Result<()> {
+        match stamped_version(&self.bytes) {
+            Some(EVENT_ABI_VERSION) => Ok(()),
+            Some(version) => Err(anyhow!(
+                "{} was built for event layout {}, but this fw decodes \
+                 layout {}; rebuild it from the same source",
+                self,
+                version,
+                EVENT_ABI_VERSION
+            )),
+            None => Err(anyhow!("{} is not an fw eBPF object", self)),
+        }
+    }
+
+    /// Contents to hand to the loader
+    ///
+    /// # Returns
+    /// * `&[u8]` - Object file bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Checksum identifying the object in logs
+    ///
+    /// # Returns
+    /// * `u64` - FNV-1a checksum of the contents
+    pub fn checksum(&self) -> u64 {
+        object_checksum(&self.bytes)
+    }
+}
+
+impl fmt::Display for BpfObject {
+    /// Name the object's origin
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(path) => write!(f, "{}", path.display()),
+            None => write!(f, "the embedded eBPF object"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fw_common::{object_stamp, OBJECT_STAMP_LEN};
+
+    #[test]
+    fn test_embedded_object_is_intact() {
+        let object = BpfObject::embedded().unwrap();
+        assert_eq!(object.bytes(), EMBEDDED);
+        assert_eq!(format!("{:016x}", object.checksum()), EMBEDDED_CHECKSUM);
+    }
+
+    #[test]
+    fn test_external_object_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fw-ebpf.o");
+        let mut bytes = b"\x7fELF".to_vec();
+        bytes.extend_from_slice(&object_stamp());
+        fs::write(&path, &bytes).unwrap();
+        assert!(BpfObject::load(&path).is_ok());
+
+        // An object from a build with another event layout
+        let last = bytes.len() - OBJECT_STAMP_LEN + 8;
+        bytes[last] = bytes[last].wrapping_add(1);
+        fs::write(&path, &bytes).unwrap();
+        let e = BpfObject::load(&path).unwrap_err().to_string();
+        assert!(e.contains("was built for event layout"), "{}", e);
+
+        fs::write(&path, b"\x7fELF").unwrap();
+        assert!(BpfObject::load(&path).is_err());
+        assert!(BpfObject::load(&dir.path().join("missing.o")).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Capabilities module
+//!
+//! Feature-tests the running kernel at startup so fw can pick the best
+//! implementation it supports instead of failing on older kernels. Where
+//! a test can be run directly (creating a map of a given type, loading a
+//! trivial program) it is, because distribution kernels backport features;
+//! otherwise the kernel version decides. Tests that need privileges fw
+//! doesn't have fall back to the version as well.
+
+use log::{debug, info, warn};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Where BTF for the running kernel is exposed
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+
+/// Mount point of the BPF filesystem
+const BPFFS: &str = "/sys/fs/bpf";
+
+/// bpf(2) command creating a map
+const BPF_MAP_CREATE: libc::c_int = 0;
+
+/// bpf(2) command loading a program
+const BPF_PROG_LOAD: libc::c_int = 5;
+
+/// Map type of the BPF ring buffer
+const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+/// Program type of kprobe programs
+const BPF_PROG_TYPE_KPROBE: u32 = 2;
+
+/// `r0 = 0; exit`, the smallest program the verifier accepts
+const TRIVIAL_PROGRAM: [u64; 2] = [0xb7, 0x95];
+
+/// A kernel release, as in "5.15.0-91-generic"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    /// Create a version from its components
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse a kernel release string
+    ///
+    /// # Arguments
+    /// * `release` - Release as reported by `uname -r`; anything after the
+    ///   numeric version is ignored
+    ///
+    /// # Returns
+    /// * `Option<KernelVersion>` - Parsed version, or None if malformed
+    pub fn parse(release: &str) -> Option<Self> {
+        let numeric = release
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?;
+        let mut parts = numeric.split('.').map(|p| p.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = parts.next().flatten().unwrap_or(0);
+        Some(Self::new(major, minor, patch))
+    }
+
+    /// Version of the running kernel
+    pub fn current() -> Option<Self> {
+        let uts = nix::sys::utsname::uname().ok()?;
+        Self::parse(&uts.release().to_string_lossy())
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// How events get from the eBPF programs to userspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTransport {
+    /// One shared BPF ring buffer, preserving order across CPUs (5.8+)
+    RingBuffer,
+    /// Per-CPU perf buffers, available on every supported kernel
+    PerfBuffer,
+}
+
+impl fmt::Display for EventTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventTransport::RingBuffer => f.write_str("ring buffer"),
+            EventTransport::PerfBuffer => f.write_str("perf buffer"),
+        }
+    }
+}
+
+/// What the running kernel supports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Running kernel version, if it could be determined
+    pub kernel: Option<KernelVersion>,
+    /// BPF_MAP_TYPE_RINGBUF maps can be created
+    pub ring_buffer: bool,
+    /// The kernel exposes its BTF
+    pub btf: bool,
+    /// Programs may be loaded as sleepable
+    pub sleepable: bool,
+    /// Kprobe programs can be loaded; None if fw lacks the privilege to
+    /// find out
+    pub kprobes: Option<bool>,
+    /// The BPF filesystem is mounted, so maps and links can be pinned
+    pub bpffs: bool,
+}
+
+impl Capabilities {
+    /// Feature-test the running kernel
+    ///
+    /// # Returns
+    /// * `Capabilities` - What the kernel supports
+    pub fn detect() -> Self {
+        let kernel = KernelVersion::current();
+        let at_least = |major, minor| {
+            kernel.is_some_and(|k| k >= KernelVersion::new(major, minor, 0))
+        };
+
+        let ring_buffer = match probe_map(BPF_MAP_TYPE_RINGBUF) {
+            Some(supported) => supported,
+            None => at_least(5, 8),
+        };
+        let btf = Path::new(VMLINUX_BTF).exists();
+        // Sleepable programs attach through BTF, so there's no trivial
+        // program to test them with
+        let sleepable = btf && at_least(5, 10);
+        let kprobes = probe_program(BPF_PROG_TYPE_KPROBE, kernel);
+        let bpffs = Path::new(BPFFS).exists();
+
+        let caps = Self {
+            kernel,
+            ring_buffer,
+            btf,
+            sleepable,
+            kprobes,
+            bpffs,
+        };
+        debug!("Kernel capabilities: {:?}", caps);
+        caps
+    }
+
+    /// Best available event transport
+    pub fn transport(&self) -> EventTransport {
+        if self.ring_buffer {
+            EventTransport::RingBuffer
+        } else {
+            EventTransport::PerfBuffer
+        }
+    }
+
+    /// Describe each feature running with a fallback implementation
+    ///
+    /// # Returns
+    /// * `Vec<String>` - One line per degraded feature; empty if the
+    ///   kernel supports everything
+    pub fn degradations(&self) -> Vec<String> {
+        let mut degraded = Vec::new();
+        if self.transport() == EventTransport::PerfBuffer {
+            degraded.push(
+                "no BPF ring buffer: events use per-CPU perf buffers and \
+                 may arrive out of order across CPUs"
+                    .to_string(),
+            );
+        }
+        if !self.sleepable {
+            degraded.push(
+                "no sleepable programs: paths in paged-out user memory \
+                 may be reported empty"
+                    .to_string(),
+            );
+        }
+        if !self.bpffs {
+            degraded.push(format!(
+                "{} is not mounted: maps and links can't be pinned",
+                BPFFS
+            ));
+        }
+        degraded
+    }
+
+    /// Log the kernel version and every degraded feature
+    pub fn log(&self) {
+        match self.kernel {
+            Some(kernel) => info!("Kernel {}", kernel),
+            None => warn!("Could not determine the kernel version"),
+        }
+        for degraded in self.degradations() {
+            warn!("Degraded: {}", degraded);
+        }
+        if self.kprobes.is_none() {
+            warn!(
+                "Not permitted to load eBPF programs; feature tests fell \
+                 back to the kernel version"
+            );
+        }
+    }
+}
+
+/// Leading fields of `union bpf_attr` for BPF_MAP_CREATE
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+    /// The kernel requires every unused attribute byte to be zero
+    _rest: [u32; 27],
+}
+
+/// Leading fields of `union bpf_attr` for BPF_PROG_LOAD
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    /// The kernel requires every unused attribute byte to be zero
+    _rest: [u64; 10],
+}
+
+/// Run a bpf(2) command that returns a file descriptor, closing it
+///
+/// # Returns
+/// * `io::Result<()>` - Success if the kernel accepted the command
+fn bpf<T>(cmd: libc::c_int, attr: &T) -> io::Result<()> {
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            std::mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::close(fd as libc::c_int) };
+    Ok(())
+}
+
+/// Interpret the result of a feature test
+///
+/// # Returns
+/// * `Option<bool>` - Whether the feature is supported, or None if fw
+///   wasn't permitted to run the test
+fn supported(result: io::Result<()>) -> Option<bool> {
+    match result {
+        Ok(()) => Some(true),
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => None,
+        Err(_) => Some(false),
+    }
+}
+
+/// Test whether maps of the given type can be created
+fn probe_map(map_type: u32) -> Option<bool> {
+    let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .unwrap_or(4096) as u32;
+    let attr = MapCreateAttr {
+        map_type,
+        max_entries: page_size,
+        ..Default::default()
+    };
+    supported(bpf(BPF_MAP_CREATE, &attr))
+}
+
+/// Test whether a trivial program of the given type can be loaded
+fn probe_program(
+    prog_type: u32,
+    kernel: Option<KernelVersion>,
+) -> Option<bool> {
+    let license = c"GPL";
+    // Kernels before 5.0 check kprobe programs against their version
+    let kern_version = kernel
+        .map(|k| (k.major << 16) | (k.minor << 8) | k.patch.min(255))
+        .unwrap_or(0);
+    let attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: TRIVIAL_PROGRAM.len() as u32,
+        insns: TRIVIAL_PROGRAM.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        kern_version,
+        ..Default::default()
+    };
+    supported(bpf(BPF_PROG_LOAD, &attr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_version_parse() {
+        assert_eq!(
+            KernelVersion::parse("5.15.0-91-generic"),
+            Some(KernelVersion::new(5, 15, 0))
+        );
+        assert_eq!(
+            KernelVersion::parse("4.19.316+"),
+            Some(KernelVersion::new(4, 19, 316))
+        );
+        assert_eq!(
+            KernelVersion::parse("6.8-rc3"),
+            Some(KernelVersion::new(6, 8, 0))
+        );
+        assert_eq!(KernelVersion::parse("linux"), None);
+        assert!(KernelVersion::new(4, 19, 0) < KernelVersion::new(5, 4, 0));
+    }
+
+    #[test]
+    fn test_old_kernel_degrades() {
+        let caps = Capabilities {
+            kernel: Some(KernelVersion::new(4, 19, 0)),
+            ring_buffer: false,
+            btf: false,
+            sleepable: false,
+            kprobes: Some(true),
+            bpffs: false,
+        };
+        assert_eq!(caps.transport(), EventTransport::PerfBuffer);
+        assert_eq!(caps.degradations().len(), 3);
+
+        let modern = Capabilities {
+            kernel: Some(KernelVersion::new(6, 1, 0)),
+            ring_buffer: true,
+            btf: true,
+            sleepable: true,
+            kprobes: Some(true),
+            bpffs: true,
+        };
+        assert_eq!(modern.transport(), EventTransport::RingBuffer);
+        assert!(modern.degradations().is_empty());
+    }
+}
@@ -1,9 +1,61 @@
 //! Command Line Interface (CLI) module
 //!
 //! Defines the command line interface structure and parsing logic using clap.
-//! Supports the `collect` command with optional file extension filtering.
+//! Supports the `collect` command with optional file extension filtering,
+//! the `forward` and `collect-remote` commands for collecting from many
+//! hosts, the `bench` command for measuring monitoring overhead, the
+//! `report` command for summarizing a capture, the `record`, `verify` and
+//! `replay` commands for checkpointed recordings, the `wait-for` command
+//! for blocking until a file event arrives, the `cleanup` command for
+//! removing stale pinned state, the `selftest` command for validating the
+//! event pipeline, and the `completions` and `man` commands for packagers.
+//! The command definition can be built with [`command`] without parsing.
 
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Command, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::audit_format::EventFormat;
+use crate::baseline::DEFAULT_BASELINE_DEPTH;
+use crate::binary_format::BinaryFormat;
+use crate::collector::{OutputMode, OutputStream};
+use crate::compression::Compression;
+use crate::diagnostics::{LogFormat, LogLevel};
+use crate::diff::DiffFormat;
+use crate::enrich::{parse_tag, EnricherKind, EnrichmentLevel};
+use crate::enrich_pool::DEFAULT_ENRICH_WORKERS;
+use crate::exec_hook::DEFAULT_EXEC_CONCURRENCY;
+use crate::file_event::FileType;
+use crate::forensic::DEFAULT_FORENSIC_BUFFER_BYTES;
+use crate::hot::{RankBy, DEFAULT_TOP};
+use crate::influx::{DEFAULT_INFLUX_GROUP_BY, DEFAULT_INFLUX_INTERVAL};
+use crate::notify::{
+    NotifyTemplate, SmtpConfig, WebhookUrl, DEFAULT_CA_BUNDLE,
+    DEFAULT_NOTIFY_TEMPLATE,
+};
+use crate::overload::DEFAULT_OVERLOAD_PERCENT;
+use crate::pagerduty::DEFAULT_PAGERDUTY_URL;
+use crate::process_cache::DEFAULT_PROCESS_CACHE_SIZE;
+use crate::process_list::ProcessListMode;
+use crate::record::{ReplaySpeed, MAX_REPLAY_FACTOR, MIN_REPLAY_FACTOR};
+use crate::report::ReportFormat;
+use crate::rollup::Resolution;
+use crate::route::Route;
+use crate::schedule::Schedule;
+use crate::severity::Severity;
+use crate::spool::{DropPolicy, DEFAULT_SPOOL_MAX_BYTES};
+use crate::stacks::StackMode;
+use crate::stats::{Dimension, ExportFormat};
+use crate::syslog::Facility;
+use crate::template::EventTemplate;
+use crate::tripwire::DEFAULT_TRIPWIRE_FILE;
+use crate::wait_for::ActionMatch;
+use crate::watchlist::DEFAULT_WATCHLIST_FILE;
 
 /// File Watcher (fw) - Monitor file operations using eBPF
 #[derive(Parser)]
@@ -14,33 +66,2151 @@ use clap::{Parser, Subcommand};
                   processes on the system with minimal overhead using eBPF \
                   technology."
 )]
-#[command(version, author)]
+// --version is defined below so it can be combined with --verbose
+#[command(author, disable_version_flag = true, arg_required_else_help = true)]
 pub struct Cli {
-    /// The command to execute
+    /// The command to execute; only optional with --version
     #[command(subcommand)]
-    pub command: Commands,
+    pub command: Option<Commands>,
+
+    /// Print the version and exit
+    #[arg(short = 'V', long = "version", help = "Print version")]
+    pub version: bool,
+
+    /// With --version, also print the build target and features, the
+    /// embedded eBPF object and the kernel features fw can use
+    #[arg(
+        long = "verbose",
+        requires = "version",
+        help = "With --version, print build and kernel details"
+    )]
+    pub verbose: bool,
+
+    /// Most verbose diagnostics to write (overrides RUST_LOG)
+    #[arg(
+        long = "log-level",
+        value_enum,
+        global = true,
+        help = "Diagnostics verbosity (default: RUST_LOG, or errors)"
+    )]
+    pub log_level: Option<LogLevel>,
+
+    /// Format of diagnostic messages
+    #[arg(
+        long = "log-format",
+        value_enum,
+        default_value_t,
+        global = true,
+        help = "Write diagnostics as text or JSON lines"
+    )]
+    pub log_format: LogFormat,
+
+    /// Append diagnostics to this file instead of stderr
+    #[arg(
+        long = "log-file",
+        global = true,
+        help = "Write diagnostics to this file instead of stderr"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    /// Keep diagnostics off stderr, so only file events are written there
+    ///
+    /// Diagnostics still go to --log-file if one is given.
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        global = true,
+        help = "Write no diagnostics to stderr"
+    )]
+    pub quiet: bool,
 }
 
 /// Available commands for the file watcher tool
+// Parsed once at startup, so the size of the collect options doesn't
+// matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Collect file operation events until interrupted (Ctrl+C)
     ///
-    /// Monitors file open/close operations and outputs events to stderr.
-    /// Each event includes the file path, program name, action type, and
-    /// timestamp.
-    Collect {
-        /// Comma-separated list of file extensions to monitor
+    /// Monitors file open/close operations and outputs events to stderr,
+    /// or to --output / --output-stream. Each event includes the file
+    /// path, program name, action type, and timestamp.
+    Collect(CollectArgs),
+
+    /// Collect events and stream them to a central `fw collect-remote`
+    ///
+    /// Runs the same collection as `collect`, sending every reported event
+    /// over TLS instead of writing it out. The central instance tags each
+    /// event with this host's name. A lost connection is re-opened; with
+    /// --spool-dir, events sent while it is down are delivered later.
+    Forward {
+        /// Central instance as host:port (port 7443 if omitted)
+        #[arg(long = "to", help = "Central fw collect-remote (host:port)")]
+        to: String,
+
+        /// CA certificates (PEM) the central instance's certificate must
+        /// chain to
+        #[arg(long = "tls-ca", help = "CA to verify the central instance")]
+        tls_ca: PathBuf,
+
+        /// Client certificate (PEM), for central instances requiring one
+        #[arg(
+            long = "tls-cert",
+            requires = "tls_key",
+            help = "Client certificate to present"
+        )]
+        tls_cert: Option<PathBuf>,
+
+        /// Private key (PEM) of --tls-cert
+        #[arg(
+            long = "tls-key",
+            requires = "tls_cert",
+            help = "Key of the client certificate"
+        )]
+        tls_key: Option<PathBuf>,
+
+        /// Name the central certificate must be valid for (default: the
+        /// host of --to)
+        #[arg(long = "server-name", help = "Expected certificate name")]
+        server_name: Option<String>,
+
+        /// Name events are tagged with centrally (default: this host's
+        /// name)
+        #[arg(long = "hostname", help = "Host name to send")]
+        hostname: Option<String>,
+
+        /// File whose first line is this host's token, for central
+        /// instances started with --tokens
+        #[arg(long = "token-file", help = "File holding this host's token")]
+        token_file: Option<PathBuf>,
+
+        /// Collection options
+        #[command(flatten)]
+        collect: CollectArgs,
+    },
+
+    /// Receive events from `fw forward` instances and report them
+    ///
+    /// Accepts TLS connections from forwarders instead of attaching
+    /// probes. Each event is tagged host=<name> and then filtered,
+    /// classified and written like local events, so --tag host=web1 or
+    /// --group-by tag=host work across the fleet.
+    CollectRemote {
+        /// Address to accept forwarders on
+        #[arg(
+            long = "listen",
+            default_value = "0.0.0.0:7443",
+            help = "Address to listen on"
+        )]
+        listen: SocketAddr,
+
+        /// Server certificate chain (PEM)
+        #[arg(long = "tls-cert", help = "Server certificate")]
+        tls_cert: PathBuf,
+
+        /// Private key (PEM) of --tls-cert
+        #[arg(long = "tls-key", help = "Server certificate key")]
+        tls_key: PathBuf,
+
+        /// Only accept forwarders with a client certificate from this CA
+        #[arg(long = "client-ca", help = "CA forwarders must present")]
+        client_ca: Option<PathBuf>,
+
+        /// Only accept client certificates valid for a name in this file
+        /// (one per line); events are tagged peer=<name>
+        #[arg(
+            long = "client-allowlist",
+            requires = "client_ca",
+            help = "Certificate names forwarders may use"
+        )]
+        client_allowlist: Option<PathBuf>,
+
+        /// File of "host token" lines; a forwarder must send the token of
+        /// the host name it claims
+        #[arg(long = "tokens", help = "Token each host must present")]
+        tokens: Option<PathBuf>,
+
+        /// Collection options
+        #[command(flatten)]
+        collect: CollectArgs,
+    },
+
+    /// Measure monitoring overhead under a synthetic file workload
+    ///
+    /// Drives a steady open/close workload against temporary files while
+    /// the monitor runs, then reports events/sec handled, drop rate, and
+    /// CPU overhead with and without extension filtering.
+    Bench {
+        /// Target number of file opens per second
+        #[arg(
+            short = 'w',
+            long = "workload",
+            default_value_t = 1000,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Synthetic workload rate in opens per second"
+        )]
+        workload: u32,
+
+        /// Duration of each measurement pass in seconds
+        #[arg(
+            short = 'd',
+            long = "duration",
+            default_value_t = 10,
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Seconds to run each measurement pass"
+        )]
+        duration: u64,
+    },
+
+    /// Summarize a capture of `fw collect` output
+    ///
+    /// Reads event lines saved from a previous run (e.g. `fw collect 2>
+    /// capture.log`) and reports the most active processes, the files
+    /// changed by the most processes, and a timeline of sensitive-file
+    /// access.
+    Report {
+        /// File holding captured `fw collect` output
+        #[arg(long = "from", help = "Capture of fw collect output")]
+        from: PathBuf,
+
+        /// Document format to print
+        #[arg(
+            long = "format",
+            value_enum,
+            default_value = "md",
+            help = "Report format"
+        )]
+        format: ReportFormat,
+    },
+
+    /// Compare file activity between two runs
+    ///
+    /// Each side is a capture or recording of `collect` output, or a
+    /// stats snapshot exported with `collect --mode stats --export`.
+    /// Groups only in CURRENT are reported as added, groups only in
+    /// BASELINE as removed, and groups whose count differs as changed.
+    Diff {
+        /// Run before the change
+        #[arg(help = "Baseline capture, recording or stats snapshot")]
+        baseline: PathBuf,
+
+        /// Run after the change
+        #[arg(help = "Current capture, recording or stats snapshot")]
+        current: PathBuf,
+
+        /// Dimensions to compare by, as for `collect --group-by`
         ///
-        /// If specified, only files with these extensions will be monitored.
-        /// Extensions should be provided without the leading dot (e.g., "rs,md,toml").
-        /// If not specified, all file operations will be monitored.
+        /// Defaults to process,path for captures and to the exported
+        /// columns for stats snapshots, whose counts are summed down to
+        /// the dimensions given.
         #[arg(
-            short = 'e',
-            long = "extensions",
+            long = "group-by",
             value_delimiter = ',',
-            help = "File extensions to monitor (e.g., rs,md,toml)"
+            value_parser = Dimension::parse,
+            help = "Compare by these dimensions (e.g., process,path)"
+        )]
+        group_by: Option<Vec<Dimension>>,
+
+        /// Output format
+        #[arg(
+            long = "format",
+            value_enum,
+            default_value = "table",
+            help = "Diff format"
+        )]
+        format: DiffFormat,
+    },
+
+    /// Convert `collect --format msgpack`, `cbor` or `proto` output to
+    /// JSON lines
+    ///
+    /// Each frame is written to stdout as one JSON object, with the keys
+    /// `fw forward` uses or, for proto, the .proto field names.
+    /// Compressed files are decompressed first.
+    Decode {
+        /// Streams to convert; stdin if none are given
+        #[arg(help = "Binary event streams (default: stdin)")]
+        inputs: Vec<PathBuf>,
+
+        /// Encoding of the frames
+        #[arg(long = "from", value_enum, help = "Frame encoding")]
+        from: BinaryFormat,
+    },
+
+    /// Learn normal file activity and alert on departures from it
+    Baseline {
+        #[command(subcommand)]
+        command: BaselineCommand,
+    },
+
+    /// Edit the named path sets of `collect --watchlists`
+    ///
+    /// A running collector picks up the edits without restarting.
+    Watchlist {
+        /// Watchlist file to edit
+        #[arg(
+            long = "file",
+            global = true,
+            default_value = DEFAULT_WATCHLIST_FILE,
+            help = "Watchlist file"
+        )]
+        file: PathBuf,
+
+        #[command(subcommand)]
+        command: WatchlistCommand,
+    },
+
+    /// Plant decoy files for `collect --tripwires` to alert on
+    ///
+    /// Collectors arm the tripwires recorded when they start.
+    Tripwire {
+        /// Tripwire file to edit
+        #[arg(
+            long = "file",
+            global = true,
+            default_value = DEFAULT_TRIPWIRE_FILE,
+            help = "Tripwire file"
+        )]
+        file: PathBuf,
+
+        #[command(subcommand)]
+        command: TripwireCommand,
+    },
+
+    /// Propose exclusion rules that cut the noise in a capture
+    ///
+    /// Picks the processes, directories and extensions leaving out the
+    /// most events until --target percent of them are gone, but never
+    /// one that would leave out a rare event (a program touching a
+    /// directory at most --keep-rare times) or an access to a sensitive
+    /// file. Prints the rules in `collect --exclude-file` syntax.
+    SuggestFilters {
+        /// Captures or recordings to analyse
+        #[arg(
+            long = "from",
+            required_unless_present = "sample",
+            help = "Capture of fw collect output (repeatable)"
+        )]
+        from: Vec<PathBuf>,
+
+        /// Watch live events for this long instead of reading captures
+        #[arg(
+            long = "sample",
+            conflicts_with = "from",
+            value_parser = parse_timeout,
+            help = "Analyse a live sample this long (e.g., 5m)"
+        )]
+        sample: Option<Duration>,
+
+        /// Percentage of the events the rules should leave out
+        #[arg(
+            long = "target",
+            default_value_t = 80,
+            value_parser = clap::value_parser!(u8).range(1..=100),
+            help = "Percentage of events to leave out"
+        )]
+        target: u8,
+
+        /// Program/directory pairs with at most this many events are
+        /// rare and never left out
+        #[arg(
+            long = "keep-rare",
+            default_value_t = 5,
+            help = "Keep pairs with at most this many events"
+        )]
+        keep_rare: u64,
+
+        /// Most rules proposed
+        #[arg(
+            long = "max-rules",
+            default_value_t = 20,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Most rules proposed"
+        )]
+        max_rules: u32,
+
+        /// File to write the rules to instead of stdout
+        #[arg(short = 'o', long = "output", help = "Exclusion file to write")]
+        output: Option<PathBuf>,
+    },
+
+    /// Record all file activity to a file with integrity checkpoints
+    ///
+    /// Writes the same event lines as `collect`, plus a checkpoint line
+    /// at most every --checkpoint-interval with the number of events so
+    /// far and a drop counter per source, so `replay` can validate it.
+    Record {
+        /// File to record to
+        #[arg(help = "Recording file")]
+        output: PathBuf,
+
+        /// Append to an existing recording, continuing its checkpoints
+        #[arg(long = "resume", help = "Append to an existing recording")]
+        resume: bool,
+
+        /// Minimum time between checkpoints
+        #[arg(
+            long = "checkpoint-interval",
+            default_value = "60s",
+            value_parser = parse_timeout,
+            help = "Time between checkpoints (e.g., 30s)"
+        )]
+        checkpoint_interval: Duration,
+
+        /// Compress the recording as it is written
+        #[arg(long = "compress", value_enum, help = "Compress the recording")]
+        compress: Option<Compression>,
+
+        /// Compression level (gzip 0-9, default 6; zstd 1-22, default 3)
+        #[arg(
+            long = "compress-level",
+            requires = "compress",
+            help = "Compression level for --compress"
+        )]
+        compress_level: Option<i32>,
+
+        /// Sign every checkpoint with this ed25519 key (PKCS#8 PEM), so
+        /// `fw verify` can prove the recording wasn't modified
+        #[arg(long = "sign-key", help = "ed25519 key to sign checkpoints")]
+        sign_key: Option<PathBuf>,
+
+        /// Keep per-minute and per-hour counts by process, directory and
+        /// action in OUTPUT.rollup.csv, for `fw trend` over long ranges
+        #[arg(long = "rollup", help = "Keep time-bucketed rollups")]
+        rollup: bool,
+
+        /// While recording, delete files in the recording's directory
+        /// older than this (e.g. 30d), as `fw prune` would; the
+        /// recording and its rollups are kept
+        #[arg(
+            long = "max-age",
+            value_parser = parse_age,
+            help = "Prune files older than this (s, m, h or d)"
+        )]
+        max_age: Option<Duration>,
+
+        /// While recording, delete the oldest files in the recording's
+        /// directory while it holds more than this (e.g. 10G)
+        #[arg(
+            long = "max-size",
+            value_parser = parse_size,
+            help = "Prune the directory down to this size (K, M or G)"
+        )]
+        max_size: Option<u64>,
+
+        /// Only prune files whose names match (e.g. "*.log"); may be
+        /// repeated
+        #[arg(long = "prune-name", help = "Name glob of files to prune")]
+        prune_names: Vec<String>,
+
+        /// Time between prunes with --max-age or --max-size
+        #[arg(
+            long = "prune-interval",
+            default_value = "1h",
+            value_parser = parse_timeout,
+            help = "Time between prunes (e.g., 10m)"
+        )]
+        prune_interval: Duration,
+    },
+
+    /// Check that a signed recording wasn't modified
+    ///
+    /// Recomputes the hash chain of every line and checks each signature
+    /// against the public key, then reports checkpoint gaps and any
+    /// segment that was changed, added to or cut. Fails if anything was
+    /// tampered with or the recording isn't signed.
+    Verify {
+        /// Recording made by `fw record --sign-key`
+        #[arg(help = "Recording file")]
+        input: PathBuf,
+
+        /// Public half of the signing key (SubjectPublicKeyInfo PEM)
+        #[arg(long = "key", help = "ed25519 public key to verify with")]
+        key: PathBuf,
+    },
+
+    /// Delete old recordings to enforce a retention policy
+    ///
+    /// Removes whole files from a directory of recordings: first those
+    /// last written more than --max-age ago, then the oldest until the
+    /// rest fit in --max-size. The newest file is always kept, since it
+    /// may still be being recorded. Reports how much was removed.
+    Prune {
+        /// Directory holding the recordings
+        #[arg(help = "Directory to prune")]
+        dir: PathBuf,
+
+        /// Delete files older than this (e.g. 30d, 12h)
+        #[arg(
+            long = "max-age",
+            value_parser = parse_age,
+            help = "Maximum age of a file (s, m, h or d)"
+        )]
+        max_age: Option<Duration>,
+
+        /// Delete the oldest files while the directory holds more than
+        /// this (e.g. 10G)
+        #[arg(
+            long = "max-size",
+            value_parser = parse_size,
+            help = "Maximum total size (K, M or G)"
+        )]
+        max_size: Option<u64>,
+
+        /// Only prune files whose names match (e.g. "*.log"); may be
+        /// repeated
+        #[arg(long = "name", help = "Name glob of files to prune")]
+        names: Vec<String>,
+
+        /// Report what would be removed without deleting anything
+        #[arg(long = "dry-run", help = "Don't delete anything")]
+        dry_run: bool,
+    },
+
+    /// Print the events of a recording and check its integrity
+    ///
+    /// Events go to stdout. Gaps (drops, time fw wasn't recording, a
+    /// missing final checkpoint) are reported on stderr, and the command
+    /// fails if checkpoints don't match the events present. With
+    /// --follow, events recorded later are printed as they are written,
+    /// through truncation and rotation, until fw is interrupted.
+    Replay {
+        /// Recording made by `fw record`
+        #[arg(help = "Recording file")]
+        input: PathBuf,
+
+        /// Print events paced at a multiple of the recorded speed (e.g.
+        /// "10x", "0.5x"), or "max" to print them as fast as possible
+        #[arg(
+            long = "speed",
+            default_value = "max",
+            value_parser = parse_speed,
+            help = "Replay speed, e.g. 10x or max"
+        )]
+        speed: ReplaySpeed,
+
+        /// Skip events recorded before this time, given as RFC 3339 or
+        /// as printed by fw ("2024-01-01 12:00:00", UTC)
+        #[arg(
+            long = "from",
+            value_parser = parse_timestamp,
+            help = "Only events at or after this time"
+        )]
+        from: Option<DateTime<Utc>>,
+
+        /// Skip events recorded after this time
+        #[arg(
+            long = "to",
+            value_parser = parse_timestamp,
+            help = "Only events at or before this time"
+        )]
+        to: Option<DateTime<Utc>>,
+
+        /// After the recorded events, keep printing new ones as `fw
+        /// record` or `fw collect -o` appends them
+        #[arg(
+            short = 'f',
+            long = "follow",
+            conflicts_with = "to",
+            help = "Keep printing events as they are recorded"
+        )]
+        follow: bool,
+    },
+
+    /// Count a recording's events per minute or hour
+    ///
+    /// Prints one line per time bucket and process, directory (two
+    /// levels deep) and action. Ranges of up to an hour are counted from
+    /// the events themselves; longer ones are read from the rollups of
+    /// `fw record --rollup` when they exist, which count the minutes or
+    /// hours at either end of the range whole.
+    Trend {
+        /// Recording or capture
+        #[arg(help = "Recording file")]
+        input: PathBuf,
+
+        /// Skip events before this time, given as RFC 3339 or as printed
+        /// by fw ("2024-01-01 12:00:00", UTC)
+        #[arg(
+            long = "from",
+            value_parser = parse_timestamp,
+            help = "Only events at or after this time"
+        )]
+        from: Option<DateTime<Utc>>,
+
+        /// Skip events after this time
+        #[arg(
+            long = "to",
+            value_parser = parse_timestamp,
+            help = "Only events at or before this time"
+        )]
+        to: Option<DateTime<Utc>>,
+
+        /// Bucket width; per minute for ranges of up to six hours and per
+        /// hour otherwise by default
+        #[arg(long = "every", value_enum, help = "Bucket width")]
+        every: Option<Resolution>,
+    },
+
+    /// Wait until a matching file event arrives
+    ///
+    /// Prints the first event whose path matches the glob and whose action
+    /// matches to stdout and exits 0. Exits non-zero if nothing matches
+    /// before the timeout.
+    WaitFor {
+        /// Glob the file path must match ("*" stays within a directory,
+        /// "**" crosses directories)
+        #[arg(long = "path", help = "Path glob to wait for")]
+        path: String,
+
+        /// Kind of action to wait for
+        #[arg(
+            long = "action",
+            value_enum,
+            default_value = "any",
+            help = "Action to wait for"
+        )]
+        action: ActionMatch,
+
+        /// How long to wait before giving up
+        #[arg(
+            long = "timeout",
+            default_value = "60s",
+            value_parser = parse_timeout,
+            help = "Time to wait (e.g., 30s, 5m); bare numbers are seconds"
+        )]
+        timeout: Duration,
+    },
+
+    /// Rank the busiest files over a sliding window
+    ///
+    /// Counts events and bytes read or written per file and redraws the
+    /// ranking each time the window slides. Counts come from fixed-size
+    /// sketches, so memory stays bounded at any event rate; they are
+    /// estimates that can overstate but never understate.
+    Hot {
+        /// Length of the sliding window
+        #[arg(
+            long = "window",
+            default_value = "60s",
+            value_parser = parse_timeout,
+            help = "Window to rank over (e.g., 60s, 5m)"
+        )]
+        window: Duration,
+
+        /// Number of files shown
+        #[arg(
+            long = "top",
+            default_value_t = DEFAULT_TOP as u32,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Number of files shown"
+        )]
+        top: u32,
+
+        /// What files are ranked by
+        #[arg(
+            long = "by",
+            value_enum,
+            default_value = "events",
+            help = "Rank by event count or bytes moved"
+        )]
+        by: RankBy,
+    },
+
+    /// Show processes that currently have files open
+    ///
+    /// Lists the open files of every process, like a targeted lsof, from
+    /// /proc/*/fd. With --watch, file events keep the list current until
+    /// interrupted.
+    Ps {
+        /// Glob the file path must match ("*" stays within a directory,
+        /// "**" crosses directories)
+        #[arg(long = "path", help = "Only show files matching this glob")]
+        path: Option<String>,
+
+        /// Keep the list updated from file events
+        #[arg(long = "watch", help = "Keep updating until interrupted")]
+        watch: bool,
+    },
+
+    /// Remove BPF maps and programs left pinned by a crashed fw
+    ///
+    /// Deletes the pins under /sys/fs/bpf/fw, which frees the maps and
+    /// detaches any programs that were still attached. Instances still in
+    /// use are skipped unless --force is given.
+    Cleanup {
+        /// Only clean up this instance ("shared" for the shared deployment)
+        #[arg(long = "instance", help = "Instance to clean up")]
+        instance: Option<String>,
+
+        /// Remove the pins even if a running fw process owns them
+        #[arg(long = "force", help = "Remove pins owned by a running fw")]
+        force: bool,
+    },
+
+    /// Verify the event pipeline with a synthetic event stream
+    ///
+    /// Feeds a fixed set of events through the same filter and output
+    /// formatting used by `collect` and checks the result. Does not
+    /// require eBPF support or root privileges.
+    Selftest,
+
+    /// Print a shell completion script to stdout
+    ///
+    /// For example `fw completions bash > /etc/bash_completion.d/fw`.
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum, help = "Shell to generate completions for")]
+        shell: Shell,
+    },
+
+    /// Print the fw(1) man page in roff format to stdout
+    Man,
+}
+
+/// Subcommands of `fw baseline`
+#[derive(Subcommand)]
+pub enum BaselineCommand {
+    /// Build a profile of the directories each program accesses
+    ///
+    /// Reads captures or recordings of normal activity, e.g. a week of
+    /// `fw record`, and writes the directories each program accessed,
+    /// cut off after --depth levels, for `collect --baseline`.
+    Learn {
+        /// Captures or recordings to learn from
+        #[arg(required = true, help = "Captures or recordings")]
+        inputs: Vec<PathBuf>,
+
+        /// File to write the profile to
+        #[arg(short = 'o', long = "output", help = "Profile file to write")]
+        output: PathBuf,
+
+        /// Directory levels kept per access (e.g. 2 keeps "/var/log" of
+        /// "/var/log/nginx/access.log")
+        #[arg(
+            long = "depth",
+            default_value_t = DEFAULT_BASELINE_DEPTH,
+            value_parser = clap::value_parser!(u64).range(1..)
+                .map(|depth| depth as usize),
+            help = "Directory levels kept per access"
+        )]
+        depth: usize,
+    },
+}
+
+/// Subcommands of `fw watchlist`
+#[derive(Subcommand)]
+pub enum WatchlistCommand {
+    /// Add path globs to a watchlist, creating it if needed
+    Add {
+        /// Name of the list, e.g. "pki"
+        #[arg(help = "Watchlist name")]
+        name: String,
+
+        /// Absolute path globs, e.g. "/etc/pki/**"
+        #[arg(required = true, help = "Path globs to add")]
+        globs: Vec<String>,
+    },
+
+    /// Remove path globs from a watchlist, or the whole list
+    Remove {
+        /// Name of the list
+        #[arg(help = "Watchlist name")]
+        name: String,
+
+        /// Globs to remove; the whole list if none are given
+        #[arg(help = "Path globs to remove")]
+        globs: Vec<String>,
+    },
+
+    /// Print the watchlists and their globs
+    List {
+        /// Only print this list
+        #[arg(help = "Watchlist name")]
+        name: Option<String>,
+    },
+}
+
+/// Subcommands of `fw tripwire`
+#[derive(Subcommand)]
+pub enum TripwireCommand {
+    /// Create decoys and record them; existing files are recorded as
+    /// they are
+    Add {
+        /// Absolute paths, e.g. "/srv/backup/passwords.txt"
+        #[arg(required = true, help = "Decoy files")]
+        paths: Vec<PathBuf>,
+
+        /// What new decoys contain
+        #[arg(
+            long = "content",
+            default_value = "",
+            help = "Contents of new decoys"
         )]
-        extensions: Option<Vec<String>>,
+        content: String,
+    },
+
+    /// Stop alerting on decoys
+    Remove {
+        /// Paths as recorded
+        #[arg(required = true, help = "Decoy files")]
+        paths: Vec<PathBuf>,
+
+        /// Also delete the decoy files fw created, if they haven't been
+        /// replaced since; existing files turned into decoys are kept
+        #[arg(long = "delete", help = "Delete the decoys fw created")]
+        delete: bool,
     },
+
+    /// Print the recorded decoys
+    List,
+}
+
+/// Options of `fw collect`, shared by the commands that run a collection
+#[derive(Args)]
+pub struct CollectArgs {
+    /// Comma-separated list of file extensions to monitor
+    ///
+    /// If specified, only files with these extensions will be monitored.
+    /// Extensions should be provided without the leading dot (e.g., "rs,md,toml").
+    /// If not specified, all file operations will be monitored.
+    #[arg(
+        short = 'e',
+        long = "extensions",
+        value_delimiter = ',',
+        help = "File extensions to monitor (e.g., rs,md,toml)"
+    )]
+    pub extensions: Option<Vec<String>>,
+
+    /// Only report files whose name matches one of these globs
+    ///
+    /// Matched against the last path component, so patterns like
+    /// "*.tar.gz" or "Makefile*" work where --extensions can't. `*`
+    /// and `?` are supported. Repeat for several globs.
+    #[arg(
+        long = "name-glob",
+        help = "Only report file names matching this glob"
+    )]
+    pub name_globs: Vec<String>,
+
+    /// Only report files whose path matches one of these globs
+    ///
+    /// Matched against the whole absolute path; `**` crosses
+    /// directories (e.g. "/srv/**/*.conf"). Repeat for several globs.
+    #[arg(long = "path-glob", help = "Only report paths matching this glob")]
+    pub path_globs: Vec<String>,
+
+    /// Match extensions and globs exactly
+    ///
+    /// By default "--extensions rs" also matches "MAIN.RS", and globs
+    /// ignore case the same way, including outside ASCII.
+    #[arg(
+        long = "case-sensitive",
+        help = "Match extensions and globs with exact case"
+    )]
+    pub case_sensitive: bool,
+
+    /// Mount points whose files should be monitored
+    ///
+    /// Only files on the filesystems mounted exactly at these
+    /// directories are reported (e.g., "/data"). May be repeated or
+    /// given as a comma-separated list.
+    #[arg(
+        long = "mount",
+        value_delimiter = ',',
+        help = "Only report files on these mount points (e.g., /data)"
+    )]
+    pub mounts: Option<Vec<String>>,
+
+    /// Filesystem types whose files should be monitored
+    ///
+    /// Only files on filesystems of these types are reported
+    /// (e.g., "ext4,xfs").
+    #[arg(
+        long = "fstype",
+        value_delimiter = ',',
+        help = "Only report files on these filesystem types (e.g., ext4,xfs)"
+    )]
+    pub fs_types: Option<Vec<String>>,
+
+    /// Only report files on network filesystems (NFS, CIFS, ...)
+    #[arg(
+        long = "remote-only",
+        conflicts_with = "local_only",
+        help = "Only report files on network filesystems"
+    )]
+    pub remote_only: bool,
+
+    /// Only report files on local filesystems
+    #[arg(
+        long = "local-only",
+        help = "Only report files on local filesystems"
+    )]
+    pub local_only: bool,
+
+    /// Only report activity of these users (names or uids)
+    ///
+    /// Filtering happens in the kernel, so other users' activity never
+    /// reaches fw.
+    #[arg(
+        long = "user",
+        value_delimiter = ',',
+        help = "Only report these users (e.g., alice,1001)"
+    )]
+    pub users: Vec<String>,
+
+    /// Never report activity of these users (names or uids)
+    #[arg(
+        long = "exclude-user",
+        value_delimiter = ',',
+        help = "Ignore these users (e.g., root)"
+    )]
+    pub exclude_users: Vec<String>,
+
+    /// Ignore the processes listed in a file, one name or regex per line
+    ///
+    /// Exact names are dropped in the kernel; lines with regex
+    /// metacharacters must match the whole process name and are checked
+    /// in fw. The file is reloaded whenever it changes or fw receives
+    /// SIGUSR1, without restarting the capture.
+    #[arg(
+        long = "process-list-file",
+        help = "File of process names or regexes to ignore"
+    )]
+    pub process_list_file: Option<PathBuf>,
+
+    /// Whether --process-list-file names processes to ignore or the only
+    /// ones to report
+    #[arg(
+        long = "process-list-mode",
+        value_enum,
+        default_value = "deny",
+        requires = "process_list_file",
+        help = "Ignore the listed processes (deny) or only report them (allow)"
+    )]
+    pub process_list_mode: ProcessListMode,
+
+    /// Only report opens and syncs that took at least this long in the
+    /// kernel
+    ///
+    /// Accepts a number with an optional unit suffix of ns, us, ms or
+    /// s (e.g. "500us", "10ms"); a bare number is in milliseconds.
+    #[arg(
+        long = "min-latency",
+        value_parser = parse_latency,
+        help = "Only report opens/syncs slower than this (e.g., 10ms)"
+    )]
+    pub min_latency_ns: Option<u64>,
+
+    /// Only report opens of files at least this large
+    ///
+    /// Opened files are statted during enrichment, at most
+    /// 1000 per second per worker; opens over that budget and other
+    /// events carry no size and are not reported.
+    #[arg(
+        long = "min-size",
+        value_parser = parse_size,
+        help = "Only report opens of files this large (e.g., 1G)"
+    )]
+    pub min_size: Option<u64>,
+
+    /// Only report opens of files at most this large (see --min-size)
+    #[arg(
+        long = "max-size",
+        value_parser = parse_size,
+        help = "Only report opens of files up to this size (e.g., 4K)"
+    )]
+    pub max_size: Option<u64>,
+
+    /// Only report opens of files modified less than this long ago
+    ///
+    /// The age is taken from the file's modification time when it is
+    /// opened; files are statted as for --min-size.
+    #[arg(
+        long = "newer-than",
+        value_parser = parse_timeout,
+        help = "Only report opens of files modified within this \
+                (e.g., 10s)"
+    )]
+    pub newer_than: Option<Duration>,
+
+    /// Types of file whose events are reported
+    ///
+    /// The type comes from the inode's mode bits when the file is
+    /// opened, so device nodes, sockets and fifos can be watched apart
+    /// from regular files. Events whose target type is unknown are
+    /// not reported.
+    #[arg(
+        long = "types",
+        value_parser = file_type_parser(),
+        value_delimiter = ',',
+        help = "Only report events on these types of file (e.g., file,dir)"
+    )]
+    pub types: Option<Vec<FileType>>,
+
+    /// Report files under /proc, /sys and /dev
+    ///
+    /// Reads of these pseudo-filesystems by system daemons make up
+    /// most of the events on a typical host, so they are left out by
+    /// default, in the kernel where the path is absolute. Watching
+    /// device nodes with --types device needs this flag.
+    #[arg(
+        long = "include-pseudo-fs",
+        help = "Also report files under /proc, /sys and /dev"
+    )]
+    pub include_pseudo_fs: bool,
+
+    /// Extended attribute namespaces whose changes are reported
+    ///
+    /// Attribute names are namespaced by their first component
+    /// (e.g. "security.selinux" is in "security").
+    #[arg(
+        long = "xattr-ns",
+        value_delimiter = ',',
+        default_value = "security,user",
+        help = "Report xattr changes in these namespaces \
+                (e.g., security,user,trusted)"
+    )]
+    pub xattr_namespaces: Vec<String>,
+
+    /// File mapping path prefixes to tags
+    ///
+    /// One prefix per line followed by its tags, e.g.
+    /// "/srv/billing team=payments class=pii". The longest matching
+    /// prefix wins for each tag.
+    #[arg(long = "tag-map", help = "Tag events by path prefix from this file")]
+    pub tag_map: Option<PathBuf>,
+
+    /// File of named path sets whose events are tagged "watchlist.NAME"
+    ///
+    /// Lines are a list name and a path glob, e.g. "pki /etc/pki/**",
+    /// as edited by `fw watchlist`. Filter, route or write severity
+    /// rules ("rule @pki any critical") on the tags. The file is
+    /// reloaded whenever it changes or fw receives SIGUSR1.
+    #[arg(
+        long = "watchlists",
+        value_name = "FILE",
+        help = "Tag events on the paths of these watchlists"
+    )]
+    pub watchlists: Option<PathBuf>,
+
+    /// Decoy files from `fw tripwire add`, every access to which is
+    /// critical
+    ///
+    /// Accesses are labelled by the policy rule "tripwire" and tagged
+    /// with the chain of processes behind them ("ancestry").
+    #[arg(
+        long = "tripwires",
+        value_name = "FILE",
+        help = "Alert on any access to the decoys in this file"
+    )]
+    pub tripwires: Option<PathBuf>,
+
+    /// Record the user and kernel stacks of accesses to tripwires
+    ///
+    /// As with policy rules capturing stacks, no other event gets one.
+    #[arg(
+        long = "tripwire-stacks",
+        requires = "tripwires",
+        help = "Record stacks of accesses to tripwires"
+    )]
+    pub tripwire_stacks: bool,
+
+    /// Built-in enrichers tagging every event
+    ///
+    /// "user" resolves the acting user and group to names (numeric
+    /// for processes in containers) and "service" tags the systemd
+    /// service the process runs in.
+    #[arg(
+        long = "enrich",
+        value_enum,
+        value_delimiter = ',',
+        help = "Tag events with these built-ins (e.g., user,service)"
+    )]
+    pub enrich: Vec<EnricherKind>,
+
+    /// Only report events carrying these tags
+    ///
+    /// Each condition is "key=value", or a bare "key" to require the
+    /// tag with any value. Repeat the flag to require several tags.
+    #[arg(
+        long = "tag",
+        value_parser = parse_tag,
+        help = "Only report events with this tag (e.g., team=payments)"
+    )]
+    pub tags: Vec<(String, Option<String>)>,
+
+    /// Policy classifying events by severity
+    ///
+    /// Replaces the built-in policy, which labels credential changes
+    /// critical, credential reads and system config changes warning,
+    /// and system config reads and user data changes notice. The policy
+    /// can also dedup and rate limit the alerts sent to --exec, --syslog,
+    /// --notify-* destinations and PagerDuty.
+    #[arg(
+        long = "severity-policy",
+        help = "Classify event severity with this policy file"
+    )]
+    pub severity_policy: Option<PathBuf>,
+
+    /// Only report events at least this severe
+    #[arg(
+        long = "min-severity",
+        value_parser = severity_parser(),
+        help = "Only report events at or above this severity"
+    )]
+    pub min_severity: Option<Severity>,
+
+    /// How much enrichment runs on every event
+    ///
+    /// "basic" applies the tag map, the --enrich built-ins and the
+    /// severity policy; "full" adds every built-in enricher; "off"
+    /// reports only what the kernel captured.
+    #[arg(
+        long = "enrichment",
+        value_enum,
+        default_value_t = EnrichmentLevel::Basic,
+        help = "Enrichment to run: off, basic or full"
+    )]
+    pub enrichment: EnrichmentLevel,
+
+    /// Number of threads enriching events
+    ///
+    /// Events of one process always go to the same worker, so they
+    /// stay in order.
+    #[arg(
+        long = "enrich-workers",
+        default_value_t = DEFAULT_ENRICH_WORKERS as u32,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Threads enriching events"
+    )]
+    pub enrich_workers: u32,
+
+    /// Percentage of dropped events that counts as overload
+    ///
+    /// When more events than this are dropped for several seconds in
+    /// a row, fw pauses enrichment (and with --overload-sampling then
+    /// samples events in the kernel at doubling rates), logging each
+    /// step; fidelity is restored once nothing has been dropped for a
+    /// while.
+    #[arg(
+        long = "overload-threshold",
+        default_value_t = DEFAULT_OVERLOAD_PERCENT,
+        value_parser = clap::value_parser!(u32).range(1..=100),
+        help = "Degrade fidelity when more than this % of events drop"
+    )]
+    pub overload_threshold: u32,
+
+    /// Keep full fidelity even while events are being dropped
+    #[arg(
+        long = "no-overload-protection",
+        conflicts_with = "overload_threshold",
+        help = "Never pause enrichment or sample events under load"
+    )]
+    pub no_overload_protection: bool,
+
+    /// Also sample events in the kernel when pausing enrichment isn't
+    /// enough
+    ///
+    /// Only 1 in 2, 4, ... 64 events is sent; events on paths the
+    /// severity policy rates warning or critical are always sent, and
+    /// stats count each sampled event as the events it stands for.
+    #[arg(
+        long = "overload-sampling",
+        conflicts_with = "no_overload_protection",
+        help = "Let overload protection sample events in the kernel"
+    )]
+    pub overload_sampling: bool,
+
+    /// Resume from maps pinned by a previous (crashed) run
+    ///
+    /// In-kernel state such as in-flight opens is kept in maps pinned
+    /// under /sys/fs/bpf/fw/<instance>. Without this flag, stale pins
+    /// are discarded and monitoring starts fresh.
+    #[arg(
+        long = "reuse-pinned",
+        help = "Reuse BPF maps pinned by a previous run of the instance"
+    )]
+    pub reuse_pinned: bool,
+
+    /// Name of this fw instance
+    ///
+    /// Each instance pins its maps in its own directory, so several
+    /// fw processes can run side by side. Defaults to a name derived
+    /// from the process ID; pass a stable name to use --reuse-pinned.
+    #[arg(
+        long = "instance",
+        conflicts_with = "shared",
+        help = "Instance name for pinned state (default: pid-<pid>)"
+    )]
+    pub instance: Option<String>,
+
+    /// Share one kernel deployment with other --shared instances
+    #[arg(
+        long = "shared",
+        help = "Join the kernel deployment shared by --shared instances"
+    )]
+    pub shared: bool,
+
+    /// Report files that were already open when monitoring started
+    ///
+    /// Open descriptors are always read from /proc/*/fd at startup so
+    /// their closes can be reported; this also emits one "already
+    /// open" event for each of them.
+    #[arg(
+        long = "snapshot",
+        help = "Emit an \"already open\" event for each file open at startup"
+    )]
+    pub snapshot: bool,
+
+    /// Load the probes from this eBPF object instead of the one built
+    /// into fw
+    ///
+    /// For developing the probes: point it at the object `cargo build`
+    /// writes in fw-ebpf to try changes without rebuilding fw. The object
+    /// must be built for the same event layout as fw.
+    #[arg(
+        long = "bpf-object",
+        help = "eBPF object to load instead of the embedded one"
+    )]
+    pub bpf_object: Option<PathBuf>,
+
+    /// Maximum number of process names kept in memory
+    ///
+    /// Names are read from /proc once per process and reused until
+    /// it exits; beyond this many processes the least recently seen
+    /// is forgotten.
+    #[arg(
+        long = "process-cache-size",
+        default_value_t = DEFAULT_PROCESS_CACHE_SIZE as u32,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Maximum process names cached"
+    )]
+    pub process_cache_size: u32,
+
+    /// What to report
+    ///
+    /// "sessions" correlates each open file's lifecycle and prints one
+    /// summary when it is closed: path, process, how long it was open
+    /// and the changes made in between. "stats" counts events per
+    /// --group-by group and prints the totals on exit.
+    #[arg(
+        long = "mode",
+        value_enum,
+        default_value = "events",
+        help = "Report events, open-to-close sessions or stats"
+    )]
+    pub mode: OutputMode,
+
+    /// Dimensions to group stats by (with --mode stats)
+    ///
+    /// Any of process, path, extension, action, user, group, dir-depth=N,
+    /// where N is how many directory levels are kept (e.g.
+    /// "/home/alice" for 2; "dir" keeps one, or builds a heat map
+    /// with --depth), severity, tag=KEY for the value of an
+    /// enrichment tag, and pattern for the access pattern found by
+    /// --access-patterns, which counts closed sessions instead of
+    /// events. Users and groups are numeric unless --enrich user
+    /// resolves their names.
+    #[arg(
+        long = "group-by",
+        value_delimiter = ',',
+        default_value = "process",
+        value_parser = Dimension::parse,
+        help = "Group stats by these dimensions \
+                (e.g., process,extension,dir-depth=2)"
+    )]
+    pub group_by: Vec<Dimension>,
+
+    /// Roll counts up the directory tree, this many levels deep
+    ///
+    /// Needs --group-by dir on its own. Every directory down to this
+    /// depth counts the events on files anywhere beneath it, and the
+    /// table lists the hottest subtrees first; a JSON --export is a
+    /// name/value/children tree for flame graph and treemap tools.
+    #[arg(
+        long = "depth",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Directory heat map this many levels deep (with \
+                --group-by dir)"
+    )]
+    pub depth: Option<u32>,
+
+    /// List the most contended files instead of grouped counts
+    ///
+    /// Needs --mode stats. Files are ranked by the lock requests that
+    /// waited for another holder or were refused, then by the time
+    /// spent waiting; the table also shows every lock request and the
+    /// longest wait.
+    #[arg(
+        long = "contention",
+        conflicts_with = "depth",
+        help = "Rank files by lock contention (with --mode stats)"
+    )]
+    pub contention: bool,
+
+    /// File to write the final stats to on exit (with --mode stats),
+    /// or every session to as a JSON line (with --mode sessions)
+    #[arg(long = "export", help = "Export final stats to this file")]
+    pub export: Option<PathBuf>,
+
+    /// Format of the --export file (default: from its extension)
+    #[arg(long = "export-format", value_enum, help = "Export format")]
+    pub export_format: Option<ExportFormat>,
+
+    /// Count events in the kernel instead of sending them to fw
+    ///
+    /// The probes keep one counter per process name, extension and
+    /// action and fw reads and resets them every --agg-interval, for
+    /// stats at close to no overhead. Implies --mode stats; only
+    /// process, extension and action can be grouped by, and options
+    /// that need each event in fw are not available.
+    #[arg(
+        long = "kernel-agg",
+        conflicts_with_all = [
+            "mode", "extensions", "name_globs", "path_globs", "mounts",
+            "fs_types", "remote_only", "local_only", "min_latency_ns",
+            "tag_map", "watchlists", "tripwires", "enrich", "tags",
+            "severity_policy",
+            "min_severity", "min_size", "max_size", "newer_than",
+            "types", "snapshot", "exec", "schedule", "contention",
+            "format", "syslog", "redact", "anonymize_home",
+            "process_list_file", "baseline", "exclude_file",
+            "notify_webhooks", "notify_smtp", "pagerduty_key_file",
+            "routes", "template", "influx_url", "verify_with_inotify",
+        ],
+        help = "Count events in the kernel (stats only)"
+    )]
+    pub kernel_agg: bool,
+
+    /// How often kernel counters are read and reset (with --kernel-agg)
+    #[arg(
+        long = "agg-interval",
+        default_value = "10s",
+        value_parser = parse_timeout,
+        requires = "kernel_agg",
+        help = "Read kernel counters this often"
+    )]
+    pub agg_interval: Duration,
+
+    /// Trace reads and writes to classify how each file is accessed
+    ///
+    /// Sessions then show whether the file was read and written
+    /// sequentially or at random and which direction dominated, and
+    /// stats can be grouped by pattern. Reads and writes happen far
+    /// more often than opens, so this costs noticeably more; needs
+    /// --mode sessions or --mode stats.
+    #[arg(
+        long = "access-patterns",
+        conflicts_with_all = ["kernel_agg", "exec"],
+        help = "Classify sequential/random, read/write-heavy access"
+    )]
+    pub access_patterns: bool,
+
+    /// Record the stack of the call behind each event
+    ///
+    /// User stacks show which code path in the program touched the
+    /// file; kernel stacks show the path through the kernel. Only
+    /// events that are reported are symbolized, using the symbols and
+    /// debug info of the program's mapped files and /proc/kallsyms.
+    /// Frames are listed under each event line and added to exported
+    /// session JSON. Severity rules with capture_stack=true record
+    /// stacks without this flag, and only for the events they match.
+    #[arg(
+        long = "stacks",
+        value_enum,
+        conflicts_with = "kernel_agg",
+        help = "Record user, kernel or both stacks with each event"
+    )]
+    pub stacks: Option<StackMode>,
+
+    /// Command to run for every reported event
+    ///
+    /// The command is split on whitespace and run without a shell;
+    /// {path}, {pid}, {action} and {program} are replaced with the
+    /// event's values, and {stack} with its stack as JSON.
+    #[arg(
+        long = "exec",
+        help = "Run a command per event (e.g., 'logger {action} {path}')"
+    )]
+    pub exec: Option<String>,
+
+    /// Maximum number of --exec commands running at once
+    ///
+    /// Events arriving while all are busy are skipped for the hook.
+    #[arg(
+        long = "exec-concurrency",
+        default_value_t = DEFAULT_EXEC_CONCURRENCY,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Maximum --exec commands running at once"
+    )]
+    pub exec_concurrency: u32,
+
+    /// Time an --exec command may run before it is killed
+    #[arg(
+        long = "exec-timeout",
+        default_value = "10s",
+        value_parser = parse_timeout,
+        help = "Kill --exec commands running longer than this"
+    )]
+    pub exec_timeout: Duration,
+
+    /// Also log every reported event to the local syslog daemon
+    ///
+    /// Each event's severity becomes the syslog severity (critical is
+    /// crit, warning is warning, notice is notice, anything else
+    /// info), so rsyslog or journald rules can route alerts by
+    /// priority.
+    #[arg(long = "syslog", help = "Log reported events to syslog (/dev/log)")]
+    pub syslog: bool,
+
+    /// Syslog facility events are logged under (with --syslog)
+    #[arg(
+        long = "syslog-facility",
+        value_enum,
+        default_value_t = Facility::User,
+        requires = "syslog",
+        help = "Syslog facility: user, auth or local0-local7"
+    )]
+    pub syslog_facility: Facility,
+
+    /// Post serious events to a Slack or Teams incoming webhook
+    ///
+    /// Events at least --notify-min-severity are rendered with
+    /// --notify-template and posted as {"text": ...}, which both accept.
+    /// May be repeated; the server must present a certificate signed by
+    /// a CA in --notify-ca.
+    #[arg(
+        long = "notify-webhook",
+        value_name = "URL",
+        value_parser = WebhookUrl::parse,
+        help = "Post serious events to this https webhook (Slack, Teams)"
+    )]
+    pub notify_webhooks: Vec<WebhookUrl>,
+
+    /// Mail serious events through this SMTP server
+    ///
+    /// Given as "host:port", or "host" for port 587. Port 465 speaks TLS
+    /// from the start; any other port must offer STARTTLS, and mail is
+    /// never sent in the clear.
+    #[arg(
+        long = "notify-smtp",
+        value_name = "HOST[:PORT]",
+        value_parser = SmtpConfig::parse_server,
+        requires_all = ["notify_from", "notify_to"],
+        help = "Mail serious events through this SMTP server (TLS)"
+    )]
+    pub notify_smtp: Option<(String, u16)>,
+
+    /// User to log in to the SMTP server as (with --notify-smtp)
+    #[arg(
+        long = "notify-smtp-user",
+        requires_all = ["notify_smtp", "notify_smtp_password_file"],
+        help = "Log in to the SMTP server as this user"
+    )]
+    pub notify_smtp_user: Option<String>,
+
+    /// File whose first line is the SMTP password, kept out of `ps`
+    #[arg(
+        long = "notify-smtp-password-file",
+        requires = "notify_smtp_user",
+        help = "Read the SMTP password from this file"
+    )]
+    pub notify_smtp_password_file: Option<PathBuf>,
+
+    /// Sender address of notification mail
+    #[arg(
+        long = "notify-from",
+        value_name = "ADDRESS",
+        requires = "notify_smtp",
+        help = "Send notification mail from this address"
+    )]
+    pub notify_from: Option<String>,
+
+    /// Recipient of notification mail; may be repeated
+    #[arg(
+        long = "notify-to",
+        value_name = "ADDRESS",
+        requires = "notify_smtp",
+        help = "Send notification mail to this address"
+    )]
+    pub notify_to: Vec<String>,
+
+    /// Least severe events notified about
+    #[arg(
+        long = "notify-min-severity",
+        value_parser = severity_parser(),
+        default_value_t = Severity::Warning,
+        help = "Only notify about events at least this severe"
+    )]
+    pub notify_min_severity: Severity,
+
+    /// Message sent for each event
+    ///
+    /// Placeholders are {time}, {host}, {severity}, {rule} (the policy
+    /// rule that labelled the event), {program}, {pid}, {action} and
+    /// {path}. Mail uses it as the subject.
+    #[arg(
+        long = "notify-template",
+        default_value = DEFAULT_NOTIFY_TEMPLATE,
+        value_parser = NotifyTemplate::parse,
+        help = "Notification message, with {severity}, {rule}, {path}, ..."
+    )]
+    pub notify_template: NotifyTemplate,
+
+    /// CA certificates (PEM) webhook, mail, PagerDuty and InfluxDB
+    /// servers are checked against
+    #[arg(
+        long = "notify-ca",
+        default_value = DEFAULT_CA_BUNDLE,
+        help = "Check notification servers against these CA certificates"
+    )]
+    pub notify_ca: PathBuf,
+
+    /// Page on-call through PagerDuty with the integration key in a file
+    ///
+    /// Events at least --pagerduty-min-severity trigger an Events v2
+    /// alert keyed by policy rule and path, so repeats land in one
+    /// incident; --notify-template gives its summary. Incidents of rules
+    /// with "resolve_after=DURATION" are resolved once the rule has been
+    /// quiet on the path that long.
+    #[arg(
+        long = "pagerduty-routing-key-file",
+        value_name = "FILE",
+        help = "Page via PagerDuty with the routing key in this file"
+    )]
+    pub pagerduty_key_file: Option<PathBuf>,
+
+    /// Least severe events paged about
+    #[arg(
+        long = "pagerduty-min-severity",
+        value_parser = severity_parser(),
+        default_value_t = Severity::Critical,
+        requires = "pagerduty_key_file",
+        help = "Only page about events at least this severe"
+    )]
+    pub pagerduty_min_severity: Severity,
+
+    /// Events v2 endpoint, e.g. the EU service region's
+    #[arg(
+        long = "pagerduty-url",
+        default_value = DEFAULT_PAGERDUTY_URL,
+        value_parser = WebhookUrl::parse,
+        requires = "pagerduty_key_file",
+        help = "PagerDuty Events v2 endpoint"
+    )]
+    pub pagerduty_url: WebhookUrl,
+
+    /// Push event counts to this InfluxDB line protocol write endpoint
+    ///
+    /// E.g. "https://influx:8086/api/v2/write?org=ops&bucket=fw" for
+    /// InfluxDB 2, ".../write?db=fw" for 1.x, or VictoriaMetrics'
+    /// "/write". Every --influx-interval, one point per
+    /// --influx-group-by group counts the events since the last push,
+    /// tagged with the host name; drops are pushed as fw_dropped.
+    #[arg(
+        long = "influx-url",
+        value_name = "URL",
+        value_parser = WebhookUrl::parse,
+        help = "Push per-interval event counts to this InfluxDB endpoint"
+    )]
+    pub influx_url: Option<WebhookUrl>,
+
+    /// File holding the API token, sent as "Authorization: Token ..."
+    #[arg(
+        long = "influx-token-file",
+        value_name = "FILE",
+        requires = "influx_url",
+        help = "InfluxDB API token file"
+    )]
+    pub influx_token_file: Option<PathBuf>,
+
+    /// Time between pushes
+    #[arg(
+        long = "influx-interval",
+        default_value = DEFAULT_INFLUX_INTERVAL,
+        value_parser = parse_timeout,
+        requires = "influx_url",
+        help = "Time between pushes (e.g., 10s)"
+    )]
+    pub influx_interval: Duration,
+
+    /// Dimensions the pushed counts are tagged with, as for --group-by
+    #[arg(
+        long = "influx-group-by",
+        value_delimiter = ',',
+        default_value = DEFAULT_INFLUX_GROUP_BY,
+        value_parser = Dimension::parse,
+        requires = "influx_url",
+        help = "Tag pushed counts with these dimensions"
+    )]
+    pub influx_group_by: Vec<Dimension>,
+
+    /// Leave out the processes, directories and extensions listed in a
+    /// file, e.g. one written by `fw suggest-filters`
+    ///
+    /// Lines are "process NAME", "path DIR" or "extension EXT";
+    /// blank lines and lines starting with '#' are ignored.
+    #[arg(
+        long = "exclude-file",
+        help = "Leave out the noise listed in this file"
+    )]
+    pub exclude_file: Option<PathBuf>,
+
+    /// Alert on accesses outside a profile from `fw baseline learn`
+    ///
+    /// An alert line is written the first time a program accesses a
+    /// directory it never accessed while learning, or a program missing
+    /// from the profile accesses anything. Alerts go to stderr, or to
+    /// the stream not carrying events with --output-stream.
+    #[arg(
+        long = "baseline",
+        help = "Alert on accesses outside this baseline profile"
+    )]
+    pub baseline: Option<PathBuf>,
+
+    /// Cross-check the probes against inotify watches on directories
+    ///
+    /// Changes to files directly in the directories that one source
+    /// reports and the other doesn't within five seconds are written as
+    /// alert lines, to stderr or the stream not carrying events, since
+    /// they point at tampering or lost events. Deletions aren't compared.
+    #[arg(
+        long = "verify-with-inotify",
+        value_name = "DIRS",
+        value_delimiter = ',',
+        help = "Alert on changes in these directories only one of the \
+                probes and inotify saw"
+    )]
+    pub verify_with_inotify: Vec<PathBuf>,
+
+    /// Give one output its own slice of the events, on top of the
+    /// filter every output shares
+    ///
+    /// SINK is output, exec, syslog, notify, pagerduty, baseline or
+    /// influx, and CRITERIA comma-separated severity=LEVEL,
+    /// action=ACTION, ext=EXT, name=GLOB, path=GLOB or tag=KEY[=VALUE];
+    /// repeated keys widen, different keys must all match. E.g.
+    /// "output:action=write" writes only changes while the alert sinks
+    /// still see every event.
+    #[arg(
+        long = "route",
+        value_name = "SINK:CRITERIA",
+        value_parser = Route::parse,
+        help = "Only send events matching CRITERIA to SINK (repeatable)"
+    )]
+    pub routes: Vec<Route>,
+
+    /// Rewrite paths matching a regex before any output sees them
+    ///
+    /// Given as "REGEX=>REPLACEMENT" and applied in order; the
+    /// replacement may use $1-style groups and {hash}, a salted hash of
+    /// the first group (or the whole match) that stays the same for the
+    /// same value. Filters still match the original paths.
+    #[arg(
+        long = "redact",
+        value_name = "REGEX=>REPLACEMENT",
+        help = "Redact paths matching REGEX (repeatable)"
+    )]
+    pub redact: Vec<String>,
+
+    /// Replace the user name in /home/<user> paths ("user", or its
+    /// hash with --redact-salt-file)
+    #[arg(long = "anonymize-home", help = "Hide user names in home paths")]
+    pub anonymize_home: bool,
+
+    /// File holding the secret salt {hash} is keyed with
+    #[arg(
+        long = "redact-salt-file",
+        help = "Salt for hashing redacted values"
+    )]
+    pub redact_salt_file: Option<PathBuf>,
+
+    /// Only monitor during these daily windows (local time)
+    ///
+    /// Probes are attached when a window opens and detached when it
+    /// closes, so nothing runs in the kernel in between. Windows ending
+    /// before they start run past midnight (e.g. "22:00-02:00").
+    #[arg(
+        long = "schedule",
+        value_parser = Schedule::parse,
+        help = "Only monitor during these windows (e.g., 02:00-04:00)"
+    )]
+    pub schedule: Option<Schedule>,
+
+    /// Directory to spool output to while it can't be written
+    ///
+    /// Lines that fail to write (e.g. a broken pipe to a shipper) are
+    /// appended to segment files here and delivered in order once the
+    /// output recovers, including by the next run.
+    #[arg(
+        long = "spool-dir",
+        help = "Spool undeliverable output here until it recovers"
+    )]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Largest total size of the spool
+    ///
+    /// Accepts a number of bytes with an optional K, M or G suffix.
+    #[arg(
+        long = "spool-max-size",
+        default_value_t = DEFAULT_SPOOL_MAX_BYTES,
+        value_parser = parse_size,
+        requires = "spool_dir",
+        help = "Largest spool size (e.g., 64M)"
+    )]
+    pub spool_max_bytes: u64,
+
+    /// What to discard once the spool is full
+    #[arg(
+        long = "spool-drop",
+        value_enum,
+        default_value = "oldest",
+        requires = "spool_dir",
+        help = "Drop the oldest or the newest lines when the spool is full"
+    )]
+    pub spool_drop: DropPolicy,
+
+    /// File to write output to instead of stderr
+    #[arg(
+        short = 'o',
+        long = "output",
+        help = "Write output to this file instead of stderr"
+    )]
+    pub output: Option<PathBuf>,
+
+    /// Standard stream to write output to (default stderr)
+    ///
+    /// Choosing a stream explicitly also moves diagnostics and the
+    /// filter summary to the other one, so `fw collect
+    /// --output-stream stdout | grep ...` sees only events.
+    #[arg(
+        long = "output-stream",
+        value_enum,
+        conflicts_with = "output",
+        help = "Write output to stdout or stderr"
+    )]
+    pub output_stream: Option<OutputStream>,
+
+    /// Keep fw's own footprint out of the record during an incident
+    ///
+    /// fw writes nothing to disk and opens no sockets: outputs, spools,
+    /// exports, pins and --log-file are refused, as are exec, syslog,
+    /// notify, paging, InfluxDB and health endpoints. Events carry only
+    /// what the kernel reports; /proc is read just once per process, for
+    /// its name and working directory. Output is held in memory and
+    /// written to the output stream on SIGUSR2 and on exit, so only the
+    /// line formats can be used.
+    #[arg(
+        long = "forensic",
+        conflicts_with_all = [
+            "output", "export", "spool_dir", "log_file", "reuse_pinned",
+            "instance", "shared", "snapshot", "stacks", "tripwire_stacks",
+            "exec", "syslog", "notify_webhooks", "notify_smtp",
+            "pagerduty_key_file", "influx_url", "health_addr",
+        ],
+        help = "Stay off disk, holding output in memory; /proc is read once \
+                per process for its name and working directory"
+    )]
+    pub forensic: bool,
+
+    /// Most output held in memory with --forensic
+    ///
+    /// Once full, the oldest lines are dropped. Accepts a number of bytes
+    /// with an optional K, M or G suffix.
+    #[arg(
+        long = "forensic-buffer",
+        default_value_t = DEFAULT_FORENSIC_BUFFER_BYTES,
+        value_parser = parse_size,
+        requires = "forensic",
+        help = "Most output held in memory (e.g., 256M)"
+    )]
+    pub forensic_buffer: u64,
+
+    /// How each event is written (with --mode events)
+    ///
+    /// "ecs-json" writes one Elastic Common Schema object per line and
+    /// "cef" one ArcSight Common Event Format record, with fw's fields
+    /// under the standard names (file.path, process.pid, event.action,
+    /// ...) so a SIEM can ingest them without a transformation layer.
+    /// "template" writes the layout given with --template. "msgpack",
+    /// "cbor" and "proto" (protocol buffers, schema in fw/proto/) write
+    /// length-prefixed binary frames, cheaper to encode than JSON for
+    /// high event rates; `fw decode` turns them back into JSON.
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value_t = EventFormat::Text,
+        help = "Event format: text, ecs-json, cef, template, msgpack, cbor \
+                or proto"
+    )]
+    pub format: EventFormat,
+
+    /// Layout of each event line (with --format template)
+    ///
+    /// Placeholders such as {comm} or {path} name event fields: ts,
+    /// comm, pid, uid, gid, user, group, action, path, truncated, mount,
+    /// fstype, remote, layer, latency, latency_ns, id, fd, link, from,
+    /// flags, xattr, tags, tag.KEY, severity, rule, type, size, mtime
+    /// and stack. {ts:%H:%M:%S} and {mtime:...} take a strftime format
+    /// (UTC), other fields a padding spec such as {comm:<16},
+    /// {pid:>6} or {path:40.40}; missing fields are written as "-" and
+    /// {{ and }} are literal braces.
+    #[arg(
+        long = "template",
+        value_parser = EventTemplate::parse,
+        required_if_eq("format", "template"),
+        help = "Event line layout, e.g. '{ts} {comm}[{pid}] {action} {path}'"
+    )]
+    pub template: Option<EventTemplate>,
+
+    /// Compress the --output file as it is written
+    ///
+    /// The file stays readable with zcat or zstdcat and by fw report,
+    /// up to the last flush, even if fw is killed.
+    #[arg(
+        long = "compress",
+        value_enum,
+        requires = "output",
+        help = "Compress the output file"
+    )]
+    pub compress: Option<Compression>,
+
+    /// Compression level (gzip 0-9, default 6; zstd 1-22, default 3)
+    #[arg(
+        long = "compress-level",
+        requires = "compress",
+        allow_negative_numbers = false,
+        help = "Compression level for --compress"
+    )]
+    pub compress_level: Option<i32>,
+
+    /// Validate the options and print the plan instead of monitoring
+    ///
+    /// Shows the probes and uid filter entries pushed into the kernel,
+    /// the criteria evaluated in userspace, the enrichment stages and
+    /// the output. No eBPF programs are loaded.
+    #[arg(long = "dry-run", help = "Print the filter plan without monitoring")]
+    pub dry_run: bool,
+
+    /// Path to run through the plan (with --dry-run)
+    ///
+    /// Each path is tested as if fw itself opened it, and the stage
+    /// that would drop the event is named. Repeat for several paths.
+    #[arg(
+        long = "test-path",
+        requires = "dry_run",
+        help = "Show whether an open of this path would be reported"
+    )]
+    pub test_paths: Vec<String>,
+
+    /// Serve /healthz and /readyz on this address
+    ///
+    /// /healthz fails once the event loop stops recording heartbeats
+    /// for --live-after; /readyz also fails while probes are detached
+    /// or heartbeats are older than --ready-after. Both answer with
+    /// 503 and the reason.
+    #[arg(
+        long = "health-addr",
+        help = "Serve /healthz and /readyz here (e.g., 0.0.0.0:9090)"
+    )]
+    pub health_addr: Option<SocketAddr>,
+
+    /// Heartbeat age after which /healthz reports the loop stalled
+    #[arg(
+        long = "live-after",
+        default_value = "30s",
+        value_parser = parse_timeout,
+        requires = "health_addr",
+        help = "Fail /healthz once heartbeats are this old"
+    )]
+    pub live_after: Duration,
+
+    /// Heartbeat age after which /readyz reports not ready
+    #[arg(
+        long = "ready-after",
+        default_value = "5s",
+        value_parser = parse_timeout,
+        requires = "health_addr",
+        help = "Fail /readyz once heartbeats are this old"
+    )]
+    pub ready_after: Duration,
+}
+
+/// Build the command definition without parsing any arguments
+///
+/// # Returns
+/// * `Command` - Definition of `fw` and its subcommands
+pub fn command() -> Command {
+    Cli::command()
+}
+
+/// Write a shell completion script
+///
+/// # Arguments
+/// * `shell` - Shell to generate completions for
+/// * `out` - Destination for the script
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let mut command = command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
+
+/// Write the man page in roff format
+///
+/// # Arguments
+/// * `out` - Destination for the page
+///
+/// # Returns
+/// * `io::Result<()>` - Success or write error
+pub fn write_man_page(out: &mut dyn Write) -> io::Result<()> {
+    clap_mangen::Man::new(command()).render(out)
+}
+
+/// Parser for `--types`, listing the type names in help and errors
+///
+/// `FileType` lives in fw-common, which doesn't depend on clap.
+///
+/// # Returns
+/// * `impl TypedValueParser` - Parser producing a `FileType`
+fn file_type_parser() -> impl TypedValueParser<Value = FileType> {
+    PossibleValuesParser::new(FileType::ALL.map(|t| t.name()))
+        .try_map(|name| name.parse::<FileType>())
+}
+
+/// Parser for the severity options, listing the names in help and errors
+///
+/// `Severity` lives in fw-common, which doesn't depend on clap.
+///
+/// # Returns
+/// * `impl TypedValueParser` - Parser producing a `Severity`
+fn severity_parser() -> impl TypedValueParser<Value = Severity> {
+    PossibleValuesParser::new(Severity::ALL.map(|s| s.name()))
+        .try_map(|name| name.parse::<Severity>())
+}
+
+/// Parse a latency such as "10ms" into nanoseconds
+///
+/// # Arguments
+/// * `value` - Number with an optional ns, us, ms or s suffix; bare
+///   numbers are milliseconds
+///
+/// # Returns
+/// * `Result<u64, String>` - Latency in nanoseconds or a usage error
+fn parse_latency(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(value, "latency")?;
+    let scale = match unit {
+        "ns" => 1.0,
+        "us" => 1e3,
+        "" | "ms" => 1e6,
+        "s" => 1e9,
+        _ => return Err(format!("unknown latency unit '{}'", unit)),
+    };
+    Ok((number * scale) as u64)
+}
+
+/// Parse a timeout such as "60s" or "5m"
+///
+/// # Arguments
+/// * `value` - Number with an optional ms, s, m or h suffix; bare numbers
+///   are seconds
+///
+/// # Returns
+/// * `Result<Duration, String>` - Timeout or a usage error
+pub(crate) fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let (number, unit) = split_unit(value, "timeout")?;
+    let scale = match unit {
+        "ms" => 1e-3,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown timeout unit '{}'", unit)),
+    };
+    Ok(Duration::from_secs_f64(number * scale))
+}
+
+/// Parse a retention age such as "30d" or "12h"
+///
+/// # Arguments
+/// * `value` - Number with a d suffix, or anything [`parse_timeout`]
+///   accepts
+///
+/// # Returns
+/// * `Result<Duration, String>` - Age or a usage error
+pub(crate) fn parse_age(value: &str) -> Result<Duration, String> {
+    match value.trim().strip_suffix('d') {
+        Some(days) => {
+            let (number, unit) = split_unit(days, "age")?;
+            if !unit.is_empty() {
+                return Err(format!("unknown age unit '{}d'", unit));
+            }
+            Ok(Duration::from_secs_f64(number * 86400.0))
+        }
+        None => parse_timeout(value),
+    }
+}
+
+/// Parse a size such as "64M" or "512K"
+///
+/// # Arguments
+/// * `value` - Number with an optional K, M or G suffix; bare numbers are
+///   bytes
+///
+/// # Returns
+/// * `Result<u64, String>` - Size in bytes or a usage error
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(value, "size")?;
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" => 1024.0,
+        "M" => 1024.0 * 1024.0,
+        "G" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("unknown size unit '{}'", unit)),
+    };
+    Ok((number * scale) as u64)
+}
+
+/// Parse a replay speed such as "10x" or "max"
+///
+/// # Arguments
+/// * `value` - "max", or a multiple from 0.001 to 1000000 with an
+///   optional x suffix
+///
+/// # Returns
+/// * `Result<ReplaySpeed, String>` - Speed or a usage error
+fn parse_speed(value: &str) -> Result<ReplaySpeed, String> {
+    if value.trim().eq_ignore_ascii_case("max") {
+        return Ok(ReplaySpeed::Max);
+    }
+    let (number, unit) = split_unit(value, "speed")?;
+    if !matches!(unit, "" | "x") {
+        return Err(format!(
+            "invalid speed '{}'; use e.g. 10x, 0.5x or max",
+            value
+        ));
+    }
+    if !(MIN_REPLAY_FACTOR..=MAX_REPLAY_FACTOR).contains(&number) {
+        return Err(format!(
+            "speed '{}' out of range; use {}x to {}x, or max",
+            value, MIN_REPLAY_FACTOR, MAX_REPLAY_FACTOR
+        ));
+    }
+    Ok(ReplaySpeed::Factor(number))
+}
+
+/// Parse a point in time such as "2024-01-01T12:00:00Z"
+///
+/// # Arguments
+/// * `value` - RFC 3339 time, or "YYYY-MM-DD HH:MM:SS" in UTC as fw
+///   prints it (with or without the " UTC" suffix)
+///
+/// # Returns
+/// * `Result<DateTime<Utc>, String>` - Time or a usage error
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.to_utc());
+    }
+    let naive = value.strip_suffix(" UTC").unwrap_or(value);
+    NaiveDateTime::parse_from_str(naive, "%Y-%m-%d %H:%M:%S")
+        .map(|time| time.and_utc())
+        .map_err(|_| {
+            format!("invalid time '{}'; use e.g. 2024-01-01T12:00:00Z", value)
+        })
+}
+
+/// Split a value like "1.5ms" into its number and unit suffix
+///
+/// # Arguments
+/// * `value` - Number with an optional unit suffix
+/// * `what` - Name of the value for error messages
+///
+/// # Returns
+/// * `Result<(f64, &str), String>` - Number and unit, or a usage error
+fn split_unit<'a>(
+    value: &'a str,
+    what: &str,
+) -> Result<(f64, &'a str), String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse()
+        .map_err(|_| format!("invalid {} '{}'", what, value))?;
+    Ok((number, unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_generates_completions_and_man_page() {
+        command().debug_assert();
+
+        let mut script = Vec::new();
+        write_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("wait-for"));
+
+        let mut page = Vec::new();
+        write_man_page(&mut page).unwrap();
+        assert!(String::from_utf8(page).unwrap().starts_with(".ie"));
+    }
+
+    #[test]
+    fn test_parse_latency() {
+        assert_eq!(parse_latency("250ns"), Ok(250));
+        assert_eq!(parse_latency("500us"), Ok(500_000));
+        assert_eq!(parse_latency("1.5ms"), Ok(1_500_000));
+        assert_eq!(parse_latency("10"), Ok(10_000_000));
+        assert_eq!(parse_latency("2s"), Ok(2_000_000_000));
+        assert!(parse_latency("10m").is_err());
+        assert!(parse_latency("ms").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_timeout("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_timeout("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_timeout("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_timeout("1d").is_err());
+    }
+
+    #[test]
+    fn test_parse_replay_options() {
+        assert_eq!(parse_speed("max"), Ok(ReplaySpeed::Max));
+        assert_eq!(parse_speed("10x"), Ok(ReplaySpeed::Factor(10.0)));
+        assert_eq!(parse_speed("0.5"), Ok(ReplaySpeed::Factor(0.5)));
+        assert!(parse_speed("0x").is_err());
+        assert!(parse_speed("0.0000001x").is_err());
+        assert!(parse_speed("1e300x").is_err());
+        assert!(parse_speed("99999999x").is_err());
+        assert!(parse_speed("fast").is_err());
+
+        let noon = parse_timestamp("2024-01-01T12:00:00Z").unwrap();
+        assert_eq!(parse_timestamp("2024-01-01 12:00:00 UTC"), Ok(noon));
+        assert_eq!(parse_timestamp("2024-01-01T13:00:00+01:00"), Ok(noon));
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 86400)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert!(parse_age("1dd").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("64M"), Ok(64 * 1024 * 1024));
+        assert_eq!(parse_size("1.5g"), Ok(1536 * 1024 * 1024));
+        assert!(parse_size("10T").is_err());
+    }
 }
@@ -0,0 +1,137 @@
+//! Clock module
+//!
+//! Source of the wall-clock time stamped on events and checkpoints. The
+//! pipeline reads the time through the `Clock` trait instead of calling
+//! `Utc::now` directly, so tests and synthetic event streams can swap in a
+//! `MockClock` and get the same timestamps on every run.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Read the current time
+    ///
+    /// # Returns
+    /// * `DateTime<Utc>` - Current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's real-time clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Shared handle to the system clock, the default everywhere
+///
+/// # Returns
+/// * `Arc<dyn Clock>` - System clock
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Clock that only moves when told to
+///
+/// Every reading can optionally advance it by a fixed step, so events
+/// stamped one after another get distinct, predictable times.
+#[derive(Debug)]
+pub struct MockClock {
+    /// Time the next reading returns
+    now: Mutex<DateTime<Utc>>,
+    /// Amount added after each reading
+    step: TimeDelta,
+}
+
+impl MockClock {
+    /// Create a clock standing still at a fixed time
+    ///
+    /// # Arguments
+    /// * `start` - Time of the first reading
+    ///
+    /// # Returns
+    /// * `MockClock` - New clock
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(start),
+            step: TimeDelta::zero(),
+        }
+    }
+
+    /// Advance the clock by a fixed step after every reading
+    ///
+    /// # Arguments
+    /// * `step` - Time between consecutive readings
+    ///
+    /// # Returns
+    /// * `MockClock` - The clock with the step set
+    pub fn with_step(mut self, step: Duration) -> Self {
+        self.step = TimeDelta::from_std(step).unwrap_or(TimeDelta::MAX);
+        self
+    }
+
+    /// Move the clock to a new time
+    ///
+    /// # Arguments
+    /// * `time` - Time the next reading returns
+    pub fn set(&self, time: DateTime<Utc>) {
+        *self.lock() = time;
+    }
+
+    /// Move the clock forward
+    ///
+    /// # Arguments
+    /// * `by` - Time to add
+    pub fn advance(&self, by: Duration) {
+        let by = TimeDelta::from_std(by).unwrap_or(TimeDelta::MAX);
+        let mut now = self.lock();
+        *now = now
+            .checked_add_signed(by)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    }
+
+    /// Lock the current time, ignoring poisoning by a panicked reader
+    fn lock(&self) -> std::sync::MutexGuard<'_, DateTime<Utc>> {
+        self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let mut now = self.lock();
+        let time = *now;
+        *now = time
+            .checked_add_signed(self.step)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_mock_clock_steps() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = MockClock::new(start).with_step(Duration::from_millis(5));
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.now(), start + TimeDelta::milliseconds(5));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(clock.now(), start + TimeDelta::milliseconds(60_010));
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+
+        // Standing still by default
+        let still = MockClock::new(start);
+        assert_eq!(still.now(), still.now());
+    }
+}
@@ -2,130 +2,1133 @@
 //!
 //! Orchestrates the file monitoring process by coordinating between the eBPF
 //! monitor and event processing. Handles signal interruption (Ctrl+C) and
-//! manages the event filtering and output.
+//! manages the event filtering and output, either to a single writer or
+//! fanned out to several independent subscribers.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
 use log::{info, warn};
+use std::fmt::{self, Write as _};
+use std::future::Future;
 use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
 use tokio::signal;
+use tokio::sync::{mpsc, watch};
 
+use crate::audit_format::EventFormat;
+use crate::baseline::{AnomalySink, BaselineConfig};
+use crate::bpf_object::BpfObject;
+use crate::compression::OutputFile;
 use crate::ebpf_monitor::EbpfMonitor;
+use crate::enrich::{EnrichConfig, Enrichers, EnrichmentLevel};
+use crate::enrich_pool::{EnrichPool, ENRICH_QUEUE_CAPACITY};
+use crate::exec_hook::{ExecConfig, ExecSink};
+use crate::fanout::{
+    FanOut, SinkReport, Subscriber, TextSink, FANOUT_CAPACITY,
+};
 use crate::file_event::FileEvent;
+use crate::filter::FilterSpec;
+use crate::forensic::{self, Exporter, MemoryBuffer};
+use crate::forward::{ForwardConfig, ForwardConnection, ForwardSink};
+use crate::health::{self, Health, HealthServerConfig, HEARTBEAT_INTERVAL};
+use crate::influx::{InfluxConfig, InfluxSink};
+use crate::inotify_verify::VerifySink;
+use crate::kernel_agg::run_kernel_stats;
+use crate::monitor_backend::MonitorBackend;
+use crate::mount_table::{self, MountTable};
+use crate::notify::{NotifyConfig, NotifySink};
+use crate::overload::{self, OverloadConfig, OverloadController};
+use crate::pagerduty::{PagerDutyConfig, PagerDutySink};
+use crate::pinning::PinDir;
+use crate::process_cache::DEFAULT_PROCESS_CACHE_SIZE;
+use crate::process_list::{self, ListWatcher, ProcessList, ProcessListConfig};
+use crate::record::{RecordConfig, RecordSink};
+use crate::redact::{RedactConfig, RedactingSink, Redactor};
+use crate::remote_monitor::{RemoteConfig, RemoteMonitor};
+use crate::rename_chain::RenameCorrelator;
+use crate::retention::{self, PruneConfig};
+use crate::rollup::RollupSink;
+use crate::route::{Route, Routes, SinkKind};
+use crate::schedule::Schedule;
+use crate::session::SessionSink;
+use crate::spool::{SpoolConfig, SpoolWriter};
+use crate::stacks::{StackMode, SymbolizingSink};
+use crate::stats::{StatsConfig, StatsSink};
+use crate::syslog::{SyslogConfig, SyslogSink};
+use crate::systemd::{self, Notifier};
+use crate::template::EventTemplate;
+use crate::throttle::{AlertLimits, ThrottlingSink};
+use crate::user_filter::UserFilter;
+use crate::watchlist::{self, WatchlistWatcher, Watchlists};
+
+/// How `fw collect` reports what it sees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// One line per event
+    #[default]
+    Events,
+    /// One summary line per open file, written when it is closed
+    Sessions,
+    /// Event counts per group, written on exit
+    Stats,
+}
+
+/// Standard stream `fw collect` writes events to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputStream {
+    /// Standard output, for piping into other tools
+    Stdout,
+    /// Standard error (the historical default)
+    #[default]
+    Stderr,
+}
+
+impl OutputStream {
+    /// The stream not carrying events, used for diagnostics
+    ///
+    /// # Returns
+    /// * `OutputStream` - Stderr for stdout and vice versa
+    pub fn other(self) -> Self {
+        match self {
+            OutputStream::Stdout => OutputStream::Stderr,
+            OutputStream::Stderr => OutputStream::Stdout,
+        }
+    }
+
+    /// Open a writer for the stream
+    ///
+    /// # Returns
+    /// * `Box<dyn Write + Send>` - Unbuffered handle to the stream
+    pub fn writer(self) -> Box<dyn Write + Send> {
+        match self {
+            OutputStream::Stdout => Box::new(io::stdout()),
+            OutputStream::Stderr => Box::new(io::stderr()),
+        }
+    }
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputStream::Stdout => write!(f, "stdout"),
+            OutputStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Settings for `fw collect`
+#[derive(Debug, Clone, Default)]
+pub struct CollectOptions {
+    /// Criteria events must match to be reported
+    pub filter: FilterSpec,
+    /// Tags to attach to events before they are filtered
+    pub enrich: EnrichConfig,
+    /// Pick up in-kernel state pinned by a previous run
+    pub reuse_pinned: bool,
+    /// Instance name for pinned state (pid-based if unset)
+    pub instance: Option<String>,
+    /// Join the deployment shared by other `--shared` instances
+    pub shared: bool,
+    /// Report files already open at startup as "already open" events
+    pub snapshot: bool,
+    /// eBPF object to load instead of the embedded one
+    pub bpf_object: Option<PathBuf>,
+    /// Maximum process names cached; the default size if unset
+    pub process_cache_size: Option<usize>,
+    /// Command to run for every reported event
+    pub exec: Option<ExecConfig>,
+    /// Log every reported event to syslog; not logged if unset
+    pub syslog: Option<SyslogConfig>,
+    /// Notify people about serious events; nobody is notified if unset
+    pub notify: Option<NotifyConfig>,
+    /// Page on-call about serious events; nobody is paged if unset
+    pub pagerduty: Option<PagerDutyConfig>,
+    /// Push per-interval counts to InfluxDB; not pushed if unset
+    pub influx: Option<InfluxConfig>,
+    /// Alert on accesses outside a learned profile; no alerts if unset
+    pub baseline: Option<BaselineConfig>,
+    /// Directories whose changes are cross-checked against inotify;
+    /// nothing is cross-checked if empty
+    pub verify_inotify: Vec<PathBuf>,
+    /// Further criteria for single outputs, on top of `filter`
+    pub routes: Vec<Route>,
+    /// Path rewriting applied before any sink sees an event
+    pub redact: RedactConfig,
+    /// Whether to report events, open-to-close sessions or stats
+    pub mode: OutputMode,
+    /// How events are written in events mode
+    pub format: EventFormat,
+    /// Layout of each event with `--format template`
+    pub template: Option<EventTemplate>,
+    /// Grouping and export settings for stats mode
+    pub stats: StatsConfig,
+    /// Count stats in the kernel, reading the counters at this interval
+    pub kernel_agg: Option<Duration>,
+    /// Trace reads and writes to classify access patterns
+    pub access_patterns: bool,
+    /// Stacks recorded with each event; none if unset
+    pub stacks: Option<StackMode>,
+    /// Degrade fidelity under sustained drops; never degrades if unset
+    pub overload: Option<OverloadConfig>,
+    /// Users whose activity is reported, filtered in the kernel
+    pub users: UserFilter,
+    /// Processes to ignore or to report exclusively, reloaded while
+    /// running; every process is reported if unset
+    pub process_list: Option<ProcessListConfig>,
+    /// Fixed processes to ignore or to report exclusively, used when
+    /// `process_list` is unset (e.g. from a compiled `FilterBuilder`)
+    pub processes: ProcessList,
+    /// Watchlist file whose lists tag events, reloaded while running;
+    /// no watchlist tags if unset
+    pub watchlists: Option<PathBuf>,
+    /// Daily windows to monitor during; always monitors if unset
+    pub schedule: Option<Schedule>,
+    /// Spool for output that can't be written; output is dropped if unset
+    pub spool: Option<SpoolConfig>,
+    /// File to write output to; `stream` if unset
+    pub output: Option<OutputFile>,
+    /// Stream to write output to when there is no output file, and
+    /// whether it was chosen explicitly, which moves the filter summary
+    /// to the other stream
+    pub stream: Option<OutputStream>,
+    /// Write a checkpointed recording instead of the --mode output
+    pub record: Option<RecordConfig>,
+    /// File to keep per-minute and per-hour rollups of the events in
+    pub rollup: Option<PathBuf>,
+    /// Retention applied to the recording's directory while recording;
+    /// nothing is pruned if unset
+    pub prune: Option<PruneConfig>,
+    /// Send events to a central instance instead of the --mode output
+    pub forward: Option<ForwardConfig>,
+    /// Receive events from forwarders instead of attaching probes
+    pub remote: Option<RemoteConfig>,
+    /// Serve health endpoints; not served if unset
+    pub health: Option<HealthServerConfig>,
+    /// Leave stderr to file events by skipping the filter summary
+    pub quiet: bool,
+    /// Hold output in memory, up to this many bytes, until it is
+    /// exported, and stay off disk and out of `/proc`; output is written
+    /// as it comes if unset
+    pub forensic: Option<u64>,
+}
+
+impl CollectOptions {
+    /// Pin directory this run should use
+    ///
+    /// # Returns
+    /// * `Result<PinDir>` - Pin directory, or error if `--reuse-pinned`
+    ///   can't find a previous run
+    pub(crate) fn pin_dir(&self) -> Result<PinDir> {
+        match (&self.instance, self.shared) {
+            (_, true) => Ok(PinDir::shared()),
+            (Some(name), false) => PinDir::for_instance(name),
+            (None, false) if self.reuse_pinned => Err(anyhow!(
+                "--reuse-pinned needs --instance or --shared, since the \
+                 default instance name changes with every run"
+            )),
+            (None, false) => Ok(PinDir::for_current_process()),
+        }
+    }
+
+    /// Routes this run gives its outputs
+    ///
+    /// # Returns
+    /// * `Result<Routes>` - Routes, or error if one names an output that
+    ///   isn't enabled or an output is routed twice
+    pub(crate) fn routes(&self) -> Result<Routes> {
+        let enabled: Vec<SinkKind> = [
+            (SinkKind::Exec, self.exec.is_some()),
+            (SinkKind::Syslog, self.syslog.is_some()),
+            (SinkKind::Notify, self.notify.is_some()),
+            (SinkKind::PagerDuty, self.pagerduty.is_some()),
+            (SinkKind::Baseline, self.baseline.is_some()),
+            (SinkKind::Influx, self.influx.is_some()),
+            (SinkKind::Output, true),
+        ]
+        .into_iter()
+        .filter_map(|(kind, enabled)| enabled.then_some(kind))
+        .collect();
+        Routes::new(self.routes.clone(), &enabled)
+    }
+}
 
 /// Run the file collection monitoring process
 ///
 /// Starts the eBPF monitor, processes file events, and handles graceful
-/// shutdown on Ctrl+C. Events are filtered by extensions if specified
-/// and output to stderr.
+/// shutdown on Ctrl+C. Events are annotated with filesystem information,
+/// filtered by the given criteria, and output to stderr.
 ///
 /// # Arguments
-/// * `extensions` - Optional list of file extensions to filter by
+/// * `options` - Filter and monitor settings
 ///
 /// # Returns
 /// * `Result<()>` - Success or error result
-pub fn run_collect(extensions: Option<Vec<String>>) -> Result<()> {
+pub fn run_collect(options: CollectOptions) -> Result<()> {
+    let pins = options.pin_dir()?;
+    let routes = options.routes()?;
+    let CollectOptions {
+        filter,
+        mut enrich,
+        reuse_pinned,
+        snapshot,
+        bpf_object,
+        process_cache_size,
+        exec,
+        syslog,
+        notify,
+        pagerduty,
+        influx,
+        baseline,
+        verify_inotify,
+        redact,
+        mode,
+        format,
+        template,
+        stats,
+        kernel_agg,
+        access_patterns,
+        stacks,
+        overload,
+        users,
+        process_list,
+        processes,
+        watchlists,
+        schedule,
+        spool,
+        output,
+        record,
+        rollup,
+        prune,
+        forward,
+        remote,
+        health: health_server,
+        quiet,
+        stream,
+        forensic,
+        ..
+    } = options;
+
     // Create a new async runtime for handling events
     let rt = tokio::runtime::Runtime::new()
         .context("Failed to create async runtime")?;
 
     rt.block_on(async {
+        // Follow the mount list for filesystem annotation; forwarded
+        // events were annotated on their own hosts
+        let (mounts, mount_watcher) = match remote {
+            Some(_) => (MountTable::empty(), None),
+            None => {
+                let (mounts, watcher) = MountTable::follow()
+                    .context("Failed to load mount table")?;
+                (mounts, Some(watcher))
+            }
+        };
+
+        // Load the watchlists now so a bad file fails the start; reloads
+        // reach the enrichers over the channel
+        let watchlist_watcher = match watchlists {
+            Some(path) => {
+                let lists = Watchlists::load(&path)?;
+                let (tx, rx) = watch::channel(Arc::new(lists));
+                enrich.watchlists = Some(rx);
+                Some((WatchlistWatcher::new(path)?, tx))
+            }
+            None => None,
+        };
+
+        let enrichers = Enrichers::for_workers(&enrich)?;
+
+        // Severity rules capturing stacks limit them to what they match,
+        // and the policy sets how often alert sinks repeat an alert; its
+        // serious paths are never sampled out, even unclassified
+        let classifier = enrich.classifier()?;
+        let sample_exemptions = classifier.sample_exemptions();
+        let (stack_criteria, alert_limits) = match enrich.level {
+            EnrichmentLevel::Off => (Vec::new(), AlertLimits::default()),
+            _ => (classifier.stack_criteria(), classifier.alert_limits()),
+        };
+        // Symbolizing reads /proc, so --forensic leaves them out
+        let stacks = stacks.or_else(|| {
+            (forensic.is_none() && !stack_criteria.is_empty())
+                .then_some(StackMode::Both)
+        });
+
         // Display filter information
-        display_filter_info(&extensions);
+        if !quiet {
+            // Keep the summary off an explicitly chosen data stream
+            let summary = stream.map_or(OutputStream::Stderr, |s| s.other());
+            let _ = summary
+                .writer()
+                .write_all(filter_summary(&filter, &mounts).as_bytes());
+        }
+
+        // Shared by the pipeline, its supervisors and the monitor
+        let health = Health::default();
 
-        // Initialize the eBPF monitor
-        let mut monitor =
-            EbpfMonitor::new().context("Failed to initialize eBPF monitor")?;
+        // Load the process list now so a bad file fails the start;
+        // reloads reach the monitor over the channel
+        let (list_watcher, list_updates) = match process_list {
+            Some(config) => {
+                let list = ProcessList::load(&config)?;
+                let (tx, rx) = watch::channel(Arc::new(list));
+                (Some((ListWatcher::new(config)?, tx)), rx)
+            }
+            None => (None, watch::channel(Arc::new(processes)).1),
+        };
 
-        // Start monitoring in the background
-        let mut event_receiver = monitor
-            .start_monitoring()
-            .await
-            .context("Failed to start eBPF monitoring")?;
+        let object = bpf_object.as_deref().map(BpfObject::load).transpose()?;
 
-        // Set up Ctrl+C signal handling
-        let ctrl_c = signal::ctrl_c();
-        tokio::pin!(ctrl_c);
+        // Initialize the eBPF monitor, or listen for forwarders
+        let mut monitor = match &remote {
+            Some(config) => CollectBackend::Remote(RemoteMonitor::new(config)?),
+            None => CollectBackend::Ebpf({
+                let monitor = EbpfMonitor::new()
+                    .context("Failed to initialize eBPF monitor")?;
+                // Pins are files on the BPF filesystem
+                match forensic {
+                    Some(_) => monitor,
+                    None => monitor.with_pinning(pins, reuse_pinned),
+                }
+                .with_object(object)
+                .with_user_filter(users)
+                .with_process_list(list_updates)
+                .with_snapshot(snapshot)
+                .with_forensic(forensic.is_some())
+                .with_kernel_aggregation(kernel_agg.is_some())
+                .with_pseudo_fs_excluded(filter.exclude_pseudo_fs)
+                .with_actions(filter.actions.as_deref())
+                .with_access_patterns(access_patterns)
+                .with_stacks(stacks)
+                .with_stack_criteria(stack_criteria)
+                .with_sample_exemptions(sample_exemptions)
+                .with_tripwires(&enrich.tripwires)
+                .with_health(&health)
+                .with_process_cache_size(
+                    process_cache_size.unwrap_or(DEFAULT_PROCESS_CACHE_SIZE),
+                )
+            }),
+        };
 
         info!("File monitoring started. Press Ctrl+C to stop.");
 
-        loop {
-            tokio::select! {
-                // Handle incoming file events
-                event_result = event_receiver.recv() => {
-                    match event_result {
-                        Some(event) => {
-                            process_file_event(event, &extensions)?;
-                        }
-                        None => {
-                            warn!("Event channel closed, stopping monitoring");
-                            break;
-                        }
+        let mut supervisors = Vec::new();
+        if let Some(watcher) = mount_watcher {
+            supervisors.push(tokio::spawn(mount_table::supervise(watcher)));
+        }
+        // Report readiness and liveness to systemd and health probes
+        if let Some(notifier) = Notifier::from_env()? {
+            supervisors.push(tokio::spawn(systemd::supervise(
+                notifier,
+                health.clone(),
+            )));
+        }
+        if let Some((watcher, tx)) = list_watcher {
+            supervisors
+                .push(tokio::spawn(process_list::supervise(watcher, tx)));
+        }
+        if let Some((watcher, tx)) = watchlist_watcher {
+            supervisors.push(tokio::spawn(watchlist::supervise(watcher, tx)));
+        }
+        if let Some(config) = prune {
+            supervisors.push(tokio::spawn(retention::supervise(config)));
+        }
+        if let Some(config) = health_server {
+            let listener = health::bind(&config).await?;
+            supervisors.push(tokio::spawn(health::serve(
+                listener,
+                health.clone(),
+                config,
+            )));
+        }
+        if let Some(config) = overload.filter(|_| kernel_agg.is_none()) {
+            // Pausing enrichment only helps if it runs, and would hide
+            // every event from filters on its tags or severity
+            let can_pause = !enrichers.is_empty()
+                && filter.tags.is_none()
+                && filter.min_severity.is_none();
+            supervisors.push(tokio::spawn(overload::supervise(
+                OverloadController::new(config, can_pause),
+                health.clone(),
+            )));
+        }
+
+        let shutdown = async {
+            // Ctrl+C errors only if the handler can't be installed; treat
+            // that as an immediate shutdown request
+            let _ = signal::ctrl_c().await;
+            info!("Received interrupt signal, stopping monitoring...");
+        };
+        let redactor = match redact.is_enabled() {
+            true => Some(Arc::new(Redactor::new(&redact)?)),
+            false => None,
+        };
+        let mut subscribers = Vec::new();
+        // Sinks raising alerts, throttled together under the policy's
+        // limits
+        let mut alerts = Vec::new();
+        // Subscribers that must see the real paths, left unredacted
+        let mut unredacted = Vec::new();
+        if let Some(config) = exec {
+            // Hooks run on this runtime so they never block event delivery
+            alerts.push(
+                Subscriber::new(
+                    "exec",
+                    filter.clone(),
+                    ExecSink::new(config, Handle::current()),
+                )
+                .with_route(routes.filter(SinkKind::Exec)),
+            );
+        }
+        if let Some(config) = &syslog {
+            alerts.push(
+                Subscriber::new(
+                    "syslog",
+                    filter.clone(),
+                    SyslogSink::new(config)?,
+                )
+                .with_route(routes.filter(SinkKind::Syslog)),
+            );
+        }
+        if let Some(config) = notify {
+            // Sends run on this runtime so they never block event delivery
+            alerts.push(
+                Subscriber::new(
+                    "notify",
+                    filter.clone(),
+                    NotifySink::new(config, Handle::current())?,
+                )
+                .with_route(routes.filter(SinkKind::Notify)),
+            );
+        }
+        if let Some(config) = pagerduty {
+            alerts.push(
+                Subscriber::new(
+                    "pagerduty",
+                    filter.clone(),
+                    PagerDutySink::new(config, Handle::current())?,
+                )
+                .with_route(routes.filter(SinkKind::PagerDuty)),
+            );
+        }
+        if let Some(config) = baseline {
+            // Alerts stay off the stream carrying events, like the
+            // filter summary
+            let other = stream.map_or(OutputStream::Stderr, |s| s.other());
+            alerts.push(
+                Subscriber::new(
+                    "baseline",
+                    filter.clone(),
+                    AnomalySink::new(config, other.writer()),
+                )
+                .with_route(routes.filter(SinkKind::Baseline)),
+            );
+        }
+        if !verify_inotify.is_empty() {
+            // Every event counts as a witness, whatever the filter, and
+            // is paired with inotify's paths before redaction
+            let other = stream.map_or(OutputStream::Stderr, |s| s.other());
+            unredacted.push(Subscriber::new(
+                "verify",
+                FilterSpec::default(),
+                VerifySink::new(
+                    &verify_inotify,
+                    other.writer(),
+                    redactor.clone(),
+                    Handle::current(),
+                )?,
+            ));
+        }
+        if let Some(config) = influx {
+            // Pushes run on this runtime so they never block event delivery
+            alerts.push(
+                Subscriber::new(
+                    "influx",
+                    filter.clone(),
+                    InfluxSink::new(config, Handle::current())?,
+                )
+                .with_route(routes.filter(SinkKind::Influx)),
+            );
+        }
+        if let Some(path) = &rollup {
+            subscribers.push(Subscriber::new(
+                "rollup",
+                filter.clone(),
+                RollupSink::create(path)?,
+            ));
+        }
+        // --forensic holds the output until SIGUSR2 or the end of the run
+        let buffer = forensic.map(MemoryBuffer::new);
+        if let Some(buffer) = &buffer {
+            let exporter =
+                Exporter::new(buffer.clone(), stream.unwrap_or_default())?;
+            supervisors.push(tokio::spawn(forensic::supervise(exporter)));
+        }
+        let (name, writer) =
+            match (&forward, &output, stream.unwrap_or_default()) {
+                (Some(config), _, _) => (
+                    config.addr.clone(),
+                    Box::new(ForwardConnection::new(config.clone())?)
+                        as Box<dyn Write + Send>,
+                ),
+                (None, Some(file), _) => {
+                    (file.path.display().to_string(), file.create()?)
+                }
+                (None, None, stream) => match &buffer {
+                    Some(buffer) => {
+                        (stream.to_string(), Box::new(buffer.clone()) as _)
                     }
+                    None => (stream.to_string(), stream.writer()),
+                },
+            };
+        let writer: Box<dyn Write + Send> = match spool {
+            Some(config) => Box::new(SpoolWriter::new(writer, config)?),
+            None => writer,
+        };
+        let reports = if let Some(interval) = kernel_agg {
+            let CollectBackend::Ebpf(monitor) = &mut monitor else {
+                return Err(anyhow!("--kernel-agg needs the eBPF monitor"));
+            };
+            run_kernel_stats(
+                monitor, stats, interval, writer, &health, shutdown,
+            )
+            .await?;
+            Vec::new()
+        } else {
+            let main = match (record, mode) {
+                (Some(config), _) => Subscriber::new(
+                    name,
+                    filter,
+                    RecordSink::new(config, writer),
+                ),
+                (None, _) if forward.is_some() => {
+                    Subscriber::new(name, filter, ForwardSink::new(writer))
+                }
+                (None, OutputMode::Events) => Subscriber::new(
+                    name,
+                    filter,
+                    TextSink::new(writer)
+                        .with_encoder(format.encoder(template)),
+                ),
+                (None, OutputMode::Sessions) => {
+                    let export =
+                        stats.export.as_ref().map(|(p, _)| p.as_path());
+                    let sink = SessionSink::new(writer).with_export(export)?;
+                    Subscriber::new(name, filter, sink)
+                }
+                (None, OutputMode::Stats) => {
+                    Subscriber::new(name, filter, StatsSink::new(stats, writer))
+                }
+            };
+            subscribers.push(main.with_route(routes.filter(SinkKind::Output)));
+            if let Some(redactor) = &redactor {
+                let redact =
+                    |s: Subscriber| RedactingSink::wrap(s, redactor.clone());
+                subscribers = subscribers.into_iter().map(redact).collect();
+                alerts = alerts.into_iter().map(redact).collect();
+            }
+            // The main output keeps every event; the alert sinks, however
+            // many, share one set of limits
+            alerts.extend(unredacted);
+            if alert_limits.is_empty() || alerts.is_empty() {
+                subscribers.extend(alerts);
+            } else {
+                subscribers
+                    .push(ThrottlingSink::wrap_all(alerts, alert_limits));
+            }
+            // Symbolizing first gives the redactor the frames' files
+            if stacks.is_some() {
+                subscribers = SymbolizingSink::wrap_all(subscribers);
+            }
+            match &schedule {
+                Some(schedule) => {
+                    run_scheduled_fanout(
+                        &mut monitor,
+                        &mounts,
+                        enrichers,
+                        subscribers,
+                        schedule,
+                        &health,
+                        shutdown,
+                    )
+                    .await?
                 }
-                // Handle Ctrl+C signal
-                _ = &mut ctrl_c => {
-                    info!("Received interrupt signal, stopping monitoring...");
-                    break;
+                None => {
+                    run_fanout(
+                        &mut monitor,
+                        &mounts,
+                        enrichers,
+                        subscribers,
+                        &health,
+                        shutdown,
+                    )
+                    .await?
                 }
             }
+        };
+        for supervisor in supervisors {
+            supervisor.abort();
+        }
+        for report in reports {
+            if let Some(e) = report.error {
+                warn!("Output '{}' failed: {}", report.name, e);
+            }
+        }
+        if let Some(buffer) = buffer {
+            let bytes = buffer
+                .export(&mut stream.unwrap_or_default().writer())
+                .map_err(|e| anyhow!("Failed to export the output: {}", e))?;
+            info!("Exported {} bytes of output", bytes);
         }
-
-        // Stop monitoring and cleanup
-        monitor
-            .stop_monitoring()
-            .await
-            .context("Failed to stop eBPF monitoring")?;
 
         info!("File monitoring stopped.");
         Ok(())
     })
 }
 
-/// Display information about active file extension filters
+/// Backend `fw collect` runs on: local probes or remote forwarders
+// Built once per run, so the size of `Ebpf` doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum CollectBackend {
+    /// Probes attached on this host
+    Ebpf(EbpfMonitor),
+    /// Events forwarded by `fw forward` on other hosts
+    Remote(RemoteMonitor),
+}
+
+impl MonitorBackend for CollectBackend {
+    async fn start_monitoring(&mut self) -> Result<mpsc::Receiver<FileEvent>> {
+        match self {
+            CollectBackend::Ebpf(monitor) => monitor.start_monitoring().await,
+            CollectBackend::Remote(monitor) => monitor.start_monitoring().await,
+        }
+    }
+
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        match self {
+            CollectBackend::Ebpf(monitor) => monitor.stop_monitoring().await,
+            CollectBackend::Remote(monitor) => monitor.stop_monitoring().await,
+        }
+    }
+}
+
+/// Run events from a monitor backend through the filter/format pipeline
+///
+/// Starts the backend and writes every event that passes the filter to
+/// `sink` until the backend closes its channel or `shutdown` completes,
+/// then stops the backend.
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `filter` - Criteria events must match to be reported
+/// * `mounts` - Mount table used to annotate events
+/// * `sink` - Destination for formatted event lines
+/// * `shutdown` - Future that resolves when monitoring should stop
+///
+/// # Returns
+/// * `Result<u64>` - Number of events written to the sink
+pub async fn run_pipeline<B, W, S>(
+    monitor: &mut B,
+    filter: &FilterSpec,
+    mounts: &MountTable,
+    sink: &mut W,
+    shutdown: S,
+) -> Result<u64>
+where
+    B: MonitorBackend,
+    W: Write,
+    S: Future<Output = ()>,
+{
+    let mut written = 0;
+    pump_events(monitor, shutdown, |event| {
+        if process_file_event(event, filter, mounts, sink)? {
+            written += 1;
+        }
+        Ok(ControlFlow::Continue(()))
+    })
+    .await?;
+    Ok(written)
+}
+
+/// Broadcast events from a monitor backend to independent subscribers
+///
+/// Events are annotated, matched up across atomic renames and enriched
+/// once and then delivered to every subscriber, which applies its own
+/// filter. A subscriber whose sink fails is detached without affecting
+/// the others. Enrichment runs on one worker per set of enrichers, off
+/// the event loop.
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `mounts` - Mount table used to annotate events
+/// * `enrichers` - Enrichers for each worker; none to skip enrichment
+/// * `subscribers` - Sinks and their filters
+/// * `health` - Liveness and counters updated as events flow
+/// * `shutdown` - Future that resolves when monitoring should stop
+///
+/// # Returns
+/// * `Result<Vec<SinkReport>>` - One report per subscriber
+pub async fn run_fanout<B, S>(
+    monitor: &mut B,
+    mounts: &MountTable,
+    enrichers: Vec<Enrichers>,
+    subscribers: Vec<Subscriber>,
+    health: &Health,
+    shutdown: S,
+) -> Result<Vec<SinkReport>>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+{
+    let fanout = FanOut::spawn(subscribers, FANOUT_CAPACITY, health);
+    let mut pool = EnrichPool::spawn(
+        enrichers,
+        fanout.publisher(),
+        ENRICH_QUEUE_CAPACITY,
+        health,
+    );
+    let mut renames = RenameCorrelator::default();
+    let pumped = pump_with_health(monitor, shutdown, health, |mut event| {
+        mounts.annotate(&mut event);
+        renames.observe(&mut event);
+        pool.submit(event);
+        Ok(ControlFlow::Continue(()))
+    })
+    .await;
+
+    // Let subscribers drain what was published even if the pump failed
+    pool.finish().await?;
+    let reports = fanout.finish().await?;
+    pumped.map(|_| reports)
+}
+
+/// Broadcast events to subscribers only while a schedule is open
+///
+/// The backend is started when a window opens and stopped when it closes,
+/// so no probes are attached in between. Subscribers stay attached for
+/// the whole run, so stats and sessions span every window.
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `mounts` - Mount table used to annotate events
+/// * `enrichers` - Enrichers for each worker; none to skip enrichment
+/// * `subscribers` - Sinks and their filters
+/// * `schedule` - Daily windows to monitor during
+/// * `health` - Liveness and counters updated as events flow
+/// * `shutdown` - Future that resolves when monitoring should stop
+///
+/// # Returns
+/// * `Result<Vec<SinkReport>>` - One report per subscriber
+pub async fn run_scheduled_fanout<B, S>(
+    monitor: &mut B,
+    mounts: &MountTable,
+    enrichers: Vec<Enrichers>,
+    subscribers: Vec<Subscriber>,
+    schedule: &Schedule,
+    health: &Health,
+    shutdown: S,
+) -> Result<Vec<SinkReport>>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+{
+    /// Why monitoring during a window stopped
+    enum WindowEnd {
+        Closed,
+        Interrupted,
+    }
+
+    let fanout = FanOut::spawn(subscribers, FANOUT_CAPACITY, health);
+    let mut pool = EnrichPool::spawn(
+        enrichers,
+        fanout.publisher(),
+        ENRICH_QUEUE_CAPACITY,
+        health,
+    );
+    let mut renames = RenameCorrelator::default();
+    let pumped = async {
+        tokio::pin!(shutdown);
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            let now = Local::now().time();
+            let wait = schedule.until_change(now);
+            if !schedule.is_open(now) {
+                info!("Outside the schedule, probes detached for {:?}", wait);
+                let idle = tokio::time::sleep(wait);
+                tokio::pin!(idle);
+                loop {
+                    tokio::select! {
+                        _ = &mut idle => break,
+                        _ = &mut shutdown => return Ok(()),
+                        // Waiting for a window is not a stalled loop
+                        _ = heartbeat.tick() => health.beat(),
+                    }
+                }
+                continue;
+            }
+
+            info!("Schedule window open for {:?}, attaching probes", wait);
+            let mut ended = None;
+            let window = async {
+                ended = Some(tokio::select! {
+                    _ = tokio::time::sleep(wait) => WindowEnd::Closed,
+                    _ = &mut shutdown => WindowEnd::Interrupted,
+                });
+            };
+            pump_with_health(monitor, window, health, |mut event| {
+                mounts.annotate(&mut event);
+                renames.observe(&mut event);
+                pool.submit(event);
+                Ok(ControlFlow::Continue(()))
+            })
+            .await?;
+            match ended {
+                Some(WindowEnd::Closed) => {
+                    info!("Schedule window closed, probes detached")
+                }
+                // Interrupted, or the backend has no more events
+                Some(WindowEnd::Interrupted) | None => return Ok(()),
+            }
+        }
+    }
+    .await;
+
+    // Let subscribers drain what was published even if the pump failed
+    pool.finish().await?;
+    let reports = fanout.finish().await?;
+    pumped.map(|_| reports)
+}
+
+/// Find the first annotated event accepted by a predicate
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `mounts` - Mount table used to annotate events
+/// * `accept` - Decides whether an event is the one being waited for
+/// * `shutdown` - Future that resolves when waiting should stop
+///
+/// # Returns
+/// * `Result<Option<FileEvent>>` - First accepted event, or None if the
+///   backend or `shutdown` stopped first
+pub async fn first_match<B, S, P>(
+    monitor: &mut B,
+    mounts: &MountTable,
+    mut accept: P,
+    shutdown: S,
+) -> Result<Option<FileEvent>>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+    P: FnMut(&FileEvent) -> bool,
+{
+    let mut found = None;
+    let mut renames = RenameCorrelator::default();
+    pump_events(monitor, shutdown, |mut event| {
+        mounts.annotate(&mut event);
+        renames.observe(&mut event);
+        if !accept(&event) {
+            return Ok(ControlFlow::Continue(()));
+        }
+        found = Some(event);
+        Ok(ControlFlow::Break(()))
+    })
+    .await?;
+    Ok(found)
+}
+
+/// Start a backend and hand each event to a handler until it stops
+///
+/// Runs until the backend closes its channel, `shutdown` completes, or
+/// the handler fails or breaks, then stops the backend.
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `shutdown` - Future that resolves when monitoring should stop
+/// * `handle` - Called with every received event; returns
+///   `ControlFlow::Break` to stop early
+///
+/// # Returns
+/// * `Result<()>` - Success or the first handler/backend error
+pub(crate) async fn pump_events<B, S, F>(
+    monitor: &mut B,
+    shutdown: S,
+    handle: F,
+) -> Result<()>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+    F: FnMut(FileEvent) -> Result<ControlFlow<()>>,
+{
+    pump_with_health(monitor, shutdown, &Health::default(), handle).await
+}
+
+/// Like [`pump_events`], recording liveness and counts in `health`
+///
+/// Probes are reported attached between starting and stopping the
+/// backend, and the loop records a heartbeat every
+/// [`HEARTBEAT_INTERVAL`] while it is free to receive events.
+async fn pump_with_health<B, S, F>(
+    monitor: &mut B,
+    shutdown: S,
+    health: &Health,
+    mut handle: F,
+) -> Result<()>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+    F: FnMut(FileEvent) -> Result<ControlFlow<()>>,
+{
+    // Start monitoring in the background
+    let mut event_receiver = monitor
+        .start_monitoring()
+        .await
+        .context("Failed to start monitoring")?;
+    health.set_attached(true);
+    health.beat();
+
+    tokio::pin!(shutdown);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    let pumped = loop {
+        tokio::select! {
+            // Handle incoming file events
+            event_result = event_receiver.recv() => {
+                match event_result {
+                    Some(event) => {
+                        health.record_event();
+                        match handle(event) {
+                            Ok(flow) if flow.is_break() => break Ok(()),
+                            Ok(_) => {}
+                            Err(e) => break Err(e),
+                        }
+                    }
+                    None => {
+                        warn!("Event channel closed, stopping monitoring");
+                        break Ok(());
+                    }
+                }
+            }
+            _ = heartbeat.tick() => health.beat(),
+            // Handle shutdown request
+            _ = &mut shutdown => break Ok(()),
+        }
+    };
+    health.set_attached(false);
+    pumped?;
+
+    // Stop monitoring and cleanup
+    monitor
+        .stop_monitoring()
+        .await
+        .context("Failed to stop monitoring")
+}
+
+/// Describe the active filters for the start of a run
+///
+/// Warns about requested mount points that are not in the mount table,
+/// since no event could ever match them.
 ///
 /// # Arguments
-/// * `extensions` - Optional list of file extensions being filtered
-fn display_filter_info(extensions: &Option<Vec<String>>) {
-    match extensions {
+/// * `filter` - Active filter criteria
+/// * `mounts` - Current mount table
+///
+/// # Returns
+/// * `String` - Summary lines ending with a separator line
+fn filter_summary(filter: &FilterSpec, mounts: &MountTable) -> String {
+    let mut out = String::new();
+    match &filter.extensions {
         Some(exts) if !exts.is_empty() => {
-            eprintln!("Monitoring files with extensions: {}", exts.join(", "));
+            let _ = writeln!(
+                out,
+                "Monitoring files with extensions: {}",
+                exts.join(", ")
+            );
         }
         _ => {
-            eprintln!("Monitoring all file operations");
+            out.push_str("Monitoring all file operations\n");
+        }
+    }
+    if let Some(mount_points) = &filter.mounts {
+        let _ = writeln!(
+            out,
+            "Limited to mount points: {}",
+            mount_points.join(", ")
+        );
+        for mount_point in mount_points {
+            if !mounts.contains_mount_point(mount_point) {
+                warn!(
+                    "{} is not a mount point; no events will match it",
+                    mount_point
+                );
+            }
         }
     }
-    eprintln!("Output format: timestamp | program (pid) | action | file_path");
-    eprintln!("{}", "-".repeat(60));
+    if let Some(fs_types) = &filter.fs_types {
+        let _ = writeln!(
+            out,
+            "Limited to filesystem types: {}",
+            fs_types.join(", ")
+        );
+    }
+    match filter.remote {
+        Some(true) => out.push_str("Limited to network filesystems\n"),
+        Some(false) => out.push_str("Limited to local filesystems\n"),
+        None => {}
+    }
+    if filter.exclude_pseudo_fs {
+        out.push_str(
+            "Excluding /proc, /sys and /dev (--include-pseudo-fs to \
+             include them)\n",
+        );
+    }
+    out.push_str(
+        "Output format: timestamp | program (pid) | action | file_path \
+         [fstype]\n",
+    );
+    let _ = writeln!(out, "{}", "-".repeat(60));
+    out
 }
 
 /// Process a single file event and output it if it matches the filter
 ///
 /// # Arguments
 /// * `event` - The file event to process
-/// * `extensions` - Optional list of file extensions to filter by
+/// * `filter` - Criteria the event must match
+/// * `mounts` - Mount table used to annotate the event
+/// * `sink` - Destination for the formatted event line
 ///
 /// # Returns
-/// * `Result<()>` - Success or error result
-fn process_file_event(
-    event: FileEvent,
-    extensions: &Option<Vec<String>>,
-) -> Result<()> {
-    // Check if the event matches the extension filter
-    if event.matches_extensions(extensions) {
-        // Output to stderr as specified in requirements
-        writeln!(io::stderr(), "{}", event)
-            .context("Failed to write event to stderr")?;
+/// * `Result<bool>` - True if the event was written to the sink
+fn process_file_event<W: Write>(
+    mut event: FileEvent,
+    filter: &FilterSpec,
+    mounts: &MountTable,
+    sink: &mut W,
+) -> Result<bool> {
+    // Annotate before filtering so filesystem criteria can be evaluated
+    mounts.annotate(&mut event);
 
-        // Flush immediately for real-time output
-        io::stderr().flush().context("Failed to flush stderr")?;
+    // Check if the event matches the filter
+    if !filter.matches(&event) {
+        return Ok(false);
     }
-    Ok(())
+
+    writeln!(sink, "{}", event).context("Failed to write event")?;
+
+    // Flush immediately for real-time output
+    sink.flush().context("Failed to flush event output")?;
+    Ok(true)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::{Clock, SystemClock};
     use crate::file_event::{FileAction, FileEvent};
+    use crate::mock_monitor::MockMonitor;
 
     #[test]
     fn test_process_file_event_no_filter() {
@@ -134,10 +1137,21 @@ mod tests {
             "rustc".to_string(),
             FileAction::Opened,
             1234,
+            SystemClock.now(),
         );
 
-        // Should not error when processing without filter
-        assert!(process_file_event(event, &None).is_ok());
+        // Should write the event when processing without filter
+        let mut sink = Vec::new();
+        assert!(process_file_event(
+            event,
+            &FilterSpec::default(),
+            &MountTable::empty(),
+            &mut sink
+        )
+        .unwrap());
+        assert!(String::from_utf8(sink)
+            .unwrap()
+            .contains("/path/to/file.rs"));
     }
 
     #[test]
@@ -147,10 +1161,247 @@ mod tests {
             "rustc".to_string(),
             FileAction::Opened,
             1234,
+            SystemClock.now(),
+        );
+        let filter = FilterSpec {
+            extensions: Some(vec!["rs".to_string()]),
+            ..Default::default()
+        };
+
+        // Should write the event when processing with matching filter
+        let mut sink = Vec::new();
+        assert!(process_file_event(
+            event,
+            &filter,
+            &MountTable::empty(),
+            &mut sink
+        )
+        .unwrap());
+        assert!(!sink.is_empty());
+    }
+
+    #[test]
+    fn test_process_file_event_with_non_matching_filter() {
+        let event = FileEvent::new(
+            "/path/to/file.py".to_string(),
+            "python".to_string(),
+            FileAction::Opened,
+            1234,
+            SystemClock.now(),
+        );
+        let filter = FilterSpec {
+            extensions: Some(vec!["rs".to_string()]),
+            ..Default::default()
+        };
+
+        // Should skip the event without writing anything
+        let mut sink = Vec::new();
+        assert!(!process_file_event(
+            event,
+            &filter,
+            &MountTable::empty(),
+            &mut sink
+        )
+        .unwrap());
+        assert!(sink.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_pipeline_with_mock_monitor() {
+        let events = vec![
+            FileEvent::new(
+                "/src/main.rs".to_string(),
+                "rustc".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            ),
+            FileEvent::new(
+                "/etc/hosts".to_string(),
+                "curl".to_string(),
+                FileAction::Opened,
+                2,
+                SystemClock.now(),
+            ),
+        ];
+        let mut monitor = MockMonitor::new(events);
+        let filter = FilterSpec {
+            extensions: Some(vec!["rs".to_string()]),
+            ..Default::default()
+        };
+        let mut sink = Vec::new();
+
+        let written = run_pipeline(
+            &mut monitor,
+            &filter,
+            &MountTable::empty(),
+            &mut sink,
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(written, 1);
+        let output = String::from_utf8(sink).unwrap();
+        assert!(output.contains("/src/main.rs"));
+        assert!(!output.contains("/etc/hosts"));
+    }
+
+    #[tokio::test]
+    async fn test_run_fanout_annotates_for_every_subscriber() {
+        use crate::enrich::PathTags;
+        use crate::fanout::EventSink;
+        use std::sync::{Arc, Mutex};
+
+        struct LineSink(Arc<Mutex<Vec<String>>>);
+        impl EventSink for LineSink {
+            fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+                self.0.lock().unwrap().push(event.to_string());
+                Ok(())
+            }
+        }
+
+        let mounts = MountTable::parse("tmpfs /tmp tmpfs rw 0 0\n");
+        let tmpfs_only = FilterSpec {
+            fs_types: Some(vec!["tmpfs".to_string()]),
+            ..Default::default()
+        };
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut monitor = MockMonitor::new(vec![
+            FileEvent::new(
+                "/tmp/a.rs".to_string(),
+                "vim".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            ),
+            FileEvent::new(
+                "relative.rs".to_string(),
+                "vim".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            ),
+        ]);
+
+        let mut enrichers = Enrichers::default();
+        enrichers.push(PathTags::parse("/tmp team=scratch").unwrap());
+        let health = Health::default();
+        let reports = run_fanout(
+            &mut monitor,
+            &mounts,
+            vec![enrichers],
+            vec![Subscriber::new(
+                "tmpfs",
+                tmpfs_only,
+                LineSink(lines.clone()),
+            )],
+            &health,
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(reports[0].written, 1);
+        assert!(lines.lock().unwrap()[0]
+            .ends_with("/tmp/a.rs [tmpfs] {team=scratch}"));
+        let health = health.snapshot();
+        assert_eq!((health.events, health.attached), (2, false));
+        assert!(health.since_beat.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_run_scheduled_fanout_only_inside_windows() {
+        use crate::fanout::EventSink;
+        use chrono::Duration;
+
+        struct CountSink(u64);
+        impl EventSink for CountSink {
+            fn write_event(&mut self, _event: &FileEvent) -> Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let window = |from: i64, to: i64| {
+            let now = Local::now().time();
+            let hhmm = |offset| {
+                (now + Duration::hours(offset)).format("%H:%M").to_string()
+            };
+            Schedule::parse(&format!("{}-{}", hhmm(from), hhmm(to))).unwrap()
+        };
+        let events = || {
+            vec![FileEvent::new(
+                "/backup/db.tar".to_string(),
+                "tar".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            )]
+        };
+
+        // Open window: events flow until the backend runs dry
+        let reports = run_scheduled_fanout(
+            &mut MockMonitor::new(events()),
+            &MountTable::empty(),
+            Vec::new(),
+            vec![Subscriber::new("n", FilterSpec::default(), CountSink(0))],
+            &window(-1, 1),
+            &Health::default(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reports[0].written, 1);
+
+        // Closed window: the backend is never started
+        let reports = run_scheduled_fanout(
+            &mut MockMonitor::new(events()),
+            &MountTable::empty(),
+            Vec::new(),
+            vec![Subscriber::new("n", FilterSpec::default(), CountSink(0))],
+            &window(2, 3),
+            &Health::default(),
+            std::future::ready(()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reports[0].written, 0);
+    }
+
+    #[test]
+    fn test_process_file_event_annotates_and_filters_fs_type() {
+        let mounts = MountTable::parse(
+            "/dev/sda1 / ext4 rw 0 0\ntmpfs /tmp tmpfs rw 0 0\n",
+        );
+        let filter = FilterSpec {
+            fs_types: Some(vec!["ext4".to_string()]),
+            ..Default::default()
+        };
+        let on_disk = FileEvent::new(
+            "/home/a.rs".to_string(),
+            "vim".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        let on_tmpfs = FileEvent::new(
+            "/tmp/a.rs".to_string(),
+            "vim".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
         );
-        let extensions = Some(vec!["rs".to_string()]);
 
-        // Should not error when processing with matching filter
-        assert!(process_file_event(event, &extensions).is_ok());
+        let mut sink = Vec::new();
+        assert!(
+            process_file_event(on_disk, &filter, &mounts, &mut sink).unwrap()
+        );
+        assert!(
+            !process_file_event(on_tmpfs, &filter, &mounts, &mut sink).unwrap()
+        );
+        assert!(String::from_utf8(sink)
+            .unwrap()
+            .contains("/home/a.rs [ext4]"));
     }
 }
@@ -0,0 +1,549 @@
+//! Compression module
+//!
+//! Writes output files as a gzip or zstd stream (`fw collect --output
+//! --compress`) and reads captures back whether or not they are
+//! compressed. Compressed output is flushed at most once per
+//! `COMPRESSED_FLUSH_INTERVAL`, so per-event flushes don't defeat the
+//! compression, and everything up to the last flush stays readable by
+//! `zcat`/`zstdcat` even if fw is killed. zstd is left out of builds
+//! without the `zstd` feature, such as static builds for targets fw has no
+//! C compiler for. A compressed capture cut short, e.g. by a crash or a
+//! full disk, is read up to its last complete line before the damage.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Longest time compressed output is held back before it is flushed
+pub const COMPRESSED_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Magic bytes starting a gzip stream
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Magic bytes starting a zstd frame
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Stream compression for output files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// gzip, readable with zcat
+    Gzip,
+    /// zstd, readable with zstdcat
+    Zstd,
+}
+
+impl Compression {
+    /// Level used when none is given
+    ///
+    /// # Returns
+    /// * `i32` - The tool's own default level
+    pub fn default_level(self) -> i32 {
+        match self {
+            Compression::Gzip => 6,
+            Compression::Zstd => 3,
+        }
+    }
+
+    /// Levels the format supports
+    ///
+    /// # Returns
+    /// * `RangeInclusive<i32>` - Fastest to smallest
+    pub fn levels(self) -> RangeInclusive<i32> {
+        match self {
+            Compression::Gzip => 0..=9,
+            Compression::Zstd => 1..=22,
+        }
+    }
+
+    /// Recognize a compressed stream from its first bytes
+    ///
+    /// # Arguments
+    /// * `header` - Start of the file
+    ///
+    /// # Returns
+    /// * `Option<Compression>` - Compression, or None for plain text
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// File that output is written to, optionally compressed
+#[derive(Debug, Clone)]
+pub struct OutputFile {
+    /// Destination path
+    pub path: PathBuf,
+    /// Compression and level, if any
+    pub compression: Option<(Compression, i32)>,
+    /// Append to an existing file instead of replacing it
+    pub append: bool,
+}
+
+impl OutputFile {
+    /// Describe an output file
+    ///
+    /// # Arguments
+    /// * `path` - Destination path
+    /// * `compression` - Stream compression, if any
+    /// * `level` - Compression level; the format's default if None
+    ///
+    /// # Returns
+    /// * `Result<OutputFile>` - Output file, or error for a level the
+    ///   format doesn't support or a level without compression
+    pub fn new(
+        path: PathBuf,
+        compression: Option<Compression>,
+        level: Option<i32>,
+    ) -> Result<Self> {
+        let compression = match (compression, level) {
+            (None, Some(_)) => {
+                return Err(anyhow!("--compress-level needs --compress"))
+            }
+            (None, None) => None,
+            (Some(kind), level) => {
+                let level = level.unwrap_or_else(|| kind.default_level());
+                if !kind.levels().contains(&level) {
+                    return Err(anyhow!(
+                        "{:?} supports levels {} to {}",
+                        kind,
+                        kind.levels().start(),
+                        kind.levels().end()
+                    ));
+                }
+                Some((kind, level))
+            }
+        };
+        Ok(Self {
+            path,
+            compression,
+            append: false,
+        })
+    }
+
+    /// Append to the file instead of replacing it
+    ///
+    /// Compressed output is appended as a new gzip member or zstd frame,
+    /// which decompressors read as one continuous stream.
+    ///
+    /// # Arguments
+    /// * `append` - Whether to keep the existing contents
+    ///
+    /// # Returns
+    /// * `OutputFile` - Updated output file
+    pub fn with_append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Create the file, or open it for appending
+    ///
+    /// # Returns
+    /// * `Result<Box<dyn Write + Send>>` - Writer that finishes the
+    ///   compressed stream when dropped
+    pub fn create(&self) -> Result<Box<dyn Write + Send>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(&self.path)
+            .with_context(|| {
+                format!("Failed to create {}", self.path.display())
+            })?;
+        Ok(match self.compression {
+            None => Box::new(file),
+            Some((Compression::Gzip, level)) => Box::new(ThrottledFlush::new(
+                GzEncoder::new(file, flate2::Compression::new(level as u32)),
+            )),
+            Some((Compression::Zstd, level)) => {
+                Box::new(ThrottledFlush::new(zstd_stream::writer(file, level)?))
+            }
+        })
+    }
+}
+
+/// Writer that passes flushes through at most once per interval
+///
+/// A flush held back is passed through before the next write once the
+/// interval is over, or by the next flush after it, such as the one sinks
+/// make every tick, so output stops short of the last flush by at most
+/// an interval even when no more events come.
+struct ThrottledFlush<W> {
+    /// Compressing writer
+    inner: W,
+    /// When the last flush was passed through
+    last_flush: Instant,
+    /// A flush was held back and hasn't been passed through yet
+    pending: bool,
+}
+
+impl<W: Write> ThrottledFlush<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            last_flush: Instant::now(),
+            pending: false,
+        }
+    }
+
+    /// Whether a flush may be passed through now
+    fn due(&self) -> bool {
+        self.last_flush.elapsed() >= COMPRESSED_FLUSH_INTERVAL
+    }
+
+    /// Flush the compressing writer
+    fn pass_flush(&mut self) -> io::Result<()> {
+        self.pending = false;
+        self.last_flush = Instant::now();
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for ThrottledFlush<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending && self.due() {
+            self.pass_flush()?;
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.due() {
+            self.pending = true;
+            return Ok(());
+        }
+        self.pass_flush()
+    }
+}
+
+/// zstd streams
+#[cfg(feature = "zstd")]
+mod zstd_stream {
+    use anyhow::{Context, Result};
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+
+    /// Start a zstd stream finished when the writer is dropped
+    pub fn writer(file: File, level: i32) -> Result<Box<dyn Write + Send>> {
+        let encoder = zstd::Encoder::new(file, level)
+            .context("Failed to start zstd stream")?;
+        Ok(Box::new(encoder.auto_finish()))
+    }
+
+    /// Decompress a zstd capture
+    pub fn reader(reader: BufReader<File>) -> Result<Box<dyn BufRead>> {
+        Ok(Box::new(BufReader::new(
+            zstd::Decoder::with_buffer(reader)
+                .context("Failed to start zstd stream")?,
+        )))
+    }
+}
+
+/// Stand-in for builds without zstd, refusing zstd streams
+#[cfg(not(feature = "zstd"))]
+mod zstd_stream {
+    use anyhow::Result;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+
+    use crate::features::not_compiled_in;
+
+    pub fn writer(_: File, _: i32) -> Result<Box<dyn Write + Send>> {
+        Err(not_compiled_in("zstd", "zstd output"))
+    }
+
+    pub fn reader(_: BufReader<File>) -> Result<Box<dyn BufRead>> {
+        Err(not_compiled_in("zstd", "Reading a zstd capture"))
+    }
+}
+
+/// Open a capture for reading, decompressing it if needed
+///
+/// # Arguments
+/// * `path` - Plain, gzip or zstd capture
+///
+/// # Returns
+/// * `Result<Box<dyn BufRead>>` - Reader yielding the plain text
+pub fn open_capture(path: &Path) -> Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(
+        File::open(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+    );
+    let header = reader
+        .fill_buf()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(match Compression::detect(header) {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => {
+            Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+        }
+        Some(Compression::Zstd) => zstd_stream::reader(reader)?,
+    })
+}
+
+/// Lines of a capture, read one at a time
+///
+/// A compressed capture that ends mid-stream yields the lines before the
+/// damage and then stops, dropping the partly decoded line;
+/// [`CaptureLines::truncated`] tells whether that happened. Plain
+/// captures end where the file does.
+pub struct CaptureLines {
+    /// Decompressed text
+    reader: Box<dyn BufRead>,
+    /// Path, for messages
+    path: PathBuf,
+    /// Whether the capture is compressed
+    compressed: bool,
+    /// Whether a compressed capture was found cut short
+    truncated: bool,
+    /// Whether lines that aren't text are read with the invalid bytes
+    /// replaced instead of failing
+    lossy: bool,
+}
+
+impl CaptureLines {
+    /// Read lines that aren't text instead of failing on them
+    ///
+    /// Invalid bytes are replaced with U+FFFD, so a line someone slipped
+    /// into a recording can still be checked and reported.
+    ///
+    /// # Returns
+    /// * `CaptureLines` - The reader, replacing invalid bytes
+    pub fn lossy(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+    /// Whether the capture was found cut short so far
+    ///
+    /// # Returns
+    /// * `bool` - True if a compressed stream ended before it was
+    ///   complete
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Iterator for CaptureLines {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.truncated {
+            return None;
+        }
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                if self.lossy {
+                    return Some(Ok(String::from_utf8_lossy(&line).into()));
+                }
+                Some(String::from_utf8(line).with_context(|| {
+                    format!("{} is not text", self.path.display())
+                }))
+            }
+            Err(e) if self.compressed => {
+                warn!(
+                    "{} is cut short ({}); reading it up to the damage",
+                    self.path.display(),
+                    e
+                );
+                self.truncated = true;
+                None
+            }
+            Err(e) => Some(Err(e).with_context(|| {
+                format!("Failed to read {}", self.path.display())
+            })),
+        }
+    }
+}
+
+/// Read a capture line by line, decompressing it if needed
+///
+/// # Arguments
+/// * `path` - Plain, gzip or zstd capture
+///
+/// # Returns
+/// * `Result<CaptureLines>` - Lines without their line endings, or error
+///   if the file can't be opened
+pub fn capture_lines(path: &Path) -> Result<CaptureLines> {
+    Ok(CaptureLines {
+        reader: open_capture(path)?,
+        path: path.to_path_buf(),
+        compressed: file_compression(path)?.is_some(),
+        truncated: false,
+        lossy: false,
+    })
+}
+
+/// Compression of an existing file
+///
+/// # Arguments
+/// * `path` - File to inspect
+///
+/// # Returns
+/// * `Result<Option<Compression>>` - Compression, or None for plain text
+pub fn file_compression(path: &Path) -> Result<Option<Compression>> {
+    let mut header = Vec::with_capacity(ZSTD_MAGIC.len());
+    File::open(path)
+        .and_then(|file| {
+            file.take(ZSTD_MAGIC.len() as u64).read_to_end(&mut header)
+        })
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Compression::detect(&header))
+}
+
+/// Read a whole capture as text, decompressing it if needed
+///
+/// # Arguments
+/// * `path` - Plain, gzip or zstd capture
+///
+/// # Returns
+/// * `Result<String>` - Capture contents, one line per line, up to the
+///   damage if a compressed capture is cut short
+pub fn read_capture(path: &Path) -> Result<String> {
+    let mut text = String::new();
+    for line in capture_lines(path)? {
+        text.push_str(&line?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_output_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let kinds = [None, Some(Compression::Gzip), Some(Compression::Zstd)];
+        for kind in kinds.into_iter().filter(|kind| {
+            cfg!(feature = "zstd") || *kind != Some(Compression::Zstd)
+        }) {
+            let path = dir.path().join("capture");
+            let output = OutputFile::new(path.clone(), kind, None).unwrap();
+            let mut writer = output.create().unwrap();
+            for i in 0..100 {
+                writeln!(writer, "line {}", i).unwrap();
+                writer.flush().unwrap();
+            }
+            drop(writer);
+
+            let text = read_capture(&path).unwrap();
+            assert_eq!(text.lines().count(), 100, "{:?}", kind);
+            assert_eq!(text.lines().last(), Some("line 99"));
+        }
+    }
+
+    /// Writer counting the flushes it gets
+    #[derive(Default)]
+    struct Flushes(usize);
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_held_back_flush_is_passed_on() {
+        let mut writer = ThrottledFlush::new(Flushes::default());
+        writer.write_all(b"line\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.0, 0);
+        assert!(writer.pending);
+
+        // Not yet due: the write goes out without a flush
+        writer.write_all(b"line\n").unwrap();
+        assert_eq!(writer.inner.0, 0);
+
+        // Due: the flush owed is passed on before the next write
+        writer.last_flush -= COMPRESSED_FLUSH_INTERVAL;
+        writer.write_all(b"line\n").unwrap();
+        assert_eq!(writer.inner.0, 1);
+        assert!(!writer.pending);
+
+        // Or by the next flush, e.g. a sink's tick
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.0, 1);
+        writer.last_flush -= COMPRESSED_FLUSH_INTERVAL;
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.0, 2);
+    }
+
+    #[test]
+    fn test_truncated_capture_reads_to_the_damage() {
+        let dir = tempfile::tempdir().unwrap();
+        let kinds = [Compression::Gzip, Compression::Zstd];
+        for kind in kinds
+            .into_iter()
+            .filter(|kind| cfg!(feature = "zstd") || *kind != Compression::Zstd)
+        {
+            let path = dir.path().join("capture");
+            let output = OutputFile::new(path.clone(), Some(kind), None)
+                .unwrap()
+                .with_append(true);
+            let mut ends = Vec::new();
+            for i in 0..3 {
+                let mut writer = output.create().unwrap();
+                writeln!(writer, "line {}", i).unwrap();
+                drop(writer);
+                ends.push(std::fs::metadata(&path).unwrap().len() as usize);
+            }
+            // Cut the last gzip member or zstd frame in half
+            let whole = std::fs::read(&path).unwrap();
+            let cut = (ends[1] + ends[2]) / 2;
+            std::fs::write(&path, &whole[..cut]).unwrap();
+
+            let mut lines = capture_lines(&path).unwrap();
+            let read: Vec<String> =
+                lines.by_ref().collect::<Result<_>>().unwrap();
+            assert_eq!(read, ["line 0", "line 1"], "{:?}", kind);
+            assert!(lines.truncated());
+            assert_eq!(read_capture(&path).unwrap(), "line 0\nline 1\n");
+        }
+    }
+
+    #[test]
+    fn test_levels_are_validated() {
+        let path = PathBuf::from("out.zst");
+        let zstd = Some(Compression::Zstd);
+        assert_eq!(
+            OutputFile::new(path.clone(), zstd, None)
+                .unwrap()
+                .compression,
+            Some((Compression::Zstd, 3))
+        );
+        assert!(OutputFile::new(path.clone(), zstd, Some(19)).is_ok());
+        assert!(OutputFile::new(
+            path.clone(),
+            Some(Compression::Gzip),
+            Some(19)
+        )
+        .is_err());
+        assert!(OutputFile::new(path, None, Some(1)).is_err());
+    }
+}
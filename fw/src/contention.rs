@@ -0,0 +1,205 @@
+//! Contention module
+//!
+//! Ranks files by lock contention (`fw collect --mode stats
+//! --contention`). Every flock and fcntl lock request is counted against
+//! its file, along with whether it had to wait for another holder (or
+//! was refused one) and how long the call took, so the files processes
+//! queue up on are listed first.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::file_event::{format_latency, FileAction, FileEvent};
+use crate::report::json_string;
+use crate::stats::{csv_row, ExportFormat};
+
+/// Lock requests on one file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Lock requests, refused ones included
+    pub requests: u64,
+    /// Requests that waited for another holder or were refused
+    pub blocked: u64,
+    /// Time spent in lock calls, in nanoseconds
+    pub wait_ns: u64,
+    /// Longest single lock call, in nanoseconds
+    pub max_wait_ns: u64,
+}
+
+/// Lock requests per file
+#[derive(Debug, Clone, Default)]
+pub struct LockContention {
+    /// Counts by file path
+    files: HashMap<String, LockStats>,
+}
+
+impl LockContention {
+    /// Create an empty contention table
+    ///
+    /// # Returns
+    /// * `LockContention` - Table with no lock requests
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a lock request; other events are ignored
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    pub fn record(&mut self, event: &FileEvent) {
+        let FileAction::Locked { blocked, .. } = event.action else {
+            return;
+        };
+        let wait_ns = event.open_latency_ns.unwrap_or(0);
+        let stats = self.files.entry(event.file_path.clone()).or_default();
+        stats.requests += 1;
+        stats.blocked += u64::from(blocked);
+        stats.wait_ns += wait_ns;
+        stats.max_wait_ns = stats.max_wait_ns.max(wait_ns);
+    }
+
+    /// Files with their lock requests, most contended first
+    ///
+    /// # Returns
+    /// * `Vec<(&str, LockStats)>` - Path and counts, ordered by blocked
+    ///   requests, then time waited
+    pub fn rows(&self) -> Vec<(&str, LockStats)> {
+        let mut rows: Vec<_> = self
+            .files
+            .iter()
+            .map(|(path, &stats)| (path.as_str(), stats))
+            .collect();
+        rows.sort_by(|a, b| {
+            (b.1.blocked, b.1.wait_ns)
+                .cmp(&(a.1.blocked, a.1.wait_ns))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        rows
+    }
+
+    /// Render the table printed when stats finish
+    ///
+    /// # Returns
+    /// * `Vec<String>` - One line per file: blocked and total requests,
+    ///   time waited, longest wait and path
+    pub fn lines(&self) -> Vec<String> {
+        self.rows()
+            .into_iter()
+            .map(|(path, stats)| {
+                format!(
+                    "{:>8} of {:>7} | waited {}, longest {} | {}",
+                    stats.blocked,
+                    stats.requests,
+                    format_latency(stats.wait_ns),
+                    format_latency(stats.max_wait_ns),
+                    path
+                )
+            })
+            .collect()
+    }
+
+    /// Render the table for export
+    ///
+    /// # Arguments
+    /// * `format` - Export format
+    ///
+    /// # Returns
+    /// * `String` - Rendered table, most contended file first
+    pub fn export(&self, format: ExportFormat) -> String {
+        let mut out = String::new();
+        match format {
+            ExportFormat::Csv => {
+                out.push_str("path,requests,blocked,wait_ns,max_wait_ns\n");
+                for (path, stats) in self.rows() {
+                    let _ = writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        csv_row(&[path.to_string()]),
+                        stats.requests,
+                        stats.blocked,
+                        stats.wait_ns,
+                        stats.max_wait_ns
+                    );
+                }
+            }
+            ExportFormat::Json => {
+                let objects: Vec<String> = self
+                    .rows()
+                    .into_iter()
+                    .map(|(path, stats)| {
+                        format!(
+                            "{{\"path\":{},\"requests\":{},\"blocked\":{},\
+                             \"wait_ns\":{},\"max_wait_ns\":{}}}",
+                            json_string(path),
+                            stats.requests,
+                            stats.blocked,
+                            stats.wait_ns,
+                            stats.max_wait_ns
+                        )
+                    })
+                    .collect();
+                let _ = writeln!(out, "[{}]", objects.join(","));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::LockKind;
+
+    fn lock(path: &str, blocked: bool, wait_ns: u64) -> FileEvent {
+        let action = FileAction::Locked {
+            kind: LockKind::Exclusive,
+            blocked,
+            acquired: true,
+        };
+        FileEvent::new(
+            path.to_string(),
+            "db".to_string(),
+            action,
+            1,
+            SystemClock.now(),
+        )
+        .with_open_latency_ns(wait_ns)
+    }
+
+    #[test]
+    fn test_files_ranked_by_contention() {
+        let mut contention = LockContention::new();
+        contention.record(&lock("/a.lock", false, 2_000));
+        contention.record(&lock("/a.lock", false, 3_000));
+        contention.record(&lock("/b.lock", true, 5_000_000));
+        contention.record(&lock("/b.lock", false, 1_000));
+        contention.record(&FileEvent::new(
+            "/b.lock".to_string(),
+            "db".to_string(),
+            FileAction::Unlocked,
+            1,
+            SystemClock.now(),
+        ));
+
+        let rows = contention.rows();
+        assert_eq!(rows[0].0, "/b.lock");
+        assert_eq!(
+            rows[0].1,
+            LockStats {
+                requests: 2,
+                blocked: 1,
+                wait_ns: 5_001_000,
+                max_wait_ns: 5_000_000,
+            }
+        );
+        assert_eq!(rows[1].1.blocked, 0);
+        assert_eq!(
+            contention.lines()[0],
+            "       1 of       2 | waited 5.0ms, longest 5.0ms | /b.lock"
+        );
+        assert!(contention
+            .export(ExportFormat::Csv)
+            .contains("\n/a.lock,2,0,5000,3000\n"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Diagnostics module
+//!
+//! Routes fw's own operational messages (attach failures, drops, sink
+//! errors, lifecycle notices) to a channel separate from file events.
+//! Messages go through the `log` macros as before; this module decides
+//! where they end up, in what format and at which verbosity. `RUST_LOG`
+//! still works and is overridden by `--log-level`.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use env_logger::{Builder, Target};
+use log::{LevelFilter, Record};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::collector::OutputStream;
+use crate::report::json_string;
+
+/// How diagnostic messages are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// env_logger's human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per message
+    Json,
+}
+
+/// Most verbose diagnostics to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    /// No diagnostics
+    Off,
+    /// Failures only
+    Error,
+    /// Failures, drops and degraded operation
+    Warn,
+    /// Lifecycle notices such as attach and detach
+    Info,
+    /// Details for troubleshooting
+    Debug,
+    /// Everything
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Where and how diagnostics are written
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsConfig {
+    /// Verbosity; `RUST_LOG` (or errors only) if unset
+    pub level: Option<LogLevel>,
+    /// Line format
+    pub format: LogFormat,
+    /// File to append diagnostics to; `stream` if unset
+    pub file: Option<PathBuf>,
+    /// Stream to write diagnostics to when there is no file
+    pub stream: OutputStream,
+    /// Keep diagnostics off stderr so it carries only file events
+    pub quiet: bool,
+}
+
+/// Install the global logger
+///
+/// # Arguments
+/// * `config` - Destination, format and verbosity
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if the log file can't be opened
+pub fn init(config: &DiagnosticsConfig) -> Result<()> {
+    let mut builder = Builder::from_default_env();
+    if let Some(level) = config.level {
+        builder.filter_level(level.into());
+    }
+    match &config.file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| {
+                    format!("Failed to open log file {}", path.display())
+                })?;
+            builder.target(Target::Pipe(Box::new(file)));
+        }
+        None if config.quiet && config.stream == OutputStream::Stderr => {
+            builder.filter_level(LevelFilter::Off);
+        }
+        None if config.stream == OutputStream::Stdout => {
+            builder.target(Target::Stdout);
+        }
+        None => {}
+    }
+    if config.format == LogFormat::Json {
+        builder.format(|buf, record| {
+            writeln!(buf, "{}", json_line(record, Utc::now()))
+        });
+    }
+    // Only fails if a logger is already installed, e.g. in tests
+    let _ = builder.try_init();
+    Ok(())
+}
+
+/// Format a diagnostic message as a JSON object
+///
+/// # Arguments
+/// * `record` - Message and its metadata
+/// * `time` - When the message was logged
+///
+/// # Returns
+/// * `String` - Object with "time", "level", "target" and "message"
+pub fn json_line(record: &Record, time: DateTime<Utc>) -> String {
+    format!(
+        "{{\"time\":{},\"level\":{},\"target\":{},\"message\":{}}}",
+        json_string(&time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        json_string(&record.level().as_str().to_ascii_lowercase()),
+        json_string(record.target()),
+        json_string(&record.args().to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_json_line() {
+        let time = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let line = json_line(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("fw::fanout")
+                .args(format_args!("Sink \"stderr\" fell behind"))
+                .build(),
+            time,
+        );
+        assert_eq!(
+            line,
+            "{\"time\":\"2024-05-01T12:00:00.000Z\",\"level\":\"warn\",\
+             \"target\":\"fw::fanout\",\
+             \"message\":\"Sink \\\"stderr\\\" fell behind\"}"
+        );
+    }
+}
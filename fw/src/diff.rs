@@ -0,0 +1,591 @@
+//! Diff module
+//!
+//! Implements `fw diff`, which compares file activity between a baseline
+//! and a current run to answer "what is new or gone since the change?".
+//! Either side can be a capture or recording of `fw collect` output,
+//! whose events are grouped by the `--group-by` dimensions, or a stats
+//! snapshot exported with `fw collect --mode stats --export`, whose
+//! groups are summed down to those dimensions. Groups only in the current
+//! run are added, groups only in the baseline are removed, and groups in
+//! both with a different count are changed.
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::compression::read_capture;
+use crate::report::{json_string, CapturedEvent};
+use crate::stats::{csv_row, Dimension};
+
+/// Events per group, keyed by the dimension values
+type Counts = BTreeMap<Vec<String>, u64>;
+
+/// Column names of a stats snapshot, and its rows of dimension values
+/// with their counts
+type Snapshot = (Vec<String>, Vec<(Vec<String>, u64)>);
+
+/// Output format of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    /// Aligned table, one group per row
+    Table,
+    /// JSON object with the added, removed and changed groups
+    Json,
+}
+
+/// How a group differs between the two runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    /// Only in the current run
+    Added,
+    /// Only in the baseline
+    Removed,
+    /// In both, with a different count
+    Changed,
+}
+
+impl Change {
+    /// Label used in the table and as the JSON key
+    fn label(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+}
+
+/// One group that differs between the two runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffRow {
+    /// How it differs
+    pub change: Change,
+    /// Dimension values, in `--group-by` order
+    pub key: Vec<String>,
+    /// Events in the baseline
+    pub baseline: u64,
+    /// Events in the current run
+    pub current: u64,
+}
+
+/// Comparison of the grouped counts of two runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// Names of the dimensions forming each group
+    pub group_by: Vec<String>,
+    /// Groups that differ: added, then removed, then changed, each with
+    /// the largest difference first
+    pub rows: Vec<DiffRow>,
+    /// Number of groups with the same count in both runs
+    pub unchanged: usize,
+}
+
+impl Diff {
+    /// Compare the grouped counts of two runs
+    ///
+    /// # Arguments
+    /// * `group_by` - Names of the dimensions forming each group
+    /// * `baseline` - Events per group in the baseline
+    /// * `current` - Events per group in the current run
+    ///
+    /// # Returns
+    /// * `Diff` - Groups that were added, removed or changed
+    pub fn new(
+        group_by: Vec<String>,
+        baseline: &Counts,
+        current: &Counts,
+    ) -> Self {
+        let mut rows = Vec::new();
+        let mut unchanged = 0;
+        for (key, &count) in current {
+            match baseline.get(key) {
+                None => rows.push(DiffRow {
+                    change: Change::Added,
+                    key: key.clone(),
+                    baseline: 0,
+                    current: count,
+                }),
+                Some(&before) if before != count => rows.push(DiffRow {
+                    change: Change::Changed,
+                    key: key.clone(),
+                    baseline: before,
+                    current: count,
+                }),
+                Some(_) => unchanged += 1,
+            }
+        }
+        for (key, &count) in baseline {
+            if !current.contains_key(key) {
+                rows.push(DiffRow {
+                    change: Change::Removed,
+                    key: key.clone(),
+                    baseline: count,
+                    current: 0,
+                });
+            }
+        }
+        rows.sort_by(|a, b| {
+            a.change
+                .cmp(&b.change)
+                .then_with(|| {
+                    b.current
+                        .abs_diff(b.baseline)
+                        .cmp(&a.current.abs_diff(a.baseline))
+                })
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        Self {
+            group_by,
+            rows,
+            unchanged,
+        }
+    }
+
+    /// Render the diff
+    ///
+    /// # Arguments
+    /// * `format` - Output format
+    ///
+    /// # Returns
+    /// * `String` - Rendered diff
+    pub fn render(&self, format: DiffFormat) -> String {
+        match format {
+            DiffFormat::Table => self.render_table(),
+            DiffFormat::Json => self.render_json(),
+        }
+    }
+
+    /// Render as an aligned table followed by a summary line
+    fn render_table(&self) -> String {
+        let mut out = format!(
+            "{:<8} {:>8} {:>8} {:>8} | {}\n",
+            "change",
+            "baseline",
+            "current",
+            "delta",
+            self.group_by.join(" | ")
+        );
+        for row in &self.rows {
+            let _ = writeln!(
+                out,
+                "{:<8} {:>8} {:>8} {:>+8} | {}",
+                row.change.label(),
+                row.baseline,
+                row.current,
+                row.current as i128 - row.baseline as i128,
+                row.key.join(" | ")
+            );
+        }
+        let count = |change| {
+            self.rows.iter().filter(|row| row.change == change).count()
+        };
+        let _ = writeln!(
+            out,
+            "{} added, {} removed, {} changed, {} unchanged",
+            count(Change::Added),
+            count(Change::Removed),
+            count(Change::Changed),
+            self.unchanged
+        );
+        out
+    }
+
+    /// Render as a JSON object with one array per kind of change
+    fn render_json(&self) -> String {
+        let section = |change: Change| {
+            let objects: Vec<String> = self
+                .rows
+                .iter()
+                .filter(|row| row.change == change)
+                .map(|row| {
+                    let fields: String = self
+                        .group_by
+                        .iter()
+                        .zip(&row.key)
+                        .map(|(name, value)| {
+                            format!(
+                                "{}:{},",
+                                json_string(name),
+                                json_string(value)
+                            )
+                        })
+                        .collect();
+                    format!(
+                        "{{{}\"baseline\":{},\"current\":{}}}",
+                        fields, row.baseline, row.current
+                    )
+                })
+                .collect();
+            format!("\"{}\":[{}]", change.label(), objects.join(","))
+        };
+        let names: Vec<String> =
+            self.group_by.iter().map(|name| json_string(name)).collect();
+        format!(
+            "{{\"group_by\":[{}],{},{},{},\"unchanged\":{}}}\n",
+            names.join(","),
+            section(Change::Added),
+            section(Change::Removed),
+            section(Change::Changed),
+            self.unchanged
+        )
+    }
+}
+
+/// Group the events of a capture
+///
+/// # Arguments
+/// * `text` - Output of `fw collect` or `fw record`
+/// * `group_by` - Dimensions forming each group
+///
+/// # Returns
+/// * `Result<Counts>` - Events per group, or an error for dimensions a
+///   capture doesn't hold
+fn group_capture(text: &str, group_by: &[Dimension]) -> Result<Counts> {
+    if let Some(dimension) = group_by
+        .iter()
+        .find(|d| matches!(d, Dimension::Tag(_) | Dimension::Pattern))
+    {
+        return Err(anyhow!(
+            "Captures can't be grouped by {}; diff stats snapshots \
+             exported with it instead",
+            dimension
+        ));
+    }
+    let mut counts = BTreeMap::new();
+    for event in text.lines().filter_map(CapturedEvent::parse) {
+        let key = group_by
+            .iter()
+            .filter_map(|d| d.value_of_captured(&event))
+            .collect();
+        *counts.entry(key).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+/// Read the groups of a stats snapshot
+///
+/// # Arguments
+/// * `text` - JSON or CSV written by `fw collect --mode stats --export`
+///
+/// # Returns
+/// * `Option<Result<Snapshot>>` - Column names and rows, None if the
+///   text is not a stats snapshot, or an error if it is a malformed one
+fn parse_snapshot(text: &str) -> Option<Result<Snapshot>> {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with(['[', '{']) {
+        return Some(parse_json_snapshot(trimmed));
+    }
+    let header = split_csv(text.lines().next()?);
+    if header.last().map(String::as_str) != Some("events") {
+        return None;
+    }
+    let columns = header[..header.len() - 1].to_vec();
+    let rows = text
+        .lines()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut values = split_csv(line);
+            let count = values
+                .pop()
+                .and_then(|count| count.parse().ok())
+                .filter(|_| values.len() == columns.len())
+                .ok_or_else(|| anyhow!("Invalid stats row: {}", line))?;
+            Ok((values, count))
+        })
+        .collect::<Result<_>>();
+    Some(rows.map(|rows| (columns, rows)))
+}
+
+/// Read the groups of a JSON stats snapshot
+fn parse_json_snapshot(text: &str) -> Result<Snapshot> {
+    let objects: Vec<OrderedObject> =
+        serde_json::from_str(text).context("Invalid JSON stats snapshot")?;
+    let mut columns: Vec<String> = Vec::new();
+    let mut rows = Vec::new();
+    for OrderedObject(fields) in objects {
+        if columns.is_empty() {
+            columns = fields
+                .iter()
+                .map(|(k, _)| k)
+                .filter(|k| *k != "events")
+                .cloned()
+                .collect();
+        }
+        let get =
+            |name: &str| fields.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+        let count = get("events").and_then(Value::as_u64);
+        let key: Option<Vec<String>> = columns
+            .iter()
+            .map(|c| get(c).and_then(Value::as_str).map(str::to_string))
+            .collect();
+        match (key, count) {
+            (Some(key), Some(count)) if fields.len() == columns.len() + 1 => {
+                rows.push((key, count))
+            }
+            _ => return Err(anyhow!("Invalid stats group: {:?}", fields)),
+        }
+    }
+    Ok((columns, rows))
+}
+
+/// A JSON object whose fields keep the order they were written in, so a
+/// snapshot's columns come out in its header order rather than sorted
+struct OrderedObject(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for OrderedObject {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = OrderedObject;
+
+            fn expecting(
+                &self,
+                f: &mut std::fmt::Formatter,
+            ) -> std::fmt::Result {
+                f.write_str("a stats group object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> std::result::Result<OrderedObject, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry::<String, Value>()? {
+                    fields.push(field);
+                }
+                Ok(OrderedObject(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+/// Split a CSV row written by [`csv_row`] into its values
+pub(crate) fn split_csv(line: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                values.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(String::new()),
+            c => values.last_mut().unwrap().push(c),
+        }
+    }
+    values
+}
+
+/// Sum the rows of a stats snapshot down to the wanted dimensions
+///
+/// # Arguments
+/// * `path` - Snapshot file, for error messages
+/// * `columns` - Column names of the snapshot
+/// * `rows` - Dimension values and counts
+/// * `names` - Names of the wanted dimensions
+///
+/// # Returns
+/// * `Result<Counts>` - Events per group, or an error if the snapshot
+///   lacks a dimension
+fn regroup_snapshot(
+    path: &Path,
+    columns: &[String],
+    rows: Vec<(Vec<String>, u64)>,
+    names: &[String],
+) -> Result<Counts> {
+    let indices = names
+        .iter()
+        .map(|name| {
+            columns.iter().position(|c| c == name).ok_or_else(|| {
+                anyhow!(
+                    "{} has no {} column (it has {})",
+                    path.display(),
+                    name,
+                    csv_row(columns)
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut counts = BTreeMap::new();
+    for (values, count) in rows {
+        let key = indices.iter().map(|&i| values[i].clone()).collect();
+        *counts.entry(key).or_default() += count;
+    }
+    Ok(counts)
+}
+
+/// Group one side of the diff
+///
+/// # Arguments
+/// * `path` - Capture, recording or stats snapshot
+/// * `group_by` - Dimensions forming each group, or None for the
+///   columns of a snapshot and process,path for a capture
+///
+/// # Returns
+/// * `Result<(Vec<String>, Counts)>` - Names of the dimensions used, and
+///   events per group
+fn load(
+    path: &Path,
+    group_by: Option<&[Dimension]>,
+) -> Result<(Vec<String>, Counts)> {
+    let text = read_capture(path)?;
+    let names = |dimensions: &[Dimension]| -> Vec<String> {
+        dimensions.iter().map(ToString::to_string).collect()
+    };
+    match parse_snapshot(&text) {
+        Some(snapshot) => {
+            let (columns, rows) = snapshot.with_context(|| {
+                format!("Failed to read {}", path.display())
+            })?;
+            let names = group_by.map_or_else(|| columns.clone(), names);
+            let counts = regroup_snapshot(path, &columns, rows, &names)?;
+            Ok((names, counts))
+        }
+        None => {
+            let default = [Dimension::Process, Dimension::Path];
+            let group_by = group_by.unwrap_or(&default);
+            Ok((names(group_by), group_capture(&text, group_by)?))
+        }
+    }
+}
+
+/// Run `fw diff`
+///
+/// # Arguments
+/// * `baseline` - Capture, recording or stats snapshot before the change
+/// * `current` - The same after the change
+/// * `group_by` - Dimensions to compare by, or None for the default
+/// * `format` - Output format
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_diff(
+    baseline: &Path,
+    current: &Path,
+    group_by: Option<&[Dimension]>,
+    format: DiffFormat,
+) -> Result<()> {
+    let (names, before) = load(baseline, group_by)?;
+    let (current_names, after) = load(current, group_by)?;
+    if names != current_names {
+        return Err(anyhow!(
+            "{} is grouped by {} but {} by {}; pass --group-by",
+            baseline.display(),
+            names.join(","),
+            current.display(),
+            current_names.join(",")
+        ));
+    }
+    print!("{}", Diff::new(names, &before, &after).render(format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASELINE: &str = "\
+2024-05-01 10:00:00 UTC | nginx (10) | opened | /etc/nginx/nginx.conf
+2024-05-01 10:00:01 UTC | nginx (10) | opened | /var/log/access.log
+2024-05-01 10:00:02 UTC | nginx (10) | opened | /var/log/access.log
+# checkpoint seq=1
+2024-05-01 10:00:03 UTC | cron (20) | opened | /etc/crontab
+";
+
+    const CURRENT: &str = "\
+2024-05-02 10:00:00 UTC | nginx (11) | opened | /etc/nginx/nginx.conf
+2024-05-02 10:00:01 UTC | nginx (11) | opened | /var/log/access.log
+2024-05-02 10:00:02 UTC | curl (30) | opened | /etc/hosts
+2024-05-02 10:00:03 UTC | curl (30) | chmod 0600 | /etc/hosts
+";
+
+    fn diff(group_by: &[Dimension]) -> Diff {
+        let names = group_by.iter().map(ToString::to_string).collect();
+        Diff::new(
+            names,
+            &group_capture(BASELINE, group_by).unwrap(),
+            &group_capture(CURRENT, group_by).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_diff_captures() {
+        let diff = diff(&[Dimension::Process, Dimension::Path]);
+        let summary: Vec<(Change, String, u64, u64)> = diff
+            .rows
+            .iter()
+            .map(|r| (r.change, r.key.join(" "), r.baseline, r.current))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Change::Added, "curl /etc/hosts".into(), 0, 2),
+                (Change::Removed, "cron /etc/crontab".into(), 1, 0),
+                (Change::Changed, "nginx /var/log/access.log".into(), 2, 1),
+            ]
+        );
+        assert_eq!(diff.unchanged, 1);
+
+        // Coarser groups hide the per-file changes
+        let by_action = self::diff(&[Dimension::Action]);
+        assert_eq!(by_action.rows.len(), 2);
+        assert!(group_capture(BASELINE, &[Dimension::Pattern]).is_err());
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let diff = diff(&[Dimension::Process, Dimension::Path]);
+        let table = diff.render(DiffFormat::Table);
+        assert!(table.contains(
+            "added           0        2       +2 | curl | /etc/hosts\n"
+        ));
+        assert!(table.contains(
+            "changed         2        1       -1 | nginx | /var/log/access.log"
+        ));
+        assert!(table.ends_with("1 added, 1 removed, 1 changed, 1 unchanged\n"));
+
+        let json = diff.render(DiffFormat::Json);
+        assert!(json.starts_with(
+            "{\"group_by\":[\"process\",\"path\"],\"added\":[{\"process\":\
+             \"curl\",\"path\":\"/etc/hosts\",\"baseline\":0,\"current\":2}]"
+        ));
+        assert!(json.ends_with(",\"unchanged\":1}\n"));
+        serde_json::from_str::<Value>(&json).unwrap();
+    }
+
+    #[test]
+    fn test_stats_snapshots() {
+        let csv = "process,extension,events\n\"a,b\",rs,3\na,log,2\na,rs,1\n";
+        let (columns, rows) = parse_snapshot(csv).unwrap().unwrap();
+        assert_eq!(columns, vec!["process", "extension"]);
+        assert_eq!(rows[0], (vec!["a,b".to_string(), "rs".to_string()], 3));
+
+        let path = Path::new("run.csv");
+        let by_ext =
+            regroup_snapshot(path, &columns, rows, &["extension".into()])
+                .unwrap();
+        assert_eq!(by_ext[&vec!["rs".to_string()]], 4);
+        let (columns, rows) = parse_snapshot(csv).unwrap().unwrap();
+        assert!(
+            regroup_snapshot(path, &columns, rows, &["user".into()]).is_err()
+        );
+
+        let json = "[{\"process\":\"a\",\"extension\":\"rs\",\"events\":5}]\n";
+        let (columns, rows) = parse_snapshot(json).unwrap().unwrap();
+        assert_eq!(columns, vec!["process", "extension"]);
+        assert_eq!(rows[0], (vec!["a".to_string(), "rs".to_string()], 5));
+        assert!(parse_snapshot("[{\"process\":1}]").unwrap().is_err());
+        assert!(parse_snapshot(BASELINE).is_none());
+    }
+}
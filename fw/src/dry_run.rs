@@ -0,0 +1,601 @@
+//! Dry run module
+//!
+//! Implements `fw collect --dry-run`, which validates the collect options
+//! and prints the plan they compile to without loading any eBPF programs:
+//! the probes and map entries pushed into the kernel, the criteria
+//! evaluated in userspace, the enrichment stages and where output goes.
+//! Paths given with `--test-path` are run through the plan to show
+//! whether, and why not, an access to them would be reported.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use nix::unistd::{getgid, getuid};
+use std::fmt::Write as _;
+
+use crate::audit_format::EventFormat;
+use crate::bpf_object::BpfObject;
+use crate::clock::{Clock, SystemClock};
+use crate::collector::CollectOptions;
+use crate::enrich::{EnricherKind, Enrichers, EnrichmentLevel};
+use crate::file_event::{FileAction, FileEvent};
+use crate::filter::FilterSpec;
+use crate::filter_builder::action_features;
+use crate::mount_table::MountTable;
+use crate::probes::{FeatureSet, ProbeFeature, ProbePlan};
+use crate::process_list::ProcessList;
+use crate::redact::Redactor;
+use crate::remote_monitor::RemoteConfig;
+use crate::user_filter::UserFilter;
+
+/// Describe the plan `fw collect` would run with these options
+///
+/// # Arguments
+/// * `options` - Collect options to describe
+/// * `mounts` - Mount table used to check mount criteria
+///
+/// # Returns
+/// * `String` - Plan as indented sections, newline-terminated
+pub fn describe_plan(options: &CollectOptions, mounts: &MountTable) -> String {
+    let mut out = String::new();
+    match &options.remote {
+        Some(remote) => describe_remote(&mut out, remote),
+        None => describe_kernel(&mut out, options),
+    }
+
+    out.push_str("Userspace filter:\n");
+    let criteria = describe_filter(&options.filter, mounts);
+    if criteria.is_empty() {
+        out.push_str("  (none, every event is reported)\n");
+    }
+    for line in criteria {
+        let _ = writeln!(out, "  {}", line);
+    }
+
+    out.push_str("Enrichment:\n");
+    let enrich = &options.enrich;
+    if enrich.level == EnrichmentLevel::Off {
+        out.push_str("  (off, events are reported as captured)\n");
+    } else {
+        let kinds = match enrich.level {
+            EnrichmentLevel::Full => EnricherKind::value_variants(),
+            _ => enrich.kinds.as_slice(),
+        };
+        let _ = writeln!(
+            out,
+            "  level: {} on {} workers",
+            value_name(&enrich.level),
+            enrich.workers.max(1)
+        );
+        if let Some(path) = &enrich.tag_map {
+            let _ = writeln!(out, "  tag map: {}", path.display());
+        }
+        if let Some(path) = &options.watchlists {
+            let _ = writeln!(out, "  watchlists: {}", path.display());
+        }
+        if !enrich.tripwires.is_empty() {
+            // --forensic can't look up ancestry or symbolize stacks
+            let detail = match (enrich.forensic, enrich.tripwire_stacks) {
+                (true, _) => "",
+                (false, false) => " with ancestry",
+                (false, true) => " with ancestry and stacks",
+            };
+            let _ = writeln!(
+                out,
+                "  tripwires: {} decoys, critical{}",
+                enrich.tripwires.len(),
+                detail
+            );
+        }
+        for kind in kinds {
+            let _ = writeln!(out, "  built-in: {}", value_name(kind));
+        }
+        let _ = writeln!(
+            out,
+            "  severity policy: {}",
+            enrich
+                .severity_policy
+                .as_ref()
+                .map_or("built-in".to_string(), |p| p.display().to_string())
+        );
+    }
+
+    out.push_str("Output:\n");
+    let _ = writeln!(out, "  mode: {}", value_name(&options.mode));
+    match &options.template {
+        Some(template) if options.format == EventFormat::Template => {
+            let _ = writeln!(out, "  format: template '{}'", template.as_str());
+        }
+        _ if options.format != EventFormat::Text => {
+            let _ = writeln!(out, "  format: {}", options.format);
+        }
+        _ => {}
+    }
+    if let Some(interval) = options.kernel_agg {
+        let _ = writeln!(out, "  kernel counting: read every {:?}", interval);
+    }
+    match (&options.forward, options.forensic) {
+        (Some(forward), _) => {
+            let _ = writeln!(
+                out,
+                "  forward: {} over TLS as host {}",
+                forward.addr, forward.hostname
+            );
+        }
+        (None, Some(max_bytes)) => {
+            let _ = writeln!(
+                out,
+                "  destination: memory (at most {} bytes), exported to {} \
+                 on SIGUSR2 and exit",
+                max_bytes,
+                options.stream.unwrap_or_default()
+            );
+        }
+        (None, None) => {
+            let _ = writeln!(
+                out,
+                "  destination: {}",
+                options.output.as_ref().map_or(
+                    options.stream.unwrap_or_default().to_string(),
+                    |o| o.path.display().to_string()
+                )
+            );
+        }
+    }
+    if let Some(exec) = &options.exec {
+        let _ = writeln!(out, "  exec: {}", exec.argv.join(" "));
+    }
+    if options.redact.anonymize_home {
+        out.push_str("  redact: user names in /home/<user>\n");
+    }
+    for rule in &options.redact.rules {
+        let _ = writeln!(out, "  redact: {}", rule);
+    }
+    if let Some(syslog) = &options.syslog {
+        let _ = writeln!(
+            out,
+            "  syslog: {} (facility {})",
+            syslog.socket.display(),
+            syslog.facility
+        );
+    }
+    if let Some(notify) = &options.notify {
+        for webhook in &notify.webhooks {
+            let _ = writeln!(
+                out,
+                "  notify: {} at {} and above",
+                webhook, notify.min_severity
+            );
+        }
+        if let Some(smtp) = &notify.email {
+            let _ = writeln!(
+                out,
+                "  notify: mail {} via {}:{} at {} and above",
+                smtp.to.join(", "),
+                smtp.host,
+                smtp.port,
+                notify.min_severity
+            );
+        }
+    }
+    if let Some(pagerduty) = &options.pagerduty {
+        let _ = writeln!(
+            out,
+            "  pagerduty: {} at {} and above, {} rules resolve on quiet",
+            pagerduty.url,
+            pagerduty.min_severity,
+            pagerduty.resolve_after.len()
+        );
+    }
+    if let Some(influx) = &options.influx {
+        let group_by: Vec<String> =
+            influx.group_by.iter().map(ToString::to_string).collect();
+        let _ = writeln!(
+            out,
+            "  influx: counts by {} every {}s to {}",
+            group_by.join(", "),
+            influx.interval.as_secs_f64(),
+            influx.url
+        );
+    }
+    if let Some(baseline) = &options.baseline {
+        let (programs, prefixes) = baseline.profile.size();
+        let _ = writeln!(
+            out,
+            "  baseline: alert outside {} programs' {} directories",
+            programs, prefixes
+        );
+    }
+    if !options.verify_inotify.is_empty() {
+        let dirs: Vec<String> = options
+            .verify_inotify
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        let _ = writeln!(
+            out,
+            "  verify: alert on changes only the probes or inotify saw in {}",
+            dirs.join(", ")
+        );
+    }
+    for route in &options.routes {
+        let _ = writeln!(
+            out,
+            "  route: {} gets only events with {}",
+            route.sink, route.criteria
+        );
+    }
+    match &options.overload {
+        Some(config) => {
+            let _ = writeln!(
+                out,
+                "  overload: degrade when over {:.0}% of events drop{}",
+                config.threshold * 100.0,
+                if config.sampling {
+                    ", sampling in the kernel"
+                } else {
+                    ""
+                }
+            );
+        }
+        None => out.push_str("  overload: full fidelity even when dropping\n"),
+    }
+    if options.schedule.is_some() {
+        out.push_str("  schedule: probes attached only inside windows\n");
+    }
+    if let Some(health) = &options.health {
+        let _ = writeln!(
+            out,
+            "  health: http://{} (live {:?}, ready {:?})",
+            health.addr, health.live_after, health.ready_after
+        );
+    }
+    out
+}
+
+/// Describe where `fw collect-remote` accepts forwarders
+fn describe_remote(out: &mut String, remote: &RemoteConfig) {
+    out.push_str("Remote:\n");
+    let _ = writeln!(out, "  listen: {} (TLS)", remote.listen);
+    let _ = writeln!(
+        out,
+        "  client certificates: {}",
+        remote
+            .client_ca
+            .as_ref()
+            .map_or("not required".to_string(), |ca| {
+                format!("required, from {}", ca.display())
+            })
+    );
+    if let Some(path) = &remote.allowlist {
+        let _ = writeln!(out, "  client allowlist: {}", path.display());
+    }
+    if let Some(path) = &remote.tokens {
+        let _ = writeln!(out, "  host tokens: {}", path.display());
+    }
+}
+
+/// Describe the probes and in-kernel filters for local monitoring
+fn describe_kernel(out: &mut String, options: &CollectOptions) {
+    out.push_str("Kernel:\n");
+    let mut features = action_features(options.filter.actions.as_deref());
+    if options.access_patterns {
+        features.insert(ProbeFeature::Io);
+    }
+    let probes = ProbePlan::between(&FeatureSet::new(), &features);
+    match &options.bpf_object {
+        Some(path) => {
+            let _ = writeln!(out, "  object: {}", path.display());
+        }
+        None => out.push_str("  object: embedded\n"),
+    }
+    let _ = writeln!(out, "  probes: {} to attach", probes.attach.len());
+    for probe in &probes.attach {
+        let _ = writeln!(out, "    {}", probe);
+    }
+    let _ = writeln!(out, "  uid filter: {}", describe_users(&options.users));
+    if let Some(list) = &options.process_list {
+        let _ = writeln!(
+            out,
+            "  process list: {} {} (reloaded on change or SIGUSR1)",
+            value_name(&list.mode),
+            list.path.display()
+        );
+    }
+    if let Some(stacks) = options.stacks {
+        let _ = writeln!(out, "  stacks: {}", stacks);
+    }
+    out.push_str(match options.filter.exclude_pseudo_fs {
+        true => "  pseudo fs: /proc, /sys and /dev excluded\n",
+        false => "  pseudo fs: included\n",
+    });
+}
+
+/// Describe the kernel uid filter
+fn describe_users(users: &UserFilter) -> String {
+    if users.is_empty() {
+        return "none (all users)".to_string();
+    }
+    let entries: Vec<String> = users
+        .entries()
+        .map(|(uid, entry)| {
+            let verb = if entry == fw_common::UID_FILTER_INCLUDE {
+                "include"
+            } else {
+                "exclude"
+            };
+            format!("{} {}", verb, uid)
+        })
+        .collect();
+    entries.join(", ")
+}
+
+/// Describe each configured userspace criterion on its own line
+fn describe_filter(filter: &FilterSpec, mounts: &MountTable) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(exts) = &filter.extensions {
+        lines.push(format!("extensions: {}", exts.join(", ")));
+    }
+    if let Some(pids) = &filter.pids {
+        let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+        lines.push(format!("pids: {}", pids.join(", ")));
+    }
+    if let Some(actions) = &filter.actions {
+        let actions: Vec<String> = actions.iter().map(value_name).collect();
+        lines.push(format!("actions: {}", actions.join(", ")));
+    }
+    if let Some(services) = &filter.services {
+        lines.push(format!("services: {}", services.join(", ")));
+    }
+    if let Some(globs) = &filter.name_globs {
+        let globs: Vec<&str> = globs.patterns().collect();
+        lines.push(format!("name globs: {}", globs.join(", ")));
+    }
+    if let Some(globs) = &filter.path_globs {
+        let globs: Vec<&str> = globs.patterns().collect();
+        lines.push(format!("path globs: {}", globs.join(", ")));
+    }
+    if let Some(exclusions) = &filter.exclusions {
+        let rules: Vec<String> =
+            exclusions.rules().map(ToString::to_string).collect();
+        lines.push(format!("excluded: {}", rules.join(", ")));
+    }
+    if filter.case_sensitive {
+        lines.push("case: sensitive".to_string());
+    }
+    if let Some(points) = &filter.mounts {
+        let points: Vec<String> = points
+            .iter()
+            .map(|p| match mounts.contains_mount_point(p) {
+                true => p.clone(),
+                false => format!("{} (not a mount point, never matches)", p),
+            })
+            .collect();
+        lines.push(format!("mounts: {}", points.join(", ")));
+    }
+    if let Some(fs_types) = &filter.fs_types {
+        lines.push(format!("fstypes: {}", fs_types.join(", ")));
+    }
+    match filter.remote {
+        Some(true) => lines.push("locality: network filesystems".to_string()),
+        Some(false) => lines.push("locality: local filesystems".to_string()),
+        None => {}
+    }
+    if let Some(min) = filter.min_latency_ns {
+        lines.push(format!("min latency: {}ns", min));
+    }
+    if let Some(namespaces) = &filter.xattr_namespaces {
+        lines.push(format!("xattr namespaces: {}", namespaces.join(", ")));
+    }
+    if let Some(tags) = &filter.tags {
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.clone(),
+            })
+            .collect();
+        lines.push(format!("tags: {}", tags.join(", ")));
+    }
+    if let Some(min) = filter.min_severity {
+        lines.push(format!("min severity: {}", min));
+    }
+    if let Some(min) = filter.min_size {
+        lines.push(format!("min size: {}B", min));
+    }
+    if let Some(max) = filter.max_size {
+        lines.push(format!("max size: {}B", max));
+    }
+    if let Some(max) = filter.newer_than {
+        lines.push(format!("newer than: {:?}", max));
+    }
+    if let Some(types) = &filter.types {
+        let types: Vec<&str> = types.iter().map(|t| t.name()).collect();
+        lines.push(format!("types: {}", types.join(", ")));
+    }
+    lines
+}
+
+/// Name of a value as accepted on the command line
+fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Run an open of a path by this process through the plan
+///
+/// # Arguments
+/// * `path` - Path to test
+/// * `options` - Collect options forming the plan
+/// * `mounts` - Mount table used to annotate the event
+/// * `enrichers` - Enrichers built from the options
+///
+/// # Returns
+/// * `String` - Verdict, naming the stage that drops the event if any
+pub fn test_path(
+    path: &str,
+    options: &CollectOptions,
+    mounts: &MountTable,
+    enrichers: &mut Enrichers,
+) -> String {
+    let uid = getuid().as_raw();
+    if !options.users.allows(uid) {
+        return format!("{}: dropped in the kernel by the uid filter", path);
+    }
+    let mut event = FileEvent::new(
+        path.to_string(),
+        "fw".to_string(),
+        FileAction::Opened,
+        std::process::id(),
+        SystemClock.now(),
+    )
+    .with_ids(uid, getgid().as_raw());
+    mounts.annotate(&mut event);
+    enrichers.enrich(&mut event);
+
+    if let Some(criterion) = options.filter.rejection(&event) {
+        return format!("{}: filtered out by the {} filter", path, criterion);
+    }
+    let mut details = Vec::new();
+    if let Some(fs_type) = &event.fs_type {
+        details.push(format!("fstype {}", fs_type));
+    }
+    if let Some(layer) = event.overlay_layer {
+        details.push(format!("{} layer of {}", layer, event.file_path));
+    }
+    if let Some(severity) = event.severity {
+        details.push(format!("severity {}", severity));
+    }
+    if let Some(size) = event.file_size {
+        details.push(format!("size {}B", size));
+    }
+    for (key, value) in &event.tags {
+        details.push(format!("{}={}", key, value));
+    }
+    format!("{}: reported ({})", path, details.join(", "))
+}
+
+/// Run `fw collect --dry-run`
+///
+/// # Arguments
+/// * `options` - Collect options to validate and describe
+/// * `test_paths` - Paths to run through the plan
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if the options are invalid
+pub fn run_dry_run(
+    options: &CollectOptions,
+    test_paths: &[String],
+) -> Result<()> {
+    let mounts = MountTable::load().context("Failed to load mount table")?;
+    // Fails on an unreadable tag map or severity policy
+    let mut enrichers = Enrichers::from_config(&options.enrich)?;
+    // Fails on a malformed redaction rule or unreadable salt
+    let redactor = options
+        .redact
+        .is_enabled()
+        .then(|| Redactor::new(&options.redact))
+        .transpose()?;
+    // Fails on a damaged object or one built for another event layout
+    if options.remote.is_none() {
+        match &options.bpf_object {
+            Some(path) => BpfObject::load(path)?,
+            None => BpfObject::embedded()?,
+        };
+    }
+    // Fails on an unreadable or malformed process list
+    if let Some(config) = &options.process_list {
+        ProcessList::load(config)?;
+    }
+    options.pin_dir()?;
+    // Fails on a route to an output that isn't enabled
+    options.routes()?;
+
+    print!("{}", describe_plan(options, &mounts));
+    if !test_paths.is_empty() {
+        println!("Test paths:");
+        for path in test_paths {
+            println!("  {}", test_path(path, options, &mounts, &mut enrichers));
+            let redacted = redactor.as_ref().map(|r| r.redact_path(path));
+            if let Some(redacted) = redacted.filter(|r| r != path) {
+                println!("    written as {}", redacted);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enrich::PathTags;
+    use crate::severity::Severity;
+    use crate::wait_for::ActionMatch;
+
+    fn options() -> CollectOptions {
+        CollectOptions {
+            filter: FilterSpec {
+                mounts: Some(vec!["/data".to_string(), "/nope".to_string()]),
+                min_severity: Some(Severity::Notice),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_describe_plan() {
+        let mounts = MountTable::parse("/dev/sdb1 /data xfs rw 0 0\n");
+        let plan = describe_plan(&options(), &mounts);
+        assert!(plan.contains("  uid filter: none (all users)\n"));
+        assert!(plan.contains("  pseudo fs: included\n"));
+        assert!(plan.contains(
+            "  mounts: /data, /nope (not a mount point, never matches)\n"
+        ));
+        assert!(plan.contains("  min severity: notice\n"));
+        assert!(plan.contains("  severity policy: built-in\n"));
+        assert!(plan.ends_with(
+            "  mode: events\n  destination: stderr\n  \
+             overload: full fidelity even when dropping\n"
+        ));
+
+        // Reads need the I/O probes and those they rely on
+        let mut options = options();
+        options.filter.actions = Some(vec![ActionMatch::Read]);
+        let plan = describe_plan(&options, &mounts);
+        assert!(plan.contains("  actions: read\n"));
+        for probe in ProbeFeature::Io.probes() {
+            assert!(plan.contains(&format!("    {}\n", probe)));
+        }
+        for probe in ProbeFeature::Opens.probes() {
+            assert!(plan.contains(&format!("    {}\n", probe)));
+        }
+        assert!(!plan
+            .contains(&format!("    {}\n", ProbeFeature::Syncs.probes()[0])));
+    }
+
+    #[test]
+    fn test_paths_against_plan() {
+        let mounts = MountTable::parse("/dev/sdb1 /data xfs rw 0 0\n");
+        let mut enrichers =
+            Enrichers::from_config(&Default::default()).unwrap();
+        enrichers.push(PathTags::parse("/data team=db").unwrap());
+        let options = options();
+
+        assert_eq!(
+            test_path("/tmp/a", &options, &mounts, &mut enrichers),
+            "/tmp/a: filtered out by the mount filter"
+        );
+        // Opens of unclassified paths are only info
+        assert_eq!(
+            test_path("/data/a", &options, &mounts, &mut enrichers),
+            "/data/a: filtered out by the severity filter"
+        );
+        let everything = CollectOptions::default();
+        assert_eq!(
+            test_path("/data/a", &everything, &mounts, &mut enrichers),
+            "/data/a: reported (fstype xfs, severity info, team=db)"
+        );
+    }
+}
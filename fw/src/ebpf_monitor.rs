@@ -5,16 +5,50 @@
 //! translates kernel events into FileEvent structures.
 
 use anyhow::{anyhow, Context, Result};
-use log::{debug, error, info, warn};
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
 
-use crate::file_event::{FileAction, FileEvent};
+use crate::arch::Arch;
+use crate::bpf_loader::{self, LoadError, LoadedProbes, SharedProbes};
+use crate::bpf_object::BpfObject;
+use crate::capabilities::Capabilities;
+use crate::clock::{self, Clock};
+use crate::fd_table::FdTable;
+use crate::file_event::{FileAction, FileEvent, FileType};
+use crate::filter_builder::{self, KernelFilterPlan};
+use crate::health::Health;
+use crate::kernel_agg::{CounterSource, KernelCounts};
+use crate::monitor_backend::MonitorBackend;
+use crate::pinning::PinDir;
+use crate::probe_decode::ProbeDecoder;
+use crate::probes::{
+    self, FeatureSet, ProbeFeature, ProbePlan, TAIL_CALL_PROGRAMS,
+};
+use crate::process_cache::{ProcessCache, DEFAULT_PROCESS_CACHE_SIZE};
+use crate::process_list::ProcessList;
+use crate::stacks::{Stack, StackMode};
+use crate::user_filter::UserFilter;
+use crate::verifier::{self, VerifierReport};
+use crate::wait_for::ActionMatch;
+use fw_common::{
+    FileEvent as RawFileEvent, StackCriterion, EVENT_FLAG_SAMPLED,
+    EVENT_FLAG_TRIPWIRE, EVENT_TYPE_EXIT, EVENT_TYPE_FORK, MAX_COMM_LEN,
+    MAX_PATH_LEN, MAX_SAMPLE_EXEMPTIONS, MAX_STACK_CRITERIA, MAX_TRIPWIRES,
+};
 
 /// Maximum number of events that can be queued before blocking
 const EVENT_QUEUE_SIZE: usize = 1024;
 
+/// How often the sampling rate is checked while no events arrive
+const SAMPLE_RATE_CHECK: Duration = Duration::from_secs(1);
+
 /// Manages eBPF program lifecycle and event processing
 ///
 /// The EbpfMonitor coordinates loading eBPF programs into the kernel,
@@ -23,8 +57,58 @@ const EVENT_QUEUE_SIZE: usize = 1024;
 pub struct EbpfMonitor {
     /// Internal state for tracking monitoring status
     is_monitoring: bool,
-    /// Process name cache to avoid repeated lookups
-    process_cache: HashMap<u32, String>,
+    /// Probes are detached until resumed, with the channel kept open
+    paused: bool,
+    /// Decoder and process cache, shared with the event reader
+    decoding: Arc<Mutex<Decoding>>,
+    /// Task translating the records the probes send while monitoring
+    reader: Option<JoinHandle<()>>,
+    /// Time source for event timestamps
+    clock: Arc<dyn Clock>,
+    /// Probe features requested by the user
+    features: FeatureSet,
+    /// Probe features whose programs are currently attached
+    attached: FeatureSet,
+    /// Where to pin the state and filter maps, and whether to reuse
+    /// existing pins
+    pinning: Option<(PinDir, bool)>,
+    /// Probes to load; the embedded object if unset
+    object: Option<BpfObject>,
+    /// Programs and maps in the kernel while monitoring
+    probes: Option<SharedProbes>,
+    /// Users whose activity the kernel reports
+    user_filter: UserFilter,
+    /// Processes whose activity is dropped, or the only ones reported;
+    /// replaced whenever the list file is reloaded
+    process_list: watch::Receiver<Arc<ProcessList>>,
+    /// Task pushing reloaded process lists into the kernel filter while
+    /// monitoring
+    list_pusher: Option<JoinHandle<()>>,
+    /// Filter map entries applied by an embedding program, pushed after
+    /// the user filter and process list they take the place of
+    kernel_filter: Option<KernelFilterPlan>,
+    /// Architecture of the running kernel, selecting syscall symbols
+    arch: Arch,
+    /// What the running kernel supports, selecting implementations
+    capabilities: Capabilities,
+    /// Report files open at startup as AlreadyOpen events
+    snapshot: bool,
+    /// Leave other processes' descriptors and the reported files alone
+    forensic: bool,
+    /// Count events in the kernel instead of sending them
+    kernel_agg: bool,
+    /// Leave out opens under /proc, /sys and /dev in the kernel
+    exclude_pseudo_fs: bool,
+    /// Stacks recorded with each event
+    stacks: Option<StackMode>,
+    /// Events whose stacks are recorded; empty for every event
+    stack_criteria: Vec<StackCriterion>,
+    /// Events sent whatever the sampling rate
+    sample_exemptions: Vec<StackCriterion>,
+    /// Tripwire decoys, whose opens bypass the filters and sampling
+    tripwires: Vec<String>,
+    /// Carries the sampling rate chosen by the overload controller
+    health: Health,
 }
 
 impl EbpfMonitor {
@@ -38,69 +122,476 @@ impl EbpfMonitor {
         // Verify eBPF support is available
         Self::check_ebpf_support()
             .context("eBPF support verification failed")?;
+        let arch = Arch::detect()?;
+        info!("Attaching probes for {}", arch);
+        let capabilities = Capabilities::detect();
+        capabilities.log();
+        if capabilities.kprobes == Some(false) {
+            return Err(anyhow!("This kernel can't load kprobe programs"));
+        }
 
         Ok(Self {
             is_monitoring: false,
-            process_cache: HashMap::new(),
+            paused: false,
+            decoding: Arc::new(Mutex::new(Decoding {
+                decoder: ProbeDecoder::new(),
+                process_cache: ProcessCache::new(DEFAULT_PROCESS_CACHE_SIZE),
+            })),
+            reader: None,
+            clock: clock::system(),
+            features: probes::default_features(),
+            attached: FeatureSet::new(),
+            pinning: None,
+            object: None,
+            probes: None,
+            user_filter: UserFilter::default(),
+            process_list: watch::channel(Arc::default()).1,
+            list_pusher: None,
+            kernel_filter: None,
+            arch,
+            capabilities,
+            snapshot: false,
+            forensic: false,
+            kernel_agg: false,
+            exclude_pseudo_fs: false,
+            stacks: None,
+            stack_criteria: Vec::new(),
+            sample_exemptions: Vec::new(),
+            tripwires: Vec::new(),
+            health: Health::default(),
         })
     }
 
-    /// Start monitoring file operations using eBPF
+    /// Pin the state and filter maps so they survive a crash
     ///
-    /// Loads the eBPF program into the kernel and begins capturing file
-    /// open/close events. Returns a receiver channel for processed events.
+    /// # Arguments
+    /// * `pins` - bpffs directory to pin into
+    /// * `reuse` - Pick up maps pinned by a previous run instead of
+    ///   starting empty
     ///
     /// # Returns
-    /// * `Result<mpsc::Receiver<FileEvent>>` - Event receiver or error
-    pub async fn start_monitoring(
-        &mut self,
-    ) -> Result<mpsc::Receiver<FileEvent>> {
-        if self.is_monitoring {
-            return Err(anyhow!("Monitor is already running"));
+    /// * `EbpfMonitor` - The monitor with pinning enabled
+    pub fn with_pinning(mut self, pins: PinDir, reuse: bool) -> Self {
+        self.pinning = Some((pins, reuse));
+        self
+    }
+
+    /// Load the probes from an object other than the embedded one
+    ///
+    /// # Arguments
+    /// * `object` - Object to load, or None for the embedded one
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor loading the given object
+    pub fn with_object(mut self, object: Option<BpfObject>) -> Self {
+        self.object = object;
+        self
+    }
+
+    /// Drive probes loaded elsewhere instead of loading the object
+    ///
+    /// # Arguments
+    /// * `probes` - Loaded probes, e.g. a fake recording the probe
+    ///   changes in tests
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor driving the given probes
+    pub fn with_probes(mut self, probes: Box<dyn LoadedProbes>) -> Self {
+        self.probes = Some(Arc::new(Mutex::new(probes)));
+        self
+    }
+
+    /// Only report activity of the users the filter allows
+    ///
+    /// # Arguments
+    /// * `filter` - Users to include or exclude
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the user filter set
+    pub fn with_user_filter(mut self, filter: UserFilter) -> Self {
+        self.user_filter = filter;
+        self
+    }
+
+    /// Filter activity by process name, following reloads of the list
+    ///
+    /// Exact names are pushed into the kernel filter again whenever a
+    /// new list arrives; regexes are matched as events are decoded.
+    ///
+    /// # Arguments
+    /// * `list` - Receiver of the current process list
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the process list set
+    pub fn with_process_list(
+        mut self,
+        list: watch::Receiver<Arc<ProcessList>>,
+    ) -> Self {
+        self.process_list = list;
+        self
+    }
+
+    /// Report the files already open at startup
+    ///
+    /// The descriptor table is seeded from `/proc` on every start; with a
+    /// snapshot, each seeded file is also sent as an AlreadyOpen event
+    /// before any kernel event.
+    ///
+    /// # Arguments
+    /// * `snapshot` - Whether to send AlreadyOpen events
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the snapshot setting applied
+    pub fn with_snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Keep lookups on the running system to a minimum (`--forensic`)
+    ///
+    /// The descriptor table isn't seeded from `/proc`, so closes of files
+    /// opened before the start go unresolved, and the type of files named
+    /// by path isn't looked up. Process names and working directories are
+    /// still read from `/proc`, once per process.
+    ///
+    /// # Arguments
+    /// * `forensic` - Whether to skip the lookups
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the lookups skipped or not
+    pub fn with_forensic(mut self, forensic: bool) -> Self {
+        self.forensic = forensic;
+        self
+    }
+
+    /// Count events per process, extension and action in the kernel
+    ///
+    /// The probes then send no events; the counts are read with
+    /// [`CounterSource::drain_counts`].
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to count instead of sending events
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the aggregation setting applied
+    pub fn with_kernel_aggregation(mut self, enabled: bool) -> Self {
+        self.kernel_agg = enabled;
+        self
+    }
+
+    /// Leave out opens of files under /proc, /sys and /dev
+    ///
+    /// The probes drop opens of absolute paths there before they are
+    /// sent; relative paths and descriptors opened before the monitor
+    /// started still reach userspace and are left out by the filter.
+    ///
+    /// # Arguments
+    /// * `excluded` - Whether to leave pseudo-filesystem opens out
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the exclusion applied
+    pub fn with_pseudo_fs_excluded(mut self, excluded: bool) -> Self {
+        self.exclude_pseudo_fs = excluded;
+        self
+    }
+
+    /// Record stack traces with each event
+    ///
+    /// # Arguments
+    /// * `stacks` - Stacks to record, or None for none
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the stack setting applied
+    pub fn with_stacks(mut self, stacks: Option<StackMode>) -> Self {
+        self.stacks = stacks;
+        self
+    }
+
+    /// Record stacks only for events matching one of the criteria
+    ///
+    /// With more criteria than the probes hold, the stacks of every
+    /// event are recorded and left to the severity rules to drop.
+    ///
+    /// # Arguments
+    /// * `criteria` - Criteria from the severity rules capturing stacks
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the criteria applied
+    pub fn with_stack_criteria(
+        mut self,
+        criteria: Vec<StackCriterion>,
+    ) -> Self {
+        if criteria.len() > MAX_STACK_CRITERIA as usize {
+            warn!(
+                "{} stack capture criteria, more than the {} the probes \
+                 hold; recording stacks of every event",
+                criteria.len(),
+                MAX_STACK_CRITERIA
+            );
+            self.stack_criteria = Vec::new();
+        } else {
+            self.stack_criteria = criteria;
         }
+        self
+    }
 
-        info!("Starting eBPF file monitoring");
+    /// Never sample out events matching one of the criteria
+    ///
+    /// With more criteria than the probes hold, every event is exempt,
+    /// so overload protection never samples.
+    ///
+    /// # Arguments
+    /// * `criteria` - Criteria from the serious severity rules
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the exemptions applied
+    pub fn with_sample_exemptions(
+        mut self,
+        criteria: Vec<StackCriterion>,
+    ) -> Self {
+        if criteria.len() > MAX_SAMPLE_EXEMPTIONS as usize {
+            warn!(
+                "{} paths exempt from sampling, more than the {} the \
+                 probes hold; never sampling",
+                criteria.len(),
+                MAX_SAMPLE_EXEMPTIONS
+            );
+            self.sample_exemptions = vec![StackCriterion::new(None, b"")];
+        } else {
+            self.sample_exemptions = criteria;
+        }
+        self
+    }
 
-        // Create event channel
-        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+    /// Report every open of the given decoys
+    ///
+    /// The probes match opens against the paths before the uid and
+    /// process filters, and send them whatever the sampling rate and
+    /// while counting in the kernel. Paths longer than one chunk can't
+    /// be matched in the kernel and are left to the severity rules.
+    ///
+    /// # Arguments
+    /// * `paths` - Absolute paths of the decoys
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the decoys applied
+    pub fn with_tripwires(mut self, paths: &[String]) -> Self {
+        let (fits, long): (Vec<String>, Vec<String>) = paths
+            .iter()
+            .cloned()
+            .partition(|path| path.len() < MAX_PATH_LEN);
+        if !long.is_empty() {
+            warn!(
+                "{} tripwires are longer than the {} bytes the probes \
+                 match; their opens may be filtered or sampled out",
+                long.len(),
+                MAX_PATH_LEN - 1
+            );
+        }
+        if fits.len() > MAX_TRIPWIRES as usize {
+            warn!(
+                "{} tripwires, more than the {} the probes hold; the \
+                 rest may be filtered or sampled out",
+                fits.len(),
+                MAX_TRIPWIRES
+            );
+        }
+        self.tripwires =
+            fits.into_iter().take(MAX_TRIPWIRES as usize).collect();
+        self
+    }
 
-        // For now, we'll implement a placeholder that demonstrates the
-        // structure. In a complete implementation, this would:
-        // 1. Load the eBPF program from a compiled .o file
-        // 2. Attach to kernel tracepoints for file operations
-        // 3. Set up event polling loop
+    /// Attach only the probes producing some kinds of action
+    ///
+    /// # Arguments
+    /// * `actions` - Kinds of action to report, or None for the default
+    ///   probes
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the probe features selected
+    pub fn with_actions(mut self, actions: Option<&[ActionMatch]>) -> Self {
+        self.features = filter_builder::action_features(actions);
+        self
+    }
 
-        // TODO: Replace with actual eBPF implementation
-        self.start_placeholder_monitoring(tx).await?;
+    /// Trace reads and writes on open files
+    ///
+    /// Adds the I/O probes, whose events carry the offset and size of
+    /// each transfer so sessions can be classified by access pattern.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to trace reads and writes
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the I/O setting applied
+    pub fn with_access_patterns(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.features.insert(ProbeFeature::Io);
+        }
+        self
+    }
 
-        self.is_monitoring = true;
-        Ok(rx)
+    /// Sample events in the kernel at the rate recorded in `health`
+    ///
+    /// # Arguments
+    /// * `health` - State the overload controller publishes its setting in
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor following the sampling rate
+    pub fn with_health(mut self, health: &Health) -> Self {
+        self.health = health.clone();
+        self
     }
 
-    /// Stop monitoring and cleanup eBPF resources
+    /// Stamp events with the time from a different clock
     ///
-    /// Detaches eBPF programs from kernel tracepoints and cleans up
-    /// any allocated resources.
+    /// # Arguments
+    /// * `clock` - Time source, e.g. a `MockClock` in tests
     ///
     /// # Returns
-    /// * `Result<()>` - Success or error result
-    pub async fn stop_monitoring(&mut self) -> Result<()> {
-        if !self.is_monitoring {
-            return Ok(()); // Already stopped
+    /// * `EbpfMonitor` - The monitor using the clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        lock(&self.decoding).decoder =
+            ProbeDecoder::new().with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    /// Limit how many process names are cached
+    ///
+    /// # Arguments
+    /// * `size` - Maximum number of cached processes
+    ///
+    /// # Returns
+    /// * `EbpfMonitor` - The monitor with the cache limit applied
+    pub fn with_process_cache_size(self, size: usize) -> Self {
+        lock(&self.decoding).process_cache = ProcessCache::new(size);
+        self
+    }
+
+    /// Change the active probe features
+    ///
+    /// While monitoring, only the probes of features that were added or
+    /// removed are attached or detached; the descriptor table, path
+    /// assembly and process caches are kept. When stopped, the features
+    /// are applied on the next start or resume.
+    ///
+    /// # Arguments
+    /// * `features` - Features that should be active
+    ///
+    /// # Returns
+    /// * `Result<ProbePlan>` - Probe changes that were applied
+    pub fn reconfigure(&mut self, features: FeatureSet) -> Result<ProbePlan> {
+        self.features = features;
+        if !self.is_monitoring || self.paused {
+            return Ok(ProbePlan::default());
         }
+        self.sync_probes()
+    }
 
-        info!("Stopping eBPF file monitoring");
+    /// Get the active probe features
+    ///
+    /// # Returns
+    /// * `&FeatureSet` - Features requested by the user
+    pub fn features(&self) -> &FeatureSet {
+        &self.features
+    }
 
-        // TODO: Implement actual eBPF cleanup
-        // This would involve:
-        // 1. Detaching from kernel tracepoints
-        // 2. Unloading eBPF programs
-        // 3. Cleaning up any BPF maps
+    /// Attach and detach probes until the requested features are attached
+    ///
+    /// # Returns
+    /// * `Result<ProbePlan>` - Probe changes that were applied
+    fn sync_probes(&mut self) -> Result<ProbePlan> {
+        let plan = ProbePlan::between(&self.attached, &self.features);
+        let probes = self.loaded()?;
+        let mut probes = bpf_loader::lock(&probes);
+        for probe in &plan.detach {
+            debug!("Detaching {}", probe);
+            probes.detach(probe)?;
+        }
+        for probe in &plan.attach {
+            self.load_program(&mut **probes, probe.program)?;
+            let symbol = probe.symbol(self.arch);
+            debug!("Attaching {} at {}", probe, symbol);
+            probes.attach(probe, &symbol)?;
+        }
+        self.attached = self.features.clone();
+        info!(
+            "Probes reconfigured: {} attached, {} detached",
+            plan.attach.len(),
+            plan.detach.len()
+        );
+        Ok(plan)
+    }
 
-        self.is_monitoring = false;
-        self.process_cache.clear();
+    /// Get the programs and maps in the kernel
+    ///
+    /// # Returns
+    /// * `Result<SharedProbes>` - Loaded probes, or error if they aren't
+    ///   loaded
+    fn loaded(&self) -> Result<SharedProbes> {
+        self.probes
+            .clone()
+            .ok_or_else(|| anyhow!("The probes are not loaded"))
+    }
 
-        info!("eBPF monitoring stopped successfully");
+    /// Explain a program the verifier rejected
+    ///
+    /// Writes the full verifier log to a diagnostics file and returns an
+    /// error with its last lines and the likely cause.
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program that failed to load
+    /// * `error` - Error the load returned
+    /// * `log` - Verifier log aya captured
+    ///
+    /// # Returns
+    /// * `anyhow::Error` - Error to fail the start with
+    fn load_failure(
+        &self,
+        program: &str,
+        error: &io::Error,
+        log: &str,
+    ) -> anyhow::Error {
+        let report =
+            VerifierReport::analyze(program, error, log, &self.capabilities);
+        verifier::load_error(&report)
+    }
+
+    /// Load a program into the kernel, explaining a verifier rejection
+    ///
+    /// # Arguments
+    /// * `probes` - Loaded object holding the program
+    /// * `program` - Name of the program
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or the error from [`Self::load_failure`]
+    fn load_program(
+        &self,
+        probes: &mut dyn LoadedProbes,
+        program: &str,
+    ) -> Result<()> {
+        probes.load(program).map_err(|e| match e {
+            LoadError::Rejected { error, log } => {
+                self.load_failure(program, &error, &log)
+            }
+            LoadError::Failed(e) => e,
+        })
+    }
+
+    /// Load the programs reached by tail call and fill their slots
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if a program was rejected or its slot
+    ///   couldn't be set
+    fn load_tail_calls(&self) -> Result<()> {
+        let probes = self.loaded()?;
+        let mut probes = bpf_loader::lock(&probes);
+        for (slot, program) in TAIL_CALL_PROGRAMS {
+            self.load_program(&mut **probes, program)?;
+            probes.set_tail_call(*slot, program)?;
+            debug!("Tail call slot {}: {}", slot, program);
+        }
         Ok(())
     }
 
@@ -119,93 +610,636 @@ impl EbpfMonitor {
             warn!("debugfs not found - some eBPF features may not work");
         }
 
-        // Check if BPF filesystem is available
-        if !Path::new("/sys/fs/bpf").exists() {
-            return Err(anyhow!(
-                "BPF filesystem not found. Ensure your kernel supports eBPF and \
-                 /sys/fs/bpf is mounted"
-            ));
-        }
-
         debug!("eBPF support verification completed");
         Ok(())
     }
 
-    /// Placeholder monitoring implementation for development
+    /// Seed the descriptor table with the files open right now
     ///
-    /// This is a temporary implementation that simulates file events for
-    /// testing purposes. In the complete implementation, this would be
-    /// replaced with actual eBPF event processing.
+    /// Without this, closes of files opened before monitoring started
+    /// can't be resolved. With a snapshot, every seeded file is also sent
+    /// as an AlreadyOpen event; they are sent from a task because there
+    /// may be more than the channel holds before the caller starts
+    /// receiving. In forensic mode the table starts empty instead.
     ///
     /// # Arguments
     /// * `tx` - Event sender channel
-    ///
-    /// # Returns
-    /// * `Result<()>` - Success or error result
-    async fn start_placeholder_monitoring(
-        &mut self,
-        tx: mpsc::Sender<FileEvent>,
-    ) -> Result<()> {
-        info!("Starting placeholder monitoring (eBPF integration pending)");
+    fn seed_fd_table(&mut self, tx: mpsc::Sender<FileEvent>) {
+        let fd_table = match self.forensic {
+            true => FdTable::new(),
+            false => FdTable::scan(Path::new("/proc")),
+        };
+        let mut decoding = lock(&self.decoding);
+        decoding.decoder = ProbeDecoder::new()
+            .with_clock(self.clock.clone())
+            .with_fd_table(fd_table);
+        info!(
+            "Seeded descriptor table with {} open files",
+            decoding.decoder.fd_table().len()
+        );
+        if !self.snapshot {
+            return;
+        }
 
-        // Spawn a background task that simulates file events
+        let seeded: Vec<_> = decoding
+            .decoder
+            .fd_table()
+            .iter()
+            .map(|(pid, fd, path)| (pid, fd, path.clone()))
+            .collect();
+        let events: Vec<FileEvent> = seeded
+            .into_iter()
+            .map(|(pid, fd, path)| {
+                let program_name = decoding.process_cache.name(pid, 0);
+                FileEvent::new(
+                    path.path,
+                    program_name,
+                    FileAction::AlreadyOpen,
+                    pid,
+                    self.clock.now(),
+                )
+                .with_path_truncated(path.truncated)
+                .with_fd(fd)
+            })
+            .collect();
         tokio::spawn(async move {
-            // This is just for demonstration - real implementation would
-            // poll eBPF maps and process kernel events
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-
-            let sample_event = FileEvent::new(
-                "/tmp/sample_file.txt".to_string(),
-                "placeholder".to_string(),
-                FileAction::Opened,
-                std::process::id(),
-            );
-
-            if let Err(e) = tx.send(sample_event).await {
-                error!("Failed to send sample event: {}", e);
+            for event in events {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
             }
         });
+    }
 
+    /// Translate the records the probes send and pass them on
+    ///
+    /// # Arguments
+    /// * `tx` - Event sender channel
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the event buffers couldn't be opened
+    fn read_events(&mut self, tx: mpsc::Sender<FileEvent>) -> Result<()> {
+        let probes = self.loaded()?;
+        let (records_tx, mut records) = mpsc::channel(EVENT_QUEUE_SIZE);
+        bpf_loader::lock(&probes).read_events(records_tx, &self.health)?;
+        let translator = self.translator();
+        let health = self.health.clone();
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
+        self.reader = Some(tokio::spawn(async move {
+            // The first tick is immediate, catching up with a rate chosen
+            // since the start
+            let mut tick = tokio::time::interval(SAMPLE_RATE_CHECK);
+            let mut rate = 0;
+            loop {
+                let record = tokio::select! {
+                    record = records.recv() => record,
+                    _ = tick.tick() => {
+                        sync_sample_rate(&probes, &health, &mut rate);
+                        continue;
+                    }
+                };
+                let Some(record) = record else {
+                    break;
+                };
+                sync_sample_rate(&probes, &health, &mut rate);
+                match translator.decode_event_bytes(&record) {
+                    Ok(Some(event)) => {
+                        if tx.send(event).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("{:#}", e),
+                }
+            }
+        }));
         Ok(())
     }
 
-    /// Get the process name for a given process ID
+    /// Translator sharing the monitor's decoding state and settings
+    ///
+    /// # Returns
+    /// * `Translator` - Translator for the event reader
+    fn translator(&self) -> Translator {
+        Translator {
+            decoding: self.decoding.clone(),
+            process_list: self.process_list.clone(),
+            forensic: self.forensic,
+            stacks: self.stacks,
+            health: self.health.clone(),
+            probes: self.probes.clone(),
+        }
+    }
+
+    /// Push the current process list into the kernel process filter, and
+    /// every reloaded one as soon as it is published
+    ///
+    /// Waiting for the next event instead could wait forever: an
+    /// allowlist the kernel applies may let no event through until the
+    /// reloaded list is pushed.
     ///
-    /// Uses a cache to avoid repeated filesystem lookups for the same PID.
+    /// # Returns
+    /// * `Result<()>` - Error if the current list couldn't be pushed
+    fn sync_process_list(&mut self) -> Result<()> {
+        let probes = self.loaded()?;
+        let mut list = self.process_list.clone();
+        push_process_list(&probes, &list.borrow_and_update())?;
+        if let Some(pusher) = self.list_pusher.take() {
+            pusher.abort();
+        }
+        self.list_pusher = Some(tokio::spawn(async move {
+            while list.changed().await.is_ok() {
+                let list = list.borrow_and_update().clone();
+                if let Err(e) = push_process_list(&probes, &list) {
+                    warn!("Failed to push the reloaded process list: {:#}", e);
+                }
+            }
+        }));
+        Ok(())
+    }
+}
+
+/// Decoding state shared with the event reader
+struct Decoding {
+    /// Translates raw kernel events, tracking descriptors and long paths
+    decoder: ProbeDecoder,
+    /// Process name cache to avoid repeated lookups
+    process_cache: ProcessCache,
+}
+
+/// Lock the decoding state, even if a holder panicked
+fn lock(decoding: &Mutex<Decoding>) -> MutexGuard<'_, Decoding> {
+    decoding.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Turns the records the probes send into FileEvents
+///
+/// The monitor makes one for its event reader. It shares the monitor's
+/// decoding state, so descriptors opened before a reconfiguration or
+/// pause still resolve after it.
+struct Translator {
+    /// Decoder and process cache of the monitor
+    decoding: Arc<Mutex<Decoding>>,
+    /// Processes whose activity is dropped, or the only ones reported
+    process_list: watch::Receiver<Arc<ProcessList>>,
+    /// Leave the reported files alone
+    forensic: bool,
+    /// Stacks recorded with each event
+    stacks: Option<StackMode>,
+    /// Carries the sampling rate sampled events are stamped with
+    health: Health,
+    /// Loaded probes holding the recorded stacks; None before loading
+    probes: Option<SharedProbes>,
+}
+
+impl Translator {
+    /// Decode perf buffer bytes into a FileEvent
+    ///
+    /// The bytes are validated for size, alignment and layout version
+    /// before being interpreted, without copying the raw event.
+    ///
+    /// # Arguments
+    /// * `bytes` - One record read from the perf buffer
+    ///
+    /// # Returns
+    /// * `Result<Option<FileEvent>>` - Decoded event, None while a long
+    ///   path is still incomplete, or a decode error
+    fn decode_event_bytes(&self, bytes: &[u8]) -> Result<Option<FileEvent>> {
+        let raw = RawFileEvent::from_bytes(bytes)
+            .context("Failed to decode raw kernel event")?;
+        Ok(self.decode_raw_event(raw))
+    }
+
+    /// Look up the stacks recorded with a raw event
     ///
     /// # Arguments
-    /// * `pid` - Process ID to look up
+    /// * `raw` - Raw event carrying stack ids
+    ///
+    /// # Returns
+    /// * `Option<Arc<Stack>>` - Stack addresses, or None if stacks aren't
+    ///   recorded or none were found
+    fn read_stack(&self, raw: &RawFileEvent) -> Option<Arc<Stack>> {
+        self.stacks?;
+        let stack = Stack {
+            kernel: self.stack_addresses(raw.kernel_stack_id),
+            user: self.stack_addresses(raw.user_stack_id),
+            frames: Vec::new(),
+        };
+        (!stack.is_empty()).then(|| Arc::new(stack))
+    }
+
+    /// Read the addresses of one stack from the stack trace map
+    ///
+    /// The entry is deleted once read, so the map only holds the stacks
+    /// of events not decoded yet and doesn't fill up, which would leave
+    /// later events without stacks.
+    ///
+    /// # Arguments
+    /// * `id` - Stack id from a raw event; negative if the stack wasn't
+    ///   recorded (e.g. the map was full)
+    ///
+    /// # Returns
+    /// * `Vec<u64>` - Addresses, innermost first
+    fn stack_addresses(&self, id: i32) -> Vec<u64> {
+        let Some(probes) = self.probes.as_ref().filter(|_| id >= 0) else {
+            return Vec::new();
+        };
+        match bpf_loader::lock(probes).take_stack(id as u32) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                debug!("No stack {}: {:#}", id, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Translate a raw kernel event into a FileEvent
+    ///
+    /// The decoder does the translation; this adds what needs the running
+    /// system: the process name, the type of files named by path, and the
+    /// recorded stacks. Events from processes the process list excludes
+    /// are dropped.
+    ///
+    /// # Arguments
+    /// * `raw` - Raw event received from the eBPF program
+    ///
+    /// # Returns
+    /// * `Option<FileEvent>` - Decoded event, or None if there is nothing
+    ///   to report yet
+    fn decode_raw_event(&self, raw: &RawFileEvent) -> Option<FileEvent> {
+        let mut decoding = lock(&self.decoding);
+        match raw.event_type {
+            EVENT_TYPE_FORK => decoding.process_cache.forked(raw.child_pid),
+            EVENT_TYPE_EXIT => decoding.process_cache.exited(raw.pid),
+            _ => {}
+        }
+        let mut event = decoding.decoder.decode(raw)?;
+        event.program_name =
+            decoding.process_cache.name(raw.pid, raw.process_start_ns);
+        drop(decoding);
+        // Regexes, and events sent before the kernel filter was updated;
+        // tripwire opens are reported whoever makes them
+        if raw.flags & EVENT_FLAG_TRIPWIRE == 0
+            && !self.process_list.borrow().allows(&event.program_name)
+        {
+            return None;
+        }
+        // Path-based calls: the object is still at the reported path
+        if !self.forensic
+            && event.fd.is_none()
+            && raw.fd < 0
+            && event.file_path.starts_with('/')
+        {
+            event.file_type = fs::symlink_metadata(&event.file_path)
+                .ok()
+                .and_then(|metadata| FileType::from_mode(metadata.mode()));
+        }
+        event.stack = self.read_stack(raw);
+        if raw.flags & EVENT_FLAG_SAMPLED != 0 {
+            event.sample_rate = Some(self.health.degradation().sample_rate);
+        }
+        Some(event)
+    }
+}
+
+impl MonitorBackend for EbpfMonitor {
+    /// Start monitoring file operations using eBPF
+    ///
+    /// Loads the eBPF program into the kernel and begins capturing file
+    /// open/close events. Returns a receiver channel for processed events.
     ///
     /// # Returns
-    /// * `String` - Process name or "unknown" if not found
-    #[allow(dead_code)]
-    fn get_process_name(&mut self, pid: u32) -> String {
-        // Check cache first
-        if let Some(name) = self.process_cache.get(&pid) {
-            return name.clone();
+    /// * `Result<mpsc::Receiver<FileEvent>>` - Event receiver or error
+    async fn start_monitoring(&mut self) -> Result<mpsc::Receiver<FileEvent>> {
+        if self.is_monitoring {
+            return Err(anyhow!("Monitor is already running"));
+        }
+
+        info!("Starting eBPF file monitoring");
+
+        if let Some((pins, reuse)) = &self.pinning {
+            if !self.capabilities.bpffs {
+                return Err(anyhow!(
+                    "Pinning needs the BPF filesystem. Mount it at /sys/fs/bpf \
+                     or run without pinning"
+                ));
+            }
+            pins.claim(*reuse).context("Failed to claim pinned maps")?;
+        }
+
+        let object = match self.object.take() {
+            Some(object) => object,
+            None => BpfObject::embedded()?,
+        };
+        info!(
+            "Loading probes from {} (checksum {:016x})",
+            object,
+            object.checksum()
+        );
+        if self.probes.is_none() {
+            let pins = self.pinning.as_ref().map(|(pins, _)| pins);
+            self.probes = Some(bpf_loader::load(&object, pins)?);
+        }
+        self.object = Some(object);
+
+        self.load_tail_calls()?;
+
+        // Written even when empty, clearing entries left pinned by an
+        // earlier run
+        let probes = self.loaded()?;
+        let uids: Vec<_> = self.user_filter.entries().collect();
+        for (uid, entry) in &uids {
+            debug!("Uid filter: {} -> {}", uid, entry);
+        }
+        bpf_loader::lock(&probes)
+            .set_uid_filter(&uids, self.user_filter.has_includes())?;
+
+        // The threads of processes started earlier are filtered by their
+        // process's name, which the probes only learn from its own calls
+        let leaders = running_comms(Path::new("/proc"));
+        debug!("Seeding {} process names", leaders.len());
+        bpf_loader::lock(&probes).set_leader_comms(&leaders)?;
+        self.sync_process_list()?;
+        if let Some(plan) = &self.kernel_filter {
+            push_kernel_plan(&probes, plan)?;
+        }
+
+        // The event reader keeps the rate in step with self.health
+        let rate = self.health.degradation().sample_rate;
+        debug!("Sampling 1 in {}", rate);
+        bpf_loader::lock(&probes).set_sample_rate(rate)?;
+        debug!(
+            "{} paths exempt from sampling",
+            self.sample_exemptions.len()
+        );
+        bpf_loader::lock(&probes)
+            .set_sample_exemptions(&self.sample_exemptions)?;
+
+        let mut tripwires = Vec::with_capacity(self.tripwires.len());
+        for path in &self.tripwires {
+            debug!("Tripwire: {}", path);
+            let mut padded = [0; MAX_PATH_LEN];
+            padded[..path.len()].copy_from_slice(path.as_bytes());
+            tripwires.push(padded);
+        }
+        bpf_loader::lock(&probes).set_tripwires(&tripwires)?;
+
+        if self.exclude_pseudo_fs {
+            debug!("Leaving out opens under /proc, /sys and /dev");
+        }
+        bpf_loader::lock(&probes)
+            .set_pseudo_fs_excluded(self.exclude_pseudo_fs)?;
+
+        if let Some(stacks) = self.stacks {
+            debug!("Recording {} stacks", stacks);
+            debug!("{} stack capture criteria", self.stack_criteria.len());
+        }
+        let mode = self.stacks.map_or(0, StackMode::flags);
+        bpf_loader::lock(&probes).set_stack_mode(mode)?;
+        bpf_loader::lock(&probes).set_stack_criteria(&self.stack_criteria)?;
+
+        if self.kernel_agg {
+            debug!("Counting events in the kernel");
         }
+        bpf_loader::lock(&probes).set_kernel_aggregation(self.kernel_agg)?;
+
+        // Create event channel
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
 
-        // Read process name from /proc/PID/comm
-        let comm_path = format!("/proc/{}/comm", pid);
-        let name = std::fs::read_to_string(&comm_path)
-            .map(|s| s.trim().to_string())
-            .unwrap_or_else(|_| "unknown".to_string());
+        self.seed_fd_table(tx.clone());
+        // The probes send through per-CPU perf buffers on every kernel
+        self.read_events(tx)?;
 
-        // Cache the result
-        self.process_cache.insert(pid, name.clone());
-        name
+        self.is_monitoring = true;
+        self.paused = false;
+        self.sync_probes()?;
+        Ok(rx)
+    }
+
+    /// Stop monitoring and cleanup eBPF resources
+    ///
+    /// Detaches eBPF programs from kernel tracepoints and cleans up
+    /// any allocated resources.
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        if !self.is_monitoring {
+            return Ok(()); // Already stopped
+        }
+
+        info!("Stopping eBPF file monitoring");
+        if let Some(pusher) = self.list_pusher.take() {
+            pusher.abort();
+        }
+        // Closing the records also ends the tasks reading the buffers
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
+
+        // Detach every probe, then unload the programs and maps; pinned
+        // maps stay in the kernel for a later --reuse-pinned
+        let requested = std::mem::take(&mut self.features);
+        self.sync_probes()?;
+        self.features = requested;
+        self.probes = None;
+
+        self.is_monitoring = false;
+        self.paused = false;
+        let mut decoding = lock(&self.decoding);
+        let cache = decoding.process_cache.stats();
+        info!(
+            "Process cache: {} hits, {} misses, {} evicted",
+            cache.hits, cache.misses, cache.evictions
+        );
+        decoding.process_cache.clear();
+        if decoding.decoder.pending_count() > 0 {
+            debug!(
+                "Discarding {} incompletely received paths",
+                decoding.decoder.pending_count()
+            );
+        }
+        decoding.decoder.clear();
+        drop(decoding);
+        if let Some((pins, _)) = &self.pinning {
+            // Leave the pins in place for a later --reuse-pinned
+            pins.release();
+        }
+
+        info!("eBPF monitoring stopped successfully");
+        Ok(())
+    }
+
+    /// Populate the filter maps from a compiled filter and attach only
+    /// the probes its actions need
+    fn apply_kernel_plan(&mut self, plan: &KernelFilterPlan) -> Result<()> {
+        self.exclude_pseudo_fs |= plan.exclude_pseudo_fs;
+        self.kernel_filter = Some(plan.clone());
+        if self.is_monitoring {
+            let probes = self.loaded()?;
+            push_kernel_plan(&probes, plan)?;
+            // Keeps an exclusion asked for with with_pseudo_fs_excluded
+            bpf_loader::lock(&probes)
+                .set_pseudo_fs_excluded(self.exclude_pseudo_fs)?;
+        }
+        self.reconfigure(plan.features.clone())?;
+        Ok(())
+    }
+
+    /// Detach the probes that only report events, keeping the maps,
+    /// caches and the probes that keep the descriptor table current
+    fn pause(&mut self) -> Result<()> {
+        if !self.is_monitoring || self.paused {
+            return Ok(());
+        }
+        info!("Pausing eBPF monitoring");
+        let bookkeeping = ProbeFeature::BOOKKEEPING
+            .into_iter()
+            .filter(|feature| self.features.contains(feature))
+            .collect();
+        let requested = std::mem::replace(&mut self.features, bookkeeping);
+        self.sync_probes()?;
+        self.features = requested;
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Attach the requested probes again
+    fn resume(&mut self) -> Result<()> {
+        if !self.is_monitoring || !self.paused {
+            return Ok(());
+        }
+        info!("Resuming eBPF monitoring");
+        self.paused = false;
+        self.sync_probes()?;
+        Ok(())
+    }
+}
+
+impl CounterSource for EbpfMonitor {
+    /// Read and reset the kernel's per-CPU counters
+    ///
+    /// # Returns
+    /// * `Result<KernelCounts>` - Counts summed over CPUs, or error if
+    ///   kernel counting isn't enabled
+    fn drain_counts(&mut self) -> Result<KernelCounts> {
+        if !self.kernel_agg {
+            return Err(anyhow!("Kernel aggregation is not enabled"));
+        }
+        // Entries incremented between the read and the delete are lost,
+        // which is bounded by one interval's increments per key
+        let probes = self.loaded()?;
+        let counts = bpf_loader::lock(&probes).drain_counts()?;
+        Ok(counts)
     }
 }
 
-// Note: In a complete eBPF implementation, we would also need:
-// 1. A separate .bpf.c file with the kernel-side eBPF program
-// 2. Build integration to compile the eBPF program with clang
-// 3. Proper libbpf-rs integration for loading and managing the program
-// 4. Event parsing logic to convert raw kernel data to FileEvent structs
+/// Write a compiled filter's entries into the kernel filter maps
+///
+/// # Arguments
+/// * `probes` - Loaded probes whose maps are written
+/// * `plan` - Entries to apply
+///
+/// # Returns
+/// * `Result<()>` - Error if a map couldn't be written
+fn push_kernel_plan(
+    probes: &SharedProbes,
+    plan: &KernelFilterPlan,
+) -> Result<()> {
+    debug!(
+        "Kernel filter: {} uids (includes {}), {} names (mode {})",
+        plan.uid_entries.len(),
+        plan.uid_includes,
+        plan.comm_names.len(),
+        plan.comm_mode
+    );
+    let mut probes = bpf_loader::lock(probes);
+    probes.set_uid_filter(&plan.uid_entries, plan.uid_includes)?;
+    probes.set_comm_filter(&plan.comm_names, plan.comm_mode)
+}
+
+/// Write a process list into the kernel process filter
+///
+/// # Arguments
+/// * `probes` - Loaded probes whose maps are written
+/// * `list` - List to apply
+///
+/// # Returns
+/// * `Result<()>` - Error if a map couldn't be written
+fn push_process_list(probes: &SharedProbes, list: &ProcessList) -> Result<()> {
+    let names: Vec<_> = list.kernel_names().collect();
+    debug!(
+        "Process filter: {} names, mode {}",
+        names.len(),
+        list.kernel_mode()
+    );
+    bpf_loader::lock(probes).set_comm_filter(&names, list.kernel_mode())
+}
+
+/// Write the sampling rate chosen by the overload controller, if it
+/// changed
+///
+/// # Arguments
+/// * `probes` - Loaded probes whose SAMPLE_RATE is written
+/// * `health` - Carries the rate chosen by the overload controller
+/// * `written` - Rate last written, updated before writing so a failure
+///   is only reported once
+fn sync_sample_rate(probes: &SharedProbes, health: &Health, written: &mut u32) {
+    let rate = health.degradation().sample_rate;
+    if rate == *written {
+        return;
+    }
+    *written = rate;
+    match bpf_loader::lock(probes).set_sample_rate(rate) {
+        Ok(()) => debug!("Sampling 1 in {}", rate),
+        Err(e) => warn!("Failed to set the sampling rate: {:#}", e),
+    }
+}
+
+/// Names of the processes running now, as the probes store them
+///
+/// # Arguments
+/// * `proc_root` - Where procfs is mounted
+///
+/// # Returns
+/// * `Vec<(u32, [u8; MAX_COMM_LEN])>` - Process ID and null-padded name
+///   of each process
+fn running_comms(proc_root: &Path) -> Vec<(u32, [u8; MAX_COMM_LEN])> {
+    let Ok(entries) = fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse().ok()?;
+            let name = fs::read(entry.path().join("comm")).ok()?;
+            let name = name.strip_suffix(b"\n").unwrap_or(&name);
+            let mut comm = [0; MAX_COMM_LEN];
+            let len = name.len().min(MAX_COMM_LEN - 1);
+            comm[..len].copy_from_slice(&name[..len]);
+            Some((pid, comm))
+        })
+        .collect()
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file_event::{SyncKind, LOCK_WAIT_THRESHOLD_NS};
+    use crate::probes::ProbeSpec;
+    use fw_common::{
+        AggKey, COMM_FILTER_ALLOW, COMM_FILTER_DENY, EVENT_TYPE_CHDIR,
+        EVENT_TYPE_CHMOD, EVENT_TYPE_CLOSE, EVENT_TYPE_DUP, EVENT_TYPE_LINK,
+        EVENT_TYPE_LINK_SOURCE, EVENT_TYPE_LOCK, EVENT_TYPE_OPEN,
+        EVENT_TYPE_SETXATTR, EVENT_TYPE_SYNC, EVENT_TYPE_TRUNCATE,
+        EVENT_TYPE_UNLOCK, LOCK_FLAG_NONBLOCKING, LOCK_FLAG_REFUSED,
+        MAX_EXT_LEN, SYNC_KIND_FDATASYNC, UID_FILTER_EXCLUDE,
+        UID_FILTER_INCLUDE,
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+    use zerocopy::{FromZeros, IntoBytes};
 
     #[test]
     fn test_new_monitor() {
@@ -214,6 +1248,785 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_snapshot_reports_open_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(file.as_file());
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let mut monitor = monitor
+            .with_snapshot(true)
+            .with_probes(Box::new(FakeProbes::default()));
+        let mut rx = monitor.start_monitoring().await.unwrap();
+
+        let own = loop {
+            let event = rx.recv().await.unwrap();
+            if event.pid == std::process::id() && event.fd == Some(fd) {
+                break event;
+            }
+        };
+        assert_eq!(own.action, FileAction::AlreadyOpen);
+        // Closes of files open before the start resolve from the table
+        assert!(lock(&monitor.decoding)
+            .decoder
+            .fd_table()
+            .iter()
+            .any(|(pid, open_fd, _)| pid == own.pid && open_fd == fd));
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    /// Loaded probes that only record what is attached
+    #[derive(Default)]
+    struct FakeProbes {
+        /// Probes attached now, shared with the test
+        attached: Arc<Mutex<Vec<ProbeSpec>>>,
+        /// Program the verifier rejects
+        rejects: Option<&'static str>,
+        /// Map contents, shared with the test
+        maps: Arc<Mutex<FakeMaps>>,
+    }
+
+    /// What the monitor wrote to the maps of [`FakeProbes`]
+    #[derive(Default)]
+    struct FakeMaps {
+        /// Program of each tail call slot
+        tail_calls: BTreeMap<u32, String>,
+        /// Sends records as if the probes had sent them
+        records: Option<mpsc::Sender<Vec<u8>>>,
+        /// UID_FILTER entries
+        uid_filter: BTreeMap<u32, u8>,
+        /// UID_FILTER_ACTIVE[0]
+        uid_includes: bool,
+        /// COMM_FILTER keys
+        comm_filter: BTreeSet<[u8; MAX_COMM_LEN]>,
+        /// COMM_FILTER_MODE[0]
+        comm_mode: u32,
+        /// LEADER_COMMS entries
+        leader_comms: BTreeMap<u32, [u8; MAX_COMM_LEN]>,
+        /// TRIPWIRES keys
+        tripwires: Vec<[u8; MAX_PATH_LEN]>,
+        /// SAMPLE_RATE[0]
+        sample_rate: u32,
+        /// The first SAMPLE_EXEMPT_COUNT[0] entries of SAMPLE_EXEMPT
+        sample_exemptions: Vec<StackCriterion>,
+        /// PSEUDO_FS_EXCLUDED[0] is 1
+        pseudo_fs_excluded: bool,
+        /// AGG_ACTIVE[0] is 1
+        agg_active: bool,
+        /// AGG_COUNTS entries, summed over CPUs
+        agg_counts: Vec<(AggKey, u64)>,
+        /// AGG_OVERFLOW[0], summed over CPUs
+        agg_overflow: u64,
+        /// STACK_MODE[0]
+        stack_mode: u32,
+        /// The first STACK_CRITERIA_COUNT[0] entries of STACK_CRITERIA
+        stack_criteria: Vec<StackCriterion>,
+        /// Addresses of each stack in STACKS
+        stacks: BTreeMap<u32, Vec<u64>>,
+    }
+
+    impl LoadedProbes for FakeProbes {
+        fn load(&mut self, program: &str) -> Result<(), LoadError> {
+            match self.rejects == Some(program) {
+                true => Err(LoadError::Rejected {
+                    error: io::Error::from_raw_os_error(libc::EACCES),
+                    log: "R1 invalid mem access 'scalar'".to_string(),
+                }),
+                false => Ok(()),
+            }
+        }
+
+        fn attach(&mut self, probe: &ProbeSpec, _symbol: &str) -> Result<()> {
+            let mut attached = self.attached.lock().unwrap();
+            if !attached.contains(probe) {
+                attached.push(*probe);
+            }
+            Ok(())
+        }
+
+        fn detach(&mut self, probe: &ProbeSpec) -> Result<()> {
+            self.attached.lock().unwrap().retain(|p| p != probe);
+            Ok(())
+        }
+
+        fn set_tail_call(&mut self, slot: u32, program: &str) -> Result<()> {
+            let mut maps = self.maps.lock().unwrap();
+            maps.tail_calls.insert(slot, program.to_string());
+            Ok(())
+        }
+
+        fn read_events(
+            &mut self,
+            records: mpsc::Sender<Vec<u8>>,
+            _health: &Health,
+        ) -> Result<()> {
+            self.maps.lock().unwrap().records = Some(records);
+            Ok(())
+        }
+
+        fn set_uid_filter(
+            &mut self,
+            entries: &[(u32, u8)],
+            includes: bool,
+        ) -> Result<()> {
+            let mut maps = self.maps.lock().unwrap();
+            maps.uid_filter = entries.iter().copied().collect();
+            maps.uid_includes = includes;
+            Ok(())
+        }
+
+        fn set_comm_filter(
+            &mut self,
+            names: &[[u8; MAX_COMM_LEN]],
+            mode: u32,
+        ) -> Result<()> {
+            let mut maps = self.maps.lock().unwrap();
+            maps.comm_filter = names.iter().copied().collect();
+            maps.comm_mode = mode;
+            Ok(())
+        }
+
+        fn set_leader_comms(
+            &mut self,
+            comms: &[(u32, [u8; MAX_COMM_LEN])],
+        ) -> Result<()> {
+            let mut maps = self.maps.lock().unwrap();
+            maps.leader_comms.extend(comms.iter().copied());
+            Ok(())
+        }
+
+        fn set_tripwires(
+            &mut self,
+            paths: &[[u8; MAX_PATH_LEN]],
+        ) -> Result<()> {
+            self.maps.lock().unwrap().tripwires = paths.to_vec();
+            Ok(())
+        }
+
+        fn set_sample_rate(&mut self, rate: u32) -> Result<()> {
+            self.maps.lock().unwrap().sample_rate = rate;
+            Ok(())
+        }
+
+        fn set_sample_exemptions(
+            &mut self,
+            criteria: &[StackCriterion],
+        ) -> Result<()> {
+            self.maps.lock().unwrap().sample_exemptions = criteria.to_vec();
+            Ok(())
+        }
+
+        fn set_pseudo_fs_excluded(&mut self, excluded: bool) -> Result<()> {
+            self.maps.lock().unwrap().pseudo_fs_excluded = excluded;
+            Ok(())
+        }
+
+        fn set_kernel_aggregation(&mut self, active: bool) -> Result<()> {
+            self.maps.lock().unwrap().agg_active = active;
+            Ok(())
+        }
+
+        fn drain_counts(&mut self) -> Result<KernelCounts> {
+            let mut maps = self.maps.lock().unwrap();
+            Ok(KernelCounts {
+                counts: std::mem::take(&mut maps.agg_counts),
+                overflow: std::mem::take(&mut maps.agg_overflow),
+            })
+        }
+
+        fn set_stack_mode(&mut self, flags: u32) -> Result<()> {
+            self.maps.lock().unwrap().stack_mode = flags;
+            Ok(())
+        }
+
+        fn set_stack_criteria(
+            &mut self,
+            criteria: &[StackCriterion],
+        ) -> Result<()> {
+            self.maps.lock().unwrap().stack_criteria = criteria.to_vec();
+            Ok(())
+        }
+
+        fn take_stack(&mut self, id: u32) -> Result<Vec<u64>> {
+            let mut maps = self.maps.lock().unwrap();
+            maps.stacks
+                .remove(&id)
+                .ok_or_else(|| anyhow!("No stack {}", id))
+        }
+    }
+
+    /// Probes of a feature set and the features it relies on
+    fn probes_of(features: &FeatureSet) -> BTreeSet<String> {
+        probes::with_prerequisites(features)
+            .into_iter()
+            .flat_map(|f| f.probes().iter().map(ToString::to_string))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_probe_changes_reach_the_links() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let fake = FakeProbes::default();
+        let attached = fake.attached.clone();
+        let links = || -> BTreeSet<String> {
+            attached
+                .lock()
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        let mut monitor = monitor.with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        assert_eq!(links(), probes_of(&probes::default_features()));
+
+        let opens: FeatureSet = [ProbeFeature::Opens].into_iter().collect();
+        let plan = monitor.reconfigure(opens.clone()).unwrap();
+        assert_eq!(links(), probes_of(&opens));
+        assert!(plan
+            .detach
+            .iter()
+            .all(|p| !links().contains(&p.to_string())));
+
+        let io: FeatureSet = [ProbeFeature::Io].into_iter().collect();
+        let plan = monitor.reconfigure(io.clone()).unwrap();
+        assert!(plan.attach.iter().all(|p| links().contains(&p.to_string())));
+        assert_eq!(links(), probes_of(&io));
+
+        monitor.stop_monitoring().await.unwrap();
+        assert!(links().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tail_call_slots_are_filled() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let mut monitor = monitor.with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        let expected: BTreeMap<u32, String> = TAIL_CALL_PROGRAMS
+            .iter()
+            .map(|(slot, program)| (*slot, program.to_string()))
+            .collect();
+        assert_eq!(maps.lock().unwrap().tail_calls, expected);
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_records_are_translated() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let mut monitor = monitor.with_probes(Box::new(fake));
+        let mut events = monitor.start_monitoring().await.unwrap();
+        let records = maps.lock().unwrap().records.clone().unwrap();
+
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, -1);
+        open.path[..6].copy_from_slice(b"/a.log");
+        records.send(vec![0; 3]).await.unwrap();
+        records.send(open.as_bytes().to_vec()).await.unwrap();
+        let event = loop {
+            let event = events.recv().await.unwrap();
+            if event.pid == 50 {
+                break event;
+            }
+        };
+        assert_eq!(event.action, FileAction::Opened);
+        assert_eq!(event.file_path, "/a.log");
+
+        monitor.stop_monitoring().await.unwrap();
+        records.closed().await;
+    }
+
+    #[tokio::test]
+    async fn test_user_filter_reaches_the_maps() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let users = UserFilter::from_uids(&[1000], &[0]).unwrap();
+        let mut monitor =
+            monitor.with_user_filter(users).with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        {
+            let maps = maps.lock().unwrap();
+            let expected = BTreeMap::from([
+                (0, UID_FILTER_EXCLUDE),
+                (1000, UID_FILTER_INCLUDE),
+            ]);
+            assert_eq!(maps.uid_filter, expected);
+            assert!(maps.uid_includes);
+        }
+
+        assert!(!maps.lock().unwrap().pseudo_fs_excluded);
+
+        // A compiled filter without users clears the entries
+        let filter =
+            crate::filter_builder::FilterBuilder::new().build().unwrap();
+        monitor.apply_kernel_plan(&filter.kernel_plan()).unwrap();
+        {
+            let maps = maps.lock().unwrap();
+            assert!(maps.uid_filter.is_empty());
+            assert!(!maps.uid_includes);
+            // Pseudo filesystems are left out unless asked for
+            assert!(maps.pseudo_fs_excluded);
+        }
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    /// Null-padded process name
+    fn comm(name: &str) -> [u8; MAX_COMM_LEN] {
+        let mut comm = [0; MAX_COMM_LEN];
+        comm[..name.len()].copy_from_slice(name.as_bytes());
+        comm
+    }
+
+    #[tokio::test]
+    async fn test_process_filter_reaches_the_maps() {
+        use crate::process_list::ProcessListMode;
+
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let deny = ProcessList::parse("cron\n", ProcessListMode::Deny);
+        let (lists, list) = watch::channel(Arc::new(deny.unwrap()));
+        let mut monitor =
+            monitor.with_process_list(list).with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        {
+            let maps = maps.lock().unwrap();
+            assert_eq!(maps.comm_filter, BTreeSet::from([comm("cron")]));
+            assert_eq!(maps.comm_mode, COMM_FILTER_DENY);
+            assert!(maps.leader_comms.contains_key(&std::process::id()));
+        }
+
+        // A reloaded list replaces the names without waiting for events
+        let allow = ProcessList::parse("sshd\n", ProcessListMode::Allow);
+        lists.send(Arc::new(allow.unwrap())).unwrap();
+        for _ in 0..100 {
+            if maps.lock().unwrap().comm_mode == COMM_FILTER_ALLOW {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        {
+            let maps = maps.lock().unwrap();
+            assert_eq!(maps.comm_filter, BTreeSet::from([comm("sshd")]));
+            assert_eq!(maps.comm_mode, COMM_FILTER_ALLOW);
+        }
+
+        // So does a compiled filter
+        let filter =
+            crate::filter_builder::FilterBuilder::new().build().unwrap();
+        monitor.apply_kernel_plan(&filter.kernel_plan()).unwrap();
+        assert!(maps.lock().unwrap().comm_filter.is_empty());
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tripwires_reach_the_map() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let long = format!("/{}", "a".repeat(MAX_PATH_LEN));
+        let paths = ["/etc/decoy".to_string(), long];
+        let mut monitor =
+            monitor.with_tripwires(&paths).with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+
+        // Only the path fitting in one chunk can be matched
+        let tripwires = maps.lock().unwrap().tripwires.clone();
+        assert_eq!(tripwires.len(), 1);
+        assert_eq!(&tripwires[0][..10], b"/etc/decoy");
+        assert!(tripwires[0][10..].iter().all(|&b| b == 0));
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sampling_follows_health() {
+        use crate::overload::Degradation;
+
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let health = Health::default();
+        let exempt = vec![StackCriterion::new(None, b"/etc/")];
+        let mut monitor = monitor
+            .with_health(&health)
+            .with_sample_exemptions(exempt.clone())
+            .with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        assert_eq!(maps.lock().unwrap().sample_rate, 1);
+        assert_eq!(maps.lock().unwrap().sample_exemptions, exempt);
+
+        // A rate chosen while running reaches the map without events
+        health.set_degradation(Degradation {
+            enrichment_paused: false,
+            sample_rate: 8,
+        });
+        for _ in 0..300 {
+            if maps.lock().unwrap().sample_rate == 8 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(maps.lock().unwrap().sample_rate, 8);
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stacks_are_read_once() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let criteria = vec![StackCriterion::new(None, b"/etc/")];
+        let mut monitor = monitor
+            .with_stacks(Some(StackMode::Kernel))
+            .with_stack_criteria(criteria.clone())
+            .with_probes(Box::new(fake));
+        let mut events = monitor.start_monitoring().await.unwrap();
+        let records = {
+            let mut maps = maps.lock().unwrap();
+            assert_eq!(maps.stack_mode, StackMode::Kernel.flags());
+            assert_eq!(maps.stack_criteria, criteria);
+            maps.stacks.insert(7, vec![0xffff_0001, 0xffff_0002]);
+            maps.records.clone().unwrap()
+        };
+
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, -1);
+        open.path[..7].copy_from_slice(b"/etc/ab");
+        open.kernel_stack_id = 7;
+        open.user_stack_id = -1;
+        records.send(open.as_bytes().to_vec()).await.unwrap();
+        let event = loop {
+            let event = events.recv().await.unwrap();
+            if event.pid == 50 {
+                break event;
+            }
+        };
+        let stack = event.stack.unwrap();
+        assert_eq!(stack.kernel, vec![0xffff_0001, 0xffff_0002]);
+        assert!(stack.user.is_empty());
+        // Deleted once read, so the map doesn't fill up
+        assert!(maps.lock().unwrap().stacks.is_empty());
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kernel_counts_are_drained() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let fake = FakeProbes::default();
+        let maps = fake.maps.clone();
+        let mut monitor = monitor
+            .with_kernel_aggregation(true)
+            .with_probes(Box::new(fake));
+        let _events = monitor.start_monitoring().await.unwrap();
+        let key = AggKey {
+            comm: comm("cat"),
+            ext: [0; MAX_EXT_LEN],
+            event_type: EVENT_TYPE_OPEN,
+            detail: 0,
+        };
+        {
+            let mut maps = maps.lock().unwrap();
+            assert!(maps.agg_active);
+            maps.agg_counts.push((key, 12));
+            maps.agg_overflow = 3;
+        }
+
+        let counts = monitor.drain_counts().unwrap();
+        assert_eq!(counts.counts, vec![(key, 12)]);
+        assert_eq!(counts.overflow, 3);
+        // Reset by the read
+        let counts = monitor.drain_counts().unwrap();
+        assert!(counts.counts.is_empty());
+        assert_eq!(counts.overflow, 0);
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[test]
+    fn test_running_comms() {
+        let proc_root = tempfile::tempdir().unwrap();
+        let process = proc_root.path().join("42");
+        fs::create_dir(&process).unwrap();
+        fs::write(process.join("comm"), "cron\n").unwrap();
+        fs::create_dir(proc_root.path().join("self")).unwrap();
+
+        let comms = running_comms(proc_root.path());
+        assert_eq!(comms, vec![(42, comm("cron"))]);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_program_is_explained() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return;
+        };
+        let (_, program) = TAIL_CALL_PROGRAMS[0];
+        let fake = FakeProbes {
+            rejects: Some(program),
+            ..FakeProbes::default()
+        };
+        let mut monitor = monitor.with_probes(Box::new(fake));
+        let error = monitor.start_monitoring().await.unwrap_err();
+        let message = error.to_string();
+        assert!(message
+            .starts_with(&format!("The kernel refused to load {}", program)));
+        assert!(message.contains("invalid mem access"));
+        let dir = Path::new(verifier::DIAGNOSTICS_DIR);
+        let _ = fs::remove_file(verifier::diagnostics_path(dir, program));
+    }
+
+    /// Build a raw descriptor event for process 50
+    fn raw_event(event_type: u32, fd: i32, old_fd: i32) -> RawFileEvent {
+        let mut raw = RawFileEvent::new_zeroed();
+        raw.version = fw_common::EVENT_ABI_VERSION;
+        raw.pid = 50;
+        raw.tgid = 50;
+        raw.event_type = event_type;
+        raw.fd = fd;
+        raw.old_fd = old_fd;
+        raw
+    }
+
+    #[test]
+    fn test_close_of_duplicated_fd_reports_path() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let translator = monitor.translator();
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, -1);
+        open.path[..6].copy_from_slice(b"/a.log");
+
+        let opened = translator.decode_raw_event(&open).unwrap();
+        assert_eq!(opened.action, FileAction::Opened);
+        assert!(translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_DUP, 5, 3))
+            .is_none());
+
+        let closed = translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_CLOSE, 5, -1))
+            .unwrap();
+        assert_eq!(closed.action, FileAction::Closed);
+        assert_eq!(closed.file_path, "/a.log");
+
+        // Closing an untracked descriptor reports nothing
+        assert!(translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_CLOSE, 9, -1))
+            .is_none());
+    }
+
+    #[test]
+    fn test_metadata_change_on_tracked_fd() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let translator = monitor.translator();
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, -1);
+        open.path[..6].copy_from_slice(b"/a.log");
+        open.dev = (8 << 20) | 1;
+        open.ino = 77;
+        open.uid = 1000;
+        open.gid = 100;
+        let opened = translator.decode_raw_event(&open).unwrap();
+        assert_eq!(opened.file_id.unwrap().to_string(), "8:1/77");
+        assert_eq!((opened.uid, opened.gid), (Some(1000), Some(100)));
+
+        let mut chmod = raw_event(EVENT_TYPE_CHMOD, 3, -1);
+        chmod.arg = 0o600;
+        let changed = translator.decode_raw_event(&chmod).unwrap();
+        assert_eq!(changed.action, FileAction::ModeChanged { mode: 0o600 });
+        assert_eq!(changed.file_path, "/a.log");
+
+        let mut truncate = raw_event(EVENT_TYPE_TRUNCATE, 3, -1);
+        truncate.arg = 42;
+        let truncated = translator.decode_raw_event(&truncate).unwrap();
+        assert_eq!(truncated.action, FileAction::Truncated { length: 42 });
+
+        let mut sync = raw_event(EVENT_TYPE_SYNC, 3, -1);
+        sync.arg = SYNC_KIND_FDATASYNC;
+        sync.open_latency_ns = 2_500_000;
+        let synced = translator.decode_raw_event(&sync).unwrap();
+        assert_eq!(
+            synced.action,
+            FileAction::Synced {
+                kind: SyncKind::Fdatasync
+            }
+        );
+        assert_eq!(synced.open_latency_ns, Some(2_500_000));
+
+        // A blocking lock that took long waited; a refused one didn't lock
+        let mut lock = raw_event(EVENT_TYPE_LOCK, 3, -1);
+        lock.arg = fw_common::LOCK_KIND_EXCLUSIVE;
+        lock.open_latency_ns = 2 * LOCK_WAIT_THRESHOLD_NS;
+        let locked = translator.decode_raw_event(&lock).unwrap();
+        assert_eq!(locked.action.to_string(), "lock exclusive blocked");
+        lock.arg2 = LOCK_FLAG_NONBLOCKING | LOCK_FLAG_REFUSED;
+        let refused = translator.decode_raw_event(&lock).unwrap();
+        assert_eq!(refused.action.to_string(), "lock exclusive refused");
+        let unlock = raw_event(EVENT_TYPE_UNLOCK, 3, -1);
+        let unlocked = translator.decode_raw_event(&unlock).unwrap();
+        assert_eq!(unlocked.action, FileAction::Unlocked);
+
+        // The descriptor is still open afterwards
+        let closed = translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_CLOSE, 3, -1))
+            .unwrap();
+        assert_eq!(closed.file_path, "/a.log");
+    }
+
+    #[test]
+    fn test_link_event_pairs_source_and_target() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let translator = monitor.translator();
+        let mut source = raw_event(EVENT_TYPE_LINK_SOURCE, -1, -1);
+        source.path[..8].copy_from_slice(b"/etc/foo");
+        assert!(translator.decode_raw_event(&source).is_none());
+
+        let mut link = raw_event(EVENT_TYPE_LINK, -1, -1);
+        link.path[..8].copy_from_slice(b"/tmp/foo");
+        let linked = translator.decode_raw_event(&link).unwrap();
+        assert_eq!(linked.action, FileAction::Linked);
+        assert_eq!(linked.file_path, "/tmp/foo");
+        assert_eq!(linked.link_source.as_deref(), Some("/etc/foo"));
+
+        // A link event whose source record was lost is dropped
+        assert!(translator.decode_raw_event(&link).is_none());
+    }
+
+    #[test]
+    fn test_open_after_chdir_resolves_against_new_cwd() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let translator = monitor.translator();
+        let mut chdir = raw_event(EVENT_TYPE_CHDIR, -1, -1);
+        chdir.path[..4].copy_from_slice(b"/srv");
+        assert!(translator.decode_raw_event(&chdir).is_none());
+
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, libc::AT_FDCWD);
+        open.path[..5].copy_from_slice(b"a.log");
+        let opened = translator.decode_raw_event(&open).unwrap();
+        assert_eq!(opened.file_path, "/srv/a.log");
+    }
+
+    #[test]
+    fn test_xattr_event_carries_name() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let translator = monitor.translator();
+        let mut raw = raw_event(EVENT_TYPE_SETXATTR, -1, -1);
+        raw.path[..8].copy_from_slice(b"/bin/ls\0");
+        raw.filename[..16].copy_from_slice(b"security.selinux");
+
+        let event = translator.decode_raw_event(&raw).unwrap();
+        assert_eq!(event.action, FileAction::XattrSet);
+        assert_eq!(event.file_path, "/bin/ls");
+        assert_eq!(event.xattr_name.as_deref(), Some("security.selinux"));
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure_preserves_descriptor_table() {
+        use crate::probes::ProbeFeature;
+
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let mut monitor = monitor.with_probes(Box::new(FakeProbes::default()));
+        let _events = monitor.start_monitoring().await.unwrap();
+        let translator = monitor.translator();
+        let mut open = raw_event(EVENT_TYPE_OPEN, 3, -1);
+        open.path[..6].copy_from_slice(b"/a.log");
+        translator.decode_raw_event(&open).unwrap();
+
+        let features = [ProbeFeature::Opens, ProbeFeature::Descriptors];
+        let plan = monitor.reconfigure(features.into_iter().collect()).unwrap();
+        assert!(plan.attach.is_empty());
+        assert!(!plan.detach.is_empty());
+
+        // Descriptors opened before the reconfiguration still resolve
+        let closed = translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_CLOSE, 3, -1))
+            .unwrap();
+        assert_eq!(closed.file_path, "/a.log");
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_detaches_probes() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let mut monitor = monitor.with_probes(Box::new(FakeProbes::default()));
+        let _events = monitor.start_monitoring().await.unwrap();
+        let translator = monitor.translator();
+        monitor.pause().unwrap();
+        let bookkeeping: FeatureSet =
+            ProbeFeature::BOOKKEEPING.into_iter().collect();
+        assert_eq!(monitor.attached, bookkeeping);
+
+        // Descriptors opened while paused still resolve after the resume
+        let mut open = raw_event(EVENT_TYPE_OPEN, 4, -1);
+        open.path[..6].copy_from_slice(b"/b.log");
+        translator.decode_raw_event(&open).unwrap();
+
+        // Reconfiguring while paused waits for the resume
+        let features: FeatureSet = [
+            ProbeFeature::Opens,
+            ProbeFeature::Descriptors,
+            ProbeFeature::Io,
+        ]
+        .into_iter()
+        .collect();
+        monitor.reconfigure(features.clone()).unwrap();
+        assert_eq!(monitor.attached, bookkeeping);
+        monitor.resume().unwrap();
+        assert_eq!(monitor.attached, features);
+        let closed = translator
+            .decode_raw_event(&raw_event(EVENT_TYPE_CLOSE, 4, -1))
+            .unwrap();
+        assert_eq!(closed.file_path, "/b.log");
+        monitor.stop_monitoring().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kernel_plan_selects_probes() {
+        let Ok(monitor) = EbpfMonitor::new() else {
+            return; // No eBPF support in this environment
+        };
+        let filter = crate::filter_builder::FilterBuilder::new()
+            .with_action(ActionMatch::Read)
+            .build()
+            .unwrap();
+        let plan = filter.kernel_plan();
+        let mut monitor = monitor.with_probes(Box::new(FakeProbes::default()));
+        monitor.apply_kernel_plan(&plan).unwrap();
+        assert_eq!(monitor.features(), &plan.features);
+        let _events = monitor.start_monitoring().await.unwrap();
+        assert_eq!(monitor.attached, plan.features);
+        monitor.stop_monitoring().await.unwrap();
+    }
+
     #[test]
     fn test_ebpf_support_check() {
         // This test may fail in environments without eBPF support
@@ -0,0 +1,693 @@
+//! Enrich module
+//!
+//! Attaches custom metadata to events as key/value tags before they are
+//! filtered and reported, e.g. the team owning a path or the service a
+//! process belongs to. Each source of tags is an [`Enricher`]; the
+//! built-ins map path prefixes to tags from a config file, cgroups to
+//! systemd services, uids and gids to user and group names, and opened
+//! paths to the size and age of the file.
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use nix::unistd::{Gid, Group, Uid, User};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::file_event::{FileAction, FileEvent};
+use crate::severity::Classifier;
+use crate::tripwire::Ancestry;
+use crate::watchlist::{WatchlistTags, Watchlists};
+
+/// Source of tags for events
+pub trait Enricher: Send {
+    /// Add tags to an event
+    ///
+    /// # Arguments
+    /// * `event` - Annotated event to tag; existing tags may be replaced
+    fn enrich(&mut self, event: &mut FileEvent);
+}
+
+/// Built-in enrichers that need no configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EnricherKind {
+    /// Resolve the acting user and group to names
+    User,
+    /// Tag events with the systemd service of the process ("service")
+    Service,
+    /// Stat opened files for their size and age
+    File,
+}
+
+/// Files statted per second by each enrichment worker, at most
+pub const MAX_FILE_STATS_PER_SEC: u32 = 1000;
+
+/// How much enrichment runs on every event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EnrichmentLevel {
+    /// None: events carry only what the kernel reported
+    Off,
+    /// The tag map, the built-ins chosen with --enrich and severity
+    #[default]
+    Basic,
+    /// Everything in basic plus every built-in enricher
+    Full,
+}
+
+/// Enrichers selected on the command line
+#[derive(Debug, Clone, Default)]
+pub struct EnrichConfig {
+    /// How much enrichment runs
+    pub level: EnrichmentLevel,
+    /// Number of enrichment workers; at least one runs unless off
+    pub workers: usize,
+    /// Path prefix to tag mapping file
+    pub tag_map: Option<PathBuf>,
+    /// Built-in enrichers to enable
+    pub kinds: Vec<EnricherKind>,
+    /// Severity policy replacing the default one
+    pub severity_policy: Option<PathBuf>,
+    /// Current watchlists, kept up to date by their supervisor; events
+    /// get no watchlist tags if unset
+    pub watchlists: Option<watch::Receiver<Arc<Watchlists>>>,
+    /// Decoy files every access to which is critical
+    pub tripwires: Vec<String>,
+    /// Record the stacks of accesses to tripwires
+    pub tripwire_stacks: bool,
+    /// Leave out enrichers reading `/proc` or statting files
+    /// (`--forensic`)
+    pub forensic: bool,
+}
+
+impl EnrichConfig {
+    /// Severity classifier of the configured policy and tripwires
+    ///
+    /// # Returns
+    /// * `Result<Classifier>` - Classifier, or error if the policy is
+    ///   invalid
+    pub fn classifier(&self) -> Result<Classifier> {
+        let classifier = match &self.severity_policy {
+            Some(path) => Classifier::load(path)?,
+            None => Classifier::default(),
+        };
+        Ok(classifier.with_tripwires(&self.tripwires, self.tripwire_stacks))
+    }
+}
+
+/// Enrichers applied to every event, in order
+#[derive(Default)]
+pub struct Enrichers {
+    enrichers: Vec<Box<dyn Enricher>>,
+}
+
+impl Enrichers {
+    /// Build the enrichers selected on the command line
+    ///
+    /// The tag map and watchlists are applied first, then the built-ins
+    /// in the order given (all of them at the full level), then the
+    /// severity classifier, which always runs unless enrichment is off,
+    /// and finally the ancestry of tripwire accesses. In forensic mode
+    /// only the enrichers working from the event itself are allowed, and
+    /// tripwire accesses get no ancestry.
+    ///
+    /// # Arguments
+    /// * `config` - Tag map, built-in enrichers and severity policy to use
+    ///
+    /// # Returns
+    /// * `Result<Enrichers>` - Enrichers, or error if the map or policy is
+    ///   invalid, or a built-in is needed in forensic mode
+    pub fn from_config(config: &EnrichConfig) -> Result<Self> {
+        let mut enrichers = Self::default();
+        let mut kinds = match config.level {
+            EnrichmentLevel::Off => return Ok(enrichers),
+            EnrichmentLevel::Basic => config.kinds.clone(),
+            EnrichmentLevel::Full => EnricherKind::value_variants().to_vec(),
+        };
+        let classifier = config.classifier()?;
+        // Rules on file size or age need the file statted first
+        if classifier.needs_file_stats() && !kinds.contains(&EnricherKind::File)
+        {
+            kinds.push(EnricherKind::File);
+        }
+        if config.forensic && !kinds.is_empty() {
+            return Err(anyhow!(
+                "--forensic leaves out enrichers that read /proc or stat \
+                 files; drop --enrich, --enrichment full and filters or \
+                 policy rules on file size or age"
+            ));
+        }
+        if let Some(path) = &config.tag_map {
+            enrichers.push(PathTags::load(path)?);
+        }
+        if let Some(lists) = &config.watchlists {
+            enrichers.push(WatchlistTags::new(lists.clone()));
+        }
+        for kind in kinds {
+            match kind {
+                EnricherKind::User => enrichers.push(IdNames::new("/proc")),
+                EnricherKind::Service => {
+                    enrichers.push(CgroupServices::new("/proc"))
+                }
+                EnricherKind::File => enrichers
+                    .push(FileStats::new("/proc", MAX_FILE_STATS_PER_SEC)),
+            }
+        }
+        enrichers.push(classifier);
+        if !config.tripwires.is_empty() && !config.forensic {
+            enrichers.push(Ancestry::new("/proc"));
+        }
+        Ok(enrichers)
+    }
+
+    /// Build one set of enrichers per enrichment worker
+    ///
+    /// Each worker gets its own enrichers, and with them its own caches.
+    ///
+    /// # Arguments
+    /// * `config` - Tag map, built-in enrichers, level and worker count
+    ///
+    /// # Returns
+    /// * `Result<Vec<Enrichers>>` - One set per worker, none when
+    ///   enrichment is off, or error if the map or policy is invalid
+    pub fn for_workers(config: &EnrichConfig) -> Result<Vec<Self>> {
+        if config.level == EnrichmentLevel::Off {
+            return Ok(Vec::new());
+        }
+        (0..config.workers.max(1))
+            .map(|_| Self::from_config(config))
+            .collect()
+    }
+
+    /// Add an enricher after the existing ones
+    ///
+    /// # Arguments
+    /// * `enricher` - Enricher to apply to every event
+    pub fn push(&mut self, enricher: impl Enricher + 'static) {
+        self.enrichers.push(Box::new(enricher));
+    }
+
+    /// Check whether no enrichers are configured
+    ///
+    /// # Returns
+    /// * `bool` - True if events are passed through untouched
+    pub fn is_empty(&self) -> bool {
+        self.enrichers.is_empty()
+    }
+
+    /// Apply every enricher to an event
+    ///
+    /// # Arguments
+    /// * `event` - Annotated event to tag
+    pub fn enrich(&mut self, event: &mut FileEvent) {
+        for enricher in &mut self.enrichers {
+            enricher.enrich(event);
+        }
+    }
+}
+
+/// Tags events whose path starts with a configured prefix
+///
+/// The mapping file has one prefix per line followed by its tags, e.g.
+/// `/srv/billing team=payments class=pii`. Blank lines and lines starting
+/// with `#` are ignored. When prefixes overlap, the longest one wins for
+/// each tag key.
+#[derive(Debug, Clone, Default)]
+pub struct PathTags {
+    /// Prefixes and their tags, shortest prefix first
+    rules: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl PathTags {
+    /// Load a mapping file
+    ///
+    /// # Arguments
+    /// * `path` - Mapping file to read
+    ///
+    /// # Returns
+    /// * `Result<PathTags>` - Mapping, or error naming the bad line
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text)
+            .with_context(|| format!("Invalid tag map {}", path.display()))
+    }
+
+    /// Parse the contents of a mapping file
+    ///
+    /// # Arguments
+    /// * `text` - Mapping lines
+    ///
+    /// # Returns
+    /// * `Result<PathTags>` - Mapping, or error naming the bad line
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let prefix = fields.next().unwrap_or_default();
+            let tags = fields
+                .map(|field| {
+                    parse_tag(field).and_then(|(key, value)| {
+                        value.map(|v| (key, v)).ok_or_else(|| {
+                            format!("tag '{}' has no value", field)
+                        })
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+            if !prefix.starts_with('/') || tags.is_empty() {
+                return Err(anyhow!(
+                    "line {}: expected '/path/prefix key=value ...'",
+                    number + 1
+                ));
+            }
+            rules.push((prefix.to_string(), tags));
+        }
+        rules.sort_by_key(|(prefix, _)| prefix.len());
+        Ok(Self { rules })
+    }
+}
+
+impl Enricher for PathTags {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        // Longer prefixes come later and overwrite shorter ones
+        for (prefix, tags) in &self.rules {
+            if is_path_prefix(prefix, &event.file_path) {
+                for (key, value) in tags {
+                    event.tags.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Check whether a prefix covers a path at a component boundary
+pub(crate) fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Resolves the uid and gid of events to user and group names
+///
+/// Names come from the passwd and group databases through NSS and are
+/// looked up once per id. Ids without an entry keep their number. So do
+/// processes in another mount namespace, such as containers, since the
+/// host's databases don't describe their users.
+#[derive(Debug, Clone)]
+pub struct IdNames {
+    proc_root: PathBuf,
+    /// Mount namespace of fw itself, if it could be read
+    own_mnt_ns: Option<PathBuf>,
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl IdNames {
+    /// Create the enricher
+    ///
+    /// # Arguments
+    /// * `proc_root` - Mount point of procfs (normally "/proc")
+    ///
+    /// # Returns
+    /// * `IdNames` - Enricher with empty name caches
+    pub fn new(proc_root: impl Into<PathBuf>) -> Self {
+        let proc_root = proc_root.into();
+        Self {
+            own_mnt_ns: fs::read_link(proc_root.join("self/ns/mnt")).ok(),
+            proc_root,
+            users: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Check whether a process sees the same passwd database as fw
+    ///
+    /// Processes that exited or can't be inspected are assumed to.
+    fn shares_host_databases(&self, pid: u32) -> bool {
+        let Some(own) = &self.own_mnt_ns else {
+            return true;
+        };
+        let ns = self.proc_root.join(pid.to_string()).join("ns/mnt");
+        fs::read_link(ns).map_or(true, |theirs| &theirs == own)
+    }
+}
+
+impl Enricher for IdNames {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        let (Some(uid), Some(gid)) = (event.uid, event.gid) else {
+            return; // The kernel did not capture the ids
+        };
+        let (user, group) = if self.shares_host_databases(event.pid) {
+            let user = self.users.entry(uid).or_insert_with(|| {
+                User::from_uid(Uid::from_raw(uid))
+                    .ok()
+                    .flatten()
+                    .map(|user| user.name)
+            });
+            let group = self.groups.entry(gid).or_insert_with(|| {
+                Group::from_gid(Gid::from_raw(gid))
+                    .ok()
+                    .flatten()
+                    .map(|group| group.name)
+            });
+            (user.clone(), group.clone())
+        } else {
+            (None, None)
+        };
+        event.user = Some(user.unwrap_or_else(|| uid.to_string()));
+        event.group = Some(group.unwrap_or_else(|| gid.to_string()));
+    }
+}
+
+/// Tags events with the systemd service of the process ("service")
+///
+/// The service is the last `.service` unit in `/proc/<pid>/cgroup`, so
+/// processes outside any service (e.g. in a login session) are untagged.
+#[derive(Debug, Clone)]
+pub struct CgroupServices {
+    proc_root: PathBuf,
+}
+
+impl CgroupServices {
+    /// Create the enricher
+    ///
+    /// # Arguments
+    /// * `proc_root` - Mount point of procfs (normally "/proc")
+    ///
+    /// # Returns
+    /// * `CgroupServices` - Enricher reading cgroups from `proc_root`
+    pub fn new(proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_root: proc_root.into(),
+        }
+    }
+}
+
+impl Enricher for CgroupServices {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        let path = self.proc_root.join(event.pid.to_string()).join("cgroup");
+        if let Some(service) = fs::read_to_string(path)
+            .ok()
+            .as_deref()
+            .and_then(service_from_cgroup)
+        {
+            event.tags.insert("service".to_string(), service);
+        }
+    }
+}
+
+/// Records the size and modification time of opened files
+///
+/// Only opens are statted, through the process's own root and working
+/// directory so paths inside containers resolve to the container's files.
+/// Files of processes that already exited aren't statted, since the same
+/// path may name another file outside their mount namespace. At most
+/// `rate` files are
+/// statted per second, so a burst of opens doesn't turn into a burst of
+/// metadata I/O; opens over the limit are left without size and age.
+#[derive(Debug, Clone)]
+pub struct FileStats {
+    proc_root: PathBuf,
+    /// Files statted per second, at most
+    rate: u32,
+    /// Start of the current one-second window
+    window_start: Instant,
+    /// Files statted in the current window
+    statted: u32,
+}
+
+impl FileStats {
+    /// Create the enricher
+    ///
+    /// # Arguments
+    /// * `proc_root` - Mount point of procfs (normally "/proc")
+    /// * `rate` - Files statted per second, at most
+    ///
+    /// # Returns
+    /// * `FileStats` - Enricher with a full budget for this second
+    pub fn new(proc_root: impl Into<PathBuf>, rate: u32) -> Self {
+        Self {
+            proc_root: proc_root.into(),
+            rate,
+            window_start: Instant::now(),
+            statted: 0,
+        }
+    }
+
+    /// Take one stat from this second's budget
+    fn take_budget(&mut self) -> bool {
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.statted = 0;
+        }
+        if self.statted >= self.rate {
+            return false;
+        }
+        self.statted += 1;
+        true
+    }
+}
+
+impl Enricher for FileStats {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        if event.action != FileAction::Opened || !self.take_budget() {
+            return;
+        }
+        let process = self.proc_root.join(event.pid.to_string());
+        let Ok(metadata) =
+            stat_in_process(&process, Path::new(&event.file_path))
+        else {
+            return;
+        };
+        event.file_size = Some(metadata.len());
+        event.file_modified = metadata.modified().ok().map(Into::into);
+    }
+}
+
+/// Stat a path as a process sees it
+///
+/// Absolute paths are resolved under the process's root with
+/// `RESOLVE_IN_ROOT`, so symlinks inside a container can't lead out of
+/// it; relative paths are resolved from its working directory.
+///
+/// # Arguments
+/// * `process` - The process's directory in procfs
+/// * `path` - Path as the process used it
+///
+/// # Returns
+/// * `io::Result<fs::Metadata>` - Metadata, or error if the process is
+///   gone or the path doesn't resolve
+fn stat_in_process(process: &Path, path: &Path) -> io::Result<fs::Metadata> {
+    let (base, path, resolve) = match path.strip_prefix("/") {
+        Ok(relative) => ("root", relative, libc::RESOLVE_IN_ROOT),
+        Err(_) => ("cwd", path, 0),
+    };
+    let dir = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(process.join(base))?;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // open_how is non-exhaustive; the kernel wants unused fields zeroed
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_PATH | libc::O_CLOEXEC) as u64;
+    how.resolve = resolve;
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owned from here, so it is closed when dropped
+    let file = unsafe { File::from_raw_fd(fd as i32) };
+    file.metadata()
+}
+
+/// Find the systemd service in the contents of `/proc/<pid>/cgroup`
+///
+/// # Arguments
+/// * `cgroup` - Lines of the form "hierarchy:controllers:path"
+///
+/// # Returns
+/// * `Option<String>` - Service name without ".service", if any
+pub fn service_from_cgroup(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.split('/'))
+        .filter_map(|unit| unit.strip_suffix(".service"))
+        .next_back()
+        .map(str::to_string)
+}
+
+/// Parse a tag such as "team=payments" or a bare key such as "team"
+///
+/// # Arguments
+/// * `value` - Tag key, optionally followed by "=" and a value
+///
+/// # Returns
+/// * `Result<(String, Option<String>), String>` - Key and value, or a
+///   usage error
+pub fn parse_tag(value: &str) -> Result<(String, Option<String>), String> {
+    let (key, tag_value) = match value.split_once('=') {
+        Some((key, tag_value)) => (key, Some(tag_value.to_string())),
+        None => (value, None),
+    };
+    if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(format!("invalid tag '{}'", value));
+    }
+    Ok((key.to_string(), tag_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    fn event(path: &str, pid: u32) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            pid,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_path_tags_longest_prefix_wins() {
+        let mut tags = PathTags::parse(
+            "# owners\n\
+             /srv team=infra\n\
+             /srv/billing/ team=payments class=pii\n",
+        )
+        .unwrap();
+
+        let mut billing = event("/srv/billing/ledger.db", 1);
+        tags.enrich(&mut billing);
+        assert_eq!(billing.tags["team"], "payments");
+        assert_eq!(billing.tags["class"], "pii");
+
+        let mut other = event("/srv/billing2/x", 1);
+        tags.enrich(&mut other);
+        assert_eq!(other.tags["team"], "infra");
+        assert!(!other.tags.contains_key("class"));
+
+        assert!(PathTags::parse("/srv team").is_err());
+        assert!(PathTags::parse("srv team=x").is_err());
+    }
+
+    #[test]
+    fn test_file_stats_rate_limited() {
+        let root = std::env::temp_dir()
+            .join(format!("fw-file-stats-{}", std::process::id()));
+        let cwd = root.join("7/cwd");
+        fs::create_dir_all(&cwd).unwrap();
+        fs::write(cwd.join("data.bin"), vec![0u8; 4096]).unwrap();
+        let container = root.join("8/root");
+        fs::create_dir_all(&container).unwrap();
+        fs::write(container.join("data.bin"), vec![0u8; 1024]).unwrap();
+        std::os::unix::fs::symlink("/data.bin", container.join("link"))
+            .unwrap();
+
+        // Relative to the process's working directory, and within budget
+        let mut stats = FileStats::new(&root, 3);
+        let mut relative = event("data.bin", 7);
+        stats.enrich(&mut relative);
+        assert_eq!(relative.file_size, Some(4096));
+        assert!(relative.file_age().unwrap() < Duration::from_secs(60));
+
+        // Closes aren't statted and don't use up the budget
+        let mut closed = event("data.bin", 7);
+        closed.action = FileAction::Closed;
+        stats.enrich(&mut closed);
+        assert_eq!(closed.file_size, None);
+
+        // Absolute paths and symlinks resolve inside the process's root
+        let mut contained = event("/link", 8);
+        stats.enrich(&mut contained);
+        assert_eq!(contained.file_size, Some(1024));
+
+        // Exited processes' paths aren't looked up in fw's own root
+        let absolute = cwd.join("data.bin").to_string_lossy().into_owned();
+        let mut exited = event(&absolute, 9);
+        stats.enrich(&mut exited);
+        assert_eq!(exited.file_size, None);
+        let mut over_budget = event("/data.bin", 8);
+        stats.enrich(&mut over_budget);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(over_budget.file_size, None);
+        assert_eq!(over_budget.file_age(), None);
+    }
+
+    #[test]
+    fn test_service_from_cgroup() {
+        assert_eq!(
+            service_from_cgroup("0::/system.slice/nginx.service\n"),
+            Some("nginx".to_string())
+        );
+        assert_eq!(
+            service_from_cgroup(
+                "0::/user.slice/user-1000.slice/session-2.scope"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_tag("team=db"),
+            Ok(("team".into(), Some("db".into())))
+        );
+        assert_eq!(parse_tag("team"), Ok(("team".into(), None)));
+        assert!(parse_tag("=db").is_err());
+    }
+
+    #[test]
+    fn test_id_names() {
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let own = || {
+            let mut own = event("/tmp/a", std::process::id());
+            own.uid = Some(uid);
+            own.gid = Some(gid);
+            own
+        };
+
+        let mut names = IdNames::new("/proc");
+        let mut resolved = own();
+        names.enrich(&mut resolved);
+        let expected = User::from_uid(Uid::from_raw(uid))
+            .ok()
+            .flatten()
+            .map(|u| u.name)
+            .unwrap_or_else(|| uid.to_string());
+        assert_eq!(resolved.user, Some(expected));
+        assert!(resolved.group.is_some());
+
+        // Another mount namespace keeps the numeric ids
+        names.own_mnt_ns = Some(PathBuf::from("mnt:[0]"));
+        let mut container = own();
+        names.enrich(&mut container);
+        assert_eq!(container.user, Some(uid.to_string()));
+        assert_eq!(container.group, Some(gid.to_string()));
+
+        let mut unknown = event("/tmp/a", 1);
+        names.enrich(&mut unknown);
+        assert_eq!(unknown.user, None);
+    }
+}
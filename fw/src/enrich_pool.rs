@@ -0,0 +1,266 @@
+//! Enrichment Pool module
+//!
+//! Runs enrichers on worker threads between the event loop and the
+//! fan-out, so reading `/proc` and resolving names never delays the loop
+//! draining the kernel's buffer. Events are routed to workers by pid, so
+//! a process's events are enriched in order and its caches stay with one
+//! worker, and published in the order they were submitted whichever
+//! worker finishes first. While the overload controller has paused
+//! enrichment, events skip the workers, still in sequence.
+
+use anyhow::{Context, Result};
+use log::warn;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+use crate::enrich::Enrichers;
+use crate::fanout::Publisher;
+use crate::file_event::FileEvent;
+use crate::health::Health;
+
+/// Number of events queued per worker before new ones are dropped
+pub const ENRICH_QUEUE_CAPACITY: usize = 1024;
+
+/// Worker count used unless `--enrich-workers` says otherwise
+pub const DEFAULT_ENRICH_WORKERS: usize = 2;
+
+/// Publishes events in the order they were submitted
+///
+/// Workers hand in events as they finish them; an event finished ahead
+/// of an earlier one waits until that one is handed in or dropped.
+struct Sequencer {
+    /// Where events go, in order
+    publisher: Publisher,
+    /// Sequence number of the next event to publish
+    next: u64,
+    /// Events finished ahead of their turn; None for dropped ones
+    pending: BTreeMap<u64, Option<FileEvent>>,
+}
+
+impl Sequencer {
+    /// Hand in an event, publishing it and any that were waiting on it
+    ///
+    /// # Arguments
+    /// * `seq` - Sequence number given at submission
+    /// * `event` - Enriched event, or None if it was dropped
+    fn finish(&mut self, seq: u64, event: Option<FileEvent>) {
+        self.pending.insert(seq, event);
+        while let Some(event) = self.pending.remove(&self.next) {
+            self.next += 1;
+            if let Some(event) = event {
+                self.publisher.publish(event);
+            }
+        }
+    }
+}
+
+/// Lock the sequencer, even if a worker panicked while holding it
+fn lock(sequencer: &Mutex<Sequencer>) -> std::sync::MutexGuard<'_, Sequencer> {
+    sequencer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// One enrichment worker
+struct Worker {
+    /// Queue of events waiting for this worker, with their sequence
+    /// numbers
+    input: mpsc::Sender<(u64, FileEvent)>,
+    /// Thread running the worker's enrichers
+    task: JoinHandle<()>,
+}
+
+/// Enriches events on worker threads and publishes them when done
+pub struct EnrichPool {
+    /// Workers, indexed by pid modulo their count
+    workers: Vec<Worker>,
+    /// Puts events finished by different workers back in order
+    sequencer: Arc<Mutex<Sequencer>>,
+    /// Sequence number of the next submitted event
+    next: u64,
+    /// Counts events dropped because a worker fell behind, and says
+    /// whether enrichment is paused
+    health: Health,
+    /// Events dropped because a worker fell behind
+    dropped: u64,
+}
+
+impl EnrichPool {
+    /// Start one worker per set of enrichers
+    ///
+    /// # Arguments
+    /// * `enrichers` - Enrichers for each worker; with none, events are
+    ///   published unenriched
+    /// * `publisher` - Where enriched events go
+    /// * `capacity` - Events queued per worker before new ones are dropped
+    /// * `health` - Counts events dropped by a worker falling behind
+    ///
+    /// # Returns
+    /// * `EnrichPool` - Running pool ready to accept events
+    pub fn spawn(
+        enrichers: Vec<Enrichers>,
+        publisher: Publisher,
+        capacity: usize,
+        health: &Health,
+    ) -> Self {
+        let sequencer = Arc::new(Mutex::new(Sequencer {
+            publisher,
+            next: 0,
+            pending: BTreeMap::new(),
+        }));
+        let workers = enrichers
+            .into_iter()
+            .map(|mut enrichers| {
+                let (input, mut queue) = mpsc::channel(capacity);
+                let sequencer = sequencer.clone();
+                // Enrichers do blocking I/O, so each gets its own thread
+                let task = tokio::task::spawn_blocking(move || {
+                    while let Some((seq, mut event)) = queue.blocking_recv() {
+                        enrichers.enrich(&mut event);
+                        lock(&sequencer).finish(seq, Some(event));
+                    }
+                });
+                Worker { input, task }
+            })
+            .collect();
+        Self {
+            workers,
+            sequencer,
+            next: 0,
+            health: health.clone(),
+            dropped: 0,
+        }
+    }
+
+    /// Queue an event for the worker handling its process
+    ///
+    /// Never blocks: if that worker's queue is full the event is dropped
+    /// and counted. While enrichment is paused the event is published
+    /// unenriched once the events submitted before it are.
+    ///
+    /// # Arguments
+    /// * `event` - Annotated event to enrich and publish
+    pub fn submit(&mut self, event: FileEvent) {
+        let seq = self.next;
+        self.next += 1;
+        if self.workers.is_empty()
+            || self.health.degradation().enrichment_paused
+        {
+            lock(&self.sequencer).finish(seq, Some(event));
+            return;
+        }
+        let worker = &self.workers[event.pid as usize % self.workers.len()];
+        match worker.input.try_send((seq, event)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                self.health.record_dropped(1);
+                lock(&self.sequencer).finish(seq, None);
+            }
+            // The worker panicked; finish reports it
+            Err(TrySendError::Closed(_)) => {
+                lock(&self.sequencer).finish(seq, None)
+            }
+        }
+    }
+
+    /// Stop accepting events and wait for queued ones to be published
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or error if a worker panicked
+    pub async fn finish(self) -> Result<()> {
+        if self.dropped > 0 {
+            warn!(
+                "Enrichment fell behind, dropped {} events; consider more \
+                 --enrich-workers",
+                self.dropped
+            );
+        }
+        for worker in self.workers {
+            drop(worker.input);
+            worker.task.await.context("Enrichment worker panicked")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::enrich::{Enricher, Enrichers};
+    use crate::fanout::{EventSink, FanOut, Subscriber};
+    use crate::file_event::FileAction;
+    use crate::filter::FilterSpec;
+    use std::time::Duration;
+
+    /// Tags each event with the worker that enriched it, taking the
+    /// given time per event
+    struct WorkerTag(&'static str, Duration);
+    impl Enricher for WorkerTag {
+        fn enrich(&mut self, event: &mut FileEvent) {
+            std::thread::sleep(self.1);
+            event.tags.insert("worker".to_string(), self.0.to_string());
+        }
+    }
+
+    struct EventsSink(Arc<Mutex<Vec<(u32, String, String)>>>);
+    impl EventSink for EventsSink {
+        fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+            self.0.lock().unwrap().push((
+                event.pid,
+                event.file_path.clone(),
+                event.tags["worker"].clone(),
+            ));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_keeps_submission_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let health = Health::default();
+        let fanout = FanOut::spawn(
+            vec![Subscriber::new(
+                "events",
+                FilterSpec::default(),
+                EventsSink(seen.clone()),
+            )],
+            1024,
+            &health,
+        );
+        // The even worker lags, so the odd one finishes events early
+        let enrichers = [("even", 2), ("odd", 0)]
+            .into_iter()
+            .map(|(name, ms)| {
+                let mut enrichers = Enrichers::default();
+                enrichers.push(WorkerTag(name, Duration::from_millis(ms)));
+                enrichers
+            })
+            .collect();
+        let mut pool =
+            EnrichPool::spawn(enrichers, fanout.publisher(), 64, &health);
+        for i in 0..20 {
+            pool.submit(FileEvent::new(
+                format!("/tmp/{}", i),
+                "cp".to_string(),
+                FileAction::Opened,
+                i % 2,
+                SystemClock.now(),
+            ));
+        }
+        pool.finish().await.unwrap();
+        fanout.finish().await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        let paths: Vec<&str> =
+            seen.iter().map(|(_, path, _)| path.as_str()).collect();
+        let expected: Vec<String> =
+            (0..20).map(|i| format!("/tmp/{}", i)).collect();
+        assert_eq!(paths, expected);
+        assert!(seen
+            .iter()
+            .all(|(pid, _, worker)| (*pid == 0) == (worker == "even")));
+        assert_eq!(health.snapshot().dropped, 0);
+    }
+}
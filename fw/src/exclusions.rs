@@ -0,0 +1,213 @@
+//! Exclusions module
+//!
+//! Loads the `fw collect --exclude-file` list of noise to leave out, as
+//! written by `fw suggest-filters` or by hand. Each line names one rule:
+//!
+//! ```text
+//! # Build caches
+//! process chrome
+//! path /var/cache
+//! extension tmp
+//! ```
+//!
+//! `process` drops every event of a program, `path` every event on a file
+//! under a directory, and `extension` every event on a file with that
+//! extension. The name, directory or extension is the rest of the line,
+//! so it may contain spaces (`process Web Content`). Blank lines and
+//! lines starting with '#' are ignored.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// One kind of noise to leave out
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Exclusion {
+    /// Every event of a program
+    Process(String),
+    /// Every event on a file under a directory
+    Path(String),
+    /// Every event on a file with an extension (without the dot)
+    Extension(String),
+}
+
+impl Exclusion {
+    /// Check whether the rule covers an event
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program
+    /// * `path` - File it accessed
+    ///
+    /// # Returns
+    /// * `bool` - True if the event should be left out
+    pub fn matches(&self, program: &str, path: &str) -> bool {
+        match self {
+            Exclusion::Process(name) => program == name,
+            Exclusion::Path(dir) => {
+                path.strip_prefix(dir.as_str()).is_some_and(|rest| {
+                    dir.ends_with('/') || rest.starts_with('/')
+                })
+            }
+            Exclusion::Extension(ext) => {
+                let name = path.rsplit('/').next().unwrap_or_default();
+                name.rsplit_once('.')
+                    .is_some_and(|(stem, e)| !stem.is_empty() && e == ext)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Exclusion {
+    /// Format as a line of an exclusion file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exclusion::Process(name) => write!(f, "process {}", name),
+            Exclusion::Path(dir) => write!(f, "path {}", dir),
+            Exclusion::Extension(ext) => write!(f, "extension {}", ext),
+        }
+    }
+}
+
+/// Rules of an exclusion file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exclusions {
+    /// Rules, without duplicates
+    rules: BTreeSet<Exclusion>,
+}
+
+impl Exclusions {
+    /// Load an exclusion file
+    ///
+    /// # Arguments
+    /// * `path` - File to read
+    ///
+    /// # Returns
+    /// * `Result<Exclusions>` - Rules, or error naming the bad line
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| {
+            format!("Invalid exclusion file {}", path.display())
+        })
+    }
+
+    /// Parse exclusion rules
+    ///
+    /// # Arguments
+    /// * `text` - One rule per line (see the module documentation)
+    ///
+    /// # Returns
+    /// * `Result<Exclusions>` - Rules, or error naming the bad line
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut rules = BTreeSet::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The value is the rest of the line, spaces and all
+            let (keyword, value) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(keyword, value)| (keyword, value.trim()));
+            let bad = |what: &str| anyhow!("line {}: {}", number + 1, what);
+            let rule = match (keyword, value) {
+                (_, "") => {
+                    return Err(bad("expected 'process NAME', 'path DIR' or \
+                         'extension EXT'"))
+                }
+                ("process", name) => Exclusion::Process(name.to_string()),
+                ("path", dir) if dir.starts_with('/') && dir.len() > 1 => {
+                    Exclusion::Path(dir.to_string())
+                }
+                ("path", _) => {
+                    return Err(bad("path must be an absolute directory"))
+                }
+                ("extension", ext) => Exclusion::Extension(
+                    ext.trim_start_matches('.').to_string(),
+                ),
+                _ => {
+                    return Err(bad("expected 'process NAME', 'path DIR' or \
+                         'extension EXT'"))
+                }
+            };
+            rules.insert(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Check whether any rule covers an event
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program
+    /// * `path` - File it accessed
+    ///
+    /// # Returns
+    /// * `bool` - True if the event should be left out
+    pub fn matches(&self, program: &str, path: &str) -> bool {
+        self.rules.iter().any(|rule| rule.matches(program, path))
+    }
+
+    /// The rules, processes first, then directories and extensions
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = &Exclusion>` - Rules in order
+    pub fn rules(&self) -> impl Iterator<Item = &Exclusion> {
+        self.rules.iter()
+    }
+
+    /// Number of rules
+    ///
+    /// # Returns
+    /// * `usize` - Rules loaded
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Check whether there are no rules
+    ///
+    /// # Returns
+    /// * `bool` - True if nothing is excluded
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let exclusions = Exclusions::parse(
+            "# noise\n\nprocess chrome\npath /var/cache\nextension .tmp\n",
+        )
+        .unwrap();
+        assert_eq!(exclusions.len(), 3);
+        assert!(exclusions.matches("chrome", "/etc/hosts"));
+        assert!(exclusions.matches("apt", "/var/cache/apt/pkgcache.bin"));
+        assert!(!exclusions.matches("apt", "/var/cache-old/x"));
+        assert!(exclusions.matches("vim", "/home/a/.notes.md.tmp"));
+        assert!(!exclusions.matches("vim", "/home/a/.tmp"));
+        assert!(!exclusions.matches("vim", "/home/a/notes.md"));
+
+        let err = Exclusions::parse("process a\npath var/log\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        assert!(Exclusions::parse("glob *.o").is_err());
+        assert!(Exclusions::parse("process\n").is_err());
+
+        // Names and directories may contain spaces
+        let spaced = Exclusion::Process("Web Content".into());
+        let exclusions =
+            Exclusions::parse(&format!("{}\npath /srv/My Files\n", spaced))
+                .unwrap();
+        assert!(exclusions.matches("Web Content", "/etc/hosts"));
+        assert!(!exclusions.matches("Web", "/etc/hosts"));
+        assert!(exclusions.matches("cp", "/srv/My Files/a"));
+        assert_eq!(
+            Exclusion::Path("/var/cache".into()).to_string(),
+            "path /var/cache"
+        );
+    }
+}
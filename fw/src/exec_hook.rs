@@ -0,0 +1,294 @@
+//! Exec hook module
+//!
+//! Runs a user command for every reported event (`fw collect --exec`).
+//! Hooks run in the background with a cap on how many may run at once and
+//! a timeout after which they are killed, so a slow hook can never stall
+//! event delivery; events arriving while every slot is busy are skipped.
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::stacks::frames_json;
+
+/// Default number of hooks allowed to run at once
+pub const DEFAULT_EXEC_CONCURRENCY: u32 = 4;
+
+/// Default time a hook may run before it is killed
+pub const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Command template and limits for an exec hook
+#[derive(Debug, Clone)]
+pub struct ExecConfig {
+    /// Program and arguments, each of which may contain placeholders
+    pub argv: Vec<String>,
+    /// Maximum number of hooks running at once
+    pub max_concurrent: u32,
+    /// Time a hook may run before it is killed
+    pub timeout: Duration,
+}
+
+impl ExecConfig {
+    /// Parse a command template
+    ///
+    /// The template is split on whitespace and run directly, without a
+    /// shell, so placeholder values are always passed as single arguments
+    /// no matter what characters a path contains. Supported placeholders
+    /// are `{path}`, `{pid}`, `{action}`, `{program}` and `{stack}`, the
+    /// event's symbolized stack as a JSON array (empty without one).
+    ///
+    /// # Arguments
+    /// * `template` - Command line, e.g. "notify-send {action} {path}"
+    /// * `max_concurrent` - Maximum number of hooks running at once
+    /// * `timeout` - Time a hook may run before it is killed
+    ///
+    /// # Returns
+    /// * `Result<ExecConfig>` - Hook configuration, or error if the template
+    ///   is empty
+    pub fn new(
+        template: &str,
+        max_concurrent: u32,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let argv: Vec<String> =
+            template.split_whitespace().map(str::to_string).collect();
+        if argv.is_empty() {
+            return Err(anyhow!("--exec needs a command"));
+        }
+        Ok(Self {
+            argv,
+            max_concurrent: max_concurrent.max(1),
+            timeout,
+        })
+    }
+
+    /// Command line for an event, with placeholders filled in
+    ///
+    /// # Arguments
+    /// * `event` - Event the hook runs for
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Program followed by its arguments
+    pub fn command_for(&self, event: &FileEvent) -> Vec<String> {
+        let stack = event
+            .stack
+            .as_ref()
+            .map_or_else(|| "[]".to_string(), |stack| frames_json(stack));
+        let value = |name: &str| match name {
+            "path" => Some(event.file_path.clone()),
+            "pid" => Some(event.pid.to_string()),
+            "action" => Some(event.action.to_string()),
+            "program" => Some(event.program_name.clone()),
+            "stack" => Some(stack.clone()),
+            _ => None,
+        };
+        self.argv.iter().map(|arg| fill(arg, value)).collect()
+    }
+}
+
+/// Fill the placeholders of one argument in a single left-to-right pass
+///
+/// Substituted values are copied verbatim, so a path or program name
+/// that itself contains e.g. `{pid}` is never expanded again.
+///
+/// # Arguments
+/// * `arg` - Template argument
+/// * `value` - Value of a placeholder name, or None if it isn't one
+///
+/// # Returns
+/// * `String` - Argument with its placeholders filled in
+fn fill(arg: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = rest
+            .find('}')
+            .and_then(|end| Some((value(&rest[1..end])?, end)));
+        match placeholder {
+            Some((value, end)) => {
+                filled.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Sink that runs an exec hook for every event it receives
+pub struct ExecSink {
+    /// Command template and limits
+    config: ExecConfig,
+    /// Runtime the hooks run on
+    runtime: Handle,
+    /// Free hook slots
+    slots: Arc<Semaphore>,
+    /// Number of events skipped because every slot was busy
+    skipped: u64,
+}
+
+impl ExecSink {
+    /// Create a sink running hooks on the given runtime
+    ///
+    /// # Arguments
+    /// * `config` - Command template and limits
+    /// * `runtime` - Runtime the hooks run on
+    ///
+    /// # Returns
+    /// * `ExecSink` - New exec sink
+    pub fn new(config: ExecConfig, runtime: Handle) -> Self {
+        let slots = Arc::new(Semaphore::new(config.max_concurrent as usize));
+        Self {
+            config,
+            runtime,
+            slots,
+            skipped: 0,
+        }
+    }
+
+    /// Number of events skipped because every slot was busy
+    ///
+    /// # Returns
+    /// * `u64` - Skipped event count
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl EventSink for ExecSink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let Ok(permit) = self.slots.clone().try_acquire_owned() else {
+            self.skipped += 1;
+            warn!(
+                "All {} exec hooks busy, skipping {}",
+                self.config.max_concurrent, event.file_path
+            );
+            return Ok(());
+        };
+
+        let argv = self.config.command_for(event);
+        let timeout = self.config.timeout;
+        self.runtime.spawn(async move {
+            // Holding the permit until the hook ends keeps the slot busy
+            let _permit = permit;
+            run_hook(&argv, timeout).await;
+        });
+        Ok(())
+    }
+}
+
+/// Run one hook to completion, killing it if it outlives the timeout
+///
+/// # Arguments
+/// * `argv` - Program followed by its arguments
+/// * `timeout` - Time the hook may run
+async fn run_hook(argv: &[String], timeout: Duration) {
+    let mut child = match Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run exec hook '{}': {}", argv[0], e);
+            return;
+        }
+    };
+
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {
+            debug!("Exec hook '{}' finished", argv[0])
+        }
+        Ok(Ok(status)) => warn!("Exec hook '{}' failed: {}", argv[0], status),
+        Ok(Err(e)) => warn!("Exec hook '{}' failed: {}", argv[0], e),
+        Err(_) => {
+            warn!("Exec hook '{}' timed out after {:?}", argv[0], timeout);
+            let _ = child.kill().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn event(path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "vim".to_string(),
+            FileAction::ModeChanged { mode: 0o644 },
+            42,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_placeholders_are_substituted_per_argument() {
+        let config = ExecConfig::new(
+            "logger -t fw {program}:{pid} {action} {path}",
+            1,
+            DEFAULT_EXEC_TIMEOUT,
+        )
+        .unwrap();
+        assert_eq!(
+            config.command_for(&event("/tmp/my file.txt")),
+            vec![
+                "logger",
+                "-t",
+                "fw",
+                "vim:42",
+                "chmod 0644",
+                "/tmp/my file.txt"
+            ]
+        );
+        assert!(ExecConfig::new("  ", 1, DEFAULT_EXEC_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_substituted_values_are_not_expanded_again() {
+        let config = ExecConfig::new(
+            "hook {path}:{program} {x}",
+            1,
+            DEFAULT_EXEC_TIMEOUT,
+        )
+        .unwrap();
+        let mut hostile = event("/tmp/{pid}{stack}/{path}");
+        hostile.program_name = "{path}".to_string();
+        assert_eq!(
+            config.command_for(&hostile),
+            vec!["hook", "/tmp/{pid}{stack}/{path}:{path}", "{x}"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_busy_hooks_skip_events() {
+        let config =
+            ExecConfig::new("sleep 5", 1, Duration::from_millis(100)).unwrap();
+        let mut sink = ExecSink::new(config, Handle::current());
+
+        sink.write_event(&event("/a")).unwrap();
+        sink.write_event(&event("/b")).unwrap();
+        assert_eq!(sink.skipped(), 1);
+
+        // The timed-out hook is killed and frees its slot
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        sink.write_event(&event("/c")).unwrap();
+        assert_eq!(sink.skipped(), 1);
+    }
+}
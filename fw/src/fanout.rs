@@ -0,0 +1,491 @@
+//! Fan-out module
+//!
+//! Broadcasts the annotated event stream from one capture to any number of
+//! independent subscribers. Each subscriber runs on its own task with its
+//! own filter, so a slow or failing sink (e.g. a full disk) never blocks or
+//! stops the others.
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+use crate::audit_format::PlainText;
+use crate::file_event::FileEvent;
+use crate::filter::FilterSpec;
+use crate::health::Health;
+
+/// Number of events buffered per subscriber before it starts lagging
+pub const FANOUT_CAPACITY: usize = 1024;
+
+/// Time between calls to [`EventSink::tick`]
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Destination for events delivered to a subscriber
+pub trait EventSink: Send + 'static {
+    /// Handle one event that passed the subscriber's filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the sink can no longer accept events
+    fn write_event(&mut self, event: &FileEvent) -> Result<()>;
+
+    /// Called when events meant for this sink were lost
+    ///
+    /// # Arguments
+    /// * `source` - Where they were lost (e.g. "lagged")
+    /// * `count` - Number of events lost
+    fn dropped(&mut self, _source: &str, _count: u64) {}
+
+    /// Called with an event the alert throttle held back or dropped, for
+    /// sinks that must still see every event
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event that wasn't passed on
+    fn throttled(&mut self, _event: &FileEvent) {}
+
+    /// Called about every [`TICK_INTERVAL`], with or without events, for
+    /// sinks with output due at a time rather than on an event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the sink can no longer accept events
+    fn tick(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the last event, for sinks that report at exit
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the final output could not be written
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How a [`TextSink`] writes each event
+pub trait EventEncoder: Send + 'static {
+    /// Write one event
+    ///
+    /// # Arguments
+    /// * `writer` - Destination of the output
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if the event couldn't be encoded or written
+    fn encode(&self, writer: &mut dyn Write, event: &FileEvent) -> Result<()>;
+}
+
+/// Sink that writes one formatted line, or binary frame, per event to a
+/// writer
+pub struct TextSink<W> {
+    /// Destination for event lines
+    writer: W,
+    /// How each event is written
+    encoder: Box<dyn EventEncoder>,
+}
+
+impl<W: Write + Send + 'static> TextSink<W> {
+    /// Create a sink writing human-readable event lines
+    ///
+    /// # Arguments
+    /// * `writer` - Destination for event lines
+    ///
+    /// # Returns
+    /// * `TextSink<W>` - New text sink
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            encoder: Box::new(PlainText),
+        }
+    }
+
+    /// Write events another way than the human-readable line
+    ///
+    /// # Arguments
+    /// * `encoder` - How each event is written, e.g. from
+    ///   [`EventFormat::encoder`](crate::audit_format::EventFormat::encoder)
+    ///
+    /// # Returns
+    /// * `TextSink<W>` - Sink writing that way
+    pub fn with_encoder(mut self, encoder: Box<dyn EventEncoder>) -> Self {
+        self.encoder = encoder;
+        self
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for TextSink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        self.encoder
+            .encode(&mut self.writer, event)
+            .context("Failed to write event")?;
+        // Flush immediately for real-time output
+        self.writer.flush().context("Failed to flush event output")
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        // Passes on a flush compressed output held back
+        self.writer.flush().context("Failed to flush event output")
+    }
+}
+
+/// A named sink together with the filter deciding what it receives
+pub struct Subscriber {
+    /// Name used in logs and reports
+    pub name: String,
+    /// Criteria events must match to reach the sink
+    pub filter: FilterSpec,
+    /// Criteria of this sink's own `--route`, checked after `filter`
+    pub route: Option<FilterSpec>,
+    /// Destination for matching events
+    pub sink: Box<dyn EventSink>,
+}
+
+impl Subscriber {
+    /// Create a subscriber
+    ///
+    /// # Arguments
+    /// * `name` - Name used in logs and reports
+    /// * `filter` - Criteria events must match to reach the sink
+    /// * `sink` - Destination for matching events
+    ///
+    /// # Returns
+    /// * `Subscriber` - New subscriber
+    pub fn new(
+        name: impl Into<String>,
+        filter: FilterSpec,
+        sink: impl EventSink,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            filter,
+            route: None,
+            sink: Box::new(sink),
+        }
+    }
+
+    /// Narrow what the sink receives beyond the shared filter
+    ///
+    /// # Arguments
+    /// * `route` - Further criteria events must match; None adds none
+    ///
+    /// # Returns
+    /// * `Subscriber` - The subscriber with its route
+    pub fn with_route(mut self, route: Option<FilterSpec>) -> Self {
+        self.route = route;
+        self
+    }
+
+    /// Check whether an event reaches the sink
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if it matches the filter and the route, if any
+    pub fn accepts(&self, event: &FileEvent) -> bool {
+        self.filter.matches(event)
+            && self.route.as_ref().is_none_or(|r| r.matches(event))
+    }
+}
+
+/// What happened to one subscriber over the life of the fan-out
+#[derive(Debug, Clone, Default)]
+pub struct SinkReport {
+    /// Name of the subscriber
+    pub name: String,
+    /// Number of events written to the sink
+    pub written: u64,
+    /// Number of events skipped because the subscriber fell behind
+    pub lagged: u64,
+    /// Error that stopped the sink, if it failed
+    pub error: Option<String>,
+}
+
+/// Broadcasts events to independently running subscribers
+pub struct FanOut {
+    /// Sending side of the broadcast channel
+    sender: broadcast::Sender<Arc<FileEvent>>,
+    /// One task per subscriber
+    tasks: Vec<JoinHandle<SinkReport>>,
+}
+
+impl FanOut {
+    /// Start one task per subscriber
+    ///
+    /// # Arguments
+    /// * `subscribers` - Sinks and their filters
+    /// * `capacity` - Events buffered per subscriber before it lags
+    /// * `health` - Counts events subscribers skip by lagging
+    ///
+    /// # Returns
+    /// * `FanOut` - Running fan-out ready to publish events
+    pub fn spawn(
+        subscribers: Vec<Subscriber>,
+        capacity: usize,
+        health: &Health,
+    ) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        let tasks = subscribers
+            .into_iter()
+            .map(|subscriber| {
+                let receiver = sender.subscribe();
+                let health = health.clone();
+                // Sinks do blocking I/O, so each gets its own thread
+                tokio::task::spawn_blocking(move || {
+                    run_subscriber(subscriber, receiver, &health)
+                })
+            })
+            .collect();
+        Self { sender, tasks }
+    }
+
+    /// Deliver an event to every subscriber that is still running
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    pub fn publish(&self, event: FileEvent) {
+        // Sending only fails once every subscriber has stopped
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    /// Handle for publishing from other tasks or threads
+    ///
+    /// Subscribers keep running until every publisher is dropped, so
+    /// drop them before calling [`FanOut::finish`].
+    ///
+    /// # Returns
+    /// * `Publisher` - Handle delivering to the same subscribers
+    pub fn publisher(&self) -> Publisher {
+        Publisher {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Stop accepting events and wait for subscribers to drain
+    ///
+    /// # Returns
+    /// * `Result<Vec<SinkReport>>` - One report per subscriber, in the
+    ///   order they were given
+    pub async fn finish(self) -> Result<Vec<SinkReport>> {
+        drop(self.sender);
+        let mut reports = Vec::with_capacity(self.tasks.len());
+        for task in self.tasks {
+            reports.push(task.await.context("Subscriber task panicked")?);
+        }
+        Ok(reports)
+    }
+}
+
+/// Cloneable handle publishing events into a [`FanOut`]
+#[derive(Clone)]
+pub struct Publisher {
+    /// Sending side of the fan-out's broadcast channel
+    sender: broadcast::Sender<Arc<FileEvent>>,
+}
+
+impl Publisher {
+    /// Deliver an event to every subscriber that is still running
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    pub fn publish(&self, event: FileEvent) {
+        // Sending only fails once every subscriber has stopped
+        let _ = self.sender.send(Arc::new(event));
+    }
+}
+
+/// Feed a subscriber from the broadcast channel until it closes
+///
+/// # Arguments
+/// * `subscriber` - Sink and filter to drive
+/// * `receiver` - Subscriber's end of the broadcast channel
+/// * `health` - Counts events skipped by lagging
+///
+/// # Returns
+/// * `SinkReport` - Counters and the error that stopped the sink, if any
+fn run_subscriber(
+    mut subscriber: Subscriber,
+    mut receiver: broadcast::Receiver<Arc<FileEvent>>,
+    health: &Health,
+) -> SinkReport {
+    let mut report = SinkReport {
+        name: subscriber.name.clone(),
+        ..Default::default()
+    };
+
+    // Sinks run on blocking threads, which may wait on the runtime
+    let handle = Handle::current();
+    let mut last_tick = Instant::now();
+    loop {
+        let received = handle
+            .block_on(tokio::time::timeout(TICK_INTERVAL, receiver.recv()));
+        if last_tick.elapsed() >= TICK_INTERVAL {
+            last_tick = Instant::now();
+            if let Err(e) = subscriber.sink.tick() {
+                report.error = Some(stopped(&report.name, e));
+                break;
+            }
+        }
+        let event = match received {
+            // Nothing arrived within the tick interval
+            Err(_) => continue,
+            Ok(Ok(event)) => event,
+            Ok(Err(RecvError::Lagged(skipped))) => {
+                warn!(
+                    "Sink '{}' fell behind, skipped {} events",
+                    report.name, skipped
+                );
+                report.lagged += skipped;
+                health.record_dropped(skipped);
+                subscriber.sink.dropped("lagged", skipped);
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => break,
+        };
+        if !subscriber.accepts(&event) {
+            continue;
+        }
+        if let Err(e) = subscriber.sink.write_event(&event) {
+            // Dropping the receiver detaches this sink from the others
+            report.error = Some(stopped(&report.name, e));
+            break;
+        }
+        report.written += 1;
+    }
+    if report.error.is_none() {
+        if let Err(e) = subscriber.sink.finish() {
+            error!("Sink '{}' failed to finish: {:#}", report.name, e);
+            report.error = Some(format!("{:#}", e));
+        }
+    }
+    report
+}
+
+/// Log a sink stopping on an error
+///
+/// # Arguments
+/// * `name` - Name of the subscriber
+/// * `e` - Error that stopped it
+///
+/// # Returns
+/// * `String` - Error for the subscriber's report
+pub(crate) fn stopped(name: &str, e: anyhow::Error) -> String {
+    error!("Sink '{}' stopped: {:#}", name, e);
+    format!("{:#}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use anyhow::anyhow;
+    use std::sync::Mutex;
+
+    /// Sink that collects event paths into a shared list
+    struct CollectSink(Arc<Mutex<Vec<String>>>);
+
+    impl EventSink for CollectSink {
+        fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+            self.0.lock().unwrap().push(event.file_path.clone());
+            Ok(())
+        }
+    }
+
+    /// Sink that fails on every event
+    struct FailingSink;
+
+    impl EventSink for FailingSink {
+        fn write_event(&mut self, _event: &FileEvent) -> Result<()> {
+            Err(anyhow!("disk full"))
+        }
+    }
+
+    fn event(path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    fn extensions(exts: &[&str]) -> FilterSpec {
+        FilterSpec {
+            extensions: Some(exts.iter().map(|e| e.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_apply_own_filters() {
+        let rust = Arc::new(Mutex::new(Vec::new()));
+        let all = Arc::new(Mutex::new(Vec::new()));
+        let routed = Arc::new(Mutex::new(Vec::new()));
+        let fanout = FanOut::spawn(
+            vec![
+                Subscriber::new(
+                    "rust",
+                    extensions(&["rs"]),
+                    CollectSink(rust.clone()),
+                ),
+                Subscriber::new(
+                    "all",
+                    FilterSpec::default(),
+                    CollectSink(all.clone()),
+                ),
+                Subscriber::new(
+                    "routed",
+                    FilterSpec::default(),
+                    CollectSink(routed.clone()),
+                )
+                .with_route(Some(extensions(&["rs"]))),
+            ],
+            FANOUT_CAPACITY,
+            &Health::default(),
+        );
+
+        fanout.publish(event("/src/main.rs"));
+        fanout.publish(event("/etc/hosts"));
+        let reports = fanout.finish().await.unwrap();
+
+        assert_eq!(*rust.lock().unwrap(), vec!["/src/main.rs"]);
+        assert_eq!(*all.lock().unwrap(), vec!["/src/main.rs", "/etc/hosts"]);
+        assert_eq!(*routed.lock().unwrap(), vec!["/src/main.rs"]);
+        assert_eq!(reports[0].written, 1);
+        assert_eq!(reports[1].written, 2);
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink_is_isolated() {
+        let healthy = Arc::new(Mutex::new(Vec::new()));
+        let fanout = FanOut::spawn(
+            vec![
+                Subscriber::new("broken", FilterSpec::default(), FailingSink),
+                Subscriber::new(
+                    "healthy",
+                    FilterSpec::default(),
+                    CollectSink(healthy.clone()),
+                ),
+            ],
+            FANOUT_CAPACITY,
+            &Health::default(),
+        );
+
+        for path in ["/a", "/b", "/c"] {
+            fanout.publish(event(path));
+        }
+        let reports = fanout.finish().await.unwrap();
+
+        assert_eq!(reports[0].error.as_deref(), Some("disk full"));
+        assert_eq!(reports[0].written, 0);
+        assert_eq!(healthy.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,660 @@
+//! Descriptor Table module
+//!
+//! Tracks which path each open file descriptor refers to, per process, so
+//! that close events (which only carry a descriptor) can be reported with
+//! the path that was opened. Duplicated descriptors share the path of
+//! their source, forked children inherit a copy of the parent's table,
+//! and threads share the table of their process.
+//! Descriptors opened before monitoring started can still be resolved
+//! through `/proc/<pid>/fd`, read once per descriptor: the path found is
+//! tracked like any other, and a descriptor that isn't a file (or whose
+//! open the kernel left out) is remembered as unresolved until it is
+//! closed or reused. Directory descriptors resolve the relative
+//! paths given to `openat` and the other `*at` calls, and each process's
+//! working directory, followed through chdir and fchdir, resolves the
+//! paths given relative to it.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::file_event::{FileId, FileType};
+use crate::path_assembler::AssembledPath;
+
+/// What an open descriptor refers to
+#[derive(Debug, Clone)]
+struct OpenFile {
+    /// Path that was opened
+    path: AssembledPath,
+    /// Kind of object opened, if known
+    file_type: Option<FileType>,
+    /// Device and inode of the file, if the kernel reported them
+    file_id: Option<FileId>,
+}
+
+/// Per-process map of open descriptors to the paths they refer to
+#[derive(Debug, Default)]
+pub struct FdTable {
+    /// Open descriptors keyed by process ID, then descriptor number
+    processes: HashMap<u32, HashMap<i32, OpenFile>>,
+    /// Working directories keyed by process ID, from chdir calls or read
+    /// once from `/proc/<pid>/cwd`
+    cwds: HashMap<u32, AssembledPath>,
+    /// Descriptors `/proc/<pid>/fd` didn't resolve to a file, keyed by
+    /// process ID, so they aren't read again on every event
+    unresolved: HashMap<u32, HashSet<i32>>,
+}
+
+impl FdTable {
+    /// Create an empty descriptor table
+    ///
+    /// # Returns
+    /// * `FdTable` - Table with no tracked descriptors
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a table of the files every process has open right now
+    ///
+    /// Reads `<proc_root>/<pid>/fd` for each process. Processes that exit
+    /// during the scan or can't be read (e.g. without root) are skipped,
+    /// as are descriptors that aren't files.
+    ///
+    /// # Arguments
+    /// * `proc_root` - Mount point of procfs, normally `/proc`
+    ///
+    /// # Returns
+    /// * `FdTable` - Table holding every open file found
+    pub fn scan(proc_root: &Path) -> Self {
+        let mut table = Self::new();
+        let Ok(entries) = fs::read_dir(proc_root) else {
+            return table;
+        };
+        for entry in entries.flatten() {
+            let Some(pid) =
+                entry.file_name().to_str().and_then(|n| n.parse().ok())
+            else {
+                continue;
+            };
+            let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+                continue;
+            };
+            for fd_entry in fds.flatten() {
+                let fd =
+                    fd_entry.file_name().to_str().and_then(|n| n.parse().ok());
+                if let (Some(fd), Some(path)) =
+                    (fd, fd_link_path(&fd_entry.path()))
+                {
+                    table.open(pid, fd, path, link_file_type(&fd_entry.path()));
+                }
+            }
+        }
+        table
+    }
+
+    /// Record that a process opened a path on a descriptor
+    ///
+    /// Any path previously tracked for the descriptor is replaced.
+    ///
+    /// # Arguments
+    /// * `pid` - Process that opened the file
+    /// * `fd` - Descriptor returned by the open
+    /// * `path` - Path that was opened
+    /// * `file_type` - Kind of object opened, if known
+    pub fn open(
+        &mut self,
+        pid: u32,
+        fd: i32,
+        path: AssembledPath,
+        file_type: Option<FileType>,
+    ) {
+        let file = OpenFile {
+            path,
+            file_type,
+            file_id: None,
+        };
+        self.forget_unresolved(pid, fd);
+        self.processes.entry(pid).or_default().insert(fd, file);
+    }
+
+    /// Record which file an open descriptor refers to
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor, already recorded with [`open`](Self::open)
+    /// * `file_id` - Device and inode of the file
+    pub fn identify(&mut self, pid: u32, fd: i32, file_id: Option<FileId>) {
+        if let Some(file) = self
+            .processes
+            .get_mut(&pid)
+            .and_then(|fds| fds.get_mut(&fd))
+        {
+            file.file_id = file_id;
+        }
+    }
+
+    /// Look up which file an open descriptor refers to
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor to look up
+    ///
+    /// # Returns
+    /// * `Option<FileId>` - Device and inode, or None if the descriptor
+    ///   isn't tracked or the kernel didn't report them
+    pub fn file_id(&self, pid: u32, fd: i32) -> Option<FileId> {
+        self.processes
+            .get(&pid)
+            .and_then(|fds| fds.get(&fd))
+            .and_then(|file| file.file_id)
+    }
+
+    /// Record that a descriptor was duplicated
+    ///
+    /// The new descriptor refers to the same path as the old one. If the
+    /// old descriptor is unknown, the new one is forgotten too, since dup2
+    /// onto an open descriptor implicitly closes it.
+    ///
+    /// # Arguments
+    /// * `pid` - Process that duplicated the descriptor
+    /// * `old_fd` - Source descriptor
+    /// * `new_fd` - Descriptor returned by the duplication
+    pub fn duplicate(&mut self, pid: u32, old_fd: i32, new_fd: i32) {
+        self.forget_unresolved(pid, new_fd);
+        let Some(fds) = self.processes.get_mut(&pid) else {
+            return;
+        };
+        match fds.get(&old_fd).cloned() {
+            Some(file) => {
+                fds.insert(new_fd, file);
+            }
+            None => {
+                fds.remove(&new_fd);
+            }
+        }
+    }
+
+    /// Record that a descriptor was closed
+    ///
+    /// # Arguments
+    /// * `pid` - Process that closed the descriptor
+    /// * `fd` - Descriptor that was closed
+    ///
+    /// # Returns
+    /// * `Option<AssembledPath>` - Path the descriptor referred to, if it
+    ///   was tracked
+    pub fn close(&mut self, pid: u32, fd: i32) -> Option<AssembledPath> {
+        self.forget_unresolved(pid, fd);
+        let fds = self.processes.get_mut(&pid)?;
+        let file = fds.remove(&fd);
+        if fds.is_empty() {
+            self.processes.remove(&pid);
+        }
+        file.map(|file| file.path)
+    }
+
+    /// Look up the path an open descriptor refers to
+    ///
+    /// Descriptors that are not tracked (e.g. opened before monitoring
+    /// started) are resolved through `/proc/<pid>/fd` while the process
+    /// still holds them. The link is read once: a path found is tracked
+    /// from then on, and a descriptor that doesn't resolve stays
+    /// unresolved until it is closed or reused.
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor to resolve
+    ///
+    /// # Returns
+    /// * `Option<AssembledPath>` - Path of the descriptor, or None if it
+    ///   can't be resolved to a file
+    pub fn resolve(&mut self, pid: u32, fd: i32) -> Option<AssembledPath> {
+        let tracked = self
+            .processes
+            .get(&pid)
+            .and_then(|fds| fds.get(&fd))
+            .map(|file| file.path.clone());
+        if tracked.is_some() || self.is_unresolved(pid, fd) {
+            return tracked;
+        }
+        let link = proc_fd_link(pid, fd);
+        match fd_link_path(&link) {
+            Some(path) => {
+                self.open(pid, fd, path.clone(), link_file_type(&link));
+                Some(path)
+            }
+            None => {
+                self.unresolved.entry(pid).or_default().insert(fd);
+                None
+            }
+        }
+    }
+
+    /// Look up the kind of object an open descriptor refers to
+    ///
+    /// Like [`resolve`](Self::resolve), untracked descriptors are looked
+    /// up through `/proc/<pid>/fd`, unless they are known not to resolve.
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor to look up
+    ///
+    /// # Returns
+    /// * `Option<FileType>` - Type, or None if it isn't known
+    pub fn file_type(&self, pid: u32, fd: i32) -> Option<FileType> {
+        match self.processes.get(&pid).and_then(|fds| fds.get(&fd)) {
+            Some(file) => file.file_type,
+            None if self.is_unresolved(pid, fd) => None,
+            None => link_file_type(&proc_fd_link(pid, fd)),
+        }
+    }
+
+    /// Check whether a descriptor was already found not to resolve
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor to check
+    ///
+    /// # Returns
+    /// * `bool` - True if `/proc/<pid>/fd` didn't resolve it to a file
+    fn is_unresolved(&self, pid: u32, fd: i32) -> bool {
+        self.unresolved
+            .get(&pid)
+            .is_some_and(|fds| fds.contains(&fd))
+    }
+
+    /// Forget that a descriptor didn't resolve, since it was reused
+    ///
+    /// # Arguments
+    /// * `pid` - Process holding the descriptor
+    /// * `fd` - Descriptor that was closed or reopened
+    fn forget_unresolved(&mut self, pid: u32, fd: i32) {
+        if let Some(fds) = self.unresolved.get_mut(&pid) {
+            fds.remove(&fd);
+            if fds.is_empty() {
+                self.unresolved.remove(&pid);
+            }
+        }
+    }
+
+    /// Make a path given relative to a directory descriptor absolute
+    ///
+    /// `AT_FDCWD` stands for the process's working directory. Absolute
+    /// paths, other negative descriptors and paths whose directory can't
+    /// be resolved are returned unchanged.
+    ///
+    /// # Arguments
+    /// * `pid` - Process that made the call
+    /// * `dirfd` - Directory descriptor passed to the call
+    /// * `path` - Path passed to the call
+    ///
+    /// # Returns
+    /// * `AssembledPath` - Path joined onto the directory's path
+    pub fn resolve_at(
+        &mut self,
+        pid: u32,
+        dirfd: i32,
+        path: AssembledPath,
+    ) -> AssembledPath {
+        if path.path.starts_with('/') {
+            return path;
+        }
+        let dir = match dirfd {
+            libc::AT_FDCWD => self.cwd(pid),
+            dirfd if dirfd >= 0 => self.resolve(pid, dirfd),
+            _ => None,
+        };
+        match dir {
+            Some(dir) => path.under(&dir),
+            None => path,
+        }
+    }
+
+    /// Record that a process changed its working directory
+    ///
+    /// A relative directory is taken from the current one. Directories
+    /// reached through ".." can't be worked out from the path alone when
+    /// symlinks are involved, so those are read from `/proc/<pid>/cwd`
+    /// the next time they are needed instead.
+    ///
+    /// # Arguments
+    /// * `pid` - Process that called chdir or fchdir
+    /// * `dir` - Directory passed to chdir, or the path of the descriptor
+    ///   passed to fchdir
+    pub fn chdir(&mut self, pid: u32, dir: AssembledPath) {
+        let dir = match dir.path.starts_with('/') {
+            true => Some(dir),
+            false => self.cwd(pid).map(|cwd| dir.under(&cwd)),
+        };
+        match dir.filter(|dir| !dir.path.split('/').any(|c| c == "..")) {
+            Some(dir) => {
+                self.cwds.insert(pid, dir);
+            }
+            None => {
+                self.cwds.remove(&pid);
+            }
+        }
+    }
+
+    /// Look up a process's working directory
+    ///
+    /// Processes that haven't called chdir since monitoring started are
+    /// looked up once through `/proc/<pid>/cwd` and remembered.
+    ///
+    /// # Arguments
+    /// * `pid` - Process to look up
+    ///
+    /// # Returns
+    /// * `Option<AssembledPath>` - Working directory, or None if the
+    ///   process is gone
+    pub fn cwd(&mut self, pid: u32) -> Option<AssembledPath> {
+        if let Some(cwd) = self.cwds.get(&pid) {
+            return Some(cwd.clone());
+        }
+        let cwd = fd_link_path(Path::new(&format!("/proc/{}/cwd", pid)))?;
+        self.cwds.insert(pid, cwd.clone());
+        Some(cwd)
+    }
+
+    /// Give a forked child a copy of its parent's descriptors and working
+    /// directory
+    ///
+    /// Only called for new processes. Threads share their process's
+    /// descriptors and working directory, which are kept under the
+    /// process ID every thread's events carry, so the kernel doesn't
+    /// report new threads or the exits of threads other than the leader.
+    ///
+    /// # Arguments
+    /// * `parent_pid` - Process that forked
+    /// * `child_pid` - Newly created process
+    pub fn fork(&mut self, parent_pid: u32, child_pid: u32) {
+        if let Some(fds) = self.processes.get(&parent_pid).cloned() {
+            self.processes.insert(child_pid, fds);
+        }
+        if let Some(cwd) = self.cwds.get(&parent_pid).cloned() {
+            self.cwds.insert(child_pid, cwd);
+        }
+    }
+
+    /// Forget every descriptor and the working directory of a process
+    /// that exited
+    ///
+    /// # Arguments
+    /// * `pid` - Process that exited
+    pub fn exit(&mut self, pid: u32) {
+        self.processes.remove(&pid);
+        self.cwds.remove(&pid);
+        self.unresolved.remove(&pid);
+    }
+
+    /// Iterate over every tracked descriptor
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = (u32, i32, &AssembledPath)>` - Process,
+    ///   descriptor and path of each entry, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, i32, &AssembledPath)> {
+        self.processes.iter().flat_map(|(pid, fds)| {
+            fds.iter().map(|(fd, file)| (*pid, *fd, &file.path))
+        })
+    }
+
+    /// Processes with at least one tracked descriptor
+    ///
+    /// # Returns
+    /// * `Vec<u32>` - Process IDs, in no particular order
+    pub fn pids(&self) -> Vec<u32> {
+        self.processes.keys().copied().collect()
+    }
+
+    /// Number of descriptors currently tracked across all processes
+    ///
+    /// # Returns
+    /// * `usize` - Count of tracked descriptors
+    pub fn len(&self) -> usize {
+        self.processes.values().map(HashMap::len).sum()
+    }
+
+    /// Check whether no descriptors are tracked
+    ///
+    /// # Returns
+    /// * `bool` - True if the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// Forget all tracked descriptors
+    pub fn clear(&mut self) {
+        self.processes.clear();
+        self.unresolved.clear();
+    }
+}
+
+/// Link in `/proc/<pid>/fd` for a descriptor
+///
+/// # Arguments
+/// * `pid` - Process holding the descriptor
+/// * `fd` - Descriptor to look up
+///
+/// # Returns
+/// * `PathBuf` - Path of the descriptor's symlink
+fn proc_fd_link(pid: u32, fd: i32) -> PathBuf {
+    PathBuf::from(format!("/proc/{}/fd/{}", pid, fd))
+}
+
+/// Read the file a procfs descriptor link points at
+///
+/// # Arguments
+/// * `link` - A `<proc>/<pid>/fd/<fd>` symlink
+///
+/// # Returns
+/// * `Option<AssembledPath>` - File path, or None for sockets, pipes and
+///   links that no longer exist
+fn fd_link_path(link: &Path) -> Option<AssembledPath> {
+    let target = fs::read_link(link).ok()?.into_os_string().into_vec();
+    // Anonymous objects read as e.g. "pipe:[1234]" rather than a path
+    target
+        .starts_with(b"/")
+        .then(|| AssembledPath::from_bytes(target, false))
+}
+
+/// Kind of object a procfs descriptor link points at
+///
+/// # Arguments
+/// * `link` - A `<proc>/<pid>/fd/<fd>` symlink
+///
+/// # Returns
+/// * `Option<FileType>` - Type, or None if the link no longer exists
+fn link_file_type(link: &Path) -> Option<FileType> {
+    let metadata = fs::metadata(link).ok()?;
+    FileType::from_mode(metadata.mode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a complete, untruncated path
+    fn path(text: &str) -> AssembledPath {
+        AssembledPath {
+            path: text.to_string(),
+            raw: None,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_open_then_close() {
+        let mut table = FdTable::new();
+        table.open(10, 3, path("/a.txt"), None);
+        assert_eq!(table.close(10, 3), Some(path("/a.txt")));
+        assert_eq!(table.close(10, 3), None);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_shares_path() {
+        let mut table = FdTable::new();
+        table.open(10, 3, path("/a.txt"), None);
+        table.duplicate(10, 3, 7);
+
+        assert_eq!(table.close(10, 3), Some(path("/a.txt")));
+        assert_eq!(table.close(10, 7), Some(path("/a.txt")));
+    }
+
+    #[test]
+    fn test_duplicate_of_unknown_fd_forgets_target() {
+        let mut table = FdTable::new();
+        table.open(10, 1, path("/old.log"), None);
+        // dup2(pipe_fd, 1) replaces the tracked file with an untracked fd
+        table.duplicate(10, 99, 1);
+        assert_eq!(table.close(10, 1), None);
+    }
+
+    #[test]
+    fn test_resolve_keeps_descriptor_open() {
+        let mut table = FdTable::new();
+        table.open(10, 3, path("/a.txt"), None);
+        assert_eq!(table.resolve(10, 3), Some(path("/a.txt")));
+        assert_eq!(table.close(10, 3), Some(path("/a.txt")));
+    }
+
+    #[test]
+    fn test_resolve_untracked_fd_from_proc() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(file.as_file());
+        let expected = fs::canonicalize(file.path()).unwrap();
+
+        let mut table = FdTable::new();
+        let pid = std::process::id();
+        let resolved = table.resolve(pid, fd).unwrap();
+        assert_eq!(resolved.path, expected.to_str().unwrap());
+        // The link is read once and the path tracked from then on
+        drop(file);
+        assert_eq!(table.resolve(pid, fd), Some(resolved));
+    }
+
+    #[test]
+    fn test_unresolved_fd_is_read_once() {
+        let (reader, _writer) = std::io::pipe().unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&reader);
+        let pid = std::process::id();
+
+        // A pipe isn't a file; the miss is remembered until reuse
+        let mut table = FdTable::new();
+        assert_eq!(table.resolve(pid, fd), None);
+        assert!(table.is_unresolved(pid, fd));
+        assert_eq!(table.file_type(pid, fd), None);
+        table.open(pid, fd, path("/a.txt"), None);
+        assert_eq!(table.resolve(pid, fd), Some(path("/a.txt")));
+
+        assert_eq!(table.resolve(u32::MAX, 3), None);
+        assert_eq!(table.close(u32::MAX, 3), None);
+        assert!(!table.is_unresolved(u32::MAX, 3));
+        assert_eq!(table.resolve(u32::MAX, 4), None);
+        table.exit(u32::MAX);
+        assert!(table.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_at_directory_descriptor() {
+        let mut table = FdTable::new();
+        table.open(10, 4, path("/srv/data/"), None);
+        table.open(10, 5, path("/srv/logs"), None);
+        assert_eq!(
+            table.resolve_at(10, 4, path("sub/a.db")),
+            path("/srv/data/sub/a.db")
+        );
+        assert_eq!(
+            table.resolve_at(10, 5, path("app.log")),
+            path("/srv/logs/app.log")
+        );
+
+        // Absolute paths, unknown descriptors and unknown processes
+        assert_eq!(table.resolve_at(10, 4, path("/etc/x")), path("/etc/x"));
+        assert_eq!(
+            table.resolve_at(u32::MAX, libc::AT_FDCWD, path("a.db")),
+            path("a.db")
+        );
+        assert_eq!(table.resolve_at(u32::MAX, 4, path("a.db")), path("a.db"));
+
+        // Non-UTF-8 directories keep their exact bytes
+        table.open(
+            10,
+            6,
+            AssembledPath::from_bytes(b"/d\xff".to_vec(), false),
+            None,
+        );
+        let joined = table.resolve_at(10, 6, path("f"));
+        assert_eq!(joined.raw.as_deref(), Some(&b"/d\xff/f"[..]));
+    }
+
+    #[test]
+    fn test_chdir_moves_working_directory() {
+        let mut table = FdTable::new();
+        table.chdir(10, path("/srv"));
+        table.chdir(10, path("data"));
+        assert_eq!(
+            table.resolve_at(10, libc::AT_FDCWD, path("a.db")),
+            path("/srv/data/a.db")
+        );
+
+        // fchdir passes the descriptor's path; children inherit the cwd
+        table.open(10, 4, path("/var/log"), None);
+        let dir = table.resolve(10, 4).unwrap();
+        table.chdir(10, dir);
+        table.fork(10, 11);
+        assert_eq!(table.cwd(11), Some(path("/var/log")));
+
+        // ".." is left to procfs, which knows nothing of a process this
+        // large
+        table.chdir(u32::MAX, path("/srv/data"));
+        table.chdir(u32::MAX, path("../logs"));
+        assert_eq!(table.cwd(u32::MAX), None);
+
+        // Processes that never called chdir are read from procfs once
+        let own = std::env::current_dir().unwrap();
+        assert_eq!(
+            table.cwd(std::process::id()).unwrap().path,
+            own.to_str().unwrap()
+        );
+        table.exit(11);
+        assert!(!table.cwds.contains_key(&11));
+    }
+
+    #[test]
+    fn test_scan_finds_own_descriptors() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(file.as_file());
+        let expected = fs::canonicalize(file.path()).unwrap();
+
+        let table = FdTable::scan(Path::new("/proc"));
+        let found = table.iter().find(|(pid, open_fd, _)| {
+            *pid == std::process::id() && *open_fd == fd
+        });
+        assert_eq!(found.unwrap().2.path, expected.to_str().unwrap());
+        assert!(FdTable::scan(Path::new("/nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn test_fork_inherits_descriptors() {
+        let mut table = FdTable::new();
+        table.open(10, 3, path("/a.txt"), None);
+        table.fork(10, 11);
+
+        assert_eq!(table.close(11, 3), Some(path("/a.txt")));
+        assert_eq!(table.close(10, 3), Some(path("/a.txt")));
+    }
+
+    #[test]
+    fn test_exit_drops_process() {
+        let mut table = FdTable::new();
+        table.open(10, 3, path("/a.txt"), None);
+        table.open(10, 4, path("/b.txt"), None);
+        assert_eq!(table.len(), 2);
+
+        table.exit(10);
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+//! Features module
+//!
+//! Lists the optional subsystems compiled into this fw. Subsystems with
+//! large dependency trees sit behind cargo features so embedded builds can
+//! leave them out; the commands and options that need them stay in the
+//! CLI and fail with an error naming the missing feature.
+
+use anyhow::{anyhow, Result};
+
+/// Every optional feature and whether it is compiled in
+pub const FEATURES: &[(&str, bool)] = &[
+    ("ebpf", cfg!(feature = "ebpf")),
+    ("zstd", cfg!(feature = "zstd")),
+    ("remote", cfg!(feature = "remote")),
+    ("sinks", cfg!(feature = "sinks")),
+    ("metrics", cfg!(feature = "metrics")),
+    ("mock", cfg!(feature = "mock")),
+];
+
+/// Features compiled into this build
+///
+/// # Returns
+/// * `Vec<&'static str>` - Names of the enabled features
+pub fn enabled() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Error for something a left-out feature provides
+///
+/// # Arguments
+/// * `feature` - Cargo feature
+/// * `what` - What needed it, e.g. "fw forward"
+///
+/// # Returns
+/// * `anyhow::Error` - Error naming the feature
+pub fn not_compiled_in(feature: &str, what: &str) -> anyhow::Error {
+    anyhow!(
+        "{} needs the '{}' feature, which is not compiled into this fw",
+        what,
+        feature
+    )
+}
+
+/// Check that a feature is compiled in
+///
+/// # Arguments
+/// * `feature` - Cargo feature
+/// * `what` - What needs it, e.g. "fw forward"
+///
+/// # Returns
+/// * `Result<()>` - Success, or error naming the missing feature
+pub fn require(feature: &str, what: &str) -> Result<()> {
+    if FEATURES.contains(&(feature, true)) {
+        Ok(())
+    } else {
+        Err(not_compiled_in(feature, what))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_require() {
+        assert_eq!(
+            require("zstd", "zstd output").is_ok(),
+            enabled().contains(&"zstd")
+        );
+        let e = require("kafka", "fw collect --kafka").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "fw collect --kafka needs the 'kafka' feature, which is not \
+             compiled into this fw"
+        );
+    }
+}
@@ -0,0 +1,642 @@
+//! Filter module
+//!
+//! Holds the user-selected criteria that decide which file events are
+//! reported. Events are annotated (e.g. with filesystem information) and
+//! enriched with tags before they reach the filter.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::exclusions::Exclusions;
+use crate::file_event::{FileEvent, FileType};
+use crate::glob::GlobSet;
+use crate::mount_table::normalize_mount_point;
+use crate::severity::Severity;
+use crate::wait_for::ActionMatch;
+
+/// Check of one criterion against an event
+type Criterion = fn(&FilterSpec, &FileEvent) -> bool;
+
+/// Criteria an event must satisfy to be reported
+///
+/// Each criterion is optional; unset criteria match every event.
+#[derive(Debug, Clone, Default)]
+pub struct FilterSpec {
+    /// File extensions to match (without the leading dot)
+    pub extensions: Option<Vec<String>>,
+    /// Globs the file name (last path component) must match
+    pub name_globs: Option<GlobSet>,
+    /// Globs the whole path must match
+    pub path_globs: Option<GlobSet>,
+    /// Match extensions and globs exactly instead of ignoring case
+    pub case_sensitive: bool,
+    /// Mount points whose files should be reported
+    pub mounts: Option<Vec<String>>,
+    /// Filesystem types whose files should be reported
+    pub fs_types: Option<Vec<String>>,
+    /// Report only remote (`Some(true)`) or only local (`Some(false)`)
+    /// files
+    pub remote: Option<bool>,
+    /// Minimum open or sync latency in nanoseconds; events without a
+    /// measured latency never match
+    pub min_latency_ns: Option<u64>,
+    /// Extended attribute namespaces (e.g. "security", "user") whose
+    /// changes are reported; other events are unaffected
+    pub xattr_namespaces: Option<Vec<String>>,
+    /// Tags the event must carry, each a key and optionally the value it
+    /// must have; all of them must match
+    pub tags: Option<Vec<(String, Option<String>)>>,
+    /// Least severe label to report; unclassified events never match
+    pub min_severity: Option<Severity>,
+    /// Smallest file size in bytes to report; events on files that
+    /// weren't statted never match
+    pub min_size: Option<u64>,
+    /// Largest file size in bytes to report; events on files that
+    /// weren't statted never match
+    pub max_size: Option<u64>,
+    /// Report only files modified less than this long before the event;
+    /// events on files that weren't statted never match
+    pub newer_than: Option<Duration>,
+    /// Types of file (regular file, directory, device...) to report;
+    /// events whose target type is unknown never match
+    pub types: Option<Vec<FileType>>,
+    /// Leave out files under /proc, /sys and /dev, which `fw collect`
+    /// does unless given `--include-pseudo-fs`
+    pub exclude_pseudo_fs: bool,
+    /// Process IDs whose activity is reported
+    pub pids: Option<Vec<u32>>,
+    /// Kinds of action to report
+    pub actions: Option<Vec<ActionMatch>>,
+    /// systemd services (cgroups) whose processes are reported; needs the
+    /// "service" tag, so events the service enricher didn't tag never
+    /// match
+    pub services: Option<Vec<String>>,
+    /// Processes, directories and extensions whose events are left out
+    pub exclusions: Option<Arc<Exclusions>>,
+}
+
+impl FilterSpec {
+    /// Check whether an event satisfies every configured criterion
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if the event should be reported
+    pub fn matches(&self, event: &FileEvent) -> bool {
+        self.rejection(event).is_none()
+    }
+
+    /// Find the first criterion an event fails
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `Option<&'static str>` - Name of the failed criterion (e.g.
+    ///   "fstype"), or None if the event should be reported
+    pub fn rejection(&self, event: &FileEvent) -> Option<&'static str> {
+        let checks: [(&'static str, Criterion); 18] = [
+            ("pseudo fs", |f, e| {
+                !f.exclude_pseudo_fs
+                    || !fw_common::is_pseudo_fs_path(e.file_path.as_bytes())
+            }),
+            ("exclusion", |f, e| {
+                f.exclusions
+                    .as_ref()
+                    .is_none_or(|x| !x.matches(&e.program_name, &e.file_path))
+            }),
+            ("extension", |f, e| {
+                e.matches_extensions(&f.extensions, f.case_sensitive)
+            }),
+            ("pid", |f, e| {
+                f.pids.as_ref().is_none_or(|p| p.contains(&e.pid))
+            }),
+            ("action", |f, e| {
+                f.actions
+                    .as_ref()
+                    .is_none_or(|a| a.iter().any(|a| a.matches(&e.action)))
+            }),
+            ("name glob", Self::matches_name),
+            ("path glob", Self::matches_path),
+            ("mount", Self::matches_mount),
+            ("fstype", Self::matches_fs_type),
+            ("type", Self::matches_type),
+            ("locality", Self::matches_locality),
+            ("latency", Self::matches_latency),
+            ("xattr namespace", Self::matches_xattr),
+            ("tag", Self::matches_tags),
+            ("service", Self::matches_service),
+            ("severity", Self::matches_severity),
+            ("size", Self::matches_size),
+            ("age", Self::matches_age),
+        ];
+        checks
+            .into_iter()
+            .find(|(_, check)| !check(self, event))
+            .map(|(name, _)| name)
+    }
+
+    /// Check the event's file name against the name globs
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no name globs are set or one matches
+    fn matches_name(&self, event: &FileEvent) -> bool {
+        let Some(globs) = &self.name_globs else {
+            return true;
+        };
+        let name = event.file_path.rsplit('/').next().unwrap_or_default();
+        globs.matches(name)
+    }
+
+    /// Check the event's path against the path globs
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no path globs are set or one matches
+    fn matches_path(&self, event: &FileEvent) -> bool {
+        self.path_globs
+            .as_ref()
+            .is_none_or(|globs| globs.matches(&event.file_path))
+    }
+
+    /// Check the event's mount point against the mount filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no mount filter is set or the mount matches
+    fn matches_mount(&self, event: &FileEvent) -> bool {
+        let Some(mounts) = &self.mounts else {
+            return true;
+        };
+        let Some(mount_point) = &event.mount_point else {
+            return false; // Unknown mount can't match an explicit filter
+        };
+        mounts
+            .iter()
+            .any(|m| normalize_mount_point(m) == mount_point)
+    }
+
+    /// Check the event's filesystem type against the fstype filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no fstype filter is set or the type matches
+    fn matches_fs_type(&self, event: &FileEvent) -> bool {
+        let Some(fs_types) = &self.fs_types else {
+            return true;
+        };
+        let Some(fs_type) = &event.fs_type else {
+            return false;
+        };
+        fs_types.iter().any(|t| t.eq_ignore_ascii_case(fs_type))
+    }
+
+    /// Check the event's filesystem locality against the remote filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no locality filter is set or it matches
+    fn matches_locality(&self, event: &FileEvent) -> bool {
+        match (self.remote, event.is_remote()) {
+            (None, _) => true,
+            (Some(wanted), Some(actual)) => wanted == actual,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check the event's open latency against the minimum latency filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no latency filter is set or the open was at
+    ///   least as slow as the minimum
+    fn matches_latency(&self, event: &FileEvent) -> bool {
+        match (self.min_latency_ns, event.open_latency_ns) {
+            (None, _) => true,
+            (Some(min), Some(actual)) => actual >= min,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check an extended attribute event against the namespace allowlist
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `bool` - True for non-xattr events, when no allowlist is set, or
+    ///   when the attribute's namespace is allowed
+    fn matches_xattr(&self, event: &FileEvent) -> bool {
+        let (Some(namespaces), Some(name)) =
+            (&self.xattr_namespaces, &event.xattr_name)
+        else {
+            return true;
+        };
+        let namespace = name.split('.').next().unwrap_or_default();
+        namespaces.iter().any(|n| n == namespace)
+    }
+
+    /// Check the event's enrichment tags against the tag filter
+    ///
+    /// # Arguments
+    /// * `event` - Annotated and enriched file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no tag filter is set or every tag matches
+    fn matches_tags(&self, event: &FileEvent) -> bool {
+        let Some(tags) = &self.tags else {
+            return true;
+        };
+        tags.iter()
+            .all(|(key, wanted)| match (event.tags.get(key), wanted) {
+                (Some(actual), Some(wanted)) => actual == wanted,
+                (Some(_), None) => true,
+                (None, _) => false,
+            })
+    }
+
+    /// Check the event's systemd service against the service filter
+    ///
+    /// # Arguments
+    /// * `event` - Enriched file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no services are set or the process runs in one
+    fn matches_service(&self, event: &FileEvent) -> bool {
+        self.services.as_ref().is_none_or(|services| {
+            event
+                .tags
+                .get("service")
+                .is_some_and(|service| services.contains(service))
+        })
+    }
+
+    /// Check the event's severity against the minimum severity filter
+    ///
+    /// # Arguments
+    /// * `event` - Classified file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no minimum is set or the event is at least as
+    ///   severe
+    fn matches_severity(&self, event: &FileEvent) -> bool {
+        match (self.min_severity, event.severity) {
+            (None, _) => true,
+            (Some(min), Some(actual)) => actual >= min,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check the type of the event's target against the type filter
+    ///
+    /// # Arguments
+    /// * `event` - Decoded file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no types are set or the target is one of them
+    fn matches_type(&self, event: &FileEvent) -> bool {
+        match &self.types {
+            None => true,
+            Some(types) => {
+                event.file_type.is_some_and(|kind| types.contains(&kind))
+            }
+        }
+    }
+
+    /// Check the size of the opened file against the size bounds
+    ///
+    /// # Arguments
+    /// * `event` - Enriched file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no bounds are set or the size is within them
+    fn matches_size(&self, event: &FileEvent) -> bool {
+        if self.min_size.is_none() && self.max_size.is_none() {
+            return true;
+        }
+        event.file_size.is_some_and(|size| {
+            self.min_size.is_none_or(|min| size >= min)
+                && self.max_size.is_none_or(|max| size <= max)
+        })
+    }
+
+    /// Check the age of the opened file against the newer-than filter
+    ///
+    /// # Arguments
+    /// * `event` - Enriched file event
+    ///
+    /// # Returns
+    /// * `bool` - True if no filter is set or the file was modified
+    ///   recently enough
+    fn matches_age(&self, event: &FileEvent) -> bool {
+        match (self.newer_than, event.file_age()) {
+            (None, _) => true,
+            (Some(max), Some(age)) => age < max,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check whether any criterion needs opened files statted
+    ///
+    /// # Returns
+    /// * `bool` - True if a size or age filter is set
+    pub fn needs_file_stats(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    /// Build an event annotated with the given mount and filesystem type
+    fn annotated_event(path: &str, mount: &str, fs_type: &str) -> FileEvent {
+        let mut event = FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        event.mount_point = Some(mount.to_string());
+        event.fs_type = Some(fs_type.to_string());
+        event
+    }
+
+    #[test]
+    fn test_default_filter_matches_everything() {
+        let event = annotated_event("/tmp/a.txt", "/tmp", "tmpfs");
+        assert!(FilterSpec::default().matches(&event));
+    }
+
+    #[test]
+    fn test_glob_filters() {
+        let filter = FilterSpec {
+            name_globs: GlobSet::names(&["*.tar.gz".to_string()]).ok(),
+            path_globs: GlobSet::paths(&["/srv/**".to_string()]).ok(),
+            ..Default::default()
+        };
+        let event = |path| annotated_event(path, "/", "ext4");
+        assert!(filter.matches(&event("/srv/backups/db.tar.gz")));
+        assert_eq!(
+            filter.rejection(&event("/srv/backups/db.gz")),
+            Some("name glob")
+        );
+        assert_eq!(
+            filter.rejection(&event("/tmp/db.tar.gz")),
+            Some("path glob")
+        );
+    }
+
+    #[test]
+    fn test_mount_filter() {
+        let filter = FilterSpec {
+            mounts: Some(vec!["/data/".to_string()]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&annotated_event("/data/a", "/data", "xfs")));
+        assert!(!filter.matches(&annotated_event("/tmp/a", "/tmp", "tmpfs")));
+    }
+
+    #[test]
+    fn test_fs_type_filter() {
+        let filter = FilterSpec {
+            fs_types: Some(vec!["ext4".to_string(), "XFS".to_string()]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&annotated_event("/data/a", "/data", "xfs")));
+        assert!(!filter.matches(&annotated_event("/tmp/a", "/tmp", "tmpfs")));
+        assert_eq!(
+            filter.rejection(&annotated_event("/tmp/a", "/tmp", "tmpfs")),
+            Some("fstype")
+        );
+    }
+
+    #[test]
+    fn test_unannotated_event_fails_fs_filters() {
+        let event = FileEvent::new(
+            "relative.txt".to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        let filter = FilterSpec {
+            fs_types: Some(vec!["ext4".to_string()]),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn test_locality_filter() {
+        let mut remote = annotated_event("/nfs/a", "/nfs", "nfs4");
+        remote.remote_source = Some("nas:/export".to_string());
+        let local = annotated_event("/data/a", "/data", "xfs");
+
+        let remote_only = FilterSpec {
+            remote: Some(true),
+            ..Default::default()
+        };
+        let local_only = FilterSpec {
+            remote: Some(false),
+            ..Default::default()
+        };
+        assert!(remote_only.matches(&remote));
+        assert!(!remote_only.matches(&local));
+        assert!(local_only.matches(&local));
+        assert!(!local_only.matches(&remote));
+    }
+
+    #[test]
+    fn test_latency_filter() {
+        let event = annotated_event("/nfs/a", "/nfs", "nfs4");
+        let filter = FilterSpec {
+            min_latency_ns: Some(1_000_000),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&event));
+        assert!(filter.matches(&event.clone().with_open_latency_ns(5_000_000)));
+        assert!(!filter.matches(&event.with_open_latency_ns(999_999)));
+    }
+
+    #[test]
+    fn test_xattr_namespace_filter() {
+        let filter = FilterSpec {
+            xattr_namespaces: Some(vec!["security".to_string()]),
+            ..Default::default()
+        };
+        let mut event = annotated_event("/bin/ls", "/", "ext4");
+        assert!(filter.matches(&event));
+
+        event.xattr_name = Some("security.selinux".to_string());
+        assert!(filter.matches(&event));
+        event.xattr_name = Some("user.mime_type".to_string());
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let filter = FilterSpec {
+            tags: Some(vec![
+                ("team".to_string(), Some("db".to_string())),
+                ("class".to_string(), None),
+            ]),
+            ..Default::default()
+        };
+        let mut event = annotated_event("/srv/db/a", "/", "ext4");
+        event.tags.insert("team".to_string(), "db".to_string());
+        assert!(!filter.matches(&event));
+
+        event.tags.insert("class".to_string(), "pii".to_string());
+        assert!(filter.matches(&event));
+        event.tags.insert("team".to_string(), "web".to_string());
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn test_severity_filter() {
+        let filter = FilterSpec {
+            min_severity: Some(Severity::Warning),
+            ..Default::default()
+        };
+        let mut event = annotated_event("/etc/shadow", "/", "ext4");
+        assert!(!filter.matches(&event));
+
+        event.severity = Some(Severity::Notice);
+        assert!(!filter.matches(&event));
+        event.severity = Some(Severity::Critical);
+        assert!(filter.matches(&event));
+    }
+
+    #[test]
+    fn test_size_and_age_filters() {
+        let filter = FilterSpec {
+            min_size: Some(1 << 30),
+            newer_than: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        assert!(filter.needs_file_stats());
+        let mut event = annotated_event("/data/dump", "/", "ext4");
+        assert_eq!(filter.rejection(&event), Some("size"));
+
+        event.file_size = Some(2 << 30);
+        event.file_modified =
+            Some(event.timestamp - chrono::Duration::seconds(3));
+        assert!(filter.matches(&event));
+        event.file_modified =
+            Some(event.timestamp - chrono::Duration::seconds(30));
+        assert_eq!(filter.rejection(&event), Some("age"));
+
+        let small = FilterSpec {
+            max_size: Some(4096),
+            ..Default::default()
+        };
+        assert!(!small.matches(&event));
+        event.file_size = Some(4096);
+        assert!(small.matches(&event));
+    }
+
+    #[test]
+    fn test_pseudo_fs_filter() {
+        let filter = FilterSpec {
+            exclude_pseudo_fs: true,
+            ..Default::default()
+        };
+        let proc = annotated_event("/proc/1/status", "/proc", "proc");
+        assert_eq!(filter.rejection(&proc), Some("pseudo fs"));
+        assert!(!filter.matches(&annotated_event(
+            "/dev/null",
+            "/dev",
+            "tmpfs"
+        )));
+        assert!(filter.matches(&annotated_event("/devel/a.c", "/", "ext4")));
+        assert!(FilterSpec::default().matches(&proc));
+    }
+
+    #[test]
+    fn test_exclusion_filter() {
+        let exclusions =
+            Exclusions::parse("process app\npath /var/cache\n").unwrap();
+        let filter = FilterSpec {
+            exclusions: Some(Arc::new(exclusions)),
+            ..Default::default()
+        };
+        let event = annotated_event("/srv/a.txt", "/", "ext4");
+        assert_eq!(filter.rejection(&event), Some("exclusion"));
+        let mut other = event.clone();
+        other.program_name = "cp".to_string();
+        assert!(filter.matches(&other));
+        other.file_path = "/var/cache/x".to_string();
+        assert!(!filter.matches(&other));
+    }
+
+    #[test]
+    fn test_type_filter() {
+        let filter = FilterSpec {
+            types: Some(vec![FileType::File, FileType::Dir]),
+            ..Default::default()
+        };
+        let mut event = annotated_event("/dev/sda", "/dev", "devtmpfs");
+        assert_eq!(filter.rejection(&event), Some("type"));
+        event.file_type = Some(FileType::Device);
+        assert!(!filter.matches(&event));
+        event.file_type = Some(FileType::Dir);
+        assert!(filter.matches(&event));
+    }
+
+    #[test]
+    fn test_process_and_action_filters() {
+        let filter = FilterSpec {
+            pids: Some(vec![1, 42]),
+            actions: Some(vec![ActionMatch::Open, ActionMatch::Link]),
+            services: Some(vec!["nginx".to_string()]),
+            ..Default::default()
+        };
+        let mut event = annotated_event("/srv/a", "/", "ext4");
+        assert_eq!(filter.rejection(&event), Some("service"));
+        event
+            .tags
+            .insert("service".to_string(), "nginx".to_string());
+        assert!(filter.matches(&event));
+        event.pid = 7;
+        assert_eq!(filter.rejection(&event), Some("pid"));
+        event.pid = 42;
+        event.action = FileAction::Closed;
+        assert_eq!(filter.rejection(&event), Some("action"));
+    }
+
+    #[test]
+    fn test_combined_filters() {
+        let filter = FilterSpec {
+            extensions: Some(vec!["rs".to_string()]),
+            fs_types: Some(vec!["ext4".to_string()]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&annotated_event("/src/a.rs", "/", "ext4")));
+        assert!(!filter.matches(&annotated_event("/src/a.py", "/", "ext4")));
+        assert!(!filter.matches(&annotated_event(
+            "/tmp/a.rs",
+            "/tmp",
+            "tmpfs"
+        )));
+    }
+}
@@ -0,0 +1,508 @@
+//! Filter builder module
+//!
+//! Typed construction of the filters `fw collect` builds from its command
+//! line, for programs using fw as a library. A `FilterBuilder` compiles
+//! into a `CompiledFilter`, holding the userspace matcher the collector
+//! applies to every event and the plan of entries the probes' filter maps
+//! are populated with, so embedders get the CLI's filtering without
+//! producing and parsing its strings.
+
+use anyhow::{anyhow, Result};
+use fw_common::MAX_COMM_LEN;
+use regex::Regex;
+
+use crate::enrich::EnricherKind;
+use crate::file_event::{FileEvent, FileType};
+use crate::filter::FilterSpec;
+use crate::glob::GlobSet;
+use crate::probes::{self, FeatureSet, ProbeFeature};
+use crate::process_list::{ProcessList, ProcessListMode};
+use crate::user_filter::UserFilter;
+use crate::wait_for::ActionMatch;
+
+/// Filter criteria to compile; unset criteria match every event
+#[derive(Debug, Clone)]
+pub struct FilterBuilder {
+    /// Criteria that need no compiling
+    spec: FilterSpec,
+    /// Globs the file name must match
+    name_globs: Vec<String>,
+    /// Globs the whole path must match
+    path_globs: Vec<String>,
+    /// Uids to report exclusively
+    uids: Vec<u32>,
+    /// Uids never to report
+    excluded_uids: Vec<u32>,
+    /// Whether listed processes are dropped or the only ones reported
+    comm_mode: ProcessListMode,
+    /// Exact process names
+    comms: Vec<String>,
+    /// Regexes matched against the whole process name
+    comm_patterns: Vec<Regex>,
+}
+
+impl Default for FilterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Add an item to an optional criterion, setting it if unset
+fn push<T>(criterion: &mut Option<Vec<T>>, item: T) {
+    criterion.get_or_insert_with(Vec::new).push(item);
+}
+
+impl FilterBuilder {
+    /// Start with the `fw collect` defaults: every event except those on
+    /// files under /proc, /sys and /dev
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Builder without criteria
+    pub fn new() -> Self {
+        Self {
+            spec: FilterSpec {
+                exclude_pseudo_fs: true,
+                ..Default::default()
+            },
+            name_globs: Vec::new(),
+            path_globs: Vec::new(),
+            uids: Vec::new(),
+            excluded_uids: Vec::new(),
+            comm_mode: ProcessListMode::default(),
+            comms: Vec::new(),
+            comm_patterns: Vec::new(),
+        }
+    }
+
+    /// Report files with an extension, like `--extensions`
+    ///
+    /// # Arguments
+    /// * `extension` - Extension without the leading dot
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        push(&mut self.spec.extensions, extension.into());
+        self
+    }
+
+    /// Report files whose name matches a glob, like `--name-glob`
+    ///
+    /// # Arguments
+    /// * `glob` - Glob such as "*.tar.gz"; may not contain "/"
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_name_glob(mut self, glob: impl Into<String>) -> Self {
+        self.name_globs.push(glob.into());
+        self
+    }
+
+    /// Report files whose path matches a glob, like `--path-glob`
+    ///
+    /// # Arguments
+    /// * `glob` - Glob such as "/srv/**"; must start with "/" or "*"
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_path_glob(mut self, glob: impl Into<String>) -> Self {
+        self.path_globs.push(glob.into());
+        self
+    }
+
+    /// Match extensions and globs exactly instead of ignoring case
+    ///
+    /// # Arguments
+    /// * `case_sensitive` - Whether case matters
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.spec.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Report files under /proc, /sys and /dev too, like
+    /// `--include-pseudo-fs`
+    ///
+    /// # Arguments
+    /// * `included` - Whether pseudo filesystems are reported
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_pseudo_fs(mut self, included: bool) -> Self {
+        self.spec.exclude_pseudo_fs = !included;
+        self
+    }
+
+    /// Report targets of a type, like `--type`
+    ///
+    /// # Arguments
+    /// * `file_type` - Regular file, directory, device...
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_type(mut self, file_type: FileType) -> Self {
+        push(&mut self.spec.types, file_type);
+        self
+    }
+
+    /// Report activity of a process
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        push(&mut self.spec.pids, pid);
+        self
+    }
+
+    /// Report activity of a user, like `--user`; once a user is added,
+    /// other users are left out in the kernel
+    ///
+    /// # Arguments
+    /// * `uid` - User ID
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Leave out activity of a user in the kernel, like `--exclude-user`
+    ///
+    /// # Arguments
+    /// * `uid` - User ID
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_excluded_uid(mut self, uid: u32) -> Self {
+        self.excluded_uids.push(uid);
+        self
+    }
+
+    /// List a process by its exact name, checked in the kernel
+    ///
+    /// # Arguments
+    /// * `comm` - Process name, at most 15 bytes
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_comm(mut self, comm: impl Into<String>) -> Self {
+        self.comms.push(comm.into());
+        self
+    }
+
+    /// List processes whose whole name matches a regex, checked in fw
+    ///
+    /// # Arguments
+    /// * `pattern` - Regex, anchored to the whole name when compiled
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_comm_pattern(mut self, pattern: Regex) -> Self {
+        self.comm_patterns.push(pattern);
+        self
+    }
+
+    /// Choose whether listed processes are dropped (the default) or the
+    /// only ones reported, like `--process-list-mode`
+    ///
+    /// # Arguments
+    /// * `mode` - Deny or allow
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_comm_mode(mut self, mode: ProcessListMode) -> Self {
+        self.comm_mode = mode;
+        self
+    }
+
+    /// Report a kind of action; the probes no action needs aren't
+    /// attached
+    ///
+    /// # Arguments
+    /// * `action` - Kind of action
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_action(mut self, action: ActionMatch) -> Self {
+        push(&mut self.spec.actions, action);
+        self
+    }
+
+    /// Report processes in the cgroup of a systemd service
+    ///
+    /// # Arguments
+    /// * `service` - Service name without ".service", e.g. "nginx"
+    ///
+    /// # Returns
+    /// * `FilterBuilder` - Updated builder
+    pub fn with_service(mut self, service: impl Into<String>) -> Self {
+        push(&mut self.spec.services, service.into());
+        self
+    }
+
+    /// Compile the criteria
+    ///
+    /// # Returns
+    /// * `Result<CompiledFilter>` - Filter, or error for a glob that can
+    ///   never match, a uid both included and excluded, or a process name
+    ///   or regex that can't be used
+    pub fn build(self) -> Result<CompiledFilter> {
+        let mut spec = self.spec;
+        let case_sensitive = spec.case_sensitive;
+        let globs =
+            |patterns: Vec<String>,
+             compile: fn(&[String]) -> Result<GlobSet, String>| {
+                (!patterns.is_empty())
+                    .then(|| compile(&patterns))
+                    .transpose()
+                    .map(|set| {
+                        set.map(|s| s.with_case_sensitive(case_sensitive))
+                    })
+                    .map_err(|e| anyhow!(e))
+            };
+        spec.name_globs = globs(self.name_globs, GlobSet::names)?;
+        spec.path_globs = globs(self.path_globs, GlobSet::paths)?;
+        let users = UserFilter::from_uids(&self.uids, &self.excluded_uids)?;
+        let processes =
+            if self.comms.is_empty() && self.comm_patterns.is_empty() {
+                ProcessList::default()
+            } else {
+                ProcessList::from_parts(
+                    self.comm_mode,
+                    self.comms,
+                    self.comm_patterns,
+                )?
+            };
+        Ok(CompiledFilter {
+            spec,
+            users,
+            processes,
+        })
+    }
+}
+
+/// Entries the probes' filter maps are populated with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelFilterPlan {
+    /// Uid and `UID_FILTER_*` entry for the UID_FILTER map
+    pub uid_entries: Vec<(u32, u8)>,
+    /// Value of UID_FILTER_ACTIVE: only included uids are reported
+    pub uid_includes: bool,
+    /// `COMM_FILTER_*` mode for COMM_FILTER_MODE
+    pub comm_mode: u32,
+    /// Null-padded process names for the COMM_FILTER map
+    pub comm_names: Vec<[u8; MAX_COMM_LEN]>,
+    /// Value of PSEUDO_FS_EXCLUDED
+    pub exclude_pseudo_fs: bool,
+    /// Probe features the requested actions need, with their
+    /// prerequisites
+    pub features: FeatureSet,
+}
+
+/// Compiled filter, for the kernel and for fw
+#[derive(Debug, Clone)]
+pub struct CompiledFilter {
+    /// Criteria checked in fw
+    spec: FilterSpec,
+    /// Users filtered in the kernel
+    users: UserFilter,
+    /// Processes filtered in the kernel and, for regexes, in fw
+    processes: ProcessList,
+}
+
+impl CompiledFilter {
+    /// Check whether an event passes every criterion
+    ///
+    /// # Arguments
+    /// * `event` - Annotated and enriched file event
+    ///
+    /// # Returns
+    /// * `bool` - True if the event should be reported
+    pub fn matches(&self, event: &FileEvent) -> bool {
+        self.rejection(event).is_none()
+    }
+
+    /// Find the first criterion an event fails, checking the kernel's
+    /// criteria first
+    ///
+    /// # Arguments
+    /// * `event` - Annotated and enriched file event
+    ///
+    /// # Returns
+    /// * `Option<&'static str>` - Name of the failed criterion, or None if
+    ///   the event should be reported
+    pub fn rejection(&self, event: &FileEvent) -> Option<&'static str> {
+        if event.uid.is_some_and(|uid| !self.users.allows(uid)) {
+            return Some("user");
+        }
+        if !self.processes.allows(&event.program_name) {
+            return Some("process");
+        }
+        self.spec.rejection(event)
+    }
+
+    /// Entries to populate the probes' filter maps with
+    ///
+    /// # Returns
+    /// * `KernelFilterPlan` - Map entries and the probe features to attach
+    pub fn kernel_plan(&self) -> KernelFilterPlan {
+        KernelFilterPlan {
+            uid_entries: self.users.entries().collect(),
+            uid_includes: self.users.has_includes(),
+            comm_mode: self.processes.kernel_mode(),
+            comm_names: self.processes.kernel_names().collect(),
+            exclude_pseudo_fs: self.spec.exclude_pseudo_fs,
+            features: action_features(self.spec.actions.as_deref()),
+        }
+    }
+
+    /// Built-in enrichers the criteria need to see their fields
+    ///
+    /// # Returns
+    /// * `Vec<EnricherKind>` - Enrichers to run before filtering
+    pub fn enrichers(&self) -> Vec<EnricherKind> {
+        let mut enrichers = Vec::new();
+        if self.spec.services.is_some() {
+            enrichers.push(EnricherKind::Service);
+        }
+        if self.spec.needs_file_stats() {
+            enrichers.push(EnricherKind::File);
+        }
+        enrichers
+    }
+
+    /// Split into the parts `CollectOptions` takes as `filter`, `users`
+    /// and `processes`
+    ///
+    /// # Returns
+    /// * `(FilterSpec, UserFilter, ProcessList)` - Userspace criteria,
+    ///   users and processes
+    pub fn into_parts(self) -> (FilterSpec, UserFilter, ProcessList) {
+        (self.spec, self.users, self.processes)
+    }
+}
+
+/// Probe features producing some kinds of action, with their
+/// prerequisites
+///
+/// # Arguments
+/// * `actions` - Kinds of action to report, or None for every kind
+///
+/// # Returns
+/// * `FeatureSet` - Features to attach; the defaults if unset
+pub fn action_features(actions: Option<&[ActionMatch]>) -> FeatureSet {
+    let Some(actions) = actions else {
+        return probes::default_features();
+    };
+    let features = actions.iter().flat_map(|&a| features(a)).collect();
+    probes::with_prerequisites(&features)
+}
+
+/// Probe features producing a kind of action
+fn features(action: ActionMatch) -> FeatureSet {
+    let features: &[ProbeFeature] = match action {
+        ActionMatch::Any => return probes::default_features(),
+        ActionMatch::Open => &[ProbeFeature::Opens],
+        ActionMatch::Close => &[ProbeFeature::Descriptors],
+        ActionMatch::Read | ActionMatch::Written => &[ProbeFeature::Io],
+        ActionMatch::Renamed => &[ProbeFeature::Links],
+        // Renames and atomic saves are traced by the link probes
+        ActionMatch::Write => &[
+            ProbeFeature::Metadata,
+            ProbeFeature::Xattrs,
+            ProbeFeature::Links,
+            ProbeFeature::Io,
+        ],
+        ActionMatch::Link => &[ProbeFeature::Links],
+        ActionMatch::Sync => &[ProbeFeature::Syncs],
+        ActionMatch::Lock => &[ProbeFeature::Locks],
+    };
+    features.iter().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use fw_common::{COMM_FILTER_DENY, UID_FILTER_INCLUDE};
+
+    #[test]
+    fn test_kernel_plan() {
+        let filter = FilterBuilder::new()
+            .with_uid(1000)
+            .with_comm("chrome")
+            .with_comm_pattern(Regex::new("kworker/.*").unwrap())
+            .with_action(ActionMatch::Sync)
+            .build()
+            .unwrap();
+        let plan = filter.kernel_plan();
+        assert_eq!(plan.uid_entries, [(1000, UID_FILTER_INCLUDE)]);
+        assert!(plan.uid_includes);
+        assert_eq!(plan.comm_mode, COMM_FILTER_DENY);
+        assert_eq!(&plan.comm_names[0][..7], b"chrome\0");
+        assert!(plan.exclude_pseudo_fs);
+        assert_eq!(
+            plan.features,
+            [
+                ProbeFeature::Opens,
+                ProbeFeature::Descriptors,
+                ProbeFeature::Syncs
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            FilterBuilder::new().build().unwrap().kernel_plan().features,
+            probes::default_features()
+        );
+    }
+
+    #[test]
+    fn test_matcher() {
+        let filter = FilterBuilder::new()
+            .with_path_glob("/srv/**")
+            .with_name_glob("*.CONF")
+            .with_excluded_uid(0)
+            .with_comm_pattern(Regex::new("kworker/.*").unwrap())
+            .with_action(ActionMatch::Open)
+            .build()
+            .unwrap();
+        let event = |path: &str, program: &str, uid: u32| {
+            FileEvent::new(
+                path.to_string(),
+                program.to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            )
+            .with_ids(uid, uid)
+        };
+        assert!(filter.matches(&event("/srv/app/db.conf", "app", 1000)));
+        assert_eq!(
+            filter.rejection(&event("/srv/app/db.conf", "app", 0)),
+            Some("user")
+        );
+        assert_eq!(
+            filter.rejection(&event("/srv/db.conf", "kworker/0:1", 1000)),
+            Some("process")
+        );
+        assert_eq!(
+            filter.rejection(&event("/proc/1/db.conf", "app", 1000)),
+            Some("pseudo fs")
+        );
+        assert!(filter.enrichers().is_empty());
+
+        assert!(FilterBuilder::new().with_path_glob("srv").build().is_err());
+        assert!(FilterBuilder::new()
+            .with_comm("a-very-long-process")
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Forensic module
+//!
+//! Keeps `fw collect --forensic` from adding to the activity it records
+//! during an incident. Nothing fw does in this mode writes to disk: output
+//! files, spools, exports, pins and log files are refused along with the
+//! sinks that open sockets or run commands. `/proc` is only read for the
+//! name and working directory of each process, once; the open-file
+//! snapshot, the enrichers reading `/proc` or statting files and stack
+//! symbolization are left out, so events carry what the kernel reported.
+//!
+//! Output is held in a [`MemoryBuffer`] of at most `--forensic-buffer`
+//! bytes, dropping the oldest lines once full, and written to the output
+//! stream on SIGUSR2 and when collection stops. Each export empties the
+//! buffer, so the exports together are the whole output. A line longer
+//! than the buffer is dropped whole, and the binary formats are refused
+//! since dropping lines would cut their frames.
+
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use crate::collector::OutputStream;
+
+/// Default limit on the output held in memory
+pub const DEFAULT_FORENSIC_BUFFER_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Output held since the last export
+#[derive(Debug, Default)]
+struct Held {
+    /// Complete lines, oldest first
+    lines: VecDeque<Vec<u8>>,
+    /// Start of a line not ended yet
+    partial: Vec<u8>,
+    /// Whether the line not ended yet outgrew the limit and is being
+    /// discarded up to its end
+    discarding: bool,
+    /// Total size of the lines and the partial line
+    bytes: usize,
+    /// Lines dropped to stay within the limit
+    dropped: u64,
+}
+
+impl Held {
+    /// Append output, dropping the oldest lines beyond the limit
+    ///
+    /// A line longer than the limit on its own is dropped as it is
+    /// written rather than held until it ends.
+    ///
+    /// # Arguments
+    /// * `buf` - Output as written by the sink
+    /// * `max_bytes` - Most bytes to hold
+    fn push(&mut self, buf: &[u8], max_bytes: usize) {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let end = rest.iter().position(|&b| b == b'\n');
+            let (piece, ended) = match end {
+                Some(end) => (&rest[..=end], true),
+                None => (rest, false),
+            };
+            rest = &rest[piece.len()..];
+            if !self.discarding {
+                self.partial.extend_from_slice(piece);
+                self.bytes += piece.len();
+                if self.partial.len() > max_bytes {
+                    self.bytes -= self.partial.len();
+                    self.partial = Vec::new();
+                    self.discarding = true;
+                }
+            }
+            if ended {
+                if self.discarding {
+                    self.discarding = false;
+                    self.dropped += 1;
+                } else {
+                    self.lines.push_back(std::mem::take(&mut self.partial));
+                }
+            }
+        }
+        while self.bytes > max_bytes {
+            let Some(line) = self.lines.pop_front() else {
+                break;
+            };
+            self.bytes -= line.len();
+            self.dropped += 1;
+        }
+    }
+}
+
+/// Output kept in memory until it is exported
+///
+/// Clones share the same buffer, so the sink can write to one while the
+/// exporter empties another.
+#[derive(Debug, Clone)]
+pub struct MemoryBuffer {
+    /// Output held since the last export
+    held: Arc<Mutex<Held>>,
+    /// Most bytes to hold
+    max_bytes: usize,
+}
+
+impl MemoryBuffer {
+    /// Create an empty buffer
+    ///
+    /// # Arguments
+    /// * `max_bytes` - Most bytes to hold before the oldest lines are
+    ///   dropped
+    ///
+    /// # Returns
+    /// * `MemoryBuffer` - Buffer holding nothing
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            held: Arc::default(),
+            max_bytes: usize::try_from(max_bytes).unwrap_or(usize::MAX),
+        }
+    }
+
+    /// Number of bytes held
+    ///
+    /// # Returns
+    /// * `usize` - Size of the output not exported yet
+    pub fn held_bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    /// Write everything held to a stream and empty the buffer
+    ///
+    /// # Arguments
+    /// * `out` - Stream to export to
+    ///
+    /// # Returns
+    /// * `io::Result<usize>` - Bytes exported, or error if the stream
+    ///   failed, in which case the output is kept
+    pub fn export(&self, out: &mut dyn Write) -> io::Result<usize> {
+        let mut held = self.lock();
+        if held.dropped > 0 {
+            warn!(
+                "Dropped the oldest {} lines to stay within \
+                 --forensic-buffer",
+                held.dropped
+            );
+        }
+        for line in &held.lines {
+            out.write_all(line)?;
+        }
+        out.write_all(&held.partial)?;
+        out.flush()?;
+        let exported = std::mem::take(&mut *held);
+        // The rest of a line being discarded is still discarded
+        held.discarding = exported.discarding;
+        Ok(exported.bytes)
+    }
+
+    /// Lock the held output, even if a holder panicked
+    fn lock(&self) -> MutexGuard<'_, Held> {
+        self.held.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Write for MemoryBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().push(buf, self.max_bytes);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Exports a buffer whenever SIGUSR2 arrives
+pub struct Exporter {
+    /// Buffer to export
+    buffer: MemoryBuffer,
+    /// Stream the buffer is exported to
+    stream: OutputStream,
+    /// SIGUSR2 deliveries
+    usr2: Signal,
+}
+
+impl Exporter {
+    /// Listen for SIGUSR2
+    ///
+    /// # Arguments
+    /// * `buffer` - Buffer to export
+    /// * `stream` - Stream to export it to
+    ///
+    /// # Returns
+    /// * `Result<Exporter>` - Exporter, or error if the signal handler
+    ///   can't be installed
+    pub fn new(buffer: MemoryBuffer, stream: OutputStream) -> Result<Self> {
+        Ok(Self {
+            buffer,
+            stream,
+            usr2: signal(SignalKind::user_defined2())?,
+        })
+    }
+}
+
+/// Export the buffer on every SIGUSR2 until aborted
+///
+/// # Arguments
+/// * `exporter` - Buffer, stream and signal to wait for
+pub async fn supervise(exporter: Exporter) {
+    let Exporter {
+        buffer,
+        stream,
+        mut usr2,
+    } = exporter;
+    while usr2.recv().await.is_some() {
+        match buffer.export(&mut stream.writer()) {
+            Ok(bytes) => info!("Exported {} bytes of output", bytes),
+            Err(e) => warn!("Failed to export the output: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_buffer() {
+        let mut buffer = MemoryBuffer::new(12);
+        buffer.write_all(b"one\ntwo\nthr").unwrap();
+        assert_eq!(buffer.held_bytes(), 11);
+        // "one\n" goes to make room; the partial line is kept whole
+        buffer.write_all(b"ee\n").unwrap();
+        let mut out = Vec::new();
+        assert_eq!(buffer.export(&mut out).unwrap(), 10);
+        assert_eq!(out, b"two\nthree\n");
+        assert_eq!(buffer.held_bytes(), 0);
+
+        // Exports concatenate to the whole output, even mid-line
+        buffer.write_all(b"fo").unwrap();
+        buffer.clone().export(&mut out).unwrap();
+        buffer.write_all(b"ur\n").unwrap();
+        buffer.export(&mut out).unwrap();
+        assert_eq!(out, b"two\nthree\nfour\n");
+
+        // A line longer than the whole buffer is dropped as it arrives
+        buffer.write_all(b"this line").unwrap();
+        assert_eq!(buffer.held_bytes(), 9);
+        buffer.write_all(b" is too long").unwrap();
+        assert_eq!(buffer.held_bytes(), 0);
+        buffer.write_all(b" to hold\nfive\n").unwrap();
+        out.clear();
+        buffer.export(&mut out).unwrap();
+        assert_eq!(out, b"five\n");
+    }
+}
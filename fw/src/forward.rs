@@ -0,0 +1,467 @@
+//! Forward module
+//!
+//! Sending side of multi-host collection. `fw forward` runs a normal
+//! collection on a server and streams every reported event over TLS to a
+//! central `fw collect-remote`: a hello line naming the host, then one
+//! JSON line per event. A dropped connection is re-opened on a later
+//! event; with --spool-dir, lines written while it is down are spooled
+//! and delivered once it is back, otherwise they are counted and lost.
+//! The central instance doesn't acknowledge lines, so those the kernel
+//! had accepted when a connection broke are lost too, spool or not.
+//! Connecting, the TLS handshake and every write give up after
+//! ten seconds so a stalled central instance can't block collection.
+//! The TLS connection needs the `remote` feature.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+#[cfg(feature = "remote")]
+use log::info;
+#[cfg(feature = "remote")]
+use rustls::crypto::CryptoProvider;
+#[cfg(feature = "remote")]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+#[cfg(feature = "remote")]
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+#[cfg(feature = "remote")]
+use std::net::ToSocketAddrs;
+#[cfg(feature = "remote")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "remote")]
+use std::{fs::File, io::BufReader, net::TcpStream, sync::Arc};
+
+/// Port `fw collect-remote` listens on unless told otherwise
+pub const DEFAULT_FORWARD_PORT: u16 = 7443;
+
+/// Version of the line protocol, sent in the hello line
+pub const FORWARD_PROTOCOL_VERSION: u32 = 1;
+
+/// Minimum time between attempts to reach the central instance
+#[cfg(feature = "remote")]
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest wait for a connection, the TLS handshake or a write
+#[cfg(feature = "remote")]
+pub const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Blocking TLS connection to the central instance
+#[cfg(feature = "remote")]
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// First line sent on every connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Line protocol version (`FORWARD_PROTOCOL_VERSION`)
+    pub version: u32,
+    /// Host the events come from, which they are tagged with centrally
+    pub host: String,
+    /// Secret proving the forwarder may send as `host`, if the central
+    /// instance checks tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Where and how to forward events
+#[derive(Debug, Clone)]
+pub struct ForwardConfig {
+    /// Central instance as "host:port"
+    pub addr: String,
+    /// Name the central instance's certificate must be valid for
+    pub server_name: String,
+    /// CA certificates the central instance's certificate must chain to
+    pub ca: PathBuf,
+    /// Client certificate and key, for central instances requiring one
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// Host name sent in the hello line
+    pub hostname: String,
+    /// Token sent in the hello line, if any
+    pub token: Option<String>,
+}
+
+impl ForwardConfig {
+    /// Build a configuration from command line values
+    ///
+    /// # Arguments
+    /// * `to` - Central instance as "host:port", or "host" for the
+    ///   default port
+    /// * `ca` - CA certificates (PEM) to verify the central instance with
+    /// * `client_cert` - Client certificate and key (PEM), if any
+    /// * `server_name` - Certificate name to expect; the host of `to` if
+    ///   unset
+    /// * `hostname` - Name to tag events with; this host's name if unset
+    ///
+    /// # Returns
+    /// * `Result<ForwardConfig>` - Configuration, or error if this host's
+    ///   name can't be read
+    pub fn new(
+        to: &str,
+        ca: PathBuf,
+        client_cert: Option<(PathBuf, PathBuf)>,
+        server_name: Option<String>,
+        hostname: Option<String>,
+    ) -> Result<Self> {
+        let (host, addr) = match to.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => {
+                (host, to.to_string())
+            }
+            _ => (to, format!("{}:{}", to, DEFAULT_FORWARD_PORT)),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let hostname = match hostname {
+            Some(name) => name,
+            None => nix::unistd::gethostname()
+                .context("Failed to read the host name")?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        Ok(Self {
+            addr,
+            server_name: server_name.unwrap_or_else(|| host.to_string()),
+            ca,
+            client_cert,
+            hostname,
+            token: None,
+        })
+    }
+}
+
+/// Read a forwarding token from the first line of a file
+///
+/// Keeping the token in a file keeps it out of `ps` output.
+///
+/// # Arguments
+/// * `path` - Token file
+///
+/// # Returns
+/// * `Result<String>` - Token, or error if the file can't be read or is
+///   empty
+pub fn read_token(path: &Path) -> Result<String> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let token = text.lines().next().unwrap_or_default().trim();
+    if token.is_empty() {
+        return Err(anyhow!("No token in {}", path.display()));
+    }
+    Ok(token.to_string())
+}
+
+/// Crypto provider used for both ends of the connection
+#[cfg(feature = "remote")]
+pub(crate) fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Read every certificate in a PEM file
+///
+/// # Arguments
+/// * `path` - PEM file
+///
+/// # Returns
+/// * `Result<Vec<CertificateDer>>` - Certificates, or error if the file
+///   can't be read or holds none
+#[cfg(feature = "remote")]
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Read the first private key in a PEM file
+///
+/// # Arguments
+/// * `path` - PEM file
+///
+/// # Returns
+/// * `Result<PrivateKeyDer>` - Key, or error if the file can't be read
+///   or holds none
+#[cfg(feature = "remote")]
+pub(crate) fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .ok_or_else(|| anyhow!("No private key in {}", path.display()))
+}
+
+/// Read CA certificates into a trust store
+///
+/// # Arguments
+/// * `path` - PEM file of CA certificates
+///
+/// # Returns
+/// * `Result<RootCertStore>` - Trust store, or error if a certificate
+///   can't be used
+#[cfg(feature = "remote")]
+pub(crate) fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+/// Connection to the central instance that reconnects as needed
+///
+/// Writes fail while the central instance can't be reached, which lets a
+/// spool in front of it hold the lines back.
+#[cfg(feature = "remote")]
+pub struct ForwardConnection {
+    /// Where to connect and the hello line to send
+    config: ForwardConfig,
+    /// TLS settings for new connections
+    tls: Arc<ClientConfig>,
+    /// Open connection, if any
+    stream: Option<TlsStream>,
+    /// When a connection was last attempted
+    last_attempt: Option<Instant>,
+}
+
+#[cfg(feature = "remote")]
+impl ForwardConnection {
+    /// Prepare a connection; nothing is sent until the first write
+    ///
+    /// # Arguments
+    /// * `config` - Central instance and TLS files
+    ///
+    /// # Returns
+    /// * `Result<ForwardConnection>` - Connection, or error if the
+    ///   certificates or key can't be loaded
+    pub fn new(config: ForwardConfig) -> Result<Self> {
+        let builder = ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?
+            .with_root_certificates(load_roots(&config.ca)?);
+        let tls = match &config.client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .context("Invalid client certificate")?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Self {
+            config,
+            tls: Arc::new(tls),
+            stream: None,
+            last_attempt: None,
+        })
+    }
+
+    /// Open a connection and send the hello line, unless one is open or
+    /// the last attempt was too recent
+    fn connect(&mut self) -> io::Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        if self
+            .last_attempt
+            .is_some_and(|last| last.elapsed() < RECONNECT_DELAY)
+        {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.last_attempt = Some(Instant::now());
+        match self.open() {
+            Ok(stream) => {
+                info!("Forwarding events to {}", self.config.addr);
+                self.stream = Some(stream);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Can't reach {}: {}", self.config.addr, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Connect, complete the TLS handshake and send the hello line
+    fn open(&self) -> io::Result<TlsStream> {
+        let server_name = ServerName::try_from(self.config.server_name.clone())
+            .map_err(io::Error::other)?;
+        let mut connection =
+            ClientConnection::new(self.tls.clone(), server_name)
+                .map_err(io::Error::other)?;
+        let mut tcp = connect_timeout(&self.config.addr, IO_TIMEOUT)?;
+        tcp.set_nodelay(true)?;
+        tcp.set_read_timeout(Some(IO_TIMEOUT))?;
+        tcp.set_write_timeout(Some(IO_TIMEOUT))?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp)?;
+        }
+        let mut stream = StreamOwned::new(connection, tcp);
+        let hello = Hello {
+            version: FORWARD_PROTOCOL_VERSION,
+            host: self.config.hostname.clone(),
+            token: self.config.token.clone(),
+        };
+        let hello = serde_json::to_string(&hello).map_err(io::Error::other)?;
+        writeln!(stream, "{}", hello)?;
+        stream.flush()?;
+        Ok(stream)
+    }
+
+    /// Run an operation on the connection, dropping it if it fails
+    fn with_stream<T>(
+        &mut self,
+        op: impl FnOnce(&mut TlsStream) -> io::Result<T>,
+    ) -> io::Result<T> {
+        self.connect()?;
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        let result = op(stream);
+        if let Err(e) = &result {
+            warn!("Lost connection to {}: {}", self.config.addr, e);
+            self.stream = None;
+        }
+        result
+    }
+}
+
+#[cfg(feature = "remote")]
+impl Write for ForwardConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_stream(|stream| stream.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_stream(|stream| stream.flush())
+    }
+}
+
+/// Connect to the first address of a host that answers in time
+///
+/// # Arguments
+/// * `addr` - HOST:PORT to resolve and connect to
+/// * `timeout` - Longest wait for each address
+///
+/// # Returns
+/// * `io::Result<TcpStream>` - Connected stream, or the error of the
+///   last address tried
+#[cfg(feature = "remote")]
+fn connect_timeout(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address to connect to")
+    }))
+}
+
+/// Stand-in for builds without the `remote` feature; never created
+#[cfg(not(feature = "remote"))]
+pub enum ForwardConnection {}
+
+#[cfg(not(feature = "remote"))]
+impl ForwardConnection {
+    /// Refuse to forward
+    ///
+    /// # Returns
+    /// * `Result<ForwardConnection>` - Always an error naming the feature
+    pub fn new(_: ForwardConfig) -> Result<Self> {
+        Err(crate::features::not_compiled_in(
+            "remote",
+            "Forwarding events",
+        ))
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+impl Write for ForwardConnection {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+/// Sink that sends each event as a JSON line
+pub struct ForwardSink<W> {
+    /// Connection to the central instance, possibly behind a spool
+    writer: W,
+    /// Events that could be neither sent nor spooled
+    dropped: u64,
+}
+
+impl<W: Write + Send + 'static> ForwardSink<W> {
+    /// Create a sink sending events to a writer
+    ///
+    /// # Arguments
+    /// * `writer` - Connection to the central instance
+    ///
+    /// # Returns
+    /// * `ForwardSink<W>` - New sink
+    pub fn new(writer: W) -> Self {
+        Self { writer, dropped: 0 }
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for ForwardSink<W> {
+    /// Send an event; one that can't be sent is counted, not an error,
+    /// so forwarding resumes once the central instance is back
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let mut line =
+            serde_json::to_string(event).context("Failed to encode event")?;
+        line.push('\n');
+        let sent = self
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|_| self.writer.flush());
+        if sent.is_err() {
+            self.dropped += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.dropped > 0 {
+            warn!("{} events could not be forwarded", self.dropped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_command_line() {
+        let ca = PathBuf::from("ca.pem");
+        let config = ForwardConfig::new(
+            "central.example",
+            ca.clone(),
+            None,
+            None,
+            Some("web1".to_string()),
+        )
+        .unwrap();
+        assert_eq!(config.addr, "central.example:7443");
+        assert_eq!(config.server_name, "central.example");
+        assert_eq!(config.hostname, "web1");
+
+        let config =
+            ForwardConfig::new("[::1]:9000", ca, None, None, None).unwrap();
+        assert_eq!(config.addr, "[::1]:9000");
+        assert_eq!(config.server_name, "::1");
+        assert!(!config.hostname.is_empty());
+    }
+}
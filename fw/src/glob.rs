@@ -0,0 +1,296 @@
+//! Glob module
+//!
+//! Shell-style globs used wherever paths are selected by pattern: the
+//! `--path` of `fw wait-for` and `fw ps`, and the `--name-glob` and
+//! `--path-glob` filters of `fw collect`, which express what extensions
+//! alone can't (e.g. `*.tar.gz` or `Makefile*`).
+
+pub use fw_common::event::eq_ignore_case;
+
+/// Piece of a compiled glob
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    /// Byte matched as is
+    Byte(u8),
+    /// `?`: one character other than `/`
+    One,
+    /// `*`: any bytes within one path component
+    Star,
+    /// `**`: any bytes across components; followed by `/`, it may also
+    /// match no directories at all
+    GlobStar,
+}
+
+/// Shell-style glob over absolute paths or file names
+///
+/// `*` matches within one path component, `**` matches across
+/// components and `?` matches a single character other than `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathGlob {
+    /// Pattern as given on the command line
+    pattern: String,
+    /// Pattern split into tokens, once
+    tokens: Vec<Token>,
+}
+
+impl PathGlob {
+    /// Create a glob from a pattern
+    ///
+    /// # Arguments
+    /// * `pattern` - Glob pattern, e.g. "/var/log/**/*.log"
+    ///
+    /// # Returns
+    /// * `PathGlob` - Compiled glob
+    pub fn new(pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        let mut tokens = Vec::with_capacity(pattern.len());
+        let mut bytes = pattern.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            tokens.push(match byte {
+                b'*' if bytes.next_if_eq(&b'*').is_some() => Token::GlobStar,
+                b'*' => Token::Star,
+                b'?' => Token::One,
+                byte => Token::Byte(byte),
+            });
+        }
+        Self { pattern, tokens }
+    }
+
+    /// Pattern as given
+    ///
+    /// # Returns
+    /// * `&str` - Glob pattern
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Check whether a path matches the glob
+    ///
+    /// Matching takes time proportional to the length of the pattern
+    /// times the length of the path, however many stars the pattern has.
+    ///
+    /// # Arguments
+    /// * `path` - Path to test
+    ///
+    /// # Returns
+    /// * `bool` - True if the whole path matches
+    pub fn matches(&self, path: &str) -> bool {
+        glob_match(&self.tokens, path.as_bytes())
+    }
+}
+
+/// Match compiled glob tokens against the whole of a path
+///
+/// Works backwards from the end of the pattern, keeping for each of the
+/// last two tokens which suffixes of the path the rest of the pattern
+/// matches, so no combination of pattern and path position is tried
+/// twice.
+///
+/// # Arguments
+/// * `tokens` - Compiled pattern
+/// * `path` - Path bytes
+///
+/// # Returns
+/// * `bool` - True if the pattern matches the path
+fn glob_match(tokens: &[Token], path: &[u8]) -> bool {
+    let len = path.len();
+    // after[i]: tokens past the current one match path[i..]; after_two
+    // is the same for the tokens past the next one
+    let mut after: Vec<bool> = (0..=len).map(|i| i == len).collect();
+    let mut after_two = vec![false; len + 1];
+    let mut current = vec![false; len + 1];
+    for (t, token) in tokens.iter().enumerate().rev() {
+        let slash_next = tokens.get(t + 1) == Some(&Token::Byte(b'/'));
+        for i in (0..=len).rev() {
+            let byte = path.get(i).copied();
+            current[i] = match token {
+                Token::Byte(c) => byte == Some(*c) && after[i + 1],
+                Token::One => match byte.and_then(utf8_width) {
+                    Some(width) => byte != Some(b'/') && after[i + width],
+                    None => false,
+                },
+                Token::Star => {
+                    after[i]
+                        || (byte.is_some_and(|b| b != b'/') && current[i + 1])
+                }
+                Token::GlobStar => {
+                    after[i]
+                        || (slash_next && after_two[i])
+                        || (byte.is_some() && current[i + 1])
+                }
+            };
+        }
+        std::mem::swap(&mut after_two, &mut after);
+        std::mem::swap(&mut after, &mut current);
+    }
+    after[0]
+}
+
+/// Length of the UTF-8 character starting with a byte
+///
+/// # Arguments
+/// * `lead` - First byte of the character
+///
+/// # Returns
+/// * `Option<usize>` - Number of bytes, or None if `lead` continues a
+///   character
+fn utf8_width(lead: u8) -> Option<usize> {
+    match lead {
+        0x00..=0x7f => Some(1),
+        0xc0..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf7 => Some(4),
+        _ => None,
+    }
+}
+
+/// Set of globs matched as one, compiled once from the command line
+///
+/// A set matches if any of its globs does. Sets match exactly unless
+/// made case-insensitive with [`GlobSet::with_case_sensitive`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobSet {
+    globs: Vec<PathGlob>,
+    /// Lowercase globs and text before matching
+    fold_case: bool,
+}
+
+impl GlobSet {
+    /// Compile globs matched against file names (the last component)
+    ///
+    /// # Arguments
+    /// * `patterns` - Globs such as "*.tar.gz" or "Makefile*"
+    ///
+    /// # Returns
+    /// * `Result<GlobSet, String>` - Set, or a usage error if a pattern
+    ///   contains "/"
+    pub fn names(patterns: &[String]) -> Result<Self, String> {
+        if let Some(bad) = patterns.iter().find(|p| p.contains('/')) {
+            return Err(format!(
+                "name glob '{}' contains '/'; use --path-glob",
+                bad
+            ));
+        }
+        Ok(Self::compile(patterns))
+    }
+
+    /// Compile globs matched against whole paths
+    ///
+    /// # Arguments
+    /// * `patterns` - Globs such as "/srv/**/*.conf"
+    ///
+    /// # Returns
+    /// * `Result<GlobSet, String>` - Set, or a usage error if a pattern
+    ///   could never match an absolute path
+    pub fn paths(patterns: &[String]) -> Result<Self, String> {
+        if let Some(bad) = patterns
+            .iter()
+            .find(|p| !p.starts_with('/') && !p.starts_with('*'))
+        {
+            return Err(format!("path glob '{}' must start with / or *", bad));
+        }
+        Ok(Self::compile(patterns))
+    }
+
+    fn compile(patterns: &[String]) -> Self {
+        Self {
+            globs: patterns.iter().map(PathGlob::new).collect(),
+            fold_case: false,
+        }
+    }
+
+    /// Choose whether matching respects case
+    ///
+    /// # Arguments
+    /// * `case_sensitive` - Match exactly if true, ignoring case if false
+    ///
+    /// # Returns
+    /// * `GlobSet` - The set with the chosen case handling
+    pub fn with_case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.fold_case = !case_sensitive;
+        if self.fold_case {
+            for glob in &mut self.globs {
+                *glob = PathGlob::new(glob.pattern.to_lowercase());
+            }
+        }
+        self
+    }
+
+    /// Check whether any glob matches
+    ///
+    /// # Arguments
+    /// * `text` - File name or path to test
+    ///
+    /// # Returns
+    /// * `bool` - True if at least one glob matches all of `text`
+    pub fn matches(&self, text: &str) -> bool {
+        if self.fold_case {
+            let text = text.to_lowercase();
+            return self.globs.iter().any(|glob| glob.matches(&text));
+        }
+        self.globs.iter().any(|glob| glob.matches(text))
+    }
+
+    /// Patterns in the set, as given
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = &str>` - Patterns in command line order
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.globs.iter().map(PathGlob::pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_glob() {
+        let glob = PathGlob::new("/var/log/*.log");
+        assert!(glob.matches("/var/log/syslog.log"));
+        assert!(!glob.matches("/var/log/nginx/access.log"));
+
+        let deep = PathGlob::new("/var/**/*.log");
+        assert!(deep.matches("/var/log/nginx/access.log"));
+        assert!(deep.matches("/var/app.log"));
+        assert!(PathGlob::new("/tmp/a?.txt").matches("/tmp/ab.txt"));
+        assert!(!PathGlob::new("/tmp/a?.txt").matches("/tmp/a/.txt"));
+        // One character, however many bytes it takes
+        assert!(PathGlob::new("caf?.txt").matches("café.txt"));
+        assert!(!PathGlob::new("caf??.txt").matches("café.txt"));
+        assert!(PathGlob::new("?").matches("🦀"));
+        assert!(PathGlob::new("**").matches("/any/path"));
+        assert!(PathGlob::new("/a/**").matches("/a/"));
+        assert!(!PathGlob::new("/a/*").matches("/a/b/c"));
+
+        // Stars don't backtrack their way into exponential time
+        let stars = format!("{}x", "/**".repeat(20));
+        let path = "/a".repeat(200);
+        let start = std::time::Instant::now();
+        assert!(!PathGlob::new(stars).matches(&path));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_glob_sets() {
+        let names =
+            GlobSet::names(&["*.tar.gz".to_string(), "Makefile*".to_string()])
+                .unwrap();
+        assert!(names.matches("backup.tar.gz"));
+        assert!(names.matches("Makefile.am"));
+        assert!(!names.matches("backup.gz"));
+        assert!(GlobSet::names(&["src/*.rs".to_string()]).is_err());
+
+        let paths = GlobSet::paths(&["/srv/**/*.conf".to_string()]).unwrap();
+        assert!(paths.matches("/srv/app/nginx.conf"));
+        assert!(!paths.matches("/etc/nginx.conf"));
+        assert!(GlobSet::paths(&["**/*.pem".to_string()]).is_ok());
+        assert!(GlobSet::paths(&["srv/*.conf".to_string()]).is_err());
+
+        let caseless = names.with_case_sensitive(false);
+        assert!(caseless.matches("BACKUP.TAR.GZ"));
+        assert!(caseless.matches("makefile"));
+        assert!(eq_ignore_case("ÉTÉ", "été"));
+        assert!(!eq_ignore_case("rs", "r"));
+    }
+}
@@ -0,0 +1,393 @@
+//! Health module
+//!
+//! Shared view of how a running collection is doing: whether probes are
+//! attached, when the event loop last showed it was alive, and how many
+//! events were received and dropped, and how much fidelity the overload
+//! controller has given up. The pipeline updates it as it runs;
+//! supervisors such as the systemd notifier and the `/healthz` and
+//! `/readyz` HTTP endpoints read snapshots of it.
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::overload::Degradation;
+
+/// How often an idle event loop records a heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client has to send its request line
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line read; longer requests are refused
+const MAX_REQUEST_LINE: u64 = 8192;
+
+/// Counters and liveness shared between the pipeline and its supervisors
+///
+/// Cloning is cheap and every clone refers to the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    inner: Arc<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    attached: AtomicBool,
+    events: AtomicU64,
+    dropped: AtomicU64,
+    last_beat: Mutex<Option<Instant>>,
+    enrichment_paused: AtomicBool,
+    /// Kernel sampling rate, with 0 standing for the default of 1
+    sample_rate: AtomicU32,
+}
+
+/// Point-in-time copy of [`Health`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthSnapshot {
+    /// Whether probes are currently attached
+    pub attached: bool,
+    /// Events received from the backend
+    pub events: u64,
+    /// Events lost before reaching a sink
+    pub dropped: u64,
+    /// Time since the event loop last recorded a heartbeat, if ever
+    pub since_beat: Option<Duration>,
+    /// Fidelity given up because of overload
+    pub degradation: Degradation,
+}
+
+impl Health {
+    /// Record whether probes are attached
+    ///
+    /// # Arguments
+    /// * `attached` - True once monitoring started, false once it stopped
+    pub fn set_attached(&self, attached: bool) {
+        self.inner.attached.store(attached, Ordering::Relaxed);
+    }
+
+    /// Record that the event loop is alive
+    pub fn beat(&self) {
+        let mut last_beat = self
+            .inner
+            .last_beat
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *last_beat = Some(Instant::now());
+    }
+
+    /// Count an event received from the backend
+    pub fn record_event(&self) {
+        self.inner.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count events lost before reaching a sink
+    ///
+    /// # Arguments
+    /// * `count` - Number of events lost
+    pub fn record_dropped(&self, count: u64) {
+        self.inner.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record how much fidelity is given up
+    ///
+    /// # Arguments
+    /// * `degradation` - Setting the pipeline and kernel should follow
+    pub fn set_degradation(&self, degradation: Degradation) {
+        self.inner
+            .enrichment_paused
+            .store(degradation.enrichment_paused, Ordering::Relaxed);
+        self.inner
+            .sample_rate
+            .store(degradation.sample_rate, Ordering::Relaxed);
+    }
+
+    /// Current fidelity setting
+    ///
+    /// # Returns
+    /// * `Degradation` - Setting last recorded, full fidelity if none
+    pub fn degradation(&self) -> Degradation {
+        Degradation {
+            enrichment_paused: self
+                .inner
+                .enrichment_paused
+                .load(Ordering::Relaxed),
+            sample_rate: self.inner.sample_rate.load(Ordering::Relaxed).max(1),
+        }
+    }
+
+    /// Copy the current state
+    ///
+    /// # Returns
+    /// * `HealthSnapshot` - Counters and liveness as of now
+    pub fn snapshot(&self) -> HealthSnapshot {
+        let last_beat = *self
+            .inner
+            .last_beat
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        HealthSnapshot {
+            attached: self.inner.attached.load(Ordering::Relaxed),
+            events: self.inner.events.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+            since_beat: last_beat.map(|at| at.elapsed()),
+            degradation: self.degradation(),
+        }
+    }
+}
+
+/// Settings for the health endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthServerConfig {
+    /// Address to serve `/healthz` and `/readyz` on
+    pub addr: SocketAddr,
+    /// Heartbeat age after which `/healthz` reports the loop stalled
+    pub live_after: Duration,
+    /// Heartbeat age after which `/readyz` reports not ready
+    pub ready_after: Duration,
+}
+
+/// Answer a health probe
+///
+/// `/healthz` fails only once the event loop's heartbeat is older than
+/// `live_after`, so a starting collector is not restarted. `/readyz`
+/// also needs probes to be attached and a heartbeat within
+/// `ready_after`.
+///
+/// # Arguments
+/// * `path` - Requested path
+/// * `health` - Current pipeline state
+/// * `config` - Staleness thresholds
+///
+/// # Returns
+/// * `(u16, String)` - HTTP status code and plain-text body
+pub fn probe(
+    path: &str,
+    health: &HealthSnapshot,
+    config: &HealthServerConfig,
+) -> (u16, String) {
+    let stale = |limit: Duration| {
+        health.since_beat.filter(|age| *age > limit).map(|age| {
+            format!("event loop stalled, last heartbeat {:?} ago\n", age)
+        })
+    };
+    let failure = match path {
+        "/healthz" => stale(config.live_after),
+        "/readyz" if health.since_beat.is_none() => {
+            Some("starting\n".to_string())
+        }
+        "/readyz" if !health.attached => {
+            Some("probes not attached\n".to_string())
+        }
+        "/readyz" => stale(config.ready_after),
+        _ => return (404, "not found\n".to_string()),
+    };
+    match failure {
+        Some(reason) => (503, reason),
+        None => (
+            200,
+            format!(
+                "ok: {} events, {} dropped\n",
+                health.events, health.dropped
+            ),
+        ),
+    }
+}
+
+/// Bind the health endpoint listener
+///
+/// # Arguments
+/// * `config` - Address to listen on
+///
+/// # Returns
+/// * `Result<TcpListener>` - Listener, or error if the address is taken
+pub async fn bind(config: &HealthServerConfig) -> Result<TcpListener> {
+    let listener = TcpListener::bind(config.addr).await.with_context(|| {
+        format!("Failed to listen for health checks on {}", config.addr)
+    })?;
+    info!("Serving /healthz and /readyz on {}", config.addr);
+    Ok(listener)
+}
+
+/// Answer health probes until cancelled
+///
+/// # Arguments
+/// * `listener` - Listener from [`bind`]
+/// * `health` - State updated by the pipeline
+/// * `config` - Staleness thresholds
+pub async fn serve(
+    listener: TcpListener,
+    health: Health,
+    config: HealthServerConfig,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!("Failed to accept health check: {}", e);
+                continue;
+            }
+        };
+        let health = health.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, &health, &config).await {
+                debug!("Health check failed: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Read one HTTP request and write the probe's response
+///
+/// Clients get [`REQUEST_TIMEOUT`] to send a request line of at most
+/// [`MAX_REQUEST_LINE`] bytes, so slow or endless requests can't pile up
+/// connections.
+async fn answer(
+    stream: TcpStream,
+    health: &Health,
+    config: &HealthServerConfig,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request = String::new();
+    let mut limited = (&mut stream).take(MAX_REQUEST_LINE);
+    tokio::time::timeout(REQUEST_TIMEOUT, limited.read_line(&mut request))
+        .await
+        .context("Timed out reading the request")??;
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        _ if !request.ends_with('\n') => {
+            (414, "request line too long\n".to_string())
+        }
+        (Some("GET"), Some(path)) => probe(path, &health.snapshot(), config),
+        _ => (405, "only GET is supported\n".to_string()),
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        414 => "URI Too Long",
+        _ => "Service Unavailable",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn config() -> HealthServerConfig {
+        HealthServerConfig {
+            addr: ([127, 0, 0, 1], 0).into(),
+            live_after: Duration::from_secs(10),
+            ready_after: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_clones_share_state() {
+        let health = Health::default();
+        assert_eq!(health.snapshot().since_beat, None);
+
+        let pipeline = health.clone();
+        pipeline.set_attached(true);
+        pipeline.record_event();
+        pipeline.record_event();
+        pipeline.record_dropped(5);
+        pipeline.beat();
+
+        let snapshot = health.snapshot();
+        assert!(snapshot.attached);
+        assert_eq!((snapshot.events, snapshot.dropped), (2, 5));
+        assert!(snapshot.since_beat.unwrap() < Duration::from_secs(5));
+        assert!(!snapshot.degradation.is_degraded());
+
+        let degraded = Degradation {
+            enrichment_paused: true,
+            sample_rate: 4,
+        };
+        pipeline.set_degradation(degraded);
+        assert_eq!(health.snapshot().degradation, degraded);
+    }
+
+    #[test]
+    fn test_probe_thresholds() {
+        let config = config();
+        let at = |attached, age: Option<u64>| HealthSnapshot {
+            attached,
+            events: 7,
+            dropped: 1,
+            since_beat: age.map(Duration::from_secs),
+            degradation: Degradation::default(),
+        };
+        let status = |path, health| probe(path, &health, &config).0;
+
+        // Starting up is alive but not ready
+        assert_eq!(status("/healthz", at(false, None)), 200);
+        assert_eq!(status("/readyz", at(false, None)), 503);
+        assert_eq!(
+            probe("/readyz", &at(true, Some(0)), &config),
+            (200, "ok: 7 events, 1 dropped\n".to_string())
+        );
+        // Detached between schedule windows
+        assert_eq!(status("/readyz", at(false, Some(0))), 503);
+        assert_eq!(status("/healthz", at(false, Some(0))), 200);
+        // Stale for readiness first, then for liveness
+        assert_eq!(status("/readyz", at(true, Some(6))), 503);
+        assert_eq!(status("/healthz", at(true, Some(6))), 200);
+        assert_eq!(status("/healthz", at(true, Some(11))), 503);
+        assert_eq!(status("/metrics", at(true, Some(0))), 404);
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_http() {
+        let listener = bind(&config()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let health = Health::default();
+        health.set_attached(true);
+        health.beat();
+        let server = tokio::spawn(serve(listener, health, config()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /readyz HTTP/1.1\r\nHost: fw\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        server.abort();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nok: 0 events, 0 dropped\n"));
+    }
+
+    #[tokio::test]
+    async fn test_answer_limits_the_request_line() {
+        let listener = bind(&config()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, Health::default(), config()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let long = vec![b'A'; MAX_REQUEST_LINE as usize + 1];
+        stream.write_all(&long).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        server.abort();
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+    }
+}
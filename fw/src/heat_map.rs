@@ -0,0 +1,208 @@
+//! Heat Map module
+//!
+//! Rolls events up the directory hierarchy (`fw collect --mode stats
+//! --group-by dir --depth N`). Every directory down to the given depth
+//! counts the events on files anywhere beneath it, so the hottest
+//! subtrees stand out however their activity is spread. The result is
+//! printed as a table of directories, busiest first, and can be exported
+//! as a JSON tree of `name`/`value`/`children` nodes, the hierarchy format
+//! read by flame graph and treemap tools.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::file_event::FileEvent;
+use crate::report::json_string;
+use crate::stats::{csv_row, ExportFormat};
+
+/// One directory and the subdirectories seen beneath it
+#[derive(Debug, Clone, Default)]
+struct DirNode {
+    /// Events on files anywhere beneath the directory
+    events: u64,
+    /// Subdirectories by name
+    children: BTreeMap<String, DirNode>,
+}
+
+impl DirNode {
+    /// Events on files in this directory rather than a subdirectory
+    fn own_events(&self) -> u64 {
+        let below: u64 = self.children.values().map(|c| c.events).sum();
+        self.events - below
+    }
+
+    /// Collect this directory and everything beneath it as table rows
+    fn rows(&self, path: &str, rows: &mut Vec<(String, u64)>) {
+        rows.push((path.to_string(), self.events));
+        for (name, child) in &self.children {
+            let child_path = match path {
+                "/" => format!("/{}", name),
+                _ => format!("{}/{}", path, name),
+            };
+            child.rows(&child_path, rows);
+        }
+    }
+
+    /// Render this directory and everything beneath it as a JSON node
+    fn to_json(&self, name: &str, path: &str) -> String {
+        let children: Vec<String> = self
+            .children
+            .iter()
+            .map(|(child, node)| {
+                let child_path = match path {
+                    "/" => format!("/{}", child),
+                    _ => format!("{}/{}", path, child),
+                };
+                node.to_json(child, &child_path)
+            })
+            .collect();
+        format!(
+            "{{\"name\":{},\"path\":{},\"value\":{},\"self\":{},\
+             \"children\":[{}]}}",
+            json_string(name),
+            json_string(path),
+            self.events,
+            self.own_events(),
+            children.join(",")
+        )
+    }
+}
+
+/// Event counts rolled up the directory tree
+#[derive(Debug, Clone)]
+pub struct DirHeatMap {
+    /// Directory levels kept below the root
+    depth: usize,
+    /// The root directory
+    root: DirNode,
+}
+
+impl DirHeatMap {
+    /// Create an empty heat map
+    ///
+    /// # Arguments
+    /// * `depth` - Directory levels kept below the root; events deeper
+    ///   down count toward their ancestor at this depth
+    ///
+    /// # Returns
+    /// * `DirHeatMap` - Heat map with no events
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            root: DirNode::default(),
+        }
+    }
+
+    /// Count one event in its directory and every ancestor
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    pub fn record(&mut self, event: &FileEvent) {
+        let dir = Path::new(&event.file_path)
+            .parent()
+            .and_then(Path::to_str)
+            .unwrap_or("/");
+        let mut node = &mut self.root;
+        node.events += 1;
+        for component in
+            dir.split('/').filter(|c| !c.is_empty()).take(self.depth)
+        {
+            node = node.children.entry(component.to_string()).or_default();
+            node.events += 1;
+        }
+    }
+
+    /// Directories with the events beneath them, busiest first
+    ///
+    /// # Returns
+    /// * `Vec<(String, u64)>` - Directory path and event count, starting
+    ///   with "/" for the total
+    pub fn rows(&self) -> Vec<(String, u64)> {
+        let mut rows = Vec::new();
+        if self.root.events > 0 {
+            self.root.rows("/", &mut rows);
+        }
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        rows
+    }
+
+    /// Render the heat map for export
+    ///
+    /// JSON is one tree rooted at "/". Each node's `value` counts the
+    /// events beneath it, as flame graphs expect; `self` leaves out those
+    /// counted in a child node, for treemaps that sum over the nodes.
+    ///
+    /// # Arguments
+    /// * `format` - Export format
+    ///
+    /// # Returns
+    /// * `String` - Rendered heat map
+    pub fn export(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Csv => {
+                let mut out = String::from("dir,events\n");
+                for (dir, count) in self.rows() {
+                    let _ = writeln!(out, "{},{}", csv_row(&[dir]), count);
+                }
+                out
+            }
+            ExportFormat::Json => format!("{}\n", self.root.to_json("/", "/")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn event(path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "cc".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_rolls_events_up_the_tree() {
+        let mut heat = DirHeatMap::new(2);
+        for path in [
+            "/home/a/src/x.c",
+            "/home/a/y.c",
+            "/home/b/z.c",
+            "/home/notes",
+            "/etc/passwd",
+        ] {
+            heat.record(&event(path));
+        }
+        assert_eq!(
+            heat.rows(),
+            vec![
+                ("/".to_string(), 5),
+                ("/home".to_string(), 4),
+                ("/home/a".to_string(), 2),
+                ("/etc".to_string(), 1),
+                ("/home/b".to_string(), 1),
+            ]
+        );
+        assert_eq!(
+            heat.export(ExportFormat::Json),
+            "{\"name\":\"/\",\"path\":\"/\",\"value\":5,\"self\":0,\
+             \"children\":[{\"name\":\"etc\",\"path\":\"/etc\",\"value\":1,\
+             \"self\":1,\"children\":[]},{\"name\":\"home\",\"path\":\
+             \"/home\",\"value\":4,\"self\":1,\"children\":[{\"name\":\"a\",\
+             \"path\":\"/home/a\",\"value\":2,\"self\":2,\"children\":[]},\
+             {\"name\":\"b\",\"path\":\"/home/b\",\"value\":1,\"self\":1,\
+             \"children\":[]}]}]}\n"
+        );
+        assert!(heat
+            .export(ExportFormat::Csv)
+            .starts_with("dir,events\n/,5\n"));
+        assert!(DirHeatMap::new(1).rows().is_empty());
+    }
+}
@@ -0,0 +1,385 @@
+//! Hot module
+//!
+//! Implements `fw hot`, a live ranking of the busiest files over a
+//! sliding window. Events and bytes moved per file are counted in
+//! count-min sketches of fixed size, one per slice of the window, so
+//! memory stays bounded however many distinct files are touched; the
+//! oldest slice is dropped as the window slides. A small set of candidate
+//! paths tracks which files are currently the heaviest hitters, and the
+//! ranking shows their estimated counts, which can only overstate.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::hash::{Hash, Hasher};
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+use tokio::signal;
+
+use crate::collector;
+use crate::ebpf_monitor::EbpfMonitor;
+use crate::file_event::{FileAction, FileEvent};
+use crate::ps::CLEAR_SCREEN;
+
+/// Number of files shown unless `--top` says otherwise
+pub const DEFAULT_TOP: usize = 10;
+
+/// Counters per sketch row
+pub const SKETCH_WIDTH: usize = 2048;
+
+/// Rows per sketch, each hashing paths independently
+pub const SKETCH_DEPTH: usize = 4;
+
+/// Slices the window is divided into; it slides one slice at a time
+pub const WINDOW_SLICES: usize = 6;
+
+/// Candidates kept per file shown, so files climbing the ranking are
+/// already being tracked when they reach it
+const CANDIDATES_PER_ROW: usize = 4;
+
+/// What files are ranked by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RankBy {
+    /// Number of events on the file
+    #[default]
+    Events,
+    /// Bytes read from and written to the file
+    Bytes,
+}
+
+/// Estimated activity of one file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Activity {
+    /// Events on the file
+    pub events: u64,
+    /// Bytes read and written
+    pub bytes: u64,
+}
+
+impl Activity {
+    /// Value the ranking is ordered by
+    fn rank(&self, by: RankBy) -> u64 {
+        match by {
+            RankBy::Events => self.events,
+            RankBy::Bytes => self.bytes,
+        }
+    }
+}
+
+/// Count-min sketch of events and bytes per path
+#[derive(Debug, Clone)]
+struct CountMin {
+    /// `SKETCH_DEPTH` rows of `SKETCH_WIDTH` counters
+    rows: Vec<Vec<Activity>>,
+}
+
+impl CountMin {
+    fn new() -> Self {
+        Self {
+            rows: vec![vec![Activity::default(); SKETCH_WIDTH]; SKETCH_DEPTH],
+        }
+    }
+
+    /// Column of a path in one row
+    fn column(row: usize, path: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        (row, path).hash(&mut hasher);
+        hasher.finish() as usize % SKETCH_WIDTH
+    }
+
+    fn add(&mut self, path: &str, bytes: u64) {
+        for (row, counters) in self.rows.iter_mut().enumerate() {
+            let counter = &mut counters[Self::column(row, path)];
+            counter.events += 1;
+            counter.bytes += bytes;
+        }
+    }
+
+    /// Smallest counters over the rows, which bound the true counts
+    /// from above
+    fn estimate(&self, path: &str) -> Activity {
+        let counters = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(row, counters)| counters[Self::column(row, path)]);
+        Activity {
+            events: counters.clone().map(|c| c.events).min().unwrap_or(0),
+            bytes: counters.map(|c| c.bytes).min().unwrap_or(0),
+        }
+    }
+}
+
+/// Ranking of the busiest files over a sliding window
+#[derive(Debug)]
+pub struct HotFiles {
+    /// One sketch per slice, oldest first
+    slices: VecDeque<CountMin>,
+    /// Paths that may rank, with their activity over the window
+    candidates: HashMap<String, Activity>,
+    /// Most candidates tracked at once
+    capacity: usize,
+    /// Number of files ranked
+    top: usize,
+    /// What files are ranked by
+    by: RankBy,
+}
+
+impl HotFiles {
+    /// Create an empty ranking
+    ///
+    /// # Arguments
+    /// * `top` - Number of files ranked
+    /// * `by` - What files are ranked by
+    ///
+    /// # Returns
+    /// * `HotFiles` - Ranking with no activity
+    pub fn new(top: usize, by: RankBy) -> Self {
+        Self {
+            slices: (0..WINDOW_SLICES).map(|_| CountMin::new()).collect(),
+            candidates: HashMap::new(),
+            capacity: top * CANDIDATES_PER_ROW,
+            top,
+            by,
+        }
+    }
+
+    /// Estimated activity of a path over the whole window
+    fn estimate(&self, path: &str) -> Activity {
+        self.slices.iter().map(|slice| slice.estimate(path)).fold(
+            Activity::default(),
+            |total, part| Activity {
+                events: total.events + part.events,
+                bytes: total.bytes + part.bytes,
+            },
+        )
+    }
+
+    /// Count one event in the current slice
+    ///
+    /// # Arguments
+    /// * `event` - Event from the monitor; reads and writes add their size
+    pub fn record(&mut self, event: &FileEvent) {
+        let bytes = match event.action {
+            FileAction::Read { bytes, .. }
+            | FileAction::Written { bytes, .. } => bytes,
+            _ => 0,
+        };
+        let path = event.file_path.as_str();
+        if let Some(current) = self.slices.back_mut() {
+            current.add(path, bytes);
+        }
+        let activity = self.estimate(path);
+        if let Some(candidate) = self.candidates.get_mut(path) {
+            *candidate = activity;
+            return;
+        }
+        if self.candidates.len() >= self.capacity {
+            let by = self.by;
+            let Some((coldest, _)) = self
+                .candidates
+                .iter()
+                .min_by_key(|(_, activity)| activity.rank(by))
+                .filter(|(_, coldest)| coldest.rank(by) < activity.rank(by))
+            else {
+                return;
+            };
+            let coldest = coldest.clone();
+            self.candidates.remove(&coldest);
+        }
+        self.candidates.insert(path.to_string(), activity);
+    }
+
+    /// Start a new slice, dropping the oldest
+    pub fn slide(&mut self) {
+        self.slices.pop_front();
+        self.slices.push_back(CountMin::new());
+        let estimates: Vec<(String, Activity)> = self
+            .candidates
+            .keys()
+            .map(|path| (path.clone(), self.estimate(path)))
+            .collect();
+        self.candidates = estimates
+            .into_iter()
+            .filter(|(_, activity)| activity.events > 0)
+            .collect();
+    }
+
+    /// The busiest files, busiest first
+    ///
+    /// # Returns
+    /// * `Vec<(&str, Activity)>` - Up to `top` paths and their activity
+    pub fn ranking(&self) -> Vec<(&str, Activity)> {
+        let mut rows: Vec<(&str, Activity)> = self
+            .candidates
+            .iter()
+            .map(|(path, activity)| (path.as_str(), *activity))
+            .collect();
+        rows.sort_by(|a, b| {
+            b.1.rank(self.by)
+                .cmp(&a.1.rank(self.by))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        rows.truncate(self.top);
+        rows
+    }
+}
+
+/// Format a ranking as an aligned table with a header
+///
+/// # Arguments
+/// * `rows` - Ranked files
+/// * `window` - Length of the window, for the title
+///
+/// # Returns
+/// * `String` - Title, header and one line per file, newline-terminated
+pub fn format_ranking(rows: &[(&str, Activity)], window: Duration) -> String {
+    let mut out = format!(
+        "Busiest files over the last {:?}\n{:>4}  {:>10}  {:>14}  PATH\n",
+        window, "RANK", "EVENTS", "BYTES"
+    );
+    for (rank, (path, activity)) in rows.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "{:>4}  {:>10}  {:>14}  {}",
+            rank + 1,
+            activity.events,
+            activity.bytes,
+            path
+        );
+    }
+    out
+}
+
+/// Run `fw hot`, redrawing the ranking each time the window slides
+///
+/// # Arguments
+/// * `window` - Length of the sliding window
+/// * `top` - Number of files shown
+/// * `by` - What files are ranked by
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if monitoring failed
+pub fn run_hot(window: Duration, top: usize, by: RankBy) -> Result<()> {
+    if window.is_zero() {
+        return Err(anyhow!("--window must be longer than zero"));
+    }
+    let slice = window / WINDOW_SLICES as u32;
+    let mut hot = HotFiles::new(top, by);
+
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+    rt.block_on(async {
+        // Reads and writes give the bytes moved
+        let mut monitor = EbpfMonitor::new()
+            .context("Failed to initialize eBPF monitor")?
+            .with_access_patterns(true);
+        print!("{}{}", CLEAR_SCREEN, format_ranking(&[], window));
+
+        let mut slice_start = Instant::now();
+        let shutdown = async {
+            let _ = signal::ctrl_c().await;
+        };
+        collector::pump_events(&mut monitor, shutdown, |event| {
+            if slice_start.elapsed() >= slice {
+                // Catch up on slices that passed without events
+                let passed =
+                    slice_start.elapsed().as_nanos() / slice.as_nanos().max(1);
+                for _ in 0..passed.min(WINDOW_SLICES as u128) {
+                    hot.slide();
+                }
+                slice_start = Instant::now();
+                print!(
+                    "{}{}",
+                    CLEAR_SCREEN,
+                    format_ranking(&hot.ranking(), window)
+                );
+            }
+            hot.record(&event);
+            Ok(ControlFlow::Continue(()))
+        })
+        .await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    fn event(path: &str, action: FileAction) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            action,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    fn read(bytes: u64) -> FileAction {
+        FileAction::Read {
+            offset: None,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_sketch_never_undercounts() {
+        let mut sketch = CountMin::new();
+        for i in 0..10_000u64 {
+            sketch.add(&format!("/f/{}", i % 5000), i % 7);
+        }
+        for i in 0..5000u64 {
+            let estimate = sketch.estimate(&format!("/f/{}", i));
+            assert!(estimate.events >= 2);
+            assert!(estimate.bytes >= i % 7 + (i + 5000) % 7);
+        }
+        assert_eq!(CountMin::new().estimate("/f/1"), Activity::default());
+    }
+
+    #[test]
+    fn test_ranking_follows_the_window() {
+        let mut hot = HotFiles::new(2, RankBy::Events);
+        for _ in 0..5 {
+            hot.record(&event("/busy", FileAction::Opened));
+        }
+        for _ in 0..3 {
+            hot.record(&event("/warm", FileAction::Opened));
+        }
+        // Many one-off files don't push out the heavy hitters
+        for i in 0..100 {
+            hot.record(&event(&format!("/once/{}", i), FileAction::Opened));
+        }
+        let ranked: Vec<&str> =
+            hot.ranking().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(ranked, ["/busy", "/warm"]);
+
+        // Activity is forgotten once it slides out of the window
+        for _ in 0..WINDOW_SLICES - 1 {
+            hot.slide();
+        }
+        assert_eq!(
+            hot.ranking()[0],
+            (
+                "/busy",
+                Activity {
+                    events: 5,
+                    bytes: 0
+                }
+            )
+        );
+        hot.slide();
+        assert!(hot.ranking().is_empty());
+
+        let mut by_bytes = HotFiles::new(1, RankBy::Bytes);
+        by_bytes.record(&event("/small", read(10)));
+        by_bytes.record(&event("/small", read(10)));
+        by_bytes.record(&event("/large", read(1 << 20)));
+        let ranking = by_bytes.ranking();
+        assert_eq!(ranking[0].0, "/large");
+        assert!(format_ranking(&ranking, Duration::from_secs(60))
+            .ends_with("   1           1         1048576  /large\n"));
+    }
+}
@@ -0,0 +1,377 @@
+//! Influx module
+//!
+//! Pushes per-interval event counts to a time series database that
+//! speaks the InfluxDB line protocol (`fw collect --influx-url`), so teams
+//! without a Prometheus scraper can chart file activity: InfluxDB 1.x
+//! (`/write?db=NAME`) and 2.x (`/api/v2/write?org=ORG&bucket=NAME`),
+//! VictoriaMetrics (`/write`) and others that accept it. Every
+//! `--influx-interval`, the counts since the last push, grouped by
+//! `--influx-group-by`, are posted as one batch:
+//!
+//! ```text
+//! fw_events,host=web1,process=nginx,action=write count=42i 1704110400000000000
+//! fw_dropped,host=web1,source=lagged count=3i 1704110400000000000
+//! ```
+//!
+//! Points carry the start of their interval, in nanoseconds. Counting
+//! happens as events arrive and posting on a background task, so a slow
+//! database never stalls event delivery; a batch that can't be sent is
+//! logged and dropped, as is one the database takes more than 10 seconds
+//! to connect or answer. The last interval is sent when collection stops.
+//! Posting needs the `metrics` feature.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::notify::WebhookUrl;
+#[cfg(feature = "metrics")]
+use crate::notify::{client_config, post};
+use crate::stats::{Dimension, StatsAggregate};
+#[cfg(feature = "metrics")]
+use log::{debug, warn};
+#[cfg(feature = "metrics")]
+use rustls::ClientConfig;
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "metrics")]
+use tokio::runtime::Handle;
+
+/// Time between pushes unless `--influx-interval` says otherwise
+pub const DEFAULT_INFLUX_INTERVAL: &str = "60s";
+
+/// Dimensions counts are grouped by unless `--influx-group-by` says
+/// otherwise
+pub const DEFAULT_INFLUX_GROUP_BY: &str = "process,action";
+
+/// Where, how often and by what to push counts
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    /// Write endpoint, with the database or bucket in its query
+    pub url: WebhookUrl,
+    /// Sent as "Authorization: Token ..." when set
+    pub token: Option<String>,
+    /// Time between pushes
+    pub interval: Duration,
+    /// Dimensions each point is tagged with
+    pub group_by: Vec<Dimension>,
+    /// CA certificates the server must present a certificate chaining to
+    pub ca: PathBuf,
+    /// Name of this host, tagged on every point
+    pub hostname: String,
+}
+
+impl InfluxConfig {
+    /// Check that every dimension can be counted per event
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if grouping by access pattern, which is
+    ///   only known once a file is closed
+    pub fn validate(&self) -> Result<()> {
+        if self.group_by.contains(&Dimension::Pattern) {
+            return Err(anyhow!("--influx-group-by can't use pattern"));
+        }
+        Ok(())
+    }
+}
+
+/// Counts of one interval
+#[derive(Debug, Clone)]
+pub struct IntervalCounts {
+    /// Start of the interval
+    pub start: DateTime<Utc>,
+    /// Events per group
+    pub events: StatsAggregate,
+    /// Events lost, per source (e.g. "lagged")
+    pub dropped: BTreeMap<String, u64>,
+}
+
+impl IntervalCounts {
+    /// Start an empty interval
+    ///
+    /// # Arguments
+    /// * `group_by` - Dimensions events are grouped by
+    /// * `start` - Start of the interval
+    ///
+    /// # Returns
+    /// * `IntervalCounts` - Counts with no events
+    pub fn new(group_by: Vec<Dimension>, start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            events: StatsAggregate::new(group_by),
+            dropped: BTreeMap::new(),
+        }
+    }
+
+    /// Render the interval as a line protocol batch
+    ///
+    /// # Arguments
+    /// * `host` - Value of the host tag
+    ///
+    /// # Returns
+    /// * `String` - One line per group and drop source, empty if nothing
+    ///   happened
+    pub fn line_protocol(&self, host: &str) -> String {
+        let time = self.start.timestamp_nanos_opt().unwrap_or_default();
+        let host = escape_tag(host);
+        let keys: Vec<String> =
+            self.events.group_by().iter().map(tag_key).collect();
+        let mut out = String::new();
+        for (values, count) in self.events.rows() {
+            let _ = write!(out, "fw_events,host={}", host);
+            for (key, value) in keys.iter().zip(values) {
+                let _ = write!(out, ",{}={}", key, escape_tag(value));
+            }
+            let _ = writeln!(out, " count={}i {}", count, time);
+        }
+        for (source, count) in &self.dropped {
+            let _ = writeln!(
+                out,
+                "fw_dropped,host={},source={} count={}i {}",
+                host,
+                escape_tag(source),
+                count,
+                time
+            );
+        }
+        out
+    }
+}
+
+/// Tag key of a dimension, e.g. "dir_depth_2" for "dir-depth=2"
+fn tag_key(dimension: &Dimension) -> String {
+    escape_tag(&dimension.to_string().replace(['-', '='], "_"))
+}
+
+/// Escape a tag key or value for the line protocol
+///
+/// Backslashes, commas, equals signs and spaces are escaped, so a value
+/// ending in a backslash can't escape the separator after it; line
+/// breaks, which the protocol can't carry, become spaces, and empty
+/// values "(none)".
+fn escape_tag(value: &str) -> String {
+    if value.is_empty() {
+        return "(none)".to_string();
+    }
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | ',' | '=' | ' ' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push_str("\\ "),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Sink that counts events for the background pusher
+#[cfg(feature = "metrics")]
+pub struct InfluxSink {
+    /// Counts of the interval being filled, shared with the pusher
+    counts: Arc<Mutex<IntervalCounts>>,
+    /// Endpoint and tags, for the final push
+    config: Arc<InfluxConfig>,
+    /// TLS settings, for the final push
+    tls: Arc<ClientConfig>,
+}
+
+#[cfg(feature = "metrics")]
+impl InfluxSink {
+    /// Create a sink and start its pushing task on the given runtime
+    ///
+    /// # Arguments
+    /// * `config` - Endpoint, interval and grouping
+    /// * `runtime` - Runtime the pushing task runs on
+    ///
+    /// # Returns
+    /// * `Result<InfluxSink>` - New sink, or error if the CA
+    ///   certificates can't be loaded
+    pub fn new(config: InfluxConfig, runtime: Handle) -> Result<Self> {
+        config.validate()?;
+        let tls = client_config(&config.ca)?;
+        let counts = Arc::new(Mutex::new(IntervalCounts::new(
+            config.group_by.clone(),
+            Utc::now(),
+        )));
+        let config = Arc::new(config);
+        runtime.spawn(push(config.clone(), tls.clone(), counts.clone()));
+        Ok(Self {
+            counts,
+            config,
+            tls,
+        })
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl EventSink for InfluxSink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        lock(&self.counts).events.record(event);
+        Ok(())
+    }
+
+    fn dropped(&mut self, source: &str, count: u64) {
+        *lock(&self.counts)
+            .dropped
+            .entry(source.to_string())
+            .or_default() += count;
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let last = take(&self.counts, &self.config);
+        send(&self.config, &self.tls, &last);
+        Ok(())
+    }
+}
+
+/// Lock the shared counts, even if a holder panicked
+#[cfg(feature = "metrics")]
+fn lock(
+    counts: &Mutex<IntervalCounts>,
+) -> std::sync::MutexGuard<'_, IntervalCounts> {
+    counts.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Take the counts of the interval that just ended, starting a new one
+#[cfg(feature = "metrics")]
+fn take(
+    counts: &Mutex<IntervalCounts>,
+    config: &InfluxConfig,
+) -> IntervalCounts {
+    let next = IntervalCounts::new(config.group_by.clone(), Utc::now());
+    std::mem::replace(&mut *lock(counts), next)
+}
+
+/// Push the counts of every interval until the runtime stops
+#[cfg(feature = "metrics")]
+async fn push(
+    config: Arc<InfluxConfig>,
+    tls: Arc<ClientConfig>,
+    counts: Arc<Mutex<IntervalCounts>>,
+) {
+    let start = tokio::time::Instant::now() + config.interval;
+    let mut ticks = tokio::time::interval_at(start, config.interval);
+    loop {
+        ticks.tick().await;
+        let interval = take(&counts, &config);
+        let (config, tls) = (config.clone(), tls.clone());
+        let result =
+            tokio::task::spawn_blocking(move || send(&config, &tls, &interval))
+                .await;
+        if let Err(e) = result {
+            warn!("InfluxDB push task failed: {}", e);
+        }
+    }
+}
+
+/// Post one interval's batch, logging failures
+#[cfg(feature = "metrics")]
+fn send(
+    config: &InfluxConfig,
+    tls: &Arc<ClientConfig>,
+    counts: &IntervalCounts,
+) {
+    let batch = counts.line_protocol(&config.hostname);
+    if batch.is_empty() {
+        return;
+    }
+    let token = config.token.as_ref().map(|t| format!("Token {}", t));
+    let content_type = "text/plain; charset=utf-8";
+    // post gives up on a server that doesn't connect or answer within
+    // the notification timeout, so a hung database only loses this batch
+    match post(&config.url, tls, content_type, token.as_deref(), &batch) {
+        Ok(()) => {
+            debug!("Pushed {} points to {}", batch.lines().count(), config.url)
+        }
+        Err(e) => warn!("Failed to push counts to {}: {}", config.url, e),
+    }
+}
+
+/// Stand-in for builds without the `metrics` feature; never created
+#[cfg(not(feature = "metrics"))]
+pub enum InfluxSink {}
+
+#[cfg(not(feature = "metrics"))]
+impl InfluxSink {
+    /// Refuse to push
+    ///
+    /// # Returns
+    /// * `Result<InfluxSink>` - Always an error naming the feature
+    pub fn new(_: InfluxConfig, _: tokio::runtime::Handle) -> Result<Self> {
+        Err(crate::features::not_compiled_in(
+            "metrics",
+            "Pushing to InfluxDB",
+        ))
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+impl EventSink for InfluxSink {
+    fn write_event(&mut self, _: &FileEvent) -> Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    #[test]
+    fn test_line_protocol() {
+        let group_by = vec![Dimension::Process, Dimension::DirDepth(2)];
+        let start = "2024-01-01T12:00:00Z".parse().unwrap();
+        let mut counts = IntervalCounts::new(group_by, start);
+        for path in ["/srv/my app/a", "/srv/my app/b", "/etc/x"] {
+            counts.events.record(&FileEvent::new(
+                path.to_string(),
+                "node,js".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            ));
+        }
+        counts.dropped.insert("lagged".to_string(), 3);
+        let lines: Vec<String> = counts
+            .line_protocol("web 1")
+            .lines()
+            .map(Into::into)
+            .collect();
+        let tags = "host=web\\ 1,process=node\\,js";
+        let time = "1704110400000000000";
+        assert_eq!(
+            lines,
+            vec![
+                format!(
+                    "fw_events,{},dir_depth_2=/srv/my\\ app count=2i {}",
+                    tags, time
+                ),
+                format!(
+                    "fw_events,{},dir_depth_2=/etc count=1i {}",
+                    tags, time
+                ),
+                format!(
+                    "fw_dropped,host=web\\ 1,source=lagged count=3i {}",
+                    time
+                ),
+            ]
+        );
+        assert_eq!(escape_tag("a=b\nc"), "a\\=b\\ c");
+        assert_eq!(escape_tag("C:\\"), "C:\\\\");
+        assert_eq!(escape_tag("a\\,b"), "a\\\\\\,b");
+        assert_eq!(escape_tag(""), "(none)");
+        assert_eq!(tag_key(&Dimension::Tag("team".into())), "tag_team");
+
+        let empty = IntervalCounts::new(vec![Dimension::Process], start);
+        assert!(empty.line_protocol("web1").is_empty());
+    }
+}
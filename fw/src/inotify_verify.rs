@@ -0,0 +1,618 @@
+//! Inotify verify module
+//!
+//! Cross-checks the probes against inotify for `fw collect
+//! --verify-with-inotify`, as a second, independent witness of changes
+//! to critical directories. Both report changes to the files directly in
+//! the listed directories; a change one of them reports and the other
+//! doesn't within a few seconds becomes an alert line, since it means
+//! either mechanism was tampered with or lost events:
+//!
+//! ```text
+//! 2024-01-01 12:00:00 UTC | verify | /etc/hosts | only inotify saw modify
+//! ```
+//!
+//! Only changes both can see are compared: opens for writing, creation,
+//! renames, links, truncation, writes and metadata changes. Deletions
+//! and directories aren't probed and are left out, and subdirectories
+//! are not watched. Changes by processes or users the kernel filters
+//! drop, through descriptors opened before fw started (unless
+//! `--snapshot` saw them), or to timestamps alone, show up as missed by
+//! the probes.
+//!
+//! The probes' events reach the reconciler before redaction, so they
+//! can be paired with inotify's real paths; with `--redact` the alert
+//! lines are redacted instead.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::warn;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+use tokio::runtime::Handle;
+
+use crate::fanout::EventSink;
+use crate::file_event::{FileAction, FileEvent};
+use crate::process_list::InotifyFd;
+use crate::redact::Redactor;
+use crate::rename_chain;
+
+/// How long one witness has to confirm a change the other reported
+pub const VERIFY_WINDOW: Duration = Duration::from_secs(5);
+
+/// How often unconfirmed changes are checked for having expired
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Source of a sighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Witness {
+    /// The eBPF probes
+    Probes,
+    /// Inotify watches on the directories
+    Inotify,
+}
+
+impl Witness {
+    fn index(self) -> usize {
+        match self {
+            Witness::Probes => 0,
+            Witness::Inotify => 1,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Witness::Probes => Witness::Inotify,
+            Witness::Inotify => Witness::Probes,
+        }
+    }
+}
+
+/// Changes one witness reported
+#[derive(Debug, Default)]
+struct Sightings {
+    /// Latest sighting per path, kept for one window
+    last: HashMap<String, Instant>,
+    /// Changes the other witness hasn't confirmed yet, with when they
+    /// were first seen and what they were
+    unconfirmed: HashMap<String, (Instant, String)>,
+}
+
+/// Change one witness saw and the other didn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Miss {
+    /// Witness that saw the change
+    pub seen_by: Witness,
+    /// Path of the changed file
+    pub path: String,
+    /// What was seen, e.g. "modify"
+    pub what: String,
+    /// Whether the probes' events were being dropped at the time
+    pub while_dropping: bool,
+}
+
+impl Miss {
+    /// Render the alert line for the miss
+    ///
+    /// # Returns
+    /// * `String` - Line without a newline
+    pub fn alert_line(&self) -> String {
+        let reason = match self.seen_by {
+            Witness::Probes => format!("only the probes saw {}", self.what),
+            Witness::Inotify if self.while_dropping => format!(
+                "only inotify saw {}, while the probes dropped events",
+                self.what
+            ),
+            Witness::Inotify => format!("only inotify saw {}", self.what),
+        };
+        format!(
+            "{} | verify | {} | {}",
+            Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+            self.path,
+            reason
+        )
+    }
+    /// Redact the path and the program named in the miss
+    ///
+    /// # Arguments
+    /// * `redactor` - Rules applied to events
+    ///
+    /// # Returns
+    /// * `Miss` - The same miss as its alert line may show it
+    pub fn redacted(&self, redactor: &Redactor) -> Self {
+        Self {
+            path: redactor.redact_path(&self.path).into_owned(),
+            what: redactor.redact_path(&self.what).into_owned(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Pairs changes reported by the probes with those seen by inotify
+#[derive(Debug)]
+pub struct Reconciler {
+    /// How long a witness has to confirm the other's change
+    window: Duration,
+    /// Sightings of the probes and of inotify
+    witnesses: [Sightings; 2],
+    /// Files open for writing before fw started, whose changes the
+    /// probes can't see
+    open_before: HashSet<String>,
+    /// When the probes' events were last dropped
+    dropped_at: Option<Instant>,
+}
+
+impl Reconciler {
+    /// Create a reconciler with nothing seen
+    ///
+    /// # Arguments
+    /// * `window` - How long a witness has to confirm the other's change
+    ///
+    /// # Returns
+    /// * `Reconciler` - New reconciler
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            witnesses: Default::default(),
+            open_before: HashSet::new(),
+            dropped_at: None,
+        }
+    }
+
+    /// Record a change one witness saw
+    ///
+    /// The change is confirmed if the other witness saw the path within
+    /// the window, and otherwise waits for it to.
+    ///
+    /// # Arguments
+    /// * `witness` - Who saw it
+    /// * `path` - Path of the changed file
+    /// * `what` - Description for an alert
+    /// * `now` - When it was seen
+    pub fn change(
+        &mut self,
+        witness: Witness,
+        path: &str,
+        what: String,
+        now: Instant,
+    ) {
+        let confirmed = self.confirm(witness, path, now)
+            || (witness == Witness::Inotify && self.open_before.contains(path));
+        if !confirmed {
+            self.witnesses[witness.index()]
+                .unconfirmed
+                .entry(path.to_string())
+                .or_insert((now, what));
+        }
+    }
+
+    /// Record activity that confirms the other witness's change but is
+    /// not a change itself, e.g. inotify seeing an open
+    ///
+    /// # Arguments
+    /// * `witness` - Who saw it
+    /// * `path` - Path of the file
+    /// * `now` - When it was seen
+    ///
+    /// # Returns
+    /// * `bool` - True if the other witness saw the path within the
+    ///   window
+    pub fn confirm(
+        &mut self,
+        witness: Witness,
+        path: &str,
+        now: Instant,
+    ) -> bool {
+        let window = self.window;
+        let other = &mut self.witnesses[witness.other().index()];
+        let confirmed = other
+            .last
+            .get(path)
+            .is_some_and(|&at| now.saturating_duration_since(at) <= window);
+        if confirmed {
+            other.unconfirmed.remove(path);
+        }
+        self.witnesses[witness.index()]
+            .last
+            .insert(path.to_string(), now);
+        confirmed
+    }
+
+    /// Note a file the probes found open for writing at startup
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file
+    pub fn open_before_start(&mut self, path: &str) {
+        self.open_before.insert(path.to_string());
+    }
+
+    /// Note that the probes' events were dropped
+    ///
+    /// # Arguments
+    /// * `now` - When they were
+    pub fn dropped(&mut self, now: Instant) {
+        self.dropped_at = Some(now);
+    }
+
+    /// Take the changes whose window passed unconfirmed
+    ///
+    /// # Arguments
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    /// * `Vec<Miss>` - Changes the other witness missed, oldest first
+    pub fn expire(&mut self, now: Instant) -> Vec<Miss> {
+        let window = self.window;
+        let expired = |at: Instant| now.saturating_duration_since(at) > window;
+        let dropping = self
+            .dropped_at
+            .is_some_and(|at| now.saturating_duration_since(at) <= window * 2);
+        let mut misses = Vec::new();
+        for witness in [Witness::Probes, Witness::Inotify] {
+            let sightings = &mut self.witnesses[witness.index()];
+            sightings.last.retain(|_, at| !expired(*at));
+            let mut stale: Vec<(String, Instant, String)> = Vec::new();
+            sightings.unconfirmed.retain(|path, (at, what)| {
+                if expired(*at) {
+                    stale.push((path.clone(), *at, std::mem::take(what)));
+                }
+                !expired(*at)
+            });
+            stale.sort_by_key(|(_, at, _)| *at);
+            misses.extend(stale.into_iter().map(|(path, _, what)| Miss {
+                seen_by: witness,
+                path,
+                what,
+                while_dropping: witness == Witness::Inotify && dropping,
+            }));
+        }
+        misses
+    }
+}
+
+/// Description of a probe event as a change, None if it isn't one
+/// inotify would see
+fn probe_change(event: &FileEvent) -> Option<String> {
+    let change = match event.action {
+        FileAction::Opened => {
+            event.open_flags.is_some_and(rename_chain::is_write)
+        }
+        // Flushing changes nothing inotify reports
+        FileAction::Synced { .. } | FileAction::AlreadyOpen => false,
+        action => action.is_write(),
+    };
+    change.then(|| {
+        format!("{} ({}) {}", event.program_name, event.pid, event.action)
+    })
+}
+
+/// Description of an inotify event, and whether it is a change rather
+/// than an open, None for events that aren't compared
+fn inotify_change(mask: AddWatchFlags) -> Option<(&'static str, bool)> {
+    if mask.contains(AddWatchFlags::IN_ISDIR) {
+        return None;
+    }
+    [
+        (AddWatchFlags::IN_CREATE, "create"),
+        (AddWatchFlags::IN_MOVED_TO, "rename"),
+        (AddWatchFlags::IN_MODIFY, "modify"),
+        (AddWatchFlags::IN_ATTRIB, "attribute change"),
+        (AddWatchFlags::IN_CLOSE_WRITE, "close after write"),
+    ]
+    .into_iter()
+    .find(|(flag, _)| mask.contains(*flag))
+    .map(|(_, what)| (what, true))
+    .or_else(|| {
+        mask.contains(AddWatchFlags::IN_OPEN)
+            .then_some(("open", false))
+    })
+}
+
+/// Sink feeding the probes' changes to the reconciler
+pub struct VerifySink {
+    /// Canonical directories being verified
+    dirs: Arc<HashSet<String>>,
+    /// Shared with the inotify task
+    reconciler: Arc<Mutex<Reconciler>>,
+}
+
+impl VerifySink {
+    /// Watch directories with inotify and start reconciling
+    ///
+    /// The inotify task runs on the given runtime and writes an alert
+    /// line for every change one witness missed.
+    ///
+    /// # Arguments
+    /// * `dirs` - Directories whose files are verified
+    /// * `alerts` - Destination for alert lines
+    /// * `redactor` - Rules the alert lines are redacted with, if any;
+    ///   the sink itself must get unredacted events
+    /// * `runtime` - Runtime the inotify task runs on
+    ///
+    /// # Returns
+    /// * `Result<VerifySink>` - Sink, or error if a directory can't be
+    ///   watched
+    pub fn new(
+        dirs: &[PathBuf],
+        alerts: Box<dyn Write + Send>,
+        redactor: Option<Arc<Redactor>>,
+        runtime: Handle,
+    ) -> Result<Self> {
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut watched = HashMap::new();
+        for dir in dirs {
+            let dir = fs::canonicalize(dir).with_context(|| {
+                format!("Failed to resolve {}", dir.display())
+            })?;
+            let wd = inotify
+                .add_watch(
+                    &dir,
+                    AddWatchFlags::IN_CREATE
+                        | AddWatchFlags::IN_MOVED_TO
+                        | AddWatchFlags::IN_MODIFY
+                        | AddWatchFlags::IN_ATTRIB
+                        | AddWatchFlags::IN_CLOSE_WRITE
+                        | AddWatchFlags::IN_OPEN
+                        | AddWatchFlags::IN_ONLYDIR,
+                )
+                .with_context(|| {
+                    format!("Failed to watch {}", dir.display())
+                })?;
+            watched.insert(wd, dir);
+        }
+        let dirs = watched
+            .values()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .collect();
+        let reconciler = Arc::new(Mutex::new(Reconciler::new(VERIFY_WINDOW)));
+        let inotify = {
+            // AsyncFd registers with the reactor of the current runtime
+            let _guard = runtime.enter();
+            AsyncFd::new(InotifyFd(inotify))?
+        };
+        runtime.spawn(watch(
+            inotify,
+            watched,
+            reconciler.clone(),
+            alerts,
+            redactor,
+        ));
+        Ok(Self {
+            dirs: Arc::new(dirs),
+            reconciler,
+        })
+    }
+
+    /// Check whether a path is directly in a verified directory
+    fn covers(&self, path: &str) -> bool {
+        Path::new(path)
+            .parent()
+            .and_then(Path::to_str)
+            .is_some_and(|dir| self.dirs.contains(dir))
+    }
+}
+
+impl EventSink for VerifySink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        self.throttled(event);
+        Ok(())
+    }
+
+    // Alerts held back still witness a change, or inotify's report of it
+    // would look unmatched
+    fn throttled(&mut self, event: &FileEvent) {
+        if !self.covers(&event.file_path) {
+            return;
+        }
+        let now = Instant::now();
+        let mut reconciler = lock(&self.reconciler);
+        if event.action == FileAction::AlreadyOpen
+            && event.open_flags.is_some_and(rename_chain::is_write)
+        {
+            reconciler.open_before_start(&event.file_path);
+        }
+        if let Some(what) = probe_change(event) {
+            reconciler.change(Witness::Probes, &event.file_path, what, now);
+        }
+    }
+
+    fn dropped(&mut self, _source: &str, _count: u64) {
+        lock(&self.reconciler).dropped(Instant::now());
+    }
+}
+
+/// Lock the reconciler, even if a holder panicked
+fn lock(
+    reconciler: &Mutex<Reconciler>,
+) -> std::sync::MutexGuard<'_, Reconciler> {
+    reconciler.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Feed inotify's changes to the reconciler and write alerts for
+/// expired ones, until the runtime stops
+async fn watch(
+    inotify: AsyncFd<InotifyFd>,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    reconciler: Arc<Mutex<Reconciler>>,
+    mut alerts: Box<dyn Write + Send>,
+    redactor: Option<Arc<Redactor>>,
+) {
+    let mut ticks = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        tokio::select! {
+            ready = inotify.readable() => {
+                let mut ready = match ready {
+                    Ok(ready) => ready,
+                    Err(e) => {
+                        warn!("Stopped verifying with inotify: {}", e);
+                        return;
+                    }
+                };
+                let events = match ready.get_inner().0.read_events() {
+                    Ok(events) => events,
+                    Err(Errno::EAGAIN) => {
+                        ready.clear_ready();
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Stopped verifying with inotify: {}", e);
+                        return;
+                    }
+                };
+                let now = Instant::now();
+                let mut reconciler = lock(&reconciler);
+                for event in events {
+                    if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                        warn!("Inotify queue overflowed; some changes \
+                               can't be verified");
+                    }
+                    let (Some(dir), Some(name)) =
+                        (dirs.get(&event.wd), &event.name)
+                    else {
+                        continue;
+                    };
+                    let Some((what, is_change)) = inotify_change(event.mask)
+                    else {
+                        continue;
+                    };
+                    let path = dir.join(name).to_string_lossy().into_owned();
+                    if is_change {
+                        reconciler.change(
+                            Witness::Inotify,
+                            &path,
+                            what.to_string(),
+                            now,
+                        );
+                    } else {
+                        reconciler.confirm(Witness::Inotify, &path, now);
+                    }
+                }
+            }
+            _ = ticks.tick() => {
+                let misses = lock(&reconciler).expire(Instant::now());
+                for miss in misses {
+                    let line = match &redactor {
+                        Some(redactor) => miss.redacted(redactor).alert_line(),
+                        None => miss.alert_line(),
+                    };
+                    if let Err(e) = writeln!(alerts, "{}", line)
+                        .and_then(|()| alerts.flush())
+                    {
+                        warn!("Failed to write verification alert: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::redact::RedactConfig;
+
+    #[test]
+    fn test_reconcile() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut reconciler = Reconciler::new(VERIFY_WINDOW);
+
+        // Seen by both, in either order
+        reconciler.change(Witness::Probes, "/etc/a", "chmod".into(), at(0));
+        reconciler.change(Witness::Inotify, "/etc/a", "attrib".into(), at(1));
+        reconciler.change(Witness::Inotify, "/etc/b", "modify".into(), at(1));
+        reconciler.change(Witness::Probes, "/etc/b", "write".into(), at(2));
+        // An inotify open confirms the probes' open for writing
+        reconciler.change(Witness::Probes, "/etc/c", "opened".into(), at(2));
+        reconciler.confirm(Witness::Inotify, "/etc/c", at(2));
+        assert!(reconciler.expire(at(10)).is_empty());
+
+        // Seen by one only
+        reconciler.change(Witness::Inotify, "/etc/d", "modify".into(), at(11));
+        reconciler.change(Witness::Probes, "/etc/e", "chown".into(), at(12));
+        assert!(reconciler.expire(at(14)).is_empty());
+        reconciler.dropped(at(15));
+        let misses = reconciler.expire(at(20));
+        assert_eq!(
+            misses,
+            vec![
+                Miss {
+                    seen_by: Witness::Probes,
+                    path: "/etc/e".into(),
+                    what: "chown".into(),
+                    while_dropping: false,
+                },
+                Miss {
+                    seen_by: Witness::Inotify,
+                    path: "/etc/d".into(),
+                    what: "modify".into(),
+                    while_dropping: true,
+                },
+            ]
+        );
+        assert!(misses[1].alert_line().ends_with(
+            "| verify | /etc/d | only inotify saw modify, while the probes \
+             dropped events"
+        ));
+        let redactor = Redactor::new(&RedactConfig {
+            rules: vec![r"/etc/d$=>/etc/[redacted]".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(misses[1]
+            .redacted(&redactor)
+            .alert_line()
+            .contains("| verify | /etc/[redacted] | only inotify"));
+
+        // Too late to confirm: both missed the other
+        reconciler.change(Witness::Probes, "/etc/f", "write".into(), at(30));
+        reconciler.change(Witness::Inotify, "/etc/f", "modify".into(), at(40));
+        assert_eq!(reconciler.expire(at(50)).len(), 2);
+
+        // Writers from before the start explain inotify's changes
+        reconciler.open_before_start("/var/log/x");
+        reconciler.change(
+            Witness::Inotify,
+            "/var/log/x",
+            "modify".into(),
+            at(60),
+        );
+        assert!(reconciler.expire(at(70)).is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut event = FileEvent::new(
+            "/etc/hosts".to_string(),
+            "vim".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+        event.open_flags = Some(libc::O_RDONLY as u32);
+        assert_eq!(probe_change(&event), None);
+        event.open_flags = Some((libc::O_WRONLY | libc::O_TRUNC) as u32);
+        assert_eq!(probe_change(&event).unwrap(), "vim (7) opened");
+        event.action = FileAction::Synced {
+            kind: crate::file_event::SyncKind::Fsync,
+        };
+        assert_eq!(probe_change(&event), None);
+
+        let modify = AddWatchFlags::IN_MODIFY;
+        assert_eq!(inotify_change(modify), Some(("modify", true)));
+        assert_eq!(
+            inotify_change(AddWatchFlags::IN_OPEN),
+            Some(("open", false))
+        );
+        let mkdir = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ISDIR;
+        assert_eq!(inotify_change(mkdir), None);
+    }
+}
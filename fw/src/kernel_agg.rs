@@ -0,0 +1,311 @@
+//! Kernel Aggregation module
+//!
+//! Statistics counted in the kernel instead of from events
+//! (`fw collect --kernel-agg`). The probes increment a counter per
+//! process name, file extension and action in a BPF hash map and send no
+//! events at all; userspace reads and resets the counters every interval
+//! and folds them into the same aggregate `--mode stats` prints and
+//! exports. Only the dimensions the kernel counts by can be grouped on,
+//! and userspace filters don't apply since no event reaches them.
+
+use anyhow::{Context, Result};
+use fw_common::{
+    AggKey, EVENT_TYPE_CHMOD, EVENT_TYPE_CHOWN, EVENT_TYPE_CLOSE,
+    EVENT_TYPE_LINK, EVENT_TYPE_LOCK, EVENT_TYPE_OPEN, EVENT_TYPE_REMOVEXATTR,
+    EVENT_TYPE_RENAME, EVENT_TYPE_SETXATTR, EVENT_TYPE_SYMLINK,
+    EVENT_TYPE_SYNC, EVENT_TYPE_TRUNCATE, EVENT_TYPE_UNLOCK,
+};
+use log::{info, warn};
+use std::future::Future;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::fanout::EventSink;
+use crate::file_event::SyncKind;
+use crate::health::{Health, HEARTBEAT_INTERVAL};
+use crate::monitor_backend::MonitorBackend;
+use crate::stats::{Dimension, StatsAggregate, StatsConfig, StatsSink};
+
+/// Counters read and reset in one pass
+#[derive(Debug, Clone, Default)]
+pub struct KernelCounts {
+    /// Count per key, summed over CPUs
+    pub counts: Vec<(AggKey, u64)>,
+    /// Events not counted because the map was full
+    pub overflow: u64,
+}
+
+/// A backend that can count events in the kernel
+pub trait CounterSource {
+    /// Read the counters gathered since the last call and reset them
+    ///
+    /// # Returns
+    /// * `Result<KernelCounts>` - Counts, or error if the maps can't be read
+    fn drain_counts(&mut self) -> Result<KernelCounts>;
+}
+
+/// Check that stats can be grouped by dimensions the kernel counts by
+///
+/// # Arguments
+/// * `group_by` - Requested dimensions
+///
+/// # Returns
+/// * `Result<(), String>` - Success, or a usage error naming the dimension
+pub fn check_dimensions(group_by: &[Dimension]) -> Result<(), String> {
+    match group_by.iter().find(|dimension| {
+        !matches!(
+            dimension,
+            Dimension::Process | Dimension::Extension | Dimension::Action
+        )
+    }) {
+        Some(dimension) => Err(format!(
+            "--kernel-agg can only group by process, extension and action, \
+             not {}",
+            dimension
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Action name of a counted event, as the action dimension shows it
+///
+/// # Arguments
+/// * `event_type` - EVENT_TYPE_* constant
+/// * `detail` - Sync kind for sync events, otherwise 0
+///
+/// # Returns
+/// * `String` - Action name, or "(unknown)" for an unexpected type
+pub fn action_name(event_type: u32, detail: u32) -> String {
+    let name = match event_type {
+        EVENT_TYPE_OPEN => "opened",
+        EVENT_TYPE_CLOSE => "closed",
+        EVENT_TYPE_CHMOD => "chmod",
+        EVENT_TYPE_CHOWN => "chown",
+        EVENT_TYPE_TRUNCATE => "truncate",
+        EVENT_TYPE_LINK => "linked",
+        EVENT_TYPE_SYMLINK => "symlinked",
+        EVENT_TYPE_RENAME => "renamed",
+        EVENT_TYPE_SETXATTR => "setxattr",
+        EVENT_TYPE_REMOVEXATTR => "removexattr",
+        EVENT_TYPE_LOCK => "lock",
+        EVENT_TYPE_UNLOCK => "unlock",
+        EVENT_TYPE_SYNC => {
+            return SyncKind::from_raw(u64::from(detail)).to_string()
+        }
+        _ => "(unknown)",
+    };
+    name.to_string()
+}
+
+/// Add counters to an aggregate, keyed by its dimensions
+///
+/// # Arguments
+/// * `aggregate` - Aggregate grouped by kernel dimensions only
+/// * `counts` - Counters read from the kernel
+pub fn fold_counts(aggregate: &mut StatsAggregate, counts: &KernelCounts) {
+    for (key, count) in &counts.counts {
+        let values = aggregate
+            .group_by()
+            .iter()
+            .map(|dimension| match dimension {
+                Dimension::Process => {
+                    key.comm_str().unwrap_or("(unknown)").to_string()
+                }
+                Dimension::Extension => match key.ext_str() {
+                    Ok(ext) if !ext.is_empty() => ext.to_string(),
+                    _ => "(none)".to_string(),
+                },
+                _ => action_name(key.event_type, key.detail),
+            })
+            .collect();
+        aggregate.add(values, *count);
+    }
+}
+
+/// Count events in the kernel until shutdown, then report the totals
+///
+/// Counters are read every `interval` so the kernel map never holds more
+/// than one interval's worth of keys, and once more after `shutdown`.
+///
+/// # Arguments
+/// * `monitor` - Backend with kernel counting enabled
+/// * `config` - Grouping and export settings
+/// * `interval` - How often to read and reset the counters
+/// * `writer` - Destination for the summary table
+/// * `health` - Records liveness while counting
+/// * `shutdown` - Future that resolves when counting should stop
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if the counters can't be read or the
+///   totals written
+pub async fn run_kernel_stats<B, W, S>(
+    monitor: &mut B,
+    config: StatsConfig,
+    interval: Duration,
+    writer: W,
+    health: &Health,
+    shutdown: S,
+) -> Result<()>
+where
+    B: MonitorBackend + CounterSource,
+    W: Write + Send + 'static,
+    S: Future<Output = ()>,
+{
+    let mut sink = StatsSink::new(config, writer);
+    let mut overflow = 0;
+    let mut drain = |monitor: &mut B, sink: &mut StatsSink<W>| {
+        let counts = monitor
+            .drain_counts()
+            .context("Failed to read kernel counters")?;
+        overflow += counts.overflow;
+        fold_counts(sink.aggregate_mut(), &counts);
+        anyhow::Ok(())
+    };
+
+    // Probes only count, so the channel carries nothing worth reporting
+    let mut events = monitor
+        .start_monitoring()
+        .await
+        .context("Failed to start monitoring")?;
+    health.set_attached(true);
+    health.beat();
+    info!("Counting in the kernel, reading every {:?}", interval);
+
+    tokio::pin!(shutdown);
+    let mut flush = tokio::time::interval(interval);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut receiving = true;
+    let counted = loop {
+        tokio::select! {
+            event = events.recv(), if receiving => {
+                receiving = event.is_some();
+            }
+            _ = flush.tick() => {
+                if let Err(e) = drain(monitor, &mut sink) {
+                    break Err(e);
+                }
+            }
+            _ = heartbeat.tick() => health.beat(),
+            _ = &mut shutdown => break Ok(()),
+        }
+    };
+    health.set_attached(false);
+    counted?;
+    drain(monitor, &mut sink)?;
+    monitor
+        .stop_monitoring()
+        .await
+        .context("Failed to stop monitoring")?;
+
+    if overflow > 0 {
+        warn!(
+            "Kernel counter map was full, {} events were not counted; \
+             use a shorter --agg-interval",
+            overflow
+        );
+    }
+    sink.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_monitor::MockMonitor;
+    use fw_common::{MAX_COMM_LEN, MAX_EXT_LEN, SYNC_KIND_FDATASYNC};
+    use std::sync::{Arc, Mutex};
+
+    fn key(comm: &str, ext: &str, event_type: u32, detail: u32) -> AggKey {
+        let mut key = AggKey {
+            comm: [0; MAX_COMM_LEN],
+            ext: [0; MAX_EXT_LEN],
+            event_type,
+            detail,
+        };
+        key.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        key.ext[..ext.len()].copy_from_slice(ext.as_bytes());
+        key
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        assert!(check_dimensions(&[
+            Dimension::Process,
+            Dimension::Extension,
+            Dimension::Action
+        ])
+        .is_ok());
+        assert_eq!(
+            check_dimensions(&[Dimension::Process, Dimension::DirDepth(2)]),
+            Err("--kernel-agg can only group by process, extension and \
+                 action, not dir-depth=2"
+                .to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_counts_are_summed_across_reads() {
+        let mut monitor = MockMonitor::new(Vec::new()).with_counts(vec![
+            KernelCounts {
+                counts: vec![
+                    (key("cc", "c", EVENT_TYPE_OPEN, 0), 3),
+                    (key("cc", "", EVENT_TYPE_CLOSE, 0), 2),
+                ],
+                overflow: 0,
+            },
+            KernelCounts {
+                counts: vec![
+                    (key("cc", "c", EVENT_TYPE_OPEN, 0), 4),
+                    (
+                        key(
+                            "postgres",
+                            "",
+                            EVENT_TYPE_SYNC,
+                            SYNC_KIND_FDATASYNC as u32,
+                        ),
+                        1,
+                    ),
+                ],
+                overflow: 5,
+            },
+        ]);
+        let out = SharedBuf::default();
+        run_kernel_stats(
+            &mut monitor,
+            StatsConfig {
+                group_by: vec![
+                    Dimension::Process,
+                    Dimension::Extension,
+                    Dimension::Action,
+                ],
+                export: None,
+                heat_map_depth: None,
+                contention: false,
+            },
+            Duration::from_millis(1),
+            out.clone(),
+            &Health::default(),
+            // The first read is immediate and the last follows shutdown
+            tokio::time::sleep(Duration::from_millis(20)),
+        )
+        .await
+        .unwrap();
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            out,
+            "       7 | cc | c | opened\n       2 | cc | (none) | closed\n       \
+             1 | postgres | (none) | fdatasync\n"
+        );
+    }
+}
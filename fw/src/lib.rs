@@ -0,0 +1,86 @@
+//! File Watcher (fw) library
+//!
+//! Exposes the monitoring pipeline used by the `fw` binary so that it can
+//! be exercised by benchmarks and integration tests.
+
+pub mod access_pattern;
+pub mod arch;
+pub mod audit_format;
+pub mod baseline;
+pub mod bench;
+pub mod binary_format;
+pub mod bpf_loader;
+pub mod bpf_object;
+pub mod capabilities;
+pub mod cli;
+pub mod clock;
+pub mod collector;
+pub mod compression;
+pub mod contention;
+pub mod diagnostics;
+pub mod diff;
+pub mod dry_run;
+pub mod ebpf_monitor;
+pub mod enrich;
+pub mod enrich_pool;
+pub mod exclusions;
+pub mod exec_hook;
+pub mod fanout;
+pub mod fd_table;
+pub mod features;
+pub mod file_event;
+pub mod filter;
+pub mod filter_builder;
+pub mod forensic;
+pub mod forward;
+pub mod glob;
+pub mod health;
+pub mod heat_map;
+pub mod hot;
+pub mod influx;
+pub mod inotify_verify;
+pub mod kernel_agg;
+pub mod mock_monitor;
+pub mod monitor_backend;
+pub mod mount_table;
+pub mod notify;
+pub mod overload;
+pub mod pagerduty;
+pub mod path_assembler;
+#[cfg(feature = "remote")]
+pub mod peer_auth;
+pub mod pinning;
+pub mod probe_decode;
+pub mod probes;
+pub mod process_cache;
+pub mod process_list;
+pub mod proto_format;
+pub mod ps;
+pub mod record;
+pub mod redact;
+pub mod remote_monitor;
+pub mod rename_chain;
+pub mod report;
+pub mod retention;
+pub mod rollup;
+pub mod route;
+pub mod schedule;
+pub mod selftest;
+pub mod session;
+pub mod severity;
+pub mod signing;
+pub mod spool;
+pub mod stacks;
+pub mod stats;
+pub mod suggest;
+pub mod syslog;
+pub mod systemd;
+pub mod template;
+pub mod throttle;
+pub mod tripwire;
+pub mod user_filter;
+pub mod verifier;
+pub mod version;
+pub mod wait_for;
+pub mod watcher;
+pub mod watchlist;
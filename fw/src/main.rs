@@ -4,31 +4,145 @@
 //! on the system using eBPF technology for minimal overhead and maximum
 //! visibility.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use clap::error::ErrorKind;
 use clap::Parser;
 use log::{error, info};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
-mod cli;
-mod collector;
-mod ebpf_monitor;
-mod file_event;
+use fw::audit_format::EventFormat;
+use fw::baseline::{BaselineConfig, Profile};
+use fw::capabilities::Capabilities;
+use fw::cli::{
+    self, BaselineCommand, Cli, CollectArgs, Commands, TripwireCommand,
+    WatchlistCommand,
+};
+use fw::clock;
+use fw::collector::{CollectOptions, OutputMode, OutputStream};
+use fw::compression::OutputFile;
+use fw::diagnostics::{self, DiagnosticsConfig};
+use fw::enrich::{EnrichConfig, EnricherKind, EnrichmentLevel};
+use fw::exclusions::Exclusions;
+use fw::exec_hook::ExecConfig;
+use fw::filter::FilterSpec;
+use fw::forward::{read_token, ForwardConfig};
+use fw::glob::{GlobSet, PathGlob};
+use fw::health::HealthServerConfig;
+use fw::influx::InfluxConfig;
+use fw::notify::{NotifyConfig, SmtpConfig};
+use fw::overload::OverloadConfig;
+use fw::pagerduty::PagerDutyConfig;
+use fw::process_list::{ProcessList, ProcessListConfig};
+use fw::record::{RecordConfig, ReplayOptions};
+use fw::redact::RedactConfig;
+use fw::remote_monitor::RemoteConfig;
+use fw::retention::{PruneConfig, RetentionPolicy};
+use fw::rollup::TrendOptions;
+use fw::severity::Classifier;
+use fw::signing::{SigningKey, VerifyingKey};
+use fw::spool::SpoolConfig;
+use fw::stats::{Dimension, ExportFormat, StatsConfig};
+use fw::suggest::SuggestConfig;
+use fw::syslog::SyslogConfig;
+use fw::user_filter::UserFilter;
+use fw::wait_for::WaitCondition;
+use fw::{
+    baseline, bench, binary_format, collector, diff, dry_run, features, hot,
+    kernel_agg, pinning, ps, record, report, retention, rollup, selftest,
+    suggest, tripwire, version, wait_for, watchlist,
+};
 
-use cli::{Cli, Commands};
+/// Compiles command line globs into a set
+type GlobCompiler = fn(&[String]) -> Result<GlobSet, String>;
 
 /// Main entry point for the file watcher application
 ///
 /// Initializes logging, parses command line arguments, and dispatches to
 /// the appropriate command handler.
 fn main() {
-    // Initialize logging
-    env_logger::init();
-
     // Parse command line arguments
     let cli = Cli::parse();
+    if cli.version {
+        if cli.verbose {
+            print!("{}", version::verbose_version(&Capabilities::detect()));
+        } else {
+            print!("{}", version::version());
+        }
+        return;
+    }
+    let Some(command) = cli.command else {
+        cli::command()
+            .error(ErrorKind::MissingSubcommand, "a command is required")
+            .exit();
+    };
+
+    // clap only checks conflicts with --log-file given after the command
+    if let (Commands::Collect(CollectArgs { forensic: true, .. }), Some(_)) =
+        (&command, &cli.log_file)
+    {
+        cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--forensic' cannot be used with '--log-file'",
+            )
+            .exit();
+    }
+
+    // The memory buffer drops whole lines, which would cut binary frames
+    if let Commands::Collect(CollectArgs {
+        forensic: true,
+        format,
+        ..
+    }) = &command
+    {
+        if format.binary().is_some() {
+            cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '--forensic' cannot be used with \
+                         '--format {}'",
+                        format
+                    ),
+                )
+                .exit();
+        }
+    }
+
+    // Initialize logging, away from the stream events were sent to
+    let stream = match &command {
+        Commands::Collect(CollectArgs {
+            output_stream: Some(stream),
+            ..
+        })
+        | Commands::CollectRemote {
+            collect:
+                CollectArgs {
+                    output_stream: Some(stream),
+                    ..
+                },
+            ..
+        } => stream.other(),
+        _ => OutputStream::Stderr,
+    };
+    let diagnostics = DiagnosticsConfig {
+        level: cli.log_level,
+        format: cli.log_format,
+        file: cli.log_file.clone(),
+        stream,
+        quiet: cli.quiet,
+    };
+    if let Err(e) = diagnostics::init(&diagnostics) {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    }
 
     // Execute the requested command and handle any errors
-    if let Err(e) = run_command(cli) {
+    if let Err(e) = run_command(command, cli.quiet) {
         error!("Error: {}", e);
         process::exit(1);
     }
@@ -37,17 +151,751 @@ fn main() {
 /// Execute the requested command based on CLI arguments
 ///
 /// # Arguments
-/// * `cli` - Parsed command line interface structure
+/// * `command` - Parsed command
+/// * `quiet` - Whether diagnostics are kept off stderr
 ///
 /// # Returns
 /// * `Result<()>` - Success or error result
-fn run_command(cli: Cli) -> Result<()> {
-    match cli.command {
-        Commands::Collect { extensions } => {
-            info!("Starting file collection with extensions: {:?}", extensions);
-            collector::run_collect(extensions)
-                .context("Failed to run file collection")?;
+fn run_command(command: Commands, quiet: bool) -> Result<()> {
+    match command {
+        Commands::Collect(args) => {
+            let (dry_run, test_paths) = (args.dry_run, args.test_paths.clone());
+            let options = collect_options(args, quiet)?;
+            start_collection(options, dry_run, &test_paths)?;
+        }
+        Commands::Forward {
+            to,
+            tls_ca,
+            tls_cert,
+            tls_key,
+            server_name,
+            hostname,
+            token_file,
+            collect,
+        } => {
+            features::require("remote", "fw forward")?;
+            if collect.forensic {
+                return Err(anyhow!(
+                    "--forensic opens no sockets; use it with fw collect"
+                ));
+            }
+            if collect.mode != OutputMode::Events
+                || collect.kernel_agg
+                || collect.output.is_some()
+                || collect.output_stream.is_some()
+                || collect.format != EventFormat::Text
+                || collect.export.is_some()
+            {
+                return Err(anyhow!(
+                    "fw forward sends every event to --to; choose --mode, \
+                     --format and outputs on fw collect-remote"
+                ));
+            }
+            let (dry_run, test_paths) =
+                (collect.dry_run, collect.test_paths.clone());
+            let mut options = collect_options(collect, quiet)?;
+            // clap requires the certificate and key together
+            let client_cert = tls_cert.zip(tls_key);
+            let mut forward = ForwardConfig::new(
+                &to,
+                tls_ca,
+                client_cert,
+                server_name,
+                hostname,
+            )?;
+            forward.token =
+                token_file.as_deref().map(read_token).transpose()?;
+            options.forward = Some(forward);
+            start_collection(options, dry_run, &test_paths)?;
+        }
+        Commands::CollectRemote {
+            listen,
+            tls_cert,
+            tls_key,
+            client_ca,
+            client_allowlist,
+            tokens,
+            collect,
+        } => {
+            features::require("remote", "fw collect-remote")?;
+            if collect.forensic {
+                return Err(anyhow!(
+                    "--forensic opens no sockets; use it with fw collect"
+                ));
+            }
+            if collect.kernel_agg
+                || collect.snapshot
+                || collect.bpf_object.is_some()
+                || collect.access_patterns
+                || collect.stacks.is_some()
+                || !collect.users.is_empty()
+                || !collect.exclude_users.is_empty()
+                || collect.process_list_file.is_some()
+                || !collect.verify_with_inotify.is_empty()
+                || collect.tripwires.is_some()
+                || collect.reuse_pinned
+                || collect.instance.is_some()
+                || collect.shared
+                || !collect.enrich.is_empty()
+                || collect.enrichment == EnrichmentLevel::Full
+                || collect.min_size.is_some()
+                || collect.max_size.is_some()
+                || collect.newer_than.is_some()
+            {
+                return Err(anyhow!(
+                    "Options that probe, stat or look up users on this host \
+                     go on the forwarders (fw forward)"
+                ));
+            }
+            let (dry_run, test_paths) =
+                (collect.dry_run, collect.test_paths.clone());
+            let mut options = collect_options(collect, quiet)?;
+            options.remote = Some(RemoteConfig {
+                listen,
+                cert: tls_cert,
+                key: tls_key,
+                client_ca,
+                allowlist: client_allowlist,
+                tokens,
+            });
+            start_collection(options, dry_run, &test_paths)?;
+        }
+        Commands::Bench { workload, duration } => {
+            info!(
+                "Starting benchmark: {} opens/sec for {}s",
+                workload, duration
+            );
+            bench::run_bench(workload, duration)
+                .context("Failed to run benchmark")?;
+        }
+        Commands::Report { from, format } => {
+            report::run_report(&from, format)
+                .context("Failed to generate report")?;
+        }
+        Commands::Diff {
+            baseline,
+            current,
+            group_by,
+            format,
+        } => {
+            diff::run_diff(&baseline, &current, group_by.as_deref(), format)?;
+        }
+        Commands::Decode { inputs, from } => {
+            binary_format::run_decode(&inputs, from)?;
+        }
+        Commands::Baseline {
+            command:
+                BaselineCommand::Learn {
+                    inputs,
+                    output,
+                    depth,
+                },
+        } => {
+            baseline::run_learn(&inputs, &output, depth)?;
+        }
+        Commands::Watchlist { file, command } => match command {
+            WatchlistCommand::Add { name, globs } => {
+                watchlist::run_add(&file, &name, &globs)?
+            }
+            WatchlistCommand::Remove { name, globs } => {
+                watchlist::run_remove(&file, &name, &globs)?
+            }
+            WatchlistCommand::List { name } => {
+                watchlist::run_list(&file, name.as_deref())?
+            }
+        },
+        Commands::Tripwire { file, command } => match command {
+            TripwireCommand::Add { paths, content } => {
+                tripwire::run_add(&file, &paths, &content)?
+            }
+            TripwireCommand::Remove { paths, delete } => {
+                tripwire::run_remove(&file, &paths, delete)?
+            }
+            TripwireCommand::List => tripwire::run_list(&file)?,
+        },
+        Commands::SuggestFilters {
+            from,
+            sample,
+            target,
+            keep_rare,
+            max_rules,
+            output,
+        } => {
+            let config = SuggestConfig {
+                target: f64::from(target) / 100.0,
+                keep_rare,
+                max_rules: max_rules as usize,
+            };
+            suggest::run_suggest(&from, sample, &config, output.as_deref())?;
+        }
+        Commands::Record {
+            output,
+            resume,
+            checkpoint_interval,
+            compress,
+            compress_level,
+            sign_key,
+            rollup,
+            max_age,
+            max_size,
+            prune_names,
+            prune_interval,
+        } => {
+            let output = OutputFile::new(output, compress, compress_level)?
+                .with_append(resume);
+            let prune = prune_config(
+                &output.path,
+                RetentionPolicy {
+                    max_age,
+                    max_bytes: max_size,
+                },
+                &prune_names,
+                prune_interval,
+            )?;
+            let (resume, chain) = if resume {
+                record::resume_point(&output)?.unzip()
+            } else {
+                (None, None)
+            };
+            let signer =
+                sign_key.as_deref().map(SigningKey::load).transpose()?;
+            info!("Recording to {}", output.path.display());
+            let rollup = rollup.then(|| rollup::rollup_path(&output.path));
+            collector::run_collect(CollectOptions {
+                output: Some(output),
+                record: Some(RecordConfig {
+                    interval: checkpoint_interval,
+                    resume,
+                    chain: chain.unwrap_or_default(),
+                    signer,
+                }),
+                rollup,
+                prune,
+                quiet,
+                ..Default::default()
+            })
+            .context("Failed to record")?;
+        }
+        Commands::Verify { input, key } => {
+            let key = VerifyingKey::load(&key)?;
+            record::run_verify(&input, &key)?;
+        }
+        Commands::Prune {
+            dir,
+            max_age,
+            max_size,
+            names,
+            dry_run,
+        } => {
+            if max_age.is_none() && max_size.is_none() {
+                return Err(anyhow!("prune needs --max-age or --max-size"));
+            }
+            let names = name_globs(&names)?;
+            let policy = RetentionPolicy {
+                max_age,
+                max_bytes: max_size,
+            };
+            retention::run_prune(&dir, names.as_ref(), &policy, dry_run)
+                .context("Prune failed")?;
+        }
+        Commands::Replay {
+            input,
+            speed,
+            from,
+            to,
+            follow,
+        } => {
+            let options = ReplayOptions {
+                speed,
+                from,
+                to,
+                follow,
+            };
+            record::run_replay(&input, &options, &*clock::system())
+                .context("Replay failed")?;
+        }
+        Commands::Trend {
+            input,
+            from,
+            to,
+            every,
+        } => {
+            let options = TrendOptions {
+                from,
+                to,
+                resolution: every,
+            };
+            rollup::run_trend(&input, &options)?;
+        }
+        Commands::WaitFor {
+            path,
+            action,
+            timeout,
+        } => {
+            let condition = WaitCondition {
+                path: PathGlob::new(path),
+                action,
+            };
+            wait_for::run_wait_for(condition, timeout)?;
+        }
+        Commands::Hot { window, top, by } => {
+            hot::run_hot(window, top as usize, by).context("fw hot failed")?;
+        }
+        Commands::Ps { path, watch } => {
+            ps::run_ps(path.map(PathGlob::new), watch)
+                .context("fw ps failed")?;
+        }
+        Commands::Cleanup { instance, force } => {
+            info!("Removing stale pinned BPF state");
+            pinning::run_cleanup(instance, force).context("Cleanup failed")?;
+        }
+        Commands::Selftest => {
+            info!("Starting pipeline self-test");
+            selftest::run_selftest().context("Self-test failed")?;
+        }
+        Commands::Completions { shell } => {
+            cli::write_completions(shell, &mut io::stdout());
+        }
+        Commands::Man => {
+            cli::write_man_page(&mut io::stdout())
+                .context("Failed to write man page")?;
         }
     }
     Ok(())
 }
+
+/// Validate `fw collect` options and turn them into collector settings
+///
+/// # Arguments
+/// * `args` - Parsed collect options (`--dry-run` and `--test-path` are
+///   left to the caller)
+/// * `quiet` - Whether diagnostics are kept off stderr
+///
+/// # Returns
+/// * `Result<CollectOptions>` - Collector settings, or error if options
+///   conflict
+fn collect_options(args: CollectArgs, quiet: bool) -> Result<CollectOptions> {
+    let CollectArgs {
+        extensions,
+        name_globs,
+        path_globs,
+        case_sensitive,
+        mounts,
+        fs_types,
+        remote_only,
+        local_only,
+        users,
+        exclude_users,
+        process_list_file,
+        process_list_mode,
+        min_latency_ns,
+        min_size,
+        max_size,
+        newer_than,
+        types,
+        include_pseudo_fs,
+        xattr_namespaces,
+        tag_map,
+        watchlists,
+        tripwires,
+        tripwire_stacks,
+        enrich,
+        tags,
+        severity_policy,
+        min_severity,
+        enrichment,
+        enrich_workers,
+        overload_threshold,
+        no_overload_protection,
+        overload_sampling,
+        reuse_pinned,
+        instance,
+        shared,
+        snapshot,
+        bpf_object,
+        process_cache_size,
+        mode,
+        group_by,
+        depth,
+        contention,
+        export,
+        export_format,
+        kernel_agg,
+        agg_interval,
+        access_patterns,
+        stacks,
+        exec,
+        exec_concurrency,
+        exec_timeout,
+        syslog,
+        syslog_facility,
+        notify_webhooks,
+        notify_smtp,
+        notify_smtp_user,
+        notify_smtp_password_file,
+        notify_from,
+        notify_to,
+        notify_min_severity,
+        notify_template,
+        notify_ca,
+        pagerduty_key_file,
+        pagerduty_min_severity,
+        pagerduty_url,
+        influx_url,
+        influx_token_file,
+        influx_interval,
+        influx_group_by,
+        exclude_file,
+        baseline,
+        verify_with_inotify,
+        routes,
+        redact,
+        anonymize_home,
+        redact_salt_file,
+        schedule,
+        spool_dir,
+        spool_max_bytes,
+        spool_drop,
+        output,
+        output_stream,
+        forensic,
+        forensic_buffer,
+        format,
+        template,
+        compress,
+        compress_level,
+        dry_run: _,
+        test_paths: _,
+        health_addr,
+        live_after,
+        ready_after,
+    } = args;
+    if enrichment == EnrichmentLevel::Off
+        && (tag_map.is_some()
+            || watchlists.is_some()
+            || tripwires.is_some()
+            || !enrich.is_empty()
+            || severity_policy.is_some()
+            || !tags.is_empty()
+            || min_severity.is_some()
+            || min_size.is_some()
+            || max_size.is_some()
+            || newer_than.is_some())
+    {
+        return Err(anyhow!(
+            "--enrichment off can't be combined with options that \
+             need tags, severity or file stats"
+        ));
+    }
+    if kernel_agg {
+        kernel_agg::check_dimensions(&group_by).map_err(|e| anyhow!(e))?;
+    }
+    if access_patterns && mode == OutputMode::Events {
+        return Err(anyhow!(
+            "--access-patterns needs --mode sessions or --mode stats"
+        ));
+    }
+    if depth.is_some()
+        && (mode != OutputMode::Stats
+            || !matches!(group_by[..], [Dimension::DirDepth(_)]))
+    {
+        return Err(anyhow!(
+            "--depth needs --mode stats and --group-by dir alone"
+        ));
+    }
+    if contention && mode != OutputMode::Stats {
+        return Err(anyhow!("--contention needs --mode stats"));
+    }
+    if format != EventFormat::Text && mode != OutputMode::Events {
+        return Err(anyhow!("--format {} needs --mode events", format));
+    }
+    if template.is_some() && format != EventFormat::Template {
+        return Err(anyhow!("--template needs --format template"));
+    }
+    if mode == OutputMode::Sessions && export_format == Some(ExportFormat::Csv)
+    {
+        return Err(anyhow!("Sessions can only be exported as JSON"));
+    }
+    // clap rejects passing both flags
+    let remote = match (remote_only, local_only) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let globs = |patterns: Vec<String>, compile: GlobCompiler| {
+        (!patterns.is_empty())
+            .then(|| compile(&patterns))
+            .transpose()
+            .map(|set| set.map(|s| s.with_case_sensitive(case_sensitive)))
+            .map_err(|e| anyhow!(e))
+    };
+    let filter = FilterSpec {
+        extensions,
+        name_globs: globs(name_globs, GlobSet::names)?,
+        path_globs: globs(path_globs, GlobSet::paths)?,
+        case_sensitive,
+        mounts,
+        fs_types,
+        remote,
+        min_latency_ns,
+        xattr_namespaces: Some(xattr_namespaces),
+        tags: (!tags.is_empty()).then_some(tags),
+        min_severity,
+        min_size,
+        max_size,
+        newer_than,
+        types,
+        exclude_pseudo_fs: !include_pseudo_fs,
+        exclusions: exclude_file
+            .map(|path| Exclusions::load(&path).map(Arc::new))
+            .transpose()?,
+        ..Default::default()
+    };
+    let mut enrich = enrich;
+    if filter.needs_file_stats() && !enrich.contains(&EnricherKind::File) {
+        enrich.push(EnricherKind::File);
+    }
+    let users = UserFilter::resolve(&users, &exclude_users)?;
+    let exec = exec
+        .map(|template| {
+            ExecConfig::new(&template, exec_concurrency, exec_timeout)
+        })
+        .transpose()?;
+    let output = output
+        .map(|path| OutputFile::new(path, compress, compress_level))
+        .transpose()?;
+    // clap requires the password file with the user, and the addresses
+    // with the server
+    let login = match notify_smtp_user.zip(notify_smtp_password_file) {
+        Some((user, path)) => Some((user, read_token(&path)?)),
+        None => None,
+    };
+    let email = notify_smtp.map(|(host, port)| SmtpConfig {
+        host,
+        port,
+        login,
+        from: notify_from.unwrap_or_default(),
+        to: notify_to,
+    });
+    let notifies = email.is_some() || !notify_webhooks.is_empty();
+    if (notifies || pagerduty_key_file.is_some())
+        && enrichment == EnrichmentLevel::Off
+    {
+        return Err(anyhow!(
+            "Notifications and paging need severities; drop --enrichment off"
+        ));
+    }
+    let hostname = nix::unistd::gethostname()
+        .context("Failed to read the host name")?
+        .to_string_lossy()
+        .into_owned();
+    let pagerduty = match pagerduty_key_file {
+        Some(path) => Some(PagerDutyConfig {
+            routing_key: read_token(&path)?,
+            url: pagerduty_url,
+            min_severity: pagerduty_min_severity,
+            template: notify_template.clone(),
+            resolve_after: match &severity_policy {
+                Some(path) => Classifier::load(path)?.resolve_after(),
+                None => Default::default(),
+            },
+            ca: notify_ca.clone(),
+            hostname: hostname.clone(),
+        }),
+        None => None,
+    };
+    let influx = match influx_url {
+        Some(url) => Some(InfluxConfig {
+            url,
+            token: influx_token_file.as_deref().map(read_token).transpose()?,
+            interval: influx_interval,
+            group_by: influx_group_by,
+            ca: notify_ca.clone(),
+            hostname: hostname.clone(),
+        }),
+        None => None,
+    };
+    if let Some(config) = &influx {
+        config.validate()?;
+    }
+    let notify = notifies.then_some(NotifyConfig {
+        webhooks: notify_webhooks,
+        email,
+        min_severity: notify_min_severity,
+        template: notify_template,
+        ca: notify_ca,
+        hostname,
+    });
+    Ok(CollectOptions {
+        filter,
+        enrich: EnrichConfig {
+            level: enrichment,
+            workers: enrich_workers as usize,
+            tag_map,
+            kinds: enrich,
+            severity_policy,
+            watchlists: None,
+            tripwires: tripwires
+                .map(|path| tripwire::load(&path))
+                .transpose()?
+                .unwrap_or_default(),
+            tripwire_stacks,
+            forensic,
+        },
+        reuse_pinned,
+        instance,
+        shared,
+        snapshot,
+        bpf_object,
+        process_cache_size: Some(process_cache_size as usize),
+        exec,
+        syslog: syslog.then(|| SyslogConfig {
+            facility: syslog_facility,
+            ..Default::default()
+        }),
+        notify,
+        pagerduty,
+        influx,
+        baseline: baseline
+            .map(|path| {
+                Profile::load(&path).map(|profile| BaselineConfig {
+                    profile: Arc::new(profile),
+                })
+            })
+            .transpose()?,
+        verify_inotify: verify_with_inotify,
+        routes,
+        redact: RedactConfig {
+            rules: redact,
+            anonymize_home,
+            salt: redact_salt_file,
+        },
+        mode: if kernel_agg { OutputMode::Stats } else { mode },
+        format,
+        template,
+        stats: StatsConfig {
+            group_by,
+            export: export.map(|path| {
+                let format = export_format
+                    .unwrap_or_else(|| ExportFormat::from_path(&path));
+                (path, format)
+            }),
+            heat_map_depth: depth.map(|depth| depth as usize),
+            contention,
+        },
+        kernel_agg: kernel_agg.then_some(agg_interval),
+        access_patterns,
+        stacks,
+        overload: (!no_overload_protection).then(|| OverloadConfig {
+            threshold: f64::from(overload_threshold) / 100.0,
+            sampling: overload_sampling,
+            ..Default::default()
+        }),
+        users,
+        process_list: process_list_file.map(|path| ProcessListConfig {
+            path,
+            mode: process_list_mode,
+        }),
+        processes: ProcessList::default(),
+        watchlists,
+        schedule,
+        spool: spool_dir.map(|dir| SpoolConfig {
+            dir,
+            max_bytes: spool_max_bytes,
+            drop_policy: spool_drop,
+        }),
+        output,
+        record: None,
+        rollup: None,
+        prune: None,
+        health: health_addr.map(|addr| HealthServerConfig {
+            addr,
+            live_after,
+            ready_after,
+        }),
+        quiet,
+        stream: output_stream,
+        forward: None,
+        remote: None,
+        forensic: forensic.then_some(forensic_buffer),
+    })
+}
+
+/// Run a collection, or only show what it would do with --dry-run
+///
+/// # Arguments
+/// * `options` - Collector settings
+/// * `dry_run` - Print the compiled settings instead of collecting
+/// * `test_paths` - Paths to check against the filter with --dry-run
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+fn start_collection(
+    options: CollectOptions,
+    dry_run: bool,
+    test_paths: &[String],
+) -> Result<()> {
+    if dry_run {
+        return dry_run::run_dry_run(&options, test_paths);
+    }
+    info!("Starting file collection with filter: {:?}", options.filter);
+    collector::run_collect(options).context("Failed to run file collection")
+}
+
+/// Compile `--name` style globs
+///
+/// # Arguments
+/// * `names` - Name globs from the command line
+///
+/// # Returns
+/// * `Result<Option<GlobSet>>` - Compiled globs, None if there are none,
+///   or error if one is invalid
+fn name_globs(names: &[String]) -> Result<Option<GlobSet>> {
+    if names.is_empty() {
+        return Ok(None);
+    }
+    GlobSet::names(names).map(Some).map_err(|e| anyhow!(e))
+}
+
+/// Retention to apply to the directory of a recording while it runs
+///
+/// # Arguments
+/// * `recording` - Recording being written
+/// * `policy` - Maximum age and size
+/// * `names` - Name globs of the files the policy applies to
+/// * `interval` - Time between prunes
+///
+/// # Returns
+/// * `Result<Option<PruneConfig>>` - Prune settings, None without a
+///   limit, or error if the globs or interval are invalid
+fn prune_config(
+    recording: &Path,
+    policy: RetentionPolicy,
+    names: &[String],
+    interval: Duration,
+) -> Result<Option<PruneConfig>> {
+    if policy.max_age.is_none() && policy.max_bytes.is_none() {
+        if !names.is_empty() {
+            return Err(anyhow!("--prune-name needs --max-age or --max-size"));
+        }
+        return Ok(None);
+    }
+    if interval.is_zero() {
+        return Err(anyhow!("--prune-interval must be positive"));
+    }
+    let dir = match recording.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let keep = [recording.to_path_buf(), rollup::rollup_path(recording)]
+        .iter()
+        .filter_map(|path| path.file_name().map(ToOwned::to_owned))
+        .collect();
+    Ok(Some(PruneConfig {
+        dir,
+        names: name_globs(names)?,
+        policy,
+        interval,
+        keep,
+    }))
+}
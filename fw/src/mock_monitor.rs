@@ -0,0 +1,153 @@
+//! Mock Monitor module
+//!
+//! Provides a `MonitorBackend` that replays a predefined list of events and
+//! then closes its channel. Used by `fw selftest` and by tests that need a
+//! deterministic event stream instead of real kernel activity.
+
+use anyhow::{anyhow, Result};
+use log::info;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::clock::Clock;
+use crate::file_event::FileEvent;
+use crate::kernel_agg::{CounterSource, KernelCounts};
+use crate::monitor_backend::MonitorBackend;
+
+/// Backend that emits a fixed sequence of events
+pub struct MockMonitor {
+    /// Events to send, in order, when monitoring starts
+    events: Vec<FileEvent>,
+    /// Kernel counters returned by successive reads, oldest first
+    counts: Vec<KernelCounts>,
+    /// Clock to restamp events with as they are sent, if any
+    clock: Option<Arc<dyn Clock>>,
+    /// Whether `start_monitoring` has been called
+    is_monitoring: bool,
+}
+
+impl MockMonitor {
+    /// Create a mock monitor that will replay the given events
+    ///
+    /// # Arguments
+    /// * `events` - Events to emit in order
+    ///
+    /// # Returns
+    /// * `MockMonitor` - New mock monitor
+    pub fn new(events: Vec<FileEvent>) -> Self {
+        Self {
+            events,
+            counts: Vec::new(),
+            clock: None,
+            is_monitoring: false,
+        }
+    }
+
+    /// Return these counters from successive kernel counter reads
+    ///
+    /// # Arguments
+    /// * `counts` - One set of counters per read; later reads are empty
+    ///
+    /// # Returns
+    /// * `MockMonitor` - The monitor with the counters queued
+    pub fn with_counts(mut self, counts: Vec<KernelCounts>) -> Self {
+        self.counts = counts;
+        self
+    }
+
+    /// Stamp each event with the time from a clock as it is sent,
+    /// replacing the timestamp it was built with
+    ///
+    /// # Arguments
+    /// * `clock` - Time source, e.g. a stepping `MockClock`
+    ///
+    /// # Returns
+    /// * `MockMonitor` - The monitor using the clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+impl CounterSource for MockMonitor {
+    /// Hand out the next queued set of counters
+    fn drain_counts(&mut self) -> Result<KernelCounts> {
+        if self.counts.is_empty() {
+            return Ok(KernelCounts::default());
+        }
+        Ok(self.counts.remove(0))
+    }
+}
+
+impl MonitorBackend for MockMonitor {
+    /// Send all events on a channel sized to hold them, then close it
+    async fn start_monitoring(&mut self) -> Result<mpsc::Receiver<FileEvent>> {
+        if self.is_monitoring {
+            return Err(anyhow!("Monitor is already running"));
+        }
+
+        info!("Starting mock monitoring with {} events", self.events.len());
+
+        let (tx, rx) = mpsc::channel(self.events.len().max(1));
+        for mut event in self.events.drain(..) {
+            if let Some(clock) = &self.clock {
+                event.timestamp = clock.now();
+            }
+            tx.send(event)
+                .await
+                .map_err(|e| anyhow!("Failed to queue mock event: {}", e))?;
+        }
+
+        self.is_monitoring = true;
+        Ok(rx)
+    }
+
+    /// Mark the mock monitor as stopped
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        self.is_monitoring = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::clock::SystemClock;
+    use crate::file_event::FileAction;
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_mock_monitor_replays_events_then_closes() {
+        let events = vec![
+            FileEvent::new(
+                "/a.rs".to_string(),
+                "rustc".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            ),
+            FileEvent::new(
+                "/a.rs".to_string(),
+                "rustc".to_string(),
+                FileAction::Closed,
+                1,
+                SystemClock.now(),
+            ),
+        ];
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = MockClock::new(start).with_step(Duration::from_secs(1));
+        let mut monitor = MockMonitor::new(events).with_clock(Arc::new(clock));
+        let mut rx = monitor.start_monitoring().await.unwrap();
+
+        let opened = rx.recv().await.unwrap();
+        assert_eq!(opened.action, FileAction::Opened);
+        assert_eq!(opened.timestamp, start);
+        let closed = rx.recv().await.unwrap();
+        assert_eq!(closed.action, FileAction::Closed);
+        assert_eq!((closed.timestamp - start).num_seconds(), 1);
+        assert!(rx.recv().await.is_none());
+        assert!(monitor.stop_monitoring().await.is_ok());
+    }
+}
@@ -0,0 +1,60 @@
+//! Monitor Backend module
+//!
+//! Defines the interface between the event pipeline and whatever produces
+//! file events. The eBPF monitor is the production backend; the mock
+//! backend feeds a fixed event stream for self-tests and unit tests.
+
+use anyhow::Result;
+use std::future::Future;
+use tokio::sync::mpsc;
+
+use crate::file_event::FileEvent;
+use crate::filter_builder::KernelFilterPlan;
+
+/// A source of file events that can be started and stopped
+pub trait MonitorBackend {
+    /// Start producing events
+    ///
+    /// # Returns
+    /// * `Result<mpsc::Receiver<FileEvent>>` - Event receiver or error. The
+    ///   channel closes when the backend has no more events to send.
+    fn start_monitoring(
+        &mut self,
+    ) -> impl Future<Output = Result<mpsc::Receiver<FileEvent>>> + Send;
+
+    /// Stop producing events and release any resources
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    fn stop_monitoring(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Filter events before they are sent, e.g. by populating the
+    /// kernel's filter maps and attaching only the probes the filter
+    /// needs; backends that can't filter send everything
+    ///
+    /// # Arguments
+    /// * `plan` - Map entries and probe features from a compiled filter
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    fn apply_kernel_plan(&mut self, _plan: &KernelFilterPlan) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stop producing events without closing the channel, e.g. by
+    /// detaching probes; backends that can't pause keep producing
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Produce events again after `pause`
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    fn resume(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,654 @@
+//! Mount Table module
+//!
+//! Maps file paths to the filesystem they live on by reading the kernel's
+//! mount list from `/proc/self/mounts`. Used to annotate events with the
+//! filesystem type and to filter by mount point or filesystem type. A
+//! table made with [`MountTable::follow`] is reloaded whenever the kernel
+//! reports a change on `/proc/self/mountinfo`, so filesystems mounted
+//! after fw started (e.g. a new container's overlay) are annotated too.
+//!
+//! Overlay filesystems (container root filesystems) are assembled from
+//! an upper and several lower directories. Events reached through those
+//! directories rather than the merged mount are mapped back to the merged
+//! path, and tagged with the layer touched, so filters written against
+//! the merged tree match them.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::watch;
+
+use crate::file_event::FileEvent;
+pub use fw_common::event::OverlayLayer;
+
+/// Location of the mount list for the current mount namespace
+const PROC_MOUNTS: &str = "/proc/self/mounts";
+
+/// Mount list the kernel flags as changed when anything is mounted or
+/// unmounted in the namespace
+const PROC_MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Filesystem types whose data lives on another host
+const REMOTE_FS_TYPES: [&str; 10] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "afs",
+    "fuse.sshfs",
+];
+
+/// A single mounted filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    /// Device or remote source (e.g. `/dev/sda1`, `server:/export`)
+    pub source: String,
+    /// Directory the filesystem is mounted on
+    pub mount_point: String,
+    /// Filesystem type (e.g. `ext4`, `tmpfs`, `overlay`)
+    pub fs_type: String,
+    /// Directories an overlay is assembled from (overlays only)
+    pub overlay: Option<OverlayDirs>,
+}
+
+/// Directories an overlay filesystem is assembled from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverlayDirs {
+    /// Writable upper directory, absent for read-only overlays
+    pub upper: Option<String>,
+    /// Read-only lower directories, topmost first
+    pub lower: Vec<String>,
+}
+
+impl OverlayDirs {
+    /// Parse the `upperdir=` and `lowerdir=` mount options
+    ///
+    /// # Arguments
+    /// * `options` - Comma separated options from the mount list
+    ///
+    /// # Returns
+    /// * `OverlayDirs` - Directories found, empty if none
+    fn parse(options: &str) -> Self {
+        let mut dirs = Self::default();
+        for option in options.split(',') {
+            match option.split_once('=') {
+                Some(("upperdir", dir)) => {
+                    dirs.upper = Some(unescape_octal(dir));
+                }
+                Some(("lowerdir", list)) => {
+                    dirs.lower = list.split(':').map(unescape_octal).collect();
+                }
+                _ => {}
+            }
+        }
+        dirs
+    }
+
+    /// Find the directory of this overlay that contains a path
+    ///
+    /// # Arguments
+    /// * `path` - Absolute file path
+    ///
+    /// # Returns
+    /// * `Option<(OverlayLayer, &str)>` - Layer and its directory, or None
+    ///   if the path is in none of them
+    fn layer_of(&self, path: &Path) -> Option<(OverlayLayer, &str)> {
+        let upper = self.upper.iter().map(|dir| (OverlayLayer::Upper, dir));
+        let lower = self
+            .lower
+            .iter()
+            .enumerate()
+            .map(|(index, dir)| (OverlayLayer::Lower(index), dir));
+        upper
+            .chain(lower)
+            .find(|(_, dir)| path.starts_with(dir))
+            .map(|(layer, dir)| (layer, dir.as_str()))
+    }
+}
+
+impl MountEntry {
+    /// Check whether this filesystem is served by another host
+    ///
+    /// # Returns
+    /// * `bool` - True for network filesystems such as NFS or CIFS
+    pub fn is_remote(&self) -> bool {
+        REMOTE_FS_TYPES.contains(&self.fs_type.as_str())
+    }
+}
+
+/// System mount list, as of the last load
+#[derive(Debug, Clone)]
+pub struct MountTable {
+    /// Entries ordered by mount point length, longest first, so the first
+    /// prefix match is the most specific mount; replaced on every reload
+    /// of a followed table
+    entries: watch::Receiver<Arc<Vec<MountEntry>>>,
+}
+
+impl Default for MountTable {
+    fn default() -> Self {
+        Self::fixed(Vec::new())
+    }
+}
+
+/// Notices changes to the mount list of a followed [`MountTable`]
+pub struct MountWatcher {
+    /// Mount list, polled for the kernel's change notifications
+    mountinfo: AsyncFd<File>,
+    /// Channel the table reads its current entries from
+    tx: watch::Sender<Arc<Vec<MountEntry>>>,
+}
+
+impl MountTable {
+    /// Create an empty mount table that annotates nothing
+    ///
+    /// # Returns
+    /// * `MountTable` - Table with no entries
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Create a table that never changes
+    fn fixed(entries: Vec<MountEntry>) -> Self {
+        // The receiver keeps the last value once the sender is gone
+        let (_, entries) = watch::channel(Arc::new(entries));
+        Self { entries }
+    }
+
+    /// Load the current mount list from `/proc/self/mounts`
+    ///
+    /// # Returns
+    /// * `Result<MountTable>` - Parsed table or error if unreadable
+    pub fn load() -> Result<Self> {
+        Ok(Self::fixed(load_entries()?))
+    }
+
+    /// Load the current mount list and keep it up to date
+    ///
+    /// The table follows the mount list while [`supervise`] runs the
+    /// returned watcher.
+    ///
+    /// # Returns
+    /// * `Result<(MountTable, MountWatcher)>` - Table and the watcher
+    ///   reloading it, or error if the mount list can't be read or
+    ///   watched
+    pub fn follow() -> Result<(Self, MountWatcher)> {
+        let mountinfo = File::open(PROC_MOUNTINFO)
+            .with_context(|| format!("Failed to open {}", PROC_MOUNTINFO))?;
+        let mountinfo = AsyncFd::with_interest(mountinfo, Interest::PRIORITY)
+            .with_context(|| {
+            format!("Failed to watch {}", PROC_MOUNTINFO)
+        })?;
+        let (tx, entries) = watch::channel(Arc::new(load_entries()?));
+        Ok((Self { entries }, MountWatcher { mountinfo, tx }))
+    }
+
+    /// Parse mount list text in `/proc/mounts` format
+    ///
+    /// Malformed lines are skipped.
+    ///
+    /// # Arguments
+    /// * `text` - Contents of a mounts file
+    ///
+    /// # Returns
+    /// * `MountTable` - Parsed table
+    pub fn parse(text: &str) -> Self {
+        Self::fixed(parse_entries(text))
+    }
+
+    /// Current entries, released before use so reloads never wait
+    fn entries(&self) -> Arc<Vec<MountEntry>> {
+        self.entries.borrow().clone()
+    }
+
+    /// Find the mount that contains the given path
+    ///
+    /// # Arguments
+    /// * `path` - Absolute file path
+    ///
+    /// # Returns
+    /// * `Option<MountEntry>` - Most specific mount, or None for relative
+    ///   paths or an empty table
+    pub fn lookup(&self, path: &str) -> Option<MountEntry> {
+        lookup(&self.entries(), path).cloned()
+    }
+
+    /// Map a path inside an overlay's upper or lower directory to the
+    /// merged mount
+    ///
+    /// The longest layer directory containing the path is the one it was
+    /// reached through. A lower directory shared by several overlays
+    /// (e.g. an image layer used by several containers) maps to the one
+    /// with the longest mount point, as the same file is seen through
+    /// each of them.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute file path
+    ///
+    /// # Returns
+    /// * `Option<(String, OverlayLayer)>` - Merged path and the layer the
+    ///   path was in, or None if it is in no overlay directory
+    pub fn merged_path(&self, path: &str) -> Option<(String, OverlayLayer)> {
+        let entries = self.entries();
+        let (mount_point, layer, dir) = overlay_dir_of(&entries, path)?;
+        Some((rebase(path, dir, mount_point), layer))
+    }
+
+    /// Annotate an event with the mount point and type of its filesystem
+    ///
+    /// Files on network filesystems also get the remote source (e.g. the
+    /// NFS server export). Paths inside an overlay's upper or lower
+    /// directory are first rewritten to the merged mount and the layer is
+    /// recorded. Events whose path can't be resolved (e.g. relative
+    /// paths) are left unannotated.
+    ///
+    /// # Arguments
+    /// * `event` - Event to annotate in place
+    pub fn annotate(&self, event: &mut FileEvent) {
+        let entries = self.entries();
+        if let Some((mount_point, layer, dir)) =
+            overlay_dir_of(&entries, &event.file_path)
+        {
+            // The directory is UTF-8, so raw bytes share its prefix and
+            // the invalid bytes come after it
+            if let Some(raw) = &mut event.raw_path {
+                let prefix = mount_point.trim_end_matches('/');
+                *raw = [prefix.as_bytes(), &raw[dir.len()..]].concat();
+            }
+            event.file_path = rebase(&event.file_path, dir, mount_point);
+            event.overlay_layer = Some(layer);
+        }
+        if let Some(entry) = lookup(&entries, &event.file_path) {
+            event.mount_point = Some(entry.mount_point.clone());
+            event.fs_type = Some(entry.fs_type.clone());
+            event.remote_source =
+                entry.is_remote().then(|| entry.source.clone());
+        }
+    }
+
+    /// Check whether a directory is a mount point in this table
+    ///
+    /// # Arguments
+    /// * `mount_point` - Directory to check
+    ///
+    /// # Returns
+    /// * `bool` - True if a filesystem is mounted there
+    pub fn contains_mount_point(&self, mount_point: &str) -> bool {
+        let mount_point = normalize_mount_point(mount_point);
+        self.entries().iter().any(|e| e.mount_point == mount_point)
+    }
+
+    /// Parse one line of the mount list
+    ///
+    /// # Arguments
+    /// * `line` - Line of the form `source target fstype options ...`
+    ///
+    /// # Returns
+    /// * `Option<MountEntry>` - Parsed entry, or None if malformed
+    fn parse_line(line: &str) -> Option<MountEntry> {
+        let mut fields = line.split_whitespace();
+        let source = unescape_octal(fields.next()?);
+        let mount_point = unescape_octal(fields.next()?);
+        let fs_type = fields.next()?.to_string();
+        let overlay = (fs_type == "overlay")
+            .then(|| OverlayDirs::parse(fields.next().unwrap_or_default()));
+        Some(MountEntry {
+            source,
+            mount_point,
+            fs_type,
+            overlay,
+        })
+    }
+}
+
+/// Reload a followed table whenever the mount list changes
+///
+/// Runs until the table and its clones are dropped. A mount list that
+/// fails to load is logged and the previous one kept.
+///
+/// # Arguments
+/// * `watcher` - Change notifications for the mount list
+pub async fn supervise(watcher: MountWatcher) {
+    let MountWatcher { mountinfo, tx } = watcher;
+    loop {
+        tokio::select! {
+            result = mountinfo.ready(Interest::PRIORITY) => match result {
+                Ok(mut guard) => guard.clear_ready(),
+                Err(e) => {
+                    warn!("Stopped watching the mount list: {}", e);
+                    return;
+                }
+            },
+            _ = tx.closed() => return,
+        }
+        match load_entries() {
+            Ok(entries) => {
+                info!("Reloaded {} mounts", entries.len());
+                tx.send_replace(Arc::new(entries));
+            }
+            Err(e) => warn!("Keeping the previous mount list: {:#}", e),
+        }
+    }
+}
+
+/// Read and parse `/proc/self/mounts`
+///
+/// # Returns
+/// * `Result<Vec<MountEntry>>` - Entries, longest mount point first, or
+///   error if the file is unreadable
+fn load_entries() -> Result<Vec<MountEntry>> {
+    let text = fs::read_to_string(PROC_MOUNTS)
+        .with_context(|| format!("Failed to read {}", PROC_MOUNTS))?;
+    let mut entries = parse_entries(&text);
+    resolve_overlay_dirs(&mut entries);
+    Ok(entries)
+}
+
+/// Parse mount list text, longest mount point first
+///
+/// # Arguments
+/// * `text` - Contents of a mounts file
+///
+/// # Returns
+/// * `Vec<MountEntry>` - Entries of the well-formed lines
+fn parse_entries(text: &str) -> Vec<MountEntry> {
+    let mut entries: Vec<MountEntry> =
+        text.lines().filter_map(MountTable::parse_line).collect();
+
+    // Later mounts shadow earlier ones on the same directory; reversing
+    // before the stable sort keeps the latest mount first
+    entries.reverse();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.mount_point.len()));
+    entries
+}
+
+/// Replace overlay directories with their canonical paths
+///
+/// Container runtimes pass layers through symlinks (e.g. Docker's
+/// `overlay2/l/<id>`), while the kernel reports resolved paths.
+/// Directories that can't be resolved are kept as they are.
+fn resolve_overlay_dirs(entries: &mut [MountEntry]) {
+    let dirs = entries.iter_mut().filter_map(|e| e.overlay.as_mut());
+    for dirs in dirs {
+        for dir in dirs.upper.iter_mut().chain(dirs.lower.iter_mut()) {
+            if let Ok(resolved) = fs::canonicalize(&*dir) {
+                *dir = resolved.to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+/// Find the mount that contains a path
+///
+/// # Arguments
+/// * `entries` - Entries, longest mount point first
+/// * `path` - Absolute file path
+///
+/// # Returns
+/// * `Option<&MountEntry>` - Most specific mount, or None for relative
+///   paths
+fn lookup<'a>(entries: &'a [MountEntry], path: &str) -> Option<&'a MountEntry> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let path = Path::new(path);
+    entries.iter().find(|e| path.starts_with(&e.mount_point))
+}
+
+/// Find the overlay directory containing a path
+///
+/// # Arguments
+/// * `entries` - Entries, longest mount point first
+/// * `path` - Absolute file path
+///
+/// # Returns
+/// * `Option<(&str, OverlayLayer, &str)>` - Merged mount point, layer
+///   and the longest layer directory containing the path
+fn overlay_dir_of<'a>(
+    entries: &'a [MountEntry],
+    path: &str,
+) -> Option<(&'a str, OverlayLayer, &'a str)> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let path = Path::new(path);
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (layer, dir) = entry.overlay.as_ref()?.layer_of(path)?;
+            Some((entry.mount_point.as_str(), layer, dir))
+        })
+        // The first of the longest, so ties go to the longest mount point
+        .rev()
+        .max_by_key(|(_, _, dir)| dir.len())
+}
+
+/// Strip trailing slashes from a mount point, keeping `/` itself
+///
+/// # Arguments
+/// * `mount_point` - Directory path as given by the user
+///
+/// # Returns
+/// * `&str` - Normalized mount point
+pub fn normalize_mount_point(mount_point: &str) -> &str {
+    let trimmed = mount_point.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/"
+    } else {
+        trimmed
+    }
+}
+
+/// Move a path from under one directory to under another
+///
+/// # Arguments
+/// * `path` - Path starting with `from`
+/// * `from` - Directory the path is in
+/// * `to` - Directory to move it to
+///
+/// # Returns
+/// * `String` - Path with `from` replaced by `to`
+fn rebase(path: &str, from: &str, to: &str) -> String {
+    let rest = path[from.len()..].trim_start_matches('/');
+    match (to, rest) {
+        (_, "") => to.to_string(),
+        ("/", _) => format!("/{}", rest),
+        _ => format!("{}/{}", to, rest),
+    }
+}
+
+/// Decode the `\NNN` octal escapes the kernel uses for spaces and tabs
+///
+/// # Arguments
+/// * `field` - Escaped field from the mount list
+///
+/// # Returns
+/// * `String` - Unescaped field
+fn unescape_octal(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u8::from_str_radix(d, 8).ok())
+        {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    const SAMPLE_MOUNTS: &str = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid 0 0
+tmpfs /tmp tmpfs rw 0 0
+/dev/sdb1 /data xfs rw 0 0
+/dev/sdc1 /data/archive ext4 rw 0 0
+/dev/sdd1 /mnt/my\\040disk ext4 rw 0 0
+fileserver:/export/home /home/shared nfs4 rw 0 0
+//winbox/share /mnt/win cifs rw 0 0
+overlay /var/lib/docker/overlay2/c1/merged overlay rw,relatime,\
+lowerdir=/var/lib/docker/overlay2/img2/diff:/var/lib/docker/overlay2/img1/diff,\
+upperdir=/var/lib/docker/overlay2/c1/diff,\
+workdir=/var/lib/docker/overlay2/c1/work 0 0
+";
+
+    #[test]
+    fn test_lookup_most_specific_mount() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        assert_eq!(table.lookup("/data/a.txt").unwrap().fs_type, "xfs");
+        assert_eq!(
+            table.lookup("/data/archive/a.txt").unwrap().mount_point,
+            "/data/archive"
+        );
+        assert_eq!(table.lookup("/home/a.txt").unwrap().fs_type, "ext4");
+        assert_eq!(table.lookup("/tmp").unwrap().fs_type, "tmpfs");
+    }
+
+    #[test]
+    fn test_lookup_respects_component_boundaries() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        // "/database" must not match the "/data" mount
+        assert_eq!(table.lookup("/database/x").unwrap().mount_point, "/");
+    }
+
+    #[test]
+    fn test_lookup_relative_path() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        assert!(table.lookup("relative/a.txt").is_none());
+        assert!(MountTable::empty().lookup("/a.txt").is_none());
+    }
+
+    #[test]
+    fn test_annotate_event() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        let mut event = FileEvent::new(
+            "/data/a.txt".to_string(),
+            "app".to_string(),
+            crate::file_event::FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        table.annotate(&mut event);
+        assert_eq!(event.mount_point.as_deref(), Some("/data"));
+        assert_eq!(event.fs_type.as_deref(), Some("xfs"));
+    }
+
+    #[test]
+    fn test_annotate_remote_event() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        let mut event = FileEvent::new(
+            "/home/shared/notes.txt".to_string(),
+            "vim".to_string(),
+            crate::file_event::FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        table.annotate(&mut event);
+        assert_eq!(event.fs_type.as_deref(), Some("nfs4"));
+        assert_eq!(
+            event.remote_source.as_deref(),
+            Some("fileserver:/export/home")
+        );
+        assert_eq!(event.is_remote(), Some(true));
+    }
+
+    #[test]
+    fn test_remote_fs_types() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        assert!(table.lookup("/mnt/win/a.doc").unwrap().is_remote());
+        assert!(!table.lookup("/data/a.txt").unwrap().is_remote());
+    }
+
+    #[test]
+    fn test_overlay_layers_map_to_merged_path() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        let merged = "/var/lib/docker/overlay2/c1/merged";
+        assert_eq!(
+            table.merged_path("/var/lib/docker/overlay2/c1/diff/etc/hosts"),
+            Some((format!("{}/etc/hosts", merged), OverlayLayer::Upper))
+        );
+        assert_eq!(
+            table.merged_path("/var/lib/docker/overlay2/img1/diff/bin/sh"),
+            Some((format!("{}/bin/sh", merged), OverlayLayer::Lower(1)))
+        );
+        // The work directory and sibling names are not layers
+        assert_eq!(
+            table.merged_path("/var/lib/docker/overlay2/c1/work/x"),
+            None
+        );
+        assert_eq!(
+            table.merged_path("/var/lib/docker/overlay2/c1/diff2/x"),
+            None
+        );
+        assert_eq!(rebase("/up/a", "/up", "/"), "/a");
+        assert_eq!(rebase("/up", "/up", "/m"), "/m");
+
+        let mut event = FileEvent::new(
+            "/var/lib/docker/overlay2/img2/diff/etc/passwd".to_string(),
+            "cat".to_string(),
+            crate::file_event::FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        table.annotate(&mut event);
+        assert_eq!(event.file_path, format!("{}/etc/passwd", merged));
+        assert_eq!(event.mount_point.as_deref(), Some(merged));
+        assert_eq!(event.fs_type.as_deref(), Some("overlay"));
+        assert_eq!(event.overlay_layer, Some(OverlayLayer::Lower(0)));
+        assert!(event.to_string().ends_with("/etc/passwd [overlay lower 0]"));
+
+        // The longest layer directory wins, whatever the mount points
+        let nested = format!(
+            "{}overlay /m2 overlay rw,lowerdir=/img,\
+             upperdir=/var/lib/docker/overlay2/img1/diff/nested 0 0\n",
+            SAMPLE_MOUNTS
+        );
+        let table = MountTable::parse(&nested);
+        assert_eq!(
+            table.merged_path("/var/lib/docker/overlay2/img1/diff/nested/x"),
+            Some(("/m2/x".to_string(), OverlayLayer::Upper))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_followed_table_reads_the_mount_list() {
+        let (table, watcher) = MountTable::follow().unwrap();
+        assert!(table.lookup("/").is_some());
+        let supervisor = tokio::spawn(supervise(watcher));
+        // Dropping the table ends the watch
+        drop(table);
+        supervisor.await.unwrap();
+    }
+
+    #[test]
+    fn test_escaped_mount_point() {
+        let table = MountTable::parse(SAMPLE_MOUNTS);
+        assert!(table.contains_mount_point("/mnt/my disk"));
+        assert!(table.contains_mount_point("/data/"));
+        assert!(!table.contains_mount_point("/home"));
+    }
+}
@@ -0,0 +1,726 @@
+//! Notify module
+//!
+//! Tells a person about serious events (`fw collect --notify-webhook`,
+//! `--notify-smtp`). Events at or above a severity threshold are rendered
+//! with a template and posted to Slack or Teams incoming webhooks, whose
+//! simplest payload both accept as `{"text": ...}`, and mailed over SMTP
+//! with TLS (implicit on port 465, STARTTLS otherwise). Like exec hooks,
+//! sends run in the background with a cap on how many may run at once, so
+//! a slow server can never stall event delivery; notifications arriving
+//! while every slot is busy are skipped. Sending needs the `sinks`
+//! feature, which brings in the TLS stack `fw forward` uses.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::fmt;
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use crate::forward::{crypto_provider, load_roots};
+use crate::report::{base64, json_string};
+use crate::severity::Severity;
+use crate::throttle::REPEATS_TAG;
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use anyhow::Context;
+#[cfg(feature = "sinks")]
+use log::{debug, warn};
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use rustls::pki_types::ServerName;
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use std::io::{self, Read, Write};
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use std::sync::Arc;
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+use std::time::Duration;
+#[cfg(feature = "sinks")]
+use tokio::runtime::Handle;
+#[cfg(feature = "sinks")]
+use tokio::sync::Semaphore;
+
+/// Message sent unless `--notify-template` says otherwise
+pub const DEFAULT_NOTIFY_TEMPLATE: &str =
+    "{severity} on {host}: {rule}: {program} ({pid}) {action} {path}";
+
+/// CA certificates webhook and mail servers are checked against
+pub const DEFAULT_CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// SMTP port used when `--notify-smtp` names none (submission with
+/// STARTTLS)
+pub const DEFAULT_SMTP_PORT: u16 = 587;
+
+/// SMTP port speaking TLS from the first byte
+#[cfg(feature = "sinks")]
+const SMTPS_PORT: u16 = 465;
+
+/// Placeholders a template may use
+const PLACEHOLDERS: [&str; 8] = [
+    "time", "host", "severity", "rule", "program", "pid", "action", "path",
+];
+
+/// Number of notifications sent at once
+#[cfg(feature = "sinks")]
+const NOTIFY_CONCURRENCY: usize = 4;
+
+/// Time a server may take to answer before a send is given up
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Blocking TLS connection to a webhook or mail server
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Message template with `{placeholder}` fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyTemplate(String);
+
+impl NotifyTemplate {
+    /// Check a template's placeholders
+    ///
+    /// # Arguments
+    /// * `text` - Template, e.g. "{severity}: {program} wrote {path}";
+    ///   placeholders are {time}, {host}, {severity}, {rule}, {program},
+    ///   {pid}, {action} and {path}
+    ///
+    /// # Returns
+    /// * `Result<NotifyTemplate, String>` - Template, or error naming an
+    ///   unknown or unclosed placeholder
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed '{{' in '{}'", text));
+            };
+            let name = &rest[start + 1..start + len];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} (expected one of {})",
+                    name,
+                    PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                ));
+            }
+            rest = &rest[start + len + 1..];
+        }
+        Ok(Self(text.to_string()))
+    }
+
+    /// Render the message for an event
+    ///
+    /// Summaries of deduplicated alerts (see [`crate::throttle`]) get the
+    /// number of repeats appended.
+    ///
+    /// # Arguments
+    /// * `event` - Classified event
+    /// * `host` - Name of this host
+    ///
+    /// # Returns
+    /// * `String` - Message with the placeholders filled in
+    pub fn render(&self, event: &FileEvent, host: &str) -> String {
+        let severity = event.severity.unwrap_or(Severity::Info).to_string();
+        let mut text = self
+            .0
+            .replace(
+                "{time}",
+                &event.timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            )
+            .replace("{host}", host)
+            .replace("{severity}", &severity)
+            .replace("{rule}", event.rule.as_deref().unwrap_or("(no rule)"))
+            .replace("{program}", &event.program_name)
+            .replace("{pid}", &event.pid.to_string())
+            .replace("{action}", &event.action.to_string())
+            .replace("{path}", &event.file_path);
+        if let Some(repeats) = event.tags.get(REPEATS_TAG) {
+            text.push_str(&format!(" (repeated {} more times)", repeats));
+        }
+        text
+    }
+}
+
+impl Default for NotifyTemplate {
+    fn default() -> Self {
+        Self(DEFAULT_NOTIFY_TEMPLATE.to_string())
+    }
+}
+
+/// HTTPS endpoint of an incoming webhook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    /// Server name, also checked against its certificate
+    pub host: String,
+    /// TCP port, 443 unless the URL names one
+    pub port: u16,
+    /// Path and query the message is posted to
+    pub path: String,
+}
+
+impl WebhookUrl {
+    /// Parse a webhook URL
+    ///
+    /// # Arguments
+    /// * `url` - URL such as "https://hooks.slack.com/services/T/B/X"
+    ///
+    /// # Returns
+    /// * `Result<WebhookUrl, String>` - Endpoint, or error if the URL
+    ///   isn't https
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("https://") else {
+            return Err(format!("webhook '{}' must be an https:// URL", url));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("bad port in webhook '{}'", url))?,
+            ),
+            None => (authority, 443),
+        };
+        if host.is_empty() {
+            return Err(format!("webhook '{}' has no host", url));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    /// Format without the path, which usually holds the webhook's secret
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}:{}/...", self.host, self.port)
+    }
+}
+
+/// Mail server and addresses for email notifications
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpConfig {
+    /// Server name, also checked against its certificate
+    pub host: String,
+    /// Port; 465 speaks TLS at once, any other upgrades with STARTTLS
+    pub port: u16,
+    /// User name and password to log in with, if the server needs them
+    pub login: Option<(String, String)>,
+    /// Sender address
+    pub from: String,
+    /// Recipient addresses
+    pub to: Vec<String>,
+}
+
+impl SmtpConfig {
+    /// Split a `--notify-smtp` value into server name and port
+    ///
+    /// # Arguments
+    /// * `server` - "host:port", or "host" for port 587
+    ///
+    /// # Returns
+    /// * `Result<(String, u16), String>` - Host and port, or error if
+    ///   the port isn't a number
+    pub fn parse_server(server: &str) -> Result<(String, u16), String> {
+        match server.rsplit_once(':') {
+            Some((host, port)) => port
+                .parse()
+                .map(|port| (host.to_string(), port))
+                .map_err(|_| format!("bad port in '{}'", server)),
+            None => Ok((server.to_string(), DEFAULT_SMTP_PORT)),
+        }
+    }
+
+    /// Message for a notification, with headers, ready for DATA
+    ///
+    /// # Arguments
+    /// * `subject` - Rendered notification, used as the subject
+    /// * `event` - Event the notification is about, shown in the body
+    /// * `date` - Time the message is sent
+    ///
+    /// # Returns
+    /// * `String` - CRLF-terminated lines, dot-stuffed, without the
+    ///   closing "."; line breaks in the subject become spaces in its
+    ///   header, so a path can't add headers
+    pub fn message(
+        &self,
+        subject: &str,
+        event: &FileEvent,
+        date: DateTime<Utc>,
+    ) -> String {
+        let one_line = subject.replace(['\r', '\n'], " ");
+        let header = match one_line.is_ascii() {
+            true => one_line,
+            false => format!("=?utf-8?b?{}?=", base64(one_line.as_bytes())),
+        };
+        let mut text = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             \r\n",
+            self.from,
+            self.to.join(", "),
+            header,
+            date.to_rfc2822()
+        );
+        let body = format!("{}\n\n{}", subject, event);
+        for line in body.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.starts_with('.') {
+                text.push('.');
+            }
+            text.push_str(line);
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+/// Where and when to send notifications
+#[derive(Debug, Clone)]
+pub struct NotifyConfig {
+    /// Slack or Teams incoming webhooks
+    pub webhooks: Vec<WebhookUrl>,
+    /// Mail server and addresses, if notifying by email
+    pub email: Option<SmtpConfig>,
+    /// Least severe events notified about
+    pub min_severity: Severity,
+    /// Message template
+    pub template: NotifyTemplate,
+    /// CA certificates servers must present a certificate chaining to
+    pub ca: PathBuf,
+    /// Name of this host, for {host}
+    pub hostname: String,
+}
+
+/// JSON payload for a Slack or Teams incoming webhook
+///
+/// # Arguments
+/// * `text` - Rendered notification
+///
+/// # Returns
+/// * `String` - `{"text": ...}`
+pub fn webhook_body(text: &str) -> String {
+    format!("{{\"text\":{}}}", json_string(text))
+}
+
+/// Sink that notifies about events above a severity threshold
+#[cfg(feature = "sinks")]
+pub struct NotifySink {
+    /// Servers, template and threshold
+    config: Arc<NotifyConfig>,
+    /// TLS settings for new connections
+    tls: Arc<ClientConfig>,
+    /// Runtime the sends run on
+    runtime: Handle,
+    /// Free send slots
+    slots: Arc<Semaphore>,
+    /// Number of notifications skipped because every slot was busy
+    skipped: u64,
+}
+
+#[cfg(feature = "sinks")]
+impl NotifySink {
+    /// Create a sink sending on the given runtime
+    ///
+    /// # Arguments
+    /// * `config` - Servers, template and threshold
+    /// * `runtime` - Runtime the sends run on
+    ///
+    /// # Returns
+    /// * `Result<NotifySink>` - New sink, or error if the CA
+    ///   certificates can't be loaded
+    pub fn new(config: NotifyConfig, runtime: Handle) -> Result<Self> {
+        Ok(Self {
+            tls: client_config(&config.ca)?,
+            config: Arc::new(config),
+            runtime,
+            slots: Arc::new(Semaphore::new(NOTIFY_CONCURRENCY)),
+            skipped: 0,
+        })
+    }
+
+    /// Number of notifications skipped because every slot was busy
+    ///
+    /// # Returns
+    /// * `u64` - Skipped notification count
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(feature = "sinks")]
+impl EventSink for NotifySink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        if event.severity.is_none_or(|s| s < self.config.min_severity) {
+            return Ok(());
+        }
+        let Ok(permit) = self.slots.clone().try_acquire_owned() else {
+            self.skipped += 1;
+            warn!("All notification slots busy, skipping {}", event.file_path);
+            return Ok(());
+        };
+        let config = self.config.clone();
+        let tls = self.tls.clone();
+        let event = event.clone();
+        self.runtime.spawn_blocking(move || {
+            // Holding the permit until the sends end keeps the slot busy
+            let _permit = permit;
+            let text = config.template.render(&event, &config.hostname);
+            for webhook in &config.webhooks {
+                match post_webhook(webhook, &tls, &webhook_body(&text)) {
+                    Ok(()) => debug!("Notified {}", webhook),
+                    Err(e) => warn!("Failed to notify {}: {}", webhook, e),
+                }
+            }
+            if let Some(smtp) = &config.email {
+                let message = smtp.message(&text, &event, Utc::now());
+                match send_mail(smtp, &tls, &message) {
+                    Ok(()) => debug!("Mailed {}", smtp.to.join(", ")),
+                    Err(e) => warn!("Failed to mail {}: {}", smtp.host, e),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// TLS settings for connections to notification servers
+///
+/// # Arguments
+/// * `ca` - CA certificates (PEM) servers must chain to
+///
+/// # Returns
+/// * `Result<Arc<ClientConfig>>` - Settings, or error if the
+///   certificates can't be loaded
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+pub(crate) fn client_config(ca: &Path) -> Result<Arc<ClientConfig>> {
+    let tls = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .context("Failed to set up TLS")?
+        .with_root_certificates(load_roots(ca)?)
+        .with_no_client_auth();
+    Ok(Arc::new(tls))
+}
+
+/// Open a TCP connection with connect, send and receive timeouts
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last = None;
+    let mut connected = None;
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, NOTIFY_TIMEOUT) {
+            Ok(tcp) => {
+                connected = Some(tcp);
+                break;
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    let tcp = match (connected, last) {
+        (Some(tcp), _) => tcp,
+        (None, Some(e)) => return Err(e),
+        (None, None) => return Err(io::ErrorKind::NotFound.into()),
+    };
+    tcp.set_read_timeout(Some(NOTIFY_TIMEOUT))?;
+    tcp.set_write_timeout(Some(NOTIFY_TIMEOUT))?;
+    Ok(tcp)
+}
+
+/// Start TLS on a connection
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+fn start_tls(
+    tcp: TcpStream,
+    host: &str,
+    tls: &Arc<ClientConfig>,
+) -> io::Result<TlsStream> {
+    let name =
+        ServerName::try_from(host.to_string()).map_err(io::Error::other)?;
+    let connection =
+        ClientConnection::new(tls.clone(), name).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(connection, tcp))
+}
+
+/// Read one CRLF-terminated line
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+fn read_line(stream: &mut impl Read) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        line.push(byte[0]);
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// POST a payload to a webhook
+///
+/// # Arguments
+/// * `url` - Webhook endpoint
+/// * `tls` - TLS settings
+/// * `body` - JSON payload
+///
+/// # Returns
+/// * `io::Result<()>` - Error unless the server answered 2xx
+#[cfg(feature = "sinks")]
+pub(crate) fn post_webhook(
+    url: &WebhookUrl,
+    tls: &Arc<ClientConfig>,
+    body: &str,
+) -> io::Result<()> {
+    post(url, tls, "application/json", None, body)
+}
+
+/// POST a payload to an HTTPS endpoint
+///
+/// # Arguments
+/// * `url` - Endpoint
+/// * `tls` - TLS settings
+/// * `content_type` - Media type of the payload
+/// * `authorization` - Value of the Authorization header, if any
+/// * `body` - Payload
+///
+/// # Returns
+/// * `io::Result<()>` - Error unless the server answered 2xx
+#[cfg(any(feature = "sinks", feature = "metrics"))]
+pub(crate) fn post(
+    url: &WebhookUrl,
+    tls: &Arc<ClientConfig>,
+    content_type: &str,
+    authorization: Option<&str>,
+    body: &str,
+) -> io::Result<()> {
+    let mut stream = start_tls(connect(&url.host, url.port)?, &url.host, tls)?;
+    let authorization = authorization
+        .map(|value| format!("Authorization: {}\r\n", value))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: fw\r\n{}\
+         Content-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        authorization,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    let status = read_line(&mut stream)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("server answered '{}'", status))),
+    }
+}
+
+/// Read an SMTP reply, checking its class
+///
+/// # Arguments
+/// * `stream` - Connection to the mail server
+/// * `expected` - Expected code; any code of the same class is accepted
+///
+/// # Returns
+/// * `io::Result<()>` - Error naming the reply if it isn't expected
+#[cfg(feature = "sinks")]
+fn reply(stream: &mut impl Read, expected: u16) -> io::Result<()> {
+    loop {
+        let line = read_line(stream)?;
+        // "250-..." continues a multi-line reply, "250 ..." ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        let code: u16 = line.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+        return match code / 100 == expected / 100 {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("server said '{}'", line))),
+        };
+    }
+}
+
+/// Send an SMTP command and check the reply
+#[cfg(feature = "sinks")]
+fn command(
+    stream: &mut (impl Read + Write),
+    line: &str,
+    expected: u16,
+) -> io::Result<()> {
+    write!(stream, "{}\r\n", line)?;
+    stream.flush()?;
+    reply(stream, expected)
+}
+
+/// Deliver a message over SMTP with TLS
+///
+/// # Arguments
+/// * `smtp` - Mail server and addresses
+/// * `tls` - TLS settings
+/// * `message` - Headers and body from [`SmtpConfig::message`]
+///
+/// # Returns
+/// * `io::Result<()>` - Error if the server refused any step
+#[cfg(feature = "sinks")]
+fn send_mail(
+    smtp: &SmtpConfig,
+    tls: &Arc<ClientConfig>,
+    message: &str,
+) -> io::Result<()> {
+    let mut tcp = connect(&smtp.host, smtp.port)?;
+    let mut stream = match smtp.port {
+        SMTPS_PORT => {
+            let mut stream = start_tls(tcp, &smtp.host, tls)?;
+            reply(&mut stream, 220)?;
+            stream
+        }
+        _ => {
+            reply(&mut tcp, 220)?;
+            command(&mut tcp, "EHLO fw", 250)?;
+            command(&mut tcp, "STARTTLS", 220)?;
+            start_tls(tcp, &smtp.host, tls)?
+        }
+    };
+    command(&mut stream, "EHLO fw", 250)?;
+    if let Some((user, password)) = &smtp.login {
+        let token = base64(format!("\0{}\0{}", user, password).as_bytes());
+        command(&mut stream, &format!("AUTH PLAIN {}", token), 235)?;
+    }
+    command(&mut stream, &format!("MAIL FROM:<{}>", smtp.from), 250)?;
+    for to in &smtp.to {
+        command(&mut stream, &format!("RCPT TO:<{}>", to), 250)?;
+    }
+    command(&mut stream, "DATA", 354)?;
+    command(&mut stream, &format!("{}.", message), 250)?;
+    let _ = command(&mut stream, "QUIT", 221);
+    Ok(())
+}
+
+/// Stand-in for builds without the `sinks` feature; never created
+#[cfg(not(feature = "sinks"))]
+pub enum NotifySink {}
+
+#[cfg(not(feature = "sinks"))]
+impl NotifySink {
+    /// Refuse to notify
+    ///
+    /// # Returns
+    /// * `Result<NotifySink>` - Always an error naming the feature
+    pub fn new(_: NotifyConfig, _: tokio::runtime::Handle) -> Result<Self> {
+        Err(crate::features::not_compiled_in("sinks", "Notifications"))
+    }
+}
+
+#[cfg(not(feature = "sinks"))]
+impl EventSink for NotifySink {
+    fn write_event(&mut self, _: &FileEvent) -> Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn event() -> FileEvent {
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "passwd".to_string(),
+            FileAction::Opened,
+            812,
+            SystemClock.now(),
+        );
+        event.severity = Some(Severity::Critical);
+        event.rule = Some("credentials-write".to_string());
+        event
+    }
+
+    #[test]
+    fn test_template() {
+        let template = NotifyTemplate::default();
+        assert_eq!(
+            template.render(&event(), "web1"),
+            "critical on web1: credentials-write: passwd (812) opened \
+             /etc/shadow"
+        );
+        let mut summary = event();
+        summary
+            .tags
+            .insert(REPEATS_TAG.to_string(), "41".to_string());
+        let template = NotifyTemplate::parse("{program} {path}").unwrap();
+        assert_eq!(
+            template.render(&summary, "web1"),
+            "passwd /etc/shadow (repeated 41 more times)"
+        );
+
+        assert!(NotifyTemplate::parse("{user} {path}").is_err());
+        assert!(NotifyTemplate::parse("{path").is_err());
+    }
+
+    #[test]
+    fn test_webhook_url_and_body() {
+        let url = WebhookUrl::parse("https://hooks.slack.com/services/T/B/X")
+            .unwrap();
+        assert_eq!(url.host, "hooks.slack.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/services/T/B/X");
+        assert_eq!(url.to_string(), "https://hooks.slack.com:443/...");
+        assert_eq!(WebhookUrl::parse("https://h:8443").unwrap().path, "/");
+        assert!(WebhookUrl::parse("http://hooks.slack.com/x").is_err());
+        assert!(WebhookUrl::parse("https://:443/x").is_err());
+
+        assert_eq!(webhook_body("a \"b\""), "{\"text\":\"a \\\"b\\\"\"}");
+    }
+
+    #[test]
+    fn test_email_message() {
+        let smtp = SmtpConfig {
+            host: "mail.example.com".to_string(),
+            port: 587,
+            login: None,
+            from: "fw@example.com".to_string(),
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+        };
+        let date = DateTime::UNIX_EPOCH;
+        let message = smtp.message("critical: shadow", &event(), date);
+        assert!(message.starts_with(
+            "From: fw@example.com\r\nTo: a@example.com, b@example.com\r\n\
+             Subject: critical: shadow\r\nDate: Thu, 1 Jan 1970 00:00:00 \
+             +0000\r\n"
+        ));
+        assert!(message.ends_with(" | /etc/shadow !critical\r\n"));
+
+        let mut dotted = event();
+        dotted.file_path = "/home/a/caf\u{e9}".to_string();
+        let message = smtp.message(".caf\u{e9}", &dotted, date);
+        assert!(message.contains("Subject: =?utf-8?b?LmNhZsOp?=\r\n"));
+        assert!(message.contains("\r\n\r\n..caf"));
+
+        // Line breaks can't add headers, and every body line is dot-stuffed
+        // and ends in CRLF
+        let injected = "x\r\nBcc: evil@example.com\n.\nQUIT";
+        let message = smtp.message(injected, &event(), date);
+        assert!(
+            message.contains("Subject: x  Bcc: evil@example.com . QUIT\r\n")
+        );
+        assert!(message
+            .contains("\r\n\r\nx\r\nBcc: evil@example.com\r\n..\r\nQUIT\r\n"));
+        assert!(!message.replace("\r\n", "").contains(['\r', '\n']));
+
+        assert_eq!(
+            SmtpConfig::parse_server("mail.example.com").unwrap(),
+            ("mail.example.com".to_string(), DEFAULT_SMTP_PORT)
+        );
+        assert_eq!(SmtpConfig::parse_server("smtp:465").unwrap().1, 465);
+        assert!(SmtpConfig::parse_server("smtp:tls").is_err());
+    }
+}
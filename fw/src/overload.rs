@@ -0,0 +1,339 @@
+//! Overload module
+//!
+//! Protects the system when fw can't keep up. A supervisor watches the
+//! drop counters in [`Health`]; when too many events are dropped for
+//! several windows in a row it degrades fidelity one step at a time,
+//! first pausing enrichment and then, only if sampling was asked for
+//! (`--overload-sampling`), sampling events in the kernel at doubling
+//! rates. Sampling never drops events on paths the severity policy rates
+//! warning or critical, and sampled events count for the events they
+//! stand for in stats. Once no events have been dropped for a while it
+//! restores fidelity the same way. Every change is logged as a warning
+//! or notice on the diagnostics channel and shown in the systemd status.
+
+use log::{info, warn};
+use std::fmt;
+use std::time::Duration;
+
+use crate::health::{Health, HealthSnapshot};
+
+/// Highest kernel sampling rate (1 in this many events)
+pub const MAX_SAMPLE_RATE: u32 = 64;
+
+/// Share of dropped events that counts as overload, as a percentage,
+/// unless `--overload-threshold` says otherwise
+pub const DEFAULT_OVERLOAD_PERCENT: u32 = 5;
+
+/// How much fidelity is currently given up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Degradation {
+    /// Events are published without running enrichers
+    pub enrichment_paused: bool,
+    /// The kernel reports 1 in this many events (1 reports all)
+    pub sample_rate: u32,
+}
+
+impl Default for Degradation {
+    fn default() -> Self {
+        Self {
+            enrichment_paused: false,
+            sample_rate: 1,
+        }
+    }
+}
+
+impl Degradation {
+    /// Check whether anything is given up
+    ///
+    /// # Returns
+    /// * `bool` - True unless running at full fidelity
+    pub fn is_degraded(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Next step down in fidelity
+    ///
+    /// # Arguments
+    /// * `can_pause_enrichment` - Whether pausing enrichment is a step
+    /// * `can_sample` - Whether sampling in the kernel is a step
+    ///
+    /// # Returns
+    /// * `Option<Degradation>` - Tighter setting, or None at the limit
+    pub fn tighten(
+        self,
+        can_pause_enrichment: bool,
+        can_sample: bool,
+    ) -> Option<Self> {
+        if can_pause_enrichment && !self.enrichment_paused {
+            return Some(Self {
+                enrichment_paused: true,
+                ..self
+            });
+        }
+        (can_sample && self.sample_rate < MAX_SAMPLE_RATE).then_some(Self {
+            sample_rate: self.sample_rate * 2,
+            ..self
+        })
+    }
+
+    /// Next step back up in fidelity, undoing the last tighten
+    ///
+    /// # Returns
+    /// * `Option<Degradation>` - Looser setting, or None at full fidelity
+    pub fn loosen(self) -> Option<Self> {
+        if self.sample_rate > 1 {
+            return Some(Self {
+                sample_rate: self.sample_rate / 2,
+                ..self
+            });
+        }
+        self.enrichment_paused.then_some(Self::default())
+    }
+}
+
+impl fmt::Display for Degradation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.enrichment_paused, self.sample_rate) {
+            (false, 1) => write!(f, "full fidelity"),
+            (true, 1) => write!(f, "enrichment paused"),
+            (false, rate) => write!(f, "1 in {} events sampled", rate),
+            (true, rate) => {
+                write!(f, "enrichment paused, 1 in {} events sampled", rate)
+            }
+        }
+    }
+}
+
+/// When the controller degrades and restores fidelity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverloadConfig {
+    /// Share of a window's events that may be dropped (0.0 to 1.0)
+    pub threshold: f64,
+    /// How often drop counts are compared
+    pub window: Duration,
+    /// Windows over the threshold in a row before degrading a step
+    pub sustain: u32,
+    /// Windows without drops in a row before restoring a step
+    pub recover: u32,
+    /// Sample events in the kernel once enrichment is paused; off unless
+    /// asked for, since sampled-out events are lost
+    pub sampling: bool,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            threshold: f64::from(DEFAULT_OVERLOAD_PERCENT) / 100.0,
+            window: Duration::from_secs(1),
+            sustain: 5,
+            recover: 30,
+            sampling: false,
+        }
+    }
+}
+
+/// A change of fidelity decided by the controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustment {
+    /// Setting to apply from now on
+    pub degradation: Degradation,
+    /// Share of events dropped in the window that triggered the change
+    pub drop_ratio: f64,
+    /// True when fidelity went down, false when it was restored
+    pub tightened: bool,
+}
+
+/// Decides when to degrade and restore fidelity from drop counts
+#[derive(Debug)]
+pub struct OverloadController {
+    /// Thresholds and window counts
+    config: OverloadConfig,
+    /// Whether pausing enrichment is one of the steps
+    can_pause_enrichment: bool,
+    /// Setting currently applied
+    degradation: Degradation,
+    /// Event and drop counters at the end of the previous window
+    last: (u64, u64),
+    /// Consecutive windows over the threshold
+    hot: u32,
+    /// Consecutive windows without drops
+    calm: u32,
+}
+
+impl OverloadController {
+    /// Create a controller starting at full fidelity
+    ///
+    /// # Arguments
+    /// * `config` - Thresholds and window counts
+    /// * `can_pause_enrichment` - False when enrichment is off or the
+    ///   filters need its tags, so pausing it would not help or would
+    ///   hide every event
+    ///
+    /// # Returns
+    /// * `OverloadController` - Controller with no history
+    pub fn new(config: OverloadConfig, can_pause_enrichment: bool) -> Self {
+        Self {
+            config,
+            can_pause_enrichment,
+            degradation: Degradation::default(),
+            last: (0, 0),
+            hot: 0,
+            calm: 0,
+        }
+    }
+
+    /// Compare the counters with the previous window's
+    ///
+    /// # Arguments
+    /// * `health` - Counters at the end of this window
+    ///
+    /// # Returns
+    /// * `Option<Adjustment>` - New setting, or None to keep the current
+    pub fn observe(&mut self, health: &HealthSnapshot) -> Option<Adjustment> {
+        let events = health.events.saturating_sub(self.last.0);
+        let dropped = health.dropped.saturating_sub(self.last.1);
+        self.last = (health.events, health.dropped);
+        let drop_ratio = dropped as f64 / events.max(1) as f64;
+
+        let (next, tightened) = if drop_ratio > self.config.threshold {
+            self.calm = 0;
+            self.hot += 1;
+            if self.hot < self.config.sustain {
+                return None;
+            }
+            self.hot = 0;
+            let next = self
+                .degradation
+                .tighten(self.can_pause_enrichment, self.config.sampling)?;
+            (next, true)
+        } else {
+            self.hot = 0;
+            self.calm = if dropped == 0 { self.calm + 1 } else { 0 };
+            if self.calm < self.config.recover {
+                return None;
+            }
+            self.calm = 0;
+            (self.degradation.loosen()?, false)
+        };
+        self.degradation = next;
+        Some(Adjustment {
+            degradation: next,
+            drop_ratio,
+            tightened,
+        })
+    }
+}
+
+/// Adjust fidelity from the drop counters until cancelled
+///
+/// # Arguments
+/// * `controller` - Controller deciding each window
+/// * `health` - Counters to watch, and where the setting is published
+pub async fn supervise(mut controller: OverloadController, health: Health) {
+    let mut windows = tokio::time::interval(controller.config.window);
+    // The first tick is immediate; start comparing from there
+    windows.tick().await;
+    controller.observe(&health.snapshot());
+    loop {
+        windows.tick().await;
+        let Some(adjustment) = controller.observe(&health.snapshot()) else {
+            continue;
+        };
+        health.set_degradation(adjustment.degradation);
+        if adjustment.tightened {
+            warn!(
+                "Overloaded: {:.0}% of events dropped; degrading to {}",
+                adjustment.drop_ratio * 100.0,
+                adjustment.degradation
+            );
+        } else {
+            info!("Load subsided; restoring to {}", adjustment.degradation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(events: u64, dropped: u64) -> HealthSnapshot {
+        HealthSnapshot {
+            attached: true,
+            events,
+            dropped,
+            since_beat: None,
+            degradation: Degradation::default(),
+        }
+    }
+
+    #[test]
+    fn test_degrades_under_sustained_drops_and_recovers() {
+        let config = OverloadConfig {
+            threshold: 0.05,
+            window: Duration::from_secs(1),
+            sustain: 2,
+            recover: 3,
+            sampling: true,
+        };
+        let mut controller = OverloadController::new(config, true);
+        let (mut events, mut dropped) = (0, 0);
+        let mut window = |controller: &mut OverloadController, e, d| {
+            events += e;
+            dropped += d;
+            controller.observe(&snapshot(events, dropped))
+        };
+
+        // A single bad window is tolerated
+        assert_eq!(window(&mut controller, 1000, 100), None);
+        assert_eq!(window(&mut controller, 1000, 0), None);
+        assert_eq!(window(&mut controller, 1000, 100), None);
+        let first = window(&mut controller, 1000, 200).unwrap();
+        assert!(first.tightened);
+        assert_eq!(first.degradation.to_string(), "enrichment paused");
+        assert!((first.drop_ratio - 0.2).abs() < 1e-9);
+        window(&mut controller, 1000, 100);
+        let second = window(&mut controller, 1000, 100).unwrap();
+        assert_eq!(
+            second.degradation.to_string(),
+            "enrichment paused, 1 in 2 events sampled"
+        );
+
+        // A few drops under the threshold don't count as calm
+        assert_eq!(window(&mut controller, 1000, 10), None);
+        window(&mut controller, 500, 0);
+        window(&mut controller, 500, 0);
+        let restored = window(&mut controller, 500, 0).unwrap();
+        assert!(!restored.tightened);
+        assert_eq!(restored.degradation.to_string(), "enrichment paused");
+        for _ in 0..2 {
+            window(&mut controller, 500, 0);
+        }
+        let full = window(&mut controller, 500, 0).unwrap();
+        assert!(!full.degradation.is_degraded());
+        for _ in 0..10 {
+            assert_eq!(window(&mut controller, 500, 0), None);
+        }
+    }
+
+    #[test]
+    fn test_ladder_limits() {
+        let mut level = Degradation::default();
+        let mut steps = 0;
+        assert_eq!(level.tighten(false, false), None);
+        assert_eq!(
+            level.tighten(true, false).unwrap().to_string(),
+            "enrichment paused"
+        );
+        while let Some(next) = level.tighten(false, true) {
+            assert!(!next.enrichment_paused);
+            level = next;
+            steps += 1;
+        }
+        assert_eq!(level.sample_rate, MAX_SAMPLE_RATE);
+        assert_eq!(steps, 6);
+        while let Some(next) = level.loosen() {
+            level = next;
+        }
+        assert_eq!(level, Degradation::default());
+    }
+}
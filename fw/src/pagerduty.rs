@@ -0,0 +1,486 @@
+//! PagerDuty module
+//!
+//! Pages on-call for serious events through the PagerDuty Events v2 API
+//! (`fw collect --pagerduty-routing-key-file`). Every event at or above
+//! `--pagerduty-min-severity` sends a trigger whose dedup key is made of
+//! the policy rule and the path, so PagerDuty folds repeats into one
+//! incident per rule and file. A rule with "resolve_after=DURATION" pages
+//! once per incident and resolves it after the rule has been quiet on
+//! the file for that long; incidents of other rules stay open until
+//! someone resolves them. Repeats are folded locally too: an event whose
+//! incident is open, or whose trigger is still waiting to be sent, only
+//! keeps the incident from resolving. Sends run on a background task so
+//! event delivery never waits for PagerDuty, and need the `sinks`
+//! feature.
+
+use anyhow::Result;
+use ring::digest::{digest, SHA256};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+#[cfg(feature = "sinks")]
+use crate::notify::{client_config, post_webhook};
+use crate::notify::{NotifyTemplate, WebhookUrl};
+use crate::report::json_string;
+use crate::severity::Severity;
+use crate::signing::to_hex;
+#[cfg(feature = "sinks")]
+use log::{debug, info, warn};
+#[cfg(feature = "sinks")]
+use rustls::ClientConfig;
+#[cfg(feature = "sinks")]
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "sinks")]
+use tokio::runtime::Handle;
+#[cfg(feature = "sinks")]
+use tokio::sync::mpsc;
+
+/// Events v2 endpoint used unless `--pagerduty-url` says otherwise
+pub const DEFAULT_PAGERDUTY_URL: &str =
+    "https://events.pagerduty.com/v2/enqueue";
+
+/// Longest dedup key PagerDuty accepts
+const MAX_DEDUP_KEY: usize = 255;
+
+/// Longest summary PagerDuty accepts
+const MAX_SUMMARY: usize = 1024;
+
+/// Events waiting to be sent before new ones are skipped
+#[cfg(feature = "sinks")]
+const PAGERDUTY_QUEUE: usize = 256;
+
+/// How often open incidents are checked for quiet
+#[cfg(feature = "sinks")]
+const QUIET_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and when to page
+#[derive(Debug, Clone)]
+pub struct PagerDutyConfig {
+    /// Integration key of the PagerDuty service
+    pub routing_key: String,
+    /// Events v2 endpoint
+    pub url: WebhookUrl,
+    /// Least severe events paged about
+    pub min_severity: Severity,
+    /// Template of the incident summary
+    pub template: NotifyTemplate,
+    /// Quiet time after which incidents are resolved, by rule name
+    pub resolve_after: HashMap<String, Duration>,
+    /// CA certificates PagerDuty must present a certificate chaining to
+    pub ca: PathBuf,
+    /// Name of this host, the source of every event
+    pub hostname: String,
+}
+
+/// Dedup key of the incident for a rule and path
+///
+/// # Arguments
+/// * `rule` - Name of the policy rule
+/// * `path` - File the rule fired on
+///
+/// # Returns
+/// * `String` - "fw:RULE:PATH", with the path hashed if the key would be
+///   too long for PagerDuty
+pub fn dedup_key(rule: &str, path: &str) -> String {
+    let key = format!("fw:{}:{}", rule, path);
+    if key.len() <= MAX_DEDUP_KEY {
+        return key;
+    }
+    let hash = to_hex(digest(&SHA256, path.as_bytes()).as_ref());
+    let key = format!("fw:{}:sha256:{}", rule, hash);
+    // Only a very long rule name still overflows
+    key.chars().take(MAX_DEDUP_KEY).collect()
+}
+
+/// PagerDuty severity of an event
+fn pagerduty_severity(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical => "critical",
+        Severity::Warning => "warning",
+        Severity::Notice | Severity::Info => "info",
+    }
+}
+
+/// Trigger request for an event
+///
+/// # Arguments
+/// * `config` - Routing key, template and host name
+/// * `event` - Classified event
+/// * `key` - Dedup key from [`dedup_key`]
+///
+/// # Returns
+/// * `String` - JSON body for the Events v2 API
+pub fn trigger_body(
+    config: &PagerDutyConfig,
+    event: &FileEvent,
+    key: &str,
+) -> String {
+    let summary: String = config
+        .template
+        .render(event, &config.hostname)
+        .chars()
+        .take(MAX_SUMMARY)
+        .collect();
+    let rule = event.rule.as_deref().unwrap_or("none");
+    format!(
+        "{{\"routing_key\":{},\"event_action\":\"trigger\",\
+         \"dedup_key\":{},\"payload\":{{\"summary\":{},\"source\":{},\
+         \"severity\":\"{}\",\"timestamp\":\"{}\",\"component\":{},\
+         \"class\":{},\"custom_details\":{{\"path\":{},\"action\":{},\
+         \"pid\":{}}}}}}}",
+        json_string(&config.routing_key),
+        json_string(key),
+        json_string(&summary),
+        json_string(&config.hostname),
+        pagerduty_severity(event.severity.unwrap_or(Severity::Info)),
+        event.timestamp.to_rfc3339(),
+        json_string(&event.program_name),
+        json_string(rule),
+        json_string(&event.file_path),
+        json_string(&event.action.to_string()),
+        event.pid
+    )
+}
+
+/// Resolve request for an incident
+///
+/// # Arguments
+/// * `routing_key` - Integration key of the PagerDuty service
+/// * `key` - Dedup key of the incident
+///
+/// # Returns
+/// * `String` - JSON body for the Events v2 API
+pub fn resolve_body(routing_key: &str, key: &str) -> String {
+    format!(
+        "{{\"routing_key\":{},\"event_action\":\"resolve\",\"dedup_key\":{}}}",
+        json_string(routing_key),
+        json_string(key)
+    )
+}
+
+/// Incidents of rules that resolve on quiet, and triggers not sent yet
+#[derive(Debug, Default)]
+pub struct Incidents {
+    /// Last time each open incident's rule fired, and its quiet time
+    open: HashMap<String, (Instant, Duration)>,
+    /// Dedup keys of the triggers waiting to be sent
+    queued: HashSet<String>,
+}
+
+impl Incidents {
+    /// Record that a rule fired
+    ///
+    /// # Arguments
+    /// * `key` - Dedup key of the incident
+    /// * `resolve_after` - Quiet time of the rule, if it resolves on quiet
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    /// * `bool` - True if a trigger should be queued: never while one
+    ///   for the key is waiting to be sent, and for rules that resolve on
+    ///   quiet only to open the incident
+    pub fn fire(
+        &mut self,
+        key: String,
+        resolve_after: Option<Duration>,
+        now: Instant,
+    ) -> bool {
+        let open = match resolve_after {
+            Some(quiet) => {
+                self.open.insert(key.clone(), (now, quiet)).is_some()
+            }
+            None => false,
+        };
+        !open && self.queued.insert(key)
+    }
+
+    /// Record that the trigger for an incident was sent
+    ///
+    /// # Arguments
+    /// * `key` - Dedup key of the incident
+    pub fn sent(&mut self, key: &str) {
+        self.queued.remove(key);
+    }
+
+    /// Forget a trigger that could not be queued, so the next event
+    /// tries again
+    ///
+    /// # Arguments
+    /// * `key` - Dedup key of the incident
+    pub fn withdraw(&mut self, key: &str) {
+        self.queued.remove(key);
+        self.open.remove(key);
+    }
+
+    /// Close the incidents that have been quiet long enough
+    ///
+    /// # Arguments
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Dedup keys of the incidents to resolve
+    pub fn quiet(&mut self, now: Instant) -> Vec<String> {
+        let mut quiet = Vec::new();
+        self.open.retain(|key, (last, after)| {
+            let open = now.duration_since(*last) < *after;
+            if !open {
+                quiet.push(key.clone());
+            }
+            open
+        });
+        quiet.sort();
+        quiet
+    }
+}
+
+/// Sink that pages about events above a severity threshold
+#[cfg(feature = "sinks")]
+pub struct PagerDutySink {
+    /// Triggers waiting for the sending task, with their dedup keys
+    queue: mpsc::Sender<(String, FileEvent)>,
+    /// Routing key, threshold and quiet times
+    config: Arc<PagerDutyConfig>,
+    /// Incidents shared with the sending task
+    incidents: Arc<Mutex<Incidents>>,
+    /// Number of events folded into an open or queued incident
+    collapsed: u64,
+    /// Number of events skipped because the queue was full
+    skipped: u64,
+}
+
+#[cfg(feature = "sinks")]
+impl PagerDutySink {
+    /// Create a sink and start its sending task on the given runtime
+    ///
+    /// # Arguments
+    /// * `config` - Routing key, endpoint and threshold
+    /// * `runtime` - Runtime the sending task runs on
+    ///
+    /// # Returns
+    /// * `Result<PagerDutySink>` - New sink, or error if the CA
+    ///   certificates can't be loaded
+    pub fn new(config: PagerDutyConfig, runtime: Handle) -> Result<Self> {
+        let tls = client_config(&config.ca)?;
+        let (queue, triggers) = mpsc::channel(PAGERDUTY_QUEUE);
+        let config = Arc::new(config);
+        let incidents = Arc::new(Mutex::default());
+        runtime.spawn(deliver(
+            config.clone(),
+            tls,
+            triggers,
+            incidents.clone(),
+        ));
+        Ok(Self {
+            queue,
+            config,
+            incidents,
+            collapsed: 0,
+            skipped: 0,
+        })
+    }
+
+    /// Number of events skipped because the queue was full
+    ///
+    /// # Returns
+    /// * `u64` - Skipped event count
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(feature = "sinks")]
+impl EventSink for PagerDutySink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        if event.severity.is_none_or(|s| s < self.config.min_severity) {
+            return Ok(());
+        }
+        let rule = event.rule.as_deref().unwrap_or("none");
+        let key = dedup_key(rule, &event.file_path);
+        let quiet = self.config.resolve_after.get(rule).copied();
+        let mut incidents = lock(&self.incidents);
+        if !incidents.fire(key.clone(), quiet, Instant::now()) {
+            self.collapsed += 1;
+            return Ok(());
+        }
+        if let Err(e) = self.queue.try_send((key, event.clone())) {
+            let (key, _) = e.into_inner();
+            incidents.withdraw(&key);
+            self.skipped += 1;
+            warn!("PagerDuty queue full, skipping {}", event.file_path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sinks")]
+impl Drop for PagerDutySink {
+    fn drop(&mut self) {
+        if self.collapsed > 0 || self.skipped > 0 {
+            info!(
+                "PagerDuty: folded {} repeated events into open incidents, \
+                 skipped {} with the queue full",
+                self.collapsed, self.skipped
+            );
+        }
+    }
+}
+
+/// Lock the shared incidents, even if a holder panicked
+#[cfg(feature = "sinks")]
+fn lock(incidents: &Mutex<Incidents>) -> MutexGuard<'_, Incidents> {
+    incidents.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Send queued triggers and resolve quiet incidents until the sink is
+/// dropped
+#[cfg(feature = "sinks")]
+async fn deliver(
+    config: Arc<PagerDutyConfig>,
+    tls: Arc<ClientConfig>,
+    mut triggers: mpsc::Receiver<(String, FileEvent)>,
+    incidents: Arc<Mutex<Incidents>>,
+) {
+    let mut check = tokio::time::interval(QUIET_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            trigger = triggers.recv() => {
+                let Some((key, event)) = trigger else {
+                    break;
+                };
+                let body = trigger_body(&config, &event, &key);
+                send(&config, &tls, body).await;
+                lock(&incidents).sent(&key);
+            }
+            _ = check.tick() => {
+                let quiet = lock(&incidents).quiet(Instant::now());
+                for key in quiet {
+                    let body = resolve_body(&config.routing_key, &key);
+                    send(&config, &tls, body).await;
+                }
+            }
+        }
+    }
+}
+
+/// Post one request to the Events v2 API, logging failures
+#[cfg(feature = "sinks")]
+async fn send(config: &PagerDutyConfig, tls: &Arc<ClientConfig>, body: String) {
+    let (url, tls) = (config.url.clone(), tls.clone());
+    let result =
+        tokio::task::spawn_blocking(move || post_webhook(&url, &tls, &body))
+            .await;
+    match result {
+        Ok(Ok(())) => debug!("Sent PagerDuty event to {}", config.url),
+        Ok(Err(e)) => warn!("Failed to send PagerDuty event: {}", e),
+        Err(e) => warn!("PagerDuty send task failed: {}", e),
+    }
+}
+
+/// Stand-in for builds without the `sinks` feature; never created
+#[cfg(not(feature = "sinks"))]
+pub enum PagerDutySink {}
+
+#[cfg(not(feature = "sinks"))]
+impl PagerDutySink {
+    /// Refuse to page
+    ///
+    /// # Returns
+    /// * `Result<PagerDutySink>` - Always an error naming the feature
+    pub fn new(_: PagerDutyConfig, _: tokio::runtime::Handle) -> Result<Self> {
+        Err(crate::features::not_compiled_in("sinks", "Paging"))
+    }
+}
+
+#[cfg(not(feature = "sinks"))]
+impl EventSink for PagerDutySink {
+    fn write_event(&mut self, _: &FileEvent) -> Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn config() -> PagerDutyConfig {
+        PagerDutyConfig {
+            routing_key: "R0UT1NG".to_string(),
+            url: WebhookUrl::parse(DEFAULT_PAGERDUTY_URL).unwrap(),
+            min_severity: Severity::Critical,
+            template: NotifyTemplate::parse("{rule}: {program} {path}")
+                .unwrap(),
+            resolve_after: HashMap::new(),
+            ca: PathBuf::from("/dev/null"),
+            hostname: "web1".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_request_bodies() {
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "vipw".to_string(),
+            FileAction::Opened,
+            42,
+            SystemClock.now(),
+        );
+        event.severity = Some(Severity::Critical);
+        event.rule = Some("shadow-write".to_string());
+        let key = dedup_key("shadow-write", &event.file_path);
+        assert_eq!(key, "fw:shadow-write:/etc/shadow");
+
+        let body: serde_json::Value =
+            serde_json::from_str(&trigger_body(&config(), &event, &key))
+                .unwrap();
+        assert_eq!(body["event_action"], "trigger");
+        assert_eq!(body["dedup_key"], key);
+        assert_eq!(
+            body["payload"]["summary"],
+            "shadow-write: vipw /etc/shadow"
+        );
+        assert_eq!(body["payload"]["severity"], "critical");
+        assert_eq!(body["payload"]["source"], "web1");
+        assert_eq!(body["payload"]["custom_details"]["pid"], 42);
+
+        let body: serde_json::Value =
+            serde_json::from_str(&resolve_body("R0UT1NG", &key)).unwrap();
+        assert_eq!(body["event_action"], "resolve");
+        assert_eq!(body["routing_key"], "R0UT1NG");
+
+        let long = format!("/srv/{}", "d/".repeat(200));
+        let key = dedup_key("data", &long);
+        assert!(key.starts_with("fw:data:sha256:"));
+        assert_eq!(key.len(), "fw:data:sha256:".len() + 64);
+    }
+
+    #[test]
+    fn test_incidents_resolve_on_quiet() {
+        let mut incidents = Incidents::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let quiet = Some(Duration::from_secs(60));
+        assert!(incidents.fire("a".to_string(), quiet, at(0)));
+        incidents.sent("a");
+        // A folded repeat still keeps the incident open
+        assert!(!incidents.fire("a".to_string(), quiet, at(30)));
+        assert!(incidents.fire("b".to_string(), None, at(30)));
+        // Repeats collapse while the trigger waits to be sent
+        assert!(!incidents.fire("b".to_string(), None, at(31)));
+        incidents.sent("b");
+        assert!(incidents.fire("b".to_string(), None, at(32)));
+        assert!(incidents.quiet(at(89)).is_empty());
+        assert_eq!(incidents.quiet(at(90)), vec!["a".to_string()]);
+        // Firing again after the resolve opens a new incident
+        assert!(incidents.fire("a".to_string(), quiet, at(91)));
+
+        // A trigger that couldn't be queued is tried again
+        assert!(incidents.fire("c".to_string(), quiet, at(91)));
+        incidents.withdraw("c");
+        assert!(incidents.fire("c".to_string(), quiet, at(92)));
+    }
+}
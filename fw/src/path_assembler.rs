@@ -0,0 +1,294 @@
+//! Path Assembler module
+//!
+//! Reassembles file paths that the eBPF program sends in multiple chunks
+//! because they are longer than a single event can carry. Chunks are keyed
+//! by pid/tgid, which is unique for an in-flight system call.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use fw_common::FileEvent as RawFileEvent;
+
+/// A complete path rebuilt from one or more kernel chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledPath {
+    /// Full path text, escaped with [`escape_path`] if not valid UTF-8
+    pub path: String,
+    /// Exact path bytes when they are not valid UTF-8
+    pub raw: Option<Vec<u8>>,
+    /// True if the kernel could not capture the whole path
+    pub truncated: bool,
+}
+
+impl AssembledPath {
+    /// Build a path from the bytes the kernel reported
+    ///
+    /// # Arguments
+    /// * `bytes` - Path bytes without a terminator
+    /// * `truncated` - True if the kernel could not capture the whole path
+    ///
+    /// # Returns
+    /// * `AssembledPath` - Path text, keeping the bytes if they aren't
+    ///   valid UTF-8
+    pub fn from_bytes(bytes: Vec<u8>, truncated: bool) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(path) => Self {
+                path,
+                raw: None,
+                truncated,
+            },
+            Err(e) => {
+                let raw = e.into_bytes();
+                Self {
+                    path: escape_path(&raw),
+                    raw: Some(raw),
+                    truncated,
+                }
+            }
+        }
+    }
+
+    /// Exact bytes of the path
+    fn bytes(&self) -> Vec<u8> {
+        self.raw
+            .clone()
+            .unwrap_or_else(|| self.path.clone().into_bytes())
+    }
+
+    /// Resolve this relative path against a directory
+    ///
+    /// # Arguments
+    /// * `dir` - Directory the path starts from
+    ///
+    /// # Returns
+    /// * `AssembledPath` - Directory and path joined with one slash,
+    ///   truncated if either was
+    pub fn under(&self, dir: &AssembledPath) -> Self {
+        let mut bytes = dir.bytes();
+        if !bytes.ends_with(b"/") {
+            bytes.push(b'/');
+        }
+        bytes.extend(self.bytes());
+        Self::from_bytes(bytes, dir.truncated || self.truncated)
+    }
+}
+
+/// Escape path bytes that are not valid UTF-8 as text
+///
+/// Invalid bytes become `\xNN` and backslashes are doubled, so
+/// [`unescape_path`] recovers the exact bytes. Only used for paths that
+/// are not valid UTF-8; valid paths are kept as they are.
+///
+/// # Arguments
+/// * `bytes` - Path bytes
+///
+/// # Returns
+/// * `String` - Escaped path text
+pub fn escape_path(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            let _ = write!(out, "\\x{:02x}", byte);
+        }
+    }
+    out
+}
+
+/// Recover the bytes of a path escaped by [`escape_path`]
+///
+/// # Arguments
+/// * `text` - Escaped path text
+///
+/// # Returns
+/// * `Vec<u8>` - Original path bytes
+pub fn unescape_path(text: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+        let hex = rest
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (hex, rest.first()) {
+            (Some(decoded), _) => {
+                out.push(decoded);
+                rest = &rest[3..];
+            }
+            (None, Some(b'\\')) => {
+                out.push(b'\\');
+                rest = &rest[1..];
+            }
+            (None, _) => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Collects path chunks until the final chunk for a pid/tgid arrives
+#[derive(Debug, Default)]
+pub struct PathAssembler {
+    /// Partially received paths keyed by (pid, tgid)
+    pending: HashMap<(u32, u32), Vec<u8>>,
+}
+
+impl PathAssembler {
+    /// Create an empty path assembler
+    ///
+    /// # Returns
+    /// * `PathAssembler` - Assembler with no pending paths
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a raw kernel event and return the full path once complete
+    ///
+    /// A chunk with index 0 always starts a new path, discarding any stale
+    /// partial path left behind by a lost chunk.
+    ///
+    /// # Arguments
+    /// * `raw` - Raw event received from the eBPF program
+    ///
+    /// # Returns
+    /// * `Option<AssembledPath>` - The full path, or None if more chunks
+    ///   are still expected
+    pub fn push(&mut self, raw: &RawFileEvent) -> Option<AssembledPath> {
+        let key = (raw.pid, raw.tgid);
+
+        // Start fresh on the first chunk of a path
+        if raw.chunk_index == 0 {
+            self.pending.remove(&key);
+        }
+
+        let bytes = self.pending.entry(key).or_default();
+        bytes.extend_from_slice(Self::chunk_bytes(raw));
+
+        if raw.has_more_chunks() {
+            return None;
+        }
+
+        // Final chunk received - hand back the complete path
+        let bytes = self.pending.remove(&key).unwrap_or_default();
+        Some(AssembledPath::from_bytes(bytes, raw.is_path_truncated()))
+    }
+
+    /// Number of paths currently waiting for more chunks
+    ///
+    /// # Returns
+    /// * `usize` - Count of incomplete paths
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Clear all partially assembled paths
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Get the path bytes of a chunk, up to its null terminator
+    ///
+    /// # Arguments
+    /// * `raw` - Raw event carrying the chunk
+    ///
+    /// # Returns
+    /// * `&[u8]` - Path bytes without the terminator
+    fn chunk_bytes(raw: &RawFileEvent) -> &[u8] {
+        let end = raw
+            .path
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(raw.path.len());
+        &raw.path[..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fw_common::{
+        EVENT_ABI_VERSION, EVENT_FLAG_MORE_CHUNKS, EVENT_FLAG_PATH_TRUNCATED,
+        PATH_CHUNK_LEN,
+    };
+    use zerocopy::FromZeros;
+
+    /// Build a raw event carrying the given chunk of path text
+    fn raw_chunk(text: &[u8], chunk_index: u32, flags: u32) -> RawFileEvent {
+        let mut raw = RawFileEvent::new_zeroed();
+        raw.version = EVENT_ABI_VERSION;
+        raw.pid = 42;
+        raw.tgid = 42;
+        raw.path[..text.len()].copy_from_slice(text);
+        raw.chunk_index = chunk_index;
+        raw.flags = flags;
+        raw
+    }
+
+    #[test]
+    fn test_single_chunk_path() {
+        let mut assembler = PathAssembler::new();
+        let result = assembler.push(&raw_chunk(b"/tmp/a.rs", 0, 0));
+        assert_eq!(
+            result,
+            Some(AssembledPath {
+                path: "/tmp/a.rs".to_string(),
+                raw: None,
+                truncated: false,
+            })
+        );
+        assert_eq!(assembler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_non_utf8_path_is_kept_exactly() {
+        let mut assembler = PathAssembler::new();
+        let bytes = b"/srv/caf\xe9\\x.txt";
+        let result = assembler.push(&raw_chunk(bytes, 0, 0)).unwrap();
+        assert_eq!(result.path, "/srv/caf\\xe9\\\\x.txt");
+        assert_eq!(result.raw.as_deref(), Some(&bytes[..]));
+        assert_eq!(unescape_path(&result.path), bytes);
+
+        let valid = AssembledPath::from_bytes(b"/srv/a\\b".to_vec(), false);
+        assert_eq!((valid.path.as_str(), valid.raw), ("/srv/a\\b", None));
+    }
+
+    #[test]
+    fn test_multi_chunk_path() {
+        let mut assembler = PathAssembler::new();
+        let first = vec![b'a'; PATH_CHUNK_LEN];
+
+        let partial =
+            assembler.push(&raw_chunk(&first, 0, EVENT_FLAG_MORE_CHUNKS));
+        assert!(partial.is_none());
+        assert_eq!(assembler.pending_count(), 1);
+
+        let result = assembler.push(&raw_chunk(b"/end.rs", 1, 0)).unwrap();
+        assert_eq!(result.path.len(), PATH_CHUNK_LEN + "/end.rs".len());
+        assert!(result.path.ends_with("a/end.rs"));
+        assert!(!result.truncated);
+        assert_eq!(assembler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_truncated_path_is_flagged() {
+        let mut assembler = PathAssembler::new();
+        let result = assembler
+            .push(&raw_chunk(b"/very/long", 0, EVENT_FLAG_PATH_TRUNCATED))
+            .unwrap();
+        assert!(result.truncated);
+    }
+
+    #[test]
+    fn test_new_first_chunk_discards_stale_partial() {
+        let mut assembler = PathAssembler::new();
+        assembler.push(&raw_chunk(b"/stale", 0, EVENT_FLAG_MORE_CHUNKS));
+
+        let result = assembler.push(&raw_chunk(b"/fresh", 0, 0)).unwrap();
+        assert_eq!(result.path, "/fresh");
+    }
+}
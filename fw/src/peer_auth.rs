@@ -0,0 +1,229 @@
+//! Peer Auth module
+//!
+//! Decides which `fw forward` instances `fw collect-remote` accepts
+//! events from. A client certificate allowlist limits connections to
+//! certificates issued for known names, and per-host tokens stop a
+//! forwarder from claiming another host's name in its hello line, so a
+//! machine that can reach the port can't inject events as someone else.
+
+use anyhow::{anyhow, Context, Result};
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use webpki::EndEntityCert;
+
+use crate::forward::Hello;
+
+/// Tag holding the certificate name a forwarder authenticated as
+pub const PEER_TAG: &str = "peer";
+
+/// Which forwarders may send events
+#[derive(Debug, Clone, Default)]
+pub struct PeerPolicy {
+    /// Names a client certificate must be valid for; any certificate the
+    /// client CA issued is accepted if unset
+    allowlist: Option<Vec<String>>,
+    /// Token each host must present; hosts aren't checked if unset
+    tokens: Option<HashMap<String, String>>,
+}
+
+impl PeerPolicy {
+    /// Load the allowlist and token files
+    ///
+    /// # Arguments
+    /// * `allowlist` - File of certificate names, one per line, if any
+    /// * `tokens` - File of "host token" lines, if any
+    ///
+    /// # Returns
+    /// * `Result<PeerPolicy>` - Policy, or error naming the bad file or line
+    pub fn load(
+        allowlist: Option<&Path>,
+        tokens: Option<&Path>,
+    ) -> Result<Self> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+        let mut policy = Self::default();
+        if let Some(path) = allowlist {
+            policy.allowlist = Some(parse_allowlist(&read(path)?));
+        }
+        if let Some(path) = tokens {
+            policy.tokens =
+                Some(parse_tokens(&read(path)?).with_context(|| {
+                    format!("Invalid token file {}", path.display())
+                })?);
+        }
+        Ok(policy)
+    }
+
+    /// Whether connections must present a client certificate
+    ///
+    /// # Returns
+    /// * `bool` - True if an allowlist is set
+    pub fn needs_client_cert(&self) -> bool {
+        self.allowlist.is_some()
+    }
+
+    /// Check a forwarder's certificate and hello line
+    ///
+    /// # Arguments
+    /// * `certs` - Verified client certificate chain, leaf first, if any
+    /// * `hello` - Hello line the forwarder sent
+    ///
+    /// # Returns
+    /// * `Result<Option<String>>` - Allowlisted name the certificate
+    ///   matched (None without an allowlist), or error if the forwarder
+    ///   isn't allowed
+    pub fn authenticate(
+        &self,
+        certs: Option<&[CertificateDer<'_>]>,
+        hello: &Hello,
+    ) -> Result<Option<String>> {
+        let peer = match &self.allowlist {
+            Some(names) => Some(allowed_name(names, certs)?),
+            None => None,
+        };
+        if let Some(tokens) = &self.tokens {
+            let expected = tokens
+                .get(&hello.host)
+                .ok_or_else(|| anyhow!("Host {} has no token", hello.host))?;
+            let token = hello.token.as_deref().unwrap_or_default();
+            if !tokens_match(expected, token) {
+                return Err(anyhow!("Wrong token for host {}", hello.host));
+            }
+        }
+        Ok(peer)
+    }
+}
+
+/// First allowlisted name a client certificate is valid for
+fn allowed_name(
+    names: &[String],
+    certs: Option<&[CertificateDer<'_>]>,
+) -> Result<String> {
+    let leaf = certs
+        .and_then(|certs| certs.first())
+        .ok_or_else(|| anyhow!("No client certificate"))?;
+    let cert = EndEntityCert::try_from(leaf)
+        .map_err(|e| anyhow!("Unreadable client certificate: {}", e))?;
+    names
+        .iter()
+        .find(|name| {
+            ServerName::try_from(name.as_str()).is_ok_and(|name| {
+                cert.verify_is_valid_for_subject_name(&name).is_ok()
+            })
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!("Client certificate is not on the allowlist"))
+}
+
+/// Compare tokens in time independent of where they differ
+fn tokens_match(expected: &str, token: &str) -> bool {
+    expected.len() == token.len()
+        && expected
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Parse allowlist lines, skipping blanks and '#' comments
+///
+/// # Arguments
+/// * `text` - Certificate names, one per line
+///
+/// # Returns
+/// * `Vec<String>` - Names in file order
+pub fn parse_allowlist(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse "host token" lines, skipping blanks and '#' comments
+///
+/// # Arguments
+/// * `text` - Token file contents
+///
+/// # Returns
+/// * `Result<HashMap<String, String>>` - Token per host, or error naming
+///   the bad line
+pub fn parse_tokens(text: &str) -> Result<HashMap<String, String>> {
+    let mut tokens = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [host, token] = fields[..] else {
+            return Err(anyhow!("line {}: expected 'host token'", number + 1));
+        };
+        tokens.insert(host.to_string(), token.to_string());
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(host: &str, token: Option<&str>) -> Hello {
+        Hello {
+            version: 1,
+            host: host.to_string(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_tokens_bind_hosts() {
+        let policy = PeerPolicy {
+            allowlist: None,
+            tokens: Some(
+                parse_tokens("# fleet\nweb1 s3cret\nweb2 other\n").unwrap(),
+            ),
+        };
+        let ok = policy.authenticate(None, &hello("web1", Some("s3cret")));
+        assert_eq!(ok.unwrap(), None);
+        // web2's token doesn't let it claim to be web1
+        assert!(policy
+            .authenticate(None, &hello("web1", Some("other")))
+            .is_err());
+        assert!(policy.authenticate(None, &hello("web1", None)).is_err());
+        assert!(policy
+            .authenticate(None, &hello("db1", Some("s3cret")))
+            .is_err());
+        assert!(parse_tokens("web1\n").is_err());
+    }
+
+    #[test]
+    fn test_allowlist_matches_certificate_names() {
+        let names = vec!["web1.example".to_string()];
+        let allowed = rcgen::generate_simple_self_signed(names.clone())
+            .unwrap()
+            .cert;
+        let other =
+            rcgen::generate_simple_self_signed(vec!["evil.example".into()])
+                .unwrap()
+                .cert;
+        let policy = PeerPolicy {
+            allowlist: Some(parse_allowlist("\nweb1.example\n# old\n")),
+            tokens: None,
+        };
+        assert!(policy.needs_client_cert());
+
+        let certs = [allowed.der().clone()];
+        let peer = policy.authenticate(Some(&certs), &hello("web1", None));
+        assert_eq!(peer.unwrap().as_deref(), Some("web1.example"));
+        let certs = [other.der().clone()];
+        assert!(policy
+            .authenticate(Some(&certs), &hello("web1", None))
+            .is_err());
+        assert!(policy.authenticate(None, &hello("web1", None)).is_err());
+    }
+}
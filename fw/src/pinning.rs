@@ -0,0 +1,488 @@
+//! Pinning module
+//!
+//! Manages the bpffs directories where fw pins its in-kernel state so a
+//! restarted fw can pick up where a crashed one left off. Every instance
+//! pins into its own directory under `/sys/fs/bpf/fw`, so concurrent runs
+//! never share maps unless they opt into the common `shared` deployment.
+//! Maps are pinned under `maps/` and program links under `links/`; an
+//! `owners` file records which processes use the pins so a conflicting
+//! instance is rejected and `fw cleanup` never removes state in use.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// bpffs directory holding one pin directory per fw instance
+pub const PIN_ROOT: &str = "/sys/fs/bpf/fw";
+
+/// Instance name of the deployment shared by `--shared` runs
+pub const SHARED_INSTANCE: &str = "shared";
+
+/// Maps whose contents are worth keeping across a restart: the state of
+/// calls in flight and descriptors, and the filters
+pub const PINNED_MAPS: [&str; 14] = [
+    "OPEN_FILES",
+    "OPEN_PATH_PTRS",
+    "DUP_SOURCES",
+    "CLONE_FLAGS",
+    "LINK_ARGS",
+    "SYNC_CALLS",
+    "LOCK_CALLS",
+    "METADATA_CALLS",
+    "CHDIR_CALLS",
+    "IO_CALLS",
+    "UID_FILTER",
+    "UID_FILTER_ACTIVE",
+    "COMM_FILTER",
+    "COMM_FILTER_MODE",
+];
+
+/// Name of the file listing the processes that use the pins
+const OWNERS_FILE: &str = "owners";
+
+/// What `fw cleanup` removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Number of pinned maps removed
+    pub maps_removed: usize,
+    /// Number of pinned program links removed, detaching their programs
+    pub links_removed: usize,
+}
+
+/// A directory on bpffs holding one instance's pinned maps and links
+#[derive(Debug, Clone)]
+pub struct PinDir {
+    /// Root directory of the pins
+    root: PathBuf,
+    /// Whether several live processes may use the pins at once
+    shared: bool,
+}
+
+impl PinDir {
+    /// Use the given directory for pins owned by a single process
+    ///
+    /// # Arguments
+    /// * `root` - Directory on a mounted bpffs
+    ///
+    /// # Returns
+    /// * `PinDir` - Pin directory handle (nothing is created yet)
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            shared: false,
+        }
+    }
+
+    /// Pin directory of a named instance
+    ///
+    /// # Arguments
+    /// * `name` - Instance name; a stable name lets `--reuse-pinned` find
+    ///   the pins after a restart
+    ///
+    /// # Returns
+    /// * `Result<PinDir>` - Pin directory, or error for an unusable name
+    pub fn for_instance(name: &str) -> Result<Self> {
+        let valid = !name.is_empty()
+            && name != SHARED_INSTANCE
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(anyhow!(
+                "Invalid instance name '{}': use letters, digits, '-', '_' \
+                 or '.', and not '{}'",
+                name,
+                SHARED_INSTANCE
+            ));
+        }
+        Ok(Self::new(Path::new(PIN_ROOT).join(name)))
+    }
+
+    /// Pin directory private to this process
+    ///
+    /// # Returns
+    /// * `PinDir` - Pin directory named after the current process ID
+    pub fn for_current_process() -> Self {
+        Self::new(
+            Path::new(PIN_ROOT).join(format!("pid-{}", std::process::id())),
+        )
+    }
+
+    /// Pin directory of the deployment shared by cooperating instances
+    ///
+    /// # Returns
+    /// * `PinDir` - Shared pin directory
+    pub fn shared() -> Self {
+        Self {
+            root: Path::new(PIN_ROOT).join(SHARED_INSTANCE),
+            shared: true,
+        }
+    }
+
+    /// Root directory of the pins
+    ///
+    /// # Returns
+    /// * `&Path` - Pin root
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where a map with the given name is pinned
+    ///
+    /// # Arguments
+    /// * `name` - Map name in the eBPF object
+    ///
+    /// # Returns
+    /// * `PathBuf` - Pin path of the map
+    pub fn map_path(&self, name: &str) -> PathBuf {
+        self.root.join("maps").join(name)
+    }
+
+    /// Directory holding pinned program links
+    ///
+    /// # Returns
+    /// * `PathBuf` - Link pin directory
+    pub fn link_dir(&self) -> PathBuf {
+        self.root.join("links")
+    }
+
+    /// Check whether every map needed for a restart is pinned
+    ///
+    /// # Returns
+    /// * `bool` - True if all `PINNED_MAPS` have pins
+    pub fn has_pinned_maps(&self) -> bool {
+        PINNED_MAPS.iter().all(|name| self.map_path(name).exists())
+    }
+
+    /// Processes currently using the pins that are still alive
+    ///
+    /// # Returns
+    /// * `Vec<u32>` - Live owner process IDs
+    pub fn owners(&self) -> Vec<u32> {
+        let text =
+            fs::read_to_string(self.root.join(OWNERS_FILE)).unwrap_or_default();
+        text.lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .filter(|pid| Path::new(&format!("/proc/{}", pid)).exists())
+            .collect()
+    }
+
+    /// Take ownership of the pins for this process
+    ///
+    /// Creates the pin directories if needed. Without `reuse`, pins left
+    /// behind by a previous run are removed so monitoring starts fresh.
+    /// A shared directory that is already in use is joined as-is.
+    ///
+    /// # Arguments
+    /// * `reuse` - Keep existing pins so their state carries over
+    ///
+    /// # Returns
+    /// * `Result<()>` - Error if another live fw owns unshared pins
+    pub fn claim(&self, reuse: bool) -> Result<()> {
+        let me = std::process::id();
+        let mut owners: Vec<u32> =
+            self.owners().into_iter().filter(|&pid| pid != me).collect();
+
+        if !owners.is_empty() && !self.shared {
+            return Err(anyhow!(
+                "Pins in {} are in use by fw process {}; choose another \
+                 --instance or use --shared",
+                self.root.display(),
+                owners[0]
+            ));
+        }
+
+        if !owners.is_empty() {
+            info!(
+                "Joining shared deployment in {} used by {} other fw \
+                 process(es)",
+                self.root.display(),
+                owners.len()
+            );
+        } else if reuse {
+            if self.has_pinned_maps() {
+                info!("Reusing pinned maps in {}", self.root.display());
+            } else {
+                warn!(
+                    "No pinned maps in {}, starting with empty state",
+                    self.root.display()
+                );
+            }
+        } else {
+            self.remove_pins()?;
+        }
+
+        for dir in [self.root.join("maps"), self.link_dir()] {
+            fs::create_dir_all(&dir).with_context(|| {
+                format!("Failed to create {}", dir.display())
+            })?;
+        }
+        owners.push(me);
+        self.write_owners(&owners)
+    }
+
+    /// Give up ownership, leaving the pins for a later `--reuse-pinned`
+    pub fn release(&self) {
+        let me = std::process::id();
+        let owners: Vec<u32> =
+            self.owners().into_iter().filter(|&pid| pid != me).collect();
+        if let Err(e) = self.write_owners(&owners) {
+            warn!("Failed to release pins: {}", e);
+        }
+    }
+
+    /// Record the processes using the pins
+    ///
+    /// # Arguments
+    /// * `owners` - Owner process IDs; an empty list removes the file
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    fn write_owners(&self, owners: &[u32]) -> Result<()> {
+        let path = self.root.join(OWNERS_FILE);
+        if owners.is_empty() {
+            return remove_if_exists(&path);
+        }
+        let text: String =
+            owners.iter().map(|pid| format!("{}\n", pid)).collect();
+        fs::write(&path, text).context("Failed to record pin owners")
+    }
+
+    /// Remove stale pins, detaching any programs left attached
+    ///
+    /// # Arguments
+    /// * `force` - Remove pins even if a live fw process owns them
+    ///
+    /// # Returns
+    /// * `Result<CleanupReport>` - What was removed
+    pub fn cleanup(&self, force: bool) -> Result<CleanupReport> {
+        if let Some(pid) = self.owners().first() {
+            if !force {
+                return Err(anyhow!(
+                    "Pins in {} are in use by fw process {} (use --force)",
+                    self.root.display(),
+                    pid
+                ));
+            }
+        }
+        let report = self.remove_pins()?;
+        remove_if_exists(&self.root.join(OWNERS_FILE))?;
+        for dir in [self.root.join("maps"), self.link_dir(), self.root.clone()]
+        {
+            match fs::remove_dir(&dir) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove {}: {}", dir.display(), e),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Remove every pinned map and link
+    ///
+    /// Unpinning a link drops the last reference to it, which detaches the
+    /// program from the kernel.
+    ///
+    /// # Returns
+    /// * `Result<CleanupReport>` - What was removed
+    fn remove_pins(&self) -> Result<CleanupReport> {
+        Ok(CleanupReport {
+            maps_removed: remove_entries(&self.root.join("maps"))?,
+            links_removed: remove_entries(&self.link_dir())?,
+        })
+    }
+}
+
+/// Remove every file in a directory
+///
+/// # Arguments
+/// * `dir` - Directory to empty; a missing directory counts as empty
+///
+/// # Returns
+/// * `Result<usize>` - Number of entries removed
+fn remove_entries(dir: &Path) -> Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read {}", dir.display()))
+        }
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Remove a file, ignoring it if it is already gone
+///
+/// # Arguments
+/// * `path` - File to remove
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e)
+            .with_context(|| format!("Failed to remove {}", path.display())),
+        _ => Ok(()),
+    }
+}
+
+/// Remove stale pins left behind by crashed fw instances
+///
+/// Without an instance name, every instance directory is checked and the
+/// ones still in use are skipped (or removed too with `force`).
+///
+/// # Arguments
+/// * `instance` - Only clean up this instance
+/// * `force` - Remove pins even if a live fw process owns them
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_cleanup(instance: Option<String>, force: bool) -> Result<()> {
+    let instances = match instance {
+        Some(name) if name == SHARED_INSTANCE => vec![PinDir::shared()],
+        Some(name) => vec![PinDir::for_instance(&name)?],
+        None => list_instances(Path::new(PIN_ROOT))?,
+    };
+
+    for pins in instances {
+        match pins.cleanup(force) {
+            Ok(report) => eprintln!(
+                "Removed {} pinned maps and {} pinned program links from {}",
+                report.maps_removed,
+                report.links_removed,
+                pins.root().display()
+            ),
+            Err(e) => eprintln!("Skipped: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Find every instance pin directory under a pin root
+///
+/// # Arguments
+/// * `root` - Directory holding instance directories
+///
+/// # Returns
+/// * `Result<Vec<PinDir>>` - One entry per instance directory
+fn list_instances(root: &Path) -> Result<Vec<PinDir>> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read {}", root.display()))
+        }
+    };
+    let mut instances = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            instances.push(PinDir::new(entry.path()));
+        }
+    }
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pin directory in a fresh temporary directory
+    fn temp_pins() -> (tempfile::TempDir, PinDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let pins = PinDir::new(dir.path().join("fw"));
+        (dir, pins)
+    }
+
+    /// Pin every restart map as if a previous run had done it
+    fn pin_all_maps(pins: &PinDir) {
+        for name in PINNED_MAPS {
+            fs::write(pins.map_path(name), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_claim_reuse_keeps_pins() {
+        let (_dir, pins) = temp_pins();
+        pins.claim(false).unwrap();
+        pin_all_maps(&pins);
+        pins.release();
+
+        pins.claim(true).unwrap();
+        assert!(pins.has_pinned_maps());
+        assert_eq!(pins.owners(), vec![std::process::id()]);
+    }
+
+    #[test]
+    fn test_claim_conflicts_with_live_instance() {
+        let (_dir, pins) = temp_pins();
+        pins.claim(false).unwrap();
+        // pid 1 is always alive and stands in for another fw instance
+        fs::write(pins.root().join(OWNERS_FILE), "1\n").unwrap();
+
+        let err = pins.claim(false).unwrap_err();
+        assert!(err.to_string().contains("in use by fw process 1"));
+
+        let shared = PinDir {
+            shared: true,
+            ..pins.clone()
+        };
+        pin_all_maps(&shared);
+        shared.claim(false).unwrap();
+        assert!(shared.has_pinned_maps(), "joining must not wipe pins");
+        assert_eq!(shared.owners(), vec![1, std::process::id()]);
+
+        shared.release();
+        assert_eq!(shared.owners(), vec![1]);
+    }
+
+    #[test]
+    fn test_instance_names() {
+        assert!(PinDir::for_instance("ci-build_2").is_ok());
+        assert!(PinDir::for_instance("../etc").is_err());
+        assert!(PinDir::for_instance(SHARED_INSTANCE).is_err());
+        assert_ne!(
+            PinDir::for_current_process().root(),
+            PinDir::shared().root()
+        );
+    }
+
+    #[test]
+    fn test_claim_without_reuse_starts_fresh() {
+        let (_dir, pins) = temp_pins();
+        pins.claim(false).unwrap();
+        pin_all_maps(&pins);
+        pins.release();
+
+        pins.claim(false).unwrap();
+        assert!(!pins.has_pinned_maps());
+    }
+
+    #[test]
+    fn test_cleanup_respects_live_owner() {
+        let (_dir, pins) = temp_pins();
+        pins.claim(false).unwrap();
+        pin_all_maps(&pins);
+        fs::write(pins.link_dir().join("openat"), "").unwrap();
+
+        // This test process is alive, so it counts as an active owner
+        assert!(pins.cleanup(false).is_err());
+
+        let report = pins.cleanup(true).unwrap();
+        assert_eq!(report.maps_removed, PINNED_MAPS.len());
+        assert_eq!(report.links_removed, 1);
+        assert!(!pins.root().exists());
+    }
+}
@@ -0,0 +1,919 @@
+//! Probe Decode module
+//!
+//! Translates raw kernel events into FileEvents. The decoder owns the
+//! state the translation needs (paths still arriving in chunks, the
+//! descriptor table and pending link sources) and nothing tied to the
+//! loaded probes, so recorded perf buffer bytes can be replayed through it
+//! in tests. Process names, file types looked up on disk and stacks are
+//! added afterwards by the monitor.
+
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::clock::{self, Clock};
+use crate::fd_table::FdTable;
+use crate::file_event::{FileAction, FileEvent, LOCK_WAIT_THRESHOLD_NS};
+use crate::path_assembler::{escape_path, AssembledPath, PathAssembler};
+use fw_common::decoded::{DecodedFileEvent, EventKind};
+use fw_common::{
+    DecodeError, FileEvent as RawFileEvent, EVENT_TYPE_CHMOD, EVENT_TYPE_CHOWN,
+    EVENT_TYPE_LOCK, EVENT_TYPE_READ, EVENT_TYPE_REMOVEXATTR,
+    EVENT_TYPE_SETXATTR, EVENT_TYPE_SYNC, EVENT_TYPE_TRUNCATE,
+    EVENT_TYPE_UNLOCK, EVENT_TYPE_WRITE,
+};
+
+/// Stateful translator from raw kernel events to FileEvents
+#[derive(Debug)]
+pub struct ProbeDecoder {
+    /// Reassembles paths sent in several chunks
+    path_assembler: PathAssembler,
+    /// Descriptors each process has open
+    fd_table: FdTable,
+    /// Source paths of links and renames in progress, keyed by
+    /// (pid, tgid), waiting for their target
+    link_sources: HashMap<(u32, u32), AssembledPath>,
+    /// Time source for event timestamps
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ProbeDecoder {
+    fn default() -> Self {
+        Self {
+            path_assembler: PathAssembler::new(),
+            fd_table: FdTable::new(),
+            link_sources: HashMap::new(),
+            clock: clock::system(),
+        }
+    }
+}
+
+impl ProbeDecoder {
+    /// Create a decoder with no descriptors or partial paths
+    ///
+    /// # Returns
+    /// * `ProbeDecoder` - Empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from descriptors that were open before monitoring began
+    ///
+    /// # Arguments
+    /// * `fd_table` - Descriptor table, e.g. from `FdTable::scan`
+    ///
+    /// # Returns
+    /// * `Self` - The decoder, for chaining
+    pub fn with_fd_table(mut self, fd_table: FdTable) -> Self {
+        self.fd_table = fd_table;
+        self
+    }
+
+    /// Stamp events with the time from a different clock
+    ///
+    /// # Arguments
+    /// * `clock` - Time source, e.g. a `MockClock` in tests
+    ///
+    /// # Returns
+    /// * `Self` - The decoder, for chaining
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Descriptors currently tracked
+    ///
+    /// # Returns
+    /// * `&FdTable` - Descriptor table
+    pub fn fd_table(&self) -> &FdTable {
+        &self.fd_table
+    }
+
+    /// Number of paths still waiting for more chunks
+    ///
+    /// # Returns
+    /// * `usize` - Count of incomplete paths
+    pub fn pending_count(&self) -> usize {
+        self.path_assembler.pending_count()
+    }
+
+    /// Forget all descriptors, partial paths and link sources
+    pub fn clear(&mut self) {
+        self.path_assembler.clear();
+        self.fd_table.clear();
+        self.link_sources.clear();
+    }
+
+    /// Decode one perf buffer record
+    ///
+    /// The bytes are validated for size, alignment and layout version
+    /// before being interpreted, without copying the raw event.
+    ///
+    /// # Arguments
+    /// * `bytes` - One record read from the perf buffer
+    ///
+    /// # Returns
+    /// * `Result<Option<FileEvent>>` - Decoded event, None if there is
+    ///   nothing to report yet, or a decode error
+    pub fn decode_bytes(&mut self, bytes: &[u8]) -> Result<Option<FileEvent>> {
+        let raw = RawFileEvent::from_bytes(bytes)
+            .context("Failed to decode raw kernel event")?;
+        Ok(self.decode(raw))
+    }
+
+    /// Translate a raw kernel event into a FileEvent
+    ///
+    /// Paths longer than one chunk arrive as several raw events; this
+    /// returns None until the final chunk has been received. Descriptor
+    /// bookkeeping events (dup, fork, exit, chdir) only update the
+    /// descriptor table, and closes of descriptors whose open was never
+    /// seen (pipes, sockets, files opened before monitoring began) are
+    /// dropped. Paths the process gave relative to a directory descriptor
+    /// or its working directory are joined onto the directory's path.
+    ///
+    /// The event type and its arguments are read through fw-common's
+    /// `DecodedFileEvent`, and events it rejects (another layout version,
+    /// an unknown type) are skipped. The program name is left empty, and
+    /// the file type is left unset for events that name a path rather
+    /// than a descriptor.
+    ///
+    /// # Arguments
+    /// * `raw` - Raw event received from the eBPF program
+    ///
+    /// # Returns
+    /// * `Option<FileEvent>` - Decoded event, or None if there is nothing
+    ///   to report yet
+    pub fn decode(&mut self, raw: &RawFileEvent) -> Option<FileEvent> {
+        let kind = match event_kind(raw) {
+            Ok(kind) => kind,
+            Err(e) => {
+                debug!("Ignoring kernel event: {}", e);
+                return None;
+            }
+        };
+        let mut latency_ns = None;
+        let mut file_id = None;
+        let mut link_source = None;
+        let mut renamed_from = None;
+        let mut open_flags = None;
+        let mut xattr_name = None;
+        let mut fd = None;
+        // Set by opens and closes, which must not look the descriptor up
+        let mut known_type = None;
+        let (assembled, action) = match kind {
+            EventKind::Open {
+                flags,
+                file_type,
+                file_id: id,
+                latency_ns: latency,
+            } => {
+                let assembled = self.path_assembler.push(raw)?;
+                let assembled =
+                    self.fd_table.resolve_at(raw.pid, raw.old_fd, assembled);
+                known_type = Some(file_type);
+                self.fd_table.open(
+                    raw.pid,
+                    raw.fd,
+                    assembled.clone(),
+                    file_type,
+                );
+                latency_ns = Some(latency);
+                file_id = id;
+                self.fd_table.identify(raw.pid, raw.fd, file_id);
+                fd = Some(raw.fd);
+                open_flags = Some(flags);
+                (assembled, FileAction::Opened)
+            }
+            EventKind::Close => {
+                fd = Some(raw.fd);
+                file_id = self.fd_table.file_id(raw.pid, raw.fd);
+                known_type = Some(self.fd_table.file_type(raw.pid, raw.fd));
+                (self.fd_table.close(raw.pid, raw.fd)?, FileAction::Closed)
+            }
+            EventKind::Chmod { mode } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::ModeChanged { mode },
+            ),
+            EventKind::Chown { uid, gid } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::OwnerChanged { uid, gid },
+            ),
+            EventKind::Truncate { length } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::Truncated { length },
+            ),
+            EventKind::Sync {
+                kind,
+                latency_ns: latency,
+            } => {
+                latency_ns = Some(latency);
+                (
+                    self.fd_table.resolve(raw.pid, raw.fd)?,
+                    FileAction::Synced { kind },
+                )
+            }
+            EventKind::Lock {
+                kind,
+                nonblocking,
+                refused,
+                latency_ns: latency,
+                ..
+            } => {
+                // Blocking calls that took a while waited for a holder
+                let waited = !nonblocking && latency >= LOCK_WAIT_THRESHOLD_NS;
+                latency_ns = Some(latency);
+                (
+                    self.fd_table.resolve(raw.pid, raw.fd)?,
+                    FileAction::Locked {
+                        kind,
+                        blocked: refused || waited,
+                        acquired: !refused,
+                    },
+                )
+            }
+            EventKind::Unlock { .. } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::Unlocked,
+            ),
+            EventKind::Read { offset, bytes } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::Read { offset, bytes },
+            ),
+            EventKind::Write { offset, bytes } => (
+                self.fd_table.resolve(raw.pid, raw.fd)?,
+                FileAction::Written { offset, bytes },
+            ),
+            EventKind::LinkSource => {
+                let source = self.path_assembler.push(raw)?;
+                let source =
+                    self.fd_table.resolve_at(raw.pid, raw.old_fd, source);
+                self.link_sources.insert((raw.pid, raw.tgid), source);
+                return None;
+            }
+            kind @ (EventKind::Link | EventKind::Symlink) => {
+                let assembled = self.path_assembler.push(raw)?;
+                let mut assembled =
+                    self.fd_table.resolve_at(raw.pid, raw.old_fd, assembled);
+                let source = self.link_sources.remove(&(raw.pid, raw.tgid))?;
+                assembled.truncated |= source.truncated;
+                link_source = Some(source.path);
+                let action = if kind == EventKind::Link {
+                    FileAction::Linked
+                } else {
+                    FileAction::Symlinked
+                };
+                (assembled, action)
+            }
+            EventKind::Rename => {
+                let assembled = self.path_assembler.push(raw)?;
+                let mut assembled =
+                    self.fd_table.resolve_at(raw.pid, raw.old_fd, assembled);
+                let old = self.link_sources.remove(&(raw.pid, raw.tgid))?;
+                assembled.truncated |= old.truncated;
+                renamed_from = Some(old.path);
+                (assembled, FileAction::Renamed)
+            }
+            EventKind::SetXattr { name } => {
+                xattr_name = Some(name);
+                (self.xattr_target(raw)?, FileAction::XattrSet)
+            }
+            EventKind::RemoveXattr { name } => {
+                xattr_name = Some(name);
+                (self.xattr_target(raw)?, FileAction::XattrRemoved)
+            }
+            EventKind::Dup { old_fd } => {
+                self.fd_table.duplicate(raw.pid, old_fd, raw.fd);
+                return None;
+            }
+            EventKind::Chdir => {
+                let dir = match raw.fd {
+                    fd if fd >= 0 => self.fd_table.resolve(raw.pid, fd)?,
+                    _ => self.path_assembler.push(raw)?,
+                };
+                self.fd_table.chdir(raw.pid, dir);
+                return None;
+            }
+            EventKind::Fork { child_pid } => {
+                self.fd_table.fork(raw.pid, child_pid);
+                return None;
+            }
+            EventKind::Exit => {
+                self.fd_table.exit(raw.pid);
+                return None;
+            }
+        };
+        // Calls on a descriptor carry it and the file it refers to, so
+        // their events can be told apart from those of another open
+        let on_descriptor = match raw.event_type {
+            EVENT_TYPE_CHMOD | EVENT_TYPE_CHOWN | EVENT_TYPE_TRUNCATE
+            | EVENT_TYPE_SYNC | EVENT_TYPE_LOCK | EVENT_TYPE_UNLOCK
+            | EVENT_TYPE_READ | EVENT_TYPE_WRITE => true,
+            EVENT_TYPE_SETXATTR | EVENT_TYPE_REMOVEXATTR => raw.fd >= 0,
+            _ => false,
+        };
+        if on_descriptor {
+            fd = Some(raw.fd);
+            file_id = self.fd_table.file_id(raw.pid, raw.fd);
+        }
+        let file_type = match known_type {
+            Some(file_type) => file_type,
+            None if raw.fd >= 0 => self.fd_table.file_type(raw.pid, raw.fd),
+            None => None,
+        };
+
+        let mut event = FileEvent::new(
+            assembled.path,
+            String::new(),
+            action,
+            raw.pid,
+            self.clock.now(),
+        )
+        .with_path_truncated(assembled.truncated)
+        .with_raw_path(assembled.raw);
+        event.open_latency_ns = latency_ns;
+        event.link_source = link_source;
+        event.renamed_from = renamed_from;
+        event.open_flags = open_flags;
+        event.xattr_name = xattr_name;
+        event.fd = fd;
+        event.file_type = file_type;
+        Some(event.with_ids(raw.uid, raw.gid).with_file_id(file_id))
+    }
+
+    /// Path an extended attribute event applies to
+    ///
+    /// # Arguments
+    /// * `raw` - Setxattr or removexattr event
+    ///
+    /// # Returns
+    /// * `Option<AssembledPath>` - File open on the event's descriptor
+    ///   for the fd variants, else the path it carries; None if the
+    ///   descriptor is unknown or more chunks are due
+    fn xattr_target(&mut self, raw: &RawFileEvent) -> Option<AssembledPath> {
+        if raw.fd >= 0 {
+            self.fd_table.resolve(raw.pid, raw.fd)
+        } else {
+            self.path_assembler.push(raw)
+        }
+    }
+}
+
+/// Event type and arguments of a raw event, typed
+///
+/// Attribute names that are not valid UTF-8 are escaped like paths
+/// instead of failing the event.
+///
+/// # Arguments
+/// * `raw` - Raw event received from the eBPF program
+///
+/// # Returns
+/// * `Result<EventKind, DecodeError>` - Kind, or error for an event of
+///   another layout version or an unknown type
+fn event_kind(raw: &RawFileEvent) -> Result<EventKind, DecodeError> {
+    match DecodedFileEvent::try_from(raw) {
+        Ok(decoded) => Ok(decoded.kind),
+        Err(DecodeError::InvalidUtf8) => {
+            let name = raw.filename.split(|&b| b == 0).next().unwrap_or(&[]);
+            let name = escape_path(name);
+            Ok(match raw.event_type {
+                EVENT_TYPE_SETXATTR => EventKind::SetXattr { name },
+                _ => EventKind::RemoveXattr { name },
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::{TimeZone, Utc};
+    use fw_common::{
+        EVENT_FLAG_MORE_CHUNKS, EVENT_TYPE_CHDIR, EVENT_TYPE_CLOSE,
+        EVENT_TYPE_DUP, EVENT_TYPE_EXIT, EVENT_TYPE_FORK, EVENT_TYPE_LINK,
+        EVENT_TYPE_LINK_SOURCE, EVENT_TYPE_OPEN, EVENT_TYPE_RENAME,
+        EVENT_TYPE_SYMLINK, IO_OFFSET_CURRENT, LOCK_FLAG_NONBLOCKING,
+        LOCK_FLAG_REFUSED, LOCK_KIND_EXCLUSIVE, PATH_CHUNK_LEN,
+        SYNC_KIND_FDATASYNC,
+    };
+    use proptest::prelude::*;
+    use std::time::Duration;
+
+    /// Recorded perf buffer records, replayed in order by
+    /// `test_golden_fixtures`. Process 4242 opens a file relative to its
+    /// working directory, works on it through a duplicated descriptor,
+    /// links and renames it, and closes it; the last open carries a path
+    /// split over two chunks.
+    const FIXTURES: &[(&str, &[u8])] = &[
+        ("chdir", include_bytes!("../testdata/events/chdir.bin")),
+        ("open", include_bytes!("../testdata/events/open.bin")),
+        ("dup", include_bytes!("../testdata/events/dup.bin")),
+        ("chmod", include_bytes!("../testdata/events/chmod.bin")),
+        ("chown", include_bytes!("../testdata/events/chown.bin")),
+        (
+            "truncate",
+            include_bytes!("../testdata/events/truncate.bin"),
+        ),
+        ("write", include_bytes!("../testdata/events/write.bin")),
+        ("read", include_bytes!("../testdata/events/read.bin")),
+        ("sync", include_bytes!("../testdata/events/sync.bin")),
+        ("lock", include_bytes!("../testdata/events/lock.bin")),
+        ("unlock", include_bytes!("../testdata/events/unlock.bin")),
+        (
+            "setxattr",
+            include_bytes!("../testdata/events/setxattr.bin"),
+        ),
+        (
+            "removexattr",
+            include_bytes!("../testdata/events/removexattr.bin"),
+        ),
+        (
+            "link_source",
+            include_bytes!("../testdata/events/link_source.bin"),
+        ),
+        ("link", include_bytes!("../testdata/events/link.bin")),
+        (
+            "symlink_source",
+            include_bytes!("../testdata/events/symlink_source.bin"),
+        ),
+        ("symlink", include_bytes!("../testdata/events/symlink.bin")),
+        (
+            "rename_source",
+            include_bytes!("../testdata/events/rename_source.bin"),
+        ),
+        ("rename", include_bytes!("../testdata/events/rename.bin")),
+        ("fork", include_bytes!("../testdata/events/fork.bin")),
+        ("close", include_bytes!("../testdata/events/close.bin")),
+        (
+            "long_open_1",
+            include_bytes!("../testdata/events/long_open_1.bin"),
+        ),
+        (
+            "long_open_2",
+            include_bytes!("../testdata/events/long_open_2.bin"),
+        ),
+        ("exit", include_bytes!("../testdata/events/exit.bin")),
+    ];
+
+    /// Raw event of process 4242, run by user 1000, with no descriptor,
+    /// path or stacks
+    fn raw_fixture(event_type: u32) -> RawFileEvent {
+        let mut raw: RawFileEvent = zerocopy::FromZeros::new_zeroed();
+        raw.version = fw_common::EVENT_ABI_VERSION;
+        raw.pid = 4242;
+        raw.tgid = 4242;
+        raw.event_type = event_type;
+        raw.fd = -1;
+        raw.old_fd = -1;
+        raw.uid = 1000;
+        raw.gid = 1000;
+        raw.user_stack_id = -1;
+        raw.kernel_stack_id = -1;
+        raw
+    }
+
+    /// Raw events the records in `FIXTURES` are generated from, in the
+    /// same order
+    ///
+    /// `FW_UPDATE_FIXTURES=1 cargo test test_fixtures_are_generated`
+    /// rewrites the records, e.g. after the layout version changes.
+    fn generated_fixtures() -> Vec<(&'static str, RawFileEvent)> {
+        let event = |event_type, fd, old_fd, path: &[u8]| {
+            let mut raw = raw_fixture(event_type);
+            raw.fd = fd;
+            raw.old_fd = old_fd;
+            raw.path[..path.len()].copy_from_slice(path);
+            raw
+        };
+        let long = format!("/srv/data/{}/b.log", "d".repeat(300));
+        let (first, rest) = long.as_bytes().split_at(PATH_CHUNK_LEN);
+
+        let mut open = event(EVENT_TYPE_OPEN, 3, libc::AT_FDCWD, b"a.log");
+        open.arg = (libc::O_RDWR | libc::O_CREAT) as u64;
+        open.arg2 = libc::S_IFREG | 0o644;
+        open.open_latency_ns = 1500;
+        open.dev = 8 << 20 | 1;
+        open.ino = 77;
+        let dup = event(EVENT_TYPE_DUP, 4, 3, b"");
+        let mut chmod = event(EVENT_TYPE_CHMOD, 4, -1, b"");
+        chmod.arg = 0o600;
+        let chown = event(EVENT_TYPE_CHOWN, 3, -1, b"");
+        let mut truncate = event(EVENT_TYPE_TRUNCATE, 3, -1, b"");
+        truncate.arg = 42;
+        let mut write = event(EVENT_TYPE_WRITE, 3, -1, b"");
+        write.arg = IO_OFFSET_CURRENT;
+        write.arg2 = 512;
+        let mut read = event(EVENT_TYPE_READ, 4, -1, b"");
+        read.arg = 4096;
+        read.arg2 = 128;
+        let mut sync = event(EVENT_TYPE_SYNC, 3, -1, b"");
+        sync.arg = SYNC_KIND_FDATASYNC;
+        sync.open_latency_ns = 2_500_000;
+        let mut lock = event(EVENT_TYPE_LOCK, 3, -1, b"");
+        lock.arg = LOCK_KIND_EXCLUSIVE;
+        lock.arg2 = LOCK_FLAG_NONBLOCKING | LOCK_FLAG_REFUSED;
+        let unlock = event(EVENT_TYPE_UNLOCK, 3, -1, b"");
+        let mut setxattr =
+            event(EVENT_TYPE_SETXATTR, -1, -1, b"/srv/data/a.log");
+        setxattr.filename[..8].copy_from_slice(b"user.tag");
+        let mut removexattr = event(EVENT_TYPE_REMOVEXATTR, 3, -1, b"");
+        removexattr.filename[..8].copy_from_slice(b"user.tag");
+        let mut fork = event(EVENT_TYPE_FORK, -1, -1, b"");
+        fork.child_pid = 4243;
+        let mut close = event(EVENT_TYPE_CLOSE, 4, -1, b"");
+        close.pid = 4243;
+        close.tgid = 4243;
+        let mut long_open_1 = event(EVENT_TYPE_OPEN, 5, libc::AT_FDCWD, first);
+        long_open_1.arg2 = libc::S_IFDIR | 0o755;
+        long_open_1.flags = EVENT_FLAG_MORE_CHUNKS;
+        let mut long_open_2 = event(EVENT_TYPE_OPEN, 5, libc::AT_FDCWD, rest);
+        long_open_2.arg2 = libc::S_IFDIR | 0o755;
+        long_open_2.chunk_index = 1;
+
+        vec![
+            ("chdir", event(EVENT_TYPE_CHDIR, -1, -1, b"/srv/data")),
+            ("open", open),
+            ("dup", dup),
+            ("chmod", chmod),
+            ("chown", chown),
+            ("truncate", truncate),
+            ("write", write),
+            ("read", read),
+            ("sync", sync),
+            ("lock", lock),
+            ("unlock", unlock),
+            ("setxattr", setxattr),
+            ("removexattr", removexattr),
+            (
+                "link_source",
+                event(EVENT_TYPE_LINK_SOURCE, -1, libc::AT_FDCWD, b"a.log"),
+            ),
+            (
+                "link",
+                event(EVENT_TYPE_LINK, -1, libc::AT_FDCWD, b"hard.log"),
+            ),
+            // A symlink's contents aren't resolved against anything
+            (
+                "symlink_source",
+                event(EVENT_TYPE_LINK_SOURCE, -1, -1, b"a.log"),
+            ),
+            (
+                "symlink",
+                event(EVENT_TYPE_SYMLINK, -1, libc::AT_FDCWD, b"soft.log"),
+            ),
+            (
+                "rename_source",
+                event(
+                    EVENT_TYPE_LINK_SOURCE,
+                    -1,
+                    libc::AT_FDCWD,
+                    b"/srv/data/a.log",
+                ),
+            ),
+            (
+                "rename",
+                event(EVENT_TYPE_RENAME, -1, libc::AT_FDCWD, b"b.log"),
+            ),
+            ("fork", fork),
+            ("close", close),
+            ("long_open_1", long_open_1),
+            ("long_open_2", long_open_2),
+            ("exit", event(EVENT_TYPE_EXIT, -1, -1, b"")),
+        ]
+    }
+
+    /// Copy bytes into an 8-byte aligned buffer, as the perf buffer
+    /// hands them over
+    fn aligned(bytes: &[u8]) -> Vec<u64> {
+        let mut buffer = vec![0u64; bytes.len().div_ceil(8)];
+        zerocopy::IntoBytes::as_mut_bytes(buffer.as_mut_slice())[..bytes.len()]
+            .copy_from_slice(bytes);
+        buffer
+    }
+
+    /// Decode one fixture record
+    fn decode(decoder: &mut ProbeDecoder, bytes: &[u8]) -> Option<FileEvent> {
+        let buffer = aligned(bytes);
+        let bytes =
+            &zerocopy::IntoBytes::as_bytes(buffer.as_slice())[..bytes.len()];
+        decoder.decode_bytes(bytes).unwrap()
+    }
+
+    /// Event fields the fixtures pin down, in one line
+    fn summary(event: &FileEvent) -> String {
+        let mut line = format!(
+            "{} {} pid={} uid={:?} fd={:?} type={:?}",
+            event.action,
+            event.file_path,
+            event.pid,
+            event.uid,
+            event.fd,
+            event.file_type
+        );
+        let extras = [
+            ("flags", event.open_flags.map(|f| format!("{:o}", f))),
+            ("latency", event.open_latency_ns.map(|ns| ns.to_string())),
+            ("id", event.file_id.map(|id| id.to_string())),
+            ("source", event.link_source.clone()),
+            ("from", event.renamed_from.clone()),
+            ("xattr", event.xattr_name.clone()),
+        ];
+        for (name, value) in extras {
+            if let Some(value) = value {
+                line.push_str(&format!(" {}={}", name, value));
+            }
+        }
+        if event.path_truncated {
+            line.push_str(" truncated");
+        }
+        line
+    }
+
+    #[test]
+    fn test_golden_fixtures() {
+        let long = format!("/srv/data/{}/b.log", "d".repeat(300));
+        let expected = [
+            ("chdir", None),
+            (
+                "open",
+                Some(
+                    "opened /srv/data/a.log pid=4242 uid=Some(1000) fd=Some(3) \
+                     type=Some(File) flags=102 latency=1500 id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            ("dup", None),
+            (
+                "chmod",
+                Some(
+                    "chmod 0600 /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(4) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "chown",
+                Some(
+                    "chown 0:0 /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "truncate",
+                Some(
+                    "truncate 42 /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "write",
+                Some(
+                    "write 512 /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "read",
+                Some(
+                    "read 128 at 4096 /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(4) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "sync",
+                Some(
+                    "fdatasync /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) latency=2500000 id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "lock",
+                Some(
+                    "lock exclusive refused /srv/data/a.log pid=4242 \
+                     uid=Some(1000) fd=Some(3) type=Some(File) latency=0 \
+                     id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "unlock",
+                Some(
+                    "unlock /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            (
+                "setxattr",
+                Some(
+                    "setxattr /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=None type=None xattr=user.tag"
+                        .to_string(),
+                ),
+            ),
+            (
+                "removexattr",
+                Some(
+                    "removexattr /srv/data/a.log pid=4242 uid=Some(1000) \
+                     fd=Some(3) type=Some(File) id=8:1/77 xattr=user.tag"
+                        .to_string(),
+                ),
+            ),
+            ("link_source", None),
+            (
+                "link",
+                Some(
+                    "linked /srv/data/hard.log pid=4242 uid=Some(1000) fd=None \
+                     type=None source=/srv/data/a.log"
+                        .to_string(),
+                ),
+            ),
+            ("symlink_source", None),
+            (
+                "symlink",
+                Some(
+                    "symlinked /srv/data/soft.log pid=4242 uid=Some(1000) \
+                     fd=None type=None source=a.log"
+                        .to_string(),
+                ),
+            ),
+            ("rename_source", None),
+            (
+                "rename",
+                Some(
+                    "renamed /srv/data/b.log pid=4242 uid=Some(1000) fd=None \
+                     type=None from=/srv/data/a.log"
+                        .to_string(),
+                ),
+            ),
+            ("fork", None),
+            (
+                "close",
+                Some(
+                    "closed /srv/data/a.log pid=4243 uid=Some(1000) \
+                     fd=Some(4) type=Some(File) id=8:1/77"
+                        .to_string(),
+                ),
+            ),
+            ("long_open_1", None),
+            (
+                "long_open_2",
+                Some(format!(
+                    "opened {} pid=4242 uid=Some(1000) fd=Some(5) \
+                     type=Some(Dir) flags=0 latency=0",
+                    long
+                )),
+            ),
+            ("exit", None),
+        ];
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = MockClock::new(start).with_step(Duration::from_millis(1));
+        let mut decoder = ProbeDecoder::new().with_clock(Arc::new(clock));
+        let mut reported = 0;
+        assert_eq!(FIXTURES.len(), expected.len());
+        for ((name, bytes), (expected_name, expected)) in
+            FIXTURES.iter().zip(expected)
+        {
+            assert_eq!(*name, expected_name);
+            let event = decode(&mut decoder, bytes);
+            assert_eq!(event.as_ref().map(summary), expected, "{}", name);
+            if let Some(event) = event {
+                let elapsed = event.timestamp - start;
+                assert_eq!(elapsed.num_milliseconds(), reported, "{}", name);
+                reported += 1;
+            }
+        }
+        // The exit dropped every descriptor of process 4242
+        assert!(decoder.fd_table().pids().iter().all(|&pid| pid != 4242));
+        assert_eq!(decoder.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_fixtures_are_generated() {
+        let update = std::env::var_os("FW_UPDATE_FIXTURES").is_some();
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/events");
+        let generated = generated_fixtures();
+        assert_eq!(FIXTURES.len(), generated.len());
+        for ((name, bytes), (generated_name, raw)) in
+            FIXTURES.iter().zip(&generated)
+        {
+            assert_eq!(name, generated_name);
+            let raw = zerocopy::IntoBytes::as_bytes(raw);
+            if update {
+                std::fs::write(dir.join(format!("{}.bin", name)), raw).unwrap();
+            } else {
+                assert!(
+                    *bytes == raw,
+                    "{}.bin is stale; rerun with FW_UPDATE_FIXTURES=1",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let (_, open) = FIXTURES[1];
+        let buffer = aligned(open);
+        let bytes =
+            &zerocopy::IntoBytes::as_bytes(buffer.as_slice())[..open.len()];
+        let mut decoder = ProbeDecoder::new();
+
+        let error = decoder.decode_bytes(&bytes[..bytes.len() - 1]);
+        assert!(matches!(
+            error.unwrap_err().downcast_ref(),
+            Some(DecodeError::TooShort { .. })
+        ));
+        assert!(decoder.decode_bytes(&[]).is_err());
+
+        let mut misaligned = vec![0u64; buffer.len() + 1];
+        let misaligned =
+            &mut zerocopy::IntoBytes::as_mut_bytes(misaligned.as_mut_slice())
+                [1..=open.len()];
+        misaligned.copy_from_slice(open);
+        assert!(matches!(
+            decoder.decode_bytes(misaligned).unwrap_err().downcast_ref(),
+            Some(DecodeError::Misaligned)
+        ));
+
+        let mut versioned = buffer.clone();
+        zerocopy::IntoBytes::as_mut_bytes(versioned.as_mut_slice())[0] ^= 0xff;
+        let versioned =
+            &zerocopy::IntoBytes::as_bytes(versioned.as_slice())[..open.len()];
+        assert!(matches!(
+            decoder.decode_bytes(versioned).unwrap_err().downcast_ref(),
+            Some(DecodeError::UnsupportedVersion { .. })
+        ));
+
+        // Attribute names that aren't UTF-8 are escaped like paths
+        let mut setxattr = raw_fixture(EVENT_TYPE_SETXATTR);
+        setxattr.path[..6].copy_from_slice(b"/a.log");
+        setxattr.filename[..6].copy_from_slice(b"user.\xff");
+        let event = decoder.decode(&setxattr).unwrap();
+        assert_eq!(event.xattr_name.as_deref(), Some("user.\\xff"));
+
+        // A well-formed record of an unknown type is skipped
+        let mut unknown = *RawFileEvent::from_bytes(bytes).unwrap();
+        unknown.event_type = 99;
+        assert!(decoder.decode(&unknown).is_none());
+        assert!(decoder.fd_table().is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_arbitrary_bytes_never_panic(
+            records in prop::collection::vec(
+                prop::collection::vec(any::<u8>(), 0..512),
+                1..8,
+            )
+        ) {
+            let mut decoder = ProbeDecoder::new();
+            for record in records {
+                let buffer = aligned(&record);
+                let bytes = &zerocopy::IntoBytes::as_bytes(buffer.as_slice())
+                    [..record.len()];
+                let _ = decoder.decode_bytes(bytes);
+            }
+        }
+
+        #[test]
+        fn prop_arbitrary_events_never_panic(
+            fields in prop::collection::vec(
+                (0u32..24, -3i32..8, -3i32..8, any::<u32>(), any::<u64>(),
+                 0u32..3, any::<u32>(), any::<[u8; 16]>()),
+                1..32,
+            )
+        ) {
+            // Few pids and descriptors so events refer to each other
+            let mut decoder = ProbeDecoder::new();
+            for (event_type, fd, old_fd, arg2, arg, chunk, flags, path) in
+                fields
+            {
+                let mut raw: RawFileEvent = zerocopy::FromZeros::new_zeroed();
+                raw.version = fw_common::EVENT_ABI_VERSION;
+                raw.pid = 1 << 30 | (arg2 & 1);
+                raw.tgid = raw.pid;
+                raw.event_type = event_type;
+                raw.fd = fd;
+                raw.old_fd = old_fd;
+                raw.child_pid = 1 << 30 | (arg2 & 2);
+                raw.arg = arg;
+                raw.arg2 = arg2;
+                raw.chunk_index = chunk;
+                raw.flags = flags;
+                raw.path[..16].copy_from_slice(&path);
+                raw.filename[..16].copy_from_slice(&path);
+                let _ = decoder.decode(&raw);
+            }
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! Probes module
+//!
+//! Describes which eBPF programs implement each monitoring feature and
+//! where they attach. Changing the active feature set produces a plan of
+//! individual probes to attach and detach, so the monitor can be
+//! reconfigured at runtime without reloading everything or losing its
+//! userspace state.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use fw_common::{TAIL_CALL_LINK_TARGET, TAIL_CALL_OPEN_PATH_CHUNKS};
+
+use crate::arch::Arch;
+
+/// How an eBPF program is attached to the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProbeKind {
+    /// Entry of a kernel function or system call
+    Kprobe,
+    /// Return of a kernel function or system call
+    Kretprobe,
+    /// Static kernel tracepoint in the given category
+    Tracepoint {
+        /// Tracepoint category (e.g. "sched")
+        category: &'static str,
+    },
+}
+
+/// One attachment of an eBPF program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProbeSpec {
+    /// Name of the program in the eBPF object
+    pub program: &'static str,
+    /// How the program attaches
+    pub kind: ProbeKind,
+    /// System call, kernel function or tracepoint name to attach to
+    pub target: &'static str,
+    /// Whether `target` is a system call, entered through the
+    /// architecture's syscall wrapper
+    pub syscall: bool,
+}
+
+impl ProbeSpec {
+    /// Kernel symbol or tracepoint name the program attaches to
+    ///
+    /// # Arguments
+    /// * `arch` - Architecture of the running kernel
+    ///
+    /// # Returns
+    /// * `String` - Syscall wrapper symbol for system calls, otherwise
+    ///   the target unchanged
+    pub fn symbol(&self, arch: Arch) -> String {
+        if self.syscall {
+            arch.syscall_symbol(self.target)
+        } else {
+            self.target.to_string()
+        }
+    }
+}
+
+impl fmt::Display for ProbeSpec {
+    /// Format as "kind:target (program)", e.g. "kretprobe:dup2 (dup_ret)"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ProbeKind::Kprobe => write!(f, "kprobe:{}", self.target)?,
+            ProbeKind::Kretprobe => write!(f, "kretprobe:{}", self.target)?,
+            ProbeKind::Tracepoint { category } => {
+                write!(f, "tracepoint:{}/{}", category, self.target)?
+            }
+        }
+        write!(f, " ({})", self.program)
+    }
+}
+
+/// Shorthand for a kprobe attachment to a system call
+const fn kprobe(program: &'static str, target: &'static str) -> ProbeSpec {
+    ProbeSpec {
+        program,
+        kind: ProbeKind::Kprobe,
+        target,
+        syscall: true,
+    }
+}
+
+/// Shorthand for a kretprobe attachment to a system call
+const fn kretprobe(program: &'static str, target: &'static str) -> ProbeSpec {
+    ProbeSpec {
+        program,
+        kind: ProbeKind::Kretprobe,
+        target,
+        syscall: true,
+    }
+}
+
+/// Shorthand for a kprobe attachment to an internal kernel function
+const fn function_kprobe(program: &'static str) -> ProbeSpec {
+    ProbeSpec {
+        program,
+        kind: ProbeKind::Kprobe,
+        target: program,
+        syscall: false,
+    }
+}
+
+/// Shorthand for a sched tracepoint attachment
+const fn sched_tracepoint(program: &'static str) -> ProbeSpec {
+    ProbeSpec {
+        program,
+        kind: ProbeKind::Tracepoint { category: "sched" },
+        target: program,
+        syscall: false,
+    }
+}
+
+/// Programs reached by tail call instead of being attached
+///
+/// Each is loaded and stored in the `TAIL_CALLS` program array at its slot
+/// before any probe that jumps to it is attached.
+pub const TAIL_CALL_PROGRAMS: &[(u32, &str)] = &[
+    (TAIL_CALL_OPEN_PATH_CHUNKS, "open_path_chunks"),
+    (TAIL_CALL_LINK_TARGET, "link_target"),
+];
+
+/// Probes reporting file opens
+const OPEN_PROBES: &[ProbeSpec] = &[
+    kprobe("openat", "openat"),
+    kretprobe("openat_ret", "openat"),
+    function_kprobe("vfs_open"),
+];
+
+/// Probes keeping the descriptor table in sync
+const DESCRIPTOR_PROBES: &[ProbeSpec] = &[
+    kprobe("close", "close"),
+    kprobe("dup", "dup"),
+    kprobe("dup2", "dup2"),
+    kprobe("dup3", "dup3"),
+    kprobe("fcntl", "fcntl"),
+    kretprobe("dup_ret", "dup"),
+    kretprobe("dup_ret", "dup2"),
+    kretprobe("dup_ret", "dup3"),
+    kretprobe("dup_ret", "fcntl"),
+    kprobe("chdir", "chdir"),
+    kprobe("fchdir", "fchdir"),
+    kretprobe("chdir_ret", "chdir"),
+    kretprobe("chdir_ret", "fchdir"),
+    function_kprobe("kernel_clone"),
+    sched_tracepoint("sched_process_fork"),
+    sched_tracepoint("sched_process_exit"),
+];
+
+/// Probes reporting metadata changes on open descriptors
+const METADATA_PROBES: &[ProbeSpec] = &[
+    kprobe("fchmod", "fchmod"),
+    kprobe("fchown", "fchown"),
+    kprobe("fchownat", "fchownat"),
+    kprobe("ftruncate", "ftruncate"),
+    kretprobe("metadata_ret", "fchmod"),
+    kretprobe("metadata_ret", "fchown"),
+    kretprobe("metadata_ret", "fchownat"),
+    kretprobe("metadata_ret", "ftruncate"),
+];
+
+/// Probes reporting hardlink and symlink creation and renames
+const LINK_PROBES: &[ProbeSpec] = &[
+    kprobe("linkat", "linkat"),
+    kprobe("symlinkat", "symlinkat"),
+    kprobe("renameat2", "renameat2"),
+    kprobe("renameat", "renameat"),
+    kprobe("rename", "rename"),
+    kretprobe("link_ret", "linkat"),
+    kretprobe("link_ret", "symlinkat"),
+    kretprobe("link_ret", "renameat2"),
+    kretprobe("link_ret", "renameat"),
+    kretprobe("link_ret", "rename"),
+];
+
+/// Probes reporting extended attribute changes
+const XATTR_PROBES: &[ProbeSpec] = &[
+    kprobe("setxattr", "setxattr"),
+    kprobe("lsetxattr", "lsetxattr"),
+    kprobe("fsetxattr", "fsetxattr"),
+    kprobe("removexattr", "removexattr"),
+    kprobe("lremovexattr", "lremovexattr"),
+    kprobe("fremovexattr", "fremovexattr"),
+];
+
+/// Probes reporting fsync, fdatasync and sync_file_range with latency
+const SYNC_PROBES: &[ProbeSpec] = &[
+    kprobe("fsync", "fsync"),
+    kprobe("fdatasync", "fdatasync"),
+    kprobe("sync_file_range", "sync_file_range"),
+    kretprobe("sync_ret", "fsync"),
+    kretprobe("sync_ret", "fdatasync"),
+    kretprobe("sync_ret", "sync_file_range"),
+];
+
+/// Probes reporting flock and fcntl record locks with latency
+const LOCK_PROBES: &[ProbeSpec] = &[
+    kprobe("flock", "flock"),
+    kprobe("fcntl_lock", "fcntl"),
+    kretprobe("lock_ret", "flock"),
+    kretprobe("lock_ret", "fcntl"),
+];
+
+/// Probes reporting reads and writes with their offset and size
+const IO_PROBES: &[ProbeSpec] = &[
+    kprobe("read", "read"),
+    kprobe("write", "write"),
+    kprobe("pread64", "pread64"),
+    kprobe("pwrite64", "pwrite64"),
+    kretprobe("io_ret", "read"),
+    kretprobe("io_ret", "write"),
+    kretprobe("io_ret", "pread64"),
+    kretprobe("io_ret", "pwrite64"),
+];
+
+/// A group of probes that can be switched on and off together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProbeFeature {
+    /// File opens with path, latency and inode
+    Opens,
+    /// Close, dup, fork and exit tracking for the descriptor table
+    Descriptors,
+    /// fchmod, fchown and ftruncate on open descriptors
+    Metadata,
+    /// Hardlink and symlink creation, and renames
+    Links,
+    /// Extended attribute changes
+    Xattrs,
+    /// Flushes of open files to storage
+    Syncs,
+    /// Locks taken and released on open files
+    Locks,
+    /// Reads and writes on open descriptors, for access patterns
+    Io,
+}
+
+/// Set of enabled probe features
+pub type FeatureSet = BTreeSet<ProbeFeature>;
+
+impl ProbeFeature {
+    /// Every feature, in attach order
+    pub const ALL: [ProbeFeature; 8] = [
+        ProbeFeature::Opens,
+        ProbeFeature::Descriptors,
+        ProbeFeature::Metadata,
+        ProbeFeature::Links,
+        ProbeFeature::Xattrs,
+        ProbeFeature::Syncs,
+        ProbeFeature::Locks,
+        ProbeFeature::Io,
+    ];
+
+    /// Features that keep the descriptor table current, left attached
+    /// while monitoring is paused so descriptors opened meanwhile still
+    /// resolve after it resumes
+    pub const BOOKKEEPING: [ProbeFeature; 2] =
+        [ProbeFeature::Opens, ProbeFeature::Descriptors];
+
+    /// Probes that implement this feature
+    ///
+    /// # Returns
+    /// * `&'static [ProbeSpec]` - Attachments owned by the feature
+    pub fn probes(self) -> &'static [ProbeSpec] {
+        match self {
+            ProbeFeature::Opens => OPEN_PROBES,
+            ProbeFeature::Descriptors => DESCRIPTOR_PROBES,
+            ProbeFeature::Metadata => METADATA_PROBES,
+            ProbeFeature::Links => LINK_PROBES,
+            ProbeFeature::Xattrs => XATTR_PROBES,
+            ProbeFeature::Syncs => SYNC_PROBES,
+            ProbeFeature::Locks => LOCK_PROBES,
+            ProbeFeature::Io => IO_PROBES,
+        }
+    }
+
+    /// Feature this one relies on to produce meaningful events
+    ///
+    /// # Returns
+    /// * `Option<ProbeFeature>` - Prerequisite feature, if any
+    fn requires(self) -> Option<ProbeFeature> {
+        match self {
+            // Closes and dups are only reported for paths seen opening
+            ProbeFeature::Descriptors => Some(ProbeFeature::Opens),
+            // Descriptor-based events are resolved through the fd table
+            ProbeFeature::Metadata
+            | ProbeFeature::Xattrs
+            | ProbeFeature::Syncs
+            | ProbeFeature::Locks
+            | ProbeFeature::Io => Some(ProbeFeature::Descriptors),
+            ProbeFeature::Opens | ProbeFeature::Links => None,
+        }
+    }
+}
+
+/// Every feature enabled
+///
+/// # Returns
+/// * `FeatureSet` - Set containing all features
+pub fn all_features() -> FeatureSet {
+    ProbeFeature::ALL.into_iter().collect()
+}
+
+/// Features enabled unless asked otherwise
+///
+/// Reads and writes are only traced on request, since they fire far
+/// more often than every other probe together.
+///
+/// # Returns
+/// * `FeatureSet` - Every feature except I/O
+pub fn default_features() -> FeatureSet {
+    ProbeFeature::ALL
+        .into_iter()
+        .filter(|&feature| feature != ProbeFeature::Io)
+        .collect()
+}
+
+/// Add the prerequisites of every feature in a set
+///
+/// # Arguments
+/// * `features` - Requested features
+///
+/// # Returns
+/// * `FeatureSet` - Requested features plus everything they rely on
+pub fn with_prerequisites(features: &FeatureSet) -> FeatureSet {
+    let mut resolved = FeatureSet::new();
+    for &feature in features {
+        let mut next = Some(feature);
+        while let Some(feature) = next {
+            resolved.insert(feature);
+            next = feature.requires();
+        }
+    }
+    resolved
+}
+
+/// Probes to attach and detach to move between two feature sets
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbePlan {
+    /// Probes of newly enabled features
+    pub attach: Vec<ProbeSpec>,
+    /// Probes of features that are no longer needed
+    pub detach: Vec<ProbeSpec>,
+}
+
+impl ProbePlan {
+    /// Work out the probe changes between two feature sets
+    ///
+    /// Both sets are expanded with their prerequisites first, so a
+    /// feature that is still needed by another stays attached.
+    ///
+    /// # Arguments
+    /// * `current` - Features that are attached now
+    /// * `desired` - Features that should be attached
+    ///
+    /// # Returns
+    /// * `ProbePlan` - Probes to attach and detach
+    pub fn between(current: &FeatureSet, desired: &FeatureSet) -> Self {
+        let current = with_prerequisites(current);
+        let desired = with_prerequisites(desired);
+        let probes_of = |features: &FeatureSet, excluded: &FeatureSet| {
+            features
+                .difference(excluded)
+                .flat_map(|f| f.probes().iter().copied())
+                .collect::<Vec<_>>()
+        };
+        let mut detach = probes_of(&current, &desired);
+        // Detach in reverse so return probes and dependent features go
+        // before the probes they rely on
+        detach.reverse();
+        Self {
+            attach: probes_of(&desired, &current),
+            detach,
+        }
+    }
+
+    /// Check whether the plan changes nothing
+    ///
+    /// # Returns
+    /// * `bool` - True if there is nothing to attach or detach
+    pub fn is_empty(&self) -> bool {
+        self.attach.is_empty() && self.detach.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(features: &[ProbeFeature]) -> FeatureSet {
+        features.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_prerequisites_are_added() {
+        let resolved = with_prerequisites(&set(&[ProbeFeature::Xattrs]));
+        assert_eq!(
+            resolved,
+            set(&[
+                ProbeFeature::Opens,
+                ProbeFeature::Descriptors,
+                ProbeFeature::Xattrs
+            ])
+        );
+    }
+
+    #[test]
+    fn test_plan_enables_only_new_probes() {
+        let plan = ProbePlan::between(
+            &set(&[ProbeFeature::Opens]),
+            &set(&[ProbeFeature::Opens, ProbeFeature::Links]),
+        );
+        assert_eq!(plan.attach, LINK_PROBES);
+        assert!(plan.detach.is_empty());
+    }
+
+    #[test]
+    fn test_plan_keeps_shared_prerequisites() {
+        // Dropping metadata keeps descriptors, which xattrs still need
+        let plan = ProbePlan::between(
+            &set(&[ProbeFeature::Metadata, ProbeFeature::Xattrs]),
+            &set(&[ProbeFeature::Xattrs]),
+        );
+        assert!(plan.attach.is_empty());
+        let mut expected = METADATA_PROBES.to_vec();
+        expected.reverse();
+        assert_eq!(plan.detach, expected);
+        assert!(ProbePlan::between(&all_features(), &all_features()).is_empty());
+    }
+
+    #[test]
+    fn test_tail_call_slots_are_unique() {
+        let slots: BTreeSet<u32> =
+            TAIL_CALL_PROGRAMS.iter().map(|(slot, _)| *slot).collect();
+        assert_eq!(slots.len(), TAIL_CALL_PROGRAMS.len());
+        assert!(slots.iter().all(|slot| *slot < fw_common::MAX_TAIL_CALLS));
+    }
+
+    #[test]
+    fn test_probe_symbols_follow_arch() {
+        let openat = kretprobe("openat_ret", "openat");
+        assert_eq!(openat.symbol(Arch::X86_64), "__x64_sys_openat");
+        assert_eq!(openat.symbol(Arch::Aarch64), "__arm64_sys_openat");
+        assert_eq!(openat.symbol(Arch::Riscv64), "__riscv_sys_openat");
+        // Kernel functions and tracepoints are the same everywhere
+        assert_eq!(
+            function_kprobe("vfs_open").symbol(Arch::Aarch64),
+            "vfs_open"
+        );
+        assert_eq!(
+            sched_tracepoint("sched_process_fork").symbol(Arch::Riscv64),
+            "sched_process_fork"
+        );
+    }
+
+    #[test]
+    fn test_probe_spec_display() {
+        assert_eq!(
+            kretprobe("dup_ret", "dup2").to_string(),
+            "kretprobe:dup2 (dup_ret)"
+        );
+        assert_eq!(
+            sched_tracepoint("sched_process_exit").to_string(),
+            "tracepoint:sched/sched_process_exit (sched_process_exit)"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Process Cache module
+//!
+//! Remembers the names of processes seen in events so `/proc` is read
+//! once per process instead of once per event. Entries are keyed by pid
+//! and the fork time the probes stamp on each event, so a reused pid
+//! misses even if the old process's exit was lost. They are also dropped
+//! when the kernel reports the process exited or its pid was handed to a
+//! new child, and the least recently used entry is evicted once the cache
+//! is full.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entries kept unless `--process-cache-size` says otherwise
+pub const DEFAULT_PROCESS_CACHE_SIZE: usize = 4096;
+
+/// Name reported for processes that exited before they were looked up
+const UNKNOWN_PROCESS: &str = "unknown";
+
+/// Counters describing how well the cache works
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to read `/proc`
+    pub misses: u64,
+    /// Entries dropped to stay within the size limit
+    pub evictions: u64,
+}
+
+/// One cached process
+#[derive(Debug)]
+struct CachedProcess {
+    /// Boot time in nanoseconds the process was forked at, or 0 for
+    /// processes forked before the probes were loaded
+    start_ns: u64,
+    /// Command name from `/proc/<pid>/stat`
+    name: String,
+    /// Lookup counter value when the entry was last used
+    used: u64,
+}
+
+/// Least recently used cache of process names
+#[derive(Debug)]
+pub struct ProcessCache {
+    /// Maximum number of entries
+    capacity: usize,
+    /// Mount point of procfs, normally `/proc`
+    proc_root: PathBuf,
+    /// Cached processes keyed by pid; an entry only answers lookups
+    /// with its own fork time
+    entries: HashMap<u32, CachedProcess>,
+    /// Pids ordered by when they were last used, oldest first
+    recency: BTreeMap<u64, u32>,
+    /// Incremented on every lookup to order entries by use
+    clock: u64,
+    /// Hit, miss and eviction counts
+    stats: CacheStats,
+    /// Clock ticks per second, the unit of start times in `/proc`
+    ticks_per_sec: u64,
+}
+
+impl ProcessCache {
+    /// Create an empty cache reading from `/proc`
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of entries (at least 1)
+    ///
+    /// # Returns
+    /// * `ProcessCache` - Cache with no entries
+    pub fn new(capacity: usize) -> Self {
+        Self::with_proc_root(capacity, Path::new("/proc"))
+    }
+
+    /// Create an empty cache reading from another procfs mount
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of entries (at least 1)
+    /// * `proc_root` - Mount point of procfs
+    ///
+    /// # Returns
+    /// * `ProcessCache` - Cache with no entries
+    pub fn with_proc_root(capacity: usize, proc_root: &Path) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            proc_root: proc_root.to_path_buf(),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+            ticks_per_sec: clock_ticks_per_sec(),
+        }
+    }
+
+    /// Look up the name of a process, reading `/proc` on a miss
+    ///
+    /// A process forked while the probes were loaded is only read from
+    /// `/proc` if the process holding its pid there started at the same
+    /// time, so a pid reused again since the event isn't misnamed.
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID to look up
+    /// * `start_ns` - Fork time from the event, or 0 if unknown
+    ///
+    /// # Returns
+    /// * `String` - Process name, or "unknown" if it can't be read
+    pub fn name(&mut self, pid: u32, start_ns: u64) -> String {
+        self.clock += 1;
+        if let Some(entry) = self
+            .entries
+            .get_mut(&pid)
+            .filter(|entry| entry.start_ns == start_ns)
+        {
+            self.stats.hits += 1;
+            self.recency.remove(&entry.used);
+            self.recency.insert(self.clock, pid);
+            entry.used = self.clock;
+            return entry.name.clone();
+        }
+        self.stats.misses += 1;
+        // Not cached: the process may have exited and its pid be reused
+        let Some((name, start_ticks)) = read_stat(&self.proc_root, pid) else {
+            return UNKNOWN_PROCESS.to_string();
+        };
+        if start_ns != 0 && !self.same_start(start_ticks, start_ns) {
+            return UNKNOWN_PROCESS.to_string();
+        }
+        // Whatever had the pid before is gone
+        self.exited(pid);
+        if self.entries.len() >= self.capacity {
+            self.evict_oldest();
+        }
+        self.recency.insert(self.clock, pid);
+        self.entries.insert(
+            pid,
+            CachedProcess {
+                start_ns,
+                name: name.clone(),
+                used: self.clock,
+            },
+        );
+        name
+    }
+
+    /// Forget a process that exited
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID of the exited process
+    pub fn exited(&mut self, pid: u32) {
+        if let Some(entry) = self.entries.remove(&pid) {
+            self.recency.remove(&entry.used);
+        }
+    }
+
+    /// Forget whatever used a pid before it was given to a new child
+    ///
+    /// Covers reuse of a pid whose exit event was lost.
+    ///
+    /// # Arguments
+    /// * `child_pid` - Process ID of the new child
+    pub fn forked(&mut self, child_pid: u32) {
+        self.exited(child_pid);
+    }
+
+    /// Fork time a process is cached under
+    ///
+    /// # Arguments
+    /// * `pid` - Process ID to look up
+    ///
+    /// # Returns
+    /// * `Option<u64>` - Fork time in nanoseconds since boot (0 if
+    ///   unknown), or None if not cached
+    pub fn start_ns(&self, pid: u32) -> Option<u64> {
+        self.entries.get(&pid).map(|entry| entry.start_ns)
+    }
+
+    /// Drop every entry, keeping the counters
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Number of cached processes
+    ///
+    /// # Returns
+    /// * `usize` - Entry count
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether no processes are cached
+    ///
+    /// # Returns
+    /// * `bool` - True if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Hit, miss and eviction counts so far
+    ///
+    /// # Returns
+    /// * `CacheStats` - Counters since the cache was created
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Check whether a start time read from `/proc` is a fork time
+    ///
+    /// The two clocks agree to within a tick; `/proc` rounds down.
+    ///
+    /// # Arguments
+    /// * `start_ticks` - Start time in clock ticks since boot
+    /// * `start_ns` - Fork time in nanoseconds since boot
+    ///
+    /// # Returns
+    /// * `bool` - True if both describe the same moment
+    fn same_start(&self, start_ticks: u64, start_ns: u64) -> bool {
+        let ns_per_tick = (1_000_000_000 / self.ticks_per_sec).max(1);
+        start_ticks.abs_diff(start_ns / ns_per_tick) <= 1
+    }
+
+    /// Drop the least recently used entry
+    fn evict_oldest(&mut self) {
+        if let Some((_, pid)) = self.recency.pop_first() {
+            self.entries.remove(&pid);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+/// Clock ticks per second the kernel reports times in
+///
+/// # Returns
+/// * `u64` - `sysconf(_SC_CLK_TCK)`, or 100 if it can't be read
+fn clock_ticks_per_sec() -> u64 {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    u64::try_from(ticks).ok().filter(|&t| t > 0).unwrap_or(100)
+}
+
+/// Read a process's name and start time from `<proc_root>/<pid>/stat`
+///
+/// # Arguments
+/// * `proc_root` - Mount point of procfs
+/// * `pid` - Process ID to read
+///
+/// # Returns
+/// * `Option<(String, u64)>` - Name and start time, or None if the process
+///   is gone or the file can't be parsed
+fn read_stat(proc_root: &Path, pid: u32) -> Option<(String, u64)> {
+    let stat = fs::read_to_string(proc_root.join(pid.to_string()).join("stat"))
+        .ok()?;
+    parse_stat(&stat)
+}
+
+/// Parse the name and start time out of a `/proc/<pid>/stat` line
+///
+/// The name is in parentheses and may itself contain spaces and ")", so
+/// fields are counted from the last ")".
+///
+/// # Arguments
+/// * `stat` - Contents of the stat file
+///
+/// # Returns
+/// * `Option<(String, u64)>` - Name and start time, or None if malformed
+fn parse_stat(stat: &str) -> Option<(String, u64)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    // Field 22 (starttime), counting from field 3 (state) after the name
+    let start_time = stat[close + 1..].split_whitespace().nth(19)?;
+    Some((name, start_time.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_stat(root: &Path, pid: u32, name: &str, start_time: u64) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let fields: Vec<String> = (3..22).map(|n| n.to_string()).collect();
+        fs::write(
+            dir.join("stat"),
+            format!(
+                "{} ({}) {} {} 0 0\n",
+                pid,
+                name,
+                fields.join(" "),
+                start_time
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "42 (tmux: server) S 1 42 42 0 -1 4194560 1 2 3 4 5 6 7 \
+                    8 20 0 1 0 123456 1 2 3";
+        assert_eq!(
+            parse_stat(stat),
+            Some(("tmux: server".to_string(), 123456))
+        );
+        assert_eq!(
+            parse_stat(
+                "7 (a) b) S 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 99"
+            ),
+            Some(("a) b".to_string(), 99))
+        );
+        assert_eq!(parse_stat("7 (short) S 1"), None);
+    }
+
+    #[test]
+    fn test_lru_eviction_and_reuse() {
+        let root = tempfile::tempdir().unwrap();
+        write_stat(root.path(), 1, "init", 10);
+        write_stat(root.path(), 2, "sshd", 20);
+        write_stat(root.path(), 3, "vim", 30);
+        let mut cache = ProcessCache::with_proc_root(2, root.path());
+
+        assert_eq!(cache.name(1, 0), "init");
+        assert_eq!(cache.name(2, 0), "sshd");
+        assert_eq!(cache.name(1, 0), "init");
+        // Full: pid 2 is the least recently used
+        assert_eq!(cache.name(3, 0), "vim");
+        assert_eq!(cache.start_ns(2), None);
+        assert_eq!(cache.start_ns(1), Some(0));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                evictions: 1
+            }
+        );
+
+        // Pid 3 is reused by a new process; the fork drops the old name
+        write_stat(root.path(), 3, "bash", 40);
+        assert_eq!(cache.name(3, 0), "vim");
+        cache.forked(3);
+        assert_eq!(cache.name(3, 0), "bash");
+
+        cache.exited(1);
+        assert_eq!(cache.len(), 1);
+        // Processes that are already gone are not cached
+        assert_eq!(cache.name(9, 0), "unknown");
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_reuse_is_told_by_start_time() {
+        let root = tempfile::tempdir().unwrap();
+        let mut cache = ProcessCache::with_proc_root(4, root.path());
+        let ns_per_tick = 1_000_000_000 / cache.ticks_per_sec;
+        write_stat(root.path(), 5, "vim", 0);
+        assert_eq!(cache.name(5, 0), "vim");
+
+        // Pid 5 is reused and the fork and exit events were lost; the
+        // new process's events carry its fork time
+        write_stat(root.path(), 5, "bash", 40);
+        let forked = 40 * ns_per_tick + 3;
+        assert_eq!(cache.name(5, forked), "bash");
+        assert_eq!(cache.start_ns(5), Some(forked));
+        assert_eq!(cache.name(5, forked), "bash");
+        assert_eq!(cache.len(), 1);
+
+        // A late event from a process whose pid was reused again since
+        write_stat(root.path(), 5, "sh", 90);
+        assert_eq!(cache.name(5, 60 * ns_per_tick), "unknown");
+        assert_eq!(cache.name(5, forked), "bash");
+    }
+}
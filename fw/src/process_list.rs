@@ -0,0 +1,416 @@
+//! Process list module
+//!
+//! Loads the `fw collect --process-list-file` list of processes to ignore
+//! (or to report exclusively) and keeps it current while fw runs. Lines
+//! are exact process names, which the probes check in the kernel, or
+//! regexes, which fw matches against the whole name. The file is reloaded
+//! when it changes on disk or on SIGUSR1, so a long list of noisy
+//! processes can be edited without restarting the capture; a list that
+//! fails to load is logged and the previous one kept. Names are those
+//! of whole processes: threads that renamed themselves are judged by
+//! their thread group leader's name, in the kernel as in fw.
+
+use anyhow::{anyhow, Context, Result};
+use fw_common::{
+    COMM_FILTER_ALLOW, COMM_FILTER_DENY, COMM_FILTER_OFF,
+    MAX_COMM_FILTER_ENTRIES, MAX_COMM_LEN,
+};
+use log::{info, warn};
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::watch;
+
+/// Characters that make a line a regex rather than an exact name
+const REGEX_METACHARACTERS: &str = r"\.+*?()|[]{}^$";
+
+/// What the processes on the list are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProcessListMode {
+    /// Noisy processes whose activity is dropped
+    #[default]
+    Deny,
+    /// The only processes whose activity is reported
+    Allow,
+}
+
+/// Where the list comes from and how it is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessListConfig {
+    /// File with one process name or regex per line
+    pub path: PathBuf,
+    /// Whether listed processes are dropped or the only ones reported
+    pub mode: ProcessListMode,
+}
+
+/// Parsed process list
+#[derive(Debug, Clone, Default)]
+pub struct ProcessList {
+    /// Whether listed processes are dropped or the only ones reported
+    mode: ProcessListMode,
+    /// Exact process names, pushed into the kernel
+    names: BTreeSet<String>,
+    /// Regexes matched against the whole name in fw
+    patterns: Vec<Regex>,
+}
+
+impl ProcessList {
+    /// Parse list lines, skipping blanks and '#' comments
+    ///
+    /// A line without regex metacharacters is an exact name; any other
+    /// line is a regex that must match the whole process name.
+    ///
+    /// # Arguments
+    /// * `text` - Process names or regexes, one per line
+    /// * `mode` - Whether listed processes are dropped or kept
+    ///
+    /// # Returns
+    /// * `Result<ProcessList>` - List, or error naming the bad line
+    pub fn parse(text: &str, mode: ProcessListMode) -> Result<Self> {
+        let mut list = Self {
+            mode,
+            ..Default::default()
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.contains(|c| REGEX_METACHARACTERS.contains(c)) {
+                let pattern = Regex::new(&format!("^(?:{})$", line))
+                    .with_context(|| {
+                        format!("line {}: invalid regex", number + 1)
+                    })?;
+                list.patterns.push(pattern);
+            } else if line.len() >= MAX_COMM_LEN {
+                return Err(anyhow!(
+                    "line {}: '{}' is longer than the {} characters of a \
+                     process name",
+                    number + 1,
+                    line,
+                    MAX_COMM_LEN - 1
+                ));
+            } else {
+                list.names.insert(line.to_string());
+            }
+        }
+        if list.names.len() > MAX_COMM_FILTER_ENTRIES as usize {
+            return Err(anyhow!(
+                "At most {} exact process names can be listed",
+                MAX_COMM_FILTER_ENTRIES
+            ));
+        }
+        Ok(list)
+    }
+
+    /// Build a list from exact names and regexes, without parsing
+    ///
+    /// # Arguments
+    /// * `mode` - Whether listed processes are dropped or kept
+    /// * `names` - Exact process names
+    /// * `patterns` - Regexes, each matched against the whole name
+    ///
+    /// # Returns
+    /// * `Result<ProcessList>` - List, or error for a name longer than a
+    ///   process name can be, or too many names
+    pub fn from_parts(
+        mode: ProcessListMode,
+        names: impl IntoIterator<Item = String>,
+        patterns: impl IntoIterator<Item = Regex>,
+    ) -> Result<Self> {
+        let names: BTreeSet<String> = names.into_iter().collect();
+        if let Some(long) = names.iter().find(|n| n.len() >= MAX_COMM_LEN) {
+            return Err(anyhow!(
+                "'{}' is longer than the {} characters of a process name",
+                long,
+                MAX_COMM_LEN - 1
+            ));
+        }
+        if names.len() > MAX_COMM_FILTER_ENTRIES as usize {
+            return Err(anyhow!(
+                "At most {} exact process names can be listed",
+                MAX_COMM_FILTER_ENTRIES
+            ));
+        }
+        let patterns = patterns
+            .into_iter()
+            .map(|p| Regex::new(&format!("^(?:{})$", p.as_str())))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            mode,
+            names,
+            patterns,
+        })
+    }
+
+    /// Load a list file
+    ///
+    /// # Arguments
+    /// * `config` - File and mode
+    ///
+    /// # Returns
+    /// * `Result<ProcessList>` - List, or error naming the file and line
+    pub fn load(config: &ProcessListConfig) -> Result<Self> {
+        let text = fs::read_to_string(&config.path).with_context(|| {
+            format!("Failed to read {}", config.path.display())
+        })?;
+        Self::parse(&text, config.mode).with_context(|| {
+            format!("Invalid process list {}", config.path.display())
+        })
+    }
+
+    /// Check whether activity of a process is reported
+    ///
+    /// # Arguments
+    /// * `name` - Process name
+    ///
+    /// # Returns
+    /// * `bool` - True if the process passes the list
+    pub fn allows(&self, name: &str) -> bool {
+        let listed = self.names.contains(name)
+            || self.patterns.iter().any(|p| p.is_match(name));
+        listed == (self.mode == ProcessListMode::Allow)
+    }
+
+    /// Number of exact names and of regexes
+    ///
+    /// # Returns
+    /// * `(usize, usize)` - Exact names and regexes on the list
+    pub fn counts(&self) -> (usize, usize) {
+        (self.names.len(), self.patterns.len())
+    }
+
+    /// Mode to write into the kernel process filter
+    ///
+    /// An allowlist with regexes can't be applied in the kernel, which
+    /// would drop processes only a regex lets through, so it is left to
+    /// fw entirely.
+    ///
+    /// # Returns
+    /// * `u32` - `COMM_FILTER_*` mode
+    pub fn kernel_mode(&self) -> u32 {
+        match self.mode {
+            ProcessListMode::Deny if !self.names.is_empty() => COMM_FILTER_DENY,
+            ProcessListMode::Allow if self.patterns.is_empty() => {
+                COMM_FILTER_ALLOW
+            }
+            _ => COMM_FILTER_OFF,
+        }
+    }
+
+    /// Keys to write into the kernel process filter map
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = [u8; MAX_COMM_LEN]>` - Null-padded names
+    pub fn kernel_names(
+        &self,
+    ) -> impl Iterator<Item = [u8; MAX_COMM_LEN]> + '_ {
+        self.names.iter().map(|name| {
+            let mut comm = [0; MAX_COMM_LEN];
+            comm[..name.len()].copy_from_slice(name.as_bytes());
+            comm
+        })
+    }
+}
+
+/// Inotify instance as a raw descriptor tokio can poll
+pub(crate) struct InotifyFd(pub(crate) Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Notices when the list should be reloaded
+pub struct ListWatcher {
+    /// File and mode to reload with
+    config: ProcessListConfig,
+    /// Watch on the directory holding the file
+    inotify: AsyncFd<InotifyFd>,
+    /// SIGUSR1 deliveries
+    usr1: Signal,
+}
+
+impl ListWatcher {
+    /// Watch a list file for changes and listen for SIGUSR1
+    ///
+    /// The directory is watched rather than the file, since editors save
+    /// by renaming a new file over the old one.
+    ///
+    /// # Arguments
+    /// * `config` - File and mode
+    ///
+    /// # Returns
+    /// * `Result<ListWatcher>` - Watcher, or error if the directory can't
+    ///   be watched or the signal handler can't be installed
+    pub fn new(config: ProcessListConfig) -> Result<Self> {
+        Ok(Self {
+            inotify: watch_parent(&config.path)?,
+            usr1: signal(SignalKind::user_defined1())?,
+            config,
+        })
+    }
+}
+
+/// Watch the directory holding a file for the file being written or
+/// replaced
+///
+/// # Arguments
+/// * `path` - File to be notified about, see [`file_changed`]
+///
+/// # Returns
+/// * `Result<AsyncFd<InotifyFd>>` - Watch, or error if the directory
+///   can't be watched
+pub(crate) fn watch_parent(path: &Path) -> Result<AsyncFd<InotifyFd>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let inotify =
+        Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    inotify
+        .add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_CREATE,
+        )
+        .with_context(|| format!("Failed to watch {}", dir.display()))?;
+    Ok(AsyncFd::new(InotifyFd(inotify))?)
+}
+
+/// Wait until a watched file was written or replaced
+pub(crate) async fn file_changed(
+    inotify: &AsyncFd<InotifyFd>,
+    path: &Path,
+) -> Result<()> {
+    let name = path.file_name();
+    loop {
+        let mut ready = inotify.readable().await?;
+        match ready.get_inner().0.read_events() {
+            Ok(events) => {
+                if events.iter().any(|e| e.name.as_deref() == name) {
+                    return Ok(());
+                }
+            }
+            Err(Errno::EAGAIN) => ready.clear_ready(),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Reload the list whenever its file changes or SIGUSR1 arrives
+///
+/// Runs until the monitor drops its receiver. A list that fails to load
+/// is logged and the previous one kept.
+///
+/// # Arguments
+/// * `watcher` - Change notifications for the list
+/// * `tx` - Channel the monitor reads the current list from
+pub async fn supervise(
+    watcher: ListWatcher,
+    tx: watch::Sender<Arc<ProcessList>>,
+) {
+    let ListWatcher {
+        config,
+        inotify,
+        mut usr1,
+    } = watcher;
+    loop {
+        tokio::select! {
+            result = file_changed(&inotify, &config.path) => {
+                if let Err(e) = result {
+                    warn!("Stopped watching the process list: {}", e);
+                    return;
+                }
+            }
+            _ = usr1.recv() => {}
+            _ = tx.closed() => return,
+        }
+        match ProcessList::load(&config) {
+            Ok(list) => {
+                let (names, patterns) = list.counts();
+                info!(
+                    "Reloaded process list: {} names, {} patterns",
+                    names, patterns
+                );
+                tx.send_replace(Arc::new(list));
+            }
+            Err(e) => warn!("Keeping the previous process list: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_names_and_patterns() {
+        let text = "# noisy\nchrome\n\nkworker/.*\nsystemd-journal\n";
+        let deny = ProcessList::parse(text, ProcessListMode::Deny).unwrap();
+        assert_eq!(deny.counts(), (2, 1));
+        assert!(!deny.allows("chrome"));
+        assert!(!deny.allows("kworker/0:1"));
+        assert!(deny.allows("chrome_crashpad"));
+        assert!(deny.allows("vim"));
+        assert_eq!(deny.kernel_mode(), COMM_FILTER_DENY);
+        let mut names: Vec<_> = deny.kernel_names().collect();
+        names.sort();
+        assert_eq!(&names[0][..7], b"chrome\0");
+
+        // A regex in an allowlist keeps the kernel from filtering at all
+        let allow = ProcessList::parse(text, ProcessListMode::Allow).unwrap();
+        assert!(allow.allows("kworker/3:2"));
+        assert!(!allow.allows("vim"));
+        assert_eq!(allow.kernel_mode(), COMM_FILTER_OFF);
+
+        assert!(ProcessList::parse(
+            "a-very-long-process",
+            ProcessListMode::Deny
+        )
+        .is_err());
+        assert!(ProcessList::parse("(", ProcessListMode::Deny).is_err());
+        assert!(ProcessList::default().allows("anything"));
+    }
+
+    #[tokio::test]
+    async fn test_reloads_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ProcessListConfig {
+            path: dir.path().join("noisy.txt"),
+            mode: ProcessListMode::Deny,
+        };
+        fs::write(&config.path, "chrome\n").unwrap();
+        let (tx, mut rx) =
+            watch::channel(Arc::new(ProcessList::load(&config).unwrap()));
+        let watcher = ListWatcher::new(config.clone()).unwrap();
+        let supervisor = tokio::spawn(supervise(watcher, tx));
+
+        // Editors save by renaming a new file over the old one
+        let new = dir.path().join("noisy.txt.new");
+        fs::write(&new, "chrome\nslack\n").unwrap();
+        fs::rename(&new, &config.path).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!rx.borrow_and_update().allows("slack"));
+
+        // A broken edit keeps the previous list
+        fs::write(&config.path, "(\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!rx.has_changed().unwrap());
+        assert!(!rx.borrow().allows("slack"));
+        supervisor.abort();
+    }
+}
@@ -0,0 +1,690 @@
+//! Proto format module
+//!
+//! Encodes events as protocol buffers following `fw/proto/event.proto`,
+//! for `fw collect --format proto`, and decodes them back into JSON for
+//! `fw decode --from proto`. Alerts and stats aggregates are encoded with
+//! the `Alert` and `Stats` messages of the same schema for programs
+//! using the library; fw has no gRPC service to share them over.
+//!
+//! The wire format is written by hand, like the JSON formats, rather
+//! than generated with prost: prost-build needs `protoc` on every build
+//! machine, including the static cross builds, and would convert events
+//! into generated structs only to serialize them again, while the
+//! encoding of these few flat messages is a page of code. The field
+//! tables below mirror the .proto file, and the tests check that the two
+//! agree.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::audit_format::action_name;
+use crate::file_event::{FileAction, FileEvent, FileType};
+use crate::report::base64;
+use crate::severity::Severity;
+use crate::stats::StatsAggregate;
+use crate::throttle::REPEATS_TAG;
+
+/// Wire type of varint fields
+const VARINT: u8 = 0;
+
+/// Wire type of 8-byte fields
+const FIXED64: u8 = 1;
+
+/// Wire type of length-delimited fields
+const LEN: u8 = 2;
+
+/// Wire type of 4-byte fields
+const FIXED32: u8 = 5;
+
+/// How a field's value is encoded
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Bytes,
+    Uint,
+    Int,
+    Bool,
+    /// Enum value, named by its position
+    Enum(&'static [&'static str]),
+    Message(&'static [Field]),
+    /// `repeated string`
+    Strings,
+    /// `repeated` message
+    Messages(&'static [Field]),
+    /// `map<string, string>`
+    StringMap,
+}
+
+/// Field of a message
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    number: u64,
+    name: &'static str,
+    kind: Kind,
+}
+
+/// Shorthand for the field tables
+const fn field(number: u64, name: &'static str, kind: Kind) -> Field {
+    Field { number, name, kind }
+}
+
+/// Values of the `Severity` enum
+const SEVERITIES: [&str; 5] = [
+    "SEVERITY_UNSPECIFIED",
+    "INFO",
+    "NOTICE",
+    "WARNING",
+    "CRITICAL",
+];
+
+/// Values of the `FileType` enum
+const FILE_TYPES: [&str; 7] = [
+    "FILE_TYPE_UNSPECIFIED",
+    "FILE",
+    "DIR",
+    "DEVICE",
+    "SOCKET",
+    "FIFO",
+    "SYMLINK",
+];
+
+/// Fields of the `FileId` message
+const FILE_ID: [Field; 2] =
+    [field(1, "dev", Kind::Uint), field(2, "ino", Kind::Uint)];
+
+/// Fields of the `Action` message
+const ACTION: [Field; 10] = [
+    field(1, "name", Kind::String),
+    field(2, "mode", Kind::Uint),
+    field(3, "uid", Kind::Uint),
+    field(4, "gid", Kind::Uint),
+    field(5, "length", Kind::Uint),
+    field(6, "offset", Kind::Uint),
+    field(7, "bytes", Kind::Uint),
+    field(8, "lock_kind", Kind::String),
+    field(9, "blocked", Kind::Bool),
+    field(10, "acquired", Kind::Bool),
+];
+
+/// Fields of the `FileEvent` message
+const FILE_EVENT: [Field; 28] = [
+    field(1, "path", Kind::String),
+    field(2, "raw_path", Kind::Bytes),
+    field(3, "program", Kind::String),
+    field(4, "pid", Kind::Uint),
+    field(5, "timestamp_ns", Kind::Int),
+    field(6, "action", Kind::Message(&ACTION)),
+    field(7, "uid", Kind::Uint),
+    field(8, "gid", Kind::Uint),
+    field(9, "user", Kind::String),
+    field(10, "group", Kind::String),
+    field(11, "path_truncated", Kind::Bool),
+    field(12, "mount_point", Kind::String),
+    field(13, "fs_type", Kind::String),
+    field(14, "remote_source", Kind::String),
+    field(15, "overlay_layer", Kind::String),
+    field(16, "latency_ns", Kind::Uint),
+    field(17, "file_id", Kind::Message(&FILE_ID)),
+    field(18, "fd", Kind::Int),
+    field(19, "link_source", Kind::String),
+    field(20, "renamed_from", Kind::String),
+    field(21, "open_flags", Kind::Uint),
+    field(22, "xattr_name", Kind::String),
+    field(23, "tags", Kind::StringMap),
+    field(24, "severity", Kind::Enum(&SEVERITIES)),
+    field(25, "rule", Kind::String),
+    field(26, "file_type", Kind::Enum(&FILE_TYPES)),
+    field(27, "file_size", Kind::Uint),
+    field(28, "file_modified_ns", Kind::Int),
+];
+
+/// Fields of the `Alert` message
+const ALERT: [Field; 2] = [
+    field(1, "event", Kind::Message(&FILE_EVENT)),
+    field(2, "repeats", Kind::Uint),
+];
+
+/// Fields of the `StatsRow` message
+const STATS_ROW: [Field; 2] = [
+    field(1, "key", Kind::Strings),
+    field(2, "events", Kind::Uint),
+];
+
+/// Fields of the `Stats` message
+const STATS: [Field; 2] = [
+    field(1, "group_by", Kind::Strings),
+    field(2, "rows", Kind::Messages(&STATS_ROW)),
+];
+
+/// Message being encoded
+#[derive(Debug, Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn tag(&mut self, number: u64, wire: u8) {
+        self.varint(number << 3 | u64::from(wire));
+    }
+
+    fn uint(&mut self, number: u64, value: u64) {
+        self.tag(number, VARINT);
+        self.varint(value);
+    }
+
+    /// Signed values, sign-extended to 64 bits as `int32`/`int64` are
+    fn int(&mut self, number: u64, value: i64) {
+        self.uint(number, value as u64);
+    }
+
+    fn bytes(&mut self, number: u64, value: &[u8]) {
+        self.tag(number, LEN);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn string(&mut self, number: u64, value: &str) {
+        self.bytes(number, value.as_bytes());
+    }
+
+    fn message(&mut self, number: u64, message: Writer) {
+        self.bytes(number, &message.0);
+    }
+
+    fn opt_uint(&mut self, number: u64, value: Option<impl Into<u64>>) {
+        if let Some(value) = value {
+            self.uint(number, value.into());
+        }
+    }
+
+    fn opt_string(&mut self, number: u64, value: Option<&str>) {
+        if let Some(value) = value {
+            self.string(number, value);
+        }
+    }
+}
+
+/// Encode what was done to the file as an `Action` message
+fn encode_action(action: &FileAction) -> Writer {
+    let mut w = Writer::default();
+    w.string(1, &action_name(action));
+    match *action {
+        FileAction::ModeChanged { mode } => w.uint(2, mode.into()),
+        FileAction::OwnerChanged { uid, gid } => {
+            w.uint(3, uid.into());
+            w.uint(4, gid.into());
+        }
+        FileAction::Truncated { length } => w.uint(5, length),
+        FileAction::Read { offset, bytes }
+        | FileAction::Written { offset, bytes } => {
+            w.opt_uint(6, offset);
+            w.uint(7, bytes);
+        }
+        FileAction::Locked {
+            kind,
+            blocked,
+            acquired,
+        } => {
+            w.string(8, &kind.to_string());
+            w.uint(9, blocked.into());
+            w.uint(10, acquired.into());
+        }
+        _ => {}
+    }
+    w
+}
+
+/// Nanoseconds since the Unix epoch of a time
+///
+/// # Returns
+/// * `Result<i64>` - Nanoseconds, or error for times before 1677 or after
+///   2262, which don't fit
+fn nanos(time: DateTime<Utc>) -> Result<i64> {
+    time.timestamp_nanos_opt()
+        .ok_or_else(|| anyhow!("{} is outside the range of an int64 ns", time))
+}
+
+/// Encode an event as a `FileEvent` message
+///
+/// # Arguments
+/// * `event` - Event to encode
+///
+/// # Returns
+/// * `Result<Vec<u8>>` - Message, without a length prefix, or error if a
+///   time doesn't fit in nanoseconds
+pub fn encode(event: &FileEvent) -> Result<Vec<u8>> {
+    encode_event(event).map(|w| w.0)
+}
+
+/// Encode an alert as an `Alert` message
+///
+/// # Arguments
+/// * `event` - Event given to an alert sink, with the number of alerts
+///   it summarizes in its "repeats" tag if any were held back
+///
+/// # Returns
+/// * `Result<Vec<u8>>` - Message, without a length prefix, or error if a
+///   time doesn't fit in nanoseconds
+pub fn encode_alert(event: &FileEvent) -> Result<Vec<u8>> {
+    let mut w = Writer::default();
+    w.message(1, encode_event(event)?);
+    let repeats = event.tags.get(REPEATS_TAG).and_then(|r| r.parse().ok());
+    w.opt_uint(2, repeats.filter(|&repeats: &u64| repeats > 0));
+    Ok(w.0)
+}
+
+/// Encode a stats aggregate as a `Stats` message
+///
+/// # Arguments
+/// * `aggregate` - Counts per group
+///
+/// # Returns
+/// * `Vec<u8>` - Message, without a length prefix
+pub fn encode_stats(aggregate: &StatsAggregate) -> Vec<u8> {
+    let mut w = Writer::default();
+    for dimension in aggregate.group_by() {
+        w.string(1, &dimension.to_string());
+    }
+    for (key, events) in aggregate.rows() {
+        let mut row = Writer::default();
+        for value in key {
+            row.string(1, value);
+        }
+        row.uint(2, events);
+        w.message(2, row);
+    }
+    w.0
+}
+
+/// Encode an event as a `FileEvent` message
+fn encode_event(event: &FileEvent) -> Result<Writer> {
+    let mut w = Writer::default();
+    w.string(1, &event.file_path);
+    if let Some(raw) = &event.raw_path {
+        w.bytes(2, raw);
+    }
+    w.string(3, &event.program_name);
+    w.uint(4, event.pid.into());
+    w.int(5, nanos(event.timestamp)?);
+    w.message(6, encode_action(&event.action));
+    w.opt_uint(7, event.uid);
+    w.opt_uint(8, event.gid);
+    w.opt_string(9, event.user.as_deref());
+    w.opt_string(10, event.group.as_deref());
+    w.uint(11, event.path_truncated.into());
+    w.opt_string(12, event.mount_point.as_deref());
+    w.opt_string(13, event.fs_type.as_deref());
+    w.opt_string(14, event.remote_source.as_deref());
+    let layer = event.overlay_layer.map(|layer| layer.to_string());
+    w.opt_string(15, layer.as_deref());
+    w.opt_uint(16, event.open_latency_ns);
+    if let Some(file_id) = &event.file_id {
+        let mut id = Writer::default();
+        id.uint(1, file_id.dev);
+        id.uint(2, file_id.ino);
+        w.message(17, id);
+    }
+    if let Some(fd) = event.fd {
+        w.int(18, fd.into());
+    }
+    w.opt_string(19, event.link_source.as_deref());
+    w.opt_string(20, event.renamed_from.as_deref());
+    w.opt_uint(21, event.open_flags);
+    w.opt_string(22, event.xattr_name.as_deref());
+    for (key, value) in &event.tags {
+        let mut entry = Writer::default();
+        entry.string(1, key);
+        entry.string(2, value);
+        w.message(23, entry);
+    }
+    let severity = match event.severity {
+        None => 0,
+        Some(Severity::Info) => 1,
+        Some(Severity::Notice) => 2,
+        Some(Severity::Warning) => 3,
+        Some(Severity::Critical) => 4,
+    };
+    w.uint(24, severity);
+    w.opt_string(25, event.rule.as_deref());
+    let file_type = match event.file_type {
+        None => 0,
+        Some(FileType::File) => 1,
+        Some(FileType::Dir) => 2,
+        Some(FileType::Device) => 3,
+        Some(FileType::Socket) => 4,
+        Some(FileType::Fifo) => 5,
+        Some(FileType::Symlink) => 6,
+    };
+    w.uint(26, file_type);
+    w.opt_uint(27, event.file_size);
+    if let Some(modified) = event.file_modified {
+        w.int(28, nanos(modified)?);
+    }
+    Ok(w)
+}
+
+/// Message being decoded
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .0
+                .split_first()
+                .ok_or_else(|| anyhow!("message ends inside a varint"))?;
+            self.0 = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("varint longer than 10 bytes"))
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8]> {
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.0.len())
+            .ok_or_else(|| anyhow!("field runs past the end of the message"))?;
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    /// Skip the value of a field the schema doesn't know
+    fn skip(&mut self, wire: u8) -> Result<()> {
+        match wire {
+            VARINT => self.varint().map(|_| ()),
+            FIXED64 => self.take(8).map(|_| ()),
+            LEN => {
+                let len = self.varint()?;
+                self.take(len).map(|_| ())
+            }
+            FIXED32 => self.take(4).map(|_| ()),
+            _ => Err(anyhow!("unknown wire type {}", wire)),
+        }
+    }
+}
+
+/// Decode a message into a JSON object named after the schema's fields
+///
+/// # Arguments
+/// * `message` - Encoded message
+/// * `fields` - Schema of the message
+///
+/// # Returns
+/// * `Result<Map<String, Value>>` - Fields present in the message;
+///   unknown fields are skipped
+fn decode_message(
+    message: &[u8],
+    fields: &[Field],
+) -> Result<Map<String, Value>> {
+    let mut reader = Reader(message);
+    let mut object = Map::new();
+    while !reader.0.is_empty() {
+        let tag = reader.varint()?;
+        let (number, wire) = (tag >> 3, (tag & 7) as u8);
+        let Some(field) = fields.iter().find(|f| f.number == number) else {
+            reader.skip(wire)?;
+            continue;
+        };
+        let value = match (field.kind, wire) {
+            (Kind::Uint, VARINT) => Value::from(reader.varint()?),
+            (Kind::Int, VARINT) => Value::from(reader.varint()? as i64),
+            (Kind::Bool, VARINT) => Value::from(reader.varint()? != 0),
+            (Kind::Enum(names), VARINT) => {
+                let value = reader.varint()?;
+                usize::try_from(value)
+                    .ok()
+                    .and_then(|i| names.get(i))
+                    .map_or(Value::from(value), |name| Value::from(*name))
+            }
+            (kind, LEN) => {
+                let len = reader.varint()?;
+                let bytes = reader.take(len)?;
+                match kind {
+                    Kind::String => {
+                        Value::from(std::str::from_utf8(bytes).map_err(
+                            |_| anyhow!("field {} is not UTF-8", field.name),
+                        )?)
+                    }
+                    Kind::Bytes => Value::from(base64(bytes)),
+                    Kind::Message(fields) => {
+                        Value::Object(decode_message(bytes, fields)?)
+                    }
+                    Kind::Strings => {
+                        let value =
+                            std::str::from_utf8(bytes).map_err(|_| {
+                                anyhow!("field {} is not UTF-8", field.name)
+                            })?;
+                        push(&mut object, field.name, Value::from(value));
+                        continue;
+                    }
+                    Kind::Messages(fields) => {
+                        let value = decode_message(bytes, fields)?;
+                        push(&mut object, field.name, Value::Object(value));
+                        continue;
+                    }
+                    Kind::StringMap => {
+                        let entry = decode_message(bytes, &MAP_ENTRY)?;
+                        let key = entry.get("key").and_then(Value::as_str);
+                        let map = object
+                            .entry(field.name)
+                            .or_insert_with(|| Value::Object(Map::new()));
+                        if let (Some(key), Value::Object(map)) = (key, map) {
+                            let value = entry.get("value").cloned();
+                            map.insert(
+                                key.to_string(),
+                                value.unwrap_or(Value::from("")),
+                            );
+                        }
+                        continue;
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "field {} has the wrong wire type",
+                            field.name
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "field {} has the wrong wire type",
+                    field.name
+                ))
+            }
+        };
+        object.insert(field.name.to_string(), value);
+    }
+    Ok(object)
+}
+
+/// Append an element of a repeated field
+fn push(object: &mut Map<String, Value>, name: &str, value: Value) {
+    let list = object
+        .entry(name)
+        .or_insert_with(|| Value::Array(Vec::new()));
+    if let Value::Array(list) = list {
+        list.push(value);
+    }
+}
+
+/// Fields of the entries of a `map<string, string>`
+const MAP_ENTRY: [Field; 2] = [
+    field(1, "key", Kind::String),
+    field(2, "value", Kind::String),
+];
+
+/// Convert a `FileEvent` message to JSON
+///
+/// # Arguments
+/// * `message` - Encoded message
+///
+/// # Returns
+/// * `Result<Value>` - Object keyed by the .proto field names, or error
+///   if the message is malformed
+pub fn to_json(message: &[u8]) -> Result<Value> {
+    decode_message(message, &FILE_EVENT)
+        .map(Value::Object)
+        .map_err(|e| anyhow!("Invalid protobuf frame: {}", e))
+}
+
+/// Convert an `Alert` message to JSON
+///
+/// # Arguments
+/// * `message` - Encoded message
+///
+/// # Returns
+/// * `Result<Value>` - Object keyed by the .proto field names, or error
+///   if the message is malformed
+pub fn alert_to_json(message: &[u8]) -> Result<Value> {
+    decode_message(message, &ALERT)
+        .map(Value::Object)
+        .map_err(|e| anyhow!("Invalid Alert message: {}", e))
+}
+
+/// Convert a `Stats` message to JSON
+///
+/// # Arguments
+/// * `message` - Encoded message
+///
+/// # Returns
+/// * `Result<Value>` - Object keyed by the .proto field names, or error
+///   if the message is malformed
+pub fn stats_to_json(message: &[u8]) -> Result<Value> {
+    decode_message(message, &STATS)
+        .map(Value::Object)
+        .map_err(|e| anyhow!("Invalid Stats message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::{FileId, LockKind};
+    use crate::stats::Dimension;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_encode_and_decode() {
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "vipw".to_string(),
+            FileAction::Locked {
+                kind: LockKind::Exclusive,
+                blocked: true,
+                acquired: true,
+            },
+            42,
+            SystemClock.now(),
+        );
+        event.timestamp = Utc.timestamp_opt(1_700_000_000, 5).unwrap();
+        event.fd = Some(-1);
+        event.file_id = Some(FileId {
+            dev: 8 << 20 | 1,
+            ino: 99,
+        });
+        event.tags.insert("team".to_string(), "infra".to_string());
+        event.severity = Some(Severity::Critical);
+        event.file_type = Some(FileType::File);
+        event.raw_path = Some(vec![0xff]);
+
+        let json = to_json(&encode(&event).unwrap()).unwrap();
+        assert_eq!(json["path"], "/etc/shadow");
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["timestamp_ns"], 1_700_000_000_000_000_005i64);
+        assert_eq!(json["action"]["name"], "lock");
+        assert_eq!(json["action"]["lock_kind"], "exclusive");
+        assert_eq!(json["action"]["blocked"], true);
+        assert_eq!(json["fd"], -1);
+        assert_eq!(json["file_id"]["ino"], 99);
+        assert_eq!(json["tags"]["team"], "infra");
+        assert_eq!(json["severity"], "CRITICAL");
+        assert_eq!(json["file_type"], "FILE");
+        assert_eq!(json["raw_path"], "/w==");
+        assert!(json.get("user").is_none());
+
+        // Fields from a later schema are skipped
+        let mut message = encode(&event).unwrap();
+        message.extend_from_slice(&[0xe8, 0x07, 0x01]);
+        assert_eq!(to_json(&message).unwrap(), json);
+        assert!(to_json(&message[..message.len() - 1]).is_err());
+
+        // Times past 2262 don't fit, and aren't written as the epoch
+        event.timestamp = Utc.timestamp_opt(10_000_000_000, 0).unwrap();
+        assert!(encode(&event).is_err());
+    }
+
+    #[test]
+    fn test_alert_and_stats() {
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "cat".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+        event.tags.insert(REPEATS_TAG.to_string(), "41".to_string());
+        let alert = alert_to_json(&encode_alert(&event).unwrap()).unwrap();
+        assert_eq!(alert["event"]["path"], "/etc/shadow");
+        assert_eq!(alert["repeats"], 41);
+
+        let mut aggregate = StatsAggregate::new(vec![
+            Dimension::Process,
+            Dimension::Tag("team".to_string()),
+        ]);
+        aggregate.add(vec!["cat".to_string(), "infra".to_string()], 2);
+        aggregate.add(vec!["vim".to_string(), "(none)".to_string()], 5);
+        let stats = stats_to_json(&encode_stats(&aggregate)).unwrap();
+        assert_eq!(
+            stats["group_by"],
+            serde_json::json!(["process", "tag=team"])
+        );
+        assert_eq!(
+            stats["rows"][0]["key"],
+            serde_json::json!(["vim", "(none)"])
+        );
+        assert_eq!(stats["rows"][0]["events"], 5);
+        assert_eq!(stats["rows"][1]["events"], 2);
+    }
+
+    #[test]
+    fn test_tables_match_proto_file() {
+        let proto = include_str!("../proto/event.proto");
+        let messages: [(&str, &[Field]); 6] = [
+            ("FileEvent", &FILE_EVENT),
+            ("Alert", &ALERT),
+            ("Stats", &STATS),
+            ("StatsRow", &STATS_ROW),
+            ("Action", &ACTION),
+            ("FileId", &FILE_ID),
+        ];
+        for (message, fields) in messages {
+            let start = proto.find(&format!("message {} {{", message)).unwrap();
+            let end = start + proto[start..].find("\n}").unwrap();
+            let body = &proto[start..end];
+            let declared = body.matches(" = ").count();
+            assert_eq!(declared, fields.len(), "{}", message);
+            for field in fields {
+                let line = format!(" {} = {};", field.name, field.number);
+                assert!(body.contains(&line), "{}.{}", message, field.name);
+            }
+        }
+        for (name, number) in SEVERITIES.iter().zip(0..) {
+            assert!(proto.contains(&format!("  {} = {};", name, number)));
+        }
+        for (name, number) in FILE_TYPES.iter().zip(0..) {
+            assert!(proto.contains(&format!("  {} = {};", name, number)));
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Ps module
+//!
+//! Implements `fw ps`, a targeted lsof: it lists the processes that have
+//! files open, optionally only files matching a path glob. The snapshot
+//! comes from `/proc/*/fd`. With `--watch`, open and close events keep the
+//! table current and it is redrawn as it changes.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::signal;
+
+use crate::collector;
+use crate::ebpf_monitor::EbpfMonitor;
+use crate::fd_table::FdTable;
+use crate::file_event::{FileAction, FileEvent};
+use crate::glob::PathGlob;
+use crate::path_assembler::AssembledPath;
+
+/// Minimum time between redraws in `--watch` mode
+const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Clears the terminal and moves the cursor home
+pub(crate) const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// One open file held by a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+    /// Process holding the file
+    pub pid: u32,
+    /// Name of the process
+    pub program: String,
+    /// Descriptor the file is open on
+    pub fd: i32,
+    /// Path of the file
+    pub path: String,
+}
+
+/// List the open files in a table that match a glob
+///
+/// # Arguments
+/// * `table` - Open descriptors to list
+/// * `glob` - Only list paths matching this glob, if given
+/// * `program_name` - Looks up the name of a process
+///
+/// # Returns
+/// * `Vec<Holder>` - Matching open files, sorted by path, then pid, then fd
+pub fn holders(
+    table: &FdTable,
+    glob: Option<&PathGlob>,
+    mut program_name: impl FnMut(u32) -> String,
+) -> Vec<Holder> {
+    let mut names = HashMap::new();
+    let mut rows: Vec<Holder> = table
+        .iter()
+        .filter(|(_, _, path)| glob.is_none_or(|g| g.matches(&path.path)))
+        .map(|(pid, fd, path)| Holder {
+            pid,
+            program: names
+                .entry(pid)
+                .or_insert_with(|| program_name(pid))
+                .clone(),
+            fd,
+            path: path.path.clone(),
+        })
+        .collect();
+    rows.sort_by(|a, b| (&a.path, a.pid, a.fd).cmp(&(&b.path, b.pid, b.fd)));
+    rows
+}
+
+/// Format open files as an aligned table with a header
+///
+/// # Arguments
+/// * `rows` - Open files to show
+///
+/// # Returns
+/// * `String` - One line per open file, newline-terminated
+pub fn format_holders(rows: &[Holder]) -> String {
+    let width = rows
+        .iter()
+        .map(|r| r.program.len())
+        .max()
+        .unwrap_or(0)
+        .max("COMMAND".len());
+    let mut out =
+        format!("{:>7}  {:<width$}  {:>4}  PATH\n", "PID", "COMMAND", "FD");
+    for row in rows {
+        let _ = writeln!(
+            out,
+            "{:>7}  {:<width$}  {:>4}  {}",
+            row.pid, row.program, row.fd, row.path
+        );
+    }
+    out
+}
+
+/// Update the table from an open or close event
+///
+/// # Arguments
+/// * `table` - Open descriptors to keep current
+/// * `event` - Event from the monitor
+///
+/// # Returns
+/// * `bool` - True if the table changed
+pub fn apply_event(table: &mut FdTable, event: &FileEvent) -> bool {
+    let Some(fd) = event.fd else {
+        return false;
+    };
+    match event.action {
+        FileAction::Opened => {
+            table.open(
+                event.pid,
+                fd,
+                AssembledPath {
+                    path: event.file_path.clone(),
+                    raw: event.raw_path.clone(),
+                    truncated: event.path_truncated,
+                },
+                event.file_type,
+            );
+            true
+        }
+        FileAction::Closed => table.close(event.pid, fd).is_some(),
+        _ => false,
+    }
+}
+
+/// Name of a running process from `/proc/<pid>/comm`
+fn proc_program_name(pid: u32) -> String {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Forget processes that exited since they were last seen
+///
+/// Exits are not reported as events, so the table is checked against
+/// `/proc` before each redraw.
+fn prune_exited(table: &mut FdTable) {
+    for pid in table.pids() {
+        if !Path::new(&format!("/proc/{}", pid)).exists() {
+            table.exit(pid);
+        }
+    }
+}
+
+/// Run `fw ps`
+///
+/// # Arguments
+/// * `glob` - Only show files whose path matches this glob, if given
+/// * `watch` - Keep the table updated from file events until interrupted
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if monitoring failed in watch mode
+pub fn run_ps(glob: Option<PathGlob>, watch: bool) -> Result<()> {
+    let mut table = FdTable::scan(Path::new("/proc"));
+    let draw = |table: &FdTable| {
+        let rows = holders(table, glob.as_ref(), proc_program_name);
+        format_holders(&rows)
+    };
+
+    if !watch {
+        print!("{}", draw(&table));
+        return Ok(());
+    }
+
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+    rt.block_on(async {
+        let mut monitor =
+            EbpfMonitor::new().context("Failed to initialize eBPF monitor")?;
+        print!("{}{}", CLEAR_SCREEN, draw(&table));
+
+        let mut last_draw = Instant::now();
+        let mut dirty = false;
+        let shutdown = async {
+            let _ = signal::ctrl_c().await;
+        };
+        collector::pump_events(&mut monitor, shutdown, |event| {
+            dirty |= apply_event(&mut table, &event);
+            if dirty && last_draw.elapsed() >= REDRAW_INTERVAL {
+                prune_exited(&mut table);
+                print!("{}{}", CLEAR_SCREEN, draw(&table));
+                last_draw = Instant::now();
+                dirty = false;
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+        .await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    fn path(text: &str) -> AssembledPath {
+        AssembledPath {
+            path: text.to_string(),
+            raw: None,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_holders_filtered_and_sorted() {
+        let mut table = FdTable::new();
+        table.open(20, 4, path("/var/log/app.log"), None);
+        table.open(10, 3, path("/var/log/app.log"), None);
+        table.open(10, 5, path("/etc/hosts"), None);
+
+        let glob = PathGlob::new("/var/log/*.log");
+        let rows = holders(&table, Some(&glob), |pid| format!("p{}", pid));
+        assert_eq!(
+            format_holders(&rows),
+            "    PID  COMMAND    FD  PATH\n     \
+             10  p10         3  /var/log/app.log\n     \
+             20  p20         4  /var/log/app.log\n"
+        );
+        assert_eq!(holders(&table, None, |_| String::new()).len(), 3);
+    }
+
+    #[test]
+    fn test_apply_event() {
+        let mut table = FdTable::new();
+        let opened = FileEvent::new(
+            "/tmp/a".to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+        assert!(!apply_event(&mut table, &opened));
+        assert!(apply_event(&mut table, &opened.clone().with_fd(3)));
+        assert_eq!(table.resolve(7, 3), Some(path("/tmp/a")));
+
+        let mut closed = opened.with_fd(3);
+        closed.action = FileAction::Closed;
+        assert!(apply_event(&mut table, &closed));
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,1207 @@
+//! Record module
+//!
+//! Implements `fw record` and `fw replay`. A recording holds the same
+//! event lines as `fw collect` output, interleaved with checkpoint lines
+//! carrying the number of events written so far, the time, and a drop
+//! counter per source. Replay uses the checkpoints to validate a
+//! recording and report where events are missing, and `fw record
+//! --resume` continues the count when appending to an existing one.
+//! With a signing key, every checkpoint is also followed by a signature
+//! over the hash chain of the recording so far, which `fw verify`
+//! checks. The checkpoint written when recording stops is an end
+//! checkpoint, so a recording cut back to an earlier signed checkpoint
+//! still shows a gap. Lines that aren't valid checkpoints are reported
+//! and checking goes on. Checkpoint lines start with '#', so `fw report`
+//! skips them.
+//! Replay can be limited to a time window and paced at a multiple of the
+//! speed the events were recorded at, going by their timestamps, which
+//! recordings keep to the nanosecond the event was stamped with. With
+//! `--follow` it keeps printing events as they are appended, like `tail
+//! -F`, so one command covers both the history and what happens next.
+//! fw keeps no event store to query, so following a recording is what
+//! stands in for a `fw query --follow`.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use log::info;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::compression::{capture_lines, file_compression, OutputFile};
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::report::CapturedEvent;
+use crate::signing::{
+    Chain, SignatureCheck, SigningKey, VerifyingKey, SIGNATURE_PREFIX,
+};
+
+/// Default time between checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Start of a periodic checkpoint line
+const CHECKPOINT_PREFIX: &str = "# checkpoint ";
+
+/// Start of the checkpoint written when a recording is resumed
+const RESUME_PREFIX: &str = "# resume ";
+
+/// Start of the checkpoint written when recording stops
+const END_PREFIX: &str = "# end ";
+
+/// How often `fw replay --follow` looks for new lines
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// State of a recording at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Number of events recorded before this checkpoint
+    pub seq: u64,
+    /// When the checkpoint was written
+    pub time: DateTime<Utc>,
+    /// Events lost so far, per source (e.g. "lagged")
+    pub dropped: BTreeMap<String, u64>,
+}
+
+impl Checkpoint {
+    /// Parse the fields of a checkpoint line after its prefix
+    ///
+    /// # Arguments
+    /// * `fields` - Space-separated key=value pairs
+    ///
+    /// # Returns
+    /// * `Option<Checkpoint>` - Checkpoint, or None if malformed
+    fn parse(fields: &str) -> Option<Self> {
+        let mut seq = None;
+        let mut time = None;
+        let mut dropped = BTreeMap::new();
+        for field in fields.split_whitespace() {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "seq" => seq = Some(value.parse().ok()?),
+                "time" => {
+                    time =
+                        Some(DateTime::parse_from_rfc3339(value).ok()?.to_utc())
+                }
+                source => {
+                    dropped.insert(source.to_string(), value.parse().ok()?);
+                }
+            }
+        }
+        Some(Self {
+            seq: seq?,
+            time: time?,
+            dropped,
+        })
+    }
+
+    /// Total events lost across all sources
+    fn total_dropped(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    /// Format the fields as "seq=12 time=... lagged=3"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seq={} time={}",
+            self.seq,
+            self.time.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        for (source, count) in &self.dropped {
+            write!(f, " {}={}", source, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecordLine {
+    /// Periodic or final checkpoint
+    Checkpoint(Checkpoint),
+    /// Checkpoint written when appending to an existing recording
+    Resume(Checkpoint),
+    /// Checkpoint written when recording stops
+    End(Checkpoint),
+    /// Checkpoint line whose fields can't be read
+    Malformed,
+    /// Anything else that isn't a comment
+    Event,
+}
+
+impl RecordLine {
+    /// Classify a line, or None for comments and blank lines
+    ///
+    /// # Returns
+    /// * `Option<RecordLine>` - Kind of line
+    fn parse(line: &str) -> Option<Self> {
+        let checkpoint = |fields: &str, kind: fn(Checkpoint) -> Self| {
+            Some(Checkpoint::parse(fields).map_or(RecordLine::Malformed, kind))
+        };
+        if let Some(fields) = line.strip_prefix(CHECKPOINT_PREFIX) {
+            checkpoint(fields, RecordLine::Checkpoint)
+        } else if let Some(fields) = line.strip_prefix(RESUME_PREFIX) {
+            checkpoint(fields, RecordLine::Resume)
+        } else if let Some(fields) = line.strip_prefix(END_PREFIX) {
+            checkpoint(fields, RecordLine::End)
+        } else if line.starts_with('#') || line.trim().is_empty() {
+            None
+        } else {
+            Some(RecordLine::Event)
+        }
+    }
+}
+
+/// Checkpoint settings for `fw record`
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    /// Minimum time between checkpoints
+    pub interval: Duration,
+    /// Last state of the recording being appended to, if resuming
+    pub resume: Option<Checkpoint>,
+    /// Hash chain of the recording being appended to, if resuming
+    pub chain: Chain,
+    /// Key to sign each checkpoint with; the recording is unsigned if
+    /// unset
+    pub signer: Option<SigningKey>,
+}
+
+/// Sink that writes event lines with periodic checkpoints
+pub struct RecordSink<W> {
+    /// Destination for the recording
+    writer: W,
+    /// Events recorded so far, including those of a resumed recording
+    seq: u64,
+    /// Events lost so far, per source
+    dropped: BTreeMap<String, u64>,
+    /// Minimum time between checkpoints
+    interval: Duration,
+    /// When the last checkpoint was written
+    last_checkpoint: Instant,
+    /// Whether the resume checkpoint still has to be written
+    resuming: bool,
+    /// Hash chain of every line written so far
+    chain: Chain,
+    /// Key to sign each checkpoint with, if any
+    signer: Option<SigningKey>,
+    /// Time source for checkpoint times
+    clock: Arc<dyn Clock>,
+}
+
+impl<W: Write + Send + 'static> RecordSink<W> {
+    /// Create a record sink
+    ///
+    /// # Arguments
+    /// * `config` - Checkpoint settings
+    /// * `writer` - Destination for the recording
+    ///
+    /// # Returns
+    /// * `RecordSink<W>` - New record sink
+    pub fn new(config: RecordConfig, writer: W) -> Self {
+        let resuming = config.resume.is_some();
+        let (seq, dropped) = config
+            .resume
+            .map(|c| (c.seq, c.dropped))
+            .unwrap_or_default();
+        Self {
+            writer,
+            seq,
+            dropped,
+            interval: config.interval,
+            last_checkpoint: Instant::now(),
+            resuming,
+            chain: config.chain,
+            signer: config.signer,
+            clock: clock::system(),
+        }
+    }
+
+    /// Take checkpoint times from a different clock
+    ///
+    /// # Arguments
+    /// * `clock` - Time source, e.g. a `MockClock` in tests
+    ///
+    /// # Returns
+    /// * `RecordSink<W>` - The sink using the clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Write a line and fold it into the hash chain
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        self.chain.push(line);
+        writeln!(self.writer, "{}", line)
+    }
+
+    /// Write a checkpoint line for the current state, signed if there is
+    /// a key
+    fn checkpoint(&mut self, prefix: &str) -> Result<()> {
+        let checkpoint = Checkpoint {
+            seq: self.seq,
+            time: self.clock.now(),
+            dropped: self.dropped.clone(),
+        };
+        self.write_line(&format!("{}{}", prefix, checkpoint))
+            .context("Failed to write checkpoint")?;
+        if let Some(signer) = &self.signer {
+            writeln!(self.writer, "{}", signer.signature_line(&self.chain))
+                .context("Failed to write signature")?;
+        }
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for RecordSink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        if std::mem::take(&mut self.resuming) {
+            self.checkpoint(RESUME_PREFIX)?;
+        }
+        self.write_line(&format!("{:#}", event))
+            .context("Failed to write event")?;
+        self.seq += 1;
+        if self.last_checkpoint.elapsed() >= self.interval {
+            self.checkpoint(CHECKPOINT_PREFIX)?;
+        }
+        self.writer.flush().context("Failed to flush recording")
+    }
+
+    fn dropped(&mut self, source: &str, count: u64) {
+        *self.dropped.entry(source.to_string()).or_default() += count;
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        // Passes on a flush compressed output held back
+        self.writer.flush().context("Failed to flush recording")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if std::mem::take(&mut self.resuming) {
+            self.checkpoint(RESUME_PREFIX)?;
+        }
+        self.checkpoint(END_PREFIX)?;
+        self.writer.flush().context("Failed to flush recording")
+    }
+}
+
+/// Result of checking a recording against its checkpoints
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Integrity {
+    /// Number of event lines
+    pub events: u64,
+    /// Number of checkpoints, including resume checkpoints
+    pub checkpoints: u64,
+    /// Last checkpoint, with its count advanced past any events after it
+    pub last: Option<Checkpoint>,
+    /// Events after the last checkpoint, which no checkpoint vouches for
+    pub unverified: u64,
+    /// Whether the last checkpoint is an end checkpoint with nothing
+    /// after it
+    pub ended: bool,
+    /// Places where events are known to be missing
+    pub gaps: Vec<String>,
+    /// Disagreements between the checkpoints and the events present, and
+    /// lines that were tampered with
+    pub errors: Vec<String>,
+    /// Hash chain of every line but the signatures, to continue from
+    pub chain: Chain,
+    /// Number of lines checked, for messages
+    lines: u64,
+}
+
+impl Integrity {
+    /// Check the lines of a recording
+    ///
+    /// # Arguments
+    /// * `lines` - Lines of the recording, in order
+    ///
+    /// # Returns
+    /// * `Integrity` - Findings
+    pub fn check<I, S>(lines: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut integrity = Self::default();
+        for line in lines {
+            integrity.push(line.as_ref());
+        }
+        integrity.finish()
+    }
+
+    /// Check the next line of a recording
+    ///
+    /// A checkpoint line that can't be read is reported as tampered with
+    /// and otherwise skipped.
+    ///
+    /// # Arguments
+    /// * `line` - Line following those already checked
+    pub fn push(&mut self, line: &str) {
+        self.lines += 1;
+        if !line.starts_with(SIGNATURE_PREFIX) {
+            self.chain.push(line);
+        }
+        let Some(line) = RecordLine::parse(line) else {
+            return;
+        };
+        self.ended = matches!(line, RecordLine::End(_));
+        let (checkpoint, resumed) = match line {
+            RecordLine::Event => {
+                self.events += 1;
+                self.unverified += 1;
+                return;
+            }
+            RecordLine::Malformed => {
+                self.errors.push(format!(
+                    "line {}: not a valid checkpoint",
+                    self.lines
+                ));
+                return;
+            }
+            RecordLine::Checkpoint(checkpoint) => (checkpoint, false),
+            RecordLine::Resume(checkpoint) => (checkpoint, true),
+            RecordLine::End(checkpoint) => (checkpoint, false),
+        };
+        self.checkpoints += 1;
+
+        if checkpoint.seq != self.events {
+            self.errors.push(format!(
+                "Checkpoint at {} counts {} events but {} are present",
+                checkpoint.time, checkpoint.seq, self.events
+            ));
+            self.events = checkpoint.seq;
+        }
+        if let Some(previous) = &self.last {
+            if resumed {
+                self.gaps.push(format!(
+                    "Not recording from {} to {}",
+                    previous.time, checkpoint.time
+                ));
+            }
+            let lost = checkpoint
+                .total_dropped()
+                .saturating_sub(previous.total_dropped());
+            if lost > 0 {
+                self.gaps.push(format!(
+                    "{} events dropped between {} and {}",
+                    lost, previous.time, checkpoint.time
+                ));
+            }
+        }
+        self.unverified = 0;
+        self.last = Some(checkpoint);
+    }
+
+    /// Findings once every line was checked
+    ///
+    /// A recording that doesn't end with an end checkpoint stopped
+    /// without writing one, or was cut short after; either way, what
+    /// came after its last checkpoint may be missing.
+    ///
+    /// # Returns
+    /// * `Integrity` - Findings, the last checkpoint advanced past the
+    ///   events after it
+    pub fn finish(mut self) -> Self {
+        if let Some(last) = &mut self.last {
+            if !self.ended {
+                self.gaps.push(format!(
+                    "Recording stops after the checkpoint at {} without an \
+                     end checkpoint; what followed may be missing",
+                    last.time
+                ));
+            }
+            last.seq += self.unverified;
+        }
+        self
+    }
+}
+
+/// Read and check a recording
+///
+/// A compressed recording cut short is checked up to the damage.
+///
+/// # Arguments
+/// * `path` - Plain or compressed recording
+///
+/// # Returns
+/// * `Result<(Integrity, bool)>` - Findings and whether the recording
+///   was cut short, or error if the file can't be read
+pub fn check_recording(path: &Path) -> Result<(Integrity, bool)> {
+    let mut lines = capture_lines(path)?;
+    let mut integrity = Integrity::default();
+    for line in lines.by_ref() {
+        integrity.push(&line?);
+    }
+    Ok((integrity.finish(), lines.truncated()))
+}
+
+/// Rewrite a compressed recording cut short as the lines before the
+/// damage, so what is appended to it can be read back
+///
+/// # Arguments
+/// * `output` - Recording to rewrite, with its compression
+///
+/// # Returns
+/// * `Result<()>` - Error if the recording can't be rewritten
+fn rewrite_truncated(output: &OutputFile) -> Result<()> {
+    let path = output.path.as_path();
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".repair");
+    let repaired = OutputFile {
+        path: PathBuf::from(name),
+        append: false,
+        ..output.clone()
+    };
+    let mut writer = repaired.create()?;
+    for line in capture_lines(path)? {
+        writeln!(writer, "{}", line?).with_context(|| {
+            format!("Failed to write {}", repaired.path.display())
+        })?;
+    }
+    writer.flush().with_context(|| {
+        format!("Failed to write {}", repaired.path.display())
+    })?;
+    drop(writer);
+    std::fs::rename(&repaired.path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Checkpoint to continue an existing recording from
+///
+/// # Arguments
+/// * `output` - Recording to append to
+///
+/// # Returns
+/// * `Result<Option<(Checkpoint, Chain)>>` - State and hash chain to
+///   continue from, None if the file is missing or empty, or error if it
+///   can't be read back intact or is compressed differently
+pub fn resume_point(
+    output: &OutputFile,
+) -> Result<Option<(Checkpoint, Chain)>> {
+    let path = output.path.as_path();
+    if fs_len(path) == 0 {
+        return Ok(None);
+    }
+    let existing = file_compression(path)?;
+    if existing != output.compression.map(|(kind, _)| kind) {
+        return Err(anyhow!(
+            "{} is compressed with {:?}; resume it with the same --compress",
+            path.display(),
+            existing
+        ));
+    }
+    let (integrity, truncated) = check_recording(path).context(
+        "Only recordings that can be read back intact can be resumed",
+    )?;
+    if truncated {
+        info!(
+            "{} was cut short; resuming after its last complete line",
+            path.display()
+        );
+        rewrite_truncated(output)?;
+    }
+    if integrity.unverified > 0 {
+        info!(
+            "{} events after the last checkpoint were not checkpointed \
+             before the recording stopped",
+            integrity.unverified
+        );
+    }
+    let checkpoint = integrity.last.unwrap_or_else(|| Checkpoint {
+        seq: integrity.events,
+        time: Utc::now(),
+        dropped: BTreeMap::new(),
+    });
+    Ok(Some((checkpoint, integrity.chain)))
+}
+
+/// Size of a file, or 0 if it doesn't exist
+fn fs_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Slowest replay speed, as a multiple of the recorded speed
+pub const MIN_REPLAY_FACTOR: f64 = 0.001;
+
+/// Fastest paced replay speed; anything faster is as good as max
+pub const MAX_REPLAY_FACTOR: f64 = 1_000_000.0;
+
+/// How fast `fw replay` prints events
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReplaySpeed {
+    /// As fast as possible
+    #[default]
+    Max,
+    /// This many times the recorded speed (e.g. 10.0 for ten times
+    /// faster), from [`MIN_REPLAY_FACTOR`] to [`MAX_REPLAY_FACTOR`]
+    Factor(f64),
+}
+
+/// Which events `fw replay` prints, and how fast
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReplayOptions {
+    /// Pacing of the printed events
+    pub speed: ReplaySpeed,
+    /// Skip events recorded before this time
+    pub from: Option<DateTime<Utc>>,
+    /// Skip events recorded after this time
+    pub to: Option<DateTime<Utc>>,
+    /// Keep printing events appended after the end of the recording
+    pub follow: bool,
+}
+
+impl ReplayOptions {
+    /// Check whether an event falls inside the time window
+    ///
+    /// Without a window every event is printed; with one, events whose
+    /// time can't be read are skipped.
+    ///
+    /// # Arguments
+    /// * `time` - Recorded time of the event, if it could be read
+    ///
+    /// # Returns
+    /// * `bool` - True if the event should be printed
+    fn includes(&self, time: Option<DateTime<Utc>>) -> bool {
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        time.is_some_and(|time| {
+            self.from.is_none_or(|from| time >= from)
+                && self.to.is_none_or(|to| time <= to)
+        })
+    }
+}
+
+/// Recorded time of an event line
+///
+/// # Arguments
+/// * `line` - Event line as written by `fw collect`
+///
+/// # Returns
+/// * `Option<DateTime<Utc>>` - Time, to the nanosecond as `fw record`
+///   writes it or to the second as `fw collect` prints it, or None if
+///   the line has no readable timestamp
+pub(crate) fn event_time(line: &str) -> Option<DateTime<Utc>> {
+    let event = CapturedEvent::parse(line)?;
+    NaiveDateTime::parse_from_str(&event.timestamp, "%Y-%m-%d %H:%M:%S%.f UTC")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// Reads the lines of a file that is still being written, following it
+/// when it is truncated or replaced
+#[derive(Debug)]
+struct LineTail {
+    /// File being followed
+    path: PathBuf,
+    /// Open file, positioned after the last line returned
+    reader: BufReader<File>,
+    /// Inode of the open file, to notice it being replaced
+    ino: u64,
+    /// Bytes of the open file read so far
+    pos: u64,
+    /// Start of a line whose newline hasn't been written yet
+    partial: String,
+}
+
+impl LineTail {
+    /// Open a file to read from its start
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let ino = file.metadata()?.ino();
+        Ok(Self {
+            path: path.to_path_buf(),
+            reader: BufReader::new(file),
+            ino,
+            pos: 0,
+            partial: String::new(),
+        })
+    }
+
+    /// Next complete line, without its newline
+    ///
+    /// # Returns
+    /// * `Result<Option<String>>` - Line, None if no complete line has
+    ///   been written yet, or error if the file can't be read
+    fn next_line(&mut self) -> Result<Option<String>> {
+        let read =
+            self.reader.read_line(&mut self.partial).with_context(|| {
+                format!("Failed to read {}", self.path.display())
+            })?;
+        self.pos += read as u64;
+        if !self.partial.ends_with('\n') {
+            return Ok(None);
+        }
+        let mut line = std::mem::take(&mut self.partial);
+        line.pop();
+        Ok(Some(line))
+    }
+
+    /// Start over if the file was truncated or replaced by a new one
+    ///
+    /// The lines appended to a replaced file since the last read are
+    /// read to its end first, so none are lost to the rotation; a line
+    /// left unfinished there is dropped. A missing file is waited for
+    /// with the old one still open, since it may be between a rotation's
+    /// rename and create.
+    ///
+    /// # Returns
+    /// * `Result<Option<Vec<String>>>` - The last lines of the replaced
+    ///   file if reading starts over, None if it doesn't, or error if a
+    ///   file can't be read or the new one opened
+    fn reopen_if_replaced(&mut self) -> Result<Option<Vec<String>>> {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return Ok(None);
+        };
+        if metadata.ino() != self.ino {
+            let mut last = Vec::new();
+            while let Some(line) = self.next_line()? {
+                last.push(line);
+            }
+            *self = Self::open(&self.path)?;
+            Ok(Some(last))
+        } else if metadata.len() < self.pos {
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+            self.partial.clear();
+            Ok(Some(Vec::new()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Spaces replayed events as far apart as they were recorded, scaled by
+/// the replay speed
+#[derive(Debug)]
+struct Pacer {
+    /// Replay speed
+    speed: ReplaySpeed,
+    /// Recorded time of the first paced event, and when it was printed
+    origin: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Pacer {
+    /// Create a pacer; the first event is printed straight away
+    fn new(speed: ReplaySpeed) -> Self {
+        Self {
+            speed,
+            origin: None,
+        }
+    }
+
+    /// Time to wait before printing an event
+    ///
+    /// Events recorded out of order are printed without waiting.
+    ///
+    /// # Arguments
+    /// * `time` - Recorded time of the event
+    /// * `now` - Current time, from the replay's clock
+    ///
+    /// # Returns
+    /// * `Duration` - How long to sleep first
+    fn delay(&mut self, time: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+        let ReplaySpeed::Factor(factor) = self.speed else {
+            return Duration::ZERO;
+        };
+        let (first, started) = *self.origin.get_or_insert((time, now));
+        let offset = (time - first).to_std().unwrap_or_default();
+        // A speed outside the parsed bounds waits as long as it can
+        // rather than overflowing
+        Duration::try_from_secs_f64(offset.as_secs_f64() / factor)
+            .ok()
+            .and_then(|offset| TimeDelta::from_std(offset).ok())
+            .and_then(|offset| started.checked_add_signed(offset))
+            .map_or(Duration::MAX, |due| {
+                (due - now).to_std().unwrap_or_default()
+            })
+    }
+}
+
+/// Print the events of a recording and report on its integrity
+///
+/// Events go to stdout and the integrity summary to stderr. Only events
+/// inside the options' time window are printed, paced at their speed;
+/// the integrity check always covers the whole recording. When
+/// following, the check covers what was recorded at the start, and
+/// events appended later are printed as they arrive until fw is stopped.
+///
+/// # Arguments
+/// * `path` - Plain or compressed recording; only plain ones can be
+///   followed
+/// * `options` - Time window, speed and whether to follow
+/// * `clock` - Time the pacing goes by
+///
+/// # Returns
+/// * `Result<()>` - Error if the checkpoints don't match the events
+pub fn run_replay(
+    path: &Path,
+    options: &ReplayOptions,
+    clock: &dyn Clock,
+) -> Result<()> {
+    let mut integrity = Integrity::default();
+    let mut pacer = Pacer::new(options.speed);
+    let mut stdout = std::io::stdout().lock();
+    let mut print = |line: &str, pacer: Option<&mut Pacer>| -> Result<()> {
+        if !matches!(RecordLine::parse(line), Some(RecordLine::Event)) {
+            return Ok(());
+        }
+        let time = event_time(line);
+        if options.includes(time) {
+            let delay = time
+                .zip(pacer)
+                .map(|(time, pacer)| pacer.delay(time, clock.now()))
+                .unwrap_or_default();
+            if !delay.is_zero() {
+                stdout.flush().context("Failed to flush events")?;
+                std::thread::sleep(delay);
+            }
+            writeln!(stdout, "{}", line).context("Failed to write event")?;
+        }
+        Ok(())
+    };
+
+    if !options.follow {
+        for line in capture_lines(path)? {
+            let line = line?;
+            print(&line, Some(&mut pacer))?;
+            integrity.push(&line);
+        }
+        std::io::stdout()
+            .flush()
+            .context("Failed to flush events")?;
+        return report_integrity(integrity.finish(), false);
+    }
+
+    if file_compression(path)?.is_some() {
+        return Err(anyhow!(
+            "{} is compressed and can't be followed; record it without \
+             --compress",
+            path.display()
+        ));
+    }
+    let mut tail = LineTail::open(path)?;
+    while let Some(line) = tail.next_line()? {
+        print(&line, Some(&mut pacer))?;
+        integrity.push(&line);
+    }
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush events")?;
+    // Keep following a recording with bad checkpoints; they're reported.
+    // It is still being written, so it isn't finished.
+    if let Err(e) = report_integrity(integrity, true) {
+        eprintln!("warning: {}", e);
+    }
+    loop {
+        std::thread::sleep(FOLLOW_POLL_INTERVAL);
+        if let Some(last) = tail.reopen_if_replaced()? {
+            for line in last {
+                print(&line, None)?;
+            }
+            eprintln!(
+                "{} was replaced; following from its start",
+                path.display()
+            );
+        }
+        while let Some(line) = tail.next_line()? {
+            print(&line, None)?;
+        }
+        std::io::stdout()
+            .flush()
+            .context("Failed to flush events")?;
+    }
+}
+
+/// Print the integrity summary of a replayed recording to stderr
+///
+/// # Arguments
+/// * `integrity` - Findings over the lines of the recording
+/// * `follow` - The recording is still being written, so events after
+///   the last checkpoint are expected
+///
+/// # Returns
+/// * `Result<()>` - Error if the checkpoints don't match the events
+fn report_integrity(integrity: Integrity, follow: bool) -> Result<()> {
+    eprintln!(
+        "{} events, {} checkpoints",
+        integrity.events, integrity.checkpoints
+    );
+    for gap in &integrity.gaps {
+        eprintln!("gap: {}", gap);
+    }
+    if integrity.unverified > 0 && !follow {
+        eprintln!(
+            "warning: last {} events have no checkpoint; the recording did \
+             not finish cleanly",
+            integrity.unverified
+        );
+    }
+    for error in &integrity.errors {
+        eprintln!("error: {}", error);
+    }
+    if !integrity.errors.is_empty() {
+        return Err(anyhow!(
+            "Recording is corrupt: {} checkpoints don't match its events",
+            integrity.errors.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Check a signed recording's signatures and checkpoints
+///
+/// Prints a summary, any gaps and any tampering to stdout.
+///
+/// # Arguments
+/// * `path` - Plain or compressed recording
+/// * `key` - Public key the recording must be signed with
+///
+/// # Returns
+/// * `Result<()>` - Error if a signature or checkpoint fails, or if the
+///   recording isn't signed
+pub fn run_verify(path: &Path, key: &VerifyingKey) -> Result<()> {
+    let lines = capture_lines(path)?.lossy().collect::<Result<Vec<_>>>()?;
+    let integrity = Integrity::check(&lines);
+    let signatures = SignatureCheck::check(&lines, key);
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(
+        stdout,
+        "{} events, {} checkpoints, {} valid signatures",
+        integrity.events, integrity.checkpoints, signatures.valid
+    )?;
+    for gap in &integrity.gaps {
+        writeln!(stdout, "gap: {}", gap)?;
+    }
+    if signatures.unsigned > 0 {
+        writeln!(
+            stdout,
+            "warning: last {} lines are not signed; the recording did not \
+             finish cleanly",
+            signatures.unsigned
+        )?;
+    }
+    for error in integrity.errors.iter().chain(&signatures.errors) {
+        writeln!(stdout, "tampered: {}", error)?;
+    }
+    let failures = integrity.errors.len() + signatures.errors.len();
+    if failures > 0 {
+        return Err(anyhow!(
+            "Recording failed verification ({} problems)",
+            failures
+        ));
+    }
+    if signatures.valid == 0 {
+        return Err(anyhow!("{} is not signed", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::clock::SystemClock;
+    use crate::compression::{read_capture, Compression};
+    use crate::file_event::FileAction;
+
+    fn event(path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "cp".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    fn record(config: RecordConfig, paths: &[&str], lagged: u64) -> String {
+        let mut sink = RecordSink::new(config, Vec::new());
+        for path in paths {
+            sink.write_event(&event(path)).unwrap();
+        }
+        sink.dropped("lagged", lagged);
+        sink.finish().unwrap();
+        String::from_utf8(sink.writer).unwrap()
+    }
+
+    #[test]
+    fn test_checkpoints_validate_and_resume() {
+        let every_event = RecordConfig {
+            interval: Duration::ZERO,
+            resume: None,
+            chain: Chain::default(),
+            signer: None,
+        };
+        let first = record(every_event.clone(), &["/a", "/b"], 0);
+        let integrity = Integrity::check(first.lines());
+        assert_eq!(integrity.events, 2);
+        assert_eq!(integrity.checkpoints, 3);
+        assert!(integrity.errors.is_empty() && integrity.gaps.is_empty());
+
+        // Resuming continues the count and marks the interruption
+        let resumed = RecordConfig {
+            resume: integrity.last,
+            ..every_event
+        };
+        let both = first + &record(resumed, &["/c"], 4);
+        let integrity = Integrity::check(both.lines());
+        assert_eq!(integrity.events, 3);
+        assert!(integrity.errors.is_empty());
+        assert_eq!(integrity.gaps.len(), 2);
+        assert!(integrity.gaps[0].starts_with("Not recording from"));
+        assert!(integrity.gaps[1].starts_with("4 events dropped"));
+        assert_eq!(integrity.last.unwrap().dropped["lagged"], 4);
+    }
+
+    #[test]
+    fn test_truncated_compressed_recording_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rec.gz");
+        let output =
+            OutputFile::new(path.clone(), Some(Compression::Gzip), None)
+                .unwrap();
+        let every_event = RecordConfig {
+            interval: Duration::ZERO,
+            resume: None,
+            chain: Chain::default(),
+            signer: None,
+        };
+        let mut sink =
+            RecordSink::new(every_event.clone(), output.create().unwrap());
+        sink.write_event(&event("/a")).unwrap();
+        sink.write_event(&event("/b")).unwrap();
+        drop(sink);
+        let whole = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &whole[..whole.len() - 12]).unwrap();
+        assert!(check_recording(&path).unwrap().1);
+
+        let (checkpoint, _) = resume_point(&output).unwrap().unwrap();
+        let (integrity, truncated) = check_recording(&path).unwrap();
+        assert!(!truncated, "the damaged tail is rewritten away");
+        assert_eq!(checkpoint.seq, integrity.events);
+
+        // What is appended after resuming reads back
+        let resumed = RecordConfig {
+            resume: Some(checkpoint),
+            ..every_event
+        };
+        let appending = output.clone().with_append(true);
+        let mut sink = RecordSink::new(resumed, appending.create().unwrap());
+        sink.write_event(&event("/c")).unwrap();
+        sink.finish().unwrap();
+        drop(sink);
+        let (integrity, truncated) = check_recording(&path).unwrap();
+        assert!(!truncated);
+        assert!(integrity.errors.is_empty(), "{:?}", integrity.errors);
+        assert!(read_capture(&path).unwrap().contains("/c"));
+    }
+
+    #[test]
+    fn test_signed_recording_survives_resume() {
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(
+            &ring::rand::SystemRandom::new(),
+        )
+        .unwrap();
+        let key = SigningKey::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let signed = RecordConfig {
+            interval: Duration::from_secs(3600),
+            resume: None,
+            chain: Chain::default(),
+            signer: Some(key.clone()),
+        };
+        let first = record(signed.clone(), &["/a", "/b"], 0);
+        let integrity = Integrity::check(first.lines());
+        let resumed = RecordConfig {
+            resume: integrity.last,
+            chain: integrity.chain,
+            ..signed
+        };
+        let both = first + &record(resumed, &["/c"], 0);
+        let public = VerifyingKey::from(&key);
+        let check = SignatureCheck::check(both.lines(), &public);
+        // The final checkpoint of each run, plus the resume checkpoint
+        assert_eq!(check.valid, 3);
+        assert_eq!(check.unsigned, 0);
+        assert!(check.errors.is_empty());
+
+        let tampered = both.replacen("/b", "/x", 1);
+        let check = SignatureCheck::check(tampered.lines(), &public);
+        assert_eq!(check.valid, 2);
+        assert_eq!(check.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_recording_with_mock_clock_is_reproducible() {
+        let start = "2024-01-01T00:00:00Z".parse().unwrap();
+        let run = || {
+            let config = RecordConfig {
+                interval: Duration::ZERO,
+                resume: None,
+                chain: Chain::default(),
+                signer: None,
+            };
+            let clock = MockClock::new(start).with_step(Duration::from_secs(1));
+            let mut sink =
+                RecordSink::new(config, Vec::new()).with_clock(Arc::new(clock));
+            for path in ["/a", "/b"] {
+                sink.write_event(&event(path).with_timestamp(start))
+                    .unwrap();
+            }
+            sink.finish().unwrap();
+            String::from_utf8(sink.writer).unwrap()
+        };
+
+        let recording = run();
+        assert_eq!(recording, run());
+        assert!(recording
+            .contains("# checkpoint seq=2 time=2024-01-01T00:00:01.000Z"));
+    }
+
+    #[test]
+    fn test_replay_window_and_pacing() {
+        let line = "2024-01-01 12:00:05 UTC | cp (1) | opened | /a";
+        let time = event_time(line).unwrap();
+        assert_eq!(
+            time,
+            "2024-01-01T12:00:05Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(event_time("# checkpoint seq=1"), None);
+        // Recordings keep the time to the nanosecond
+        let recorded =
+            event("/a").with_timestamp(time + Duration::from_millis(250));
+        let line = format!("{:#}", recorded);
+        assert!(line.starts_with("2024-01-01 12:00:05.250000000 UTC | "));
+        assert_eq!(event_time(&line), Some(recorded.timestamp));
+
+        let window = ReplayOptions {
+            from: "2024-01-01T12:00:00Z".parse().ok(),
+            to: "2024-01-01T12:00:05Z".parse().ok(),
+            ..Default::default()
+        };
+        assert!(window.includes(Some(time)));
+        assert!(!window.includes(Some(time + chrono::TimeDelta::seconds(1))));
+        assert!(!window.includes(None));
+        assert!(ReplayOptions::default().includes(None));
+
+        // Ten times faster: 5s apart in the recording is 500ms apart
+        let clock = MockClock::new(time);
+        let mut pacer = Pacer::new(ReplaySpeed::Factor(10.0));
+        assert_eq!(pacer.delay(time, clock.now()), Duration::ZERO);
+        let later = time + chrono::TimeDelta::seconds(5);
+        assert_eq!(pacer.delay(later, clock.now()), Duration::from_millis(500));
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(pacer.delay(later, clock.now()), Duration::from_millis(300));
+        // Out-of-order events don't wait
+        let earlier = time - chrono::TimeDelta::seconds(5);
+        assert_eq!(pacer.delay(earlier, clock.now()), Duration::ZERO);
+        // Events within the same second are still spaced apart
+        let mut pacer = Pacer::new(ReplaySpeed::Factor(1.0));
+        pacer.delay(time, clock.now());
+        let soon = time + chrono::TimeDelta::milliseconds(250);
+        assert_eq!(pacer.delay(soon, clock.now()), Duration::from_millis(250));
+        let mut max = Pacer::new(ReplaySpeed::Max);
+        assert_eq!(max.delay(later, clock.now()), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_tail_follows_appends_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fw.log");
+        std::fs::write(&path, "a | 1\nb |").unwrap();
+        let mut tail = LineTail::open(&path).unwrap();
+        assert_eq!(tail.next_line().unwrap().as_deref(), Some("a | 1"));
+        // The second line isn't complete yet
+        assert_eq!(tail.next_line().unwrap(), None);
+        let append = |text: &str| {
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+        append(" 2\n");
+        assert_eq!(tail.next_line().unwrap().as_deref(), Some("b | 2"));
+        assert_eq!(tail.reopen_if_replaced().unwrap(), None);
+
+        // Truncated in place, then replaced by a new file
+        std::fs::write(&path, "c\n").unwrap();
+        assert_eq!(tail.reopen_if_replaced().unwrap(), Some(Vec::new()));
+        assert_eq!(tail.next_line().unwrap().as_deref(), Some("c"));
+        // Lines appended just before the rotation are still read
+        append("c2\nc3\nunfinished");
+        let rotated = dir.path().join("fw.log.new");
+        std::fs::write(&rotated, "d\n").unwrap();
+        std::fs::rename(&rotated, &path).unwrap();
+        assert_eq!(
+            tail.reopen_if_replaced().unwrap(),
+            Some(vec!["c2".to_string(), "c3".to_string()])
+        );
+        assert_eq!(tail.next_line().unwrap().as_deref(), Some("d"));
+        assert_eq!(tail.next_line().unwrap(), None);
+    }
+
+    #[test]
+    fn test_missing_events_and_unfinished_tail() {
+        let recording = "\
+a | 1
+b | 2
+# checkpoint seq=3 time=2026-01-01T00:00:00.000Z
+c | 3
+";
+        let integrity = Integrity::check(recording.lines());
+        assert_eq!(integrity.errors.len(), 1);
+        assert_eq!(integrity.unverified, 1);
+        assert!(integrity.gaps[0].contains("without an end checkpoint"));
+        assert_eq!(integrity.last.unwrap().seq, 4);
+    }
+
+    #[test]
+    fn test_injected_lines_and_cut_recordings_are_reported() {
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(
+            &ring::rand::SystemRandom::new(),
+        )
+        .unwrap();
+        let key = SigningKey::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public = VerifyingKey::from(&key);
+        let signed = RecordConfig {
+            interval: Duration::ZERO,
+            resume: None,
+            chain: Chain::default(),
+            signer: Some(key),
+        };
+        let recording = record(signed, &["/a", "/b", "/c"], 0);
+        let lines: Vec<&str> = recording.lines().collect();
+        let integrity = Integrity::check(&lines);
+        assert!(integrity.ended);
+        assert!(integrity.errors.is_empty() && integrity.gaps.is_empty());
+
+        // Garbage is reported and the rest of the recording still checked
+        let mut injected = lines.clone();
+        injected.insert(2, "# checkpoint \u{1}garbage");
+        let integrity = Integrity::check(&injected);
+        assert_eq!(integrity.errors, ["line 3: not a valid checkpoint"]);
+        assert_eq!((integrity.events, integrity.checkpoints), (3, 4));
+        assert_eq!(SignatureCheck::check(&injected, &public).errors.len(), 1);
+
+        // Cut back to the first signed checkpoint, every signature holds
+        let cut = &lines[..3];
+        let check = SignatureCheck::check(cut, &public);
+        assert!(check.errors.is_empty() && check.unsigned == 0);
+        let integrity = Integrity::check(cut);
+        assert!(!integrity.ended);
+        assert_eq!(integrity.gaps.len(), 1);
+        assert!(integrity.gaps[0].contains("without an end checkpoint"));
+    }
+}
@@ -0,0 +1,460 @@
+//! Redact module
+//!
+//! Rewrites sensitive parts of paths before events reach any sink, so
+//! logs can be shipped without the usernames or customer IDs embedded in
+//! them. Rules are regex-to-replacement pairs applied in order, after a
+//! built-in rule anonymizing home directories. A replacement may contain
+//! `{hash}`, which becomes a salt-keyed hash of the matched value, so the
+//! same value always redacts to the same token and events stay
+//! correlatable without revealing it. Filters still see the real paths.
+//!
+//! The rules also apply to the other strings an event carries that can
+//! hold a path or a user name: program name, extended attribute name, tag
+//! values (ancestry chains, watchlist globs, ...) and the modules and
+//! source files of stack frames. With home anonymization, user and group
+//! names are replaced the way the home rule replaces the user name in a
+//! path, so `/home/alice` and user `alice` redact to the same token.
+
+use anyhow::{anyhow, Context, Result};
+use regex::{Captures, Regex};
+use ring::hmac;
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::fanout::{EventSink, Subscriber};
+use crate::file_event::FileEvent;
+
+/// Placeholder replaced by the hash of the matched value
+pub const HASH_PLACEHOLDER: &str = "{hash}";
+
+/// Separator between a rule's pattern and its replacement
+const RULE_SEPARATOR: &str = "=>";
+
+/// Hex digits of the hash kept in redacted paths
+const HASH_LEN: usize = 12;
+
+/// Built-in rule for home directories, hashing the user name if there is
+/// a salt
+const HOME_RULE: &str = "^/home/([^/]+)";
+
+/// Redaction settings from the command line
+#[derive(Debug, Clone, Default)]
+pub struct RedactConfig {
+    /// Rules as "REGEX=>REPLACEMENT", applied in order
+    pub rules: Vec<String>,
+    /// Replace the user name in /home/<user> paths
+    pub anonymize_home: bool,
+    /// File holding the secret salt `{hash}` is keyed with
+    pub salt: Option<PathBuf>,
+}
+
+impl RedactConfig {
+    /// Whether any redaction is configured
+    ///
+    /// # Returns
+    /// * `bool` - True if there are rules or home anonymization
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty() || self.anonymize_home
+    }
+}
+
+/// One compiled rule
+#[derive(Debug)]
+struct Rule {
+    /// What to replace
+    pattern: Regex,
+    /// Replacement, with `$1`-style references and possibly `{hash}`
+    replacement: String,
+}
+
+/// Compiled redaction rules
+#[derive(Debug)]
+pub struct Redactor {
+    /// Rules in the order they are applied
+    rules: Vec<Rule>,
+    /// Key `{hash}` is computed with, if a salt was given
+    key: Option<hmac::Key>,
+    /// Whether user and group names are replaced like home directories
+    anonymize_home: bool,
+}
+
+impl Redactor {
+    /// Compile redaction rules
+    ///
+    /// # Arguments
+    /// * `config` - Rules, home anonymization and salt file
+    ///
+    /// # Returns
+    /// * `Result<Redactor>` - Redactor, or error for a malformed rule, an
+    ///   unreadable salt, or `{hash}` without a salt
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        let key = match &config.salt {
+            Some(path) => {
+                let salt = fs::read(path).with_context(|| {
+                    format!("Failed to read {}", path.display())
+                })?;
+                if salt.is_empty() {
+                    return Err(anyhow!(
+                        "Salt file {} is empty",
+                        path.display()
+                    ));
+                }
+                Some(hmac::Key::new(hmac::HMAC_SHA256, &salt))
+            }
+            None => None,
+        };
+        let mut rules = Vec::new();
+        if config.anonymize_home {
+            let replacement = match key {
+                Some(_) => format!("/home/{}", HASH_PLACEHOLDER),
+                None => "/home/user".to_string(),
+            };
+            rules.push(Rule {
+                pattern: Regex::new(HOME_RULE)?,
+                replacement,
+            });
+        }
+        for rule in &config.rules {
+            let (pattern, replacement) =
+                rule.split_once(RULE_SEPARATOR).ok_or_else(|| {
+                    anyhow!(
+                        "Redaction rule '{}' is not REGEX=>REPLACEMENT",
+                        rule
+                    )
+                })?;
+            if replacement.contains(HASH_PLACEHOLDER) && key.is_none() {
+                return Err(anyhow!(
+                    "Redaction rule '{}' uses {} but no salt was given",
+                    rule,
+                    HASH_PLACEHOLDER
+                ));
+            }
+            rules.push(Rule {
+                pattern: Regex::new(pattern).with_context(|| {
+                    format!("Invalid pattern in redaction rule '{}'", rule)
+                })?,
+                replacement: replacement.to_string(),
+            });
+        }
+        Ok(Self {
+            rules,
+            key,
+            anonymize_home: config.anonymize_home,
+        })
+    }
+
+    /// Salt-keyed hash of a value
+    fn hash(&self, value: &str) -> String {
+        let Some(key) = &self.key else {
+            return String::new();
+        };
+        let tag = hmac::sign(key, value.as_bytes());
+        let mut hex = String::with_capacity(HASH_LEN);
+        for byte in &tag.as_ref()[..HASH_LEN / 2] {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        hex
+    }
+
+    /// Apply every rule to a path
+    ///
+    /// # Arguments
+    /// * `path` - Path to redact
+    ///
+    /// # Returns
+    /// * `Cow<str>` - Redacted path, borrowed if no rule matched
+    pub fn redact_path<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let mut path = Cow::Borrowed(path);
+        for rule in &self.rules {
+            let replaced = if rule.replacement.contains(HASH_PLACEHOLDER) {
+                rule.pattern.replace_all(&path, |caps: &Captures| {
+                    // The first group if there is one, else the whole match
+                    let value = caps.get(1).or_else(|| caps.get(0));
+                    let hash = self.hash(value.map_or("", |m| m.as_str()));
+                    let mut out = String::new();
+                    caps.expand(
+                        &rule.replacement.replace(HASH_PLACEHOLDER, &hash),
+                        &mut out,
+                    );
+                    out
+                })
+            } else {
+                rule.pattern.replace_all(&path, rule.replacement.as_str())
+            };
+            if let Cow::Owned(replaced) = replaced {
+                path = Cow::Owned(replaced);
+            }
+        }
+        path
+    }
+
+    /// Redact a user or group name
+    ///
+    /// # Arguments
+    /// * `name` - Name to redact
+    /// * `anonymous` - Replacement when home anonymization is on and there
+    ///   is no salt to hash with
+    ///
+    /// # Returns
+    /// * `String` - The name's hash or `anonymous` with home anonymization,
+    ///   then with every rule applied
+    fn redact_name(&self, name: &str, anonymous: &str) -> String {
+        let name = match (self.anonymize_home, &self.key) {
+            (false, _) => name.to_string(),
+            (true, Some(_)) => self.hash(name),
+            (true, None) => anonymous.to_string(),
+        };
+        self.redact_path(&name).into_owned()
+    }
+
+    /// Redact every path and name an event carries
+    ///
+    /// # Arguments
+    /// * `event` - Event to redact in place
+    pub fn redact(&self, event: &mut FileEvent) {
+        if let Cow::Owned(path) = self.redact_path(&event.file_path) {
+            event.file_path = path;
+            // The raw bytes would give the original name away
+            event.raw_path = None;
+        }
+        redact_in_place(self, &mut event.program_name);
+        for text in [
+            &mut event.mount_point,
+            &mut event.link_source,
+            &mut event.renamed_from,
+            &mut event.xattr_name,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            redact_in_place(self, text);
+        }
+        for value in event.tags.values_mut() {
+            redact_in_place(self, value);
+        }
+        if let Some(user) = &mut event.user {
+            *user = self.redact_name(user, "user");
+        }
+        if let Some(group) = &mut event.group {
+            *group = self.redact_name(group, "group");
+        }
+        if let Some(stack) = &mut event.stack {
+            let stack = Arc::make_mut(stack);
+            for frame in &mut stack.frames {
+                for text in [&mut frame.module, &mut frame.location]
+                    .into_iter()
+                    .flatten()
+                {
+                    redact_in_place(self, text);
+                }
+            }
+        }
+    }
+}
+
+/// Apply every rule to a string, replacing it only if a rule matched
+fn redact_in_place(redactor: &Redactor, text: &mut String) {
+    if let Cow::Owned(redacted) = redactor.redact_path(text) {
+        *text = redacted;
+    }
+}
+
+/// Sink wrapper redacting events before passing them on
+pub struct RedactingSink {
+    /// Rules to apply
+    redactor: Arc<Redactor>,
+    /// Sink receiving redacted events
+    inner: Box<dyn EventSink>,
+}
+
+impl RedactingSink {
+    /// Redact what a subscriber's sink receives, after its filter ran
+    ///
+    /// # Arguments
+    /// * `subscriber` - Subscriber whose sink to wrap
+    /// * `redactor` - Rules shared by every subscriber
+    ///
+    /// # Returns
+    /// * `Subscriber` - Same subscriber writing redacted events
+    pub fn wrap(subscriber: Subscriber, redactor: Arc<Redactor>) -> Subscriber {
+        Subscriber {
+            sink: Box::new(Self {
+                redactor,
+                inner: subscriber.sink,
+            }),
+            ..subscriber
+        }
+    }
+}
+
+impl EventSink for RedactingSink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let mut event = event.clone();
+        self.redactor.redact(&mut event);
+        self.inner.write_event(&event)
+    }
+
+    fn dropped(&mut self, source: &str, count: u64) {
+        self.inner.dropped(source, count);
+    }
+
+    fn throttled(&mut self, event: &FileEvent) {
+        let mut event = event.clone();
+        self.redactor.redact(&mut event);
+        self.inner.throttled(&event);
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.inner.tick()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use crate::stacks::{Frame, Stack};
+
+    #[test]
+    fn test_rules_and_home_anonymizer() {
+        let dir = tempfile::tempdir().unwrap();
+        let salt = dir.path().join("salt");
+        fs::write(&salt, "pepper").unwrap();
+        let redactor = Redactor::new(&RedactConfig {
+            rules: vec![
+                r"/customers/(\d+)/=>/customers/{hash}/".to_string(),
+                r"\.(key|pem)$=>.secret".to_string(),
+            ],
+            anonymize_home: true,
+            salt: Some(salt),
+        })
+        .unwrap();
+
+        let mut event = FileEvent::new(
+            "/home/alice/customers/4711/tls.key".to_string(),
+            "vi".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        event.renamed_from = Some("/home/alice/notes".to_string());
+        event.raw_path = Some(event.file_path.clone().into_bytes());
+        redactor.redact(&mut event);
+        let parts: Vec<&str> = event.file_path.split('/').collect();
+        assert_eq!(parts[1], "home");
+        assert_eq!(parts[2].len(), HASH_LEN);
+        assert_ne!(parts[2], "alice");
+        assert_eq!(parts[4].len(), HASH_LEN);
+        assert_eq!(parts[5], "tls.secret");
+        assert!(event.raw_path.is_none());
+        // The same user hashes the same everywhere
+        let renamed = event.renamed_from.unwrap();
+        assert_eq!(renamed.split('/').nth(2), Some(parts[2]));
+
+        assert_eq!(redactor.redact_path("/etc/hosts"), "/etc/hosts");
+    }
+
+    #[test]
+    fn test_redacts_every_field() {
+        let redactor = Redactor::new(&RedactConfig {
+            rules: vec!["alice=>someone".to_string()],
+            anonymize_home: true,
+            salt: None,
+        })
+        .unwrap();
+
+        let mut event = FileEvent::new(
+            "/home/alice/notes".to_string(),
+            "alice-sync".to_string(),
+            FileAction::XattrSet,
+            1,
+            SystemClock.now(),
+        );
+        event.user = Some("alice".to_string());
+        event.group = Some("alice".to_string());
+        event.xattr_name = Some("user.alice.tag".to_string());
+        event
+            .tags
+            .insert("ancestry".to_string(), "alice-sync(9) < sh(1)".into());
+        event
+            .tags
+            .insert("watchlist".to_string(), "/srv/alice/**".to_string());
+        event.stack = Some(Arc::new(Stack {
+            frames: vec![Frame {
+                addr: 0x1000,
+                kernel: false,
+                symbol: Some("main".to_string()),
+                location: Some("/home/alice/src/main.rs:3".to_string()),
+                module: Some("/srv/alice/bin/sync".to_string()),
+            }],
+            ..Default::default()
+        }));
+        redactor.redact(&mut event);
+
+        assert_eq!(event.file_path, "/home/user/notes");
+        assert_eq!(event.program_name, "someone-sync");
+        assert_eq!(event.user.as_deref(), Some("user"));
+        assert_eq!(event.group.as_deref(), Some("group"));
+        assert_eq!(event.xattr_name.as_deref(), Some("user.someone.tag"));
+        assert_eq!(event.tags["ancestry"], "someone-sync(9) < sh(1)");
+        assert_eq!(event.tags["watchlist"], "/srv/someone/**");
+        let frame = &event.stack.as_ref().unwrap().frames[0];
+        assert_eq!(frame.location.as_deref(), Some("/home/user/src/main.rs:3"));
+        assert_eq!(frame.module.as_deref(), Some("/srv/someone/bin/sync"));
+        assert_eq!(frame.symbol.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_names_hash_like_home_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let salt = dir.path().join("salt");
+        fs::write(&salt, "pepper").unwrap();
+        let redactor = Redactor::new(&RedactConfig {
+            anonymize_home: true,
+            salt: Some(salt),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut event = FileEvent::new(
+            "/home/alice/notes".to_string(),
+            "vi".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        event.user = Some("alice".to_string());
+        redactor.redact(&mut event);
+        let user = event.user.unwrap();
+        assert_eq!(user.len(), HASH_LEN);
+        assert_eq!(event.file_path, format!("/home/{}/notes", user));
+    }
+
+    #[test]
+    fn test_hash_needs_salt() {
+        let unsalted = RedactConfig {
+            rules: vec!["x=>{hash}".to_string()],
+            ..Default::default()
+        };
+        assert!(Redactor::new(&unsalted).is_err());
+        let malformed = RedactConfig {
+            rules: vec!["no separator".to_string()],
+            ..Default::default()
+        };
+        assert!(Redactor::new(&malformed).is_err());
+
+        let home = Redactor::new(&RedactConfig {
+            anonymize_home: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(home.redact_path("/home/bob/.ssh/id"), "/home/user/.ssh/id");
+    }
+}
@@ -0,0 +1,527 @@
+//! Remote Monitor module
+//!
+//! Event backend for `fw collect-remote`. Instead of attaching probes it
+//! accepts TLS connections from `fw forward` instances and yields the
+//! events they send, tagged with the host each came from, so filters,
+//! enrichment, severity rules and sinks run on them unchanged. Which
+//! forwarders are accepted is decided by the peer policy; events from a
+//! forwarder that authenticated with an allowlisted certificate are also
+//! tagged with the certificate name. Accepting connections needs the
+//! `remote` feature.
+
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+use crate::file_event::FileEvent;
+#[cfg(feature = "remote")]
+use crate::forward::{
+    crypto_provider, load_certs, load_key, load_roots, Hello,
+    FORWARD_PROTOCOL_VERSION,
+};
+use crate::monitor_backend::MonitorBackend;
+#[cfg(feature = "remote")]
+use crate::peer_auth::{PeerPolicy, PEER_TAG};
+#[cfg(feature = "remote")]
+use anyhow::{anyhow, Context};
+#[cfg(feature = "remote")]
+use log::{info, warn};
+#[cfg(feature = "remote")]
+use rustls::{server::WebPkiClientVerifier, ServerConfig};
+#[cfg(feature = "remote")]
+use std::sync::Arc;
+#[cfg(feature = "remote")]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+#[cfg(feature = "remote")]
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature = "remote")]
+use tokio::task::{JoinHandle, JoinSet};
+#[cfg(feature = "remote")]
+use tokio_rustls::TlsAcceptor;
+
+/// Tag holding the host a forwarded event came from
+pub const HOST_TAG: &str = "host";
+
+/// Maximum events queued between the connections and the pipeline
+#[cfg(feature = "remote")]
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// Longest line accepted from a forwarder
+#[cfg(feature = "remote")]
+const MAX_LINE_BYTES: u64 = 1024 * 1024;
+
+/// Where to listen and which certificates to use
+#[derive(Debug, Clone)]
+pub struct RemoteConfig {
+    /// Address to accept forwarders on
+    pub listen: SocketAddr,
+    /// Server certificate chain (PEM)
+    pub cert: PathBuf,
+    /// Server private key (PEM)
+    pub key: PathBuf,
+    /// CA forwarders' client certificates must chain to; any forwarder
+    /// may connect if unset
+    pub client_ca: Option<PathBuf>,
+    /// Certificate names forwarders must authenticate as, one per line;
+    /// any certificate from `client_ca` is accepted if unset
+    pub allowlist: Option<PathBuf>,
+    /// "host token" lines forwarders must present a token from; host
+    /// names aren't checked if unset
+    pub tokens: Option<PathBuf>,
+}
+
+/// Backend receiving events from `fw forward` instances
+#[cfg(feature = "remote")]
+pub struct RemoteMonitor {
+    /// Listening address
+    listen: SocketAddr,
+    /// TLS settings for accepted connections
+    acceptor: TlsAcceptor,
+    /// Which forwarders may send events
+    policy: Arc<PeerPolicy>,
+    /// Address actually bound, once started
+    local_addr: Option<SocketAddr>,
+    /// Task accepting connections and reading their events
+    server: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "remote")]
+impl RemoteMonitor {
+    /// Load the certificates for a remote backend
+    ///
+    /// # Arguments
+    /// * `config` - Listening address and certificate files
+    ///
+    /// # Returns
+    /// * `Result<RemoteMonitor>` - Backend, or error if a certificate,
+    ///   key, allowlist or token file can't be loaded
+    pub fn new(config: &RemoteConfig) -> Result<Self> {
+        let policy = PeerPolicy::load(
+            config.allowlist.as_deref(),
+            config.tokens.as_deref(),
+        )?;
+        if policy.needs_client_cert() && config.client_ca.is_none() {
+            return Err(anyhow!("A client allowlist needs a client CA"));
+        }
+        let builder = ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?;
+        let builder = match &config.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(ca)?),
+                    crypto_provider(),
+                )
+                .build()
+                .context("Invalid client CA")?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let tls = builder
+            .with_single_cert(load_certs(&config.cert)?, load_key(&config.key)?)
+            .context("Invalid server certificate or key")?;
+        Ok(Self {
+            listen: config.listen,
+            acceptor: TlsAcceptor::from(Arc::new(tls)),
+            policy: Arc::new(policy),
+            local_addr: None,
+            server: None,
+        })
+    }
+
+    /// Address the backend is listening on
+    ///
+    /// # Returns
+    /// * `Option<SocketAddr>` - Bound address (useful with port 0), or
+    ///   None before monitoring starts
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+#[cfg(feature = "remote")]
+impl MonitorBackend for RemoteMonitor {
+    /// Listen for forwarders, sending their events on the channel
+    async fn start_monitoring(&mut self) -> Result<mpsc::Receiver<FileEvent>> {
+        if self.server.is_some() {
+            return Err(anyhow!("Monitor is already running"));
+        }
+        let listener = TcpListener::bind(self.listen)
+            .await
+            .with_context(|| format!("Failed to listen on {}", self.listen))?;
+        let local_addr = listener.local_addr()?;
+        info!("Accepting forwarded events on {}", local_addr);
+
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+        self.server = Some(tokio::spawn(serve(
+            listener,
+            self.acceptor.clone(),
+            self.policy.clone(),
+            tx,
+        )));
+        self.local_addr = Some(local_addr);
+        Ok(rx)
+    }
+
+    /// Stop listening and drop every forwarder connection
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+        self.local_addr = None;
+        Ok(())
+    }
+}
+
+/// Stand-in for builds without the `remote` feature; never created
+#[cfg(not(feature = "remote"))]
+pub enum RemoteMonitor {}
+
+#[cfg(not(feature = "remote"))]
+impl RemoteMonitor {
+    /// Refuse to accept forwarders
+    ///
+    /// # Returns
+    /// * `Result<RemoteMonitor>` - Always an error naming the feature
+    pub fn new(_: &RemoteConfig) -> Result<Self> {
+        Err(crate::features::not_compiled_in(
+            "remote",
+            "Receiving forwarded events",
+        ))
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+impl MonitorBackend for RemoteMonitor {
+    async fn start_monitoring(&mut self) -> Result<mpsc::Receiver<FileEvent>> {
+        match *self {}
+    }
+
+    async fn stop_monitoring(&mut self) -> Result<()> {
+        match *self {}
+    }
+}
+
+/// Accept forwarders until aborted, reading each on its own task
+#[cfg(feature = "remote")]
+async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    policy: Arc<PeerPolicy>,
+    tx: mpsc::Sender<FileEvent>,
+) {
+    // Dropped with this task, which aborts every connection
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let acceptor = acceptor.clone();
+                    let policy = policy.clone();
+                    let tx = tx.clone();
+                    connections.spawn(async move {
+                        let received =
+                            receive(acceptor, &policy, stream, peer, tx);
+                        if let Err(e) = received.await {
+                            warn!("Forwarder {}: {:#}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept a forwarder: {}", e),
+            },
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+/// Authenticate one forwarder, then read its events
+#[cfg(feature = "remote")]
+async fn receive(
+    acceptor: TlsAcceptor,
+    policy: &PeerPolicy,
+    stream: TcpStream,
+    peer: SocketAddr,
+    tx: mpsc::Sender<FileEvent>,
+) -> Result<()> {
+    let stream = acceptor
+        .accept(stream)
+        .await
+        .context("TLS handshake failed")?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    (&mut reader)
+        .take(MAX_LINE_BYTES)
+        .read_line(&mut line)
+        .await
+        .context("Failed to read hello")?;
+    let hello: Hello =
+        serde_json::from_str(&line).context("Invalid hello line")?;
+    if hello.version != FORWARD_PROTOCOL_VERSION {
+        return Err(anyhow!(
+            "Protocol version {} is not supported (expected {})",
+            hello.version,
+            FORWARD_PROTOCOL_VERSION
+        ));
+    }
+    let certs = reader.get_ref().get_ref().1.peer_certificates();
+    let identity = policy
+        .authenticate(certs, &hello)
+        .with_context(|| format!("Rejected {}", hello.host))?;
+    info!("Forwarder {} connected from {}", hello.host, peer);
+
+    loop {
+        line.clear();
+        let read = (&mut reader)
+            .take(MAX_LINE_BYTES)
+            .read_line(&mut line)
+            .await?;
+        // A line cut off by a dropped connection is sent again in full
+        // on the next one
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        match decode_event(&line, &hello.host, identity.as_deref()) {
+            Ok(event) => {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("Skipping event from {}: {:#}", hello.host, e),
+        }
+    }
+    info!("Forwarder {} disconnected", hello.host);
+    Ok(())
+}
+
+/// Decode a forwarded event and tag it with its host and peer
+///
+/// The peer tag always comes from the certificate the connection
+/// authenticated with, never from the forwarder, so an event claiming a
+/// different peer, or any peer when the connection has no certificate
+/// identity, is rejected.
+///
+/// # Arguments
+/// * `line` - JSON line sent by `fw forward`
+/// * `host` - Host named in the connection's hello line
+/// * `peer` - Certificate name the connection authenticated as, if any
+///
+/// # Returns
+/// * `Result<FileEvent>` - Event tagged with the host and peer, or error
+///   if the line isn't an event or claims another peer
+#[cfg(feature = "remote")]
+pub fn decode_event(
+    line: &str,
+    host: &str,
+    peer: Option<&str>,
+) -> Result<FileEvent> {
+    let mut event: FileEvent =
+        serde_json::from_str(line).context("Invalid event")?;
+    if let Some(claimed) = event.tags.get(PEER_TAG) {
+        if peer != Some(claimed.as_str()) {
+            return Err(anyhow!(
+                "Event claims peer {} but the connection authenticated as {}",
+                claimed,
+                peer.unwrap_or("no one")
+            ));
+        }
+    }
+    event.tags.insert(HOST_TAG.to_string(), host.to_string());
+    if let Some(peer) = peer {
+        event.tags.insert(PEER_TAG.to_string(), peer.to_string());
+    }
+    Ok(event)
+}
+
+#[cfg(all(test, feature = "remote"))]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::fanout::EventSink;
+    use crate::file_event::FileAction;
+    use crate::forward::{ForwardConfig, ForwardConnection, ForwardSink};
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_forwarded_events_arrive_tagged_with_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let certified =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                .unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+
+        let mut monitor = RemoteMonitor::new(&RemoteConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            cert: cert.clone(),
+            key,
+            client_ca: None,
+            allowlist: None,
+            tokens: None,
+        })
+        .unwrap();
+        let mut rx = monitor.start_monitoring().await.unwrap();
+        let addr = monitor.local_addr().unwrap();
+
+        let config = ForwardConfig::new(
+            &addr.to_string(),
+            cert,
+            None,
+            Some("localhost".to_string()),
+            Some("web1".to_string()),
+        )
+        .unwrap();
+        let mut event = FileEvent::new(
+            "/etc/app.conf".to_string(),
+            "vi".to_string(),
+            FileAction::ModeChanged { mode: 0o600 },
+            42,
+            SystemClock.now(),
+        )
+        .with_ids(1000, 1000);
+        event.fs_type = Some("ext4".to_string());
+        let sent = event.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = ForwardConnection::new(config).unwrap();
+            let mut sink = ForwardSink::new(connection);
+            sink.write_event(&sent).unwrap();
+        })
+        .await
+        .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.file_path, event.file_path);
+        assert_eq!(received.action, event.action);
+        assert_eq!(received.timestamp, event.timestamp);
+        assert_eq!(received.uid, Some(1000));
+        assert_eq!(received.fs_type.as_deref(), Some("ext4"));
+        assert_eq!(
+            received.tags.get(HOST_TAG).map(String::as_str),
+            Some("web1")
+        );
+
+        monitor.stop_monitoring().await.unwrap();
+        assert!(rx.recv().await.is_none());
+        assert!(decode_event("not json", "web1", None).is_err());
+    }
+
+    #[test]
+    fn test_peer_tag_comes_from_the_certificate() {
+        let mut event = FileEvent::new(
+            "/etc/passwd".to_string(),
+            "vi".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+        let line = serde_json::to_string(&event).unwrap();
+        let decoded = decode_event(&line, "web1", Some("web1.example"));
+        assert_eq!(decoded.unwrap().tags[PEER_TAG], "web1.example");
+
+        // A forwarder can't claim someone else's certificate
+        event
+            .tags
+            .insert(PEER_TAG.to_string(), "db1.example".to_string());
+        let line = serde_json::to_string(&event).unwrap();
+        assert!(decode_event(&line, "web1", Some("web1.example")).is_err());
+        assert!(decode_event(&line, "web1", None).is_err());
+        assert!(decode_event(&line, "db1", Some("db1.example")).is_ok());
+    }
+
+    /// Issue a certificate for `name` from `ca`, returning PEM files
+    fn issue(
+        ca: &rcgen::Issuer<'_, rcgen::KeyPair>,
+        name: &str,
+        dir: &Path,
+    ) -> (PathBuf, PathBuf) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![name.to_string()])
+            .unwrap()
+            .signed_by(&key, ca)
+            .unwrap();
+        let (cert_path, key_path) = (
+            dir.join(format!("{}.pem", name)),
+            dir.join(format!("{}.key", name)),
+        );
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[tokio::test]
+    async fn test_only_allowlisted_forwarders_with_tokens_are_heard() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec![]).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::CertifiedIssuer::self_signed(
+            params,
+            rcgen::KeyPair::generate().unwrap(),
+        )
+        .unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        let (cert, key) = issue(&ca, "localhost", dir.path());
+        let allowlist = dir.path().join("allowlist");
+        std::fs::write(&allowlist, "web1.example\n").unwrap();
+        let tokens = dir.path().join("tokens");
+        std::fs::write(&tokens, "web1 s3cret\nevil s3cret\n").unwrap();
+
+        let mut monitor = RemoteMonitor::new(&RemoteConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            cert,
+            key,
+            client_ca: Some(ca_path.clone()),
+            allowlist: Some(allowlist),
+            tokens: Some(tokens),
+        })
+        .unwrap();
+        let mut rx = monitor.start_monitoring().await.unwrap();
+        let addr = monitor.local_addr().unwrap().to_string();
+
+        // Same CA and a valid token, but not an allowlisted certificate,
+        // then the allowlisted one
+        let forwarders = [("evil", "evil.example"), ("web1", "web1.example")];
+        for (host, name) in forwarders {
+            let mut config = ForwardConfig::new(
+                &addr,
+                ca_path.clone(),
+                Some(issue(&ca, name, dir.path())),
+                Some("localhost".to_string()),
+                Some(host.to_string()),
+            )
+            .unwrap();
+            config.token = Some("s3cret".to_string());
+            let event = FileEvent::new(
+                format!("/srv/{}", host),
+                "vi".to_string(),
+                FileAction::Opened,
+                7,
+                SystemClock.now(),
+            );
+            tokio::task::spawn_blocking(move || {
+                let connection = ForwardConnection::new(config).unwrap();
+                ForwardSink::new(connection).write_event(&event).unwrap();
+            })
+            .await
+            .unwrap();
+        }
+
+        let received = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.file_path, "/srv/web1");
+        assert_eq!(received.tags.get(HOST_TAG).unwrap(), "web1");
+        assert_eq!(received.tags.get(PEER_TAG).unwrap(), "web1.example");
+        monitor.stop_monitoring().await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,396 @@
+//! Rename Chain module
+//!
+//! Recognizes atomic saves: editors and build tools write a temporary
+//! file and rename it over the real one, so the write itself is only ever
+//! seen on the temporary name. The correlator remembers files recently
+//! opened for writing and, when one of them is renamed, reports the
+//! rename as "modified via atomic rename" on the final path with the
+//! temporary name it was written under. A rename is matched by its old
+//! path, or failing that by the device and inode now found at the new
+//! path, which still identify the file that was written. Relative paths
+//! are made absolute with the process's working directory, read from
+//! `/proc` once per process and kept as long as a written file is.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use crate::file_event::{FileAction, FileEvent, FileId};
+
+/// How long after its open a written file can still be renamed into place
+pub const CHAIN_TTL_SECS: i64 = 60;
+
+/// Written files remembered at once; the oldest is forgotten beyond this
+pub const MAX_TRACKED_WRITES: usize = 4096;
+
+/// A file opened for writing and not yet renamed
+#[derive(Debug, Clone)]
+struct Written {
+    /// Path the file was first written under, kept across renames
+    origin: String,
+    /// Device and inode of the file, if the kernel captured them
+    file_id: Option<FileId>,
+    /// When it was opened, or last renamed
+    at: DateTime<Utc>,
+}
+
+/// Working directory of a process, as read from `/proc`
+#[derive(Debug, Clone)]
+struct Cwd {
+    /// Directory, or None if the process was already gone
+    dir: Option<PathBuf>,
+    /// When it was read
+    at: DateTime<Utc>,
+}
+
+/// Turns renames of freshly written files into atomic save events
+#[derive(Debug)]
+pub struct RenameCorrelator {
+    /// Written files by absolute path
+    written: HashMap<String, Written>,
+    /// Path of each written file by identity
+    by_id: HashMap<FileId, String>,
+    /// Working directories by process ID, read once and kept for the
+    /// TTL, so a directory changed since is only seen once it expires
+    cwds: HashMap<u32, Cwd>,
+    /// How long a written file is remembered
+    ttl: Duration,
+    /// Most written files remembered at once
+    capacity: usize,
+}
+
+impl Default for RenameCorrelator {
+    fn default() -> Self {
+        Self::new(Duration::seconds(CHAIN_TTL_SECS), MAX_TRACKED_WRITES)
+    }
+}
+
+impl RenameCorrelator {
+    /// Create a correlator remembering nothing yet
+    ///
+    /// # Arguments
+    /// * `ttl` - How long after its open a file can be renamed into place
+    /// * `capacity` - Most written files remembered at once
+    ///
+    /// # Returns
+    /// * `RenameCorrelator` - Empty correlator
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            written: HashMap::new(),
+            by_id: HashMap::new(),
+            cwds: HashMap::new(),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// Note a write open, or rewrite the rename of a written file
+    ///
+    /// Renames of files not opened for writing within the TTL are left as
+    /// plain renames. A rewritten event keeps its final path and gets the
+    /// first temporary name of the chain in `renamed_from`.
+    ///
+    /// # Arguments
+    /// * `event` - Annotated event, updated in place
+    pub fn observe(&mut self, event: &mut FileEvent) {
+        match event.action {
+            FileAction::Opened if event.open_flags.is_some_and(is_write) => {
+                let path =
+                    self.absolute(event.pid, &event.file_path, event.timestamp);
+                let origin = path.clone();
+                self.track(path, origin, event.file_id, event.timestamp);
+            }
+            FileAction::Renamed => {
+                let Some(from) = &event.renamed_from else {
+                    return;
+                };
+                let from = self.absolute(event.pid, from, event.timestamp);
+                let to =
+                    self.absolute(event.pid, &event.file_path, event.timestamp);
+                let Some(written) = self.take(&from, &to, event.timestamp)
+                else {
+                    return;
+                };
+                event.action = FileAction::AtomicSave;
+                event.renamed_from = Some(written.origin.clone());
+                self.track(
+                    to,
+                    written.origin,
+                    written.file_id,
+                    event.timestamp,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Make a path relative to a process's working directory absolute
+    ///
+    /// # Arguments
+    /// * `pid` - Process that used the path
+    /// * `path` - Path as passed to the system call
+    /// * `now` - Time of the event
+    ///
+    /// # Returns
+    /// * `String` - Absolute path, or the path unchanged if it already is
+    ///   or the process is gone
+    fn absolute(&mut self, pid: u32, path: &str, now: DateTime<Utc>) -> String {
+        if path.starts_with('/') {
+            return path.to_string();
+        }
+        let cached = self
+            .cwds
+            .get(&pid)
+            .filter(|cwd| now - cwd.at <= self.ttl)
+            .map(|cwd| cwd.dir.clone());
+        let dir = match cached {
+            Some(dir) => dir,
+            None => {
+                if self.cwds.len() >= self.capacity {
+                    let ttl = self.ttl;
+                    self.cwds.retain(|_, cwd| now - cwd.at <= ttl);
+                }
+                if self.cwds.len() >= self.capacity {
+                    self.cwds.clear();
+                }
+                let dir = fs::read_link(format!("/proc/{}/cwd", pid)).ok();
+                let cwd = Cwd {
+                    dir: dir.clone(),
+                    at: now,
+                };
+                self.cwds.insert(pid, cwd);
+                dir
+            }
+        };
+        match dir {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        }
+    }
+
+    /// Remember a written file, forgetting expired and excess ones
+    fn track(
+        &mut self,
+        path: String,
+        origin: String,
+        file_id: Option<FileId>,
+        at: DateTime<Utc>,
+    ) {
+        if self.written.len() >= self.capacity {
+            self.expire(at);
+        }
+        if self.written.len() >= self.capacity {
+            let oldest = self
+                .written
+                .iter()
+                .min_by_key(|(_, written)| written.at)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.forget(&oldest);
+            }
+        }
+        self.forget(&path);
+        if let Some(file_id) = file_id {
+            self.by_id.insert(file_id, path.clone());
+        }
+        self.written.insert(
+            path,
+            Written {
+                origin,
+                file_id,
+                at,
+            },
+        );
+    }
+
+    /// Find and forget the written file a rename moved
+    ///
+    /// # Arguments
+    /// * `from` - Absolute old path of the rename
+    /// * `to` - Absolute new path, looked up by identity if `from` wasn't
+    ///   written
+    /// * `now` - Time of the rename
+    ///
+    /// # Returns
+    /// * `Option<Written>` - The written file, if it is recent enough
+    fn take(
+        &mut self,
+        from: &str,
+        to: &str,
+        now: DateTime<Utc>,
+    ) -> Option<Written> {
+        let path = match self.written.contains_key(from) {
+            true => from.to_string(),
+            false => self.by_id.get(&file_id_of(to)?)?.clone(),
+        };
+        let written = self.forget(&path)?;
+        (now - written.at <= self.ttl).then_some(written)
+    }
+
+    /// Drop a written file from both indexes
+    fn forget(&mut self, path: &str) -> Option<Written> {
+        let written = self.written.remove(path)?;
+        if let Some(file_id) = written.file_id {
+            if self.by_id.get(&file_id).is_some_and(|p| p == path) {
+                self.by_id.remove(&file_id);
+            }
+        }
+        Some(written)
+    }
+
+    /// Drop every written file older than the TTL
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let expired: Vec<String> = self
+            .written
+            .iter()
+            .filter(|(_, written)| now - written.at > self.ttl)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in expired {
+            self.forget(&path);
+        }
+    }
+}
+
+/// Check whether open flags allow changing the file
+///
+/// # Arguments
+/// * `flags` - Flags passed to open
+///
+/// # Returns
+/// * `bool` - True for write access, creation or truncation
+pub(crate) fn is_write(flags: u32) -> bool {
+    let flags = flags as i32;
+    flags & libc::O_ACCMODE != libc::O_RDONLY
+        || flags & (libc::O_CREAT | libc::O_TRUNC) != 0
+}
+
+/// Device and inode of the file at a path, in kernel encoding
+///
+/// # Arguments
+/// * `path` - Absolute path to look up
+///
+/// # Returns
+/// * `Option<FileId>` - Identity, or None if the path can't be read
+fn file_id_of(path: &str) -> Option<FileId> {
+    let metadata = fs::metadata(path).ok()?;
+    let dev = metadata.dev();
+    let dev = (u64::from(libc::major(dev)) << 20) | u64::from(libc::minor(dev));
+    FileId::from_raw(dev, metadata.ino())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+
+    fn open(path: &str, flags: i32, ino: u64) -> FileEvent {
+        let mut event = FileEvent::new(
+            path.to_string(),
+            "cargo".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        )
+        .with_file_id(FileId::from_raw(2049, ino));
+        event.open_flags = Some(flags as u32);
+        event
+    }
+
+    fn rename(from: &str, to: &str) -> FileEvent {
+        let mut event = FileEvent::new(
+            to.to_string(),
+            "cargo".to_string(),
+            FileAction::Renamed,
+            7,
+            SystemClock.now(),
+        );
+        event.renamed_from = Some(from.to_string());
+        event
+    }
+
+    #[test]
+    fn test_rename_of_written_file_is_atomic_save() {
+        let mut correlator = RenameCorrelator::default();
+        let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+        correlator.observe(&mut open("/src/.lib.rs.tmp", flags, 11));
+        correlator.observe(&mut open("/src/main.rs", libc::O_RDONLY, 12));
+
+        // A chain of renames keeps the name the data was written under
+        let mut hop = rename("/src/.lib.rs.tmp", "/src/.lib.rs.swp");
+        correlator.observe(&mut hop);
+        let mut save = rename("/src/.lib.rs.swp", "/src/lib.rs");
+        correlator.observe(&mut save);
+        assert_eq!(save.action, FileAction::AtomicSave);
+        assert_eq!(save.renamed_from.as_deref(), Some("/src/.lib.rs.tmp"));
+        assert!(save.to_string().ends_with(
+            "| modified via atomic rename | /src/lib.rs (from \
+             /src/.lib.rs.tmp)"
+        ));
+
+        // Files only read, or written too long ago, are plain renames
+        let mut moved = rename("/src/main.rs", "/src/bin.rs");
+        correlator.observe(&mut moved);
+        assert_eq!(moved.action, FileAction::Renamed);
+        assert_eq!(moved.renamed_from.as_deref(), Some("/src/main.rs"));
+
+        let mut stale = open("/src/a.tmp", flags, 13);
+        stale.timestamp -= Duration::seconds(CHAIN_TTL_SECS + 1);
+        correlator.observe(&mut stale);
+        let mut late = rename("/src/a.tmp", "/src/a");
+        correlator.observe(&mut late);
+        assert_eq!(late.action, FileAction::Renamed);
+    }
+
+    #[test]
+    fn test_rename_matched_by_inode() {
+        let dir = std::env::temp_dir()
+            .join(format!("fw-rename-chain-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("config.json");
+        fs::write(&target, b"{}").unwrap();
+        let target = target.to_string_lossy().into_owned();
+        let id = file_id_of(&target).unwrap();
+
+        // The temporary file was opened through a different name than
+        // the one the rename reports
+        let mut correlator = RenameCorrelator::default();
+        let mut written = open("/proc/self/fd/9", libc::O_RDWR, id.ino);
+        written.file_id = Some(id);
+        correlator.observe(&mut written);
+        let mut save = rename("tmpXYZ", &target);
+        save.pid = u32::MAX;
+        correlator.observe(&mut save);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(save.action, FileAction::AtomicSave);
+        assert_eq!(save.renamed_from.as_deref(), Some("/proc/self/fd/9"));
+    }
+
+    #[test]
+    fn test_working_directory_read_once_per_process() {
+        let mut correlator = RenameCorrelator::default();
+        let now = Utc::now();
+        let pid = std::process::id();
+        let own = std::env::current_dir().unwrap();
+        assert_eq!(
+            correlator.absolute(pid, "a.tmp", now),
+            own.join("a.tmp").to_string_lossy()
+        );
+
+        // Later paths use the directory read first, until it expires
+        correlator.cwds.get_mut(&pid).unwrap().dir = Some("/srv".into());
+        assert_eq!(correlator.absolute(pid, "b.tmp", now), "/srv/b.tmp");
+        let later = now + Duration::seconds(CHAIN_TTL_SECS + 1);
+        assert_eq!(
+            correlator.absolute(pid, "b.tmp", later),
+            own.join("b.tmp").to_string_lossy()
+        );
+
+        // Processes already gone are remembered as such
+        assert_eq!(correlator.absolute(u32::MAX, "c.tmp", now), "c.tmp");
+        assert!(correlator.cwds[&u32::MAX].dir.is_none());
+    }
+}
@@ -0,0 +1,558 @@
+//! Report module
+//!
+//! Implements `fw report`, which turns a capture of `fw collect` output
+//! (e.g. `fw collect 2> capture.log`) into a shareable summary: the most
+//! active processes, the files changed by the most processes, and a
+//! timeline of access to sensitive files. Lines that are not event lines,
+//! such as the banner printed at startup, are skipped, and gzip or zstd
+//! captures are decompressed on the fly.
+
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::compression::read_capture;
+use crate::path_assembler::unescape_path;
+
+/// Number of entries shown in each "top" table
+pub const REPORT_TOP_N: usize = 10;
+
+/// Path prefixes whose access is listed in the sensitive-file timeline
+pub const SENSITIVE_PREFIXES: &[&str] = &[
+    "/etc/shadow",
+    "/etc/gshadow",
+    "/etc/sudoers",
+    "/etc/ssh/",
+    "/root/.ssh/",
+    "/etc/passwd",
+    "/etc/group",
+];
+
+/// Actions that change a file rather than just open or close it
+const WRITE_ACTIONS: &[&str] = &[
+    "truncate",
+    "chmod",
+    "chown",
+    "setxattr",
+    "removexattr",
+    "linked",
+    "symlinked",
+    "renamed",
+    // "modified via atomic rename"
+    "modified",
+    "write",
+];
+
+/// Output format of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Markdown document
+    Md,
+    /// Standalone HTML page
+    Html,
+    /// JSON object for further processing
+    Json,
+}
+
+/// One event read back from a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedEvent {
+    /// Timestamp as printed by `fw collect`
+    pub timestamp: String,
+    /// Name of the program
+    pub program: String,
+    /// Process ID
+    pub pid: u32,
+    /// User name or uid, if the capture resolved it
+    pub user: Option<String>,
+    /// Group name or gid, if the capture resolved it
+    pub group: Option<String>,
+    /// Action with its details (e.g. "chmod 0644")
+    pub action: String,
+    /// Path of the file, escaped if it is not valid UTF-8
+    pub path: String,
+    /// Exact bytes of a path that is not valid UTF-8
+    pub raw_path: Option<Vec<u8>>,
+    /// Severity label, if above info
+    pub severity: Option<String>,
+}
+
+impl CapturedEvent {
+    /// Parse an event line printed by `fw collect`
+    ///
+    /// # Arguments
+    /// * `line` - One line of captured output
+    ///
+    /// # Returns
+    /// * `Option<CapturedEvent>` - Event, or None for non-event lines
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, " | ");
+        let timestamp = fields.next()?.trim();
+        let (program, process) = fields.next()?.rsplit_once(" (")?;
+        let process = process.strip_suffix(')')?;
+        // Resolved ids follow the pid as ", user:group"
+        let (pid, owner) = match process.split_once(", ") {
+            Some((pid, owner)) => (pid, Some(owner)),
+            None => (process, None),
+        };
+        let pid = pid.parse().ok()?;
+        let (user, group) = match owner.map(|o| o.split_once(':')) {
+            Some(Some((user, group))) => {
+                (Some(user.to_string()), Some(group.to_string()))
+            }
+            Some(None) => (owner.map(str::to_string), None),
+            None => (None, None),
+        };
+        let action = fields.next()?;
+        let rest = fields.next()?;
+        // Drop the annotations appended after the path
+        let end = [
+            " -> ",
+            " (from ",
+            " (truncated)",
+            " (non-utf8)",
+            " [",
+            " <",
+            " !",
+            " {",
+        ]
+        .iter()
+        .filter_map(|marker| rest.find(marker))
+        .min()
+        .unwrap_or(rest.len());
+        let path = &rest[..end];
+        let raw_path = rest[end..]
+            .contains(" (non-utf8)")
+            .then(|| unescape_path(path));
+        let severity = rest[end..]
+            .split_once(" !")
+            .and_then(|(_, marked)| marked.split_whitespace().next())
+            .map(str::to_string);
+        if !timestamp.ends_with("UTC") || !path.starts_with('/') {
+            return None;
+        }
+        Some(Self {
+            timestamp: timestamp.to_string(),
+            program: program.to_string(),
+            pid,
+            user,
+            group,
+            action: action.to_string(),
+            path: path.to_string(),
+            raw_path,
+            severity,
+        })
+    }
+
+    /// Check whether the event changed the file
+    ///
+    /// # Returns
+    /// * `bool` - True for truncation, metadata, xattr, link, rename and
+    ///   write events
+    pub fn is_write(&self) -> bool {
+        let verb = self.action.split_whitespace().next().unwrap_or("");
+        WRITE_ACTIONS.contains(&verb)
+    }
+
+    /// Check whether the event touched a sensitive file
+    ///
+    /// # Returns
+    /// * `bool` - True if the path is under a `SENSITIVE_PREFIXES` entry
+    pub fn is_sensitive(&self) -> bool {
+        SENSITIVE_PREFIXES
+            .iter()
+            .any(|prefix| self.path.starts_with(prefix))
+    }
+}
+
+/// Aggregations computed from a capture
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Number of events read
+    pub total_events: u64,
+    /// Programs with the most events, most active first
+    pub top_processes: Vec<(String, u64)>,
+    /// Files changed by the most distinct processes, with that count
+    pub top_written_files: Vec<(String, usize)>,
+    /// Every access to a sensitive file, in capture order
+    pub sensitive_timeline: Vec<CapturedEvent>,
+}
+
+impl Report {
+    /// Compute the report for a sequence of events
+    ///
+    /// # Arguments
+    /// * `events` - Events in capture order
+    ///
+    /// # Returns
+    /// * `Report` - Aggregated report
+    pub fn from_events(
+        events: impl IntoIterator<Item = CapturedEvent>,
+    ) -> Self {
+        let mut report = Report::default();
+        let mut per_program: HashMap<String, u64> = HashMap::new();
+        let mut writers: HashMap<String, BTreeSet<u32>> = HashMap::new();
+
+        for event in events {
+            report.total_events += 1;
+            *per_program.entry(event.program.clone()).or_default() += 1;
+            if event.is_write() {
+                writers
+                    .entry(event.path.clone())
+                    .or_default()
+                    .insert(event.pid);
+            }
+            if event.is_sensitive() {
+                report.sensitive_timeline.push(event);
+            }
+        }
+
+        report.top_processes = top_n(per_program.into_iter().collect());
+        report.top_written_files = top_n(
+            writers
+                .into_iter()
+                .map(|(path, pids)| (path, pids.len()))
+                .collect(),
+        );
+        report
+    }
+
+    /// Render the report as a document
+    ///
+    /// # Arguments
+    /// * `format` - Output format
+    ///
+    /// # Returns
+    /// * `String` - Rendered document
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Md => self.render_markdown(),
+            ReportFormat::Html => self.render_html(),
+            ReportFormat::Json => self.render_json(),
+        }
+    }
+
+    /// Render as Markdown
+    fn render_markdown(&self) -> String {
+        let mut out = String::from("# File activity report\n\n");
+        let _ = writeln!(out, "Events analysed: {}\n", self.total_events);
+        out.push_str("## Top processes\n\n| Program | Events |\n|---|---|\n");
+        for (program, count) in &self.top_processes {
+            let _ = writeln!(out, "| {} | {} |", program, count);
+        }
+        out.push_str(
+            "\n## Top files by writers\n\n| File | Writers |\n|---|---|\n",
+        );
+        for (path, writers) in &self.top_written_files {
+            let _ = writeln!(out, "| {} | {} |", path, writers);
+        }
+        out.push_str(
+            "\n## Sensitive file access\n\n\
+             | Time | Program (pid) | Action | File |\n|---|---|---|---|\n",
+        );
+        for e in &self.sensitive_timeline {
+            let _ = writeln!(
+                out,
+                "| {} | {} ({}) | {} | {} |",
+                e.timestamp, e.program, e.pid, e.action, e.path
+            );
+        }
+        out
+    }
+
+    /// Render as a standalone HTML page
+    fn render_html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <title>File activity report</title></head><body>\n\
+             <h1>File activity report</h1>\n",
+        );
+        let _ = writeln!(out, "<p>Events analysed: {}</p>", self.total_events);
+        let rows = |rows: Vec<Vec<String>>| -> String {
+            rows.iter()
+                .map(|cells| {
+                    let cells: String = cells
+                        .iter()
+                        .map(|c| format!("<td>{}</td>", html_escape(c)))
+                        .collect();
+                    format!("<tr>{}</tr>\n", cells)
+                })
+                .collect()
+        };
+        let _ = write!(
+            out,
+            "<h2>Top processes</h2>\n<table>\
+             <tr><th>Program</th><th>Events</th></tr>\n{}</table>\n",
+            rows(
+                self.top_processes
+                    .iter()
+                    .map(|(p, c)| vec![p.clone(), c.to_string()])
+                    .collect()
+            )
+        );
+        let _ = write!(
+            out,
+            "<h2>Top files by writers</h2>\n<table>\
+             <tr><th>File</th><th>Writers</th></tr>\n{}</table>\n",
+            rows(
+                self.top_written_files
+                    .iter()
+                    .map(|(p, w)| vec![p.clone(), w.to_string()])
+                    .collect()
+            )
+        );
+        let _ = write!(
+            out,
+            "<h2>Sensitive file access</h2>\n<table><tr><th>Time</th>\
+             <th>Program (pid)</th><th>Action</th><th>File</th></tr>\n{}\
+             </table>\n",
+            rows(
+                self.sensitive_timeline
+                    .iter()
+                    .map(|e| vec![
+                        e.timestamp.clone(),
+                        format!("{} ({})", e.program, e.pid),
+                        e.action.clone(),
+                        e.path.clone(),
+                    ])
+                    .collect()
+            )
+        );
+        out.push_str("</body></html>\n");
+        out
+    }
+
+    /// Render as a JSON object
+    fn render_json(&self) -> String {
+        let processes: Vec<String> = self
+            .top_processes
+            .iter()
+            .map(|(p, c)| {
+                format!("{{\"program\":{},\"events\":{}}}", json_string(p), c)
+            })
+            .collect();
+        let files: Vec<String> = self
+            .top_written_files
+            .iter()
+            .map(|(p, w)| {
+                format!("{{\"path\":{},\"writers\":{}}}", json_string(p), w)
+            })
+            .collect();
+        let timeline: Vec<String> = self
+            .sensitive_timeline
+            .iter()
+            .map(|e| {
+                format!(
+                    "{{\"timestamp\":{},\"program\":{},\"pid\":{},\
+                     \"user\":{},\"group\":{},\"action\":{},\"path\":{},\
+                     \"path_base64\":{},\"severity\":{}}}",
+                    json_string(&e.timestamp),
+                    json_string(&e.program),
+                    e.pid,
+                    json_optional(&e.user),
+                    json_optional(&e.group),
+                    json_string(&e.action),
+                    json_string(&e.path),
+                    e.raw_path.as_deref().map_or_else(
+                        || "null".to_string(),
+                        |raw| json_string(&base64(raw))
+                    ),
+                    json_optional(&e.severity)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"total_events\":{},\"top_processes\":[{}],\
+             \"top_written_files\":[{}],\"sensitive_timeline\":[{}]}}\n",
+            self.total_events,
+            processes.join(","),
+            files.join(","),
+            timeline.join(",")
+        )
+    }
+}
+
+/// Sort counted entries, largest first, and keep the top `REPORT_TOP_N`
+///
+/// # Arguments
+/// * `entries` - Names with their counts
+///
+/// # Returns
+/// * `Vec<(String, N)>` - Largest entries, ties ordered by name
+fn top_n<N: Ord + Copy>(mut entries: Vec<(String, N)>) -> Vec<(String, N)> {
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(REPORT_TOP_N);
+    entries
+}
+
+/// Escape text for inclusion in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Quote optional text as a JSON string, or `null` if absent
+fn json_optional(text: &Option<String>) -> String {
+    text.as_deref()
+        .map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Encode bytes as standard base64 with padding
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Quote and escape text as a JSON string
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Run `fw report`
+///
+/// # Arguments
+/// * `from` - Capture of `fw collect` output
+/// * `format` - Output format
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_report(from: &Path, format: ReportFormat) -> Result<()> {
+    if matches!(
+        from.extension().and_then(|e| e.to_str()),
+        Some("sqlite" | "db")
+    ) {
+        return Err(anyhow!(
+            "SQLite captures are not supported; pass a file holding \
+             `fw collect` output"
+        ));
+    }
+    let text = read_capture(from)?;
+    let report =
+        Report::from_events(text.lines().filter_map(CapturedEvent::parse));
+    print!("{}", report.render(format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPTURE: &str = "\
+Monitoring all file operations
+2024-05-01 10:00:00 UTC | sshd (10, root:shadow) | opened (1.2ms) | /etc/shadow [ext4] <8:1/5> !warning
+2024-05-01 10:00:01 UTC | vim (20) | truncate 0 | /home/a/notes.md [ext4]
+2024-05-01 10:00:02 UTC | sed (21) | chmod 0644 | /home/a/notes.md
+2024-05-01 10:00:03 UTC | vim (20) | closed | /home/a/notes.md
+2024-05-01 10:00:04 UTC | ln (30) | symlinked | /usr/bin/py -> python3
+";
+
+    fn report() -> Report {
+        Report::from_events(CAPTURE.lines().filter_map(CapturedEvent::parse))
+    }
+
+    #[test]
+    fn test_parse_strips_annotations() {
+        let event = CapturedEvent::parse(
+            "2024-05-01 10:00:00 UTC | sshd (10) | opened (1.2ms) | \
+             /etc/shadow [ext4] <8:1/5>",
+        )
+        .unwrap();
+        assert_eq!(event.program, "sshd");
+        assert_eq!(event.pid, 10);
+        assert_eq!(event.action, "opened (1.2ms)");
+        assert_eq!(event.path, "/etc/shadow");
+        assert_eq!(event.user, None);
+        assert_eq!(event.severity, None);
+        assert!(
+            CapturedEvent::parse("Monitoring all file operations").is_none()
+        );
+    }
+
+    #[test]
+    fn test_report_aggregations() {
+        let report = report();
+        assert_eq!(report.total_events, 5);
+        assert_eq!(report.top_processes[0], ("vim".to_string(), 2));
+        assert_eq!(
+            report.top_written_files[0],
+            ("/home/a/notes.md".to_string(), 2)
+        );
+        assert_eq!(report.sensitive_timeline.len(), 1);
+        assert_eq!(report.sensitive_timeline[0].program, "sshd");
+        assert_eq!(report.sensitive_timeline[0].pid, 10);
+        assert_eq!(
+            report.sensitive_timeline[0].group,
+            Some("shadow".to_string())
+        );
+        assert_eq!(
+            report.sensitive_timeline[0].severity,
+            Some("warning".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let report = report();
+        assert!(report
+            .render(ReportFormat::Md)
+            .contains("| /home/a/notes.md | 2 |"));
+        assert!(report
+            .render(ReportFormat::Html)
+            .contains("<td>sshd (10)</td>"));
+        let json = report.render(ReportFormat::Json);
+        assert!(json.starts_with("{\"total_events\":5,"));
+        assert!(json.contains("\"pid\":10,\"user\":\"root\",\"group\""));
+        assert!(json.contains("{\"path\":\"/usr/bin/py\",\"writers\":1}"));
+        assert_eq!(json_string("a\"b\\"), "\"a\\\"b\\\\\"");
+    }
+
+    #[test]
+    fn test_non_utf8_path_round_trips() {
+        let event = CapturedEvent::parse(
+            "2024-05-01 10:00:00 UTC | cp (4) | opened | \
+             /etc/caf\\xe9.conf (non-utf8) [ext4]",
+        )
+        .unwrap();
+        assert_eq!(event.path, "/etc/caf\\xe9.conf");
+        assert_eq!(event.raw_path.as_deref(), Some(&b"/etc/caf\xe9.conf"[..]));
+
+        let mut report = Report::default();
+        report.sensitive_timeline.push(event);
+        let json = report.render(ReportFormat::Json);
+        assert!(json.contains("\"path_base64\":\"L2V0Yy9jYWbpLmNvbmY=\""));
+        assert_eq!(base64(b"fw"), "Znc=");
+        assert_eq!(base64(b"fw!"), "Znch");
+    }
+}
@@ -0,0 +1,333 @@
+//! Retention module
+//!
+//! Implements `fw prune`, which enforces a retention policy on a directory
+//! of recordings (e.g. one `fw record` file per night). Files older than
+//! the maximum age are deleted, then the oldest remaining files until the
+//! directory fits the size limit. Whole files are removed, so the
+//! checkpoints and signatures of the recordings that are kept stay valid.
+//! `fw record` can apply the same policy to the directory it records in
+//! while it runs, leaving the recording in progress alone.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::glob::GlobSet;
+
+/// How long and how much activity data may be kept
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Delete files last written longer ago than this
+    pub max_age: Option<Duration>,
+    /// Delete the oldest files while the total is larger than this
+    pub max_bytes: Option<u64>,
+}
+
+/// Retention applied periodically while recording
+#[derive(Debug, Clone)]
+pub struct PruneConfig {
+    /// Directory holding the recordings
+    pub dir: PathBuf,
+    /// Name globs of the files the policy applies to; every file if None
+    pub names: Option<GlobSet>,
+    /// Maximum age and size
+    pub policy: RetentionPolicy,
+    /// Time between prunes
+    pub interval: Duration,
+    /// Names of files never removed, such as the recording being written
+    pub keep: Vec<OsString>,
+}
+
+/// A file a policy applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// Location of the file
+    pub path: PathBuf,
+    /// Size in bytes
+    pub bytes: u64,
+    /// When it was last written
+    pub modified: SystemTime,
+}
+
+/// What a prune removed, or would remove
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// Files removed, oldest first
+    pub removed: Vec<StoredFile>,
+    /// Files kept
+    pub kept: usize,
+    /// Bytes kept
+    pub kept_bytes: u64,
+}
+
+impl PruneReport {
+    /// Total bytes removed
+    ///
+    /// # Returns
+    /// * `u64` - Sum of the sizes of the removed files
+    pub fn removed_bytes(&self) -> u64 {
+        self.removed.iter().map(|file| file.bytes).sum()
+    }
+}
+
+impl RetentionPolicy {
+    /// Decide which files to remove
+    ///
+    /// The most recently written file is never removed for size, since
+    /// it may be a recording still in progress.
+    ///
+    /// # Arguments
+    /// * `files` - Files in the store
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    /// * `PruneReport` - Files to remove and what stays
+    pub fn plan(
+        &self,
+        mut files: Vec<StoredFile>,
+        now: SystemTime,
+    ) -> PruneReport {
+        files.sort_by_key(|file| file.modified);
+        let mut report = PruneReport::default();
+        let expired = |file: &StoredFile| {
+            self.max_age.is_some_and(|max_age| {
+                now.duration_since(file.modified)
+                    .is_ok_and(|age| age > max_age)
+            })
+        };
+        let (removed, mut kept): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(expired);
+        report.removed = removed;
+
+        let mut total: u64 = kept.iter().map(|file| file.bytes).sum();
+        if let Some(max_bytes) = self.max_bytes {
+            let mut oldest = 0;
+            while total > max_bytes && oldest + 1 < kept.len() {
+                total -= kept[oldest].bytes;
+                oldest += 1;
+            }
+            report.removed.extend(kept.drain(..oldest));
+        }
+        report.removed.sort_by_key(|file| file.modified);
+        report.kept = kept.len();
+        report.kept_bytes = total;
+        report
+    }
+}
+
+/// List the regular files in a directory whose names match
+///
+/// # Arguments
+/// * `dir` - Directory holding the store
+/// * `names` - Name globs files must match; every file if None
+///
+/// # Returns
+/// * `Result<Vec<StoredFile>>` - Matching files, or error if the
+///   directory can't be read
+pub fn list_files(
+    dir: &Path,
+    names: Option<&GlobSet>,
+) -> Result<Vec<StoredFile>> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry
+            .with_context(|| format!("Failed to read {}", dir.display()))?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let name = entry.file_name();
+        if names.is_some_and(|globs| !globs.matches(&name.to_string_lossy())) {
+            continue;
+        }
+        files.push(StoredFile {
+            path,
+            bytes: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+    Ok(files)
+}
+
+/// Apply a retention policy to a directory
+///
+/// # Arguments
+/// * `dir` - Directory holding recordings
+/// * `names` - Name globs of the files the policy applies to
+/// * `policy` - Maximum age and size
+/// * `keep` - Names of files left alone whatever their age
+/// * `dry_run` - Only report what would be removed
+///
+/// # Returns
+/// * `Result<PruneReport>` - What was removed, or error if a file can't
+///   be deleted
+pub fn prune(
+    dir: &Path,
+    names: Option<&GlobSet>,
+    policy: &RetentionPolicy,
+    keep: &[OsString],
+    dry_run: bool,
+) -> Result<PruneReport> {
+    let mut files = list_files(dir, names)?;
+    files.retain(|file| {
+        !keep.iter().any(|name| file.path.file_name() == Some(name))
+    });
+    let report = policy.plan(files, SystemTime::now());
+    for file in &report.removed {
+        if !dry_run {
+            fs::remove_file(&file.path).with_context(|| {
+                format!("Failed to remove {}", file.path.display())
+            })?;
+        }
+        info!("Removed {} ({} bytes)", file.path.display(), file.bytes);
+    }
+    Ok(report)
+}
+
+/// Apply a retention policy to a directory and print what was removed
+///
+/// # Arguments
+/// * `dir` - Directory holding recordings
+/// * `names` - Name globs of the files the policy applies to
+/// * `policy` - Maximum age and size
+/// * `dry_run` - Only report what would be removed
+///
+/// # Returns
+/// * `Result<PruneReport>` - What was removed, or error if a file can't
+///   be deleted
+pub fn run_prune(
+    dir: &Path,
+    names: Option<&GlobSet>,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<PruneReport> {
+    let report = prune(dir, names, policy, &[], dry_run)?;
+    println!(
+        "{} {} files ({} bytes); kept {} files ({} bytes)",
+        if dry_run { "Would remove" } else { "Removed" },
+        report.removed.len(),
+        report.removed_bytes(),
+        report.kept,
+        report.kept_bytes
+    );
+    Ok(report)
+}
+
+/// Prune on an interval until aborted
+///
+/// # Arguments
+/// * `config` - Directory, policy and interval
+pub async fn supervise(config: PruneConfig) {
+    let config = std::sync::Arc::new(config);
+    let mut tick = tokio::time::interval(config.interval);
+    loop {
+        tick.tick().await;
+        let config = config.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            prune(
+                &config.dir,
+                config.names.as_ref(),
+                &config.policy,
+                &config.keep,
+                false,
+            )
+        })
+        .await;
+        match result {
+            Ok(Ok(report)) if !report.removed.is_empty() => info!(
+                "Pruned {} files ({} bytes) from the recordings",
+                report.removed.len(),
+                report.removed_bytes()
+            ),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Failed to prune the recordings: {:#}", e),
+            Err(e) => warn!("Prune task failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(
+        name: &str,
+        bytes: u64,
+        days_old: u64,
+        now: SystemTime,
+    ) -> StoredFile {
+        StoredFile {
+            path: PathBuf::from(name),
+            bytes,
+            modified: now - Duration::from_secs(days_old * 86400),
+        }
+    }
+
+    #[test]
+    fn test_age_then_size_oldest_first() {
+        let now = SystemTime::now();
+        let files = vec![
+            file("today.log", 50, 0, now),
+            file("old.log", 10, 40, now),
+            file("week.log", 30, 7, now),
+            file("month.log", 20, 29, now),
+        ];
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(30 * 86400)),
+            max_bytes: Some(80),
+        };
+        let report = policy.plan(files.clone(), now);
+        let removed: Vec<_> = report
+            .removed
+            .iter()
+            .map(|f| f.path.to_str().unwrap())
+            .collect();
+        assert_eq!(removed, ["old.log", "month.log"]);
+        assert_eq!(report.removed_bytes(), 30);
+        assert_eq!((report.kept, report.kept_bytes), (2, 80));
+
+        // The newest file survives even when it alone is over the limit
+        let tiny = RetentionPolicy {
+            max_age: None,
+            max_bytes: Some(1),
+        };
+        let report = tiny.plan(files, now);
+        assert_eq!(report.kept, 1);
+        assert_eq!(report.kept_bytes, 50);
+    }
+
+    #[test]
+    fn test_prune_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.log"), "x").unwrap();
+        fs::write(dir.path().join("notes.txt"), "keep").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let logs = GlobSet::names(&["*.log".to_string()]).unwrap();
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::ZERO),
+            max_bytes: None,
+        };
+        std::thread::sleep(Duration::from_millis(10));
+
+        let report = run_prune(dir.path(), Some(&logs), &policy, true).unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(dir.path().join("a.log").exists());
+        run_prune(dir.path(), Some(&logs), &policy, false).unwrap();
+        assert!(!dir.path().join("a.log").exists());
+        assert!(dir.path().join("notes.txt").exists());
+
+        // The recording in progress is kept however old it is
+        let notes = dir.path().join("notes.txt");
+        let keep = [OsString::from("notes.txt")];
+        prune(dir.path(), None, &policy, &keep, false).unwrap();
+        assert!(notes.exists());
+        prune(dir.path(), None, &policy, &[], false).unwrap();
+        assert!(!notes.exists());
+    }
+}
@@ -0,0 +1,634 @@
+//! Rollup module
+//!
+//! Keeps time-bucketed counts next to a recording (`fw record --rollup`)
+//! so questions about long stretches of it don't have to read every
+//! event, and implements `fw trend`, which answers them. Each minute and
+//! hour becomes rows of a CSV file beside the recording, counting events
+//! per process, directory (two levels deep) and action:
+//!
+//! ```text
+//! resolution,start,process,dir,action,events
+//! minute,2024-01-01T12:00:00Z,nginx,/var/log,write,42
+//! hour,2024-01-01T12:00:00Z,nginx,/var/log,write,1730
+//! ```
+//!
+//! A bucket is written once it ends by the wall clock, checked every
+//! [`TICK_INTERVAL`](crate::fanout::TICK_INTERVAL), or when the recording
+//! stops, so the latest minute and hour show up when they are over.
+//! Events arriving after their bucket was written add rows of their own,
+//! which are summed on reading. `fw trend` counts raw events for ranges
+//! of up to an hour, which are exact to the last event, and reads the
+//! rollups for longer ones; those only hold whole buckets, so a range
+//! starting or ending inside one counts all of it.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::clock::{self, Clock};
+use crate::compression::capture_lines;
+use crate::diff::split_csv;
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::record::event_time;
+use crate::report::CapturedEvent;
+use crate::stats::{csv_row, dir_prefix, verb};
+
+/// Header row of a rollup file
+const HEADER: &str = "resolution,start,process,dir,action,events";
+
+/// Directory levels events are grouped by
+const DIR_DEPTH: usize = 2;
+
+/// Longest range `fw trend` counts from raw events
+const RAW_RANGE: TimeDelta = TimeDelta::hours(1);
+
+/// Longest range `fw trend` shows per minute unless told otherwise
+const MINUTE_RANGE: TimeDelta = TimeDelta::hours(6);
+
+/// Width of a rollup bucket
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum,
+)]
+pub enum Resolution {
+    /// One bucket per minute
+    Minute,
+    /// One bucket per hour
+    Hour,
+}
+
+impl Resolution {
+    /// Name used in rollup files
+    ///
+    /// # Returns
+    /// * `&'static str` - "minute" or "hour"
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+
+    /// Start of the bucket a time falls in
+    ///
+    /// # Arguments
+    /// * `time` - Time of an event
+    ///
+    /// # Returns
+    /// * `DateTime<Utc>` - Time rounded down to the minute or hour
+    pub fn bucket(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let width = match self {
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+        };
+        let start = time.timestamp().div_euclid(width) * width;
+        DateTime::from_timestamp(start, 0).unwrap_or(time)
+    }
+}
+
+/// Process, directory prefix and action an event is counted under
+type Group = (String, String, String);
+
+/// Group of an event as it is recorded
+fn group_of(event: &FileEvent) -> Group {
+    (
+        event.program_name.clone(),
+        dir_prefix(&event.file_path, DIR_DEPTH),
+        verb(&event.action.to_string()),
+    )
+}
+
+/// Rollup file kept beside a recording
+///
+/// # Arguments
+/// * `recording` - Recording file
+///
+/// # Returns
+/// * `PathBuf` - The recording's path with ".rollup.csv" appended
+pub fn rollup_path(recording: &Path) -> PathBuf {
+    let mut path = recording.as_os_str().to_owned();
+    path.push(".rollup.csv");
+    PathBuf::from(path)
+}
+
+/// Counts of the bucket being filled at one resolution
+#[derive(Debug)]
+struct Bucket {
+    /// Width of the bucket
+    resolution: Resolution,
+    /// Start of the bucket, None before the first event
+    start: Option<DateTime<Utc>>,
+    /// Events per group so far
+    counts: BTreeMap<Group, u64>,
+}
+
+impl Bucket {
+    fn new(resolution: Resolution) -> Self {
+        Self {
+            resolution,
+            start: None,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count an event, closing the bucket if the event is past it
+    ///
+    /// Events that arrive after their bucket was closed are counted in
+    /// the current one, or reopen theirs if none is being filled.
+    ///
+    /// # Arguments
+    /// * `time` - Time of the event
+    /// * `group` - Group of the event
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Rows of the bucket this closed, if any
+    fn add(&mut self, time: DateTime<Utc>, group: Group) -> Vec<String> {
+        let rows = self.close_before(time);
+        self.start.get_or_insert(self.resolution.bucket(time));
+        *self.counts.entry(group).or_default() += 1;
+        rows
+    }
+
+    /// Close the bucket if it ended before a time
+    ///
+    /// # Arguments
+    /// * `time` - Time of an event, or the current time
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Rows of the bucket if it ended, else none
+    fn close_before(&mut self, time: DateTime<Utc>) -> Vec<String> {
+        match self.start {
+            Some(start) if self.resolution.bucket(time) > start => self.close(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Rows of the bucket, leaving it empty
+    fn close(&mut self) -> Vec<String> {
+        let Some(start) = self.start.take() else {
+            return Vec::new();
+        };
+        let start = start.to_rfc3339_opts(SecondsFormat::Secs, true);
+        std::mem::take(&mut self.counts)
+            .into_iter()
+            .map(|((process, dir, action), count)| {
+                csv_row(&[
+                    self.resolution.name().to_string(),
+                    start.clone(),
+                    process,
+                    dir,
+                    action,
+                    count.to_string(),
+                ])
+            })
+            .collect()
+    }
+}
+
+/// Sink that keeps per-minute and per-hour rollups of the events
+pub struct RollupSink<W> {
+    /// Destination for rollup rows
+    writer: W,
+    /// Minute and hour being filled
+    buckets: [Bucket; 2],
+    /// Time buckets are closed by while no events arrive
+    clock: Arc<dyn Clock>,
+}
+
+impl RollupSink<File> {
+    /// Open a rollup file to append to, writing its header if it's new
+    ///
+    /// # Arguments
+    /// * `path` - Rollup file
+    ///
+    /// # Returns
+    /// * `Result<RollupSink<File>>` - Sink, or error if the file can't be
+    ///   opened
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER).with_context(|| {
+                format!("Failed to write {}", path.display())
+            })?;
+        }
+        Ok(Self::new(file))
+    }
+}
+
+impl<W: Write + Send + 'static> RollupSink<W> {
+    /// Create a rollup sink writing rows without a header
+    ///
+    /// # Arguments
+    /// * `writer` - Destination for rollup rows
+    ///
+    /// # Returns
+    /// * `RollupSink<W>` - Sink with no buckets open
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buckets: [
+                Bucket::new(Resolution::Minute),
+                Bucket::new(Resolution::Hour),
+            ],
+            clock: clock::system(),
+        }
+    }
+
+    /// Close buckets by the time of a different clock
+    ///
+    /// # Arguments
+    /// * `clock` - Time source, e.g. a `MockClock` in tests
+    ///
+    /// # Returns
+    /// * `RollupSink<W>` - The sink using the clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn write_rows(&mut self, rows: Vec<String>) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        for row in rows {
+            writeln!(self.writer, "{}", row)
+                .context("Failed to write rollup")?;
+        }
+        // Closed buckets are what `fw trend` reads while recording goes on
+        self.writer.flush().context("Failed to flush rollup")
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for RollupSink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let group = group_of(event);
+        let rows: Vec<String> = self
+            .buckets
+            .iter_mut()
+            .flat_map(|bucket| bucket.add(event.timestamp, group.clone()))
+            .collect();
+        self.write_rows(rows)
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        let rows = self
+            .buckets
+            .iter_mut()
+            .flat_map(|bucket| bucket.close_before(now))
+            .collect();
+        self.write_rows(rows)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let rows = self.buckets.iter_mut().flat_map(Bucket::close).collect();
+        self.write_rows(rows)
+    }
+}
+
+/// Time range and bucket width of `fw trend`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TrendOptions {
+    /// Skip events before this time
+    pub from: Option<DateTime<Utc>>,
+    /// Skip events after this time
+    pub to: Option<DateTime<Utc>>,
+    /// Bucket width; per minute for ranges of up to six hours, per hour
+    /// for longer or open-ended ones if unset
+    pub resolution: Option<Resolution>,
+}
+
+impl TrendOptions {
+    /// Length of the range, None if it has no start
+    fn range(&self, now: DateTime<Utc>) -> Option<TimeDelta> {
+        self.from.map(|from| self.to.unwrap_or(now) - from)
+    }
+
+    /// Bucket width to show
+    fn resolution(&self, now: DateTime<Utc>) -> Resolution {
+        self.resolution.unwrap_or(match self.range(now) {
+            Some(range) if range <= MINUTE_RANGE => Resolution::Minute,
+            _ => Resolution::Hour,
+        })
+    }
+
+    /// Check whether the range is short enough to count raw events
+    fn prefers_raw(&self, now: DateTime<Utc>) -> bool {
+        self.range(now).is_some_and(|range| range <= RAW_RANGE)
+    }
+}
+
+/// Events per bucket and group
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trend {
+    /// Width of the buckets
+    resolution: Option<Resolution>,
+    /// Events per bucket start and group
+    counts: BTreeMap<(DateTime<Utc>, Group), u64>,
+}
+
+impl Trend {
+    /// Count the events of a recording or capture
+    ///
+    /// # Arguments
+    /// * `lines` - Lines of the recording
+    /// * `options` - Time range
+    /// * `resolution` - Bucket width
+    ///
+    /// # Returns
+    /// * `Trend` - Counts of the events inside the range
+    fn from_events<I, S>(
+        lines: I,
+        options: &TrendOptions,
+        resolution: Resolution,
+    ) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut trend = Self {
+            resolution: Some(resolution),
+            ..Self::default()
+        };
+        for line in lines {
+            let line = line.as_ref();
+            let (Some(event), Some(time)) =
+                (CapturedEvent::parse(line), event_time(line))
+            else {
+                continue;
+            };
+            if options.from.is_some_and(|from| time < from)
+                || options.to.is_some_and(|to| time > to)
+            {
+                continue;
+            }
+            let group = (
+                event.program,
+                dir_prefix(&event.path, DIR_DEPTH),
+                verb(&event.action),
+            );
+            *trend
+                .counts
+                .entry((resolution.bucket(time), group))
+                .or_default() += 1;
+        }
+        trend
+    }
+
+    /// Read the rows of a rollup file at one resolution
+    ///
+    /// Rollups can't be split within a bucket, so buckets are kept whole
+    /// if they overlap the range: a range starting or ending inside one
+    /// also counts its events outside the range.
+    ///
+    /// # Arguments
+    /// * `text` - Contents of the rollup file
+    /// * `options` - Time range
+    /// * `resolution` - Rows to read
+    ///
+    /// # Returns
+    /// * `Result<Trend>` - Counts, or error if a row can't be read
+    fn from_rollups(
+        text: &str,
+        options: &TrendOptions,
+        resolution: Resolution,
+    ) -> Result<Self> {
+        let mut trend = Self {
+            resolution: Some(resolution),
+            ..Self::default()
+        };
+        let first = options.from.map(|from| resolution.bucket(from));
+        for (number, line) in text.lines().enumerate() {
+            if line == HEADER || line.is_empty() {
+                continue;
+            }
+            let bad = || anyhow!("Invalid rollup row {}: {}", number + 1, line);
+            let [kind, start, process, dir, action, count]: [String; 6] =
+                split_csv(line).try_into().map_err(|_| bad())?;
+            if kind != resolution.name() {
+                continue;
+            }
+            let start: DateTime<Utc> = start.parse().map_err(|_| bad())?;
+            let count: u64 = count.parse().map_err(|_| bad())?;
+            if first.is_some_and(|first| start < first)
+                || options.to.is_some_and(|to| start > to)
+            {
+                continue;
+            }
+            *trend
+                .counts
+                .entry((start, (process, dir, action)))
+                .or_default() += count;
+        }
+        Ok(trend)
+    }
+
+    /// Render one line per bucket and group, oldest bucket first and
+    /// busiest group first within a bucket
+    ///
+    /// # Returns
+    /// * `String` - Table of counts
+    pub fn render(&self) -> String {
+        let format = match self.resolution {
+            Some(Resolution::Hour) => "%Y-%m-%d %H:00",
+            _ => "%Y-%m-%d %H:%M",
+        };
+        let mut rows: Vec<_> = self.counts.iter().collect();
+        rows.sort_by(|((a, _), a_count), ((b, _), b_count)| {
+            a.cmp(b).then_with(|| b_count.cmp(a_count))
+        });
+        let mut out = String::new();
+        for ((start, (process, dir, action)), count) in rows {
+            let _ = writeln!(
+                out,
+                "{} {:>8} | {} | {} | {}",
+                start.format(format),
+                count,
+                process,
+                dir,
+                action
+            );
+        }
+        out
+    }
+}
+
+/// Run `fw trend`
+///
+/// Ranges of up to an hour, and recordings without rollups, are counted
+/// from the raw events; longer ranges are read from the rollup file, in
+/// whole buckets. The source used is noted on stderr.
+///
+/// # Arguments
+/// * `recording` - Recording or capture, plain or compressed
+/// * `options` - Time range and bucket width
+///
+/// # Returns
+/// * `Result<()>` - Error if the recording or its rollups can't be read
+pub fn run_trend(recording: &Path, options: &TrendOptions) -> Result<()> {
+    let now = Utc::now();
+    let resolution = options.resolution(now);
+    let rollups = rollup_path(recording);
+    let trend = if options.prefers_raw(now) || !rollups.exists() {
+        eprintln!("per {}, from the raw events", resolution.name());
+        let lines = capture_lines(recording)?.collect::<Result<Vec<_>>>()?;
+        Trend::from_events(&lines, options, resolution)
+    } else {
+        eprintln!(
+            "per {}, from {}; {}s at the ends of the range are counted \
+             whole, and the latest is added once it ends",
+            resolution.name(),
+            rollups.display(),
+            resolution.name()
+        );
+        let text = fs::read_to_string(&rollups)
+            .with_context(|| format!("Failed to read {}", rollups.display()))?;
+        Trend::from_rollups(&text, options, resolution)?
+    };
+    print!("{}", trend.render());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn time(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    fn event(path: &str, at: &str) -> FileEvent {
+        let mut event = FileEvent::new(
+            path.to_string(),
+            "nginx".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        event.timestamp = time(at);
+        event
+    }
+
+    #[test]
+    fn test_rollups_match_raw_events() {
+        let events = [
+            event("/var/log/nginx/a.log", "2024-01-01T12:00:05Z"),
+            event("/var/log/nginx/b.log", "2024-01-01T12:00:59Z"),
+            event("/etc/nginx.conf", "2024-01-01T12:01:00Z"),
+            event("/var/log/c.log", "2024-01-01T13:30:00Z"),
+        ];
+        let mut sink = RollupSink::new(Vec::new());
+        for event in &events {
+            sink.write_event(event).unwrap();
+        }
+        sink.finish().unwrap();
+        let text = String::from_utf8(sink.writer).unwrap();
+        assert!(text
+            .contains("minute,2024-01-01T12:00:00Z,nginx,/var/log,opened,2\n"));
+        assert!(text
+            .contains("hour,2024-01-01T12:00:00Z,nginx,/var/log,opened,2\n"));
+        assert!(
+            text.contains("hour,2024-01-01T12:00:00Z,nginx,/etc,opened,1\n")
+        );
+
+        // The same events read back from their recorded lines
+        let lines: Vec<String> =
+            events.iter().map(ToString::to_string).collect();
+        let options = TrendOptions {
+            from: Some(time("2024-01-01T12:00:30Z")),
+            to: Some(time("2024-01-01T14:00:00Z")),
+            ..Default::default()
+        };
+        for resolution in [Resolution::Minute, Resolution::Hour] {
+            let raw = Trend::from_events(
+                &lines,
+                &TrendOptions::default(),
+                resolution,
+            );
+            let rolled = Trend::from_rollups(
+                &text,
+                &TrendOptions::default(),
+                resolution,
+            )
+            .unwrap();
+            assert_eq!(raw, rolled);
+            // A range keeps whole buckets from the rollups
+            let rolled =
+                Trend::from_rollups(&text, &options, resolution).unwrap();
+            assert_eq!(rolled.counts.values().sum::<u64>(), 4);
+        }
+        let minutes = Trend::from_events(&lines, &options, Resolution::Minute);
+        assert_eq!(minutes.counts.values().sum::<u64>(), 3);
+        assert_eq!(
+            minutes.render().lines().next(),
+            Some("2024-01-01 12:00        1 | nginx | /var/log | opened")
+        );
+        assert!(
+            Trend::from_rollups("minute,x", &options, Resolution::Minute)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_buckets_close_on_tick() {
+        let clock = Arc::new(MockClock::new(time("2024-01-01T12:00:30Z")));
+        let mut sink = RollupSink::new(Vec::new()).with_clock(clock.clone());
+        sink.write_event(&event("/var/log/a.log", "2024-01-01T12:00:05Z"))
+            .unwrap();
+        sink.tick().unwrap();
+        assert!(sink.writer.is_empty());
+
+        // The minute ends with no later event; the hour is still open
+        clock.set(time("2024-01-01T12:01:00Z"));
+        sink.tick().unwrap();
+        let text = String::from_utf8(sink.writer.clone()).unwrap();
+        assert_eq!(
+            text,
+            "minute,2024-01-01T12:00:00Z,nginx,/var/log,opened,1\n"
+        );
+
+        // A late event adds a row to its minute, summed when read back
+        sink.write_event(&event("/var/log/b.log", "2024-01-01T12:00:59Z"))
+            .unwrap();
+        sink.tick().unwrap();
+        let text = String::from_utf8(sink.writer.clone()).unwrap();
+        let minutes = Trend::from_rollups(
+            &text,
+            &TrendOptions::default(),
+            Resolution::Minute,
+        )
+        .unwrap();
+        assert_eq!(minutes.counts.values().collect::<Vec<_>>(), [&2]);
+        assert!(!text.contains("hour,"));
+    }
+
+    #[test]
+    fn test_trend_routing() {
+        let now = time("2024-01-02T00:00:00Z");
+        let last = |hours| TrendOptions {
+            from: Some(now - TimeDelta::hours(hours)),
+            ..Default::default()
+        };
+        assert!(last(1).prefers_raw(now));
+        assert_eq!(last(1).resolution(now), Resolution::Minute);
+        assert!(!last(2).prefers_raw(now));
+        assert_eq!(last(6).resolution(now), Resolution::Minute);
+        assert_eq!(last(7).resolution(now), Resolution::Hour);
+        assert!(!TrendOptions::default().prefers_raw(now));
+        assert_eq!(TrendOptions::default().resolution(now), Resolution::Hour);
+
+        let path = rollup_path(Path::new("/var/lib/fw/night.log.zst"));
+        assert_eq!(path, Path::new("/var/lib/fw/night.log.zst.rollup.csv"));
+    }
+}
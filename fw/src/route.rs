@@ -0,0 +1,311 @@
+//! Route module
+//!
+//! Gives each output of `fw collect` its own slice of the events
+//! (`--route SINK:CRITERIA`): critical events to the notifications,
+//! everything to the output file, only writes to syslog. A route narrows
+//! what the shared filter lets through and is checked by the fan-out on
+//! the sink's own task. Criteria are comma-separated KEY=VALUE pairs:
+//!
+//! ```text
+//! severity=LEVEL   events at least this severe
+//! action=ACTION    events of this kind (open, close, write, link, ...)
+//! ext=EXT          files with this extension
+//! name=GLOB        files whose name matches
+//! path=GLOB        files whose path matches
+//! tag=KEY[=VALUE]  events carrying the tag
+//! ```
+//!
+//! Repeating a key widens it (`action=write,action=link`); different keys
+//! must all match. Extensions and globs ignore case.
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::enrich::parse_tag;
+use crate::filter::FilterSpec;
+use crate::glob::GlobSet;
+use crate::severity::Severity;
+use crate::wait_for::ActionMatch;
+
+/// Outputs of `fw collect` a route can apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SinkKind {
+    /// The --mode output: stream, file, recording or forwarder
+    Output,
+    /// The --exec hook
+    Exec,
+    /// The --syslog messages
+    Syslog,
+    /// The webhook and mail notifications
+    Notify,
+    /// The PagerDuty pages
+    PagerDuty,
+    /// The --baseline anomaly alerts
+    Baseline,
+    /// The counts pushed to InfluxDB
+    Influx,
+}
+
+impl SinkKind {
+    /// Every kind, in the order `fw collect` starts them
+    pub const ALL: [SinkKind; 7] = [
+        SinkKind::Exec,
+        SinkKind::Syslog,
+        SinkKind::Notify,
+        SinkKind::PagerDuty,
+        SinkKind::Baseline,
+        SinkKind::Influx,
+        SinkKind::Output,
+    ];
+
+    /// Name used on the command line
+    ///
+    /// # Returns
+    /// * `&'static str` - Name such as "notify"
+    pub fn name(self) -> &'static str {
+        match self {
+            SinkKind::Output => "output",
+            SinkKind::Exec => "exec",
+            SinkKind::Syslog => "syslog",
+            SinkKind::Notify => "notify",
+            SinkKind::PagerDuty => "pagerduty",
+            SinkKind::Baseline => "baseline",
+            SinkKind::Influx => "influx",
+        }
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Criteria one output's events must match on top of the shared filter
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Output the route applies to
+    pub sink: SinkKind,
+    /// Criteria as given, for summaries
+    pub criteria: String,
+    /// Criteria compiled into a filter
+    pub filter: FilterSpec,
+}
+
+impl Route {
+    /// Parse a `--route` value such as "notify:severity=warning"
+    ///
+    /// # Arguments
+    /// * `value` - Output name, ':' and comma-separated criteria
+    ///
+    /// # Returns
+    /// * `Result<Route, String>` - Route, or a usage error
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, criteria) = value.split_once(':').ok_or_else(|| {
+            format!("expected SINK:CRITERIA, got '{}'", value)
+        })?;
+        let sink = SinkKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| {
+            let names: Vec<&str> =
+                SinkKind::ALL.iter().map(|k| k.name()).collect();
+            format!("unknown sink '{}' (expected {})", name, names.join(", "))
+        })?;
+
+        let mut filter = FilterSpec::default();
+        let (mut names, mut paths) = (Vec::new(), Vec::new());
+        for criterion in criteria.split(',') {
+            let (key, value) = criterion
+                .split_once('=')
+                .filter(|(_, value)| !value.is_empty())
+                .ok_or_else(|| {
+                    format!("expected KEY=VALUE, got '{}'", criterion)
+                })?;
+            match key {
+                "severity" => {
+                    let severity = value
+                        .parse::<Severity>()
+                        .map_err(|_| format!("unknown severity '{}'", value))?;
+                    filter.min_severity = Some(severity);
+                }
+                "action" => {
+                    let action = ActionMatch::from_str(value, true)?;
+                    filter.actions.get_or_insert_with(Vec::new).push(action);
+                }
+                "ext" => filter
+                    .extensions
+                    .get_or_insert_with(Vec::new)
+                    .push(value.trim_start_matches('.').to_string()),
+                "name" => names.push(value.to_string()),
+                "path" => paths.push(value.to_string()),
+                "tag" => filter
+                    .tags
+                    .get_or_insert_with(Vec::new)
+                    .push(parse_tag(value)?),
+                _ => {
+                    return Err(format!(
+                        "unknown criterion '{}' (expected severity, action, \
+                         ext, name, path or tag)",
+                        key
+                    ))
+                }
+            }
+        }
+        if !names.is_empty() {
+            let globs = GlobSet::names(&names)?.with_case_sensitive(false);
+            filter.name_globs = Some(globs);
+        }
+        if !paths.is_empty() {
+            let globs = GlobSet::paths(&paths)?.with_case_sensitive(false);
+            filter.path_globs = Some(globs);
+        }
+        Ok(Self {
+            sink,
+            criteria: criteria.to_string(),
+            filter,
+        })
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.sink, self.criteria)
+    }
+}
+
+/// Routes of one `fw collect`, at most one per output
+#[derive(Debug, Clone, Default)]
+pub struct Routes {
+    /// Route of each output that has one
+    routes: HashMap<SinkKind, Route>,
+}
+
+impl Routes {
+    /// Collect the `--route` values
+    ///
+    /// # Arguments
+    /// * `routes` - Parsed routes, in command line order
+    /// * `enabled` - Outputs this run starts
+    ///
+    /// # Returns
+    /// * `Result<Routes>` - Routes, or error if an output is routed twice
+    ///   or isn't enabled
+    pub fn new(routes: Vec<Route>, enabled: &[SinkKind]) -> Result<Self> {
+        let mut by_sink = HashMap::new();
+        for route in routes {
+            if !enabled.contains(&route.sink) {
+                return Err(anyhow!(
+                    "--route {}: that output isn't enabled",
+                    route.sink
+                ));
+            }
+            let sink = route.sink;
+            if by_sink.insert(sink, route).is_some() {
+                return Err(anyhow!(
+                    "--route {} given twice; combine its criteria",
+                    sink
+                ));
+            }
+        }
+        Ok(Self { routes: by_sink })
+    }
+
+    /// Filter an output's events must also match
+    ///
+    /// # Arguments
+    /// * `sink` - Output being started
+    ///
+    /// # Returns
+    /// * `Option<FilterSpec>` - Its route's criteria, or None if it gets
+    ///   everything the shared filter lets through
+    pub fn filter(&self, sink: SinkKind) -> Option<FilterSpec> {
+        self.routes.get(&sink).map(|route| route.filter.clone())
+    }
+
+    /// The routes, in the order `fw collect` starts the outputs
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = &Route>` - Routes in order
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        SinkKind::ALL
+            .into_iter()
+            .filter_map(|kind| self.routes.get(&kind))
+    }
+
+    /// Check whether no output has a route
+    ///
+    /// # Returns
+    /// * `bool` - True if every output gets the same events
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::{FileAction, FileEvent};
+
+    fn event(path: &str, action: FileAction) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            action,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        let route = Route::parse("output:action=write,action=link,path=/srv/*")
+            .unwrap();
+        assert_eq!(route.sink, SinkKind::Output);
+        assert_eq!(
+            route.to_string(),
+            "output:action=write,action=link,path=/srv/*"
+        );
+        let write = event("/SRV/app.conf", FileAction::AtomicSave);
+        assert!(route.filter.matches(&write));
+        assert!(!route.filter.matches(&event("/srv/a", FileAction::Opened)));
+        assert!(!route.filter.matches(&event("/etc/a", FileAction::Linked)));
+
+        let route = Route::parse("notify:severity=warning,ext=.pem").unwrap();
+        let mut key = event("/etc/ssl/host.pem", FileAction::Opened);
+        assert!(!route.filter.matches(&key));
+        key.severity = Some(Severity::Warning);
+        assert!(route.filter.matches(&key));
+
+        for bad in [
+            "notify",
+            "kafka:severity=warning",
+            "notify:severity=loud",
+            "exec:size=10",
+            "exec:action=",
+            "exec:name=a/b",
+        ] {
+            assert!(Route::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_routes_per_sink() {
+        let routes = |values: &[&str]| {
+            let parsed = values.iter().map(|v| Route::parse(v).unwrap());
+            Routes::new(parsed.collect(), &[SinkKind::Output, SinkKind::Exec])
+        };
+        let both = routes(&["output:ext=log", "exec:tag=team"]).unwrap();
+        assert!(both.filter(SinkKind::Exec).is_some());
+        assert!(both.filter(SinkKind::Syslog).is_none());
+        let order: Vec<String> = both.iter().map(|r| r.to_string()).collect();
+        assert_eq!(order, vec!["exec:tag=team", "output:ext=log"]);
+
+        assert!(routes(&["output:ext=log", "output:ext=txt"]).is_err());
+        let err = routes(&["syslog:ext=log"]).unwrap_err();
+        assert!(err.to_string().contains("isn't enabled"));
+    }
+}
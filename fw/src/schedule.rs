@@ -0,0 +1,135 @@
+//! Schedule module
+//!
+//! Daily time windows during which `fw collect --schedule` keeps its
+//! probes attached. Outside every window the probes are detached, so the
+//! watcher costs nothing in the kernel while it waits for the next one.
+
+use chrono::{NaiveTime, Timelike};
+use std::time::Duration;
+
+/// Seconds in a day, the period every schedule repeats with
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Daily time windows in local time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    /// Start and end of each window; a window whose end is before its
+    /// start runs past midnight
+    windows: Vec<(NaiveTime, NaiveTime)>,
+}
+
+impl Schedule {
+    /// Parse a schedule such as "02:00-04:00" or "22:00-02:00,12:00-13:00"
+    ///
+    /// # Arguments
+    /// * `value` - Comma-separated `HH:MM-HH:MM` windows
+    ///
+    /// # Returns
+    /// * `Result<Schedule, String>` - Schedule or a usage error
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let windows = value
+            .split(',')
+            .map(|window| {
+                let (start, end) =
+                    window.trim().split_once('-').ok_or_else(|| {
+                        format!("'{}' is not a HH:MM-HH:MM window", window)
+                    })?;
+                let start = parse_time(start)?;
+                let end = parse_time(end)?;
+                if start == end {
+                    return Err(format!("window '{}' is empty", window));
+                }
+                Ok((start, end))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { windows })
+    }
+
+    /// Check whether a time falls inside any window
+    ///
+    /// # Arguments
+    /// * `now` - Local time of day
+    ///
+    /// # Returns
+    /// * `bool` - True if monitoring should be running
+    pub fn is_open(&self, now: NaiveTime) -> bool {
+        self.windows.iter().any(|&(start, end)| {
+            if start < end {
+                start <= now && now < end
+            } else {
+                now >= start || now < end
+            }
+        })
+    }
+
+    /// Time until the next window starts or ends
+    ///
+    /// # Arguments
+    /// * `now` - Local time of day
+    ///
+    /// # Returns
+    /// * `Duration` - Time until the schedule should be checked again
+    pub fn until_change(&self, now: NaiveTime) -> Duration {
+        let now = now.num_seconds_from_midnight();
+        let secs = self
+            .windows
+            .iter()
+            .flat_map(|&(start, end)| [start, end])
+            .map(|boundary| {
+                let boundary = boundary.num_seconds_from_midnight();
+                match (boundary + SECS_PER_DAY - now) % SECS_PER_DAY {
+                    0 => SECS_PER_DAY,
+                    secs => secs,
+                }
+            })
+            .min()
+            .unwrap_or(SECS_PER_DAY);
+        Duration::from_secs(secs.into())
+    }
+}
+
+/// Parse a "HH:MM" time of day
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|_| format!("'{}' is not a HH:MM time", value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!(Schedule::parse("02:00-04:00").is_ok());
+        assert!(Schedule::parse("22:00-02:00, 12:00-12:30").is_ok());
+        assert!(Schedule::parse("02:00").is_err());
+        assert!(Schedule::parse("02:00-25:00").is_err());
+        assert!(Schedule::parse("03:00-03:00").is_err());
+    }
+
+    #[test]
+    fn test_windows_open_and_close() {
+        let backup = Schedule::parse("02:00-04:00").unwrap();
+        assert!(!backup.is_open(at(1, 59)));
+        assert!(backup.is_open(at(2, 0)));
+        assert!(!backup.is_open(at(4, 0)));
+        assert_eq!(backup.until_change(at(1, 0)), Duration::from_secs(3600));
+        assert_eq!(
+            backup.until_change(at(4, 0)),
+            Duration::from_secs(22 * 3600)
+        );
+
+        let overnight = Schedule::parse("22:00-02:00").unwrap();
+        assert!(overnight.is_open(at(23, 0)));
+        assert!(overnight.is_open(at(1, 0)));
+        assert!(!overnight.is_open(at(12, 0)));
+        assert_eq!(
+            overnight.until_change(at(23, 30)),
+            Duration::from_secs(150 * 60)
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Self-test module
+//!
+//! Implements `fw selftest`, which pushes a deterministic synthetic event
+//! stream through the same filter/format pipeline used by `collect` and
+//! checks the output line by line. It needs no kernel support, so it can
+//! validate an install and serve as a stable test in CI.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{TimeZone, Utc};
+use std::sync::Arc;
+
+use crate::clock::{Clock, MockClock};
+use crate::collector::run_pipeline;
+use crate::file_event::{FileAction, FileEvent};
+use crate::filter::FilterSpec;
+use crate::mock_monitor::MockMonitor;
+use crate::mount_table::MountTable;
+
+/// Extension filter applied during the self-test
+const SELFTEST_EXTENSIONS: [&str; 2] = ["rs", "toml"];
+
+/// Fixed mount list so filesystem annotation doesn't depend on the host
+const SELFTEST_MOUNTS: &str = "\
+/dev/sda1 / ext4 rw 0 0
+tmpfs /tmp tmpfs rw 0 0
+";
+
+/// Lines the pipeline must produce for the synthetic event stream
+const EXPECTED_OUTPUT: [&str; 5] = [
+    "2024-01-01 00:00:00 UTC | rustc (100) | opened | /src/main.rs [ext4]",
+    "2024-01-01 00:00:00 UTC | rustc (100) | closed | /src/main.rs [ext4]",
+    "2024-01-01 00:00:00 UTC | cargo (200) | opened | /proj/Cargo.toml \
+     [ext4]",
+    "2024-01-01 00:00:00 UTC | rustc (600) | opened | /tmp/build.rs [tmpfs]",
+    "2024-01-01 00:00:00 UTC | node (400) | opened | /deep/lib.rs \
+     (truncated) [ext4]",
+];
+
+/// Run the self-test and report the result on stderr
+///
+/// # Returns
+/// * `Result<()>` - Success if the pipeline produced the expected output
+pub fn run_selftest() -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+
+    let checked = rt.block_on(check_pipeline())?;
+    eprintln!("selftest passed: {} events verified", checked);
+    Ok(())
+}
+
+/// Push the synthetic stream through the pipeline and verify the output
+///
+/// # Returns
+/// * `Result<usize>` - Number of output lines verified, or an error
+///   describing the first mismatch
+pub async fn check_pipeline() -> Result<usize> {
+    // A clock standing still keeps the formatted output stable
+    let clock = Arc::new(MockClock::new(
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+    ));
+    let mut monitor =
+        MockMonitor::new(synthetic_events(&*clock)).with_clock(clock);
+    let filter = FilterSpec {
+        extensions: Some(
+            SELFTEST_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+        ),
+        ..Default::default()
+    };
+    let mounts = MountTable::parse(SELFTEST_MOUNTS);
+    let mut sink = Vec::new();
+
+    run_pipeline(
+        &mut monitor,
+        &filter,
+        &mounts,
+        &mut sink,
+        std::future::pending(),
+    )
+    .await
+    .context("Self-test pipeline failed")?;
+
+    let output =
+        String::from_utf8(sink).context("Pipeline output is not UTF-8")?;
+    verify_output(&output)
+}
+
+/// Build the deterministic event stream used by the self-test
+///
+/// The stream mixes events that pass and fail the filter.
+///
+/// # Arguments
+/// * `clock` - Source of the events' timestamps
+///
+/// # Returns
+/// * `Vec<FileEvent>` - Synthetic events in emission order
+pub fn synthetic_events(clock: &dyn Clock) -> Vec<FileEvent> {
+    let event = |path: &str, program: &str, action, pid| {
+        FileEvent::new(
+            path.to_string(),
+            program.to_string(),
+            action,
+            pid,
+            clock.now(),
+        )
+    };
+
+    vec![
+        event("/src/main.rs", "rustc", FileAction::Opened, 100),
+        event("/src/main.rs", "rustc", FileAction::Closed, 100),
+        event("/etc/passwd", "cat", FileAction::Opened, 300),
+        event("/proj/Cargo.toml", "cargo", FileAction::Opened, 200),
+        event("/tmp/archive.tar.gz", "tar", FileAction::Opened, 500),
+        event("/tmp/build.rs", "rustc", FileAction::Opened, 600),
+        event("/deep/lib.rs", "node", FileAction::Opened, 400)
+            .with_path_truncated(true),
+    ]
+}
+
+/// Compare pipeline output against the expected lines
+///
+/// # Arguments
+/// * `output` - Text written by the pipeline
+///
+/// # Returns
+/// * `Result<usize>` - Number of lines verified, or the first mismatch
+fn verify_output(output: &str) -> Result<usize> {
+    let lines: Vec<&str> = output.lines().collect();
+
+    for (index, expected) in EXPECTED_OUTPUT.iter().enumerate() {
+        let actual = lines.get(index).copied().unwrap_or("<missing>");
+        if actual != *expected {
+            return Err(anyhow!(
+                "line {}: expected '{}', got '{}'",
+                index + 1,
+                expected,
+                actual
+            ));
+        }
+    }
+
+    if lines.len() != EXPECTED_OUTPUT.len() {
+        return Err(anyhow!(
+            "expected {} lines, got {}",
+            EXPECTED_OUTPUT.len(),
+            lines.len()
+        ));
+    }
+    Ok(lines.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_pipeline_passes() {
+        assert_eq!(check_pipeline().await.unwrap(), EXPECTED_OUTPUT.len());
+    }
+
+    #[test]
+    fn test_verify_output_reports_mismatch() {
+        let err = verify_output("unexpected line\n").unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+
+    #[test]
+    fn test_verify_output_reports_extra_lines() {
+        let mut output = EXPECTED_OUTPUT.join("\n");
+        output.push_str("\nextra\n");
+        assert!(verify_output(&output).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+//! Session module
+//!
+//! Correlates the events of one open file into a single summary (`fw
+//! collect --mode sessions`). A session starts when a process opens a
+//! path, collects the changes made while it is open, and is reported
+//! once the process closes it, replacing the individual event lines.
+//! Reads and writes are not listed as changes; they are tallied into the
+//! session's access pattern instead. With `--export`, every session is
+//! also written to a file as a JSON line.
+//!
+//! Sessions are keyed by process, descriptor and file identity, so a
+//! descriptor number reused for another file starts a new session.
+//! Processes don't close their files when they exit, so sessions of
+//! processes found gone are reported as ended by the exit, and those
+//! still open when collection stops are reported then.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::access_pattern::AccessTracker;
+use crate::fanout::EventSink;
+use crate::file_event::{format_latency, FileAction, FileEvent, FileId};
+use crate::report::json_string;
+use crate::stacks::{frames_json, Stack};
+
+/// How often open sessions are checked for processes that exited
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The process closed the descriptor
+    Closed,
+    /// The process exited with the descriptor open
+    Exited,
+    /// Collection stopped with the descriptor open
+    StillOpen,
+}
+
+impl SessionEnd {
+    /// Name used in JSON
+    fn name(self) -> &'static str {
+        match self {
+            SessionEnd::Closed => "close",
+            SessionEnd::Exited => "exit",
+            SessionEnd::StillOpen => "open",
+        }
+    }
+}
+
+/// Lifecycle of one open file, from open to close
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Path of the file
+    pub file_path: String,
+    /// Name of the program that opened the file
+    pub program_name: String,
+    /// Process ID that opened the file
+    pub pid: u32,
+    /// When the file was opened
+    pub opened_at: DateTime<Utc>,
+    /// When the file was closed, or when the session was found ended
+    /// otherwise
+    pub closed_at: DateTime<Utc>,
+    /// How the session ended
+    pub end: SessionEnd,
+    /// Flags the file was opened with, if known
+    pub open_flags: Option<u32>,
+    /// Changes made while the file was open, in order
+    pub activity: Vec<FileAction>,
+    /// Reads and writes made while the file was open
+    pub access: AccessTracker,
+    /// Filesystem type of the file, if known
+    pub fs_type: Option<String>,
+    /// Stack of the open, with `--stacks`
+    pub stack: Option<Arc<Stack>>,
+}
+
+impl Session {
+    /// Time between open and close
+    ///
+    /// # Returns
+    /// * `u64` - Duration in nanoseconds, 0 if the clock went backwards
+    pub fn duration_ns(&self) -> u64 {
+        (self.closed_at - self.opened_at)
+            .num_nanoseconds()
+            .unwrap_or(i64::MAX)
+            .max(0) as u64
+    }
+
+    /// Close event standing for the end of the session
+    ///
+    /// # Returns
+    /// * `FileEvent` - Close of the session's path by its process, at the
+    ///   time it ended
+    pub fn closing_event(&self) -> FileEvent {
+        FileEvent::new(
+            self.file_path.clone(),
+            self.program_name.clone(),
+            FileAction::Closed,
+            self.pid,
+            self.closed_at,
+        )
+    }
+
+    /// Render the session as a single-line JSON object
+    ///
+    /// # Returns
+    /// * `String` - JSON object with the bytes read and written, the
+    ///   access pattern fields when the file was read or written, and the
+    ///   frames of the open's stack when it was symbolized
+    pub fn to_json(&self) -> String {
+        let activity: Vec<String> = self
+            .activity
+            .iter()
+            .map(|action| json_string(&action.to_string()))
+            .collect();
+        let mut out = format!(
+            "{{\"opened_at\":{},\"program\":{},\"pid\":{},\"path\":{},\
+             \"duration_ns\":{},\"end\":\"{}\",\"activity\":[{}]",
+            json_string(&self.opened_at.to_rfc3339()),
+            json_string(&self.program_name),
+            self.pid,
+            json_string(&self.file_path),
+            self.duration_ns(),
+            self.end.name(),
+            activity.join(",")
+        );
+        if let Some(flags) = self.open_flags {
+            out.push_str(&format!(",\"open_flags\":{}", flags));
+        }
+        if let Some(fs_type) = &self.fs_type {
+            out.push_str(&format!(",\"fs_type\":{}", json_string(fs_type)));
+        }
+        let access = self.access.json_fields();
+        if access.is_empty() {
+            out.push_str(",\"read_bytes\":0,\"written_bytes\":0");
+        } else {
+            out.push(',');
+            out.push_str(&access);
+        }
+        if let Some(stack) =
+            self.stack.as_ref().filter(|s| !s.frames.is_empty())
+        {
+            out.push_str(&format!(",\"stack\":{}", frames_json(stack)));
+        }
+        out.push('}');
+        out
+    }
+}
+
+impl fmt::Display for Session {
+    /// Format as "timestamp | program (pid) | session 1.2ms | path",
+    /// followed by the open flags, the filesystem type, the access
+    /// pattern if the file was read or written, the changes made in
+    /// between and how the session ended if not by a close (e.g. " flags
+    /// 0o2 [ext4] (sequential read-only, read 4096B in 1, wrote 0B in 0)
+    /// {truncate 0, chmod 0644} <exited>")
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} | {} ({}) | session {} | {}",
+            self.opened_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.program_name,
+            self.pid,
+            format_latency(self.duration_ns()),
+            self.file_path
+        )?;
+        if let Some(flags) = self.open_flags {
+            write!(f, " flags {:#o}", flags)?;
+        }
+        if let Some(fs_type) = &self.fs_type {
+            write!(f, " [{}]", fs_type)?;
+        }
+        if self.access.transfers() > 0 {
+            write!(f, " ({})", self.access)?;
+        }
+        if !self.activity.is_empty() {
+            let activity: Vec<String> =
+                self.activity.iter().map(ToString::to_string).collect();
+            write!(f, " {{{}}}", activity.join(", "))?;
+        }
+        match self.end {
+            SessionEnd::Closed => Ok(()),
+            SessionEnd::Exited => write!(f, " <exited>"),
+            SessionEnd::StillOpen => write!(f, " <still open>"),
+        }
+    }
+}
+
+/// What an open session is found by
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SessionKey {
+    /// Process, descriptor and identity of the file it refers to
+    Descriptor(u32, i32, Option<FileId>),
+    /// Process and path, for events that carry no descriptor
+    Path(u32, String),
+}
+
+impl SessionKey {
+    /// Key of the session an event belongs to
+    fn of(event: &FileEvent) -> Self {
+        match event.fd {
+            Some(fd) => SessionKey::Descriptor(event.pid, fd, event.file_id),
+            None => SessionKey::Path(event.pid, event.file_path.clone()),
+        }
+    }
+
+    /// Process the session belongs to
+    fn pid(&self) -> u32 {
+        match self {
+            SessionKey::Descriptor(pid, ..) | SessionKey::Path(pid, _) => *pid,
+        }
+    }
+}
+
+/// Builds sessions from the event stream
+#[derive(Debug)]
+pub struct SessionAggregator {
+    /// Open sessions; repeated opens under the same key stack and are
+    /// closed most recent first
+    open: HashMap<SessionKey, Vec<Session>>,
+    /// Time of the latest event, when sessions found ended otherwise than
+    /// by a close are taken to have ended
+    last_seen: DateTime<Utc>,
+    /// When open sessions were last checked for exited processes
+    last_exit_check: Instant,
+}
+
+impl Default for SessionAggregator {
+    fn default() -> Self {
+        Self {
+            open: HashMap::new(),
+            last_seen: DateTime::UNIX_EPOCH,
+            last_exit_check: Instant::now(),
+        }
+    }
+}
+
+impl SessionAggregator {
+    /// Create an aggregator with no open sessions
+    ///
+    /// # Returns
+    /// * `SessionAggregator` - New aggregator
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find the open sessions an event without an exact key match
+    /// belongs to: a close whose file identity is unknown, or a path-based
+    /// call on a file the process has open
+    fn fallback_key(&self, event: &FileEvent) -> Option<SessionKey> {
+        self.open
+            .iter()
+            .filter(|(key, sessions)| {
+                key.pid() == event.pid
+                    && match (key, event.fd) {
+                        (SessionKey::Descriptor(_, fd, _), Some(event_fd)) => {
+                            *fd == event_fd && event.file_id.is_none()
+                        }
+                        (_, None) => sessions
+                            .last()
+                            .is_some_and(|s| s.file_path == event.file_path),
+                        _ => false,
+                    }
+            })
+            .max_by_key(|(_, sessions)| {
+                sessions.last().map(|session| session.opened_at)
+            })
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Feed one event into the aggregator
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `Option<Session>` - The session the event completed, if any
+    pub fn observe(&mut self, event: &FileEvent) -> Option<Session> {
+        self.last_seen = self.last_seen.max(event.timestamp);
+        let mut key = SessionKey::of(event);
+        if !matches!(event.action, FileAction::Opened | FileAction::AlreadyOpen)
+            && !self.open.contains_key(&key)
+        {
+            key = self.fallback_key(event)?;
+        }
+        match event.action {
+            FileAction::Opened | FileAction::AlreadyOpen => {
+                self.open.entry(key).or_default().push(Session {
+                    file_path: event.file_path.clone(),
+                    program_name: event.program_name.clone(),
+                    pid: event.pid,
+                    opened_at: event.timestamp,
+                    closed_at: event.timestamp,
+                    end: SessionEnd::Closed,
+                    open_flags: event.open_flags,
+                    activity: Vec::new(),
+                    access: AccessTracker::default(),
+                    fs_type: event.fs_type.clone(),
+                    stack: event.stack.clone(),
+                });
+                None
+            }
+            FileAction::Closed => {
+                let stack = self.open.get_mut(&key)?;
+                let mut session = stack.pop()?;
+                if stack.is_empty() {
+                    self.open.remove(&key);
+                }
+                session.closed_at = event.timestamp;
+                Some(session)
+            }
+            action => {
+                if let Some(session) =
+                    self.open.get_mut(&key).and_then(|stack| stack.last_mut())
+                {
+                    if !session.access.record(&action) {
+                        session.activity.push(action);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Number of sessions still open
+    ///
+    /// # Returns
+    /// * `usize` - Open session count
+    pub fn open_sessions(&self) -> usize {
+        self.open.values().map(Vec::len).sum()
+    }
+
+    /// End the sessions of processes that exited
+    ///
+    /// # Arguments
+    /// * `alive` - Whether a process still exists
+    ///
+    /// # Returns
+    /// * `Vec<Session>` - Sessions of exited processes, oldest first
+    pub fn evict_exited(
+        &mut self,
+        alive: impl Fn(u32) -> bool,
+    ) -> Vec<Session> {
+        let exited: Vec<SessionKey> = self
+            .open
+            .keys()
+            .filter(|key| !alive(key.pid()))
+            .cloned()
+            .collect();
+        let mut ended: Vec<Session> = exited
+            .iter()
+            .filter_map(|key| self.open.remove(key))
+            .flatten()
+            .collect();
+        self.end_all(&mut ended, SessionEnd::Exited);
+        ended
+    }
+
+    /// End the sessions of exited processes, at most once per
+    /// `EXIT_CHECK_INTERVAL`
+    ///
+    /// # Returns
+    /// * `Vec<Session>` - Sessions of processes no longer in `/proc`
+    pub fn evict_exited_periodically(&mut self) -> Vec<Session> {
+        if self.last_exit_check.elapsed() < EXIT_CHECK_INTERVAL {
+            return Vec::new();
+        }
+        self.last_exit_check = Instant::now();
+        self.evict_exited(|pid| Path::new(&format!("/proc/{}", pid)).exists())
+    }
+
+    /// End every open session, when collection stops
+    ///
+    /// # Returns
+    /// * `Vec<Session>` - Sessions still open, oldest first
+    pub fn drain(&mut self) -> Vec<Session> {
+        let mut ended: Vec<Session> = self
+            .open
+            .drain()
+            .flat_map(|(_, sessions)| sessions)
+            .collect();
+        self.end_all(&mut ended, SessionEnd::StillOpen);
+        ended
+    }
+
+    /// Mark sessions ended at the latest event time, oldest first
+    fn end_all(&self, sessions: &mut [Session], end: SessionEnd) {
+        sessions.sort_by_key(|session| session.opened_at);
+        for session in sessions {
+            session.end = end;
+            session.closed_at = self.last_seen.max(session.opened_at);
+        }
+    }
+}
+
+/// Sink that writes one line per completed session to a writer
+pub struct SessionSink<W> {
+    /// Session state built from the events seen so far
+    aggregator: SessionAggregator,
+    /// Destination for session lines
+    writer: W,
+    /// File receiving each session as a JSON line, if exporting
+    export: Option<BufWriter<File>>,
+}
+
+impl<W: Write + Send + 'static> SessionSink<W> {
+    /// Create a sink writing session summaries
+    ///
+    /// # Arguments
+    /// * `writer` - Destination for session lines
+    ///
+    /// # Returns
+    /// * `SessionSink<W>` - New session sink
+    pub fn new(writer: W) -> Self {
+        Self {
+            aggregator: SessionAggregator::new(),
+            writer,
+            export: None,
+        }
+    }
+
+    /// Also write every session to a file as a JSON line
+    ///
+    /// # Arguments
+    /// * `path` - File to create, or None to not export
+    ///
+    /// # Returns
+    /// * `Result<SessionSink<W>>` - The sink, or error if the file can't
+    ///   be created
+    pub fn with_export(mut self, path: Option<&Path>) -> Result<Self> {
+        self.export = path
+            .map(|path| {
+                File::create(path).map(BufWriter::new).with_context(|| {
+                    format!("Failed to create {}", path.display())
+                })
+            })
+            .transpose()?;
+        Ok(self)
+    }
+}
+
+impl<W: Write + Send + 'static> SessionSink<W> {
+    /// Write completed sessions
+    fn write_sessions(&mut self, sessions: Vec<Session>) -> Result<()> {
+        if sessions.is_empty() {
+            return Ok(());
+        }
+        for session in sessions {
+            if let Some(export) = &mut self.export {
+                writeln!(export, "{}", session.to_json())
+                    .context("Failed to export session")?;
+            }
+            writeln!(self.writer, "{}", session)
+                .context("Failed to write session")?;
+        }
+        self.writer
+            .flush()
+            .context("Failed to flush session output")
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for SessionSink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let mut ended = self.aggregator.evict_exited_periodically();
+        ended.extend(self.aggregator.observe(event));
+        self.write_sessions(ended)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let open = self.aggregator.drain();
+        self.write_sessions(open)?;
+        match &mut self.export {
+            Some(export) => export.flush().context("Failed to export sessions"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use chrono::Duration;
+
+    fn event(path: &str, action: FileAction, pid: u32, ms: i64) -> FileEvent {
+        let mut event = FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            action,
+            pid,
+            SystemClock.now(),
+        );
+        event.timestamp =
+            DateTime::<Utc>::UNIX_EPOCH + Duration::milliseconds(ms);
+        event
+    }
+
+    #[test]
+    fn test_session_collects_activity_until_close() {
+        let mut sessions = SessionAggregator::new();
+        let events = [
+            event("/db/wal", FileAction::Opened, 7, 0),
+            event("/db/wal", FileAction::Truncated { length: 0 }, 7, 1),
+            // Same path in another process is a separate session
+            event("/db/wal", FileAction::Opened, 8, 2),
+            event("/db/wal", FileAction::ModeChanged { mode: 0o600 }, 7, 3),
+        ];
+        for e in &events {
+            assert!(sessions.observe(e).is_none());
+        }
+        assert_eq!(sessions.open_sessions(), 2);
+
+        let session = sessions
+            .observe(&event("/db/wal", FileAction::Closed, 7, 12))
+            .unwrap();
+        assert_eq!(session.pid, 7);
+        assert_eq!(
+            session.activity,
+            vec![
+                FileAction::Truncated { length: 0 },
+                FileAction::ModeChanged { mode: 0o600 }
+            ]
+        );
+        assert!(session
+            .to_string()
+            .ends_with("| session 12.0ms | /db/wal {truncate 0, chmod 0600}"));
+        assert_eq!(sessions.open_sessions(), 1);
+
+        // Reads and writes are tallied, not listed
+        sessions.observe(&event("/db/wal", FileAction::Opened, 9, 20));
+        for ms in 21..23 {
+            sessions.observe(&event(
+                "/db/wal",
+                FileAction::Written {
+                    offset: None,
+                    bytes: 512,
+                },
+                9,
+                ms,
+            ));
+        }
+        let session = sessions
+            .observe(&event("/db/wal", FileAction::Closed, 9, 24))
+            .unwrap();
+        assert!(session.activity.is_empty());
+        assert!(session.to_string().ends_with(
+            "| /db/wal (sequential write-only, read 0B in 0, wrote 1024B in 2)"
+        ));
+        assert!(session.to_json().ends_with(
+            "\"pattern\":\"sequential write-only\",\"order\":\
+             \"sequential\",\"balance\":\"write-only\",\"reads\":0,\
+             \"writes\":2,\"read_bytes\":0,\"written_bytes\":1024}"
+        ));
+
+        // A close without a matching open is ignored
+        assert!(sessions
+            .observe(&event("/other", FileAction::Closed, 7, 13))
+            .is_none());
+    }
+
+    #[test]
+    fn test_descriptor_sessions_end_on_exit_and_finish() {
+        let on_fd = |action, fd, ino, ms| {
+            let mut event = event("/db/wal", action, 7, ms);
+            event.fd = Some(fd);
+            event.file_id = FileId::from_raw(8 << 20, ino);
+            event
+        };
+        let mut sessions = SessionAggregator::new();
+        let mut opened = on_fd(FileAction::Opened, 3, 11, 0);
+        opened.open_flags = Some(0o2);
+        sessions.observe(&opened);
+        // The path was replaced and opened again on another descriptor
+        sessions.observe(&on_fd(FileAction::Opened, 4, 12, 1));
+        sessions.observe(&on_fd(
+            FileAction::Written {
+                offset: None,
+                bytes: 100,
+            },
+            3,
+            11,
+            2,
+        ));
+        let closed = sessions
+            .observe(&on_fd(FileAction::Closed, 3, 11, 5))
+            .unwrap();
+        assert_eq!(closed.access.written_bytes, 100);
+        assert_eq!(closed.open_flags, Some(0o2));
+        assert!(closed.to_string().contains("| /db/wal flags 0o2 ("));
+        assert!(closed.to_json().contains("\"end\":\"close\""));
+        assert_eq!(sessions.open_sessions(), 1);
+
+        // The process exits holding descriptor 4
+        assert_eq!(sessions.evict_exited(|pid| pid != 7).len(), 1);
+        assert_eq!(sessions.open_sessions(), 0);
+
+        sessions.observe(&on_fd(FileAction::Opened, 5, 13, 9));
+        let open = sessions.drain();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].end, SessionEnd::StillOpen);
+        assert!(open[0].to_string().ends_with("<still open>"));
+        assert!(open[0].to_json().contains("\"written_bytes\":0"));
+    }
+
+    #[test]
+    fn test_session_sink_writes_only_summaries() {
+        let mut sink = SessionSink::new(Vec::new());
+        sink.write_event(&event("/a.rs", FileAction::Opened, 1, 0))
+            .unwrap();
+        assert!(sink.writer.is_empty());
+        sink.write_event(&event("/a.rs", FileAction::Closed, 1, 5))
+            .unwrap();
+        let output = String::from_utf8(sink.writer).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains("session 5.0ms | /a.rs"));
+    }
+}
@@ -0,0 +1,774 @@
+//! Severity module
+//!
+//! Labels every event info, notice, warning or critical from the class of
+//! the path it touched (credentials, system configuration, user data,
+//! temporary files, ...) and whether it accessed or changed the file.
+//! Classes and labels come from a policy; the built-in
+//! [`DEFAULT_POLICY`] can be replaced with `--severity-policy`. The policy
+//! also sets how often alert sinks may repeat an alert (see
+//! [`crate::throttle`]).
+
+use anyhow::{anyhow, Context, Result};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::cli::{parse_size, parse_timeout};
+use crate::enrich::{is_path_prefix, Enricher};
+use crate::file_event::FileEvent;
+use crate::rename_chain;
+use crate::throttle::{AlertLimits, RateLimit};
+use crate::tripwire::TRIPWIRE_RULE;
+use crate::watchlist;
+use fw_common::StackCriterion;
+
+pub use fw_common::event::Severity;
+
+/// Policy used when no `--severity-policy` is given
+///
+/// "class NAME PREFIX..." puts paths under the prefixes into a class; the
+/// longest prefix wins. "rule CLASS ACCESS SEVERITY" labels events on a
+/// class, where ACCESS is read, write or any; paths in no class are in
+/// "other". "@NAME" in place of the class matches paths on the watchlist
+/// NAME (see [`crate::watchlist`]) instead. A rule may add conditions on
+/// the opened file, "size>SIZE", "size<SIZE", "age<DURATION" or
+/// "age>DURATION" (e.g. "size>1G", "age<10s"), which then must all hold;
+/// files that weren't statted meet none. "pid=PID" limits a rule to one
+/// process, and "capture_stack=true" records the stack of the events it
+/// matches (user and kernel, unless `--stacks` picks one); once any rule
+/// captures stacks, no other event gets one. When several rules match,
+/// the most severe one applies, and events no rule matches are info.
+///
+/// Rules are named CLASS-ACCESS (e.g. "credentials-write", or "pki-any"
+/// for "@pki") unless "name=NAME" says otherwise. "dedup=DURATION" has
+/// alert sinks pass the first of a run of identical alerts from the rule
+/// (same program, action and path) and then one summary with the number
+/// held back once DURATION has passed. "ratelimit COUNT DURATION" caps
+/// every alert sink at COUNT alerts above info per DURATION, over all
+/// rules.
+/// "resolve_after=DURATION" resolves the PagerDuty incident a rule opened
+/// for a path once the rule hasn't fired on it for DURATION.
+pub const DEFAULT_POLICY: &str = "\
+class credentials /etc/shadow /etc/gshadow /etc/sudoers /etc/sudoers.d \
+/etc/passwd /etc/group /etc/ssh /root/.ssh /etc/ssl/private
+class system-config /etc /boot /usr/lib/systemd /lib/systemd
+class user-data /home /root /srv
+class temp /tmp /var/tmp /dev/shm
+rule credentials write critical
+rule credentials read warning
+rule system-config write warning
+rule system-config read notice
+rule user-data write notice
+";
+
+/// Kind of access a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+impl Access {
+    /// Check whether an event's action falls under this access kind
+    fn covers(self, is_write: bool) -> bool {
+        match self {
+            Access::Read => !is_write,
+            Access::Write => is_write,
+            Access::Any => true,
+        }
+    }
+}
+
+/// Condition on the opened file that a rule can add
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileCondition {
+    /// More than this many bytes
+    SizeAbove(u64),
+    /// Fewer than this many bytes
+    SizeBelow(u64),
+    /// Modified less than this long before the event
+    AgeBelow(Duration),
+    /// Modified more than this long before the event
+    AgeAbove(Duration),
+}
+
+impl FileCondition {
+    /// Parse a condition such as "size>1G" or "age<10s"
+    fn parse(field: &str) -> Result<Self, String> {
+        let (name, op, value) = match field.find(['<', '>']) {
+            Some(at) => (&field[..at], &field[at..=at], &field[at + 1..]),
+            None => return Err(format!("bad condition '{}'", field)),
+        };
+        match (name, op) {
+            ("size", ">") => parse_size(value).map(Self::SizeAbove),
+            ("size", "<") => parse_size(value).map(Self::SizeBelow),
+            ("age", "<") => parse_timeout(value).map(Self::AgeBelow),
+            ("age", ">") => parse_timeout(value).map(Self::AgeAbove),
+            _ => Err(format!(
+                "bad condition '{}' (expected size or age with < or >)",
+                field
+            )),
+        }
+    }
+
+    /// Check the condition against an enriched event
+    fn holds(&self, event: &FileEvent) -> bool {
+        match *self {
+            Self::SizeAbove(min) => event.file_size.is_some_and(|s| s > min),
+            Self::SizeBelow(max) => event.file_size.is_some_and(|s| s < max),
+            Self::AgeBelow(max) => event.file_age().is_some_and(|a| a < max),
+            Self::AgeAbove(min) => event.file_age().is_some_and(|a| a > min),
+        }
+    }
+}
+
+/// Policy rule labelling events on a class
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Class of paths the rule applies to
+    class: String,
+    /// Kind of access the rule applies to
+    access: Access,
+    /// Conditions on the opened file, all of which must hold
+    conditions: Vec<FileCondition>,
+    /// Process the rule is limited to, if any
+    pid: Option<u32>,
+    /// Whether matching events get their stack recorded
+    capture_stack: bool,
+    /// Label of matching events
+    severity: Severity,
+    /// Name alerts from the rule are reported under
+    name: String,
+    /// Time identical alerts from the rule are held back for, if any
+    dedup: Option<Duration>,
+    /// Quiet time after which the rule's incidents are resolved, if any
+    resolve_after: Option<Duration>,
+}
+
+impl Rule {
+    /// Check whether the rule applies to an event on a class
+    fn matches(&self, class: &str, event: &FileEvent) -> bool {
+        let on_class = match self.class.strip_prefix('@') {
+            Some(list) => event.tags.contains_key(&watchlist::tag_key(list)),
+            None => self.class == class,
+        };
+        on_class
+            && self.access.covers(is_write(event))
+            && self.pid.is_none_or(|pid| pid == event.pid)
+            && self.conditions.iter().all(|c| c.holds(event))
+    }
+}
+
+/// Check whether an event changed the file or opened it to change it
+///
+/// # Arguments
+/// * `event` - Event to check
+///
+/// # Returns
+/// * `bool` - True for the actions that change a file (writes, renames,
+///   truncation, metadata, ...) and for opens for writing, creation or
+///   truncation
+fn is_write(event: &FileEvent) -> bool {
+    event.action.is_write()
+        || event.open_flags.is_some_and(rename_chain::is_write)
+}
+
+/// Class assigned to paths under no configured prefix
+const OTHER_CLASS: &str = "other";
+
+/// Labels events with a severity according to a policy
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    /// Prefixes and their class, longest prefix first
+    classes: Vec<(String, String)>,
+    /// Rules in policy order
+    rules: Vec<Rule>,
+    /// Most alerts each alert sink passes in a window, if capped
+    rate_limit: Option<RateLimit>,
+}
+
+impl Default for Classifier {
+    fn default() -> Self {
+        Self::parse(DEFAULT_POLICY).expect("default policy is valid")
+    }
+}
+
+impl Classifier {
+    /// Load a policy file
+    ///
+    /// # Arguments
+    /// * `path` - Policy file to read
+    ///
+    /// # Returns
+    /// * `Result<Classifier>` - Classifier, or error naming the bad line
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| {
+            format!("Invalid severity policy {}", path.display())
+        })
+    }
+
+    /// Parse a policy
+    ///
+    /// # Arguments
+    /// * `text` - Policy lines (see [`DEFAULT_POLICY`])
+    ///
+    /// # Returns
+    /// * `Result<Classifier>` - Classifier, or error naming the bad line
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut classes = Vec::new();
+        let mut rules = Vec::new();
+        let mut rate_limit = None;
+        for (number, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bad = |what: &str| anyhow!("line {}: {}", number + 1, what);
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["class", name, prefixes @ ..] if !prefixes.is_empty() => {
+                    for prefix in prefixes {
+                        if !prefix.starts_with('/') {
+                            return Err(bad("class prefixes must be absolute"));
+                        }
+                        classes.push((prefix.to_string(), name.to_string()));
+                    }
+                }
+                ["rule", class, access, severity, options @ ..] => {
+                    let mut name =
+                        format!("{}-{}", class.trim_start_matches('@'), access);
+                    let access = match *access {
+                        "read" => Access::Read,
+                        "write" => Access::Write,
+                        "any" => Access::Any,
+                        _ => {
+                            return Err(bad(
+                                "access must be read, write or any",
+                            ))
+                        }
+                    };
+                    let severity = severity
+                        .parse::<Severity>()
+                        .map_err(|_| bad("unknown severity"))?;
+                    let mut conditions = Vec::new();
+                    let mut pid = None;
+                    let mut capture_stack = false;
+                    let mut dedup = None;
+                    let mut resolve_after = None;
+                    for field in options {
+                        match field.split_once('=') {
+                            Some(("name", value)) if !value.is_empty() => {
+                                name = value.to_string()
+                            }
+                            Some(("dedup", value)) => {
+                                dedup = Some(
+                                    parse_timeout(value)
+                                        .ok()
+                                        .filter(|d| !d.is_zero())
+                                        .ok_or_else(|| {
+                                            bad("dedup must be a duration")
+                                        })?,
+                                )
+                            }
+                            Some(("resolve_after", value)) => {
+                                resolve_after = Some(
+                                    parse_timeout(value)
+                                        .ok()
+                                        .filter(|d| !d.is_zero())
+                                        .ok_or_else(|| {
+                                            bad("resolve_after must be a \
+                                                 duration")
+                                        })?,
+                                )
+                            }
+                            Some(("pid", value)) => {
+                                pid = Some(
+                                    value
+                                        .parse()
+                                        .map_err(|_| bad("bad pid"))?,
+                                )
+                            }
+                            Some(("capture_stack", value)) => {
+                                capture_stack = value.parse().map_err(|_| {
+                                    bad("capture_stack must be true or false")
+                                })?
+                            }
+                            _ => conditions.push(
+                                FileCondition::parse(field)
+                                    .map_err(|e| bad(&e))?,
+                            ),
+                        }
+                    }
+                    rules.push(Rule {
+                        class: class.to_string(),
+                        access,
+                        conditions,
+                        pid,
+                        capture_stack,
+                        severity,
+                        name,
+                        dedup,
+                        resolve_after,
+                    });
+                }
+                ["ratelimit", count, window] => {
+                    let count = count
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| bad("bad alert count"))?;
+                    let window = parse_timeout(window)
+                        .ok()
+                        .filter(|d| !d.is_zero())
+                        .ok_or_else(|| bad("bad rate limit window"))?;
+                    rate_limit = Some(RateLimit { count, window });
+                }
+                _ => {
+                    return Err(bad("expected 'class NAME PREFIX...', \
+                         'rule CLASS ACCESS SEVERITY [OPTION...]' or \
+                         'ratelimit COUNT DURATION'"))
+                }
+            }
+        }
+        classes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        Ok(Self {
+            classes,
+            rules,
+            rate_limit,
+        })
+    }
+
+    /// Make every access to the given decoys critical
+    ///
+    /// The decoys become a class of their own, which their exact paths
+    /// rank above any other, labelled by the rule "tripwire" ahead of
+    /// the policy's rules (see [`crate::tripwire`]).
+    ///
+    /// # Arguments
+    /// * `paths` - Absolute paths of the decoys
+    /// * `capture_stack` - Record the stack of accesses to them
+    ///
+    /// # Returns
+    /// * `Classifier` - The classifier with the tripwire rule, unchanged
+    ///   if there are no decoys
+    pub fn with_tripwires(
+        mut self,
+        paths: &[String],
+        capture_stack: bool,
+    ) -> Self {
+        if paths.is_empty() {
+            return self;
+        }
+        for path in paths {
+            self.classes.push((path.clone(), TRIPWIRE_RULE.to_string()));
+        }
+        self.classes
+            .sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        self.rules.insert(
+            0,
+            Rule {
+                class: TRIPWIRE_RULE.to_string(),
+                access: Access::Any,
+                conditions: Vec::new(),
+                pid: None,
+                capture_stack,
+                severity: Severity::Critical,
+                name: TRIPWIRE_RULE.to_string(),
+                dedup: None,
+                resolve_after: None,
+            },
+        );
+        self
+    }
+
+    /// Class of a path
+    ///
+    /// # Arguments
+    /// * `path` - Absolute file path
+    ///
+    /// # Returns
+    /// * `&str` - Class of the longest matching prefix, or "other"
+    pub fn class_of(&self, path: &str) -> &str {
+        self.classes
+            .iter()
+            .find(|(prefix, _)| is_path_prefix(prefix, path))
+            .map_or(OTHER_CLASS, |(_, class)| class)
+    }
+
+    /// Severity of an event
+    ///
+    /// # Arguments
+    /// * `event` - Event to label
+    ///
+    /// # Returns
+    /// * `Severity` - Most severe matching rule, or info
+    pub fn classify(&self, event: &FileEvent) -> Severity {
+        self.deciding_rule(event)
+            .map_or(Severity::Info, |rule| rule.severity)
+    }
+
+    /// Rule whose label an event gets: the most severe match, the first
+    /// in policy order among equals
+    fn deciding_rule<'a>(&'a self, event: &'a FileEvent) -> Option<&'a Rule> {
+        self.matching_rules(event).reduce(|best, rule| {
+            match rule.severity > best.severity {
+                true => rule,
+                false => best,
+            }
+        })
+    }
+
+    /// Check whether a rule matching an event captures its stack
+    ///
+    /// # Arguments
+    /// * `event` - Event to check
+    ///
+    /// # Returns
+    /// * `bool` - True if the event's stack should be kept
+    pub fn captures_stack(&self, event: &FileEvent) -> bool {
+        self.matching_rules(event).any(|rule| rule.capture_stack)
+    }
+
+    /// Rules applying to an event
+    fn matching_rules<'a>(
+        &'a self,
+        event: &'a FileEvent,
+    ) -> impl Iterator<Item = &'a Rule> {
+        let class = self.class_of(&event.file_path);
+        self.rules
+            .iter()
+            .filter(move |rule| rule.matches(class, event))
+    }
+
+    /// Kernel criteria for the events whose stacks rules capture
+    ///
+    /// Each capturing rule contributes its pid and the prefixes of its
+    /// class; rules on "other" (or a class without prefixes) match paths
+    /// the kernel can't tell apart, so they contribute their pid alone.
+    /// The criteria are a superset: events they let through that no rule
+    /// captures lose their stack when classified. That happens once
+    /// descriptors are resolved to paths, so the events the kernel only
+    /// knows by descriptor, which the criteria let through on their pid,
+    /// are judged on their real path.
+    ///
+    /// # Returns
+    /// * `Vec<StackCriterion>` - Criteria, empty if no rule captures
+    ///   stacks
+    pub fn stack_criteria(&self) -> Vec<StackCriterion> {
+        self.criteria(|rule| rule.capture_stack)
+    }
+
+    /// Kernel criteria for the events overload sampling must not drop
+    ///
+    /// Built like [`Classifier::stack_criteria`] from the rules rating
+    /// events warning or critical.
+    ///
+    /// # Returns
+    /// * `Vec<StackCriterion>` - Criteria, empty if no rule rates events
+    ///   that high
+    pub fn sample_exemptions(&self) -> Vec<StackCriterion> {
+        self.criteria(|rule| rule.severity >= Severity::Warning)
+    }
+
+    /// Kernel criteria for the events of the selected rules
+    fn criteria(
+        &self,
+        selected: impl Fn(&Rule) -> bool,
+    ) -> Vec<StackCriterion> {
+        let mut criteria = Vec::new();
+        for rule in self.rules.iter().filter(|rule| selected(rule)) {
+            let prefixes: Vec<&str> = self
+                .classes
+                .iter()
+                .filter(|(_, class)| *class == rule.class)
+                .map(|(prefix, _)| prefix.as_str())
+                .collect();
+            if prefixes.is_empty() {
+                criteria.push(StackCriterion::new(rule.pid, b""));
+            }
+            for prefix in prefixes {
+                criteria.push(StackCriterion::new(rule.pid, prefix.as_bytes()));
+            }
+        }
+        criteria
+    }
+
+    /// Check whether any rule captures stacks
+    ///
+    /// # Returns
+    /// * `bool` - True if stacks are kept only for events rules capture
+    pub fn has_stack_rules(&self) -> bool {
+        self.rules.iter().any(|rule| rule.capture_stack)
+    }
+
+    /// Check whether any rule looks at the opened file
+    ///
+    /// # Returns
+    /// * `bool` - True if events must be statted for the rules to match
+    pub fn needs_file_stats(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.conditions.is_empty())
+    }
+
+    /// Limits on how often alert sinks repeat alerts
+    ///
+    /// Rules sharing a name share the longest of their dedup windows.
+    ///
+    /// # Returns
+    /// * `AlertLimits` - Dedup windows by rule name and the rate limit
+    pub fn alert_limits(&self) -> AlertLimits {
+        AlertLimits {
+            dedup: self.windows(|rule| rule.dedup),
+            rate_limit: self.rate_limit,
+        }
+    }
+
+    /// Quiet time after which each rule's incidents are resolved
+    ///
+    /// Rules sharing a name share the longest of their times.
+    ///
+    /// # Returns
+    /// * `HashMap<String, Duration>` - Time by rule name, for the rules
+    ///   with resolve_after
+    pub fn resolve_after(&self) -> HashMap<String, Duration> {
+        self.windows(|rule| rule.resolve_after)
+    }
+
+    /// Longest of a per-rule duration, by rule name
+    fn windows(
+        &self,
+        window: impl Fn(&Rule) -> Option<Duration>,
+    ) -> HashMap<String, Duration> {
+        let mut windows: HashMap<String, Duration> = HashMap::new();
+        for rule in &self.rules {
+            if let Some(window) = window(rule) {
+                let longest = windows.entry(rule.name.clone()).or_default();
+                *longest = window.max(*longest);
+            }
+        }
+        windows
+    }
+}
+
+impl Enricher for Classifier {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        let (severity, rule) = self
+            .deciding_rule(event)
+            .map_or((Severity::Info, None), |rule| {
+                (rule.severity, Some(rule.name.clone()))
+            });
+        event.severity = Some(severity);
+        event.rule = rule;
+        if event.stack.is_some()
+            && self.has_stack_rules()
+            && !self.captures_stack(event)
+        {
+            event.stack = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn event(path: &str, action: FileAction) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            action,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let classifier = Classifier::default();
+        let chmod = FileAction::ModeChanged { mode: 0o644 };
+        assert_eq!(classifier.class_of("/etc/ssh/sshd_config"), "credentials");
+        assert_eq!(classifier.class_of("/etc/hosts"), "system-config");
+        assert_eq!(classifier.class_of("/etcetera"), "other");
+
+        let cases = [
+            ("/etc/shadow", chmod, Severity::Critical),
+            ("/etc/shadow", FileAction::Opened, Severity::Warning),
+            ("/etc/hosts", FileAction::Opened, Severity::Notice),
+            ("/home/a/notes.md", chmod, Severity::Notice),
+            ("/tmp/x", chmod, Severity::Info),
+        ];
+        for (path, action, expected) in cases {
+            assert_eq!(classifier.classify(&event(path, action)), expected);
+        }
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let classifier = Classifier::parse(
+            "# build outputs\n\
+             class build /srv/ci/out\n\
+             rule build any notice\n\
+             rule build write warning\n\
+             rule other any info\n",
+        )
+        .unwrap();
+        let truncated =
+            event("/srv/ci/out/a.o", FileAction::Truncated { length: 0 });
+        assert_eq!(classifier.classify(&truncated), Severity::Warning);
+        let written = FileAction::Written {
+            offset: None,
+            bytes: 1,
+        };
+        let written = event("/srv/ci/out/a.o", written);
+        assert_eq!(classifier.classify(&written), Severity::Warning);
+        let renamed = event("/srv/ci/out/a.o", FileAction::Renamed);
+        assert_eq!(classifier.classify(&renamed), Severity::Warning);
+
+        // Opens count as writes when their flags allow changing the file
+        let mut opened = event("/srv/ci/out/a.o", FileAction::Opened);
+        opened.open_flags = Some(libc::O_RDONLY as u32);
+        assert_eq!(classifier.classify(&opened), Severity::Notice);
+        opened.open_flags = Some((libc::O_WRONLY | libc::O_CREAT) as u32);
+        assert_eq!(classifier.classify(&opened), Severity::Warning);
+        assert!(Severity::Critical > Severity::Warning);
+
+        // Rules on a watchlist match events tagged with it, in any class
+        let mut listed = Classifier::parse("rule @pki any critical\n").unwrap();
+        let mut key = event("/etc/pki/ca.pem", FileAction::Opened);
+        assert_eq!(listed.classify(&key), Severity::Info);
+        key.tags
+            .insert("watchlist.pki".into(), "/etc/pki/**".into());
+        listed.enrich(&mut key);
+        assert_eq!(key.severity, Some(Severity::Critical));
+        assert_eq!(key.rule.as_deref(), Some("pki-any"));
+
+        assert!(Classifier::parse("class temp tmp").is_err());
+        assert!(Classifier::parse("rule temp modify info").is_err());
+        assert!(Classifier::parse("rule temp any urgent").is_err());
+        assert!(!classifier.needs_file_stats());
+    }
+
+    #[test]
+    fn test_rule_file_conditions() {
+        let classifier = Classifier::parse(
+            "class data /srv\n\
+             rule data read notice size>1G\n\
+             rule data any warning age<10s size<1K\n",
+        )
+        .unwrap();
+        assert!(classifier.needs_file_stats());
+        let opened = |size, age_secs| {
+            let mut event = event("/srv/dump", FileAction::Opened);
+            event.file_size = size;
+            event.file_modified =
+                Some(event.timestamp - chrono::Duration::seconds(age_secs));
+            event
+        };
+        let cases = [
+            (Some(2 << 30), 3600, Severity::Notice),
+            (Some(512), 3, Severity::Warning),
+            (Some(512), 60, Severity::Info),
+            (None, 3, Severity::Info),
+        ];
+        for (size, age, expected) in cases {
+            assert_eq!(classifier.classify(&opened(size, age)), expected);
+        }
+
+        assert!(Classifier::parse("rule data any info size=1G").is_err());
+        assert!(Classifier::parse("rule data any info mtime<1s").is_err());
+        assert!(Classifier::parse("rule data any info age<soon").is_err());
+    }
+
+    #[test]
+    fn test_stack_capture_rules() {
+        let mut classifier = Classifier::parse(
+            "class keys /etc/ssh /root/.ssh\n\
+             rule keys write critical capture_stack=true\n\
+             rule other any notice pid=42 capture_stack=true\n\
+             rule keys read warning\n",
+        )
+        .unwrap();
+        let criteria = classifier.stack_criteria();
+        assert_eq!(criteria.len(), 3);
+        assert!(criteria.iter().any(|c| c.matches(1, b"/etc/ssh/known")));
+        assert!(criteria[2].matches(42, b"/var/log/app"));
+        assert!(!criteria[2].matches(7, b"/var/log/app"));
+        let exempt = classifier.sample_exemptions();
+        assert_eq!(exempt.len(), 4);
+        assert!(exempt.iter().any(|c| c.matches(7, b"/root/.ssh/id")));
+        assert!(!exempt.iter().any(|c| c.matches(42, b"/var/log/app")));
+
+        let with_stack = |path: &str, action, pid| {
+            let mut event = FileEvent::new(
+                path.to_string(),
+                "app".to_string(),
+                action,
+                pid,
+                SystemClock.now(),
+            );
+            event.stack = Some(Default::default());
+            event
+        };
+        let chmod = FileAction::ModeChanged { mode: 0o600 };
+        let cases = [
+            (with_stack("/etc/ssh/key", chmod, 1), true),
+            (with_stack("/etc/ssh/key", FileAction::Opened, 1), false),
+            (with_stack("/var/log/app", FileAction::Opened, 42), true),
+            (with_stack("/var/log/app", FileAction::Opened, 7), false),
+        ];
+        for (mut event, kept) in cases {
+            classifier.enrich(&mut event);
+            assert_eq!(event.stack.is_some(), kept, "{}", event.file_path);
+        }
+
+        assert!(!Classifier::default().has_stack_rules());
+        assert!(Classifier::parse("rule a any info pid=me").is_err());
+        assert!(Classifier::parse("rule a any info capture_stack=1").is_err());
+    }
+
+    #[test]
+    fn test_rule_names_and_alert_limits() {
+        let mut classifier = Classifier::parse(
+            "class keys /etc/ssh\n\
+             rule keys read notice dedup=30s\n\
+             rule keys write critical name=key-change\n\
+             rule keys any warning name=key-change dedup=1m\n\
+             ratelimit 100 1m\n",
+        )
+        .unwrap();
+        let mut opened = event("/etc/ssh/key", FileAction::Opened);
+        classifier.enrich(&mut opened);
+        assert_eq!(opened.severity, Some(Severity::Warning));
+        assert_eq!(opened.rule.as_deref(), Some("key-change"));
+        let mut other = event("/tmp/x", FileAction::Opened);
+        classifier.enrich(&mut other);
+        assert_eq!(other.rule, None);
+
+        let limits = classifier.alert_limits();
+        assert_eq!(limits.dedup.len(), 2);
+        assert_eq!(limits.dedup["keys-read"], Duration::from_secs(30));
+        assert_eq!(limits.dedup["key-change"], Duration::from_secs(60));
+        assert_eq!(
+            limits.rate_limit,
+            Some(RateLimit {
+                count: 100,
+                window: Duration::from_secs(60),
+            })
+        );
+        assert!(Classifier::default().alert_limits().is_empty());
+
+        assert!(Classifier::parse("rule a any info dedup=0s").is_err());
+        let resolve = Classifier::parse("rule a any info resolve_after=10m")
+            .unwrap()
+            .resolve_after();
+        assert_eq!(resolve["a-any"], Duration::from_secs(600));
+        assert!(Classifier::parse("rule a any info resolve_after=x").is_err());
+        assert!(Classifier::parse("rule a any info name=").is_err());
+        assert!(Classifier::parse("ratelimit 0 1m").is_err());
+        assert!(Classifier::parse("ratelimit 10").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Signing module
+//!
+//! Tamper evidence for `fw record`. Every line of a recording except the
+//! signature lines is folded into a SHA-256 hash chain, and with
+//! --sign-key each checkpoint is followed by a signature line holding the
+//! chain head and an ed25519 signature over it. `fw verify` recomputes
+//! the chain, so changing, adding, removing or reordering any line before
+//! a signature makes that signature fail to match. Signature lines start
+//! with '#', so `fw report` and `fw replay` skip them.
+
+use anyhow::{anyhow, Context, Result};
+use ring::digest::{self, SHA256};
+use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{PrivatePkcs8KeyDer, SubjectPublicKeyInfoDer};
+use std::fmt::{self, Write as _};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Start of a signature line
+pub const SIGNATURE_PREFIX: &str = "# signature ";
+
+/// Signed ahead of the chain head, so signatures can't be reused for
+/// anything else made with the same key
+const SIGNATURE_CONTEXT: &[u8] = b"fw-record-chain-v1";
+
+/// DER header of an ed25519 SubjectPublicKeyInfo, before the 32-byte key
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Running hash of the lines of a recording
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Chain([u8; 32]);
+
+impl Chain {
+    /// Fold the next line into the chain
+    ///
+    /// # Arguments
+    /// * `line` - Line without its newline
+    pub fn push(&mut self, line: &str) {
+        let mut context = digest::Context::new(&SHA256);
+        context.update(&self.0);
+        context.update(line.as_bytes());
+        self.0.copy_from_slice(context.finish().as_ref());
+    }
+
+    /// Bytes a signature covers
+    fn message(&self) -> Vec<u8> {
+        [SIGNATURE_CONTEXT, &self.0].concat()
+    }
+
+    /// Parse a chain head written by [`Chain`]'s Display
+    fn parse(hex: &str) -> Option<Self> {
+        parse_hex(hex)?.try_into().ok().map(Self)
+    }
+}
+
+impl fmt::Display for Chain {
+    /// Format the chain head as lowercase hex
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+/// Lowercase hex of a byte string
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// Bytes of a hex string, or None if it isn't hex
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Private key recordings are signed with
+#[derive(Clone)]
+pub struct SigningKey(Arc<Ed25519KeyPair>);
+
+impl fmt::Debug for SigningKey {
+    /// Show only the public half
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningKey({})", to_hex(self.0.public_key().as_ref()))
+    }
+}
+
+impl SigningKey {
+    /// Load an ed25519 private key
+    ///
+    /// # Arguments
+    /// * `path` - PKCS#8 PEM file, as written by `openssl genpkey
+    ///   -algorithm ed25519`
+    ///
+    /// # Returns
+    /// * `Result<SigningKey>` - Key, or error if the file holds no ed25519
+    ///   private key
+    pub fn load(path: &Path) -> Result<Self> {
+        let der =
+            PrivatePkcs8KeyDer::from_pem_file(path).with_context(|| {
+                format!("No PKCS#8 private key in {}", path.display())
+            })?;
+        Self::from_pkcs8(der.secret_pkcs8_der()).with_context(|| {
+            format!("{} is not an ed25519 key", path.display())
+        })
+    }
+
+    /// Build a key from PKCS#8 DER
+    ///
+    /// # Arguments
+    /// * `pkcs8` - DER-encoded PKCS#8 v1 or v2 ed25519 key
+    ///
+    /// # Returns
+    /// * `Result<SigningKey>` - Key, or error if it isn't an ed25519 key
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|e| anyhow!("{}", e))?;
+        Ok(Self(Arc::new(pair)))
+    }
+
+    /// Signature line vouching for every line folded into `chain`
+    ///
+    /// # Arguments
+    /// * `chain` - Chain head after the checkpoint just written
+    ///
+    /// # Returns
+    /// * `String` - "# signature chain=<hex> sig=<hex>"
+    pub fn signature_line(&self, chain: &Chain) -> String {
+        let sig = self.0.sign(&chain.message());
+        format!(
+            "{}chain={} sig={}",
+            SIGNATURE_PREFIX,
+            chain,
+            to_hex(sig.as_ref())
+        )
+    }
+}
+
+/// Public key recordings are verified with
+#[derive(Debug, Clone)]
+pub struct VerifyingKey(Vec<u8>);
+
+impl VerifyingKey {
+    /// Load an ed25519 public key
+    ///
+    /// # Arguments
+    /// * `path` - SubjectPublicKeyInfo PEM file, as written by `openssl
+    ///   pkey -pubout`
+    ///
+    /// # Returns
+    /// * `Result<VerifyingKey>` - Key, or error if the file holds no
+    ///   ed25519 public key
+    pub fn load(path: &Path) -> Result<Self> {
+        let der = SubjectPublicKeyInfoDer::from_pem_file(path)
+            .with_context(|| format!("No public key in {}", path.display()))?;
+        der.strip_prefix(&ED25519_SPKI_PREFIX[..])
+            .filter(|key| key.len() == 32)
+            .map(|key| Self(key.to_vec()))
+            .ok_or_else(|| anyhow!("{} is not an ed25519 key", path.display()))
+    }
+
+    /// Check a signature over a chain head
+    fn verify(&self, chain: &Chain, sig: &[u8]) -> bool {
+        UnparsedPublicKey::new(&signature::ED25519, &self.0)
+            .verify(&chain.message(), sig)
+            .is_ok()
+    }
+}
+
+impl From<&SigningKey> for VerifyingKey {
+    fn from(key: &SigningKey) -> Self {
+        Self(key.0.public_key().as_ref().to_vec())
+    }
+}
+
+/// Result of checking the signatures of a recording
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignatureCheck {
+    /// Signature lines that matched the lines before them
+    pub valid: u64,
+    /// Lines after the last signature, which no signature vouches for
+    pub unsigned: u64,
+    /// Signatures that failed, naming the line and what is wrong
+    pub errors: Vec<String>,
+}
+
+impl SignatureCheck {
+    /// Check the signature lines of a recording
+    ///
+    /// A signature that is genuine but whose chain head differs from the
+    /// one recomputed means lines before it were tampered with; checking
+    /// continues from its chain head, so each altered segment is reported
+    /// on its own.
+    ///
+    /// # Arguments
+    /// * `lines` - Lines of the recording, in order
+    /// * `key` - Public key the recording must be signed with
+    ///
+    /// # Returns
+    /// * `SignatureCheck` - Findings
+    pub fn check<I, S>(lines: I, key: &VerifyingKey) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut check = Self::default();
+        let mut chain = Chain::default();
+        for (number, line) in lines.into_iter().enumerate() {
+            let line = line.as_ref();
+            let Some(fields) = line.strip_prefix(SIGNATURE_PREFIX) else {
+                chain.push(line);
+                check.unsigned += 1;
+                continue;
+            };
+            check.unsigned = 0;
+            let Some((signed, sig)) = parse_signature(fields) else {
+                check
+                    .errors
+                    .push(format!("line {}: malformed signature", number + 1));
+                continue;
+            };
+            if !key.verify(&signed, &sig) {
+                check.errors.push(format!(
+                    "line {}: signature was not made with this key",
+                    number + 1
+                ));
+            } else if signed != chain {
+                check.errors.push(format!(
+                    "line {}: lines before this signature were changed, \
+                     added or removed",
+                    number + 1
+                ));
+                chain = signed;
+            } else {
+                check.valid += 1;
+            }
+        }
+        check
+    }
+}
+
+/// Chain head and signature of a signature line's fields
+fn parse_signature(fields: &str) -> Option<(Chain, Vec<u8>)> {
+    let mut chain = None;
+    let mut sig = None;
+    for field in fields.split_whitespace() {
+        match field.split_once('=')? {
+            ("chain", value) => chain = Some(Chain::parse(value)?),
+            ("sig", value) => sig = Some(parse_hex(value)?),
+            _ => return None,
+        }
+    }
+    Some((chain?, sig?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+
+    /// Generate a throwaway signing key
+    fn generate_key() -> SigningKey {
+        let pkcs8 =
+            Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        SigningKey::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_signatures_catch_tampering() {
+        let key = generate_key();
+        let public = VerifyingKey::from(&key);
+
+        let mut lines = Vec::new();
+        let mut chain = Chain::default();
+        for line in ["a | 1", "# checkpoint seq=1", "b | 2", "# end"] {
+            chain.push(line);
+            lines.push(line.to_string());
+            if line.starts_with('#') {
+                lines.push(key.signature_line(&chain));
+            }
+        }
+        lines.push("c | 3".to_string());
+        let check = SignatureCheck::check(&lines, &public);
+        assert_eq!(check.valid, 2);
+        assert_eq!(check.unsigned, 1);
+        assert!(check.errors.is_empty());
+
+        // Editing the first segment fails only its signature
+        let mut tampered = lines.clone();
+        tampered[0] = "a | 9".to_string();
+        let check = SignatureCheck::check(&tampered, &public);
+        assert_eq!(check.valid, 1);
+        assert_eq!(check.errors.len(), 1);
+        assert!(check.errors[0].starts_with("line 3:"));
+
+        // Dropping a whole segment with its signature breaks the next one
+        let check = SignatureCheck::check(&lines[3..], &public);
+        assert_eq!(check.valid, 0);
+        assert_eq!(check.errors.len(), 1);
+
+        // Re-signing with another key is caught too
+        let other = VerifyingKey::from(&generate_key());
+        let check = SignatureCheck::check(&lines, &other);
+        assert_eq!(check.valid, 0);
+        assert!(check.errors[0].contains("not made with this key"));
+    }
+}
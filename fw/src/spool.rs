@@ -0,0 +1,411 @@
+//! Spool module
+//!
+//! Write-ahead buffering for output that can become unavailable. Lines
+//! that can't be written are appended to size-capped segment files in a
+//! spool directory instead of being lost, and are drained to the output,
+//! oldest first, as soon as it accepts writes again. Lines are delivered
+//! at least once: a drain that fails part way is retried from the start
+//! of its segment.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Default limit on the total size of a spool
+pub const DEFAULT_SPOOL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Size at which a new segment file is started, for large spools
+const SPOOL_SEGMENT_BYTES: u64 = 1024 * 1024;
+
+/// File extension of spool segments
+const SEGMENT_EXTENSION: &str = "spool";
+
+/// What to discard when the spool is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DropPolicy {
+    /// Delete the oldest segments to make room
+    #[default]
+    Oldest,
+    /// Discard lines that don't fit
+    Newest,
+}
+
+/// Where and how much to spool
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    /// Directory holding the segment files
+    pub dir: PathBuf,
+    /// Limit on the total size of all segments
+    pub max_bytes: u64,
+    /// What to discard when the limit is reached
+    pub drop_policy: DropPolicy,
+}
+
+/// One segment file
+#[derive(Debug)]
+struct Segment {
+    /// Sequence number, which is also the file name
+    seq: u64,
+    /// Size in bytes
+    bytes: u64,
+    /// Number of lines
+    lines: u64,
+}
+
+/// Segment files holding lines not yet delivered, oldest first
+#[derive(Debug)]
+struct Spool {
+    /// Location and limits
+    config: SpoolConfig,
+    /// Segments in delivery order; lines are appended to the last one
+    segments: VecDeque<Segment>,
+    /// Size at which a new segment is started
+    segment_bytes: u64,
+    /// Sequence number of the next segment
+    next_seq: u64,
+    /// Number of lines discarded because the spool was full
+    dropped: u64,
+}
+
+impl Spool {
+    /// Open a spool, picking up segments left by an earlier run
+    ///
+    /// # Arguments
+    /// * `config` - Location and limits
+    ///
+    /// # Returns
+    /// * `Result<Spool>` - Spool, or error if the directory is unusable
+    fn open(config: SpoolConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir).with_context(|| {
+            format!("Failed to create spool {}", config.dir.display())
+        })?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&config.dir).with_context(|| {
+            format!("Failed to read spool {}", config.dir.display())
+        })? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str())
+                != Some(SEGMENT_EXTENSION)
+            {
+                continue;
+            }
+            let Some(seq) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse().ok())
+            else {
+                continue;
+            };
+            let contents = fs::read(&path).with_context(|| {
+                format!("Failed to read spool segment {}", path.display())
+            })?;
+            segments.push(Segment {
+                seq,
+                bytes: contents.len() as u64,
+                lines: contents.iter().filter(|&&b| b == b'\n').count() as u64,
+            });
+        }
+        segments.sort_by_key(|s| s.seq);
+
+        let next_seq = segments.last().map_or(0, |s| s.seq + 1);
+        // Keep several segments so dropping the oldest frees only part of
+        // the spool
+        let segment_bytes = SPOOL_SEGMENT_BYTES.min(config.max_bytes / 4);
+        Ok(Self {
+            config,
+            segments: segments.into(),
+            segment_bytes,
+            next_seq,
+            dropped: 0,
+        })
+    }
+
+    /// Check whether every spooled line has been delivered
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Number of lines waiting to be delivered
+    fn pending_lines(&self) -> u64 {
+        self.segments.iter().map(|s| s.lines).sum()
+    }
+
+    /// Path of a segment file
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        segment_path(&self.config.dir, seq)
+    }
+
+    /// Append a line, discarding data per the drop policy if full
+    ///
+    /// # Arguments
+    /// * `line` - One complete line, including its newline
+    ///
+    /// # Returns
+    /// * `io::Result<()>` - Error if the segment could not be written
+    fn append(&mut self, line: &[u8]) -> io::Result<()> {
+        let len = line.len() as u64;
+        let total = |spool: &Self| -> u64 {
+            spool.segments.iter().map(|s| s.bytes).sum()
+        };
+        if len > self.config.max_bytes {
+            self.dropped += 1;
+            return Ok(());
+        }
+        while total(self) + len > self.config.max_bytes {
+            match self.config.drop_policy {
+                DropPolicy::Newest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+                DropPolicy::Oldest => {
+                    let oldest = self
+                        .segments
+                        .pop_front()
+                        .expect("a full spool has segments");
+                    fs::remove_file(self.segment_path(oldest.seq))?;
+                    self.dropped += oldest.lines;
+                }
+            }
+        }
+
+        if self
+            .segments
+            .back()
+            .is_none_or(|s| s.bytes + len > self.segment_bytes)
+        {
+            self.segments.push_back(Segment {
+                seq: self.next_seq,
+                bytes: 0,
+                lines: 0,
+            });
+            self.next_seq += 1;
+        }
+        let segment = self.segments.back_mut().expect("segment just added");
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.config.dir, segment.seq))?
+            .write_all(line)?;
+        segment.bytes += len;
+        segment.lines += 1;
+        Ok(())
+    }
+
+    /// Deliver spooled segments to a writer, oldest first
+    ///
+    /// # Arguments
+    /// * `writer` - Output the lines were meant for
+    ///
+    /// # Returns
+    /// * `io::Result<()>` - Ok once the spool is empty, or the first error
+    fn drain_into<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        while let Some(segment) = self.segments.front() {
+            let path = self.segment_path(segment.seq);
+            writer.write_all(&fs::read(&path)?)?;
+            writer.flush()?;
+            fs::remove_file(&path)?;
+            self.segments.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Path of the segment with the given sequence number
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+}
+
+/// Writer that spools lines its output can't take and delivers them later
+pub struct SpoolWriter<W: Write> {
+    /// Output the lines are meant for
+    inner: W,
+    /// Lines not yet delivered
+    spool: Spool,
+    /// Bytes written since the last complete line
+    pending: Vec<u8>,
+}
+
+impl<W: Write> SpoolWriter<W> {
+    /// Wrap an output, first delivering anything an earlier run spooled
+    ///
+    /// # Arguments
+    /// * `inner` - Output the lines are meant for
+    /// * `config` - Spool location and limits
+    ///
+    /// # Returns
+    /// * `Result<SpoolWriter<W>>` - Writer, or error if the spool directory
+    ///   is unusable
+    pub fn new(inner: W, config: SpoolConfig) -> Result<Self> {
+        let spool = Spool::open(config)?;
+        if !spool.is_empty() {
+            info!(
+                "Delivering {} lines spooled by an earlier run",
+                spool.pending_lines()
+            );
+        }
+        let mut writer = Self {
+            inner,
+            spool,
+            pending: Vec::new(),
+        };
+        writer.try_drain();
+        Ok(writer)
+    }
+
+    /// Deliver spooled lines if the output accepts them again
+    ///
+    /// # Returns
+    /// * `bool` - True if nothing is left in the spool
+    fn try_drain(&mut self) -> bool {
+        if self.spool.is_empty() {
+            return true;
+        }
+        if self.spool.drain_into(&mut self.inner).is_err() {
+            return false;
+        }
+        info!("Output recovered, spool drained");
+        true
+    }
+
+    /// Write one complete line, spooling it if the output fails
+    fn deliver(&mut self, line: &[u8]) -> io::Result<()> {
+        if !self.try_drain() {
+            return self.spool.append(line);
+        }
+        if let Err(e) = self.inner.write_all(line) {
+            warn!(
+                "Output unavailable ({}), spooling to {}",
+                e,
+                self.spool.config.dir.display()
+            );
+            return self.spool.append(line);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SpoolWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.deliver(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // A failed flush is retried with the next line; only losing the
+        // spool itself is an error
+        if self.spool.is_empty() {
+            let _ = self.inner.flush();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for SpoolWriter<W> {
+    fn drop(&mut self) {
+        if self.spool.dropped > 0 {
+            warn!("Spool was full, dropped {} lines", self.spool.dropped);
+        }
+        if !self.spool.is_empty() {
+            info!(
+                "{} lines left in {}, delivered on the next run",
+                self.spool.pending_lines(),
+                self.spool.config.dir.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output that fails every write while down
+    struct Flaky {
+        up: bool,
+        out: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.up {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config(dir: &Path, max_bytes: u64, policy: DropPolicy) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            drop_policy: policy,
+        }
+    }
+
+    #[test]
+    fn test_spooled_lines_are_delivered_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let flaky = Flaky {
+            up: false,
+            out: Vec::new(),
+        };
+        let mut writer = SpoolWriter::new(
+            flaky,
+            config(dir.path(), DEFAULT_SPOOL_MAX_BYTES, DropPolicy::Oldest),
+        )
+        .unwrap();
+
+        writeln!(writer, "one").unwrap();
+        write!(writer, "tw").unwrap();
+        writeln!(writer, "o").unwrap();
+        assert_eq!(writer.spool.pending_lines(), 2);
+
+        // Lines left over from a crash are picked up by the next writer
+        drop(writer);
+        let flaky = Flaky {
+            up: true,
+            out: Vec::new(),
+        };
+        let mut writer = SpoolWriter::new(
+            flaky,
+            config(dir.path(), DEFAULT_SPOOL_MAX_BYTES, DropPolicy::Oldest),
+        )
+        .unwrap();
+        writeln!(writer, "three").unwrap();
+        assert_eq!(writer.inner.out, b"one\ntwo\nthree\n");
+        assert!(writer.spool.is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_drop_policies_when_full() {
+        for (policy, kept) in [
+            (DropPolicy::Oldest, &b"bbbb\ncccc\n"[..]),
+            (DropPolicy::Newest, &b"aaaa\nbbbb\n"[..]),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut spool =
+                Spool::open(config(dir.path(), 10, policy)).unwrap();
+            for line in [b"aaaa\n", b"bbbb\n", b"cccc\n"] {
+                spool.append(line).unwrap();
+            }
+            assert_eq!(spool.dropped, 1);
+
+            let mut out = Vec::new();
+            spool.drain_into(&mut out).unwrap();
+            assert_eq!(out, kept);
+        }
+    }
+}
@@ -0,0 +1,693 @@
+//! Stacks module
+//!
+//! Per-event stack traces (`fw collect --stacks user|kernel|both`). The
+//! probes store the ids of each event's stacks from a stack trace map,
+//! and the monitor looks up their addresses as events are decoded.
+//! Symbolizing is the expensive part, so it is left to subscribers and
+//! only done for events that passed their filter: user frames are
+//! resolved through the process's memory map and the symbols and debug
+//! info of the mapped file, kernel frames through /proc/kallsyms. Frames
+//! are listed under event lines and added to exported session JSON.
+//! Subscribers share one symbolizer, so an event reaching several of them
+//! is symbolized once.
+
+use addr2line::Loader;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::fanout::{EventSink, Subscriber};
+use crate::file_event::FileEvent;
+use crate::report::json_string;
+use fw_common::{STACK_KERNEL, STACK_USER};
+
+pub use fw_common::record::{Frame, Stack};
+
+/// Processes whose memory maps are cached before the cache is cleared
+pub const MAX_CACHED_PROCESSES: usize = 256;
+
+/// Mapped files whose symbols are cached before the cache is cleared
+pub const MAX_CACHED_FILES: usize = 64;
+
+/// Resolved stacks kept for other subscribers before the ones whose
+/// events every subscriber has seen are pruned
+const MAX_SHARED_STACKS: usize = 1024;
+
+/// ELF program header type of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// Which stacks are recorded with each event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StackMode {
+    /// The stack of the calling program
+    User,
+    /// The kernel stack where the event was recorded
+    Kernel,
+    /// Both stacks
+    Both,
+}
+
+impl StackMode {
+    /// Flags telling the probes which stacks to record
+    ///
+    /// # Returns
+    /// * `u32` - `STACK_USER` and/or `STACK_KERNEL`
+    pub fn flags(self) -> u32 {
+        match self {
+            StackMode::User => STACK_USER,
+            StackMode::Kernel => STACK_KERNEL,
+            StackMode::Both => STACK_USER | STACK_KERNEL,
+        }
+    }
+}
+
+impl fmt::Display for StackMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackMode::User => write!(f, "user"),
+            StackMode::Kernel => write!(f, "kernel"),
+            StackMode::Both => write!(f, "user and kernel"),
+        }
+    }
+}
+
+/// Render a frame as a JSON object
+///
+/// # Arguments
+/// * `frame` - Frame to render
+///
+/// # Returns
+/// * `String` - Object with the address as a hex string and null for
+///   whatever couldn't be resolved
+fn frame_json(frame: &Frame) -> String {
+    let optional = |value: &Option<String>| match value {
+        Some(text) => json_string(text),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"addr\":\"{:#x}\",\"kernel\":{},\"symbol\":{},\
+         \"location\":{},\"module\":{}}}",
+        frame.addr,
+        frame.kernel,
+        optional(&frame.symbol),
+        optional(&frame.location),
+        optional(&frame.module)
+    )
+}
+
+/// Render the resolved frames of a stack as a JSON array
+///
+/// # Arguments
+/// * `stack` - Stack to render
+///
+/// # Returns
+/// * `String` - Array of frame objects, innermost first
+pub fn frames_json(stack: &Stack) -> String {
+    let frames: Vec<String> = stack.frames.iter().map(frame_json).collect();
+    format!("[{}]", frames.join(","))
+}
+
+/// File-backed region of a process's address space
+#[derive(Debug, Clone)]
+struct Mapping {
+    /// First address of the region
+    start: u64,
+    /// Address just past the region
+    end: u64,
+    /// Offset into the file the region starts at
+    offset: u64,
+    /// Mapped file
+    path: PathBuf,
+}
+
+/// Cached memory map of a process
+#[derive(Debug, Clone)]
+struct ProcessMaps {
+    /// Layout of the address space the map was read for, to notice the
+    /// process exec'ing a new program
+    layout: Option<ExecLayout>,
+    /// File-backed regions
+    mappings: Vec<Mapping>,
+}
+
+/// Addresses the kernel picks when a program is exec'd
+///
+/// The start of the stack is randomized on every exec, and the code
+/// range moves with the program, so a change means the cached memory map
+/// belongs to a program the process no longer runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExecLayout {
+    /// Start of the program's code
+    start_code: u64,
+    /// End of the program's code
+    end_code: u64,
+    /// Start of the main thread's stack
+    start_stack: u64,
+}
+
+/// Read the exec layout of a process from `/proc/<pid>/stat`
+///
+/// # Arguments
+/// * `pid` - Process to look up
+///
+/// # Returns
+/// * `Option<ExecLayout>` - Layout, or None if the process is gone or
+///   its addresses are hidden from fw
+fn exec_layout(pid: u32) -> Option<ExecLayout> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_exec_layout(&stat)
+}
+
+/// Parse the exec layout out of a `/proc/<pid>/stat` line
+///
+/// # Arguments
+/// * `stat` - Contents of the stat file
+///
+/// # Returns
+/// * `Option<ExecLayout>` - Layout, or None if the line is malformed or
+///   the addresses read as zero
+fn parse_exec_layout(stat: &str) -> Option<ExecLayout> {
+    // The command name may contain spaces; fields resume after its ')',
+    // starting with field 3 (state)
+    let (_, rest) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |number: usize| fields.get(number - 3)?.parse::<u64>().ok();
+    let layout = ExecLayout {
+        start_code: field(26)?,
+        end_code: field(27)?,
+        start_stack: field(28)?,
+    };
+    (layout.start_stack != 0).then_some(layout)
+}
+
+/// Parse a `/proc/<pid>/maps` listing, keeping file-backed regions
+///
+/// # Arguments
+/// * `text` - Contents of the maps file
+///
+/// # Returns
+/// * `Vec<Mapping>` - Regions in the order listed
+fn parse_maps(text: &str) -> Vec<Mapping> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let offset = fields.nth(1)?;
+            // Skip the device and inode to reach the path
+            let path = fields.nth(2)?;
+            path.starts_with('/').then_some(())?;
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: PathBuf::from(path),
+            })
+        })
+        .collect()
+}
+
+/// Parse `/proc/kallsyms` into symbols sorted by address
+///
+/// Addresses read as zero when kernel pointers are hidden from the
+/// reader (`kptr_restrict`); such entries are dropped.
+///
+/// # Arguments
+/// * `text` - Contents of kallsyms
+///
+/// # Returns
+/// * `Vec<(u64, String, Option<String>)>` - Address, name and module
+fn parse_kallsyms(text: &str) -> Vec<(u64, String, Option<String>)> {
+    let mut symbols: Vec<(u64, String, Option<String>)> = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let name = fields.nth(1)?;
+            let module = fields
+                .next()
+                .map(|m| m.trim_matches(|c| c == '[' || c == ']').to_string());
+            (addr != 0).then(|| (addr, name.to_string(), module))
+        })
+        .collect();
+    symbols.sort_by_key(|(addr, _, _)| *addr);
+    symbols
+}
+
+/// Loadable segment of an ELF file
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    /// Offset of the segment in the file
+    offset: u64,
+    /// Bytes of the segment stored in the file
+    file_size: u64,
+    /// Address the segment is linked at
+    vaddr: u64,
+}
+
+/// Read the loadable segments of a 64-bit little-endian ELF file
+///
+/// # Arguments
+/// * `file` - Open ELF file
+///
+/// # Returns
+/// * `Option<Vec<Segment>>` - Segments, or None if the file isn't an ELF
+///   file fw can read
+fn read_segments(file: &File) -> Option<Vec<Segment>> {
+    let mut header = [0u8; 64];
+    file.read_exact_at(&mut header, 0).ok()?;
+    if &header[..4] != b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return None;
+    }
+    let u16_at = |bytes: &[u8], at: usize| {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    };
+    let u64_at = |bytes: &[u8], at: usize| {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&bytes[at..at + 8]);
+        u64::from_le_bytes(word)
+    };
+    let phoff = u64_at(&header, 32);
+    let entry_size = u16_at(&header, 54);
+    let count = u16_at(&header, 56);
+    if entry_size < 56 {
+        return None;
+    }
+    let mut table = vec![0u8; entry_size * count];
+    file.read_exact_at(&mut table, phoff).ok()?;
+    Some(
+        table
+            .chunks_exact(entry_size)
+            .filter(|entry| {
+                u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]])
+                    == PT_LOAD
+            })
+            .map(|entry| Segment {
+                offset: u64_at(entry, 8),
+                vaddr: u64_at(entry, 16),
+                file_size: u64_at(entry, 32),
+            })
+            .collect(),
+    )
+}
+
+/// Symbols and debug info of one mapped file
+struct ObjectInfo {
+    /// Symbol and line lookups
+    loader: Loader,
+    /// Loadable segments, to turn file offsets into link addresses
+    segments: Vec<Segment>,
+}
+
+impl ObjectInfo {
+    /// Load a mapped file
+    fn load(path: &Path) -> Option<Self> {
+        let segments = read_segments(&File::open(path).ok()?)?;
+        let loader = Loader::new(path).ok()?;
+        Some(Self { loader, segments })
+    }
+
+    /// Resolve an address given as an offset into the file
+    fn frame(&self, file_offset: u64, frame: &mut Frame) {
+        let Some(vaddr) = self
+            .segments
+            .iter()
+            .find(|s| (s.offset..s.offset + s.file_size).contains(&file_offset))
+            .map(|s| file_offset - s.offset + s.vaddr)
+        else {
+            return;
+        };
+        frame.symbol = self.loader.find_symbol(vaddr).map(|name| {
+            addr2line::demangle_auto(name.into(), None).into_owned()
+        });
+        if let Ok(Some(location)) = self.loader.find_location(vaddr) {
+            if let (Some(file), Some(line)) = (location.file, location.line) {
+                frame.location = Some(format!("{}:{}", file, line));
+            }
+        }
+    }
+}
+
+/// Resolves stack addresses to functions and source lines
+///
+/// Memory maps, mapped files and the kernel symbol table are read on
+/// first use and cached.
+#[derive(Default)]
+pub struct Symbolizer {
+    /// Kernel symbols, loaded with the first kernel frame
+    kallsyms: Option<Vec<(u64, String, Option<String>)>>,
+    /// File-backed regions per process
+    maps: HashMap<u32, ProcessMaps>,
+    /// Mapped files by path; None if they couldn't be read
+    objects: HashMap<PathBuf, Option<ObjectInfo>>,
+}
+
+impl Symbolizer {
+    /// Create a symbolizer with empty caches
+    ///
+    /// # Returns
+    /// * `Symbolizer` - New symbolizer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve every address of a stack
+    ///
+    /// Frames that can't be resolved (e.g. the process has exited, or
+    /// kernel pointers are hidden) keep just their address.
+    ///
+    /// # Arguments
+    /// * `pid` - Process the user stack belongs to
+    /// * `stack` - Stack with addresses
+    ///
+    /// # Returns
+    /// * `Stack` - The same stack with its frames filled in
+    pub fn symbolize(&mut self, pid: u32, stack: &Stack) -> Stack {
+        if !stack.user.is_empty() {
+            self.forget_replaced_maps(pid);
+        }
+        let mut frames: Vec<Frame> = stack
+            .kernel
+            .iter()
+            .map(|&ip| self.kernel_frame(ip))
+            .collect();
+        for &addr in &stack.user {
+            frames.push(self.user_frame(pid, addr));
+        }
+        Stack {
+            frames,
+            ..stack.clone()
+        }
+    }
+
+    /// Drop the cached memory map of a process that has exec'd since it
+    /// was read
+    ///
+    /// # Arguments
+    /// * `pid` - Process whose stack is about to be symbolized
+    fn forget_replaced_maps(&mut self, pid: u32) {
+        if let Some(cached) = self.maps.get(&pid) {
+            if cached.layout != exec_layout(pid) {
+                self.maps.remove(&pid);
+            }
+        }
+    }
+
+    /// Resolve a kernel instruction pointer to symbol+offset
+    fn kernel_frame(&mut self, ip: u64) -> Frame {
+        let symbols = self.kallsyms.get_or_insert_with(|| {
+            fs::read_to_string("/proc/kallsyms")
+                .map(|text| parse_kallsyms(&text))
+                .unwrap_or_default()
+        });
+        let mut frame = Frame {
+            addr: ip,
+            kernel: true,
+            ..Default::default()
+        };
+        let index = symbols.partition_point(|(addr, _, _)| *addr <= ip);
+        if let Some((addr, name, module)) =
+            index.checked_sub(1).map(|i| &symbols[i])
+        {
+            frame.symbol = Some(format!("{}+{:#x}", name, ip - addr));
+            frame.module = module.clone();
+        }
+        frame
+    }
+
+    /// Resolve a user return address through the process's memory map
+    fn user_frame(&mut self, pid: u32, addr: u64) -> Frame {
+        let mut frame = Frame {
+            addr,
+            ..Default::default()
+        };
+        if self.maps.len() >= MAX_CACHED_PROCESSES
+            && !self.maps.contains_key(&pid)
+        {
+            self.maps.clear();
+        }
+        let maps = self.maps.entry(pid).or_insert_with(|| ProcessMaps {
+            layout: exec_layout(pid),
+            mappings: fs::read_to_string(format!("/proc/{}/maps", pid))
+                .map(|text| parse_maps(&text))
+                .unwrap_or_default(),
+        });
+        let Some(mapping) = maps
+            .mappings
+            .iter()
+            .find(|m| (m.start..m.end).contains(&addr))
+        else {
+            return frame;
+        };
+        frame.module = Some(mapping.path.display().to_string());
+        // A return address points past the call; look up the call itself,
+        // which is in another region if the address starts this one
+        let Some(file_offset) = addr
+            .checked_sub(1)
+            .and_then(|call| call.checked_sub(mapping.start))
+            .map(|offset| offset + mapping.offset)
+        else {
+            return frame;
+        };
+        let path = mapping.path.clone();
+        if self.objects.len() >= MAX_CACHED_FILES
+            && !self.objects.contains_key(&path)
+        {
+            self.objects.clear();
+        }
+        let object = self
+            .objects
+            .entry(path)
+            .or_insert_with_key(|path| ObjectInfo::load(path));
+        if let Some(object) = object {
+            object.frame(file_offset, &mut frame);
+        }
+        frame
+    }
+}
+
+/// Symbolizer and stacks resolved with it, shared by every subscriber
+#[derive(Default)]
+struct SharedSymbolizer {
+    /// Caches shared by every subscriber
+    symbolizer: Symbolizer,
+    /// Stacks resolved for one subscriber, by the address of the
+    /// unresolved stack, for the others to reuse
+    resolved: HashMap<usize, (Weak<Stack>, Arc<Stack>)>,
+}
+
+impl SharedSymbolizer {
+    /// Resolve an event's stack, or reuse the frames another subscriber
+    /// already resolved for it
+    ///
+    /// # Arguments
+    /// * `pid` - Process the user stack belongs to
+    /// * `stack` - Stack with addresses, shared by the event's copies
+    ///
+    /// # Returns
+    /// * `Arc<Stack>` - The same stack with its frames filled in
+    fn resolve(&mut self, pid: u32, stack: &Arc<Stack>) -> Arc<Stack> {
+        let key = Arc::as_ptr(stack) as usize;
+        if let Some((unresolved, resolved)) = self.resolved.get(&key) {
+            if unresolved.upgrade().is_some_and(|s| Arc::ptr_eq(&s, stack)) {
+                return resolved.clone();
+            }
+        }
+        if self.resolved.len() >= MAX_SHARED_STACKS {
+            // Events every subscriber has seen are dropped by the fan-out
+            self.resolved
+                .retain(|_, (unresolved, _)| unresolved.strong_count() > 0);
+        }
+        let resolved = Arc::new(self.symbolizer.symbolize(pid, stack));
+        self.resolved
+            .insert(key, (Arc::downgrade(stack), resolved.clone()));
+        resolved
+    }
+}
+
+/// Sink that symbolizes the stacks of events before passing them on
+///
+/// Wrapping subscribers' sinks rather than the event stream means only
+/// events that passed a subscriber's filter are symbolized.
+pub struct SymbolizingSink {
+    /// Symbolizer shared with the other subscribers
+    shared: Arc<Mutex<SharedSymbolizer>>,
+    /// Sink receiving the symbolized events
+    inner: Box<dyn EventSink>,
+}
+
+impl SymbolizingSink {
+    /// Wrap subscribers' sinks so their events arrive symbolized
+    ///
+    /// The subscribers share one symbolizer, so an event that passes
+    /// several filters is symbolized for the first and the frames reused
+    /// for the rest.
+    ///
+    /// # Arguments
+    /// * `subscribers` - Subscribers whose sinks should get resolved frames
+    ///
+    /// # Returns
+    /// * `Vec<Subscriber>` - The same subscribers with symbolizing sinks
+    pub fn wrap_all(subscribers: Vec<Subscriber>) -> Vec<Subscriber> {
+        let shared = Arc::new(Mutex::new(SharedSymbolizer::default()));
+        subscribers
+            .into_iter()
+            .map(|subscriber| Subscriber {
+                sink: Box::new(Self {
+                    shared: shared.clone(),
+                    inner: subscriber.sink,
+                }),
+                ..subscriber
+            })
+            .collect()
+    }
+}
+
+impl EventSink for SymbolizingSink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        match &event.stack {
+            Some(stack) if stack.frames.is_empty() => {
+                let mut event = event.clone();
+                let stack = self
+                    .shared
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .resolve(event.pid, stack);
+                event.stack = Some(stack);
+                self.inner.write_event(&event)
+            }
+            _ => self.inner.write_event(event),
+        }
+    }
+
+    fn dropped(&mut self, source: &str, count: u64) {
+        self.inner.dropped(source, count);
+    }
+
+    fn throttled(&mut self, event: &FileEvent) {
+        self.inner.throttled(event);
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.inner.tick()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn marker_function() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    #[test]
+    fn test_kernel_symbols() {
+        let symbols = parse_kallsyms(
+            "ffffffff81200000 T vfs_read\n\
+             0000000000000000 T hidden\n\
+             ffffffff81100000 T do_sys_openat2\n\
+             ffffffffc0a00000 t ext4_file_open\t[ext4]\n",
+        );
+        assert_eq!(symbols.len(), 3);
+        let mut symbolizer = Symbolizer {
+            kallsyms: Some(symbols),
+            ..Default::default()
+        };
+        let frame = symbolizer.kernel_frame(0xffffffff81100040);
+        assert_eq!(frame.symbol.as_deref(), Some("do_sys_openat2+0x40"));
+        let frame = symbolizer.kernel_frame(0xffffffffc0a00010);
+        assert_eq!(frame.module.as_deref(), Some("ext4"));
+        assert_eq!(
+            frame.to_string(),
+            "0xffffffffc0a00010 ext4_file_open+0x10 [ext4]"
+        );
+        assert_eq!(symbolizer.kernel_frame(0x1000).symbol, None);
+    }
+
+    #[test]
+    fn test_user_frames_resolve_through_the_memory_map() {
+        let maps = parse_maps(
+            "55d0c0000000-55d0c0021000 r-xp 00002000 08:01 1234 /usr/bin/app\n\
+             7ffd1000-7ffd2000 rw-p 00000000 00:00 0 [stack]\n",
+        );
+        assert_eq!(maps.len(), 1);
+        assert_eq!(maps[0].offset, 0x2000);
+
+        // Resolve a function of this test binary from its own address
+        let addr = marker_function as *const () as usize as u64 + 1;
+        let stack = Stack {
+            user: vec![addr, 0x10],
+            ..Default::default()
+        };
+        let stack = Symbolizer::new().symbolize(std::process::id(), &stack);
+        assert!(stack.frames[0]
+            .symbol
+            .as_deref()
+            .is_some_and(|name| name.contains("marker_function")));
+        assert_eq!(stack.frames[1].symbol, None);
+        assert!(frames_json(&stack).ends_with(
+            "{\"addr\":\"0x10\",\"kernel\":false,\"symbol\":null,\
+             \"location\":null,\"module\":null}]"
+        ));
+        assert_eq!(marker_function(), 42);
+    }
+
+    #[test]
+    fn test_return_address_at_the_start_of_a_region() {
+        let mut symbolizer = Symbolizer::new();
+        let pid = std::process::id();
+        symbolizer.maps.insert(
+            pid,
+            ProcessMaps {
+                layout: exec_layout(pid),
+                mappings: parse_maps(
+                    "1000-2000 r-xp 00000000 08:01 1 /nonexistent/app\n",
+                ),
+            },
+        );
+        let frame = symbolizer.user_frame(pid, 0x1000);
+        assert_eq!(frame.module.as_deref(), Some("/nonexistent/app"));
+        assert_eq!(frame.symbol, None);
+    }
+
+    #[test]
+    fn test_exec_layout() {
+        let stat = "42 (my (odd) app) S 1 42 42 0 -1 4194560 100 0 0 0 \
+                    1 2 0 0 20 0 1 0 500 1000000 200 18446744073709551615 \
+                    94000000000000 94000000100000 140730000000000 0 0 0 0 \
+                    0 0 0 0 0 17 3 0 0 0 0 0";
+        assert_eq!(
+            parse_exec_layout(stat),
+            Some(ExecLayout {
+                start_code: 94000000000000,
+                end_code: 94000000100000,
+                start_stack: 140730000000000,
+            })
+        );
+        // Hidden from readers that can't trace the process
+        let hidden = stat.replace("140730000000000", "0");
+        assert_eq!(parse_exec_layout(&hidden), None);
+
+        // A cached map from before an exec is dropped
+        let mut symbolizer = Symbolizer::new();
+        let pid = std::process::id();
+        symbolizer.maps.insert(
+            pid,
+            ProcessMaps {
+                layout: parse_exec_layout(stat),
+                mappings: Vec::new(),
+            },
+        );
+        symbolizer.forget_replaced_maps(pid);
+        assert!(!symbolizer.maps.contains_key(&pid));
+    }
+}
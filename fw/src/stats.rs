@@ -0,0 +1,656 @@
+//! Stats module
+//!
+//! Rolls events up into counts per group (`fw collect --mode stats`)
+//! instead of printing them. Groups are formed from configurable
+//! dimensions such as the process, the file extension or the directory
+//! down to a given depth, and the final aggregate is printed on exit and
+//! optionally exported as JSON or CSV so two runs can be diffed.
+//! Grouping by access pattern counts closed sessions instead of events,
+//! since a pattern only exists once a file's reads and writes are known.
+//! With a heat map depth, counts are rolled up the directory tree by
+//! [`DirHeatMap`] instead, and with contention, files are ranked by lock
+//! contention by [`LockContention`].
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::contention::LockContention;
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::heat_map::DirHeatMap;
+use crate::report::{json_string, CapturedEvent};
+use crate::session::{Session, SessionAggregator};
+
+/// Property of an event that stats are grouped by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dimension {
+    /// Name of the program
+    Process,
+    /// Full path of the file
+    Path,
+    /// File extension, or "(none)"
+    Extension,
+    /// Directory of the file, cut off after this many components
+    DirDepth(usize),
+    /// Action without its details (e.g. "chmod")
+    Action,
+    /// User name, uid, or "(unknown)"
+    User,
+    /// Group name, gid, or "(unknown)"
+    Group,
+    /// Severity label, or "(none)" if unclassified
+    Severity,
+    /// Value of an enrichment tag, or "(none)"
+    Tag(String),
+    /// Access pattern of the session (e.g. "sequential read-heavy"), or
+    /// "(no i/o)"; counts sessions rather than events
+    Pattern,
+}
+
+impl Dimension {
+    /// Parse a dimension such as "process", "dir-depth=2" or "tag=team"
+    ///
+    /// # Arguments
+    /// * `value` - Dimension name; "dir" is short for "dir-depth=1"
+    ///
+    /// # Returns
+    /// * `Result<Dimension, String>` - Dimension or a usage error
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "process" => Ok(Dimension::Process),
+            "path" => Ok(Dimension::Path),
+            "extension" => Ok(Dimension::Extension),
+            "action" => Ok(Dimension::Action),
+            "user" => Ok(Dimension::User),
+            "group" => Ok(Dimension::Group),
+            "severity" => Ok(Dimension::Severity),
+            "pattern" => Ok(Dimension::Pattern),
+            "dir" => Ok(Dimension::DirDepth(1)),
+            other if other.starts_with("tag=") => match &other[4..] {
+                "" => Err("tag dimension needs a key (e.g. tag=team)".into()),
+                key => Ok(Dimension::Tag(key.to_string())),
+            },
+            other => other
+                .strip_prefix("dir-depth=")
+                .and_then(|depth| depth.parse().ok())
+                .filter(|&depth| depth > 0)
+                .map(Dimension::DirDepth)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown dimension '{}' (expected process, \
+                         path, extension, action, user, group, severity, \
+                         pattern, dir-depth=N or tag=KEY)",
+                        other
+                    )
+                }),
+        }
+    }
+
+    /// Value of this dimension for an event
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    ///
+    /// # Returns
+    /// * `String` - Group value
+    fn value_of(&self, event: &FileEvent) -> String {
+        match self {
+            Dimension::Process => event.program_name.clone(),
+            Dimension::Path => event.file_path.clone(),
+            Dimension::Extension => extension_of(&event.file_path),
+            &Dimension::DirDepth(depth) => dir_prefix(&event.file_path, depth),
+            Dimension::Action => verb(&event.action.to_string()),
+            Dimension::User => id_name(&event.user, event.uid),
+            Dimension::Group => id_name(&event.group, event.gid),
+            Dimension::Severity => event
+                .severity
+                .map_or_else(|| "(none)".to_string(), |s| s.to_string()),
+            Dimension::Tag(key) => event
+                .tags
+                .get(key)
+                .cloned()
+                .unwrap_or_else(|| "(none)".to_string()),
+            // Only known for sessions, see StatsAggregate::record_session
+            Dimension::Pattern => "(none)".to_string(),
+        }
+    }
+
+    /// Value of this dimension for an event read back from a capture
+    ///
+    /// # Arguments
+    /// * `event` - Event parsed from `fw collect` output
+    ///
+    /// # Returns
+    /// * `Option<String>` - Group value, or None for tags and access
+    ///   patterns, which captures don't hold
+    pub fn value_of_captured(&self, event: &CapturedEvent) -> Option<String> {
+        let unknown = |value: &Option<String>, none: &str| {
+            value.clone().unwrap_or_else(|| none.to_string())
+        };
+        Some(match self {
+            Dimension::Process => event.program.clone(),
+            Dimension::Path => event.path.clone(),
+            Dimension::Extension => extension_of(&event.path),
+            &Dimension::DirDepth(depth) => dir_prefix(&event.path, depth),
+            Dimension::Action => verb(&event.action),
+            Dimension::User => unknown(&event.user, "(unknown)"),
+            Dimension::Group => unknown(&event.group, "(unknown)"),
+            Dimension::Severity => unknown(&event.severity, "(none)"),
+            Dimension::Tag(_) | Dimension::Pattern => return None,
+        })
+    }
+}
+
+/// File extension of a path, or "(none)"
+pub(crate) fn extension_of(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+/// Directory of a path, cut off after `depth` components
+pub(crate) fn dir_prefix(path: &str, depth: usize) -> String {
+    let dir = Path::new(path)
+        .parent()
+        .and_then(Path::to_str)
+        .unwrap_or("/");
+    let components: Vec<&str> = dir
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .take(depth)
+        .collect();
+    format!("/{}", components.join("/"))
+}
+
+/// Action without its details, e.g. "chmod" for "chmod 0644"
+pub(crate) fn verb(action: &str) -> String {
+    action
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Resolved name of a user or group, falling back to the numeric id
+fn id_name(name: &Option<String>, id: Option<u32>) -> String {
+    match (name, id) {
+        (Some(name), _) => name.clone(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "(unknown)".to_string(),
+    }
+}
+
+impl fmt::Display for Dimension {
+    /// Format as the name accepted by `--group-by`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dimension::Process => write!(f, "process"),
+            Dimension::Path => write!(f, "path"),
+            Dimension::Extension => write!(f, "extension"),
+            Dimension::DirDepth(depth) => write!(f, "dir-depth={}", depth),
+            Dimension::Action => write!(f, "action"),
+            Dimension::User => write!(f, "user"),
+            Dimension::Group => write!(f, "group"),
+            Dimension::Severity => write!(f, "severity"),
+            Dimension::Tag(key) => write!(f, "tag={}", key),
+            Dimension::Pattern => write!(f, "pattern"),
+        }
+    }
+}
+
+/// File format of an exported aggregate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Array of objects, one per group
+    Json,
+    /// Header row followed by one row per group
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guess the format from a file name, defaulting to JSON
+    ///
+    /// # Arguments
+    /// * `path` - Export destination
+    ///
+    /// # Returns
+    /// * `ExportFormat` - CSV for ".csv" files, JSON otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+/// How stats are grouped and where they are exported
+#[derive(Debug, Clone)]
+pub struct StatsConfig {
+    /// Dimensions forming each group, in column order
+    pub group_by: Vec<Dimension>,
+    /// File to export the final aggregate to, and its format
+    pub export: Option<(PathBuf, ExportFormat)>,
+    /// Directory levels of a heat map built instead of grouped counts
+    pub heat_map_depth: Option<usize>,
+    /// Rank files by lock contention instead of grouping counts
+    pub contention: bool,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            group_by: vec![Dimension::Process],
+            export: None,
+            heat_map_depth: None,
+            contention: false,
+        }
+    }
+}
+
+/// Event counts per group
+#[derive(Debug, Clone, Default)]
+pub struct StatsAggregate {
+    /// Dimensions forming each group
+    group_by: Vec<Dimension>,
+    /// Number of events per group, keyed by the dimension values
+    counts: BTreeMap<Vec<String>, u64>,
+}
+
+impl StatsAggregate {
+    /// Create an empty aggregate
+    ///
+    /// # Arguments
+    /// * `group_by` - Dimensions forming each group
+    ///
+    /// # Returns
+    /// * `StatsAggregate` - Aggregate with no events
+    pub fn new(group_by: Vec<Dimension>) -> Self {
+        Self {
+            group_by,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count one event, as the events it stands for if it was sampled
+    ///
+    /// # Arguments
+    /// * `event` - Annotated file event
+    pub fn record(&mut self, event: &FileEvent) {
+        let key = self.group_by.iter().map(|d| d.value_of(event)).collect();
+        *self.counts.entry(key).or_default() += event.weight();
+    }
+
+    /// Count one closed session
+    ///
+    /// # Arguments
+    /// * `event` - Close event that ended the session
+    /// * `session` - The session, giving the access pattern
+    pub fn record_session(&mut self, event: &FileEvent, session: &Session) {
+        let key = self
+            .group_by
+            .iter()
+            .map(|dimension| match dimension {
+                Dimension::Pattern => session
+                    .access
+                    .pattern()
+                    .map_or_else(|| "(no i/o)".to_string(), |p| p.to_string()),
+                other => other.value_of(event),
+            })
+            .collect();
+        *self.counts.entry(key).or_default() += event.weight();
+    }
+
+    /// Add counts that were aggregated elsewhere, e.g. in the kernel
+    ///
+    /// # Arguments
+    /// * `key` - Dimension values, in `group_by` order
+    /// * `count` - Number of events in the group
+    pub fn add(&mut self, key: Vec<String>, count: u64) {
+        *self.counts.entry(key).or_default() += count;
+    }
+
+    /// Dimensions forming each group
+    ///
+    /// # Returns
+    /// * `&[Dimension]` - Dimensions in column order
+    pub fn group_by(&self) -> &[Dimension] {
+        &self.group_by
+    }
+
+    /// Groups with their counts, busiest first
+    ///
+    /// # Returns
+    /// * `Vec<(&[String], u64)>` - Dimension values and event count
+    pub fn rows(&self) -> Vec<(&[String], u64)> {
+        let mut rows: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(key, &count)| (key.as_slice(), count))
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        rows
+    }
+
+    /// Render the aggregate for export
+    ///
+    /// # Arguments
+    /// * `format` - Export format
+    ///
+    /// # Returns
+    /// * `String` - Rendered aggregate
+    pub fn export(&self, format: ExportFormat) -> String {
+        let names: Vec<String> =
+            self.group_by.iter().map(ToString::to_string).collect();
+        let mut out = String::new();
+        match format {
+            ExportFormat::Csv => {
+                let _ = writeln!(out, "{},events", csv_row(&names));
+                for (key, count) in self.rows() {
+                    let _ = writeln!(out, "{},{}", csv_row(key), count);
+                }
+            }
+            ExportFormat::Json => {
+                let objects: Vec<String> = self
+                    .rows()
+                    .into_iter()
+                    .map(|(key, count)| {
+                        let fields: String = names
+                            .iter()
+                            .zip(key)
+                            .map(|(name, value)| {
+                                format!(
+                                    "{}:{},",
+                                    json_string(name),
+                                    json_string(value)
+                                )
+                            })
+                            .collect();
+                        format!("{{{}\"events\":{}}}", fields, count)
+                    })
+                    .collect();
+                let _ = writeln!(out, "[{}]", objects.join(","));
+            }
+        }
+        out
+    }
+}
+
+/// Join values into a CSV row, quoting those that need it
+pub(crate) fn csv_row(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| {
+            if value.contains([',', '"', '\n']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Sink that aggregates events and reports the totals when it finishes
+pub struct StatsSink<W> {
+    /// Running totals
+    aggregate: StatsAggregate,
+    /// File to export the final aggregate to, and its format
+    export: Option<(PathBuf, ExportFormat)>,
+    /// Destination for the summary table
+    writer: W,
+    /// Sessions being built, when grouping by access pattern
+    sessions: Option<SessionAggregator>,
+    /// Directory tree counts, replacing the aggregate when set
+    heat_map: Option<DirHeatMap>,
+    /// Lock requests per file, replacing the aggregate when set
+    contention: Option<LockContention>,
+}
+
+impl<W: Write + Send + 'static> StatsSink<W> {
+    /// Create a stats sink
+    ///
+    /// # Arguments
+    /// * `config` - Grouping and export settings
+    /// * `writer` - Destination for the summary table
+    ///
+    /// # Returns
+    /// * `StatsSink<W>` - New stats sink
+    pub fn new(config: StatsConfig, writer: W) -> Self {
+        let sessions = config
+            .group_by
+            .contains(&Dimension::Pattern)
+            .then(SessionAggregator::new);
+        Self {
+            aggregate: StatsAggregate::new(config.group_by),
+            export: config.export,
+            writer,
+            sessions,
+            heat_map: config.heat_map_depth.map(DirHeatMap::new),
+            contention: config.contention.then(LockContention::new),
+        }
+    }
+
+    /// Running totals, for counts that don't arrive as events
+    ///
+    /// # Returns
+    /// * `&mut StatsAggregate` - Aggregate written when the sink finishes
+    pub fn aggregate_mut(&mut self) -> &mut StatsAggregate {
+        &mut self.aggregate
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for StatsSink<W> {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        if let Some(heat_map) = &mut self.heat_map {
+            heat_map.record(event);
+            return Ok(());
+        }
+        if let Some(contention) = &mut self.contention {
+            contention.record(event);
+            return Ok(());
+        }
+        match &mut self.sessions {
+            Some(sessions) => {
+                for session in sessions.evict_exited_periodically() {
+                    self.aggregate
+                        .record_session(&session.closing_event(), &session);
+                }
+                if let Some(session) = sessions.observe(event) {
+                    self.aggregate.record_session(event, &session);
+                }
+            }
+            None => self.aggregate.record(event),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(sessions) = &mut self.sessions {
+            for session in sessions.drain() {
+                self.aggregate
+                    .record_session(&session.closing_event(), &session);
+            }
+        }
+        let lines: Vec<String> = match (&self.heat_map, &self.contention) {
+            (Some(heat_map), _) => heat_map
+                .rows()
+                .into_iter()
+                .map(|(dir, count)| format!("{:>8} | {}", count, dir))
+                .collect(),
+            (None, Some(contention)) => contention.lines(),
+            (None, None) => self
+                .aggregate
+                .rows()
+                .into_iter()
+                .map(|(key, count)| {
+                    format!("{:>8} | {}", count, key.join(" | "))
+                })
+                .collect(),
+        };
+        for line in lines {
+            writeln!(self.writer, "{}", line)
+                .context("Failed to write stats")?;
+        }
+        self.writer.flush().context("Failed to flush stats")?;
+
+        if let Some((path, format)) = &self.export {
+            let exported = match (&self.heat_map, &self.contention) {
+                (Some(heat_map), _) => heat_map.export(*format),
+                (None, Some(contention)) => contention.export(*format),
+                (None, None) => self.aggregate.export(*format),
+            };
+            fs::write(path, exported).with_context(|| {
+                format!("Failed to export stats to {}", path.display())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    fn event(program: &str, path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            program.to_string(),
+            FileAction::ModeChanged { mode: 0o644 },
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(Dimension::parse("process"), Ok(Dimension::Process));
+        assert_eq!(Dimension::parse("path"), Ok(Dimension::Path));
+        assert_eq!(Dimension::parse("dir-depth=2"), Ok(Dimension::DirDepth(2)));
+        assert_eq!(Dimension::parse("dir"), Ok(Dimension::DirDepth(1)));
+        assert!(Dimension::parse("dir-depth=0").is_err());
+        assert!(Dimension::parse("inode").is_err());
+        assert_eq!(
+            Dimension::parse("tag=team"),
+            Ok(Dimension::Tag("team".to_string()))
+        );
+        assert!(Dimension::parse("tag=").is_err());
+
+        let mut tagged = event("cc", "/srv/x");
+        tagged.tags.insert("team".to_string(), "db".to_string());
+        let team = Dimension::Tag("team".to_string());
+        assert_eq!(team.value_of(&tagged), "db");
+        assert_eq!(team.value_of(&event("cc", "/x")), "(none)");
+
+        let owned = event("cc", "/x").with_ids(1000, 100);
+        assert_eq!(Dimension::parse("user"), Ok(Dimension::User));
+        assert_eq!(Dimension::User.value_of(&owned), "1000");
+        assert_eq!(Dimension::Group.value_of(&event("cc", "/x")), "(unknown)");
+        assert_eq!(Dimension::parse("severity"), Ok(Dimension::Severity));
+    }
+
+    #[test]
+    fn test_rollup_by_extension_and_directory() {
+        let mut stats = StatsAggregate::new(vec![
+            Dimension::Extension,
+            Dimension::DirDepth(2),
+            Dimension::Action,
+        ]);
+        stats.record(&event("cc", "/home/a/src/x.c"));
+        stats.record(&event("cc", "/home/a/lib/y.c"));
+        stats.record(&event("sh", "/etc/profile"));
+        // A sampled event counts as the events it stands for
+        let mut sampled = event("sh", "/etc/passwd");
+        sampled.sample_rate = Some(8);
+        stats.record(&sampled);
+
+        assert_eq!(
+            stats.rows(),
+            vec![
+                (&["(none)".into(), "/etc".into(), "chmod".into()][..], 9),
+                (&["c".into(), "/home/a".into(), "chmod".into()][..], 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pattern_counts_closed_sessions() {
+        let config = StatsConfig {
+            group_by: vec![Dimension::Process, Dimension::Pattern],
+            export: None,
+            heat_map_depth: None,
+            contention: false,
+        };
+        let mut sink = StatsSink::new(config, Vec::new());
+        let io = |path: &str, action| {
+            FileEvent::new(
+                path.into(),
+                "pg".into(),
+                action,
+                1,
+                SystemClock.now(),
+            )
+        };
+        for action in [
+            FileAction::Opened,
+            FileAction::Read {
+                offset: Some(8192),
+                bytes: 8192,
+            },
+            FileAction::Read {
+                offset: Some(0),
+                bytes: 8192,
+            },
+            FileAction::Closed,
+        ] {
+            sink.write_event(&io("/db/base", action)).unwrap();
+        }
+        sink.write_event(&io("/db/conf", FileAction::Opened))
+            .unwrap();
+        sink.write_event(&io("/db/conf", FileAction::Closed))
+            .unwrap();
+        // Still open, so not counted until the sink finishes
+        sink.write_event(&io("/db/wal", FileAction::Opened))
+            .unwrap();
+
+        assert_eq!(
+            sink.aggregate.rows(),
+            vec![
+                (&["pg".into(), "(no i/o)".into()][..], 1),
+                (&["pg".into(), "random read-only".into()][..], 1),
+            ]
+        );
+        sink.finish().unwrap();
+        assert_eq!(
+            sink.aggregate.rows()[0],
+            (&["pg".into(), "(no i/o)".into()][..], 2)
+        );
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let mut stats =
+            StatsAggregate::new(vec![Dimension::Process, Dimension::Extension]);
+        stats.record(&event("a,b", "/x.rs"));
+
+        assert_eq!(
+            stats.export(ExportFormat::Csv),
+            "process,extension,events\n\"a,b\",rs,1\n"
+        );
+        assert_eq!(
+            stats.export(ExportFormat::Json),
+            "[{\"process\":\"a,b\",\"extension\":\"rs\",\"events\":1}]\n"
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("run1.csv")),
+            ExportFormat::Csv
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Suggest module
+//!
+//! Implements `fw suggest-filters`, which looks at a capture or a live
+//! sample of events and proposes exclusion rules for the noise in it.
+//! Rules are picked greedily, each time the process, directory (one to
+//! three levels deep) or extension leaving out the most remaining events,
+//! until the wanted share of the volume is gone. Rare events are never
+//! given up for volume: a rule is only proposed if it leaves every event
+//! of a rare program/directory pair, and every access to a sensitive
+//! file, in place. The rules are written in the syntax of
+//! `fw collect --exclude-file`.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::signal;
+
+use crate::collector;
+use crate::compression::read_capture;
+use crate::ebpf_monitor::EbpfMonitor;
+use crate::exclusions::Exclusion;
+use crate::report::{CapturedEvent, SENSITIVE_PREFIXES};
+use crate::stats::{dir_prefix, extension_of};
+
+/// Deepest directory level proposed as a path rule
+const MAX_RULE_DEPTH: usize = 3;
+
+/// Directory levels forming the program/directory pairs checked for
+/// rarity
+const RARITY_DEPTH: usize = 2;
+
+/// Smallest share of all events a rule must leave out to be proposed
+const MIN_RULE_SHARE: f64 = 0.01;
+
+/// How `fw suggest-filters` picks its rules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SuggestConfig {
+    /// Share of the events to leave out, from 0 to 1
+    pub target: f64,
+    /// Program/directory pairs with at most this many events are rare
+    pub keep_rare: u64,
+    /// Most rules proposed
+    pub max_rules: usize,
+}
+
+/// Proposed rule and what it leaves out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Rule for the exclusion file
+    pub rule: Exclusion,
+    /// Events it leaves out that no earlier rule did
+    pub events: u64,
+}
+
+/// Rules proposed for a sample of events
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestions {
+    /// Events in the sample
+    pub total: u64,
+    /// Rare or sensitive events no rule may leave out
+    pub kept: u64,
+    /// Share of the events the rules were meant to leave out
+    pub target: f64,
+    /// Rules, most effective first
+    pub rules: Vec<Suggestion>,
+    /// Picking stopped at the most rules allowed
+    pub capped: bool,
+}
+
+/// Rules that would leave out an event
+///
+/// # Arguments
+/// * `program` - Name of the program
+/// * `path` - File it accessed
+///
+/// # Returns
+/// * `Vec<Exclusion>` - Its process, each of its directories up to
+///   `MAX_RULE_DEPTH` levels deep, and its extension
+fn candidates(program: &str, path: &str) -> Vec<Exclusion> {
+    let mut rules = vec![Exclusion::Process(program.to_string())];
+    let dirs: BTreeSet<String> = (1..=MAX_RULE_DEPTH)
+        .map(|depth| dir_prefix(path, depth))
+        .filter(|dir| dir != "/")
+        .collect();
+    rules.extend(dirs.into_iter().map(Exclusion::Path));
+    let extension = extension_of(path);
+    if extension != "(none)" {
+        rules.push(Exclusion::Extension(extension));
+    }
+    rules
+}
+
+/// Propose exclusion rules for a sample of events
+///
+/// # Arguments
+/// * `events` - Program and path of each event
+/// * `config` - Target share and rarity threshold
+///
+/// # Returns
+/// * `Suggestions` - Rules, most effective first
+pub fn suggest(
+    events: &[(String, String)],
+    config: &SuggestConfig,
+) -> Suggestions {
+    let total = events.len() as u64;
+    let mut pairs: HashMap<(&str, String), u64> = HashMap::new();
+    for (program, path) in events {
+        *pairs
+            .entry((program.as_str(), dir_prefix(path, RARITY_DEPTH)))
+            .or_default() += 1;
+    }
+    let is_kept = |program: &str, path: &str| {
+        SENSITIVE_PREFIXES.iter().any(|p| path.starts_with(p))
+            || pairs[&(program, dir_prefix(path, RARITY_DEPTH))]
+                <= config.keep_rare
+    };
+
+    // Rules that would leave out a kept event are never proposed
+    let mut blocked = BTreeSet::new();
+    let mut remaining = Vec::new();
+    let mut kept = 0;
+    for (program, path) in events {
+        if is_kept(program, path) {
+            blocked.extend(candidates(program, path));
+            kept += 1;
+        } else {
+            remaining.push((program.as_str(), path.as_str()));
+        }
+    }
+
+    let wanted = (config.target * total as f64).ceil() as u64;
+    let minimum = ((MIN_RULE_SHARE * total as f64).ceil() as u64).max(1);
+    let mut rules: Vec<Suggestion> = Vec::new();
+    let mut excluded = 0;
+    while excluded < wanted && rules.len() < config.max_rules {
+        let mut counts: HashMap<Exclusion, u64> = HashMap::new();
+        for (program, path) in &remaining {
+            for rule in candidates(program, path) {
+                if !blocked.contains(&rule) {
+                    *counts.entry(rule).or_default() += 1;
+                }
+            }
+        }
+        // Most events first, then the first rule kind and name
+        let best = counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)));
+        let Some((rule, events)) = best.filter(|(_, n)| *n >= minimum) else {
+            break;
+        };
+        remaining.retain(|(program, path)| !rule.matches(program, path));
+        excluded += events;
+        rules.push(Suggestion { rule, events });
+    }
+    Suggestions {
+        total,
+        kept,
+        target: config.target,
+        capped: rules.len() == config.max_rules,
+        rules,
+    }
+}
+
+impl Suggestions {
+    /// Share of the events the rules leave out
+    ///
+    /// # Returns
+    /// * `f64` - Share from 0 to 1
+    pub fn excluded_share(&self) -> f64 {
+        let excluded: u64 = self.rules.iter().map(|s| s.events).sum();
+        excluded as f64 / self.total.max(1) as f64
+    }
+
+    /// Render the rules as an exclusion file
+    ///
+    /// # Arguments
+    /// * `source` - Where the events came from, for the header
+    ///
+    /// # Returns
+    /// * `String` - File for `fw collect --exclude-file`
+    pub fn render(&self, source: &str) -> String {
+        let share =
+            |events: u64| events as f64 * 100.0 / self.total.max(1) as f64;
+        let mut out = format!(
+            "# Suggested by fw suggest-filters from {} events ({})\n\
+             # Leaves out {:.1}% of them; {} rare or sensitive events are \
+             kept\n",
+            self.total,
+            source,
+            self.excluded_share() * 100.0,
+            self.kept
+        );
+        if self.excluded_share() < self.target {
+            let _ = writeln!(
+                out,
+                "# Short of the {:.0}% target: {}",
+                self.target * 100.0,
+                match self.capped {
+                    true => "more rules are needed (--max-rules)",
+                    false => "more would leave out rare events",
+                }
+            );
+        }
+        out.push_str("# Use with: fw collect --exclude-file FILE\n");
+        for suggestion in &self.rules {
+            let _ = write!(
+                out,
+                "\n# {:.1}% ({} events)\n{}\n",
+                share(suggestion.events),
+                suggestion.events,
+                suggestion.rule
+            );
+        }
+        out
+    }
+}
+
+/// Read the program and path of the events in captures
+///
+/// # Arguments
+/// * `inputs` - Captures or recordings of `fw collect` output
+///
+/// # Returns
+/// * `Result<Vec<(String, String)>>` - Program and path of each event
+fn read_events(inputs: &[PathBuf]) -> Result<Vec<(String, String)>> {
+    let mut events = Vec::new();
+    for input in inputs {
+        let text = read_capture(input)?;
+        events.extend(
+            text.lines()
+                .filter_map(CapturedEvent::parse)
+                .map(|event| (event.program, event.path)),
+        );
+    }
+    Ok(events)
+}
+
+/// Watch live events for a while
+///
+/// # Arguments
+/// * `duration` - How long to sample; Ctrl+C stops early
+///
+/// # Returns
+/// * `Result<Vec<(String, String)>>` - Program and path of each event,
+///   leaving out /proc, /sys and /dev like `fw collect`
+fn sample_events(duration: Duration) -> Result<Vec<(String, String)>> {
+    let mut events = Vec::new();
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+    rt.block_on(async {
+        let mut monitor =
+            EbpfMonitor::new().context("Failed to initialize eBPF monitor")?;
+        eprintln!("Sampling events for {:?} (Ctrl+C stops early)", duration);
+        let shutdown = async {
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {}
+                _ = signal::ctrl_c() => {}
+            }
+        };
+        collector::pump_events(&mut monitor, shutdown, |event| {
+            if !fw_common::is_pseudo_fs_path(event.file_path.as_bytes()) {
+                events.push((event.program_name, event.file_path));
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+        .await
+    })?;
+    Ok(events)
+}
+
+/// Run `fw suggest-filters`
+///
+/// # Arguments
+/// * `inputs` - Captures to analyse; a live sample is taken if empty
+/// * `sample` - How long to sample live events
+/// * `config` - Target share and rarity threshold
+/// * `output` - File to write the rules to; stdout if None
+///
+/// # Returns
+/// * `Result<()>` - Success or error result
+pub fn run_suggest(
+    inputs: &[PathBuf],
+    sample: Option<Duration>,
+    config: &SuggestConfig,
+    output: Option<&Path>,
+) -> Result<()> {
+    let (events, source) = match sample {
+        Some(duration) => {
+            (sample_events(duration)?, format!("a {:?} sample", duration))
+        }
+        None => {
+            let names: Vec<String> =
+                inputs.iter().map(|p| p.display().to_string()).collect();
+            (read_events(inputs)?, names.join(", "))
+        }
+    };
+    if events.is_empty() {
+        return Err(anyhow!("No events to analyse"));
+    }
+    let rules = suggest(&events, config).render(&source);
+    match output {
+        Some(path) => std::fs::write(path, rules)
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            print!("{}", rules);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exclusions::Exclusions;
+
+    fn events() -> Vec<(String, String)> {
+        let mut events = Vec::new();
+        let mut add = |program: &str, path: &str, count: usize| {
+            for i in 0..count {
+                events.push((program.to_string(), format!("{}{}", path, i)));
+            }
+        };
+        add("chrome", "/home/a/.cache/chrome/f", 50);
+        add("updatedb", "/var/lib/mlocate/db.tmp", 30);
+        add("vim", "/home/a/notes/n.md", 15);
+        // Rare: only twice under /home/a, and a sensitive file
+        add("ssh", "/home/a/.ssh/id_", 2);
+        add("sshd", "/etc/shadow", 1);
+        add("bash", "/etc/profile.d/x", 2);
+        events
+    }
+
+    fn config(target: f64) -> SuggestConfig {
+        SuggestConfig {
+            target,
+            keep_rare: 3,
+            max_rules: 20,
+        }
+    }
+
+    #[test]
+    fn test_suggest_keeps_rare_events() {
+        let events = events();
+        let suggestions = suggest(&events, &config(0.8));
+        assert_eq!(suggestions.total, 100);
+        assert_eq!(suggestions.kept, 5);
+        let rules: Vec<String> = suggestions
+            .rules
+            .iter()
+            .map(|s| format!("{} {}", s.rule, s.events))
+            .collect();
+        // Ties go to process rules; /home is off limits because of ssh
+        assert_eq!(rules, vec!["process chrome 50", "process updatedb 30"]);
+        assert!(suggestions.excluded_share() >= 0.8);
+
+        let exclusions =
+            Exclusions::parse(&suggestions.render("capture.log")).unwrap();
+        assert_eq!(exclusions.len(), 2);
+        for (program, path) in &events {
+            if program.starts_with("ssh") || program == "bash" {
+                assert!(!exclusions.matches(program, path));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unreachable_target() {
+        let suggestions = suggest(&events(), &config(0.99));
+        assert_eq!(suggestions.rules.len(), 3);
+        assert!(suggestions.excluded_share() < 0.99);
+        let rendered = suggestions.render("capture.log");
+        assert!(rendered.contains(
+            "# Short of the 99% target: more would leave out rare events"
+        ));
+        assert!(rendered.contains("\n# 15.0% (15 events)\nprocess vim\n"));
+    }
+}
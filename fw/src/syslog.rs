@@ -0,0 +1,330 @@
+//! Syslog module
+//!
+//! Sends reported events to the local syslog daemon through `/dev/log`.
+//! The severity a policy rule assigned becomes the syslog severity and
+//! the facility is configurable, so existing rsyslog/journald routing,
+//! logrotate rules and alerting can pick fw's alerts out by priority
+//! without parsing the message. When the daemon goes away (e.g. while
+//! it restarts) events are dropped and the socket is reconnected with
+//! exponential backoff.
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::{info, warn};
+use std::fmt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::fanout::EventSink;
+use crate::file_event::FileEvent;
+use crate::severity::Severity;
+
+/// Socket the local syslog daemon listens on
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Wait before the first attempt to reconnect to the daemon
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between attempts to reconnect, however many failed
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Syslog facility events are logged under
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Facility {
+    /// Generic user-level messages
+    #[default]
+    User,
+    /// Security and authorization messages
+    Auth,
+    /// Locally defined facility 0
+    Local0,
+    /// Locally defined facility 1
+    Local1,
+    /// Locally defined facility 2
+    Local2,
+    /// Locally defined facility 3
+    Local3,
+    /// Locally defined facility 4
+    Local4,
+    /// Locally defined facility 5
+    Local5,
+    /// Locally defined facility 6
+    Local6,
+    /// Locally defined facility 7
+    Local7,
+}
+
+impl Facility {
+    /// Facility number from RFC 5424
+    ///
+    /// # Returns
+    /// * `u8` - Numeric facility code
+    pub fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Auth => 4,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Facility::User => write!(f, "user"),
+            Facility::Auth => write!(f, "auth"),
+            local => write!(f, "local{}", local.code() - 16),
+        }
+    }
+}
+
+/// Syslog severity number for an event's severity
+///
+/// Events that weren't classified are logged as informational.
+///
+/// # Arguments
+/// * `severity` - Severity assigned by the policy, if any
+///
+/// # Returns
+/// * `u8` - crit (2), warning (4), notice (5) or info (6)
+pub fn syslog_severity(severity: Option<Severity>) -> u8 {
+    match severity {
+        Some(Severity::Critical) => 2,
+        Some(Severity::Warning) => 4,
+        Some(Severity::Notice) => 5,
+        Some(Severity::Info) | None => 6,
+    }
+}
+
+/// Where and under which facility events are logged
+#[derive(Debug, Clone)]
+pub struct SyslogConfig {
+    /// Facility every event is logged under
+    pub facility: Facility,
+    /// Datagram socket of the syslog daemon
+    pub socket: PathBuf,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            facility: Facility::default(),
+            socket: PathBuf::from(DEFAULT_SYSLOG_SOCKET),
+        }
+    }
+}
+
+/// Sink that logs each event as one syslog message
+pub struct SyslogSink {
+    /// Socket connected to the syslog daemon, or None while it is gone
+    socket: Option<UnixDatagram>,
+    /// Datagram socket of the syslog daemon
+    path: PathBuf,
+    /// Facility every event is logged under
+    facility: Facility,
+    /// Wait before the next attempt to reconnect, doubled on failure
+    delay: Duration,
+    /// When the socket may next be reconnected
+    retry_at: Instant,
+    /// Events dropped since the connection was lost
+    dropped: u64,
+}
+
+impl SyslogSink {
+    /// Connect to the syslog daemon
+    ///
+    /// # Arguments
+    /// * `config` - Socket and facility to log with
+    ///
+    /// # Returns
+    /// * `Result<SyslogSink>` - Connected sink, or error if nothing
+    ///   listens on the socket
+    pub fn new(config: &SyslogConfig) -> Result<Self> {
+        Ok(Self {
+            socket: Some(connect(&config.socket)?),
+            path: config.socket.clone(),
+            facility: config.facility,
+            delay: RECONNECT_DELAY,
+            retry_at: Instant::now(),
+            dropped: 0,
+        })
+    }
+
+    /// Reconnect to the daemon if the connection was lost and the
+    /// backoff has passed
+    ///
+    /// # Returns
+    /// * `Option<&UnixDatagram>` - Connected socket, or None if the
+    ///   daemon can't be reached yet
+    fn socket(&mut self) -> Option<&UnixDatagram> {
+        if self.socket.is_none() && Instant::now() >= self.retry_at {
+            match connect(&self.path) {
+                Ok(socket) => {
+                    info!(
+                        "Reconnected to syslog, {} events dropped while \
+                         it was unreachable",
+                        self.dropped
+                    );
+                    self.socket = Some(socket);
+                    self.delay = RECONNECT_DELAY;
+                    self.dropped = 0;
+                }
+                Err(e) => self.back_off(&e),
+            }
+        }
+        self.socket.as_ref()
+    }
+
+    /// Drop the connection and wait longer before the next attempt
+    ///
+    /// # Arguments
+    /// * `e` - Why the daemon couldn't be reached
+    fn back_off(&mut self, e: &anyhow::Error) {
+        if self.socket.take().is_some() {
+            warn!("Lost connection to syslog: {:#}", e);
+        }
+        self.retry_at = Instant::now() + self.delay;
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+
+    /// Format an event as an RFC 3164 message
+    ///
+    /// # Arguments
+    /// * `event` - Event to log
+    ///
+    /// # Returns
+    /// * `String` - "<PRI>timestamp fw[pid]: event line"
+    pub fn message(&self, event: &FileEvent) -> String {
+        let priority =
+            self.facility.code() * 8 + syslog_severity(event.severity);
+        format!(
+            "<{}>{} fw[{}]: {}",
+            priority,
+            event
+                .timestamp
+                .with_timezone(&Local)
+                .format("%b %e %H:%M:%S"),
+            std::process::id(),
+            event
+        )
+    }
+}
+
+/// Connect an unbound datagram socket to the syslog daemon
+fn connect(path: &Path) -> Result<UnixDatagram> {
+    let socket =
+        UnixDatagram::unbound().context("Failed to create syslog socket")?;
+    socket
+        .connect(path)
+        .with_context(|| format!("Failed to connect to {}", path.display()))?;
+    Ok(socket)
+}
+
+impl EventSink for SyslogSink {
+    /// Events are dropped rather than failing the sink while the daemon
+    /// can't be reached
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        let message = self.message(event);
+        let sent = match self.socket() {
+            Some(socket) => socket
+                .send(message.as_bytes())
+                .context("Failed to send event to syslog"),
+            None => {
+                self.dropped += 1;
+                return Ok(());
+            }
+        };
+        if let Err(e) = sent {
+            self.back_off(&e);
+            self.dropped += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    #[test]
+    fn test_priority_from_facility_and_severity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let config = SyslogConfig {
+            facility: Facility::Local3,
+            socket: path,
+        };
+        let mut sink = SyslogSink::new(&config).unwrap();
+
+        let mut event = FileEvent::new(
+            "/etc/shadow".to_string(),
+            "cat".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+        event.severity = Some(Severity::Critical);
+        sink.write_event(&event).unwrap();
+        let mut buf = [0; 512];
+        let len = daemon.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        // local3 (19) * 8 + crit (2)
+        assert!(message.starts_with("<154>"), "{}", message);
+        assert!(message.contains(" fw["));
+        assert!(message.ends_with("| opened | /etc/shadow !critical"));
+
+        assert_eq!(syslog_severity(None), 6);
+        assert_eq!(Facility::Local3.to_string(), "local3");
+        assert_eq!(Facility::Auth.code() * 8 + syslog_severity(None), 38);
+    }
+
+    #[test]
+    fn test_reconnects_after_daemon_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let config = SyslogConfig {
+            socket: path.clone(),
+            ..Default::default()
+        };
+        let mut sink = SyslogSink::new(&config).unwrap();
+        let event = FileEvent::new(
+            "/etc/passwd".to_string(),
+            "vi".to_string(),
+            FileAction::Opened,
+            7,
+            SystemClock.now(),
+        );
+
+        // The daemon restarts; events are dropped, not errors
+        drop(daemon);
+        std::fs::remove_file(&path).unwrap();
+        sink.write_event(&event).unwrap();
+        assert!(sink.socket.is_none());
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        sink.write_event(&event).unwrap();
+        assert!(sink.socket.is_none());
+        assert_eq!(sink.dropped, 2);
+        assert_eq!(sink.delay, RECONNECT_DELAY * 2);
+
+        // Once the backoff has passed the next event goes through
+        sink.retry_at = Instant::now();
+        sink.write_event(&event).unwrap();
+        let mut buf = [0; 512];
+        let len = daemon.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(message.ends_with("| opened | /etc/passwd"), "{}", message);
+        assert_eq!((sink.dropped, sink.delay), (0, RECONNECT_DELAY));
+    }
+}
@@ -0,0 +1,221 @@
+//! Systemd module
+//!
+//! Lets `fw collect` run as a `Type=notify` service. When systemd passes
+//! a notification socket, fw reports READY=1 once the event loop is up
+//! (probes attached, or waiting for a `--schedule` window), keeps a
+//! STATUS= line with event and drop counts current, and sends WATCHDOG=1
+//! while the event loop keeps recording heartbeats, so a wedged loop is
+//! restarted when `WatchdogSec=` is set.
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use crate::health::{Health, HealthSnapshot};
+
+/// How often status is reported when systemd runs no watchdog
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sends state changes to systemd's notification socket
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Notifier {
+    /// Connect to the socket systemd passed in `NOTIFY_SOCKET`
+    ///
+    /// # Returns
+    /// * `Result<Option<Notifier>>` - Notifier, None if fw was not started
+    ///   by systemd, or error if the socket address is invalid
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var("NOTIFY_SOCKET") {
+            Ok(path) => Self::new(&path).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Create a notifier for a socket address
+    ///
+    /// # Arguments
+    /// * `path` - Socket path, or abstract name starting with "@"
+    ///
+    /// # Returns
+    /// * `Result<Notifier>` - Notifier, or error if the address is invalid
+    pub fn new(path: &str) -> Result<Self> {
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None if path.starts_with('/') => SocketAddr::from_pathname(path),
+            None => return Err(anyhow!("Unsupported NOTIFY_SOCKET {}", path)),
+        }
+        .with_context(|| format!("Invalid NOTIFY_SOCKET {}", path))?;
+        let socket = UnixDatagram::unbound()
+            .context("Failed to create notification socket")?;
+        Ok(Self { socket, addr })
+    }
+
+    /// Send newline-separated state assignments such as "READY=1"
+    ///
+    /// # Arguments
+    /// * `state` - Assignments to send in one datagram
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or error if systemd can't be reached
+    pub fn notify(&self, state: &str) -> Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.addr)
+            .context("Failed to notify systemd")?;
+        Ok(())
+    }
+}
+
+/// Watchdog interval systemd expects pings within
+///
+/// # Arguments
+/// * `usec` - Value of `WATCHDOG_USEC`
+/// * `pid` - Value of `WATCHDOG_PID`, naming the process to ping from
+/// * `own_pid` - Pid of this process
+///
+/// # Returns
+/// * `Option<Duration>` - Interval, or None if no watchdog applies to us
+pub fn watchdog_interval(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    match usec?.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(usec) => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Status line shown by `systemctl status`
+///
+/// # Arguments
+/// * `health` - Current pipeline state
+///
+/// # Returns
+/// * `String` - "STATUS=" assignment with event and drop counts, and
+///   the degradation while overloaded
+pub fn status_line(health: &HealthSnapshot) -> String {
+    let mut line = format!(
+        "STATUS={}: {} events, {} dropped",
+        if health.attached {
+            "Monitoring"
+        } else {
+            "Probes detached"
+        },
+        health.events,
+        health.dropped
+    );
+    if health.degradation.is_degraded() {
+        line.push_str(&format!(" (overloaded: {})", health.degradation));
+    }
+    line
+}
+
+/// Report readiness, status and watchdog pings until cancelled
+///
+/// Watchdog pings are sent at half the interval systemd asked for, and
+/// only while the event loop's last heartbeat is within that interval.
+///
+/// # Arguments
+/// * `notifier` - Connection to systemd
+/// * `health` - State updated by the pipeline
+pub async fn supervise(notifier: Notifier, health: Health) {
+    let watchdog = watchdog_interval(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    );
+    if let Some(interval) = watchdog {
+        info!("Systemd watchdog enabled, pinging every {:?}", interval / 2);
+    }
+    let period = watchdog.map_or(STATUS_INTERVAL, |interval| {
+        (interval / 2).min(STATUS_INTERVAL)
+    });
+    let mut ticks = tokio::time::interval(period);
+    let mut ready = false;
+    loop {
+        ticks.tick().await;
+        let snapshot = health.snapshot();
+        let Some(since_beat) = snapshot.since_beat else {
+            continue; // Still starting up
+        };
+        let mut state = status_line(&snapshot);
+        if !ready {
+            state.push_str("\nREADY=1");
+            ready = true;
+        }
+        if watchdog.is_some_and(|interval| since_beat < interval) {
+            state.push_str("\nWATCHDOG=1");
+        }
+        if let Err(e) = notifier.notify(&state) {
+            debug!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overload::Degradation;
+
+    #[test]
+    fn test_notify_reaches_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::new(path.to_str().unwrap()).unwrap();
+        notifier.notify("READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        assert!(Notifier::new("relative/notify").is_err());
+        assert!(Notifier::new("@fw-test").is_ok());
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        let interval = watchdog_interval(Some("30000000"), None, 7);
+        assert_eq!(interval, Some(Duration::from_secs(30)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 7), interval);
+        // The watchdog belongs to another process of the unit
+        assert_eq!(watchdog_interval(Some("30000000"), Some("8"), 7), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 7), None);
+        assert_eq!(watchdog_interval(None, None, 7), None);
+
+        let snapshot = HealthSnapshot {
+            attached: true,
+            events: 120,
+            dropped: 3,
+            since_beat: None,
+            degradation: Degradation::default(),
+        };
+        assert_eq!(
+            status_line(&snapshot),
+            "STATUS=Monitoring: 120 events, 3 dropped"
+        );
+        let overloaded = HealthSnapshot {
+            degradation: Degradation {
+                enrichment_paused: false,
+                sample_rate: 8,
+            },
+            ..snapshot
+        };
+        assert_eq!(
+            status_line(&overloaded),
+            "STATUS=Monitoring: 120 events, 3 dropped \
+             (overloaded: 1 in 8 events sampled)"
+        );
+    }
+}
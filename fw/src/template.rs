@@ -0,0 +1,419 @@
+//! Template module
+//!
+//! Implements `fw collect --format template --template TEMPLATE`, which
+//! writes each event in a layout given on the command line, e.g. to
+//! match a parser that already exists:
+//!
+//! ```text
+//! {ts:%b %d %H:%M:%S} {comm:<16}[{pid:>6}] {action} {path}
+//! ```
+//!
+//! Placeholders name a field of the event, optionally followed by ':' and
+//! a spec. The time fields (`ts`, `mtime`) take a strftime format, in
+//! UTC; every other field takes a padding spec: an optional alignment
+//! ('<' left, the default, '>' right or '^' centre), a width and an
+//! optional '.' and largest number of characters kept. Fields the event
+//! doesn't have are written as "-", and "{{" and "}}" are literal
+//! braces. Templates are checked when fw starts, so a typo never
+//! surfaces as a malformed line later.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::Write;
+
+use crate::fanout::EventEncoder;
+use crate::file_event::{format_latency, FileEvent};
+
+/// Fields a placeholder may name, besides `tag.KEY`
+const FIELDS: [&str; 29] = [
+    "ts",
+    "comm",
+    "pid",
+    "uid",
+    "gid",
+    "user",
+    "group",
+    "action",
+    "path",
+    "truncated",
+    "mount",
+    "fstype",
+    "remote",
+    "layer",
+    "latency",
+    "latency_ns",
+    "id",
+    "fd",
+    "link",
+    "from",
+    "flags",
+    "xattr",
+    "tags",
+    "severity",
+    "rule",
+    "type",
+    "size",
+    "mtime",
+    "stack",
+];
+
+/// Fields whose spec is a strftime format
+const TIME_FIELDS: [&str; 2] = ["ts", "mtime"];
+
+/// Prefix of placeholders naming a single tag
+const TAG_PREFIX: &str = "tag.";
+
+/// Written for fields the event doesn't have
+const MISSING: &str = "-";
+
+/// Side a padded value is pushed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Centre,
+}
+
+/// How a field's value is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Spec {
+    /// As is
+    Plain,
+    /// Padded to a width and optionally cut to a length
+    Pad {
+        align: Align,
+        width: usize,
+        max: Option<usize>,
+    },
+    /// Time written with a strftime format
+    Time(String),
+}
+
+/// Piece of a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// Literal text
+    Text(String),
+    /// Field of the event; the name is from `FIELDS` or starts with
+    /// `TAG_PREFIX`
+    Field { name: String, spec: Spec },
+}
+
+/// Parsed `--template` layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventTemplate {
+    /// Template as given, for summaries
+    text: String,
+    /// Literal text and placeholders in order
+    parts: Vec<Part>,
+}
+
+/// Parse the spec after a field name
+///
+/// # Arguments
+/// * `name` - Field the spec is for
+/// * `spec` - Text after the ':', if any
+///
+/// # Returns
+/// * `Result<Spec, String>` - Spec, or a usage error
+fn parse_spec(name: &str, spec: Option<&str>) -> Result<Spec, String> {
+    let Some(spec) = spec else {
+        return Ok(Spec::Plain);
+    };
+    if TIME_FIELDS.contains(&name) {
+        if StrftimeItems::new(spec).any(|item| item == Item::Error) {
+            return Err(format!(
+                "invalid time format '{}' in {{{}}}",
+                spec, name
+            ));
+        }
+        return Ok(Spec::Time(spec.to_string()));
+    }
+    let bad = || {
+        format!(
+            "invalid spec '{}' in {{{}}}; expected e.g. '<16', '>6' or '8.8'",
+            spec, name
+        )
+    };
+    let (align, rest) = match spec.chars().next() {
+        Some('<') => (Align::Left, &spec[1..]),
+        Some('>') => (Align::Right, &spec[1..]),
+        Some('^') => (Align::Centre, &spec[1..]),
+        _ => (Align::Left, spec),
+    };
+    let (width, max) = match rest.split_once('.') {
+        Some((width, max)) => (width, Some(max)),
+        None => (rest, None),
+    };
+    let width = match width {
+        "" => 0,
+        width => width.parse().map_err(|_| bad())?,
+    };
+    let max = max.map(|m| m.parse().map_err(|_| bad())).transpose()?;
+    if width == 0 && max.is_none() {
+        return Err(bad());
+    }
+    Ok(Spec::Pad { align, width, max })
+}
+
+impl EventTemplate {
+    /// Parse and check a template
+    ///
+    /// # Arguments
+    /// * `text` - Template (see the module documentation)
+    ///
+    /// # Returns
+    /// * `Result<EventTemplate, String>` - Template, or error naming an
+    ///   unknown field, a bad spec or an unmatched brace
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..at]);
+            let brace = &rest[at..at + 1];
+            rest = &rest[at + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                // Doubled braces are literal
+                literal.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err(format!("unmatched '}}' in '{}'", text));
+            }
+            let Some(end) = rest.find('}') else {
+                return Err(format!("unclosed '{{' in '{}'", text));
+            };
+            let (name, spec) = match rest[..end].split_once(':') {
+                Some((name, spec)) => (name, Some(spec)),
+                None => (&rest[..end], None),
+            };
+            let is_tag = name
+                .strip_prefix(TAG_PREFIX)
+                .is_some_and(|key| !key.is_empty());
+            if !is_tag && !FIELDS.contains(&name) {
+                return Err(format!(
+                    "unknown field {{{}}} (expected {{tag.KEY}} or one of {})",
+                    name,
+                    FIELDS.join(", ")
+                ));
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Field {
+                name: name.to_string(),
+                spec: parse_spec(name, spec)?,
+            });
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Self {
+            text: text.to_string(),
+            parts,
+        })
+    }
+
+    /// Template as given
+    ///
+    /// # Returns
+    /// * `&str` - Text the template was parsed from
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Write an event in the template's layout
+    ///
+    /// # Arguments
+    /// * `event` - Event to write
+    ///
+    /// # Returns
+    /// * `String` - Line for the event, without a newline
+    pub fn render(&self, event: &FileEvent) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field {
+                    name,
+                    spec: Spec::Time(format),
+                } => line.push_str(&match time_field(name, event) {
+                    Some(time) => time.format(format).to_string(),
+                    None => MISSING.to_string(),
+                }),
+                Part::Field { name, spec } => {
+                    let value = field(name, event);
+                    pad(&mut line, &value, spec);
+                }
+            }
+        }
+        line
+    }
+}
+
+impl EventEncoder for EventTemplate {
+    fn encode(
+        &self,
+        writer: &mut dyn Write,
+        event: &FileEvent,
+    ) -> anyhow::Result<()> {
+        Ok(writeln!(writer, "{}", self.render(event))?)
+    }
+}
+
+/// Value of a time field
+fn time_field(name: &str, event: &FileEvent) -> Option<DateTime<Utc>> {
+    match name {
+        "ts" => Some(event.timestamp),
+        _ => event.file_modified,
+    }
+}
+
+/// Value of a field, "-" if the event doesn't have it
+///
+/// # Arguments
+/// * `name` - Field from `FIELDS`, or `tag.KEY`
+/// * `event` - Event to read it from
+///
+/// # Returns
+/// * `String` - Field as written without a spec
+fn field(name: &str, event: &FileEvent) -> String {
+    fn or_missing<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(|| MISSING.to_string(), |v| v.to_string())
+    }
+    if let Some(key) = name.strip_prefix(TAG_PREFIX) {
+        return or_missing(event.tags.get(key));
+    }
+    match name {
+        "ts" | "mtime" => or_missing(
+            time_field(name, event)
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ),
+        "comm" => event.program_name.clone(),
+        "pid" => event.pid.to_string(),
+        "uid" => or_missing(event.uid),
+        "gid" => or_missing(event.gid),
+        "user" => or_missing(event.user.as_ref()),
+        "group" => or_missing(event.group.as_ref()),
+        "action" => event.action.to_string(),
+        "path" => event.file_path.clone(),
+        "truncated" => event.path_truncated.to_string(),
+        "mount" => or_missing(event.mount_point.as_ref()),
+        "fstype" => or_missing(event.fs_type.as_ref()),
+        "remote" => or_missing(event.remote_source.as_ref()),
+        "layer" => or_missing(event.overlay_layer),
+        "latency" => or_missing(event.open_latency_ns.map(format_latency)),
+        "latency_ns" => or_missing(event.open_latency_ns),
+        "id" => or_missing(event.file_id),
+        "fd" => or_missing(event.fd),
+        "link" => or_missing(event.link_source.as_ref()),
+        "from" => or_missing(event.renamed_from.as_ref()),
+        "flags" => or_missing(event.open_flags.map(|f| format!("{:#o}", f))),
+        "xattr" => or_missing(event.xattr_name.as_ref()),
+        "tags" if event.tags.is_empty() => MISSING.to_string(),
+        "tags" => {
+            let tags: Vec<String> = event
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            tags.join(",")
+        }
+        "severity" => or_missing(event.severity),
+        "rule" => or_missing(event.rule.as_ref()),
+        "type" => or_missing(event.file_type),
+        "size" => or_missing(event.file_size),
+        "stack" => match &event.stack {
+            Some(stack) if !stack.frames.is_empty() => {
+                let frames: Vec<String> =
+                    stack.frames.iter().map(|f| f.to_string()).collect();
+                frames.join(" < ")
+            }
+            _ => MISSING.to_string(),
+        },
+        _ => unreachable!("unchecked template field {}", name),
+    }
+}
+
+/// Append a value laid out by a padding spec
+fn pad(line: &mut String, value: &str, spec: &Spec) {
+    let Spec::Pad { align, width, max } = *spec else {
+        line.push_str(value);
+        return;
+    };
+    let kept: String = match max {
+        Some(max) => value.chars().take(max).collect(),
+        None => value.to_string(),
+    };
+    let fill = width.saturating_sub(kept.chars().count());
+    let (before, after) = match align {
+        Align::Left => (0, fill),
+        Align::Right => (fill, 0),
+        Align::Centre => (fill / 2, fill - fill / 2),
+    };
+    line.extend(std::iter::repeat_n(' ', before));
+    line.push_str(&kept);
+    line.extend(std::iter::repeat_n(' ', after));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use chrono::TimeZone;
+
+    fn event() -> FileEvent {
+        let mut event = FileEvent::new(
+            "/etc/hosts".to_string(),
+            "vim".to_string(),
+            FileAction::Opened,
+            4242,
+            SystemClock.now(),
+        );
+        event.timestamp = Utc.with_ymd_and_hms(2024, 3, 9, 7, 5, 1).unwrap();
+        event.tags.insert("team".to_string(), "infra".to_string());
+        event
+    }
+
+    #[test]
+    fn test_render() {
+        let template = EventTemplate::parse(
+            "{ts:%b %d %H:%M:%S} {comm:<6}[{pid:>6}] {action} {path}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&event()),
+            "Mar 09 07:05:01 vim   [  4242] opened /etc/hosts"
+        );
+        let template =
+            EventTemplate::parse("{{{tag.team}}} {size}|{user:^5.3}|").unwrap();
+        assert_eq!(template.render(&event()), "{infra} -|  -  |");
+        let template = EventTemplate::parse("{ts} {comm:.2} {tags}").unwrap();
+        assert_eq!(
+            template.render(&event()),
+            "2024-03-09T07:05:01.000Z vi team=infra"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (bad, why) in [
+            ("{ts} {prog}", "unknown field {prog}"),
+            ("{path", "unclosed"),
+            ("path}", "unmatched"),
+            ("{pid:>x}", "invalid spec"),
+            ("{pid:<}", "invalid spec"),
+            ("{ts:%Q}", "invalid time format"),
+            ("{tag.}", "unknown field"),
+        ] {
+            let err = EventTemplate::parse(bad).unwrap_err();
+            assert!(err.contains(why), "{}: {}", bad, err);
+        }
+        assert!(EventTemplate::parse("{{literal}} only").is_ok());
+    }
+}
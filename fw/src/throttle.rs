@@ -0,0 +1,589 @@
+//! Throttle module
+//!
+//! Keeps alert sinks readable when a policy rule
+//! fires thousands of times a second. A rule with a dedup window passes
+//! the first of a run of identical alerts (same rule, program, action and
+//! path) and holds back the rest; once the window has passed, one summary
+//! follows: the last alert held back, tagged with how many were (e.g.
+//! "{repeats=4121}"). A rate limit then caps the alerts above info passed
+//! per window over all rules; what it drops is logged and reported to
+//! the sinks as dropped. Tripwire alerts are never held back or dropped.
+//!
+//! The limits are global: every alert sink (`--exec`, `--syslog`,
+//! `--notify-*`, `--pagerduty-*`, `--baseline`, `--influx-url` and
+//! `--verify-with-inotify`) is fed by one [`ThrottlingSink`], so an
+//! alert is counted once however many sinks receive it. Sinks that need
+//! every event, like the inotify cross-check pairing the probes' events,
+//! are still shown the ones held back.
+//!
+//! Windows follow event timestamps, so replayed captures are throttled
+//! as they were when recorded. While no events arrive, the sink's tick
+//! moves that time on from the latest event, so a summary is sent once
+//! its window has passed rather than with the next alert.
+
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::warn;
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::fanout::{self, EventSink, Subscriber};
+use crate::file_event::FileEvent;
+use crate::filter::FilterSpec;
+use crate::severity::Severity;
+use crate::tripwire::TRIPWIRE_RULE;
+
+/// Tag a summary carries with the number of alerts it stands for
+pub const REPEATS_TAG: &str = "repeats";
+
+/// Most alerts passed per window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Alerts passed in each window
+    pub count: u32,
+    /// Length of a window
+    pub window: Duration,
+}
+
+/// Limits an alert sink applies, from the severity policy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlertLimits {
+    /// Time identical alerts are held back for, by rule name
+    pub dedup: HashMap<String, Duration>,
+    /// Most alerts above info passed per window, over all rules
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl AlertLimits {
+    /// Check whether the limits leave every alert through
+    ///
+    /// # Returns
+    /// * `bool` - True if no rule dedups and there is no rate limit
+    pub fn is_empty(&self) -> bool {
+        self.dedup.is_empty() && self.rate_limit.is_none()
+    }
+}
+
+/// Rule, program, action and path of an alert
+type AlertKey = (String, String, String, String);
+
+/// Run of identical alerts inside a dedup window
+struct Held {
+    /// When the window closes
+    until: DateTime<Utc>,
+    /// Alerts held back so far
+    count: u64,
+    /// Last alert held back, which the summary reports
+    last: Option<FileEvent>,
+}
+
+/// Deduplication and rate limiting state of one alert sink
+pub struct AlertThrottle {
+    /// Limits applied
+    limits: AlertLimits,
+    /// Open dedup windows
+    held: HashMap<AlertKey, Held>,
+    /// Earliest time a dedup window closes
+    next_close: Option<DateTime<Utc>>,
+    /// When the current rate limit window closes
+    rate_until: Option<DateTime<Utc>>,
+    /// Alerts passed in the current rate limit window
+    passed: u32,
+    /// Alerts dropped in the current rate limit window
+    limited: u64,
+    /// Alerts dropped in closed windows and not yet reported
+    unreported: u64,
+}
+
+/// Time a window lasts, as a timestamp offset
+fn span(window: Duration) -> TimeDelta {
+    TimeDelta::from_std(window).unwrap_or(TimeDelta::MAX)
+}
+
+impl AlertThrottle {
+    /// Create a throttle with nothing held back
+    ///
+    /// # Arguments
+    /// * `limits` - Dedup windows and rate limit to apply
+    ///
+    /// # Returns
+    /// * `AlertThrottle` - New throttle
+    pub fn new(limits: AlertLimits) -> Self {
+        Self {
+            limits,
+            held: HashMap::new(),
+            next_close: None,
+            rate_until: None,
+            passed: 0,
+            limited: 0,
+            unreported: 0,
+        }
+    }
+
+    /// Check whether an event passes deduplication
+    ///
+    /// # Arguments
+    /// * `event` - Classified event
+    ///
+    /// # Returns
+    /// * `bool` - False if it repeats an alert inside its rule's window
+    pub fn admit(&mut self, event: &FileEvent) -> bool {
+        let Some(rule) = &event.rule else {
+            return true;
+        };
+        if rule == TRIPWIRE_RULE {
+            return true;
+        }
+        let Some(&window) = self.limits.dedup.get(rule) else {
+            return true;
+        };
+        let key = (
+            rule.clone(),
+            event.program_name.clone(),
+            event.action.to_string(),
+            event.file_path.clone(),
+        );
+        if let Some(held) = self.held.get_mut(&key) {
+            if event.timestamp < held.until {
+                held.count += 1;
+                held.last = Some(event.clone());
+                return false;
+            }
+        }
+        let until = event.timestamp + span(window);
+        self.next_close = Some(self.next_close.map_or(until, |t| t.min(until)));
+        self.held.insert(
+            key,
+            Held {
+                until,
+                count: 0,
+                last: None,
+            },
+        );
+        true
+    }
+
+    /// Close the dedup windows and rate limit window that have passed
+    ///
+    /// # Arguments
+    /// * `now` - Time of the latest event
+    ///
+    /// # Returns
+    /// * `Vec<FileEvent>` - Summaries of the windows that held alerts back
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<FileEvent> {
+        if self.rate_until.is_some_and(|t| now >= t) {
+            self.rate_until = None;
+            self.passed = 0;
+            self.unreported += mem::take(&mut self.limited);
+        }
+        if self.next_close.is_none_or(|t| now < t) {
+            return Vec::new();
+        }
+        let mut summaries = Vec::new();
+        self.held.retain(|_, held| {
+            if now < held.until {
+                return true;
+            }
+            summaries.extend(summary(held));
+            false
+        });
+        self.next_close = self.held.values().map(|h| h.until).min();
+        summaries.sort_by_key(|s| s.timestamp);
+        summaries
+    }
+
+    /// Close every dedup window, e.g. when collection stops
+    ///
+    /// # Returns
+    /// * `Vec<FileEvent>` - Summaries of the windows that held alerts back
+    pub fn flush(&mut self) -> Vec<FileEvent> {
+        self.next_close = None;
+        let mut summaries: Vec<FileEvent> = self
+            .held
+            .drain()
+            .filter_map(|(_, mut held)| summary(&mut held))
+            .collect();
+        summaries.sort_by_key(|s| s.timestamp);
+        summaries
+    }
+
+    /// Check whether an event fits in the rate limit
+    ///
+    /// # Arguments
+    /// * `event` - Classified event, or summary
+    ///
+    /// # Returns
+    /// * `bool` - False if the window's alerts above info are used up
+    pub fn within_rate(&mut self, event: &FileEvent) -> bool {
+        let Some(limit) = self.limits.rate_limit else {
+            return true;
+        };
+        if event.severity.is_none_or(|s| s <= Severity::Info)
+            || event.rule.as_deref() == Some(TRIPWIRE_RULE)
+        {
+            return true;
+        }
+        if self.rate_until.is_none_or(|t| event.timestamp >= t) {
+            self.rate_until = Some(event.timestamp + span(limit.window));
+            self.passed = 0;
+            self.unreported += mem::take(&mut self.limited);
+        }
+        if self.passed < limit.count {
+            self.passed += 1;
+            return true;
+        }
+        self.limited += 1;
+        false
+    }
+
+    /// Take the count of alerts the rate limit dropped
+    ///
+    /// # Arguments
+    /// * `closing` - Also count the current window, e.g. when collection
+    ///   stops
+    ///
+    /// # Returns
+    /// * `u64` - Alerts dropped since the last call
+    pub fn take_limited(&mut self, closing: bool) -> u64 {
+        if closing {
+            self.unreported += mem::take(&mut self.limited);
+        }
+        mem::take(&mut self.unreported)
+    }
+}
+
+/// Summary of a run of identical alerts
+///
+/// # Returns
+/// * `Option<FileEvent>` - Last alert held back, tagged with how many
+///   were, or None if none was
+fn summary(held: &mut Held) -> Option<FileEvent> {
+    let mut event = held.last.take()?;
+    event
+        .tags
+        .insert(REPEATS_TAG.to_string(), held.count.to_string());
+    Some(event)
+}
+
+/// Sink that deduplicates and rate limits alerts, under one set of
+/// limits, before the alert sinks
+pub struct ThrottlingSink {
+    /// Deduplication and rate limiting state shared by the alert sinks
+    throttle: AlertThrottle,
+    /// Alert sinks receiving what gets through, with their filters
+    inner: Vec<Subscriber>,
+    /// Timestamp of the latest event, and when it arrived, to close
+    /// windows by while no events arrive
+    latest: Option<(DateTime<Utc>, Instant)>,
+}
+
+impl ThrottlingSink {
+    /// Throttle the alerts a group of subscribers receives together
+    ///
+    /// Each alert is deduplicated and counted against the rate limit
+    /// once, however many of the subscribers receive it, and each still
+    /// applies its own filter and route.
+    ///
+    /// # Arguments
+    /// * `subscribers` - Alert sinks to throttle
+    /// * `limits` - Dedup windows and rate limit to apply
+    ///
+    /// # Returns
+    /// * `Subscriber` - One subscriber feeding all of them
+    pub fn wrap_all(
+        subscribers: Vec<Subscriber>,
+        limits: AlertLimits,
+    ) -> Subscriber {
+        let name = subscribers
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>()
+            .join("+");
+        Subscriber::new(
+            name,
+            FilterSpec::default(),
+            Self {
+                throttle: AlertThrottle::new(limits),
+                inner: subscribers,
+                latest: None,
+            },
+        )
+    }
+
+    /// Pass an event to every sink it is for, unless the rate limit drops
+    /// it
+    fn deliver(&mut self, event: &FileEvent) {
+        if !self.throttle.within_rate(event) {
+            self.hold_back(event);
+            return;
+        }
+        // A failing sink is detached, like a failing subscriber
+        self.inner.retain_mut(|subscriber| {
+            if !subscriber.accepts(event) {
+                return true;
+            }
+            match subscriber.sink.write_event(event) {
+                Ok(()) => true,
+                Err(e) => {
+                    fanout::stopped(&subscriber.name, e);
+                    false
+                }
+            }
+        });
+    }
+
+    /// Show an event that isn't passed on to the sinks it was for
+    fn hold_back(&mut self, event: &FileEvent) {
+        for subscriber in &mut self.inner {
+            if subscriber.accepts(event) {
+                subscriber.sink.throttled(event);
+            }
+        }
+    }
+
+    /// Pass on the summaries of the dedup windows closed by a time
+    fn expire(&mut self, now: DateTime<Utc>) {
+        for summary in self.throttle.expire(now) {
+            self.deliver(&summary);
+        }
+    }
+
+    /// Report alerts the rate limit dropped
+    fn report_limited(&mut self, closing: bool) {
+        let count = self.throttle.take_limited(closing);
+        if count > 0 {
+            warn!("Alert rate limit dropped {} alerts", count);
+            for subscriber in &mut self.inner {
+                subscriber.sink.dropped("rate-limited", count);
+            }
+        }
+    }
+}
+
+impl EventSink for ThrottlingSink {
+    fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+        if !self.inner.iter().any(|s| s.accepts(event)) {
+            return Ok(());
+        }
+        self.latest = Some((event.timestamp, Instant::now()));
+        self.expire(event.timestamp);
+        match self.throttle.admit(event) {
+            true => self.deliver(event),
+            false => self.hold_back(event),
+        }
+        self.report_limited(false);
+        Ok(())
+    }
+
+    fn dropped(&mut self, source: &str, count: u64) {
+        for subscriber in &mut self.inner {
+            subscriber.sink.dropped(source, count);
+        }
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        // Windows go by event time, so while none arrive it moves on
+        // from the latest at the pace of the wall clock
+        if let Some((timestamp, arrived)) = self.latest {
+            self.expire(timestamp + span(arrived.elapsed()));
+            self.report_limited(false);
+        }
+        self.inner
+            .retain_mut(|subscriber| match subscriber.sink.tick() {
+                Ok(()) => true,
+                Err(e) => {
+                    fanout::stopped(&subscriber.name, e);
+                    false
+                }
+            });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for summary in self.throttle.flush() {
+            self.deliver(&summary);
+        }
+        self.report_limited(true);
+        // Every sink gets to finish; the first failure is reported
+        let mut failed = None;
+        for subscriber in &mut self.inner {
+            if let Err(e) = subscriber.sink.finish() {
+                let name = subscriber.name.clone();
+                failed.get_or_insert(e.context(format!("Sink '{}'", name)));
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use std::sync::{Arc, Mutex};
+
+    /// Sink recording what reaches it
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<FileEvent>>>,
+        dropped: Arc<Mutex<u64>>,
+    }
+
+    impl EventSink for Recorder {
+        fn write_event(&mut self, event: &FileEvent) -> Result<()> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+
+        fn dropped(&mut self, _source: &str, count: u64) {
+            *self.dropped.lock().unwrap() += count;
+        }
+    }
+
+    fn alert(path: &str, rule: &str, secs: i64) -> FileEvent {
+        let mut event = FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        );
+        event.timestamp = DateTime::UNIX_EPOCH + TimeDelta::seconds(secs);
+        event.severity = Some(Severity::Warning);
+        event.rule = Some(rule.to_string());
+        event
+    }
+
+    fn limits(rate_limit: Option<RateLimit>) -> AlertLimits {
+        AlertLimits {
+            dedup: HashMap::from([(
+                "keys".to_string(),
+                Duration::from_secs(30),
+            )]),
+            rate_limit,
+        }
+    }
+
+    #[test]
+    fn test_dedup_summarizes_repeats() {
+        let recorder = Recorder::default();
+        let subscriber =
+            Subscriber::new("test", Default::default(), recorder.clone());
+        let mut sink =
+            ThrottlingSink::wrap_all(vec![subscriber], limits(None)).sink;
+        for (path, rule, secs) in [
+            ("/etc/ssh/key", "keys", 0),
+            ("/etc/ssh/key", "keys", 1),
+            ("/etc/ssh/other", "keys", 2),
+            ("/etc/ssh/key", "keys", 10),
+            ("/etc/ssh/key", "logs", 11),
+            ("/etc/ssh/key", "logs", 12),
+            // Closes the first window, which then starts over
+            ("/etc/ssh/key", "keys", 31),
+            ("/etc/ssh/key", "keys", 32),
+        ] {
+            sink.write_event(&alert(path, rule, secs)).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let events = recorder.events.lock().unwrap();
+        let seen: Vec<(i64, Option<&str>)> = events
+            .iter()
+            .map(|e| {
+                (
+                    e.timestamp.timestamp(),
+                    e.tags.get(REPEATS_TAG).map(String::as_str),
+                )
+            })
+            .collect();
+        assert_eq!(
+            seen,
+            vec![
+                (0, None),
+                (2, None),
+                (11, None),
+                (12, None),
+                (10, Some("2")),
+                (31, None),
+                (32, Some("1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let recorder = Recorder::default();
+        let subscriber =
+            Subscriber::new("test", Default::default(), recorder.clone());
+        let rate_limit = RateLimit {
+            count: 2,
+            window: Duration::from_secs(60),
+        };
+        let mut limits = limits(Some(rate_limit));
+        limits
+            .dedup
+            .insert(TRIPWIRE_RULE.to_string(), Duration::from_secs(60));
+        let mut sink = ThrottlingSink::wrap_all(vec![subscriber], limits).sink;
+        let mut info = alert("/tmp/x", "other-any", 3);
+        info.severity = Some(Severity::Info);
+        for secs in 0..5 {
+            sink.write_event(&alert("/var/log/a", "logs", secs))
+                .unwrap();
+        }
+        sink.write_event(&info).unwrap();
+        assert_eq!(*recorder.dropped.lock().unwrap(), 0);
+        sink.write_event(&alert("/var/log/a", "logs", 60)).unwrap();
+        assert_eq!(*recorder.dropped.lock().unwrap(), 3);
+        sink.write_event(&alert("/var/log/a", "logs", 61)).unwrap();
+        sink.write_event(&alert("/var/log/a", "logs", 62)).unwrap();
+        // Tripwire alerts are neither deduplicated nor rate limited
+        for _ in 0..3 {
+            let tripwire = alert("/srv/passwords.txt", TRIPWIRE_RULE, 62);
+            sink.write_event(&tripwire).unwrap();
+        }
+        sink.finish().unwrap();
+        assert_eq!(*recorder.dropped.lock().unwrap(), 4);
+        assert_eq!(recorder.events.lock().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_limits_are_shared_and_windows_close_on_tick() {
+        let (first, second) = (Recorder::default(), Recorder::default());
+        let subscribers = vec![
+            Subscriber::new("first", Default::default(), first.clone()),
+            Subscriber::new("second", Default::default(), second.clone()),
+        ];
+        let limits = AlertLimits {
+            dedup: HashMap::from([(
+                "keys".to_string(),
+                Duration::from_millis(20),
+            )]),
+            rate_limit: Some(RateLimit {
+                count: 3,
+                window: Duration::from_secs(60),
+            }),
+        };
+        let mut sink = ThrottlingSink::wrap_all(subscribers, limits).sink;
+        // Each alert counts once against the limit, whichever sinks
+        // receive it
+        sink.write_event(&alert("/var/log/a", "logs", 0)).unwrap();
+        sink.write_event(&alert("/etc/ssh/key", "keys", 0)).unwrap();
+        sink.write_event(&alert("/etc/ssh/key", "keys", 0)).unwrap();
+        assert_eq!(first.events.lock().unwrap().len(), 2);
+        assert_eq!(second.events.lock().unwrap().len(), 2);
+
+        // The summary is sent once the window passes, not with the next
+        // alert, and uses up the limit shared by both sinks
+        std::thread::sleep(Duration::from_millis(30));
+        sink.tick().unwrap();
+        let events = first.events.lock().unwrap().clone();
+        assert_eq!(events[2].tags.get(REPEATS_TAG).unwrap(), "1");
+        assert_eq!(second.events.lock().unwrap().len(), 3);
+        sink.write_event(&alert("/var/log/b", "logs", 1)).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(*first.dropped.lock().unwrap(), 1);
+        assert_eq!(*second.dropped.lock().unwrap(), 1);
+    }
+}
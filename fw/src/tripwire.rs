@@ -0,0 +1,480 @@
+//! Tripwire module
+//!
+//! Plants decoy files nothing legitimate ever touches (honeyfiles) and
+//! raises the alarm when something does. `fw tripwire add` creates the
+//! decoys and records them in a file, one path per line; `fw collect
+//! --tripwires` makes every access to a recorded path critical under the
+//! policy rule "tripwire", so it reaches the alert sinks, and tags it
+//! with the chain of processes that led to it ("ancestry"), e.g.
+//! "cat(4242) < bash(4100) < sshd(900) < systemd(1)". With
+//! `--tripwire-stacks` the probes also record its stack, checked against
+//! the decoy paths in the kernel.
+//!
+//! Decoys are matched by their exact path, so a tripwire ranks above any
+//! class of the policy it sits in. The paths are also pushed to the
+//! probes, so opening a decoy is reported even by processes the kernel
+//! filters leave out and while overload sampling or kernel aggregation
+//! is on, and the alert sinks never deduplicate or rate limit tripwire
+//! alerts. Ancestry is read from /proc when the event is enriched; a
+//! process that already exited is recorded as such. Tripwires are armed
+//! when collection starts.
+//!
+//! Decoys `fw tripwire add` created are recorded with the device and
+//! inode of the file it planted ("path<TAB>planted DEV:INO"), and only
+//! those are deleted by `fw tripwire remove --delete`; existing files
+//! turned into decoys are never deleted.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::enrich::Enricher;
+use crate::file_event::FileEvent;
+use crate::watchlist::{lock_for_edit, read_for_edit, replace};
+
+/// File `fw tripwire` edits unless `--file` says otherwise
+pub const DEFAULT_TRIPWIRE_FILE: &str = "/etc/fw/tripwires";
+
+/// Policy rule, and class, of accesses to a tripwire
+pub const TRIPWIRE_RULE: &str = "tripwire";
+
+/// Processes followed up the tree at most, in case of a /proc loop
+const MAX_ANCESTRY: usize = 32;
+
+/// Marker after the tab of a decoy fw created, followed by its identity
+const PLANTED: &str = "planted ";
+
+/// Decoy recorded in a tripwire file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    /// Absolute path of the decoy
+    path: String,
+    /// Device and inode of the file fw planted there, if it created it
+    planted: Option<(u64, u64)>,
+}
+
+impl Entry {
+    /// Render the entry as a line of the tripwire file, without newline
+    fn line(&self) -> String {
+        match self.planted {
+            Some((dev, ino)) => {
+                format!("{}\t{}{}:{}", self.path, PLANTED, dev, ino)
+            }
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// Parse a tripwire file, skipping blanks and '#' comments
+///
+/// # Arguments
+/// * `text` - One absolute path per line
+///
+/// # Returns
+/// * `Result<Vec<String>>` - Decoy paths, or error naming the bad line
+pub fn parse(text: &str) -> Result<Vec<String>> {
+    Ok(parse_entries(text)?.into_iter().map(|e| e.path).collect())
+}
+
+/// Parse a tripwire file into its entries
+///
+/// # Arguments
+/// * `text` - One absolute path per line, each optionally followed by a
+///   tab and the identity of the decoy fw planted
+///
+/// # Returns
+/// * `Result<Vec<Entry>>` - Decoys, or error naming the bad line
+fn parse_entries(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (path, planted) = match line.split_once('\t') {
+            Some((path, marker)) => {
+                let id = marker
+                    .trim()
+                    .strip_prefix(PLANTED)
+                    .and_then(|id| id.split_once(':'))
+                    .and_then(|(dev, ino)| {
+                        Some((dev.parse().ok()?, ino.parse().ok()?))
+                    })
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "line {}: invalid marker '{}'",
+                            number + 1,
+                            marker
+                        )
+                    })?;
+                (path.trim_end(), Some(id))
+            }
+            None => (line, None),
+        };
+        if !path.starts_with('/') {
+            return Err(anyhow!(
+                "line {}: tripwire '{}' must be an absolute path",
+                number + 1,
+                path
+            ));
+        }
+        entries.push(Entry {
+            path: path.to_string(),
+            planted,
+        });
+    }
+    Ok(entries)
+}
+
+/// Load a tripwire file
+///
+/// # Arguments
+/// * `path` - File to read
+///
+/// # Returns
+/// * `Result<Vec<String>>` - Decoy paths, or error naming the file and
+///   line
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&text)
+        .with_context(|| format!("Invalid tripwires {}", path.display()))
+}
+
+/// Create a decoy, unless the file already exists
+///
+/// # Arguments
+/// * `path` - Absolute path of the decoy
+/// * `content` - What the new decoy contains
+///
+/// # Returns
+/// * `Result<Option<(u64, u64)>>` - Device and inode of the file
+///   created, or None if it existed
+fn plant(path: &Path, content: &str) -> Result<Option<(u64, u64)>> {
+    let mut file =
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Ok(None)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to create {}", path.display())
+                })
+            }
+        };
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    let metadata = file
+        .metadata()
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    Ok(Some((metadata.dev(), metadata.ino())))
+}
+
+/// Run `fw tripwire add`
+///
+/// Paths are recorded as the kernel reports them, with symlinks
+/// resolved, and decoys created here with the identity of the new file.
+///
+/// # Arguments
+/// * `file` - Tripwire file, created if missing
+/// * `paths` - Decoys to plant, or existing files to turn into decoys
+/// * `content` - What new decoys contain
+///
+/// # Returns
+/// * `Result<()>` - Error if a decoy can't be created or the file
+///   updated
+pub fn run_add(file: &Path, paths: &[PathBuf], content: &str) -> Result<()> {
+    let _lock = lock_for_edit(file)?;
+    let text = read_for_edit(file)?;
+    let mut recorded = parse_entries(&text)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    let mut text = text;
+    for path in paths {
+        if !path.is_absolute() {
+            return Err(anyhow!(
+                "tripwire '{}' must be an absolute path",
+                path.display()
+            ));
+        }
+        let planted = plant(path, content)?;
+        let path = fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve {}", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        match planted {
+            Some(_) => eprintln!("Planted {}", path),
+            None => eprintln!("Using existing {} as a decoy", path),
+        }
+        if recorded.iter().any(|entry| entry.path == path) {
+            continue;
+        }
+        let entry = Entry { path, planted };
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&entry.line());
+        text.push('\n');
+        recorded.push(entry);
+    }
+    replace(file, &text)
+}
+
+/// Run `fw tripwire remove`
+///
+/// # Arguments
+/// * `file` - Tripwire file
+/// * `paths` - Decoys to stop watching
+/// * `delete` - Also delete the decoys fw planted, if they are still the
+///   files it created
+///
+/// # Returns
+/// * `Result<()>` - Error if a path isn't recorded or the file can't be
+///   updated
+pub fn run_remove(file: &Path, paths: &[PathBuf], delete: bool) -> Result<()> {
+    let _lock = lock_for_edit(file)?;
+    let text = read_for_edit(file)?;
+    let recorded = parse_entries(&text)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    let mut removed = Vec::new();
+    for path in paths {
+        let path = path.to_string_lossy();
+        let entry = recorded
+            .iter()
+            .find(|entry| entry.path == path)
+            .ok_or_else(|| {
+                anyhow!("{} is not a tripwire in {}", path, file.display())
+            })?;
+        removed.push(entry);
+    }
+    let kept: String = text
+        .lines()
+        .filter(|line| {
+            let entry =
+                parse_entries(line).ok().and_then(|e| e.into_iter().next());
+            !entry.is_some_and(|entry| {
+                removed.iter().any(|r| r.path == entry.path)
+            })
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect();
+    replace(file, &kept)?;
+    for entry in removed.iter().filter(|_| delete) {
+        match delete_planted(entry) {
+            Ok(()) => eprintln!("Deleted {}", entry.path),
+            Err(e) => eprintln!("Left {}: {}", entry.path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Delete a decoy if it is still the file fw planted
+///
+/// # Arguments
+/// * `entry` - Decoy being removed
+///
+/// # Returns
+/// * `Result<()>` - Error saying why the file was left
+fn delete_planted(entry: &Entry) -> Result<()> {
+    let planted = entry
+        .planted
+        .ok_or_else(|| anyhow!("fw didn't create it"))?;
+    let metadata = fs::symlink_metadata(&entry.path)?;
+    if !metadata.is_file() || (metadata.dev(), metadata.ino()) != planted {
+        return Err(anyhow!("no longer the file fw created"));
+    }
+    fs::remove_file(&entry.path)?;
+    Ok(())
+}
+
+/// Run `fw tripwire list`
+///
+/// # Arguments
+/// * `file` - Tripwire file
+///
+/// # Returns
+/// * `Result<()>` - Error if the file can't be read
+pub fn run_list(file: &Path) -> Result<()> {
+    let paths = parse(&read_for_edit(file)?)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    for path in paths {
+        match Path::new(&path).exists() {
+            true => println!("{}", path),
+            false => println!("{} (missing)", path),
+        }
+    }
+    Ok(())
+}
+
+/// Tags accesses to tripwires with their process ancestry ("ancestry")
+///
+/// Runs after the severity classifier and looks only at events it
+/// labelled with the tripwire rule, so other events cost nothing.
+#[derive(Debug, Clone)]
+pub struct Ancestry {
+    proc_root: PathBuf,
+}
+
+impl Ancestry {
+    /// Create the enricher
+    ///
+    /// # Arguments
+    /// * `proc_root` - Mount point of procfs (normally "/proc")
+    ///
+    /// # Returns
+    /// * `Ancestry` - Enricher reading processes from `proc_root`
+    pub fn new(proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_root: proc_root.into(),
+        }
+    }
+
+    /// Name and parent of a process
+    fn process(&self, pid: u32) -> Option<(String, u32)> {
+        let path = self.proc_root.join(pid.to_string()).join("stat");
+        parse_stat(&fs::read_to_string(path).ok()?)
+    }
+
+    /// Chain from a process up to the first process
+    ///
+    /// # Arguments
+    /// * `pid` - Process that made the access
+    /// * `name` - Its name as captured, used if it already exited
+    ///
+    /// # Returns
+    /// * `String` - "name(pid)" entries, child first, joined by " < "
+    fn chain(&self, pid: u32, name: &str) -> String {
+        let mut chain = Vec::new();
+        let mut pid = pid;
+        while pid != 0 && chain.len() < MAX_ANCESTRY {
+            let Some((comm, ppid)) = self.process(pid) else {
+                if chain.is_empty() {
+                    chain.push(format!("{}({}, exited)", name, pid));
+                }
+                break;
+            };
+            chain.push(format!("{}({})", comm, pid));
+            pid = ppid;
+        }
+        chain.join(" < ")
+    }
+}
+
+/// Name and parent pid from the contents of /proc/PID/stat
+///
+/// The name is in parentheses and may itself contain spaces and
+/// parentheses, so the fields are read after the last ')'.
+fn parse_stat(stat: &str) -> Option<(String, u32)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat.get(open + 1..close)?.to_string();
+    let ppid = stat[close + 1..].split_whitespace().nth(1)?.parse().ok()?;
+    Some((comm, ppid))
+}
+
+impl Enricher for Ancestry {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        if event.rule.as_deref() != Some(TRIPWIRE_RULE) {
+            return;
+        }
+        let chain = self.chain(event.pid, &event.program_name);
+        event.tags.insert("ancestry".to_string(), chain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::enrich::{EnrichConfig, Enrichers, EnrichmentLevel};
+    use crate::file_event::FileAction;
+    use crate::severity::Severity;
+
+    #[test]
+    fn test_add_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        let file = dir.join("tripwires");
+        let decoy = dir.join("passwords.txt");
+        let existing = dir.join("id_rsa");
+        fs::write(&existing, "key").unwrap();
+
+        run_add(&file, &[decoy.clone(), existing.clone()], "admin:1234\n")
+            .unwrap();
+        run_add(&file, std::slice::from_ref(&decoy), "").unwrap();
+        assert_eq!(fs::read_to_string(&decoy).unwrap(), "admin:1234\n");
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "key");
+        let paths = load(&file).unwrap();
+        assert_eq!(paths.len(), 2);
+
+        run_remove(&file, std::slice::from_ref(&decoy), true).unwrap();
+        assert!(!decoy.exists());
+        assert_eq!(load(&file).unwrap(), vec![existing.display().to_string()]);
+        assert!(run_remove(&file, &[decoy], false).is_err());
+
+        // Only files fw created, and only while they are still those
+        run_remove(&file, std::slice::from_ref(&existing), true).unwrap();
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "key");
+        let replaced = dir.join("replaced.txt");
+        run_add(&file, std::slice::from_ref(&replaced), "").unwrap();
+        fs::remove_file(&replaced).unwrap();
+        fs::write(&replaced, "real data").unwrap();
+        run_remove(&file, std::slice::from_ref(&replaced), true).unwrap();
+        assert!(replaced.exists());
+        assert!(load(&file).unwrap().is_empty());
+        assert!(run_add(&file, &[PathBuf::from("x")], "").is_err());
+        assert!(parse("relative\n").is_err());
+    }
+
+    #[test]
+    fn test_tripwire_events() {
+        let proc_root = tempfile::tempdir().unwrap();
+        for (pid, stat) in [
+            (4242, "4242 (cat) R 4100 4242"),
+            (4100, "4100 (my (odd) sh) S 1 4100"),
+            (1, "1 (systemd) S 0 1"),
+        ] {
+            let dir = proc_root.path().join(pid.to_string());
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("stat"), stat).unwrap();
+        }
+        let config = EnrichConfig {
+            level: EnrichmentLevel::Basic,
+            tripwires: vec!["/srv/backup/passwords.txt".to_string()],
+            tripwire_stacks: true,
+            ..Default::default()
+        };
+        let classifier = config.classifier().unwrap();
+        assert_eq!(classifier.stack_criteria().len(), 1);
+        let mut enrichers = Enrichers::default();
+        enrichers.push(classifier);
+        enrichers.push(Ancestry::new(proc_root.path()));
+
+        let mut event = |path: &str, pid| {
+            let mut event = FileEvent::new(
+                path.to_string(),
+                "cat".to_string(),
+                FileAction::Opened,
+                pid,
+                SystemClock.now(),
+            );
+            enrichers.enrich(&mut event);
+            event
+        };
+        let read = event("/srv/backup/passwords.txt", 4242);
+        assert_eq!(read.severity, Some(Severity::Critical));
+        assert_eq!(read.rule.as_deref(), Some(TRIPWIRE_RULE));
+        assert_eq!(
+            read.tags["ancestry"],
+            "cat(4242) < my (odd) sh(4100) < systemd(1)"
+        );
+        let gone = event("/srv/backup/passwords.txt", 77);
+        assert_eq!(gone.tags["ancestry"], "cat(77, exited)");
+        // Only the exact path
+        let near = event("/srv/backup/passwords.txt.bak", 4242);
+        assert_ne!(near.rule.as_deref(), Some(TRIPWIRE_RULE));
+        assert!(!near.tags.contains_key("ancestry"));
+    }
+}
@@ -0,0 +1,159 @@
+//! User filter module
+//!
+//! Resolves the users given to `fw collect --user` and `--exclude-user`
+//! into the uid entries the eBPF programs check before reporting
+//! anything, so activity of other users is dropped in the kernel.
+
+use anyhow::{anyhow, Context, Result};
+use fw_common::{
+    MAX_UID_FILTER_ENTRIES, UID_FILTER_EXCLUDE, UID_FILTER_INCLUDE,
+};
+use nix::unistd::User;
+use std::collections::BTreeMap;
+
+/// Users whose activity is included or excluded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserFilter {
+    /// Filter entry (`UID_FILTER_*`) per uid
+    entries: BTreeMap<u32, u8>,
+}
+
+impl UserFilter {
+    /// Resolve user names or uids into a filter
+    ///
+    /// # Arguments
+    /// * `include` - Users to report; if empty, every user not excluded
+    ///   is reported
+    /// * `exclude` - Users never to report
+    ///
+    /// # Returns
+    /// * `Result<UserFilter>` - Filter, or error for an unknown user, a user
+    ///   both included and excluded, or too many users
+    pub fn resolve(include: &[String], exclude: &[String]) -> Result<Self> {
+        let include = include
+            .iter()
+            .map(|user| resolve_user(user))
+            .collect::<Result<Vec<_>>>()?;
+        let exclude = exclude
+            .iter()
+            .map(|user| resolve_user(user))
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_uids(&include, &exclude)
+    }
+
+    /// Build a filter from uids
+    ///
+    /// # Arguments
+    /// * `include` - Uids to report; if empty, every uid not excluded is
+    ///   reported
+    /// * `exclude` - Uids never to report
+    ///
+    /// # Returns
+    /// * `Result<UserFilter>` - Filter, or error for a uid both included
+    ///   and excluded, or too many uids
+    pub fn from_uids(include: &[u32], exclude: &[u32]) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        for &uid in include {
+            entries.insert(uid, UID_FILTER_INCLUDE);
+        }
+        for &uid in exclude {
+            if entries.insert(uid, UID_FILTER_EXCLUDE).is_some() {
+                return Err(anyhow!(
+                    "User {} is both included and excluded",
+                    uid
+                ));
+            }
+        }
+        if entries.len() > MAX_UID_FILTER_ENTRIES as usize {
+            return Err(anyhow!(
+                "At most {} users can be filtered",
+                MAX_UID_FILTER_ENTRIES
+            ));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Check whether the filter lets everything through
+    ///
+    /// # Returns
+    /// * `bool` - True if no users were given
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Check whether only included users are reported
+    ///
+    /// # Returns
+    /// * `bool` - True if any user was included
+    pub fn has_includes(&self) -> bool {
+        self.entries.values().any(|&e| e == UID_FILTER_INCLUDE)
+    }
+
+    /// Entries to write into the kernel uid filter map
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = (u32, u8)>` - Uid and `UID_FILTER_*` entry
+    pub fn entries(&self) -> impl Iterator<Item = (u32, u8)> + '_ {
+        self.entries.iter().map(|(&uid, &entry)| (uid, entry))
+    }
+
+    /// Check a uid the same way the eBPF programs do
+    ///
+    /// # Arguments
+    /// * `uid` - User ID of the acting task
+    ///
+    /// # Returns
+    /// * `bool` - True if the user's activity is reported
+    pub fn allows(&self, uid: u32) -> bool {
+        match self.entries.get(&uid) {
+            Some(&entry) => entry == UID_FILTER_INCLUDE,
+            None => !self.has_includes(),
+        }
+    }
+}
+
+/// Resolve a user name or numeric uid
+///
+/// # Arguments
+/// * `user` - User name (e.g. "postgres") or uid (e.g. "1000")
+///
+/// # Returns
+/// * `Result<u32>` - User ID, or error if no such user exists
+fn resolve_user(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse::<u32>() {
+        return Ok(uid);
+    }
+    User::from_name(user)
+        .with_context(|| format!("Failed to look up user '{}'", user))?
+        .map(|u| u.uid.as_raw())
+        .ok_or_else(|| anyhow!("Unknown user '{}'", user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let only = UserFilter::resolve(&users(&["1000", "root"]), &[]).unwrap();
+        assert!(only.allows(0));
+        assert!(only.allows(1000));
+        assert!(!only.allows(1001));
+
+        let not_root = UserFilter::resolve(&[], &users(&["0"])).unwrap();
+        assert!(!not_root.allows(0));
+        assert!(not_root.allows(1000));
+        assert!(UserFilter::default().allows(0));
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        assert!(UserFilter::resolve(&users(&["0"]), &users(&["root"])).is_err());
+        assert!(UserFilter::resolve(&users(&["no-such-user-fw-test"]), &[])
+            .is_err());
+    }
+}
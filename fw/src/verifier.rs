@@ -0,0 +1,401 @@
+//! Verifier module
+//!
+//! Explains why the kernel refused to load a probe program. The verifier
+//! log it returns can run to thousands of lines, with the reason buried
+//! at the end, so on a load failure the full log is written to a
+//! diagnostics file in [`DIAGNOSTICS_DIR`], a directory only fw's user
+//! can write to, and the error shows only its last lines together
+//! with the likely cause: a kernel without BTF, a kernel too old for a
+//! helper or for loops, the instruction limit, or a memory access the
+//! verifier couldn't prove safe.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::fmt::{self, Write as _};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write as _};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::capabilities::{Capabilities, KernelVersion};
+
+/// Directory the diagnostics files are written to
+pub const DIAGNOSTICS_DIR: &str = "/run/fw";
+
+/// Lines of the log shown in the error; the rest is in the file
+const EXCERPT_LINES: usize = 6;
+
+/// Oldest kernel whose verifier accepts bounded loops
+const BOUNDED_LOOPS: KernelVersion = KernelVersion::new(5, 3, 0);
+
+/// Likely reason a program was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hint {
+    /// The kernel exposes no BTF to relocate the program against
+    MissingBtf,
+    /// The kernel lacks a helper the program calls
+    MissingHelper(String),
+    /// The verifier gave up after too many instructions
+    InstructionLimit,
+    /// The kernel predates bounded loops
+    NoBoundedLoops,
+    /// The verifier couldn't prove a memory access safe on this kernel
+    UnsafeAccess,
+    /// fw isn't allowed to load programs
+    Permission,
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hint::MissingBtf => write!(
+                f,
+                "missing BTF: the kernel has no /sys/kernel/btf/vmlinux; \
+                 use a kernel built with CONFIG_DEBUG_INFO_BTF=y"
+            ),
+            Hint::MissingHelper(helper) => write!(
+                f,
+                "kernel too old: it doesn't provide the {} helper",
+                helper
+            ),
+            Hint::InstructionLimit => write!(
+                f,
+                "instruction limit: the verifier gave up exploring the \
+                 program (kernels before 5.2 allow far fewer instructions)"
+            ),
+            Hint::NoBoundedLoops => write!(
+                f,
+                "kernel too old: loops need kernel {} or later",
+                BOUNDED_LOOPS
+            ),
+            Hint::UnsafeAccess => write!(
+                f,
+                "the verifier couldn't prove a memory access safe on this \
+                 kernel; please report it with the diagnostics file"
+            ),
+            Hint::Permission => write!(
+                f,
+                "not permitted: run as root or with CAP_BPF and CAP_PERFMON"
+            ),
+        }
+    }
+}
+
+/// Analysis of a rejected program
+#[derive(Debug, Clone)]
+pub struct VerifierReport {
+    /// Program that failed to load
+    pub program: String,
+    /// Error the load returned
+    pub error: String,
+    /// Full verifier log
+    pub log: String,
+    /// Likely causes, most specific first
+    pub hints: Vec<Hint>,
+    /// Last lines of the log, where the verifier says what it rejected
+    pub excerpt: Vec<String>,
+}
+
+impl VerifierReport {
+    /// Work out why a program was rejected
+    ///
+    /// # Arguments
+    /// * `program` - Name of the program
+    /// * `error` - Error the load returned
+    /// * `log` - Verifier log; empty if the kernel didn't write one
+    /// * `capabilities` - Feature tests of the running kernel
+    ///
+    /// # Returns
+    /// * `VerifierReport` - Hints and log excerpt
+    pub fn analyze(
+        program: &str,
+        error: &io::Error,
+        log: &str,
+        capabilities: &Capabilities,
+    ) -> Self {
+        let mut hints = Vec::new();
+        if let Some(helper) = missing_helper(log) {
+            hints.push(Hint::MissingHelper(helper));
+        }
+        if log.contains("back-edge") {
+            hints.push(Hint::NoBoundedLoops);
+        }
+        if log.contains("BPF program is too large")
+            || log.contains("too many states")
+        {
+            hints.push(Hint::InstructionLimit);
+        }
+        if [
+            "invalid mem access",
+            "invalid access to",
+            "unbounded memory",
+        ]
+        .iter()
+        .any(|pattern| log.contains(pattern))
+        {
+            hints.push(Hint::UnsafeAccess);
+        }
+        if !capabilities.btf
+            && (hints.is_empty() || log.to_lowercase().contains("btf"))
+        {
+            hints.push(Hint::MissingBtf);
+        }
+        if error.raw_os_error() == Some(libc::EPERM) {
+            hints.push(Hint::Permission);
+        }
+        let lines: Vec<&str> =
+            log.lines().filter(|line| !line.trim().is_empty()).collect();
+        let excerpt = lines[lines.len().saturating_sub(EXCERPT_LINES)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        Self {
+            program: program.to_string(),
+            error: error.to_string(),
+            log: log.to_string(),
+            hints,
+            excerpt,
+        }
+    }
+
+    /// Summary for the error message
+    ///
+    /// # Returns
+    /// * `String` - Program, error, likely causes and the log excerpt
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "The kernel refused to load {}: {}\n",
+            self.program, self.error
+        );
+        for hint in &self.hints {
+            let _ = writeln!(out, "  likely cause: {}", hint);
+        }
+        if self.hints.is_empty() {
+            out.push_str("  likely cause: unknown; see the verifier log\n");
+        }
+        if !self.excerpt.is_empty() {
+            out.push_str("  verifier log ends with:\n");
+            for line in &self.excerpt {
+                let _ = writeln!(out, "    {}", line);
+            }
+        }
+        out
+    }
+
+    /// Write the summary and the full log to a new diagnostics file
+    ///
+    /// The file is created with only the owner allowed to read it, and
+    /// never through an existing file or symbolic link.
+    ///
+    /// # Arguments
+    /// * `path` - File to create
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or error if the file exists or can't be
+    ///   written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let kernel = KernelVersion::current()
+            .map_or("unknown".to_string(), |k| k.to_string());
+        let contents = format!(
+            "fw {}\nkernel {}\n\n{}\nFull verifier log:\n{}",
+            env!("CARGO_PKG_VERSION"),
+            kernel,
+            self.summary(),
+            self.log
+        );
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Helper the verifier reported as unknown, as in "unknown func
+/// bpf_get_current_task_btf#158"
+fn missing_helper(log: &str) -> Option<String> {
+    log.lines().find_map(|line| {
+        let rest = line
+            .split_once("unknown func ")
+            .or_else(|| line.split_once("invalid func "))?
+            .1;
+        let name = rest.split(['#', ' ']).next().unwrap_or(rest);
+        Some(name.to_string())
+    })
+}
+
+/// Create a directory only the current user can write to, or check
+/// that an existing one is
+///
+/// # Arguments
+/// * `dir` - Directory for the diagnostics files
+///
+/// # Returns
+/// * `Result<()>` - Success, or error if the directory can't be created
+///   or others could plant files in it
+pub fn private_dir(dir: &Path) -> Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| {
+                format!("Failed to create {}", dir.display())
+            });
+        }
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    if !metadata.is_dir()
+        || metadata.uid() != nix::unistd::geteuid().as_raw()
+        || metadata.mode() & 0o022 != 0
+    {
+        return Err(anyhow!(
+            "{} is not a directory only fw's user can write to",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Where the diagnostics file of a rejected program is written
+///
+/// # Arguments
+/// * `dir` - Directory from [`private_dir`]
+/// * `program` - Name of the program
+///
+/// # Returns
+/// * `PathBuf` - File in the directory, unique to this run
+pub fn diagnostics_path(dir: &Path, program: &str) -> PathBuf {
+    dir.join(format!("verifier-{}-{}.log", program, std::process::id()))
+}
+
+/// Turn a rejected program into a self-explaining error
+///
+/// # Arguments
+/// * `report` - Analysis of the rejection
+///
+/// # Returns
+/// * `anyhow::Error` - Summary, naming the diagnostics file if it could
+///   be written
+pub fn load_error(report: &VerifierReport) -> anyhow::Error {
+    let dir = Path::new(DIAGNOSTICS_DIR);
+    let path = diagnostics_path(dir, &report.program);
+    match private_dir(dir).and_then(|()| report.write(&path)) {
+        Ok(()) => {
+            anyhow!("{}Full verifier log: {}", report.summary(), path.display())
+        }
+        Err(e) => {
+            warn!("{:#}", e);
+            anyhow!("{}", report.summary().trim_end())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(btf: bool) -> Capabilities {
+        Capabilities {
+            kernel: Some(KernelVersion::new(5, 4, 0)),
+            ring_buffer: false,
+            btf,
+            sleepable: false,
+            kprobes: Some(true),
+            bpffs: true,
+        }
+    }
+
+    #[test]
+    fn test_hints_from_log() {
+        let einval = io::Error::from_raw_os_error(libc::EINVAL);
+        let log = "0: (bf) r6 = r1\n\
+                   1: (85) call bpf_get_current_task_btf#158\n\
+                   unknown func bpf_get_current_task_btf#158\n\
+                   processed 2 insns (limit 1000000)\n";
+        let report = VerifierReport::analyze(
+            "fw_openat",
+            &einval,
+            log,
+            &capabilities(true),
+        );
+        assert_eq!(
+            report.hints,
+            [Hint::MissingHelper("bpf_get_current_task_btf".to_string())]
+        );
+        assert_eq!(report.excerpt.len(), 4);
+        assert!(report
+            .summary()
+            .contains("likely cause: kernel too old: it doesn't provide"));
+
+        let log = "21: (07) r2 += 8\nR2 invalid mem access 'scalar'\n";
+        let report = VerifierReport::analyze(
+            "fw_close",
+            &einval,
+            log,
+            &capabilities(false),
+        );
+        assert_eq!(report.hints, [Hint::UnsafeAccess]);
+
+        // Nothing recognizable: the missing BTF is the best guess
+        let eperm = io::Error::from_raw_os_error(libc::EPERM);
+        let report = VerifierReport::analyze(
+            "fw_openat",
+            &eperm,
+            "",
+            &capabilities(false),
+        );
+        assert_eq!(report.hints, [Hint::MissingBtf, Hint::Permission]);
+    }
+
+    #[test]
+    fn test_diagnostics_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("verifier.log");
+        let log = (0..100)
+            .map(|i| format!("{}: insn\n", i))
+            .collect::<String>()
+            + "BPF program is too large. Processed 1000001 insn\n";
+        let report = VerifierReport::analyze(
+            "fw_rename",
+            &io::Error::from_raw_os_error(libc::E2BIG),
+            &log,
+            &capabilities(true),
+        );
+        assert_eq!(report.hints, [Hint::InstructionLimit]);
+        assert_eq!(report.excerpt.len(), EXCERPT_LINES);
+        report.write(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("likely cause: instruction limit"));
+        assert!(written.contains("\n0: insn\n"));
+
+        // Never written through an existing file or link
+        assert!(report.write(&path).is_err());
+        let link = dir.path().join("link.log");
+        std::os::unix::fs::symlink(dir.path().join("target"), &link).unwrap();
+        assert!(report.write(&link).is_err());
+        assert!(!dir.path().join("target").exists());
+    }
+
+    #[test]
+    fn test_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let diagnostics = dir.path().join("fw");
+        private_dir(&diagnostics).unwrap();
+        private_dir(&diagnostics).unwrap();
+        let mode = fs::metadata(&diagnostics).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // Anyone could plant a link in a world-writable directory
+        fs::set_permissions(&diagnostics, fs::Permissions::from_mode(0o777))
+            .unwrap();
+        assert!(private_dir(&diagnostics).is_err());
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+        assert!(private_dir(&link).is_err());
+    }
+}
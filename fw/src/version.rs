@@ -0,0 +1,123 @@
+//! Version module
+//!
+//! Builds the `fw --version` output. With `--verbose` it also says how the
+//! binary was built (target, static or dynamic linking, optional features),
+//! which eBPF object is embedded in it, and what the running kernel
+//! supports, so a binary copied onto a host can be checked in one command
+//! before it is run.
+
+use fw_common::EVENT_ABI_VERSION;
+use std::fmt::Write as _;
+
+use crate::bpf_object::BpfObject;
+use crate::capabilities::Capabilities;
+use crate::features;
+
+/// Target triple fw was built for, recorded by build.rs
+const TARGET: &str = env!("FW_TARGET");
+
+/// Render a yes/no answer
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Version line, as printed by `fw --version`
+///
+/// # Returns
+/// * `String` - Name and version
+pub fn version() -> String {
+    format!("fw {}\n", env!("CARGO_PKG_VERSION"))
+}
+
+/// Version with build details and the kernel features fw can use
+///
+/// # Arguments
+/// * `capabilities` - Feature tests of the running kernel
+///
+/// # Returns
+/// * `String` - One detail per line
+pub fn verbose_version(capabilities: &Capabilities) -> String {
+    let mut out = version();
+    let linking = if cfg!(target_feature = "crt-static") {
+        "static"
+    } else {
+        "dynamic"
+    };
+    let _ = writeln!(out, "target: {} ({})", TARGET, linking);
+    let features = features::enabled();
+    let _ = writeln!(
+        out,
+        "features: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+    match BpfObject::embedded() {
+        Ok(object) => {
+            let _ = writeln!(
+                out,
+                "eBPF object: checksum {:016x}, event layout {}, {} bytes",
+                object.checksum(),
+                EVENT_ABI_VERSION,
+                object.bytes().len()
+            );
+        }
+        Err(e) => {
+            let _ = writeln!(out, "eBPF object: {}", e);
+        }
+    }
+
+    let kernel = capabilities
+        .kernel
+        .map_or("unknown".to_string(), |k| k.to_string());
+    let _ = writeln!(out, "kernel: {}", kernel);
+    let _ = writeln!(out, "  event transport: {}", capabilities.transport());
+    let _ = writeln!(out, "  BTF: {}", yes_no(capabilities.btf));
+    let _ = writeln!(
+        out,
+        "  sleepable programs: {}",
+        yes_no(capabilities.sleepable)
+    );
+    let _ = writeln!(
+        out,
+        "  kprobes: {}",
+        capabilities
+            .kprobes
+            .map_or("unknown (not permitted to test)", yes_no)
+    );
+    let _ = writeln!(out, "  BPF filesystem: {}", yes_no(capabilities.bpffs));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::KernelVersion;
+
+    #[test]
+    fn test_verbose_version() {
+        let capabilities = Capabilities {
+            kernel: Some(KernelVersion::new(5, 4, 0)),
+            ring_buffer: false,
+            btf: true,
+            sleepable: false,
+            kprobes: None,
+            bpffs: true,
+        };
+        let text = verbose_version(&capabilities);
+        assert!(text.starts_with(&version()));
+        assert!(text.contains(&format!("target: {} (", TARGET)));
+        assert!(text.contains(&format!(
+            "eBPF object: checksum {}",
+            env!("EBPF_OBJECT_CHECKSUM")
+        )));
+        assert!(text.contains("kernel: 5.4.0\n  event transport: perf buffer"));
+        assert!(text.contains("kprobes: unknown"));
+    }
+}
@@ -0,0 +1,270 @@
+//! Wait-for module
+//!
+//! Implements `fw wait-for`, which blocks until a file event matching a
+//! path glob and action arrives. Scripts use it to wait for another
+//! process to touch a file instead of polling: the matching event is
+//! printed to stdout and the command succeeds, or it fails on timeout.
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal;
+
+use crate::collector;
+use crate::ebpf_monitor::EbpfMonitor;
+use crate::file_event::{FileAction, FileEvent};
+use crate::glob::PathGlob;
+use crate::monitor_backend::MonitorBackend;
+use crate::mount_table::MountTable;
+
+/// Kinds of action `fw wait-for` can wait for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ActionMatch {
+    /// Any action
+    Any,
+    /// The file was opened
+    Open,
+    /// A descriptor of the file was closed
+    Close,
+    /// Data was read from the file
+    Read,
+    /// Data was written to the file
+    Written,
+    /// The file was renamed or replaced by an atomic rename
+    Renamed,
+    /// The file was changed: written to, truncated, renamed or replaced
+    /// by an atomic rename, or its mode, owner or extended attributes
+    /// modified
+    Write,
+    /// A hardlink or symlink was created at the path
+    Link,
+    /// The file was flushed to storage
+    Sync,
+    /// A lock on the file was taken or released
+    Lock,
+}
+
+impl ActionMatch {
+    /// Check whether an action is of this kind
+    ///
+    /// # Arguments
+    /// * `action` - Action of a file event
+    ///
+    /// # Returns
+    /// * `bool` - True if the action matches
+    pub fn matches(self, action: &FileAction) -> bool {
+        match self {
+            ActionMatch::Any => true,
+            ActionMatch::Open => *action == FileAction::Opened,
+            ActionMatch::Close => *action == FileAction::Closed,
+            ActionMatch::Read => matches!(action, FileAction::Read { .. }),
+            ActionMatch::Written => {
+                matches!(action, FileAction::Written { .. })
+            }
+            ActionMatch::Renamed => {
+                matches!(action, FileAction::Renamed | FileAction::AtomicSave)
+            }
+            ActionMatch::Write => matches!(
+                action,
+                FileAction::Written { .. }
+                    | FileAction::Truncated { .. }
+                    | FileAction::ModeChanged { .. }
+                    | FileAction::OwnerChanged { .. }
+                    | FileAction::XattrSet
+                    | FileAction::XattrRemoved
+                    | FileAction::Renamed
+                    | FileAction::AtomicSave
+            ),
+            ActionMatch::Link => {
+                matches!(action, FileAction::Linked | FileAction::Symlinked)
+            }
+            ActionMatch::Sync => matches!(action, FileAction::Synced { .. }),
+            ActionMatch::Lock => matches!(
+                action,
+                FileAction::Locked { acquired: true, .. }
+                    | FileAction::Unlocked
+            ),
+        }
+    }
+}
+
+/// What `fw wait-for` waits for
+#[derive(Debug, Clone)]
+pub struct WaitCondition {
+    /// Glob the event path must match
+    pub path: PathGlob,
+    /// Kind of action the event must have
+    pub action: ActionMatch,
+}
+
+impl WaitCondition {
+    /// Check whether an event satisfies the condition
+    ///
+    /// # Arguments
+    /// * `event` - File event to test
+    ///
+    /// # Returns
+    /// * `bool` - True if both path and action match
+    pub fn matches(&self, event: &FileEvent) -> bool {
+        self.action.matches(&event.action)
+            && self.path.matches(&event.file_path)
+    }
+}
+
+/// Wait for the first event matching a condition
+///
+/// # Arguments
+/// * `monitor` - Backend producing file events
+/// * `mounts` - Mount table used to annotate events
+/// * `condition` - Path and action to wait for
+/// * `shutdown` - Future that resolves when waiting should give up
+///
+/// # Returns
+/// * `Result<Option<FileEvent>>` - Matching event, or None if `shutdown`
+///   completed or the backend stopped first
+pub async fn wait_for_event<B, S>(
+    monitor: &mut B,
+    mounts: &MountTable,
+    condition: &WaitCondition,
+    shutdown: S,
+) -> Result<Option<FileEvent>>
+where
+    B: MonitorBackend,
+    S: Future<Output = ()>,
+{
+    collector::first_match(monitor, mounts, |e| condition.matches(e), shutdown)
+        .await
+}
+
+/// Run `fw wait-for`
+///
+/// # Arguments
+/// * `condition` - Path and action to wait for
+/// * `timeout` - How long to wait before failing
+///
+/// # Returns
+/// * `Result<()>` - Success once a matching event was printed, or error on
+///   timeout or interrupt
+pub fn run_wait_for(condition: WaitCondition, timeout: Duration) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()
+        .context("Failed to create async runtime")?;
+
+    rt.block_on(async {
+        let mounts =
+            MountTable::load().context("Failed to load mount table")?;
+        let mut monitor = EbpfMonitor::new()
+            .context("Failed to initialize eBPF monitor")?
+            .with_actions(Some(&[condition.action]));
+
+        info!(
+            "Waiting up to {:?} for {:?} on {}",
+            timeout,
+            condition.action,
+            condition.path.pattern()
+        );
+        let shutdown = async {
+            tokio::select! {
+                _ = tokio::time::sleep(timeout) => {}
+                _ = signal::ctrl_c() => {}
+            }
+        };
+
+        match wait_for_event(&mut monitor, &mounts, &condition, shutdown)
+            .await?
+        {
+            Some(event) => {
+                println!("{}", event);
+                Ok(())
+            }
+            None => Err(anyhow!(
+                "No matching event for {} within {:?}",
+                condition.path.pattern(),
+                timeout
+            )),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::mock_monitor::MockMonitor;
+
+    #[test]
+    fn test_write_matches_modifications() {
+        assert!(
+            ActionMatch::Write.matches(&FileAction::Truncated { length: 0 })
+        );
+        assert!(ActionMatch::Write.matches(&FileAction::XattrSet));
+        assert!(ActionMatch::Write.matches(&FileAction::Written {
+            offset: None,
+            bytes: 4,
+        }));
+        assert!(ActionMatch::Write.matches(&FileAction::Renamed));
+        let read = FileAction::Read {
+            offset: Some(0),
+            bytes: 4,
+        };
+        assert!(ActionMatch::Read.matches(&read));
+        assert!(!ActionMatch::Write.matches(&read));
+        assert!(!ActionMatch::Write.matches(&FileAction::Opened));
+        assert!(ActionMatch::Written.matches(&FileAction::Written {
+            offset: Some(0),
+            bytes: 4,
+        }));
+        assert!(!ActionMatch::Written.matches(&FileAction::XattrSet));
+        assert!(ActionMatch::Renamed.matches(&FileAction::AtomicSave));
+        assert!(!ActionMatch::Renamed.matches(&read));
+        assert!(ActionMatch::Any.matches(&FileAction::Closed));
+    }
+
+    fn event(path: &str, action: FileAction) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            action,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_returns_first_match() {
+        let condition = WaitCondition {
+            path: PathGlob::new("/srv/*.ready"),
+            action: ActionMatch::Write,
+        };
+        let mut monitor = MockMonitor::new(vec![
+            event("/srv/a.ready", FileAction::Opened),
+            event("/srv/a.txt", FileAction::Truncated { length: 0 }),
+            event("/srv/a.ready", FileAction::Truncated { length: 0 }),
+            event("/srv/b.ready", FileAction::Truncated { length: 0 }),
+        ]);
+
+        let found = wait_for_event(
+            &mut monitor,
+            &MountTable::empty(),
+            &condition,
+            std::future::pending(),
+        )
+        .await
+        .unwrap()
+        .expect("a matching event was sent");
+        assert_eq!(found.file_path, "/srv/a.ready");
+        assert_eq!(found.action, FileAction::Truncated { length: 0 });
+
+        let mut monitor =
+            MockMonitor::new(vec![event("/srv/a.txt", FileAction::Opened)]);
+        let missing = wait_for_event(
+            &mut monitor,
+            &MountTable::empty(),
+            &condition,
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+        assert!(missing.is_none());
+    }
+}
@@ -0,0 +1,365 @@
+//! Watcher module
+//!
+//! Library entry point for programs that consume file events themselves
+//! instead of running `fw collect`. A `Watcher` starts a monitor backend
+//! and delivers its events either as an async stream or to a callback,
+//! running the task that moves them internally, and can pause and resume
+//! the backend without losing its state.
+
+use anyhow::{anyhow, Result};
+use futures_core::Stream;
+use log::debug;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::file_event::FileEvent;
+use crate::filter_builder::CompiledFilter;
+use crate::monitor_backend::MonitorBackend;
+
+/// Events buffered for a stream consumer before the backend waits
+const STREAM_QUEUE_SIZE: usize = 1024;
+
+/// Async stream of the events a `Watcher` delivers
+pub struct EventStream {
+    rx: mpsc::Receiver<FileEvent>,
+}
+
+impl Stream for EventStream {
+    type Item = FileEvent;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<FileEvent>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Delivers a backend's events to an embedding program
+pub struct Watcher<B: MonitorBackend> {
+    /// Source of the events
+    backend: B,
+    /// Criteria events must match to be delivered; all if unset
+    filter: Option<CompiledFilter>,
+    /// Whether delivery is paused, shared with the delivery task
+    paused: watch::Sender<bool>,
+    /// Task moving events from the backend to the consumer
+    task: Option<JoinHandle<()>>,
+}
+
+impl<B: MonitorBackend> Watcher<B> {
+    /// Create a watcher over a backend, such as a configured
+    /// `EbpfMonitor`
+    ///
+    /// # Arguments
+    /// * `backend` - Source of the events; started by `events` or
+    ///   `on_event`
+    ///
+    /// # Returns
+    /// * `Watcher` - New watcher, not yet started
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            filter: None,
+            paused: watch::channel(false).0,
+            task: None,
+        }
+    }
+
+    /// Deliver only the events a filter matches
+    ///
+    /// The filter's kernel plan is applied to the backend when it
+    /// starts, so only the probes its actions need are attached and the
+    /// uid, process and pseudo-filesystem criteria are checked in the
+    /// kernel. Criteria on fields the backend doesn't set, such as the
+    /// mount or tags that `fw collect` adds while annotating, never match.
+    ///
+    /// # Arguments
+    /// * `filter` - Compiled filter
+    ///
+    /// # Returns
+    /// * `Watcher` - The watcher with the filter set
+    pub fn with_filter(mut self, filter: CompiledFilter) -> Self {
+        self.set_filter(filter);
+        self
+    }
+
+    /// Replace the filter, from the next start
+    ///
+    /// # Arguments
+    /// * `filter` - Compiled filter
+    pub fn set_filter(&mut self, filter: CompiledFilter) {
+        self.filter = Some(filter);
+    }
+
+    /// Start the backend and deliver its events as a stream, which ends
+    /// when the backend has no more events or the watcher is stopped
+    ///
+    /// # Returns
+    /// * `Result<EventStream>` - Stream of events, or error if the watcher
+    ///   is already running or the backend fails to start
+    pub async fn events(&mut self) -> Result<EventStream> {
+        let (tx, rx) = mpsc::channel(STREAM_QUEUE_SIZE);
+        self.start(move |event| {
+            let tx = tx.clone();
+            async move { tx.send(event).await.is_ok() }
+        })
+        .await?;
+        Ok(EventStream { rx })
+    }
+
+    /// Start the backend and call a function for each of its events on
+    /// the delivery task; the function should hand slow work off rather
+    /// than block
+    ///
+    /// # Arguments
+    /// * `callback` - Called with each event, in order
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or error if the watcher is already
+    ///   running or the backend fails to start
+    pub async fn on_event<F>(&mut self, mut callback: F) -> Result<()>
+    where
+        F: FnMut(FileEvent) + Send + 'static,
+    {
+        self.start(move |event| {
+            callback(event);
+            async { true }
+        })
+        .await
+    }
+
+    /// Start the backend and spawn the task delivering its events
+    ///
+    /// # Arguments
+    /// * `deliver` - Hands an event to the consumer; resolves to false
+    ///   once the consumer is gone
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    async fn start<F, Fut>(&mut self, mut deliver: F) -> Result<()>
+    where
+        F: FnMut(FileEvent) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = bool> + Send,
+    {
+        if self.task.is_some() {
+            return Err(anyhow!("Watcher is already running"));
+        }
+        if let Some(filter) = &self.filter {
+            self.backend.apply_kernel_plan(&filter.kernel_plan())?;
+        }
+        let mut events = self.backend.start_monitoring().await?;
+        self.paused.send_replace(false);
+        let paused = self.paused.subscribe();
+        let filter = self.filter.clone();
+        self.task = Some(tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                // Events already queued when the backend paused
+                if *paused.borrow() {
+                    continue;
+                }
+                if filter.as_ref().is_some_and(|f| !f.matches(&event)) {
+                    continue;
+                }
+                if !deliver(event).await {
+                    debug!("Event consumer is gone, stopping delivery");
+                    break;
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// Stop delivering events until `resume`, detaching the backend's
+    /// probes except those keeping its descriptor table current; events
+    /// are not buffered while paused
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    pub fn pause(&mut self) -> Result<()> {
+        self.backend.pause()?;
+        self.paused.send_replace(true);
+        Ok(())
+    }
+
+    /// Deliver events again, attaching the backend's probes
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    pub fn resume(&mut self) -> Result<()> {
+        self.backend.resume()?;
+        self.paused.send_replace(false);
+        Ok(())
+    }
+
+    /// Check whether delivery is paused
+    ///
+    /// # Returns
+    /// * `bool` - True between `pause` and `resume`
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Stop the backend and the delivery task, ending the stream; the
+    /// watcher can be started again afterwards
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error result
+    pub async fn stop(&mut self) -> Result<()> {
+        self.backend.stop_monitoring().await?;
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        Ok(())
+    }
+
+    /// Get the backend, e.g. to reconfigure its probes
+    ///
+    /// # Returns
+    /// * `&mut B` - The backend
+    pub fn backend(&mut self) -> &mut B {
+        &mut self.backend
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+    use crate::filter_builder::{FilterBuilder, KernelFilterPlan};
+    use crate::mock_monitor::MockMonitor;
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex};
+
+    /// Backend whose events are sent by the test while it runs
+    #[derive(Default)]
+    struct ChannelMonitor {
+        tx: Option<mpsc::Sender<FileEvent>>,
+        plan: Option<KernelFilterPlan>,
+        paused: bool,
+    }
+
+    impl ChannelMonitor {
+        /// Send an event and wait until the delivery task has taken it
+        async fn send(&self, path: &str) {
+            let tx = self.tx.as_ref().unwrap();
+            tx.send(event(path)).await.unwrap();
+            while tx.capacity() < tx.max_capacity() {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    impl MonitorBackend for ChannelMonitor {
+        async fn start_monitoring(
+            &mut self,
+        ) -> Result<mpsc::Receiver<FileEvent>> {
+            let (tx, rx) = mpsc::channel(4);
+            self.tx = Some(tx);
+            Ok(rx)
+        }
+
+        async fn stop_monitoring(&mut self) -> Result<()> {
+            self.tx = None;
+            Ok(())
+        }
+
+        fn apply_kernel_plan(&mut self, plan: &KernelFilterPlan) -> Result<()> {
+            self.plan = Some(plan.clone());
+            Ok(())
+        }
+
+        fn pause(&mut self) -> Result<()> {
+            self.paused = true;
+            Ok(())
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            self.paused = false;
+            Ok(())
+        }
+    }
+
+    fn event(path: &str) -> FileEvent {
+        FileEvent::new(
+            path.to_string(),
+            "app".to_string(),
+            FileAction::Opened,
+            1,
+            SystemClock.now(),
+        )
+    }
+
+    fn events() -> Vec<FileEvent> {
+        ["/a.log", "/proc/1/stat", "/b.log"]
+            .into_iter()
+            .map(event)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_delivers_matching_events() {
+        let filter = FilterBuilder::new().build().unwrap();
+        let mut watcher =
+            Watcher::new(MockMonitor::new(events())).with_filter(filter);
+        let mut stream = watcher.events().await.unwrap();
+        assert!(watcher.events().await.is_err());
+
+        let mut paths = Vec::new();
+        while let Some(event) =
+            poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            paths.push(event.file_path);
+        }
+        assert_eq!(paths, ["/a.log", "/b.log"]);
+    }
+
+    #[tokio::test]
+    async fn test_callback_and_pause() {
+        let mut watcher = Watcher::new(MockMonitor::new(events()));
+        watcher.pause().unwrap();
+        assert!(watcher.is_paused());
+        watcher.resume().unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        watcher
+            .on_event(move |event| sink.lock().unwrap().push(event.file_path))
+            .await
+            .unwrap();
+        watcher.task.take().unwrap().await.unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        watcher.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_while_running() {
+        let filter = FilterBuilder::new().build().unwrap();
+        let plan = filter.kernel_plan();
+        let mut watcher =
+            Watcher::new(ChannelMonitor::default()).with_filter(filter);
+        let mut stream = watcher.events().await.unwrap();
+        assert_eq!(watcher.backend().plan, Some(plan));
+
+        watcher.backend().send("/a.log").await;
+        watcher.pause().unwrap();
+        assert!(watcher.backend().paused);
+        // Sent by the probes left attached, or queued before the pause
+        watcher.backend().send("/b.log").await;
+        watcher.resume().unwrap();
+        assert!(!watcher.backend().paused);
+        watcher.backend().send("/c.log").await;
+        watcher.stop().await.unwrap();
+
+        let mut paths = Vec::new();
+        while let Some(event) =
+            poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+        {
+            paths.push(event.file_path);
+        }
+        assert_eq!(paths, ["/a.log", "/c.log"]);
+    }
+}
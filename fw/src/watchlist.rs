@@ -0,0 +1,583 @@
+//! Watchlist module
+//!
+//! Named sets of paths that matter, e.g. PKI material, configuration or
+//! binaries, kept in one file (`fw collect --watchlists`) and edited with
+//! `fw watchlist add|remove|list`. Lines are a list name and a path glob:
+//!
+//! ```text
+//! # name   glob
+//! pki      /etc/pki/**
+//! pki      /etc/ssl/private/*
+//! binaries /usr/local/bin/*
+//! ```
+//!
+//! Every event on a listed path is tagged "watchlist.NAME" with the glob
+//! it matched, so lists can be used wherever tags can: `--tag
+//! watchlist.pki`, `--route notify:tag=watchlist.pki`, `--group-by
+//! tag=watchlist.pki`, and severity rules on "@pki" instead of a class.
+//! The file is reloaded when it changes or on SIGUSR1, like the process
+//! list, so edits take effect without restarting the capture; a file that
+//! fails to load is logged and the previous lists kept. Edits hold a lock
+//! on "FILE.lock", so two at once don't lose each other's changes.
+//!
+//! Lists are matched in fw only. Pushing their exact and prefix entries
+//! into a kernel map is out of scope: the probes have no path filter to
+//! consult one, and lists tag events rather than drop them, so a kernel
+//! copy would save no work.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::watch;
+
+use crate::enrich::Enricher;
+use crate::file_event::FileEvent;
+use crate::glob::{GlobSet, PathGlob};
+use crate::process_list::{file_changed, watch_parent, InotifyFd};
+
+/// File `fw watchlist` edits unless `--file` says otherwise
+pub const DEFAULT_WATCHLIST_FILE: &str = "/etc/fw/watchlists";
+
+/// Prefix of the tags events on listed paths get
+pub const WATCHLIST_TAG_PREFIX: &str = "watchlist.";
+
+/// Tag key of a list
+///
+/// # Arguments
+/// * `name` - List name, e.g. "pki"
+///
+/// # Returns
+/// * `String` - Tag key, e.g. "watchlist.pki"
+pub fn tag_key(name: &str) -> String {
+    format!("{}{}", WATCHLIST_TAG_PREFIX, name)
+}
+
+/// Check that a list name can be used in tags and policy rules
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "watchlist name '{}' must be letters, digits, '-' or '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Parse one line of a watchlist file
+///
+/// # Returns
+/// * `Result<Option<(&str, &str)>, String>` - Name and glob, None for
+///   blanks and comments, or error if the line is malformed
+fn parse_line(line: &str) -> Result<Option<(&str, &str)>, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [] => Ok(None),
+        [comment, ..] if comment.starts_with('#') => Ok(None),
+        [name, glob] => {
+            check_name(name)?;
+            GlobSet::paths(&[glob.to_string()])?;
+            Ok(Some((name, glob)))
+        }
+        _ => Err("expected 'NAME GLOB'".to_string()),
+    }
+}
+
+/// Parsed watchlists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Watchlists {
+    /// Globs of each list, in file order
+    lists: BTreeMap<String, Vec<PathGlob>>,
+}
+
+impl Watchlists {
+    /// Parse watchlist lines, skipping blanks and '#' comments
+    ///
+    /// # Arguments
+    /// * `text` - "NAME GLOB" lines
+    ///
+    /// # Returns
+    /// * `Result<Watchlists>` - Lists, or error naming the bad line
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lists = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let parsed = parse_line(line)
+                .map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+            if let Some((name, glob)) = parsed {
+                lists
+                    .lists
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(PathGlob::new(glob));
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Load a watchlist file
+    ///
+    /// # Arguments
+    /// * `path` - File to read
+    ///
+    /// # Returns
+    /// * `Result<Watchlists>` - Lists, or error naming the file and line
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text)
+            .with_context(|| format!("Invalid watchlists {}", path.display()))
+    }
+
+    /// Lists and their globs, by name
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = (&str, &[PathGlob])>` - Lists
+    pub fn lists(&self) -> impl Iterator<Item = (&str, &[PathGlob])> {
+        self.lists
+            .iter()
+            .map(|(name, globs)| (name.as_str(), globs.as_slice()))
+    }
+
+    /// Lists a path is on
+    ///
+    /// # Arguments
+    /// * `path` - Path to test
+    ///
+    /// # Returns
+    /// * `impl Iterator<Item = (&str, &str)>` - Name of each list the
+    ///   path is on and the first of its globs that matched
+    pub fn matching<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.lists.iter().filter_map(move |(name, globs)| {
+            globs
+                .iter()
+                .find(|glob| glob.matches(path))
+                .map(|glob| (name.as_str(), glob.pattern()))
+        })
+    }
+}
+
+/// Tags events on listed paths with "watchlist.NAME"
+///
+/// Reads the current lists from the channel the supervisor reloads them
+/// into, so every enrichment worker sees an edit at once.
+pub struct WatchlistTags {
+    /// Current lists
+    lists: watch::Receiver<Arc<Watchlists>>,
+}
+
+impl WatchlistTags {
+    /// Create an enricher following reloads of the lists
+    ///
+    /// # Arguments
+    /// * `lists` - Channel the current lists are read from
+    ///
+    /// # Returns
+    /// * `WatchlistTags` - New enricher
+    pub fn new(lists: watch::Receiver<Arc<Watchlists>>) -> Self {
+        Self { lists }
+    }
+}
+
+impl Enricher for WatchlistTags {
+    fn enrich(&mut self, event: &mut FileEvent) {
+        // Release the channel before matching, so reloads never wait
+        let lists = self.lists.borrow().clone();
+        for (name, glob) in lists.matching(&event.file_path) {
+            event.tags.insert(tag_key(name), glob.to_string());
+        }
+    }
+}
+
+/// Notices when the watchlists should be reloaded
+pub struct WatchlistWatcher {
+    /// File to reload
+    path: PathBuf,
+    /// Watch on the directory holding the file
+    inotify: AsyncFd<InotifyFd>,
+    /// SIGUSR1 deliveries
+    usr1: Signal,
+}
+
+impl WatchlistWatcher {
+    /// Watch a watchlist file for changes and listen for SIGUSR1
+    ///
+    /// # Arguments
+    /// * `path` - Watchlist file
+    ///
+    /// # Returns
+    /// * `Result<WatchlistWatcher>` - Watcher, or error if the directory
+    ///   can't be watched or the signal handler can't be installed
+    pub fn new(path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            inotify: watch_parent(&path)?,
+            usr1: signal(SignalKind::user_defined1())?,
+            path,
+        })
+    }
+}
+
+/// Reload the lists whenever their file changes or SIGUSR1 arrives
+///
+/// Runs until the enrichers drop their receivers. Lists that fail to
+/// load are logged and the previous ones kept.
+///
+/// # Arguments
+/// * `watcher` - Change notifications for the file
+/// * `tx` - Channel the enrichers read the current lists from
+pub async fn supervise(
+    watcher: WatchlistWatcher,
+    tx: watch::Sender<Arc<Watchlists>>,
+) {
+    let WatchlistWatcher {
+        path,
+        inotify,
+        mut usr1,
+    } = watcher;
+    loop {
+        tokio::select! {
+            result = file_changed(&inotify, &path) => {
+                if let Err(e) = result {
+                    warn!("Stopped watching the watchlists: {}", e);
+                    return;
+                }
+            }
+            _ = usr1.recv() => {}
+            _ = tx.closed() => return,
+        }
+        match Watchlists::load(&path) {
+            Ok(lists) => {
+                info!("Reloaded {} watchlists", lists.lists.len());
+                tx.send_replace(Arc::new(lists));
+            }
+            Err(e) => warn!("Keeping the previous watchlists: {:#}", e),
+        }
+    }
+}
+
+/// Lock a list file, such as the watchlists, until the edit is written
+///
+/// The file is replaced on every edit, so the lock is taken on
+/// "FILE.lock" beside it; another edit waits until the returned file is
+/// dropped.
+///
+/// # Arguments
+/// * `path` - List file about to be edited
+///
+/// # Returns
+/// * `Result<File>` - Open lock file holding the lock, or error if it
+///   can't be created or locked
+pub(crate) fn lock_for_edit(path: &Path) -> Result<File> {
+    create_parent(path)?;
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&lock)
+        .with_context(|| format!("Failed to open {}", lock.display()))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Failed to lock {}", lock.display()));
+    }
+    Ok(file)
+}
+
+/// Create the directory a list file goes in
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Read a list file, such as the watchlists, for editing
+///
+/// # Returns
+/// * `Result<String>` - Contents, empty if the file doesn't exist yet
+pub(crate) fn read_for_edit(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to read {}", path.display()))
+        }
+    }
+}
+
+/// Replace a list file, such as the watchlists, with new contents
+///
+/// The contents are written beside the file and renamed over it, so a
+/// running collector never reloads a half-written file.
+pub(crate) fn replace(path: &Path, text: &str) -> Result<()> {
+    create_parent(path)?;
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".new");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, text)
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+    fs::rename(&temp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Append globs to a list, skipping those already on it
+///
+/// # Arguments
+/// * `text` - Contents of the watchlist file
+/// * `name` - List to add to, created if new
+/// * `globs` - Globs to add
+///
+/// # Returns
+/// * `Result<(String, usize)>` - New contents and the number of globs
+///   added, or error if the file, name or a glob is invalid
+fn with_added(
+    text: &str,
+    name: &str,
+    globs: &[String],
+) -> Result<(String, usize)> {
+    let lists = Watchlists::parse(text)?;
+    check_name(name).map_err(|e| anyhow!(e))?;
+    let mut listed: Vec<&str> = lists
+        .lists
+        .get(name)
+        .map(|globs| globs.iter().map(PathGlob::pattern).collect())
+        .unwrap_or_default();
+    let mut text = text.to_string();
+    let mut added = 0;
+    for glob in globs {
+        GlobSet::paths(std::slice::from_ref(glob)).map_err(|e| anyhow!(e))?;
+        if listed.contains(&glob.as_str()) {
+            continue;
+        }
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&format!("{} {}\n", name, glob));
+        listed.push(glob);
+        added += 1;
+    }
+    Ok((text, added))
+}
+
+/// Remove globs from a list, or the whole list
+///
+/// Other lines, comments included, are kept as they are.
+///
+/// # Arguments
+/// * `text` - Contents of the watchlist file
+/// * `name` - List to remove from
+/// * `globs` - Globs to remove; all of them if empty
+///
+/// # Returns
+/// * `Result<(String, usize)>` - New contents and the number of lines
+///   removed, or error if the file is invalid
+fn with_removed(
+    text: &str,
+    name: &str,
+    globs: &[String],
+) -> Result<(String, usize)> {
+    Watchlists::parse(text)?;
+    let mut removed = 0;
+    let mut kept = String::with_capacity(text.len());
+    for line in text.lines() {
+        let listed = matches!(
+            parse_line(line),
+            Ok(Some((n, glob)))
+                if n == name
+                    && (globs.is_empty() || globs.iter().any(|g| g == glob))
+        );
+        if listed {
+            removed += 1;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    Ok((kept, removed))
+}
+
+/// Run `fw watchlist add`
+///
+/// # Arguments
+/// * `file` - Watchlist file, created if missing
+/// * `name` - List to add to
+/// * `globs` - Globs to add
+///
+/// # Returns
+/// * `Result<()>` - Error if the file can't be updated or an argument
+///   is invalid
+pub fn run_add(file: &Path, name: &str, globs: &[String]) -> Result<()> {
+    let _lock = lock_for_edit(file)?;
+    let text = read_for_edit(file)?;
+    let (text, added) = with_added(&text, name, globs)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    if added > 0 {
+        replace(file, &text)?;
+    }
+    eprintln!("Added {} globs to {}", added, name);
+    Ok(())
+}
+
+/// Run `fw watchlist remove`
+///
+/// # Arguments
+/// * `file` - Watchlist file
+/// * `name` - List to remove from
+/// * `globs` - Globs to remove; the whole list if empty
+///
+/// # Returns
+/// * `Result<()>` - Error if the file can't be updated or nothing on it
+///   matched
+pub fn run_remove(file: &Path, name: &str, globs: &[String]) -> Result<()> {
+    let _lock = lock_for_edit(file)?;
+    let text = read_for_edit(file)?;
+    let (text, removed) = with_removed(&text, name, globs)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    if removed == 0 {
+        return Err(anyhow!(
+            "{} has no matching entries in {}",
+            name,
+            file.display()
+        ));
+    }
+    replace(file, &text)?;
+    eprintln!("Removed {} globs from {}", removed, name);
+    Ok(())
+}
+
+/// Run `fw watchlist list`
+///
+/// # Arguments
+/// * `file` - Watchlist file
+/// * `name` - Only print this list if set
+///
+/// # Returns
+/// * `Result<()>` - Error if the file can't be read or has no such list
+pub fn run_list(file: &Path, name: Option<&str>) -> Result<()> {
+    let lists = Watchlists::parse(&read_for_edit(file)?)
+        .map_err(|e| anyhow!("{}: {:#}", file.display(), e))?;
+    if let Some(name) = name {
+        if !lists.lists.contains_key(name) {
+            return Err(anyhow!("No watchlist {} in {}", name, file.display()));
+        }
+    }
+    for (list, globs) in lists.lists() {
+        if name.is_some_and(|name| name != list) {
+            continue;
+        }
+        println!("{}", list);
+        for glob in globs {
+            println!("  {}", glob.pattern());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::file_event::FileAction;
+
+    #[test]
+    fn test_edit_and_match() {
+        let text = "# critical files\npki /etc/pki/**\n";
+        let names = ["/etc/ssl/private/*".to_string()];
+        let (text, added) = with_added(text, "pki", &names).unwrap();
+        assert_eq!(added, 1);
+        let (text, added) = with_added(&text, "pki", &names).unwrap();
+        assert_eq!(added, 0);
+        let bins = ["/usr/local/bin/*".to_string()];
+        let (text, _) = with_added(&text, "binaries", &bins).unwrap();
+
+        let lists = Watchlists::parse(&text).unwrap();
+        let on: Vec<_> = lists.matching("/etc/ssl/private/web.key").collect();
+        assert_eq!(on, vec![("pki", "/etc/ssl/private/*")]);
+        assert_eq!(lists.matching("/etc/hosts").count(), 0);
+
+        // Removing a list keeps comments and the other lists
+        let (text, removed) = with_removed(&text, "pki", &[]).unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(text, "# critical files\nbinaries /usr/local/bin/*\n");
+
+        assert!(with_added("", "p k", &names).is_err());
+        assert!(with_added("", "pki", &["etc/*".to_string()]).is_err());
+        assert!(Watchlists::parse("pki\n").is_err());
+    }
+
+    #[test]
+    fn test_edits_wait_for_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lists/watchlists");
+        let lock = lock_for_edit(&path).unwrap();
+        let editor = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                run_add(&path, "pki", &["/etc/pki/**".to_string()])
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!path.exists());
+        drop(lock);
+        editor.join().unwrap().unwrap();
+        let lists = Watchlists::load(&path).unwrap();
+        assert_eq!(lists.matching("/etc/pki/ca.pem").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tags_follow_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watchlists");
+        run_add(&path, "pki", &["/etc/pki/**".to_string()]).unwrap();
+        let (tx, rx) =
+            watch::channel(Arc::new(Watchlists::load(&path).unwrap()));
+        let mut observer = rx.clone();
+        let mut tags = WatchlistTags::new(rx);
+        let watcher = WatchlistWatcher::new(path.clone()).unwrap();
+        let supervisor = tokio::spawn(supervise(watcher, tx));
+
+        let tagged = |tags: &mut WatchlistTags, path: &str| {
+            let mut event = FileEvent::new(
+                path.to_string(),
+                "cat".to_string(),
+                FileAction::Opened,
+                1,
+                SystemClock.now(),
+            );
+            tags.enrich(&mut event);
+            event.tags
+        };
+        let event = tagged(&mut tags, "/etc/pki/tls/ca.pem");
+        assert_eq!(event.get("watchlist.pki").unwrap(), "/etc/pki/**");
+        assert!(tagged(&mut tags, "/etc/ssh/sshd_config").is_empty());
+
+        run_add(&path, "ssh", &["/etc/ssh/*".to_string()]).unwrap();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            observer.changed(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let event = tagged(&mut tags, "/etc/ssh/sshd_config");
+        assert!(event.contains_key("watchlist.ssh"));
+        supervisor.abort();
+    }
+}
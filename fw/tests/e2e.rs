@@ -0,0 +1,195 @@
+//! End-to-end tests against a real kernel
+//!
+//! Each test starts a privileged container with the fw binary and a
+//! scratch directory mounted, runs `fw collect` with ECS JSON output in
+//! it, performs a fixed sequence of file operations, and checks the
+//! events fw wrote. They need docker or podman and a kernel with eBPF
+//! support, so they only run when asked for:
+//!
+//! ```text
+//! cargo test -p fw --test e2e -- --ignored
+//! ```
+//!
+//! `FW_E2E_RUNTIME` picks the container runtime (default docker) and
+//! `FW_E2E_IMAGE` the image (default debian:bookworm-slim), which must be
+//! able to run the binary built on the host.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Image the tests run in unless FW_E2E_IMAGE is set
+const DEFAULT_IMAGE: &str = "debian:bookworm-slim";
+
+/// Seconds fw is given to attach its probes before the workload starts
+const STARTUP_SECS: u32 = 3;
+
+/// One reported event, reduced to the fields the tests check
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Observed {
+    /// ECS `event.action`
+    action: String,
+    /// Path relative to the workload directory, or the full path of
+    /// files outside it
+    name: String,
+    /// ECS `process.name`
+    process: String,
+    /// ECS `file.target_path` of link events
+    target: Option<String>,
+}
+
+/// Run a shell workload in a privileged container while fw watches it
+///
+/// The workload runs in /work/e2e, and fw reports only paths under it.
+///
+/// # Arguments
+/// * `workload` - Shell commands, run with `sh -c`
+///
+/// # Returns
+/// * `Result<Vec<Observed>>` - Events fw reported, in order
+fn run_in_container(workload: &str) -> Result<Vec<Observed>> {
+    let runtime =
+        std::env::var("FW_E2E_RUNTIME").unwrap_or_else(|_| "docker".into());
+    let image = std::env::var("FW_E2E_IMAGE")
+        .unwrap_or_else(|_| DEFAULT_IMAGE.to_string());
+    let work = tempfile::tempdir()?;
+    fs::create_dir(work.path().join("e2e"))?;
+
+    // SIGINT makes fw detach its probes and flush the output file
+    let script = format!(
+        "fw collect --path-glob '/work/e2e/**' --format ecs-json \
+         --output /work/events.json 2>/work/fw.log &\n\
+         fw_pid=$!\n\
+         sleep {}\n\
+         cd /work/e2e\n\
+         {}\n\
+         sleep 1\n\
+         kill -INT $fw_pid\n\
+         wait $fw_pid\n",
+        STARTUP_SECS, workload
+    );
+    // The host pid namespace lets fw read /proc for the pids the kernel
+    // reports
+    let output = Command::new(&runtime)
+        .args(["run", "--rm", "--privileged", "--pid=host", "-v"])
+        .arg(format!("{}:/usr/local/bin/fw:ro", env!("CARGO_BIN_EXE_fw")))
+        .arg("-v")
+        .arg(format!("{}:/work", work.path().display()))
+        .args([image.as_str(), "sh", "-c", &script])
+        .output()
+        .with_context(|| format!("Failed to run {}", runtime))?;
+    let log =
+        fs::read_to_string(work.path().join("fw.log")).unwrap_or_default();
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Container exited with {}: {}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr),
+            log
+        ));
+    }
+    parse_events(&work.path().join("events.json"))
+        .with_context(|| format!("fw log:\n{}", log))
+}
+
+/// Read the ECS JSON lines fw wrote
+///
+/// # Arguments
+/// * `path` - Output file of `fw collect --format ecs-json`
+///
+/// # Returns
+/// * `Result<Vec<Observed>>` - Events, in order
+fn parse_events(path: &Path) -> Result<Vec<Observed>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("fw wrote no {}", path.display()))?;
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let event: Value = serde_json::from_str(line)
+            .with_context(|| format!("Invalid JSON line: {}", line))?;
+        let field = |pointer: &str| {
+            event
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let path = field("/file/path").unwrap_or_default();
+        let name = path.strip_prefix("/work/e2e/").unwrap_or(&path);
+        events.push(Observed {
+            action: field("/event/action").unwrap_or_default(),
+            name: name.to_string(),
+            process: field("/process/name").unwrap_or_default(),
+            target: field("/file/target_path"),
+        });
+    }
+    Ok(events)
+}
+
+/// Check that the expected events were reported, in order
+///
+/// Other events may come in between, e.g. from the shell's own lookups.
+///
+/// # Arguments
+/// * `events` - Events fw reported
+/// * `expected` - (action, name, process) of each event to find
+fn assert_in_order(events: &[Observed], expected: &[(&str, &str, &str)]) {
+    let mut rest = events.iter();
+    for &(action, name, process) in expected {
+        assert!(
+            rest.any(|e| e.action == action
+                && e.name == name
+                && e.process == process),
+            "No {} of {} by {} in order; reported: {:#?}",
+            action,
+            name,
+            process,
+            events
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a container runtime and eBPF; run with --ignored"]
+fn test_file_lifecycle() -> Result<()> {
+    let events = run_in_container(
+        "printf hello > notes.txt\n\
+         mv notes.txt kept.txt\n\
+         ln kept.txt hard.txt\n\
+         ln -s kept.txt soft.txt\n\
+         cat kept.txt > /dev/null",
+    )?;
+
+    assert_in_order(
+        &events,
+        &[
+            ("opened", "notes.txt", "sh"),
+            ("closed", "notes.txt", "sh"),
+            ("renamed", "kept.txt", "mv"),
+            ("linked", "hard.txt", "ln"),
+            ("symlinked", "soft.txt", "ln"),
+            ("opened", "kept.txt", "cat"),
+            ("closed", "kept.txt", "cat"),
+        ],
+    );
+    let hard = events.iter().find(|e| e.action == "linked").unwrap();
+    assert_eq!(hard.target.as_deref(), Some("/work/e2e/kept.txt"));
+    let soft = events.iter().find(|e| e.action == "symlinked").unwrap();
+    assert_eq!(soft.target.as_deref(), Some("kept.txt"));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs a container runtime and eBPF; run with --ignored"]
+fn test_filters_apply_in_container() -> Result<()> {
+    let events = run_in_container(
+        "mkdir -p sub\n\
+         printf a > sub/inside.log\n\
+         printf b > /work/outside.log",
+    )?;
+
+    assert_in_order(&events, &[("opened", "sub/inside.log", "sh")]);
+    // Only /work/e2e is watched
+    assert!(events.iter().all(|e| !e.name.starts_with('/')));
+    Ok(())
+}
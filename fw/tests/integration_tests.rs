@@ -289,6 +289,19 @@ async fn test_cli_collect_help() -> Result<()> {
     Ok(())
 }
 
+/// Test the full filter/format pipeline with the synthetic event stream
+///
+/// Unlike the kernel-driven tests above, this is deterministic and does
+/// not require eBPF support.
+#[tokio::test]
+async fn test_selftest_pipeline() -> Result<()> {
+    let verified = fw::selftest::check_pipeline().await?;
+    assert!(verified > 0);
+
+    println!("✅ Selftest pipeline verified {} events", verified);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;